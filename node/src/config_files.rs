@@ -0,0 +1,689 @@
+//! Egg `config_files` rewrites, applied to a container's files before it starts.
+//!
+//! Eggs map a file path to a parser and a `find` table of key -> value, where values may
+//! use placeholders such as `{{server.build.default.port}}`. This keeps files like
+//! `server.properties` in sync with the allocation and variables the panel assigned.
+//! The rules are kept in a container label so every later start re-applies them.
+
+use bollard::Docker;
+use bollard::container::{
+    DownloadFromContainerOptions, InspectContainerOptions, UploadToContainerOptions,
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Config files are small; anything bigger is almost certainly the wrong path.
+const MAX_CONFIG_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Container label holding the create's `config_files` rules as JSON
+pub const RULES_LABEL: &str = "yunexal.config_files";
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    parser: String,
+    #[serde(default)]
+    find: Map<String, Value>,
+}
+
+/// Applies every rule in `config_files` to a created or stopped container.
+/// Problems with one file are logged and skipped; they never stop the container from starting.
+pub async fn apply(
+    docker: &Docker,
+    container_id: &str,
+    config_files: &Value,
+    env: &HashMap<String, String>,
+) {
+    let Some(files) = config_files.as_object() else {
+        eprintln!(
+            "Ignoring config_files for {}: expected an object of file paths",
+            container_id
+        );
+        return;
+    };
+    if files.is_empty() {
+        return;
+    }
+
+    // Relative egg paths are relative to the image's working directory (/home/container in most eggs)
+    let workdir = docker
+        .inspect_container(container_id, None::<InspectContainerOptions>)
+        .await
+        .ok()
+        .and_then(|c| c.config)
+        .and_then(|c| c.working_dir)
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/".to_string());
+
+    for (path, spec) in files {
+        let result = match serde_json::from_value::<ConfigFile>(spec.clone()) {
+            Ok(file) => apply_file(docker, container_id, &workdir, path, &file, env).await,
+            Err(e) => Err(format!("invalid rule: {}", e)),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Config file {} in {}: {}, leaving it unchanged",
+                path, container_id, e
+            );
+        }
+    }
+}
+
+/// Rules stored on a container at create, with the environment it was created with.
+pub struct StoredRules {
+    pub running: bool,
+    rules: Value,
+    env: HashMap<String, String>,
+}
+
+/// The config rules in a container's label; `None` when it has none or can't be inspected.
+pub async fn stored(docker: &Docker, container: &str) -> Option<StoredRules> {
+    let inspected = docker
+        .inspect_container(container, None::<InspectContainerOptions>)
+        .await
+        .ok()?;
+    let config = inspected.config?;
+    let rules = config
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(RULES_LABEL))
+        .and_then(|json| serde_json::from_str::<Value>(json).ok())
+        .filter(|rules| rules.as_object().is_some_and(|files| !files.is_empty()))?;
+    Some(StoredRules {
+        running: inspected.state.and_then(|s| s.running).unwrap_or(false),
+        rules,
+        env: env_map(config.env.as_deref().unwrap_or_default()),
+    })
+}
+
+impl StoredRules {
+    /// Re-applies the rules, e.g. after the server rewrote its own files while it ran.
+    pub async fn apply(&self, docker: &Docker, container: &str) {
+        apply(docker, container, &self.rules, &self.env).await;
+    }
+}
+
+/// Docker's `KEY=value` environment list as a map.
+fn env_map(env: &[String]) -> HashMap<String, String> {
+    env.iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+async fn apply_file(
+    docker: &Docker,
+    container_id: &str,
+    workdir: &str,
+    path: &str,
+    file: &ConfigFile,
+    env: &HashMap<String, String>,
+) -> Result<(), String> {
+    let full_path = container_path(workdir, path)
+        .ok_or("path must stay inside the container's working directory")?;
+
+    // A missing file starts out empty, so keyed parsers can still write their values
+    let existing = read_file(docker, container_id, &full_path).await?;
+    let original = match &existing {
+        Some((content, _)) => {
+            String::from_utf8(content.clone()).map_err(|_| "file is not UTF-8 text".to_string())?
+        }
+        None => String::new(),
+    };
+
+    let rewritten = rewrite(&file.parser, &original, &file.find, env)?;
+    if rewritten == original {
+        return Ok(());
+    }
+    write_file(
+        docker,
+        container_id,
+        &full_path,
+        rewritten.as_bytes(),
+        existing.as_ref().map(|(_, h)| h),
+    )
+    .await
+}
+
+fn container_path(workdir: &str, path: &str) -> Option<String> {
+    if path.is_empty() || path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    if path.starts_with('/') {
+        return Some(path.to_string());
+    }
+    Some(format!(
+        "{}/{}",
+        workdir.trim_end_matches('/'),
+        path.trim_start_matches("./")
+    ))
+}
+
+/// Contents and tar header of a file in the container, or `None` if it doesn't exist.
+async fn read_file(
+    docker: &Docker,
+    container_id: &str,
+    path: &str,
+) -> Result<Option<(Vec<u8>, tar::Header)>, String> {
+    let mut stream = docker.download_from_container(
+        container_id,
+        Some(DownloadFromContainerOptions {
+            path: path.to_string(),
+        }),
+    );
+    let mut archive = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(bytes) => archive.extend_from_slice(&bytes),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => return Ok(None),
+            Err(e) => return Err(format!("could not read file: {}", e)),
+        }
+    }
+
+    let mut archive = tar::Archive::new(archive.as_slice());
+    let mut entry = archive
+        .entries()
+        .and_then(|mut entries| entries.next().transpose())
+        .map_err(|e| format!("could not read file: {}", e))?
+        .ok_or("could not read file: empty archive")?;
+
+    let header = entry.header().clone();
+    if !header.entry_type().is_file() {
+        return Err("not a regular file".to_string());
+    }
+    if header.size().unwrap_or(0) > MAX_CONFIG_FILE_SIZE {
+        return Err(format!("larger than {} bytes", MAX_CONFIG_FILE_SIZE));
+    }
+
+    let mut content = Vec::new();
+    entry
+        .read_to_end(&mut content)
+        .map_err(|e| format!("could not read file: {}", e))?;
+    Ok(Some((content, header)))
+}
+
+/// Writes `content` to `path`, keeping the owner and mode of the file it replaces.
+async fn write_file(
+    docker: &Docker,
+    container_id: &str,
+    path: &str,
+    content: &[u8],
+    previous: Option<&tar::Header>,
+) -> Result<(), String> {
+    let (dir, name) = path.rsplit_once('/').ok_or("invalid path")?;
+    let dir = if dir.is_empty() { "/" } else { dir };
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    // New files are world-writable: the server may run as any user and has to be able to edit them
+    header.set_mode(previous.and_then(|h| h.mode().ok()).unwrap_or(0o666));
+    header.set_uid(previous.and_then(|h| h.uid().ok()).unwrap_or(0));
+    header.set_gid(previous.and_then(|h| h.gid().ok()).unwrap_or(0));
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+        .append_data(&mut header, name, content)
+        .map_err(|e| format!("could not pack file: {}", e))?;
+    let archive = builder
+        .into_inner()
+        .map_err(|e| format!("could not pack file: {}", e))?;
+
+    docker
+        .upload_to_container(
+            container_id,
+            Some(UploadToContainerOptions {
+                path: dir.to_string(),
+                ..Default::default()
+            }),
+            bollard::body_full(archive.into()),
+        )
+        .await
+        .map_err(|e| format!("could not write file: {}", e))
+}
+
+/// Rewrites `content` with one of the parser types eggs declare.
+fn rewrite(
+    parser: &str,
+    content: &str,
+    find: &Map<String, Value>,
+    env: &HashMap<String, String>,
+) -> Result<String, String> {
+    match parser {
+        "properties" => Ok(rewrite_keyed_lines(content, find, env, &['=', ':'])),
+        "ini" => Ok(rewrite_ini(content, find, env)),
+        "file" => Ok(rewrite_prefixed_lines(content, find, env)),
+        "json" => {
+            let mut doc = if content.trim().is_empty() {
+                Value::Object(Map::new())
+            } else {
+                serde_json::from_str(content).map_err(|e| format!("not valid JSON: {}", e))?
+            };
+            rewrite_document(&mut doc, find, env);
+            serde_json::to_string_pretty(&doc)
+                .map(|s| s + "\n")
+                .map_err(|e| e.to_string())
+        }
+        "yaml" => {
+            let mut doc: Value = if content.trim().is_empty() {
+                Value::Object(Map::new())
+            } else {
+                serde_yaml::from_str(content).map_err(|e| format!("not valid YAML: {}", e))?
+            };
+            rewrite_document(&mut doc, find, env);
+            serde_yaml::to_string(&doc).map_err(|e| e.to_string())
+        }
+        other => Err(format!("parser '{}' is not supported", other)),
+    }
+}
+
+/// Replaces `{{...}}` placeholders. Unknown placeholders are left as they are.
+fn render(template: &str, env: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + len + 2;
+        out.push_str(&rest[..start]);
+        match placeholder(rest[start + 2..end - 2].trim(), env) {
+            Some(value) => out.push_str(value),
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Pterodactyl placeholder names, resolved from the environment the panel sent.
+fn placeholder<'a>(name: &str, env: &'a HashMap<String, String>) -> Option<&'a str> {
+    let var = match name {
+        "server.build.default.port" => "SERVER_PORT",
+        "server.build.default.ip" => "SERVER_IP",
+        "server.build.memory" => "SERVER_MEMORY",
+        "server.uuid" => "P_SERVER_UUID",
+        _ => name
+            .strip_prefix("server.build.env.")
+            .or_else(|| name.strip_prefix("env."))?,
+    };
+    env.get(var).map(String::as_str)
+}
+
+/// Scalar rule value as text; `None` for the conditional (object) form.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Object(_) | Value::Array(_) => None,
+        other => Some(other.to_string()),
+    }
+}
+
+fn line_rules<'a>(
+    find: &'a Map<String, Value>,
+    env: &HashMap<String, String>,
+) -> Vec<(&'a str, String)> {
+    find.iter()
+        .filter_map(|(key, value)| match scalar(value) {
+            Some(v) => Some((key.as_str(), render(&v, env))),
+            None => {
+                eprintln!(
+                    "Config rule for '{}' needs a plain value for this parser, skipping it",
+                    key
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+fn join_lines(lines: Vec<String>) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// Key of a `key=value` line, if it isn't a comment.
+fn line_key<'a>(line: &'a str, separators: &[char]) -> Option<(&'a str, usize)> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with(['#', '!', ';']) {
+        return None;
+    }
+    let sep = line.find(separators)?;
+    Some((line[..sep].trim(), sep))
+}
+
+/// Keeps the key and separator as written and swaps only the value.
+fn replace_value(line: &str, sep: usize, value: &str) -> String {
+    let after = &line[sep + 1..];
+    let padding = &after[..after.len() - after.trim_start().len()];
+    format!("{}{}{}", &line[..=sep], padding, value)
+}
+
+/// `properties`: sets `key=value`, appending keys the file doesn't have yet.
+fn rewrite_keyed_lines(
+    content: &str,
+    find: &Map<String, Value>,
+    env: &HashMap<String, String>,
+    separators: &[char],
+) -> String {
+    let rules = line_rules(find, env);
+    let mut seen = vec![false; rules.len()];
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            if let Some((key, sep)) = line_key(line, separators)
+                && let Some(i) = rules.iter().position(|(k, _)| *k == key)
+            {
+                seen[i] = true;
+                return replace_value(line, sep, &rules[i].1);
+            }
+            line.to_string()
+        })
+        .collect();
+
+    for ((key, value), seen) in rules.iter().zip(seen) {
+        if !seen {
+            lines.push(format!("{}={}", key, value));
+        }
+    }
+    join_lines(lines)
+}
+
+/// `file`: replaces every line starting with the key by the whole value. Nothing is appended.
+fn rewrite_prefixed_lines(
+    content: &str,
+    find: &Map<String, Value>,
+    env: &HashMap<String, String>,
+) -> String {
+    let rules = line_rules(find, env);
+    let lines = content
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start();
+            match rules.iter().find(|(key, _)| trimmed.starts_with(key)) {
+                Some((_, value)) => value.clone(),
+                None => line.to_string(),
+            }
+        })
+        .collect();
+    join_lines(lines)
+}
+
+/// `ini`: keys are `section.key` (or just `key` before the first section).
+fn rewrite_ini(content: &str, find: &Map<String, Value>, env: &HashMap<String, String>) -> String {
+    let rules: Vec<(&str, &str, String)> = line_rules(find, env)
+        .into_iter()
+        .map(|(key, value)| match key.split_once('.') {
+            Some((section, key)) => (section, key, value),
+            None => ("", key, value),
+        })
+        .collect();
+    let mut seen = vec![false; rules.len()];
+
+    // Lines per section, in file order; "" is the part before the first header
+    let mut sections: Vec<(String, Vec<String>)> = vec![(String::new(), Vec::new())];
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            sections.push((
+                trimmed[1..trimmed.len() - 1].trim().to_string(),
+                vec![line.to_string()],
+            ));
+            continue;
+        }
+
+        let section = sections.last_mut().unwrap();
+        let line = match line_key(line, &['=']).and_then(|(key, sep)| {
+            rules
+                .iter()
+                .position(|(s, k, _)| *s == section.0 && *k == key)
+                .map(|i| (i, sep))
+        }) {
+            Some((i, sep)) => {
+                seen[i] = true;
+                replace_value(line, sep, &rules[i].2)
+            }
+            None => line.to_string(),
+        };
+        section.1.push(line);
+    }
+
+    for ((section, key, value), seen) in rules.iter().zip(seen) {
+        if seen {
+            continue;
+        }
+        let line = format!("{}={}", key, value);
+        match sections.iter_mut().find(|(name, _)| name == section) {
+            Some((_, lines)) => {
+                // Before trailing blank lines, so the value stays with its section
+                let at = lines
+                    .iter()
+                    .rposition(|l| !l.trim().is_empty())
+                    .map_or(lines.len(), |i| i + 1);
+                lines.insert(at, line);
+            }
+            None => sections.push((section.to_string(), vec![format!("[{}]", section), line])),
+        }
+    }
+
+    join_lines(sections.into_iter().flat_map(|(_, lines)| lines).collect())
+}
+
+enum Segment {
+    Key(String),
+    Index(usize),
+    /// `*`: every value of an object or element of an array
+    Any,
+}
+
+/// Parses `listeners[0].host` or `servers.*.address`.
+fn parse_path(path: &str) -> Option<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (name, mut indexes) = part.split_at(part.find('[').unwrap_or(part.len()));
+        match name {
+            "" if indexes.is_empty() => return None,
+            "" => {}
+            "*" => segments.push(Segment::Any),
+            name => segments.push(Segment::Key(name.to_string())),
+        }
+        while !indexes.is_empty() {
+            let end = indexes.find(']')?;
+            segments.push(Segment::Index(indexes[1..end].parse().ok()?));
+            indexes = &indexes[end + 1..];
+        }
+    }
+    Some(segments)
+}
+
+/// `yaml` / `json`: sets values by path. An object value maps current values to
+/// replacements, so only matching values change.
+fn rewrite_document(doc: &mut Value, find: &Map<String, Value>, env: &HashMap<String, String>) {
+    for (path, rule) in find {
+        let Some(segments) = parse_path(path) else {
+            eprintln!("Config rule path '{}' is invalid, skipping it", path);
+            continue;
+        };
+        set_path(doc, &segments, rule, env);
+    }
+}
+
+fn set_path(node: &mut Value, path: &[Segment], rule: &Value, env: &HashMap<String, String>) {
+    let Some((segment, rest)) = path.split_first() else {
+        replace_node(node, rule, env);
+        return;
+    };
+
+    match segment {
+        Segment::Key(key) => {
+            if node.is_null() {
+                *node = Value::Object(Map::new());
+            }
+            let Some(map) = node.as_object_mut() else {
+                return;
+            };
+            // Conditional rules only ever change values that are already there
+            if rule.is_object() && !map.contains_key(key) {
+                return;
+            }
+            set_path(
+                map.entry(key.clone()).or_insert(Value::Null),
+                rest,
+                rule,
+                env,
+            );
+        }
+        Segment::Index(i) => {
+            if let Some(child) = node.as_array_mut().and_then(|a| a.get_mut(*i)) {
+                set_path(child, rest, rule, env);
+            }
+        }
+        Segment::Any => match node {
+            Value::Object(map) => map
+                .values_mut()
+                .for_each(|child| set_path(child, rest, rule, env)),
+            Value::Array(items) => items
+                .iter_mut()
+                .for_each(|child| set_path(child, rest, rule, env)),
+            _ => {}
+        },
+    }
+}
+
+fn replace_node(node: &mut Value, rule: &Value, env: &HashMap<String, String>) {
+    let Some(cases) = rule.as_object() else {
+        if let Some(value) = scalar(rule) {
+            *node = typed(render(&value, env));
+        }
+        return;
+    };
+
+    let Some(current) = scalar(node) else {
+        return;
+    };
+    for (matches, replacement) in cases {
+        if matches.starts_with("regex:") {
+            eprintln!(
+                "Config rule condition '{}' uses a regex, which is not supported; skipping it",
+                matches
+            );
+            continue;
+        }
+        if *matches == current
+            && let Some(value) = scalar(replacement)
+        {
+            *node = typed(render(&value, env));
+            return;
+        }
+    }
+}
+
+/// Integers and booleans go back into structured files unquoted, like the original values.
+fn typed(value: String) -> Value {
+    if let Ok(n) = value.parse::<i64>() {
+        return Value::from(n);
+    }
+    if let Ok(b) = value.parse::<bool>() {
+        return Value::Bool(b);
+    }
+    Value::String(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_env() -> HashMap<String, String> {
+        env_map(&[
+            "SERVER_PORT=25601".to_string(),
+            "SERVER_IP=0.0.0.0".to_string(),
+            "SERVER_MEMORY=2048".to_string(),
+        ])
+    }
+
+    /// Runs every rule of a fixture egg's `config_files` over the file it names and compares
+    /// the result with `<file>.expected`.
+    fn assert_fixture(egg: &str, rules: &str, files: &[(&str, &str, &str)]) {
+        let rules: Map<String, Value> = serde_json::from_str(rules).unwrap();
+        for (path, input, expected) in files {
+            let file: ConfigFile = serde_json::from_value(rules[*path].clone()).unwrap();
+            let output = rewrite(&file.parser, input, &file.find, &server_env()).unwrap();
+            assert_eq!(output, *expected, "{} {}", egg, path);
+        }
+    }
+
+    #[test]
+    fn paper_server_properties() {
+        assert_fixture(
+            "paper",
+            include_str!("../tests/fixtures/config_files/paper/config_files.json"),
+            &[(
+                "server.properties",
+                include_str!("../tests/fixtures/config_files/paper/server.properties"),
+                include_str!("../tests/fixtures/config_files/paper/server.properties.expected"),
+            )],
+        );
+    }
+
+    #[test]
+    fn velocity_toml() {
+        assert_fixture(
+            "velocity",
+            include_str!("../tests/fixtures/config_files/velocity/config_files.json"),
+            &[(
+                "velocity.toml",
+                include_str!("../tests/fixtures/config_files/velocity/velocity.toml"),
+                include_str!("../tests/fixtures/config_files/velocity/velocity.toml.expected"),
+            )],
+        );
+    }
+
+    #[test]
+    fn rewriting_twice_changes_nothing() {
+        let rules: Map<String, Value> = serde_json::from_str(include_str!(
+            "../tests/fixtures/config_files/paper/config_files.json"
+        ))
+        .unwrap();
+        let file: ConfigFile = serde_json::from_value(rules["server.properties"].clone()).unwrap();
+        let expected =
+            include_str!("../tests/fixtures/config_files/paper/server.properties.expected");
+        assert_eq!(
+            rewrite(&file.parser, expected, &file.find, &server_env()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn missing_properties_file_gets_every_key() {
+        let rules: Map<String, Value> = serde_json::from_str(include_str!(
+            "../tests/fixtures/config_files/paper/config_files.json"
+        ))
+        .unwrap();
+        let file: ConfigFile = serde_json::from_value(rules["server.properties"].clone()).unwrap();
+        assert_eq!(
+            rewrite(&file.parser, "", &file.find, &server_env()).unwrap(),
+            "enable-query=true\nquery.port=25601\nserver-ip=0.0.0.0\nserver-port=25601\n"
+        );
+    }
+
+    #[test]
+    fn env_map_splits_on_the_first_equals_sign() {
+        let env = env_map(&[
+            "JAVA_OPTS=-Dfoo=bar".to_string(),
+            "EMPTY=".to_string(),
+            "BROKEN".to_string(),
+        ]);
+        assert_eq!(env.get("JAVA_OPTS").map(String::as_str), Some("-Dfoo=bar"));
+        assert_eq!(env.get("EMPTY").map(String::as_str), Some(""));
+        assert_eq!(env.len(), 2);
+    }
+}
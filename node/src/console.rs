@@ -0,0 +1,151 @@
+//! Shaping of console output on its way to a WebSocket: optional ANSI stripping for
+//! clients that can't render it, and a per-connection byte budget so a chatty container
+//! can't bury a slow browser.
+
+use std::time::{Duration, Instant};
+
+/// Sent in place of output that went over the budget, once per window
+pub const TRUNCATED_MARKER: &str = "\r\n[output truncated]\r\n";
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Removes ANSI escape sequences: CSI (`ESC [ ... final`), OSC (`ESC ] ... BEL` or
+/// `ESC ] ... ESC \`) and two-byte escapes. Text and line breaks are kept.
+pub fn strip_ansi(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameters and intermediates, up to a final byte in @..~
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Byte budget for one console connection, refilled every second. Output past it is
+/// dropped and replaced by `TRUNCATED_MARKER` once per window.
+pub struct OutputLimiter {
+    /// Bytes per window; 0 lets everything through
+    limit: u64,
+    window_start: Instant,
+    used: u64,
+    truncated: bool,
+}
+
+impl OutputLimiter {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            window_start: Instant::now(),
+            used: 0,
+            truncated: false,
+        }
+    }
+
+    /// What of `chunk` to send now; `None` when all of it is dropped silently.
+    pub fn admit(&mut self, mut chunk: String) -> Option<String> {
+        if self.limit == 0 {
+            return Some(chunk);
+        }
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.used = 0;
+            self.truncated = false;
+        }
+
+        let left = self.limit.saturating_sub(self.used) as usize;
+        if chunk.len() <= left {
+            self.used += chunk.len() as u64;
+            return Some(chunk);
+        }
+        if self.truncated {
+            return None;
+        }
+
+        // Whatever still fits, cut on a character boundary, then the marker
+        let mut cut = left;
+        while !chunk.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        chunk.truncate(cut);
+        chunk.push_str(TRUNCATED_MARKER);
+        self.used = self.limit;
+        self.truncated = true;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_ansi_keeps_text_and_line_breaks() {
+        assert_eq!(
+            strip_ansi("\u{1b}[1;32m[INFO]\u{1b}[0m Done (3.2s)!\r\n"),
+            "[INFO] Done (3.2s)!\r\n"
+        );
+        // Window title, ended by BEL or by ESC \
+        assert_eq!(strip_ansi("\u{1b}]0;server\u{7}> "), "> ");
+        assert_eq!(strip_ansi("\u{1b}]2;title\u{1b}\\ok"), "ok");
+        // Two-byte escapes and cursor movement
+        assert_eq!(strip_ansi("\u{1b}7a\u{1b}8\u{1b}[2Kb"), "ab");
+        assert_eq!(strip_ansi("plain ünïcode"), "plain ünïcode");
+        // A sequence cut off at the end of a chunk is dropped
+        assert_eq!(strip_ansi("text\u{1b}[38;5"), "text");
+    }
+
+    #[test]
+    fn no_limit_lets_everything_through() {
+        let mut limiter = OutputLimiter::new(0);
+        let chunk = "x".repeat(1 << 20);
+        assert_eq!(limiter.admit(chunk.clone()), Some(chunk));
+    }
+
+    #[test]
+    fn output_over_the_budget_is_truncated_once_per_window() {
+        let mut limiter = OutputLimiter::new(10);
+        assert_eq!(limiter.admit("hello".to_string()).as_deref(), Some("hello"));
+        // Only 5 bytes left; "é" is two bytes and would straddle the cut
+        assert_eq!(
+            limiter.admit("abcdé more".to_string()),
+            Some(format!("abcd{}", TRUNCATED_MARKER))
+        );
+        assert_eq!(limiter.admit("dropped".to_string()), None);
+
+        // The next window starts with a fresh budget
+        limiter.window_start -= WINDOW;
+        assert_eq!(
+            limiter.admit("0123456789".to_string()).as_deref(),
+            Some("0123456789")
+        );
+        assert_eq!(
+            limiter.admit("!".to_string()),
+            Some(TRUNCATED_MARKER.to_string())
+        );
+    }
+}
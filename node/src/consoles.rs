@@ -0,0 +1,233 @@
+//! One attach per container, shared by every console viewer. The first viewer attaches;
+//! output fans out through a broadcast channel and the last `REPLAY_BYTES` are kept, so a
+//! console opened later starts with recent output instead of a blank screen. Stdin goes
+//! through a single writer, so lines from two viewers (or `/command`) don't interleave.
+//! The attach outlives its viewers by `IDLE_TIMEOUT` and ends with the container's output.
+
+use bollard::Docker;
+use bollard::container::AttachContainerOptions;
+use futures_util::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Recent output replayed to a viewer that joins
+const REPLAY_BYTES: usize = 64 * 1024;
+
+/// How long an attach with no viewers is kept for the next one
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Output chunks a slow viewer may fall behind before it skips ahead
+const CHANNEL_CAPACITY: usize = 1024;
+
+type Input = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// Output kept for replay, trimmed to `REPLAY_BYTES` from the front, and the channel live
+/// output goes out on. Both sit under one lock, so a joining viewer gets each chunk exactly
+/// once: in its replay or from the channel.
+struct Output {
+    chunks: VecDeque<String>,
+    bytes: usize,
+    /// Dropped when the attach ends, which closes every viewer's channel
+    sender: Option<broadcast::Sender<String>>,
+}
+
+impl Output {
+    fn push(&mut self, chunk: &str) {
+        self.bytes += chunk.len();
+        self.chunks.push_back(chunk.to_string());
+        while self.bytes > REPLAY_BYTES && self.chunks.len() > 1 {
+            if let Some(old) = self.chunks.pop_front() {
+                self.bytes -= old.len();
+            }
+        }
+    }
+}
+
+struct Broker {
+    /// Tells this attach apart from a later one for the same container
+    generation: u64,
+    output: Mutex<Output>,
+    input: tokio::sync::Mutex<Input>,
+    viewers: AtomicUsize,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Broker {
+    fn stop(&self) {
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ConsoleHub {
+    /// Container name -> its shared attach
+    brokers: tokio::sync::Mutex<HashMap<String, Arc<Broker>>>,
+    generations: AtomicU64,
+}
+
+/// One viewer's handle on a container's shared console. Dropping it lets the attach go
+/// once no viewer has come back within `IDLE_TIMEOUT`.
+pub struct ConsoleSession {
+    /// Recent output, to send before anything from `output`
+    pub replay: String,
+    pub output: broadcast::Receiver<String>,
+    hub: Arc<ConsoleHub>,
+    container: String,
+    broker: Arc<Broker>,
+}
+
+impl ConsoleSession {
+    /// A handle on the container's stdin, for the task reading the viewer's input.
+    pub fn writer(&self) -> ConsoleWriter {
+        ConsoleWriter {
+            broker: Arc::clone(&self.broker),
+        }
+    }
+}
+
+/// Writes to a container's stdin through its console's single writer.
+pub struct ConsoleWriter {
+    broker: Arc<Broker>,
+}
+
+impl ConsoleWriter {
+    pub async fn write(&self, bytes: &[u8]) -> std::io::Result<()> {
+        write_input(&self.broker, bytes).await
+    }
+}
+
+impl Drop for ConsoleSession {
+    fn drop(&mut self) {
+        if self.broker.viewers.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        let hub = Arc::clone(&self.hub);
+        let container = self.container.clone();
+        let generation = self.broker.generation;
+        tokio::spawn(async move {
+            tokio::time::sleep(IDLE_TIMEOUT).await;
+            let mut brokers = hub.brokers.lock().await;
+            if let Some(broker) = brokers.get(&container)
+                && broker.generation == generation
+                && broker.viewers.load(Ordering::SeqCst) == 0
+            {
+                broker.stop();
+                brokers.remove(&container);
+            }
+        });
+    }
+}
+
+async fn write_input(broker: &Broker, bytes: &[u8]) -> std::io::Result<()> {
+    let mut input = broker.input.lock().await;
+    input.write_all(bytes).await?;
+    input.flush().await
+}
+
+impl ConsoleHub {
+    /// Joins the container's console, attaching first when nobody is watching it yet.
+    pub async fn subscribe(
+        self: &Arc<Self>,
+        docker: &Docker,
+        container: &str,
+    ) -> Result<ConsoleSession, bollard::errors::Error> {
+        let mut brokers = self.brokers.lock().await;
+        let broker = match brokers.get(container) {
+            Some(broker) => Arc::clone(broker),
+            None => {
+                let broker = self.attach(docker, container).await?;
+                brokers.insert(container.to_string(), Arc::clone(&broker));
+                broker
+            }
+        };
+        broker.viewers.fetch_add(1, Ordering::SeqCst);
+        drop(brokers);
+
+        let (replay, output) = {
+            let output = broker.output.lock().unwrap();
+            let receiver = match &output.sender {
+                Some(sender) => sender.subscribe(),
+                // The attach ended just now; a closed channel sends the viewer off to reconnect
+                None => broadcast::channel(1).1,
+            };
+            (output.chunks.iter().map(String::as_str).collect(), receiver)
+        };
+        Ok(ConsoleSession {
+            replay,
+            output,
+            hub: Arc::clone(self),
+            container: container.to_string(),
+            broker,
+        })
+    }
+
+    /// Writes to the container's stdin through its console's writer; `None` when no
+    /// console is attached.
+    pub async fn write(&self, container: &str, bytes: &[u8]) -> Option<std::io::Result<()>> {
+        let broker = self.brokers.lock().await.get(container).cloned()?;
+        Some(write_input(&broker, bytes).await)
+    }
+
+    async fn attach(
+        self: &Arc<Self>,
+        docker: &Docker,
+        container: &str,
+    ) -> Result<Arc<Broker>, bollard::errors::Error> {
+        let options = Some(AttachContainerOptions::<String> {
+            stdin: Some(true),
+            stdout: Some(true),
+            stderr: Some(true),
+            stream: Some(true),
+            logs: Some(true),
+            ..Default::default()
+        });
+        let io = docker.attach_container(container, options).await?;
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let broker = Arc::new(Broker {
+            generation: self.generations.fetch_add(1, Ordering::SeqCst),
+            output: Mutex::new(Output {
+                chunks: VecDeque::new(),
+                bytes: 0,
+                sender: Some(sender),
+            }),
+            input: tokio::sync::Mutex::new(io.input),
+            viewers: AtomicUsize::new(0),
+            reader: Mutex::new(None),
+        });
+
+        let hub = Arc::clone(self);
+        let reading = Arc::clone(&broker);
+        let container = container.to_string();
+        let mut stream = io.output;
+        let reader = tokio::spawn(async move {
+            while let Some(Ok(chunk)) = stream.next().await {
+                let chunk = chunk.to_string();
+                let mut output = reading.output.lock().unwrap();
+                output.push(&chunk);
+                // No viewers right now is fine; the replay still has it
+                if let Some(sender) = &output.sender {
+                    let _ = sender.send(chunk);
+                }
+            }
+            // The container stopped: viewers see their channel close and reconnect
+            reading.output.lock().unwrap().sender = None;
+            let mut brokers = hub.brokers.lock().await;
+            if brokers
+                .get(&container)
+                .is_some_and(|b| b.generation == reading.generation)
+            {
+                brokers.remove(&container);
+            }
+        });
+        *broker.reader.lock().unwrap() = Some(reader);
+        Ok(broker)
+    }
+}
@@ -0,0 +1,197 @@
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::models::PortHolder;
+
+/// JSON body returned by every REST handler on failure.
+/// The panel matches on `code`; `error` is for humans. See API.md.
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub code: &'static str,
+    /// Host ports that caused a `port_in_use` conflict
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<u16>,
+    /// Managed containers publishing some of `ports`; ports held by anything else are missing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub holders: Vec<PortHolder>,
+    /// Operations that kept a `/self-update` from starting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_operations: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    /// Seconds the caller should wait before retrying (sent as `Retry-After`)
+    pub retry_after: Option<u64>,
+    /// Boxed: port conflicts are rare, and every handler's `Result` carries this type
+    pub ports_in_use: Option<Box<PortsInUse>>,
+    pub active_operations: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct PortsInUse {
+    pub ports: Vec<u16>,
+    pub holders: Vec<PortHolder>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+            ports_in_use: None,
+            active_operations: None,
+        }
+    }
+
+    pub fn busy(message: impl Into<String>, retry_after: u64) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..Self::new(StatusCode::TOO_MANY_REQUESTS, "node_busy", message)
+        }
+    }
+
+    /// A self-update is under way; the agent restarts shortly.
+    pub fn updating(retry_after: u64) -> Self {
+        Self {
+            retry_after: Some(retry_after),
+            ..Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "node_updating",
+                "Node agent is updating and will be back shortly",
+            )
+        }
+    }
+
+    /// `/self-update` without `force` while operations are still running.
+    pub fn operations_running(active: usize) -> Self {
+        Self {
+            active_operations: Some(active),
+            ..Self::new(
+                StatusCode::CONFLICT,
+                "operations_running",
+                format!(
+                    "Node is running {} operations; retry when they finish or force the update",
+                    active
+                ),
+            )
+        }
+    }
+
+    /// Each port is described as held by a managed container (and its server) or by an
+    /// unknown process: anything else on the host, including unmanaged containers.
+    pub fn ports_in_use(ports: Vec<u16>, holders: Vec<PortHolder>) -> Self {
+        let list = ports
+            .iter()
+            .map(|p| match holders.iter().find(|h| h.port == *p) {
+                Some(h) => format!(
+                    "{} (yunexal container {}, server {})",
+                    p, h.container, h.server_id
+                ),
+                None => format!("{} (unknown process)", p),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        Self {
+            ports_in_use: Some(Box::new(PortsInUse { ports, holders })),
+            ..Self::new(
+                StatusCode::CONFLICT,
+                "port_in_use",
+                format!("Ports already bound on this node: {}", list),
+            )
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or invalid token",
+        )
+    }
+
+    pub fn internal(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, code, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (ports, holders) = match self.ports_in_use {
+            Some(conflict) => (conflict.ports, conflict.holders),
+            None => (Vec::new(), Vec::new()),
+        };
+        let body = ErrorResponse {
+            error: self.message,
+            code: self.code,
+            ports,
+            holders,
+            active_operations: self.active_operations,
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn port_conflicts_name_each_port_and_its_holder() {
+        let holder = PortHolder {
+            port: 25565,
+            container: "yunexal-survival".to_string(),
+            server_id: "abc".to_string(),
+            state: "running".to_string(),
+        };
+        let response = ApiError::ports_in_use(vec![25565, 25566], vec![holder]).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = body_json(response).await;
+        assert_eq!(body["code"], "port_in_use");
+        assert_eq!(body["ports"], serde_json::json!([25565, 25566]));
+        assert_eq!(body["holders"][0]["container"], "yunexal-survival");
+        let message = body["error"].as_str().unwrap();
+        assert!(message.contains("25565 (yunexal container yunexal-survival, server abc)"));
+        assert!(message.contains("25566 (unknown process)"));
+    }
+
+    #[tokio::test]
+    async fn other_errors_leave_out_the_port_fields() {
+        let response = ApiError::new(StatusCode::NOT_FOUND, "image_not_found", "No such image")
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "No such image", "code": "image_not_found" })
+        );
+    }
+}
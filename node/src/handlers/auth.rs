@@ -1,10 +1,14 @@
+use crate::{
+    error::ApiError,
+    models::{HeartbeatPayload, NodeConfig, UpdateTokenRequest},
+    state::NodeState,
+};
 use axum::{
-    extract::{State, Request, Json},
+    extract::{Json, Request, State},
+    http::{HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
-    http::{StatusCode, HeaderMap},
 };
-use crate::{state::NodeState, models::{UpdateTokenRequest, HeartbeatPayload, NodeConfig}};
 use std::fs;
 
 pub async fn auth_middleware(
@@ -12,13 +16,14 @@ pub async fn auth_middleware(
     headers: HeaderMap,
     request: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, ApiError> {
     // Allow public endpoints
-    if request.uri().path() == "/health" {
+    if matches!(request.uri().path(), "/health" | "/version") {
         return Ok(next.run(request).await);
     }
 
-    let auth_header = headers.get("Authorization")
+    let auth_header = headers
+        .get("Authorization")
         .and_then(|h| h.to_str().ok())
         .and_then(|h| h.strip_prefix("Bearer "));
 
@@ -30,16 +35,20 @@ pub async fn auth_middleware(
             Ok(next.run(request).await)
         }
         _ => {
-            // User requested Error 500 for access denial
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        },
+            let mut err = ApiError::unauthorized();
+            if state.legacy_auth_error {
+                // Older panels expect 500 for access denial
+                err.status = StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            Err(err)
+        }
     }
 }
 
 pub async fn update_token_handler(
     State(state): State<NodeState>,
     Json(payload): Json<UpdateTokenRequest>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<StatusCode, ApiError> {
     let old_token = state.token.read().await.clone();
     let new_token = payload.token;
 
@@ -51,8 +60,8 @@ pub async fn update_token_handler(
 
     // 2. Try to ping Panel with new token
     let client = reqwest::Client::new();
-    let url = format!("{}/api/nodes/{}/heartbeat", state.panel_url, state.node_id);
-    
+    let url = format!("{}/nodes/{}/heartbeat", state.panel_url, state.node_id);
+
     // We send a dummy heartbeat just to verify auth
     let payload = HeartbeatPayload {
         node_id: state.node_id.clone(),
@@ -64,14 +73,19 @@ pub async fn update_token_handler(
         uptime: 0,
         version: env!("CARGO_PKG_VERSION").to_string(),
         timestamp: chrono::Utc::now().timestamp_millis(),
+        heartbeat_interval: state.heartbeat_interval,
+        cpu_cores: 0,
         disk_read: 0,
         disk_write: 0,
         net_rx: 0,
         net_tx: 0,
         disks: vec![],
+        containers: None,
+        update: None,
     };
 
-    let resp = client.post(&url)
+    let resp = client
+        .post(&url)
         .header("Authorization", format!("Bearer {}", new_token))
         .json(&payload)
         .send()
@@ -80,11 +94,11 @@ pub async fn update_token_handler(
     match resp {
         Ok(res) if res.status().is_success() => {
             // 3. Success: Write to config.yml
-            // We need to preserve limits if possible, but here we don't have them in state easily 
+            // We need to preserve limits if possible, but here we don't have them in state easily
             // unless we add them to NodeState.
-            // For now, let's default to 0 (Auto) if we are re-writing config, 
+            // For now, let's default to 0 (Auto) if we are re-writing config,
             // OR we should read the existing config first to preserve them.
-            
+
             let mut current_config = if let Ok(content) = fs::read_to_string("config.yml") {
                 serde_yaml::from_str::<NodeConfig>(&content).unwrap_or(NodeConfig {
                     token: "".to_string(),
@@ -94,43 +108,74 @@ pub async fn update_token_handler(
                     sftp_port: 2022,
                     ram_limit: 0,
                     disk_limit: 0,
+                    legacy_auth_error: state.legacy_auth_error,
+                    max_concurrent_creates: state.max_concurrent_creates,
+                    heartbeat_interval: state.heartbeat_interval,
+                    install_test_timeout: state.install_test_timeout,
+                    max_concurrent_install_tests: state.max_concurrent_install_tests,
+                    install_timeout: state.install_timeout,
+                    reboot_command: state.reboot_command.clone().unwrap_or_default(),
+                    console_output_limit: state.console_output_limit,
+                    console_strip_ansi: state.console_strip_ansi,
+                    stored_logs_limit: state.logs.limit(),
                 })
             } else {
-                 NodeConfig {
+                NodeConfig {
                     token: "".to_string(),
                     node_id: state.node_id.clone(),
                     panel_url: state.panel_url.clone(),
                     port: state.port,
                     sftp_port: 2022,
-                    ram_limit: 0, // Auto
+                    ram_limit: 0,  // Auto
                     disk_limit: 0, // Auto
+                    legacy_auth_error: state.legacy_auth_error,
+                    max_concurrent_creates: state.max_concurrent_creates,
+                    heartbeat_interval: state.heartbeat_interval,
+                    install_test_timeout: state.install_test_timeout,
+                    max_concurrent_install_tests: state.max_concurrent_install_tests,
+                    install_timeout: state.install_timeout,
+                    reboot_command: state.reboot_command.clone().unwrap_or_default(),
+                    console_output_limit: state.console_output_limit,
+                    console_strip_ansi: state.console_strip_ansi,
+                    stored_logs_limit: state.logs.limit(),
                 }
             };
-            
+
             // Update fields
             current_config.token = new_token;
             current_config.node_id = state.node_id.clone();
             current_config.panel_url = state.panel_url.clone();
             current_config.port = state.port;
-            
-            if let Ok(content) = serde_yaml::to_string(&current_config) {
-                if let Err(e) = fs::write("config.yml", content) {
-                    eprintln!("Failed to write config.yml: {}", e);
-                    // Revert memory? Or just log error? 
-                    // If we can't save, we should probably revert to avoid restart issues.
-                    let mut token_lock = state.token.write().await;
-                    *token_lock = old_token;
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
+
+            if let Ok(content) = serde_yaml::to_string(&current_config)
+                && let Err(e) = fs::write("config.yml", content)
+            {
+                eprintln!("Failed to write config.yml: {}", e);
+                // Revert memory? Or just log error?
+                // If we can't save, we should probably revert to avoid restart issues.
+                let mut token_lock = state.token.write().await;
+                *token_lock = old_token;
+                return Err(ApiError::internal(
+                    "config_write_failed",
+                    format!("Failed to write config.yml: {}", e),
+                ));
             }
-            
+
             Ok(StatusCode::OK)
-        },
-        _ => {
+        }
+        other => {
             // 4. Failure: Revert to old token
             let mut token_lock = state.token.write().await;
             *token_lock = old_token;
-            Err(StatusCode::UNAUTHORIZED) // Or 500
+            let reason = match other {
+                Ok(res) => format!("Panel rejected the new token with status {}", res.status()),
+                Err(e) => format!("Could not reach panel to verify the new token: {}", e),
+            };
+            Err(ApiError::new(
+                StatusCode::BAD_GATEWAY,
+                "token_verification_failed",
+                reason,
+            ))
         }
     }
 }
@@ -0,0 +1,45 @@
+use crate::{models::AgentConfigResponse, state::NodeState};
+use axum::{Json, extract::State};
+
+/// Effective configuration the agent is running with. The token is never returned.
+pub async fn get_config(State(state): State<NodeState>) -> Json<AgentConfigResponse> {
+    let config_file = std::path::Path::new("config.yml").exists();
+
+    Json(AgentConfigResponse {
+        node_id: state.node_id.clone(),
+        panel_url: state.panel_url.clone(),
+        port: state.port,
+        ram_limit: state.ram_limit,
+        disk_limit: state.disk_limit,
+        legacy_auth_error: state.legacy_auth_error,
+        max_concurrent_creates: state.max_concurrent_creates,
+        heartbeat_interval: state.heartbeat_interval,
+        reboot_enabled: state.reboot_command.is_some(),
+        console_output_limit: state.console_output_limit,
+        console_strip_ansi: state.console_strip_ansi,
+        stored_logs_limit: state.logs.limit(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        source: if config_file {
+            "config.yml"
+        } else {
+            "environment"
+        }
+        .to_string(),
+        token: "[redacted]".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn config_never_contains_the_token() {
+        let state = NodeState::for_tests("s3cret-node-token", "http://panel.test");
+        let Json(config) = get_config(State(state)).await;
+        assert_eq!(config.token, "[redacted]");
+        assert_eq!(config.panel_url, "http://panel.test");
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("s3cret-node-token"));
+    }
+}
@@ -1,23 +1,91 @@
-use crate::{models::CreateContainerRequest, state::NodeState};
+use crate::{
+    config_files, console,
+    error::ApiError,
+    handlers::install_test::data_volume,
+    models::{
+        CommandMode, CommandRequest, ConsoleQuery, ContainerPortBinding, ContainerState,
+        CreateContainerRequest, DeleteContainerQuery, DeleteContainerResponse,
+        DockerSummaryResponse, FreePortsQuery, FreePortsResponse, NetCounters, PortHolder,
+        PowerAction, PowerRequest, PullPolicy, UpdateLimitsRequest, UpdateLimitsResponse,
+    },
+    startup,
+    state::NodeState,
+};
 use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Json, Path, State},
+    extract::{
+        Json, Path, Query, State,
+        rejection::JsonRejection,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
     http::StatusCode,
     response::IntoResponse,
 };
 use bollard::container::{
-    AttachContainerOptions, Config as DockerConfig, CreateContainerOptions, ListContainersOptions,
-    RemoveContainerOptions, StartContainerOptions, StopContainerOptions,
+    AttachContainerOptions, AttachContainerResults, Config as DockerConfig, CreateContainerOptions,
+    InspectContainerOptions, KillContainerOptions, ListContainersOptions, RemoveContainerOptions,
+    RestartContainerOptions, StartContainerOptions, StatsOptions, StopContainerOptions,
+};
+use bollard::image::CreateImageOptions;
+use bollard::models::{
+    ContainerInspectResponse, ContainerSummary, ContainerSummaryStateEnum, ContainerUpdateBody,
+    SystemDataUsageResponse,
 };
 use bollard::service::{HostConfig, PortBinding};
-use futures_util::{StreamExt, SinkExt};
+use bollard::volume::RemoveVolumeOptions;
+use futures_util::{SinkExt, StreamExt};
 use std::collections::HashMap;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt; // For writing to container input
+use tokio::sync::broadcast;
 
 fn is_port_free(port: u16) -> bool {
     std::net::TcpListener::bind(("0.0.0.0", port)).is_ok()
 }
 
-pub async fn list_containers(State(state): State<NodeState>) -> Json<Vec<String>> {
+/// Most ports one `GET /free-ports` probes; each costs a bind
+pub const MAX_FREE_PORTS_RANGE: u32 = 1000;
+
+/// Ports in `start..=end` that `is_port_free` accepts, leaving out the agent's own port.
+fn free_ports_in(start: u16, end: u16, own_port: u16) -> Vec<u16> {
+    (start..=end)
+        .filter(|p| *p != own_port && is_port_free(*p))
+        .collect()
+}
+
+/// `GET /free-ports?start=&end=`: which host ports in the range nothing listens on, so the
+/// panel can suggest ports before creating allocations. Only as good as the moment it ran.
+pub async fn free_ports(
+    State(state): State<NodeState>,
+    Query(query): Query<FreePortsQuery>,
+) -> Result<Json<FreePortsResponse>, ApiError> {
+    let FreePortsQuery { start, end } = query;
+    if start == 0 || start > end {
+        return Err(ApiError::bad_request(format!(
+            "Invalid port range {}-{}",
+            start, end
+        )));
+    }
+    if (end - start) as u32 + 1 > MAX_FREE_PORTS_RANGE {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "range_too_large",
+            format!(
+                "At most {} ports can be checked at once",
+                MAX_FREE_PORTS_RANGE
+            ),
+        ));
+    }
+
+    let own_port = state.port;
+    let free = tokio::task::spawn_blocking(move || free_ports_in(start, end, own_port))
+        .await
+        .map_err(|e| ApiError::internal("probe_failed", format!("Port probe failed: {}", e)))?;
+    Ok(Json(FreePortsResponse { start, end, free }))
+}
+
+pub async fn list_containers(
+    State(state): State<NodeState>,
+) -> Result<Json<Vec<String>>, ApiError> {
     let mut filters: HashMap<String, Vec<String>> = HashMap::new();
     //DO NOT CHANGE THIS LABEL ANYWAY!
     //Why? Because it's used to identify containers created by Node.
@@ -51,27 +119,848 @@ pub async fn list_containers(State(state): State<NodeState>) -> Json<Vec<String>
                     format!("{} [{}]", name, state)
                 })
                 .collect();
-            Json(names)
+            Ok(Json(names))
         }
-        Err(_) => Json(vec!["Error listing containers".to_string()]),
+        Err(e) => Err(ApiError::internal(
+            "docker_error",
+            format!("Error listing containers: {}", e),
+        )),
     }
 }
 
-pub async fn create_container(
+/// Managed containers publishing any of `ports` on the host, so a `port_in_use` answer can
+/// name a container left behind by a server the panel no longer has. Only running
+/// containers report published ports; a failed listing just leaves the holders out.
+async fn port_holders(state: &NodeState, ports: &[u16]) -> Vec<PortHolder> {
+    let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+    filters.insert(
+        "label".to_string(),
+        vec!["yunexal.managed=true".to_string()],
+    );
+    let options = Some(ListContainersOptions {
+        all: true,
+        filters,
+        ..Default::default()
+    });
+
+    let containers = match state.docker.list_containers(options).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            eprintln!("Failed to list containers for a port conflict: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut holders = Vec::new();
+    for c in containers {
+        let Some(server_id) = c
+            .labels
+            .as_ref()
+            .and_then(|l| l.get("yunexal.server_id"))
+            .cloned()
+        else {
+            continue;
+        };
+        let container = c
+            .names
+            .as_ref()
+            .and_then(|n| n.first())
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+        let container_state = c.state.map(|s| s.to_string()).unwrap_or_default();
+        let mut published: Vec<u16> = c
+            .ports
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| p.public_port)
+            .filter(|p| ports.contains(p))
+            .collect();
+        // IPv4 and IPv6 bindings list the same port twice
+        published.sort_unstable();
+        published.dedup();
+        for port in published {
+            holders.push(PortHolder {
+                port,
+                container: container.clone(),
+                server_id: server_id.clone(),
+                state: container_state.clone(),
+            });
+        }
+    }
+    holders.sort_by_key(|h| h.port);
+    holders
+}
+
+/// `GET /port-bindings`: the host ports every managed container is set up to publish, taken
+/// from `inspect` so stopped containers are included, for auditing the panel's allocations.
+pub async fn port_bindings(
     State(state): State<NodeState>,
-    Json(payload): Json<CreateContainerRequest>,
-) -> Result<Json<String>, StatusCode> {
-    // Check if ports are available
-    for host_port in payload.ports.values() {
-        if let Ok(port) = host_port.parse::<u16>() {
-            if !is_port_free(port) {
-                eprintln!("Port {} is occupied on this node.", port);
-                return Err(StatusCode::CONFLICT);
+) -> Result<Json<Vec<ContainerPortBinding>>, ApiError> {
+    let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+    filters.insert(
+        "label".to_string(),
+        vec!["yunexal.managed=true".to_string()],
+    );
+    let options = Some(ListContainersOptions {
+        all: true,
+        filters,
+        ..Default::default()
+    });
+    let containers = state.docker.list_containers(options).await.map_err(|e| {
+        ApiError::internal("docker_error", format!("Error listing containers: {}", e))
+    })?;
+
+    let mut bindings = Vec::new();
+    for c in containers {
+        let Some(id) = c.id else { continue };
+        // Removed between the listing and the inspect
+        let Ok(info) = state
+            .docker
+            .inspect_container(&id, None::<InspectContainerOptions>)
+            .await
+        else {
+            continue;
+        };
+        let server_id = info
+            .config
+            .as_ref()
+            .and_then(|cfg| cfg.labels.as_ref())
+            .and_then(|l| l.get("yunexal.server_id"))
+            .cloned()
+            .unwrap_or_default();
+        let container = info
+            .name
+            .as_deref()
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string();
+        let container_state = info
+            .state
+            .and_then(|s| s.status)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        let published = info
+            .host_config
+            .and_then(|h| h.port_bindings)
+            .unwrap_or_default();
+        for (container_port, host) in published {
+            for binding in host.unwrap_or_default() {
+                let Some(host_port) = binding.host_port.and_then(|p| p.parse::<u16>().ok()) else {
+                    continue;
+                };
+                bindings.push(ContainerPortBinding {
+                    host_port,
+                    host_ip: binding.host_ip.unwrap_or_default(),
+                    container_port: container_port.clone(),
+                    container: container.clone(),
+                    server_id: server_id.clone(),
+                    state: container_state.clone(),
+                });
+            }
+        }
+    }
+    bindings.sort_by(|a, b| {
+        a.host_port
+            .cmp(&b.host_port)
+            .then_with(|| a.container.cmp(&b.container))
+    });
+    Ok(Json(bindings))
+}
+
+/// State of every managed container for the heartbeat. Start times come from inspecting
+/// the running ones, since the container list only has a human-readable status.
+pub async fn managed_container_states(state: &NodeState) -> Option<Vec<ContainerState>> {
+    let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+    filters.insert(
+        "label".to_string(),
+        vec!["yunexal.managed=true".to_string()],
+    );
+    let options = Some(ListContainersOptions {
+        all: true,
+        filters,
+        ..Default::default()
+    });
+
+    let containers = match state.docker.list_containers(options).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            eprintln!("Failed to list containers for heartbeat: {}", e);
+            return None;
+        }
+    };
+
+    let states = containers.into_iter().filter_map(|c| {
+        let server_id = c.labels.as_ref()?.get("yunexal.server_id")?.clone();
+        let markers = startup::markers(c.labels.as_ref());
+        let running = c.state == Some(ContainerSummaryStateEnum::RUNNING);
+        let container_state = c.state.map(|s| s.to_string()).unwrap_or_default();
+        let id = c.id.unwrap_or_default();
+        Some(async move {
+            let (started_at, traffic) = if running {
+                let started_at = state
+                    .docker
+                    .inspect_container(&id, None::<InspectContainerOptions>)
+                    .await
+                    .ok()
+                    .map_or(0, |info| {
+                        started_at_secs(info.state.and_then(|s| s.started_at))
+                    });
+                (started_at, network_traffic(state, &id).await)
+            } else {
+                (0, None)
+            };
+            if running {
+                state
+                    .logs
+                    .follow(&state.docker, &id, &server_id, started_at);
+            }
+            let ready = if running {
+                state
+                    .startup
+                    .ready(&state.docker, &id, &server_id, started_at, markers)
+            } else {
+                None
+            };
+            let current = ContainerState {
+                server_id,
+                state: container_state,
+                started_at,
+                rx_bytes: None,
+                tx_bytes: None,
+                ready,
+            };
+            (current, traffic)
+        })
+    });
+    let sampled = futures_util::future::join_all(states).await;
+
+    let live: std::collections::HashSet<&str> =
+        sampled.iter().map(|(c, _)| c.server_id.as_str()).collect();
+    state.startup.retain(&live);
+
+    let mut counters = state.net_counters.lock().unwrap();
+    counters.retain(|server_id, _| live.contains(server_id.as_str()));
+    let states = sampled
+        .into_iter()
+        .map(|(mut current, traffic)| {
+            let totals = match (counters.get_mut(&current.server_id), traffic) {
+                (Some(totals), Some((rx, tx))) => {
+                    totals.observe(rx, tx);
+                    *totals
+                }
+                (Some(totals), None) => *totals,
+                // Started under this agent: everything Docker counted is new
+                (None, Some((rx, tx))) if current.started_at >= state.started_at => {
+                    let mut totals = NetCounters::default();
+                    totals.observe(rx, tx);
+                    *counters.entry(current.server_id.clone()).or_insert(totals)
+                }
+                (None, Some((rx, tx))) => *counters
+                    .entry(current.server_id.clone())
+                    .or_insert(NetCounters::baseline(rx, tx)),
+                (None, None) => NetCounters::default(),
+            };
+            current.rx_bytes = Some(totals.rx);
+            current.tx_bytes = Some(totals.tx);
+            current
+        })
+        .collect();
+
+    Some(states)
+}
+
+/// Bytes received and sent over all of a container's networks, from a one-shot stats call.
+async fn network_traffic(state: &NodeState, id: &str) -> Option<(u64, u64)> {
+    let options = Some(StatsOptions {
+        stream: false,
+        one_shot: true,
+    });
+    let stats = state.docker.stats(id, options).next().await?.ok()?;
+    let networks = stats.networks?;
+    Some(networks.values().fold((0, 0), |(rx, tx), n| {
+        (rx + n.rx_bytes.unwrap_or(0), tx + n.tx_bytes.unwrap_or(0))
+    }))
+}
+
+/// Docker's RFC 3339 `StartedAt` as Unix seconds (0 when missing).
+fn started_at_secs(started_at: Option<String>) -> i64 {
+    started_at
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(&t).ok())
+        .map_or(0, |t| t.timestamp())
+}
+
+/// One managed container's state, straight from `docker inspect`.
+async fn inspect(state: &NodeState, uuid: &str) -> Result<ContainerInspectResponse, ApiError> {
+    state
+        .docker
+        .inspect_container(
+            &format!("yunexal-{}", uuid),
+            None::<InspectContainerOptions>,
+        )
+        .await
+        .map_err(|e| match e {
+            bollard::errors::Error::DockerResponseServerError {
+                status_code: 404,
+                message,
+            } => ApiError::new(StatusCode::NOT_FOUND, "container_not_found", message),
+            e => ApiError::internal("docker_error", e.to_string()),
+        })
+}
+
+async fn inspect_container_state(
+    state: &NodeState,
+    uuid: &str,
+) -> Result<ContainerState, ApiError> {
+    let info = inspect(state, uuid).await?;
+
+    let docker_state = info.state.unwrap_or_default();
+    let started_at = if docker_state.running.unwrap_or(false) {
+        started_at_secs(docker_state.started_at)
+    } else {
+        0
+    };
+    Ok(ContainerState {
+        server_id: uuid.to_string(),
+        state: docker_state
+            .status
+            .map_or_else(|| "unknown".to_string(), |s| s.to_string()),
+        started_at,
+        rx_bytes: None,
+        tx_bytes: None,
+        ready: None,
+    })
+}
+
+/// `GET /containers/{uuid}/state`
+pub async fn container_state(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+) -> Result<Json<ContainerState>, ApiError> {
+    inspect_container_state(&state, &uuid).await.map(Json)
+}
+
+/// `GET /containers/{uuid}/inspect`: Docker's inspect output as-is, for debugging from the panel
+pub async fn inspect_container(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+) -> Result<Json<ContainerInspectResponse>, ApiError> {
+    inspect(&state, &uuid).await.map(Json)
+}
+
+/// Starts, stops, restarts or kills a container and answers with the state Docker reports
+/// right after. `start` and `kill` return immediately; the container may still be coming up.
+pub async fn power_container(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+    payload: Result<Json<PowerRequest>, JsonRejection>,
+) -> Result<Json<ContainerState>, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let _operation = state.operations.begin()?;
+    let container_name = format!("yunexal-{}", uuid);
+    let grace = payload
+        .grace
+        .unwrap_or(DEFAULT_STOP_GRACE)
+        .min(MAX_STOP_GRACE);
+
+    let result = match payload.action {
+        PowerAction::Start => {
+            if let Some(rules) = config_files::stored(&state.docker, &container_name).await
+                && !rules.running
+            {
+                rules.apply(&state.docker, &container_name).await;
+            }
+            state
+                .docker
+                .start_container(&container_name, None::<StartContainerOptions<String>>)
+                .await
+        }
+        PowerAction::Stop => {
+            if stop_or_kill(&state, &container_name, grace).await {
+                eprintln!(
+                    "Container {} did not stop within {}s, killed it",
+                    container_name, grace
+                );
+            }
+            Ok(())
+        }
+        // Containers with config rules stop first so the rewrite lands between the two runs
+        PowerAction::Restart => match config_files::stored(&state.docker, &container_name).await {
+            Some(rules) => {
+                if stop_or_kill(&state, &container_name, grace).await {
+                    eprintln!(
+                        "Container {} did not stop within {}s, killed it",
+                        container_name, grace
+                    );
+                }
+                rules.apply(&state.docker, &container_name).await;
+                state
+                    .docker
+                    .start_container(&container_name, None::<StartContainerOptions<String>>)
+                    .await
+            }
+            None => {
+                state
+                    .docker
+                    .restart_container(
+                        &container_name,
+                        Some(RestartContainerOptions { t: grace as isize }),
+                    )
+                    .await
+            }
+        },
+        PowerAction::Kill => {
+            state
+                .docker
+                .kill_container(
+                    &container_name,
+                    Some(KillContainerOptions { signal: "SIGKILL" }),
+                )
+                .await
+        }
+    };
+
+    match result {
+        // Already in the requested state: 304 from start, 409 from killing a stopped container
+        Ok(())
+        | Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 304 | 409,
+            ..
+        }) => {}
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message,
+        }) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "container_not_found",
+                message,
+            ));
+        }
+        Err(e) => {
+            eprintln!(
+                "Power action {:?} on {} failed: {}",
+                payload.action, container_name, e
+            );
+            return Err(ApiError::internal("power_action_failed", e.to_string()));
+        }
+    }
+
+    inspect_container_state(&state, &uuid).await.map(Json)
+}
+
+/// Authoritative Docker usage on this host: container counts (ours vs. everything else),
+/// images, volumes and how much `docker system prune` could win back.
+pub async fn docker_summary(
+    State(state): State<NodeState>,
+) -> Result<Json<DockerSummaryResponse>, ApiError> {
+    let options = Some(ListContainersOptions::<String> {
+        all: true,
+        ..Default::default()
+    });
+    let containers = state.docker.list_containers(options).await.map_err(|e| {
+        ApiError::internal("docker_error", format!("Error listing containers: {}", e))
+    })?;
+    let usage = state.docker.df(None).await.map_err(|e| {
+        ApiError::internal("docker_error", format!("Error reading disk usage: {}", e))
+    })?;
+
+    Ok(Json(summarize(&containers, &usage)))
+}
+
+fn summarize(
+    containers: &[ContainerSummary],
+    usage: &SystemDataUsageResponse,
+) -> DockerSummaryResponse {
+    let mut summary = DockerSummaryResponse {
+        containers_total: containers.len() as u64,
+        ..Default::default()
+    };
+
+    for c in containers {
+        let managed = c
+            .labels
+            .as_ref()
+            .and_then(|l| l.get("yunexal.managed"))
+            .is_some_and(|v| v == "true");
+        if managed {
+            summary.containers_managed += 1;
+        } else {
+            summary.containers_unmanaged += 1;
+        }
+
+        if c.state == Some(ContainerSummaryStateEnum::RUNNING) {
+            summary.containers_running += 1;
+        } else {
+            summary.containers_stopped += 1;
+        }
+    }
+
+    for image in usage.images.iter().flatten() {
+        summary.images += 1;
+        summary.images_size += image.size;
+        if image.containers == 0 {
+            summary.reclaimable += image.size;
+        }
+    }
+
+    for volume in usage.volumes.iter().flatten() {
+        summary.volumes += 1;
+        if let Some(data) = &volume.usage_data {
+            // Docker reports -1 when it couldn't size the volume
+            let size = data.size.max(0);
+            summary.volumes_size += size;
+            if data.ref_count == 0 {
+                summary.reclaimable += size;
+            }
+        }
+    }
+
+    for cache in usage.build_cache.iter().flatten() {
+        let size = cache.size.unwrap_or(0);
+        summary.build_cache_size += size;
+        if !cache.in_use.unwrap_or(false) {
+            summary.reclaimable += size;
+        }
+    }
+
+    summary
+}
+
+/// One percent of one core in Docker's NanoCpus (billionths of a CPU).
+const NANO_CPUS_PER_PERCENT: i64 = 10_000_000;
+
+fn available_cores() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Converts `cpu_limit` (percent of one core, 100 = one core) to NanoCpus.
+/// 0 means unlimited and yields None, so no NanoCpus is sent at all.
+fn nano_cpus(cpu_limit: f64) -> Result<Option<i64>, ApiError> {
+    if !cpu_limit.is_finite() || cpu_limit < 0.0 {
+        return Err(ApiError::bad_request(format!(
+            "cpu_limit must be 0 (unlimited) or a positive percentage, got {}",
+            cpu_limit
+        )));
+    }
+    if cpu_limit == 0.0 {
+        return Ok(None);
+    }
+
+    // Docker's own bounds are 0.01 CPU up to the host's core count
+    let cores = available_cores();
+    if cpu_limit < 1.0 || cpu_limit > cores as f64 * 100.0 {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "cpu_limit_out_of_range",
+            format!(
+                "cpu_limit {}% is outside 1-{}% ({} cores on this node)",
+                cpu_limit,
+                cores * 100,
+                cores
+            ),
+        ));
+    }
+    Ok(Some(
+        (cpu_limit * NANO_CPUS_PER_PERCENT as f64).round() as i64
+    ))
+}
+
+/// Validates `run_as_user`: a name, uid or `uid:gid`. Blank means the image's default user.
+fn container_user(run_as_user: Option<&str>) -> Result<Option<String>, ApiError> {
+    let Some(user) = run_as_user.map(str::trim).filter(|u| !u.is_empty()) else {
+        return Ok(None);
+    };
+    let valid = user.len() <= 64
+        && user.split(':').count() <= 2
+        && user.split(':').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        });
+    if !valid {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_run_as_user",
+            format!("run_as_user '{}' must be a user name, uid or uid:gid", user),
+        ));
+    }
+    Ok(Some(user.to_string()))
+}
+
+/// Docker `SecurityOpt` entries for a new container.
+fn security_opts(no_new_privileges: bool) -> Option<Vec<String>> {
+    no_new_privileges.then(|| vec!["no-new-privileges:true".to_string()])
+}
+
+/// Splits `repo[:tag]` / `repo@digest` into Docker's `fromImage` and `tag`. An untagged
+/// image gets `latest`; an empty tag would make Docker pull every tag of the repository.
+fn image_pull_ref(image: &str) -> (String, String) {
+    if image.contains('@') {
+        return (image.to_string(), String::new());
+    }
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    match image[name_start..].rfind(':') {
+        Some(i) => (
+            image[..name_start + i].to_string(),
+            image[name_start + i + 1..].to_string(),
+        ),
+        None => (image.to_string(), "latest".to_string()),
+    }
+}
+
+/// Whether a create pulls its image first. `present` (is the image on the node) is only
+/// looked up for `if_not_present`; the other policies ignore it.
+fn should_pull(policy: PullPolicy, present: Option<bool>) -> bool {
+    match policy {
+        PullPolicy::Never => false,
+        PullPolicy::Always => true,
+        PullPolicy::IfNotPresent => present == Some(false),
+    }
+}
+
+/// Pulls `image` when the pull policy asks for it. With `never`, or when the image is already
+/// present for `if_not_present`, Docker is left alone and create reports a missing image.
+async fn ensure_image(state: &NodeState, image: &str, policy: PullPolicy) -> Result<(), ApiError> {
+    let present = match policy {
+        PullPolicy::IfNotPresent => match state.docker.inspect_image(image).await {
+            Ok(_) => Some(true),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Some(false),
+            Err(e) => return Err(ApiError::internal("docker_error", e.to_string())),
+        },
+        PullPolicy::Always | PullPolicy::Never => None,
+    };
+    if !should_pull(policy, present) {
+        return Ok(());
+    }
+
+    let (from_image, tag) = image_pull_ref(image);
+    tracing::info!("Pulling image {} ({:?})", image, policy);
+    let mut progress = state.docker.create_image(
+        Some(CreateImageOptions {
+            from_image,
+            tag,
+            ..Default::default()
+        }),
+        None,
+        None,
+    );
+    while let Some(step) = progress.next().await {
+        if let Err(e) = step {
+            tracing::error!("Failed to pull image {}: {}", image, e);
+            return Err(match e {
+                bollard::errors::Error::DockerResponseServerError {
+                    status_code: 404,
+                    message,
+                } => ApiError::new(StatusCode::NOT_FOUND, "image_not_found", message),
+                e => ApiError::internal("image_pull_failed", e.to_string()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Splits a startup command into arguments the way `sh` would for plain words: whitespace
+/// separates, single quotes keep everything literally, double quotes keep spaces but allow
+/// `\"` and `\\`, and a backslash outside quotes escapes the next character. There is no
+/// variable expansion, globbing or redirection.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // Distinguishes `''` (an empty argument) from no argument at all
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => {
+                            return Err("Unterminated single quote in startup command".to_string());
+                        }
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => {
+                                return Err(
+                                    "Unterminated double quote in startup command".to_string()
+                                );
+                            }
+                        },
+                        Some(c) => current.push(c),
+                        None => {
+                            return Err("Unterminated double quote in startup command".to_string());
+                        }
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => current.push(c),
+                    None => return Err("Startup command ends with a lone backslash".to_string()),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
             }
         }
     }
+    if in_word {
+        args.push(current);
+    }
+
+    if args.is_empty() {
+        return Err("Startup command is empty".to_string());
+    }
+    Ok(args)
+}
+
+pub async fn create_container(
+    State(state): State<NodeState>,
+    payload: Result<Json<CreateContainerRequest>, JsonRejection>,
+) -> Result<Json<String>, ApiError> {
+    let Json(mut payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let _operation = state.operations.begin()?;
 
+    // Rejected up front, before any ports are checked or images pulled
+    let cmd =
+        match payload.command_mode {
+            // Wrap command in shell to ensure variable expansion and simple parsing works
+            CommandMode::Shell => Some(vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                std::mem::take(&mut payload.startup_command),
+            ]),
+            CommandMode::Argv => Some(split_command(&payload.startup_command).map_err(|e| {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_startup_command", e)
+            })?),
+            CommandMode::Entrypoint => None,
+        };
+
+    // Small hosts fall over when dozens of creates/pulls land at once
+    let _permit = state
+        .create_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| {
+            ApiError::busy(
+                format!(
+                    "Node is already running {} container operations",
+                    state.max_concurrent_creates
+                ),
+                5,
+            )
+        })?;
+
+    // A retry of a create that got through: the container exists, so it only needs to run.
+    // Checked before the ports, which that container holds itself.
     let container_name = format!("yunexal-{}", payload.uuid);
+    if let Ok(existing) = state
+        .docker
+        .inspect_container(&container_name, None::<InspectContainerOptions>)
+        .await
+    {
+        let running = existing.state.and_then(|s| s.running).unwrap_or(false);
+        if !running
+            && payload.start
+            && let Err(e) = state
+                .docker
+                .start_container(&container_name, None::<StartContainerOptions<String>>)
+                .await
+        {
+            eprintln!(
+                "Failed to start existing container {}: {}",
+                container_name, e
+            );
+            return Err(ApiError::internal("container_start_failed", e.to_string()));
+        }
+        eprintln!(
+            "Container {} already exists, treating the create as done",
+            container_name
+        );
+        return Ok(Json(existing.id.unwrap_or(container_name)));
+    }
+
+    // The agent's own port is always busy; say so instead of blaming an unknown process
+    if payload
+        .ports
+        .values()
+        .any(|p| p.parse::<u16>() == Ok(state.port))
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "port_reserved",
+            format!("Port {} is the node agent's own API port", state.port),
+        ));
+    }
+
+    // Check if ports are available, reporting every conflict at once
+    let mut occupied: Vec<u16> = payload
+        .ports
+        .values()
+        .filter_map(|p| p.parse::<u16>().ok())
+        .filter(|p| !is_port_free(*p))
+        .collect();
+    if !occupied.is_empty() {
+        occupied.sort_unstable();
+        let holders = port_holders(&state, &occupied).await;
+        eprintln!("Ports {:?} are occupied on this node.", occupied);
+        for holder in &holders {
+            eprintln!(
+                "Port {} is published by managed container {} ({}) of server {}",
+                holder.port, holder.container, holder.state, holder.server_id
+            );
+        }
+        return Err(ApiError::ports_in_use(occupied, holders));
+    }
+
+    let nano_cpus = nano_cpus(payload.cpu_limit)?;
+    let user = container_user(payload.run_as_user.as_deref())?;
+
+    ensure_image(&state, &payload.image, payload.pull_policy).await?;
+
+    // Where the image expects its files; /home/container for most eggs
+    let binds = if payload.data_volume {
+        let workdir = state
+            .docker
+            .inspect_image(&payload.image)
+            .await
+            .ok()
+            .and_then(|i| i.config)
+            .and_then(|c| c.working_dir)
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(|| "/home/container".to_string());
+        Some(vec![format!("{}:{}", data_volume(&payload.uuid), workdir)])
+    } else {
+        None
+    };
 
     let options = Some(CreateContainerOptions {
         name: container_name.clone(),
@@ -81,6 +970,19 @@ pub async fn create_container(
     let mut labels = HashMap::new();
     labels.insert("yunexal.managed".to_string(), "true".to_string());
     labels.insert("yunexal.server_id".to_string(), payload.uuid.clone());
+    if let Some(rules) = payload
+        .config_files
+        .as_ref()
+        .filter(|r| r.as_object().is_some_and(|f| !f.is_empty()))
+    {
+        labels.insert(config_files::RULES_LABEL.to_string(), rules.to_string());
+    }
+    if !payload.startup_done.is_empty() {
+        labels.insert(
+            startup::DONE_LABEL.to_string(),
+            serde_json::to_string(&payload.startup_done).unwrap_or_default(),
+        );
+    }
 
     // Port Bindings
     let mut port_bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
@@ -96,9 +998,12 @@ pub async fn create_container(
     let host_config = HostConfig {
         memory: Some(payload.memory_limit * 1024 * 1024), // MB to Bytes
         memory_swap: Some(payload.swap_limit * 1024 * 1024),
-        nano_cpus: Some(payload.cpu_limit * 10_000_000), 
+        nano_cpus,
         blkio_weight: Some(payload.io_weight),
-        port_bindings: Some(port_bindings),
+        // Portless servers get no bindings at all rather than an empty map
+        port_bindings: (!port_bindings.is_empty()).then_some(port_bindings),
+        security_opt: security_opts(payload.no_new_privileges),
+        binds,
         ..Default::default()
     };
 
@@ -111,16 +1016,13 @@ pub async fn create_container(
 
     let config = DockerConfig {
         image: Some(payload.image),
+        user,
         labels: Some(labels),
         env: Some(env),
-        // Wrap command in shell to ensure variable expansion and simple parsing works
-        cmd: Some(vec![
-            "/bin/sh".to_string(),
-            "-c".to_string(),
-            payload.startup_command,
-        ]),
+        cmd,
         host_config: Some(host_config),
-        tty: Some(true), // Enable TTY for console access
+        stop_timeout: payload.stop_timeout.map(|t| t.min(MAX_STOP_GRACE) as i64),
+        tty: Some(true),        // Enable TTY for console access
         open_stdin: Some(true), // Keep stdin open
         attach_stdin: Some(true),
         attach_stdout: Some(true),
@@ -130,34 +1032,80 @@ pub async fn create_container(
 
     match state.docker.create_container(options, config).await {
         Ok(res) => {
-            // Start the container
+            // Sync config files with this server's ports and variables before it first reads them
+            if let Some(config_files) = &payload.config_files {
+                config_files::apply(&state.docker, &res.id, config_files, &payload.environment)
+                    .await;
+            }
+
+            if !payload.start {
+                return Ok(Json(res.id));
+            }
             if let Err(e) = state
                 .docker
                 .start_container(&res.id, None::<StartContainerOptions<String>>)
                 .await
             {
                 eprintln!("Failed to start container: {}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                return Err(ApiError::internal("container_start_failed", e.to_string()));
             }
             Ok(Json(res.id))
         }
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message,
+        }) => {
+            eprintln!("Image not found: {}", message);
+            Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                "image_not_found",
+                message,
+            ))
+        }
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 400,
+            message,
+        }) => {
+            eprintln!("Docker rejected the container config: {}", message);
+            Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_container_config",
+                message,
+            ))
+        }
         Err(e) => {
             eprintln!("Failed to create container: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(ApiError::internal("container_create_failed", e.to_string()))
         }
     }
 }
 
+/// Default seconds a container gets to stop before it is killed.
+const DEFAULT_STOP_GRACE: u64 = 10;
+/// Upper bound for the caller's `grace`, so one request can't pin a handler for ages.
+const MAX_STOP_GRACE: u64 = 300;
+/// Extra time on top of the grace period before we stop waiting for Docker's own stop call.
+const STOP_CALL_SLACK: u64 = 5;
+
 pub async fn delete_container(
     State(state): State<NodeState>,
     Path(uuid): Path<String>,
-) -> Result<Json<String>, StatusCode> {
+    Query(query): Query<DeleteContainerQuery>,
+) -> Result<Json<DeleteContainerResponse>, ApiError> {
+    let _operation = state.operations.begin()?;
     let container_name = format!("yunexal-{}", uuid);
+    let grace = query
+        .grace
+        .unwrap_or(DEFAULT_STOP_GRACE)
+        .min(MAX_STOP_GRACE);
 
-    // Stop container
-    let stop_opts = Some(StopContainerOptions { t: 10 });
-    // Ignore error if already stopped or not found
-    let _ = state.docker.stop_container(&container_name, stop_opts).await;
+    let killed = stop_or_kill(&state, &container_name, grace).await;
+    if killed {
+        eprintln!(
+            "Container {} did not stop within {}s, killed it",
+            container_name, grace
+        );
+    }
 
     // Remove container
     let remove_opts = Some(RemoveContainerOptions {
@@ -165,11 +1113,161 @@ pub async fn delete_container(
         ..Default::default()
     });
 
-    match state.docker.remove_container(&container_name, remove_opts).await {
-        Ok(_) => Ok(Json("deleted".to_string())),
+    let removed = state
+        .docker
+        .remove_container(&container_name, remove_opts)
+        .await;
+    // With `purge` the server's files go too, only once the container no longer holds them;
+    // also when only a failed install left them behind
+    let purged = query.purge && !matches!(removed, Err(ref e) if !is_not_found(e));
+    if purged {
+        remove_data_volume(&state, &uuid).await;
+        state.logs.remove(&uuid).await;
+    }
+
+    match removed {
+        Ok(_) => Ok(Json(DeleteContainerResponse {
+            status: "deleted",
+            killed,
+            purged,
+        })),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message,
+        }) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            message,
+        )),
         Err(e) => {
             eprintln!("Failed to delete container: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(ApiError::internal("container_delete_failed", e.to_string()))
+        }
+    }
+}
+
+fn is_not_found(e: &bollard::errors::Error) -> bool {
+    matches!(
+        e,
+        bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+async fn remove_data_volume(state: &NodeState, uuid: &str) {
+    let volume = data_volume(uuid);
+    match state
+        .docker
+        .remove_volume(&volume, Some(RemoveVolumeOptions { force: true }))
+        .await
+    {
+        Ok(()) => println!("Removed data volume {}", volume),
+        Err(e) if is_not_found(&e) => {}
+        Err(e) => eprintln!("Failed to remove data volume {}: {}", volume, e),
+    }
+}
+
+/// Stops a container, giving it `grace` seconds before Docker kills it. If the stop call
+/// fails or hangs (a wedged container can keep the daemon from answering), sends SIGKILL.
+/// Returns whether the container had to be killed by us.
+async fn stop_or_kill(state: &NodeState, name: &str, grace: u64) -> bool {
+    let stop = state
+        .docker
+        .stop_container(name, Some(StopContainerOptions { t: grace as i64 }));
+
+    match tokio::time::timeout(Duration::from_secs(grace + STOP_CALL_SLACK), stop).await {
+        Ok(Ok(())) => false,
+        // Already stopped (304) or gone (404); removal sorts out the rest
+        Ok(Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 304 | 404,
+            ..
+        })) => false,
+        Ok(Err(e)) => {
+            eprintln!("Failed to stop container {}: {}", name, e);
+            kill(state, name).await
+        }
+        Err(_) => kill(state, name).await,
+    }
+}
+
+async fn kill(state: &NodeState, name: &str) -> bool {
+    match state
+        .docker
+        .kill_container(name, Some(KillContainerOptions { signal: "SIGKILL" }))
+        .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            // Removal uses force, so this is not fatal
+            eprintln!("Failed to kill container {}: {}", name, e);
+            false
+        }
+    }
+}
+
+/// Applies new resource limits to a running container without recreating it.
+pub async fn update_container_limits(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+    payload: Result<Json<UpdateLimitsRequest>, JsonRejection>,
+) -> Result<Json<UpdateLimitsResponse>, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let container_name = format!("yunexal-{}", uuid);
+    let mut warnings = Vec::new();
+    // Docker ignores a zero NanoCpus on update, so lifting the limit means allowing every core
+    let nano_cpus = nano_cpus(payload.cpu_limit)?
+        .unwrap_or(available_cores() as i64 * NANO_CPUS_PER_PERCENT * 100);
+
+    // Docker refuses (or OOM-kills) when the limit drops below what the container already uses
+    let memory_bytes = payload.memory_limit * 1024 * 1024;
+    if memory_bytes > 0 {
+        let options = Some(StatsOptions {
+            stream: false,
+            one_shot: true,
+        });
+        if let Some(Ok(stats)) = state.docker.stats(&container_name, options).next().await
+            && let Some(usage) = stats.memory_stats.and_then(|m| m.usage)
+            && usage as i64 > memory_bytes
+        {
+            warnings.push(format!(
+                "New memory limit {} MB is below current usage {} MB",
+                payload.memory_limit,
+                usage / 1024 / 1024
+            ));
+        }
+    }
+
+    let update = ContainerUpdateBody {
+        memory: Some(memory_bytes),
+        memory_swap: Some(payload.swap_limit * 1024 * 1024),
+        nano_cpus: Some(nano_cpus),
+        blkio_weight: Some(payload.io_weight),
+        ..Default::default()
+    };
+
+    match state.docker.update_container(&container_name, update).await {
+        Ok(()) => Ok(Json(UpdateLimitsResponse { warnings })),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message,
+        }) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            message,
+        )),
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 400,
+            message,
+        }) => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_container_config",
+            message,
+        )),
+        Err(e) => {
+            eprintln!("Failed to update container limits: {}", e);
+            Err(ApiError::internal("container_update_failed", e.to_string()))
         }
     }
 }
@@ -177,33 +1275,125 @@ pub async fn delete_container(
 pub async fn console_handler(
     State(state): State<NodeState>,
     Path(uuid): Path<String>,
+    Query(query): Query<ConsoleQuery>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, uuid))
+    let strip_ansi = match query.ansi.as_deref() {
+        Some("raw") => false,
+        Some("strip") => true,
+        _ => state.console_strip_ansi,
+    };
+    ws.on_upgrade(move |socket| handle_socket(socket, state, uuid, strip_ansi))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: NodeState, uuid: String) {
-    let container_name = format!("yunexal-{}", uuid);
-
+/// Attaches to a container's stdin only; consoles attach through `state.consoles`.
+async fn attach_stdin(
+    state: &NodeState,
+    container_name: &str,
+) -> Result<AttachContainerResults, bollard::errors::Error> {
     let options = Some(AttachContainerOptions::<String> {
         stdin: Some(true),
-        stdout: Some(true),
-        stderr: Some(true),
+        stdout: Some(false),
+        stderr: Some(false),
         stream: Some(true),
-        logs: Some(true),
+        logs: Some(false),
         ..Default::default()
     });
+    state.docker.attach_container(container_name, options).await
+}
+
+/// Longest line `/command` accepts
+const MAX_COMMAND_LEN: usize = 1024;
 
-    match state.docker.attach_container(&container_name, options).await {
-        Ok(io) => {
+/// Writes one line to the container's stdin over the console's attach path, without
+/// keeping a session open. Fails with 409 when the container isn't running.
+pub async fn send_command(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+    payload: Result<Json<CommandRequest>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    let command = payload.command.trim_end_matches(['\r', '\n']);
+    if command.trim().is_empty()
+        || command.contains(['\r', '\n'])
+        || command.len() > MAX_COMMAND_LEN
+    {
+        return Err(ApiError::bad_request(format!(
+            "command must be a single non-empty line of at most {} bytes",
+            MAX_COMMAND_LEN
+        )));
+    }
+
+    let info = inspect(&state, &uuid).await?;
+    if !info.state.and_then(|s| s.running).unwrap_or(false) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "container_not_running",
+            "Container is not running",
+        ));
+    }
+
+    let container_name = format!("yunexal-{}", uuid);
+    let line = format!("{}\n", command);
+    // With a console open, go through its writer so the line can't land inside a viewer's
+    if let Some(written) = state.consoles.write(&container_name, line.as_bytes()).await {
+        return written.map(|_| StatusCode::NO_CONTENT).map_err(|e| {
+            ApiError::internal("command_failed", format!("Failed to write command: {}", e))
+        });
+    }
+    let mut io = attach_stdin(&state, &container_name).await.map_err(|e| {
+        ApiError::internal(
+            "command_failed",
+            format!("Failed to attach to container: {}", e),
+        )
+    })?;
+    io.input.write_all(line.as_bytes()).await.map_err(|e| {
+        ApiError::internal("command_failed", format!("Failed to write command: {}", e))
+    })?;
+    let _ = io.input.flush().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn handle_socket(mut socket: WebSocket, state: NodeState, uuid: String, strip_ansi: bool) {
+    let container_name = format!("yunexal-{}", uuid);
+    let mut limiter = console::OutputLimiter::new(state.console_output_limit);
+    let shape = move |text: String| {
+        if strip_ansi {
+            console::strip_ansi(&text)
+        } else {
+            text
+        }
+    };
+
+    match state
+        .consoles
+        .subscribe(&state.docker, &container_name)
+        .await
+    {
+        Ok(mut session) => {
             let (mut ws_sender, mut ws_receiver) = socket.split();
-            let mut container_output = io.output;
-            let mut container_input = io.input;
+            let input = session.writer();
+
+            // Recent output first, outside the rate cap; it is bounded already
+            let replay = shape(session.replay.clone());
+            if !replay.is_empty() && ws_sender.send(Message::Text(replay.into())).await.is_err() {
+                return;
+            }
 
             // Task to forward container output to WebSocket
             let mut send_task = tokio::spawn(async move {
-                while let Some(Ok(msg)) = container_output.next().await {
-                    let text = msg.to_string(); 
+                loop {
+                    let text = match session.output.recv().await {
+                        Ok(text) => text,
+                        // Fell behind the shared stream; skip ahead like the cap would
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            console::TRUNCATED_MARKER.to_string()
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let Some(text) = limiter.admit(shape(text)) else {
+                        continue;
+                    };
                     if ws_sender.send(Message::Text(text.into())).await.is_err() {
                         break;
                     }
@@ -214,15 +1404,15 @@ async fn handle_socket(mut socket: WebSocket, state: NodeState, uuid: String) {
             let mut recv_task = tokio::spawn(async move {
                 while let Some(Ok(msg)) = ws_receiver.next().await {
                     if let Message::Text(text) = msg {
-                        if container_input.write_all(text.as_bytes()).await.is_err() {
+                        if input.write(text.as_bytes()).await.is_err() {
                             break;
                         }
                     } else if let Message::Close(_) = msg {
-                         break;
+                        break;
                     }
                 }
             });
-            
+
             tokio::select! {
                 _ = (&mut send_task) => recv_task.abort(),
                 _ = (&mut recv_task) => send_task.abort(),
@@ -230,8 +1420,326 @@ async fn handle_socket(mut socket: WebSocket, state: NodeState, uuid: String) {
         }
         Err(e) => {
             let _ = socket
-                .send(Message::Text(format!("Error attaching to container: {}", e).into()))
+                .send(Message::Text(
+                    format!("Error attaching to container: {}", e).into(),
+                ))
                 .await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn split_command_splits_on_whitespace() {
+        assert_eq!(
+            split_command("java  -Xmx1G\t-jar server.jar").unwrap(),
+            args(&["java", "-Xmx1G", "-jar", "server.jar"])
+        );
+    }
+
+    #[test]
+    fn split_command_keeps_single_quotes_literal() {
+        assert_eq!(
+            split_command(r#"echo 'a "b" \c'"#).unwrap(),
+            args(&["echo", r#"a "b" \c"#])
+        );
+    }
+
+    #[test]
+    fn split_command_handles_double_quote_escapes() {
+        assert_eq!(
+            split_command(r#"echo "say \"hi\" \\ \n""#).unwrap(),
+            args(&["echo", r#"say "hi" \ \n"#])
+        );
+    }
+
+    #[test]
+    fn split_command_escapes_outside_quotes() {
+        assert_eq!(
+            split_command(r"touch my\ file \'x").unwrap(),
+            args(&["touch", "my file", "'x"])
+        );
+    }
+
+    #[test]
+    fn split_command_keeps_empty_quoted_arguments() {
+        assert_eq!(
+            split_command("run '' \"\" x''y").unwrap(),
+            args(&["run", "", "", "xy"])
+        );
+    }
+
+    #[test]
+    fn split_command_rejects_unterminated_input() {
+        assert!(split_command("echo 'open").is_err());
+        assert!(split_command("echo \"open").is_err());
+        assert!(split_command("echo \"open\\").is_err());
+        assert!(split_command("echo \\").is_err());
+    }
+
+    #[test]
+    fn split_command_rejects_empty_commands() {
+        assert!(split_command("").is_err());
+        assert!(split_command("   ").is_err());
+    }
+
+    #[test]
+    fn nano_cpus_zero_is_unlimited() {
+        assert_eq!(nano_cpus(0.0).unwrap(), None);
+    }
+
+    #[test]
+    fn nano_cpus_converts_percent_of_a_core() {
+        assert_eq!(nano_cpus(50.0).unwrap(), Some(500_000_000));
+        assert_eq!(nano_cpus(100.0).unwrap(), Some(1_000_000_000));
+        assert_eq!(nano_cpus(12.5).unwrap(), Some(125_000_000));
+    }
+
+    #[test]
+    fn nano_cpus_is_bounded_by_the_host_cores() {
+        if available_cores() >= 3 {
+            assert_eq!(nano_cpus(250.0).unwrap(), Some(2_500_000_000));
+        } else {
+            assert_eq!(nano_cpus(250.0).unwrap_err().code, "cpu_limit_out_of_range");
+        }
+        let over = (available_cores() * 100 + 1) as f64;
+        assert_eq!(nano_cpus(over).unwrap_err().code, "cpu_limit_out_of_range");
+        assert_eq!(nano_cpus(0.5).unwrap_err().code, "cpu_limit_out_of_range");
+    }
+
+    #[test]
+    fn nano_cpus_rejects_negative_and_non_finite_limits() {
+        for limit in [-1.0, -100.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                nano_cpus(limit).unwrap_err().code,
+                "invalid_request",
+                "{}",
+                limit
+            );
+        }
+    }
+
+    #[test]
+    fn container_user_blank_keeps_the_image_default() {
+        assert_eq!(container_user(None).unwrap(), None);
+        assert_eq!(container_user(Some("")).unwrap(), None);
+        assert_eq!(container_user(Some("   ")).unwrap(), None);
+    }
+
+    #[test]
+    fn container_user_accepts_names_uids_and_uid_gid() {
+        assert_eq!(
+            container_user(Some("container")).unwrap().as_deref(),
+            Some("container")
+        );
+        assert_eq!(
+            container_user(Some(" 1000 ")).unwrap().as_deref(),
+            Some("1000")
+        );
+        assert_eq!(
+            container_user(Some("1000:1000")).unwrap().as_deref(),
+            Some("1000:1000")
+        );
+        assert_eq!(
+            container_user(Some("mc-server_1.x")).unwrap().as_deref(),
+            Some("mc-server_1.x")
+        );
+    }
+
+    #[test]
+    fn container_user_rejects_malformed_users() {
+        let too_long = "a".repeat(65);
+        for user in [
+            "1000:1000:1000",
+            "1000:",
+            ":1000",
+            "root user",
+            "root;id",
+            "$(id)",
+            too_long.as_str(),
+        ] {
+            assert_eq!(
+                container_user(Some(user)).unwrap_err().code,
+                "invalid_run_as_user",
+                "{}",
+                user
+            );
+        }
+    }
+
+    #[test]
+    fn security_opts_only_sets_no_new_privileges_when_asked() {
+        assert_eq!(
+            security_opts(true),
+            Some(vec!["no-new-privileges:true".to_string()])
+        );
+        assert_eq!(security_opts(false), None);
+    }
+
+    fn pull_ref(from_image: &str, tag: &str) -> (String, String) {
+        (from_image.to_string(), tag.to_string())
+    }
+
+    #[test]
+    fn image_pull_ref_defaults_untagged_images_to_latest() {
+        assert_eq!(image_pull_ref("nginx"), pull_ref("nginx", "latest"));
+        assert_eq!(
+            image_pull_ref("ghcr.io/pterodactyl/yolks"),
+            pull_ref("ghcr.io/pterodactyl/yolks", "latest")
+        );
+    }
+
+    #[test]
+    fn image_pull_ref_splits_the_tag() {
+        assert_eq!(image_pull_ref("nginx:1.27"), pull_ref("nginx", "1.27"));
+        assert_eq!(
+            image_pull_ref("ghcr.io/pterodactyl/yolks:java_21"),
+            pull_ref("ghcr.io/pterodactyl/yolks", "java_21")
+        );
+    }
+
+    #[test]
+    fn image_pull_ref_ignores_a_registry_port() {
+        assert_eq!(
+            image_pull_ref("registry.local:5000/team/app"),
+            pull_ref("registry.local:5000/team/app", "latest")
+        );
+        assert_eq!(
+            image_pull_ref("registry.local:5000/team/app:v2"),
+            pull_ref("registry.local:5000/team/app", "v2")
+        );
+    }
+
+    #[test]
+    fn image_pull_ref_passes_digests_through() {
+        let image = "nginx@sha256:0d17b565c37bcbd895e9d92315a05c1c3c9a29f762b011a10c54a66cd53c9b31";
+        assert_eq!(image_pull_ref(image), pull_ref(image, ""));
+    }
+
+    #[test]
+    fn always_pulls_even_when_the_image_is_present() {
+        assert!(should_pull(PullPolicy::Always, None));
+        assert!(should_pull(PullPolicy::Always, Some(true)));
+    }
+
+    #[test]
+    fn never_does_not_pull_even_when_the_image_is_missing() {
+        assert!(!should_pull(PullPolicy::Never, None));
+        assert!(!should_pull(PullPolicy::Never, Some(false)));
+    }
+
+    #[test]
+    fn if_not_present_pulls_only_missing_images() {
+        assert!(should_pull(PullPolicy::IfNotPresent, Some(false)));
+        assert!(!should_pull(PullPolicy::IfNotPresent, Some(true)));
+    }
+
+    fn container(managed: bool, state: ContainerSummaryStateEnum) -> ContainerSummary {
+        let labels = managed.then(|| HashMap::from([("yunexal.managed".into(), "true".into())]));
+        ContainerSummary {
+            labels,
+            state: Some(state),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary_counts_containers_and_reclaimable_space() {
+        use bollard::models::{BuildCache, ImageSummary, Volume, VolumeUsageData};
+
+        let containers = [
+            container(true, ContainerSummaryStateEnum::RUNNING),
+            container(true, ContainerSummaryStateEnum::EXITED),
+            container(false, ContainerSummaryStateEnum::RUNNING),
+        ];
+        let image = |size, containers| ImageSummary {
+            size,
+            containers,
+            ..Default::default()
+        };
+        let volume = |size, ref_count| Volume {
+            usage_data: Some(VolumeUsageData { size, ref_count }),
+            ..Default::default()
+        };
+        let cache = |size, in_use| BuildCache {
+            size: Some(size),
+            in_use: Some(in_use),
+            ..Default::default()
+        };
+        let usage = SystemDataUsageResponse {
+            images: Some(vec![image(1000, 2), image(300, 0)]),
+            // -1: Docker couldn't size it
+            volumes: Some(vec![volume(50, 1), volume(20, 0), volume(-1, 0)]),
+            build_cache: Some(vec![cache(7, true), cache(5, false)]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            summarize(&containers, &usage),
+            DockerSummaryResponse {
+                containers_total: 3,
+                containers_running: 2,
+                containers_stopped: 1,
+                containers_managed: 2,
+                containers_unmanaged: 1,
+                images: 2,
+                images_size: 1300,
+                volumes: 3,
+                volumes_size: 70,
+                build_cache_size: 12,
+                reclaimable: 300 + 20 + 5,
+            }
+        );
+    }
+
+    #[test]
+    fn summary_of_an_empty_host_is_all_zero() {
+        assert_eq!(
+            summarize(&[], &SystemDataUsageResponse::default()),
+            DockerSummaryResponse::default()
+        );
+    }
+
+    #[test]
+    fn ports_in_use_and_the_agents_own_are_not_free() {
+        let taken = std::net::TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        let own = std::net::TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let own_port = own.local_addr().unwrap().port();
+        drop(own);
+
+        assert!(free_ports_in(taken_port, taken_port, 0).is_empty());
+        assert!(free_ports_in(own_port, own_port, own_port).is_empty());
+        assert_eq!(free_ports_in(own_port, own_port, 0), [own_port]);
+    }
+
+    async fn probe(start: u16, end: u16) -> Result<FreePortsResponse, ApiError> {
+        let state = NodeState::for_tests("token", "http://127.0.0.1:1");
+        free_ports(State(state), Query(FreePortsQuery { start, end }))
+            .await
+            .map(|Json(res)| res)
+    }
+
+    #[tokio::test]
+    async fn free_ports_checks_the_range() {
+        for (start, end) in [(0, 10), (2000, 1999)] {
+            let err = probe(start, end).await.unwrap_err();
+            assert_eq!(err.status, StatusCode::BAD_REQUEST);
+            assert_eq!(err.code, "invalid_request");
+        }
+        let err = probe(40000, 41000).await.unwrap_err();
+        assert_eq!(err.code, "range_too_large");
+
+        let res = probe(40000, 40999).await.unwrap();
+        assert_eq!((res.start, res.end), (40000, 40999));
+        assert!(res.free.windows(2).all(|w| w[0] < w[1]));
+        assert!(res.free.iter().all(|p| (40000..=40999).contains(p)));
+    }
+}
@@ -1,3 +1,13 @@
+use crate::models::VersionResponse;
+use axum::Json;
+
 pub async fn health_check() -> &'static str {
     "OK"
 }
+
+/// Agent version, public like `/health` so the panel can read it without a token.
+pub async fn version_handler() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
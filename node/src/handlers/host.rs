@@ -0,0 +1,58 @@
+use crate::{error::ApiError, operations::UpdateRefused, state::NodeState};
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+use std::time::Duration;
+
+/// `POST /host/reboot`: runs the configured `reboot_command` so the panel can reboot the host
+/// during a maintenance window. Disabled unless `reboot_command` is set. Takes the same lock
+/// as a self-update: refused while operations run, and new ones are turned away once started.
+pub async fn reboot_host(State(state): State<NodeState>) -> Result<impl IntoResponse, ApiError> {
+    let Some(command) = state.reboot_command.clone() else {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "reboot_disabled",
+            "Host reboot is not enabled; set reboot_command in config.yml",
+        ));
+    };
+    state
+        .operations
+        .begin_update(false)
+        .map_err(|refused| match refused {
+            UpdateRefused::Busy(active) => ApiError::operations_running(active),
+            UpdateRefused::InProgress => ApiError::new(
+                StatusCode::CONFLICT,
+                "update_in_progress",
+                "An update or reboot is already running on this node",
+            ),
+        })?;
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(1)).await; // Give time for response to flush
+        println!("Rebooting host: {}", command);
+        let result = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+            .await;
+        match result {
+            // The host takes us down with it; nothing left to do
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("Reboot command exited with {}", status);
+                state.operations.end_update();
+            }
+            Err(e) => {
+                eprintln!("Failed to run reboot command: {}", e);
+                state.operations.end_update();
+            }
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "status": "rebooting",
+            "message": "Reboot initiated. The host will go down shortly.",
+        })),
+    ))
+}
@@ -0,0 +1,333 @@
+use crate::{
+    error::ApiError,
+    models::{InstallTestEvent, InstallTestRequest},
+    operations::OperationGuard,
+    state::NodeState,
+};
+use axum::{
+    body::Body,
+    extract::{Json, Path, State, rejection::JsonRejection},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use bollard::container::{
+    Config as DockerConfig, CreateContainerOptions, KillContainerOptions, LogOutput, LogsOptions,
+    RemoveContainerOptions, StartContainerOptions, WaitContainerOptions,
+};
+use bollard::service::HostConfig;
+use bollard::volume::{CreateVolumeOptions, RemoveVolumeOptions};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, mpsc};
+
+/// Runs an install script in a disposable container against a temp volume and streams
+/// its output back as NDJSON (`InstallTestEvent` per line). The last line is always
+/// an `exit` or `error` event. Container and volume are removed afterwards.
+pub async fn run_install_test(
+    State(state): State<NodeState>,
+    payload: Result<Json<InstallTestRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    validate(&payload)?;
+
+    let operation = state.operations.begin()?;
+
+    // A runaway script must not be able to pile up containers on the host
+    let permit = state
+        .install_test_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| {
+            ApiError::busy(
+                format!(
+                    "Node is already running {} install tests",
+                    state.max_concurrent_install_tests
+                ),
+                10,
+            )
+        })?;
+
+    let name = format!("yunexal-install-test-{}", uuid::Uuid::new_v4());
+    let run = InstallRun {
+        container: name.clone(),
+        volume: name,
+        keep_volume: false,
+        server_id: None,
+        timeout: state.install_test_timeout,
+    };
+    Ok(stream(state, run, payload, permit, operation))
+}
+
+/// Runs a server's install script before its container is first created. Same script
+/// environment and event stream as an install test, but the volume is the server's own
+/// `yunexal-<uuid>` data volume, kept for the container to mount, and the run counts
+/// against `max_concurrent_creates` and `install_timeout`.
+pub async fn run_server_install(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+    payload: Result<Json<InstallTestRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(payload) = payload.map_err(|e| ApiError::bad_request(e.body_text()))?;
+    validate(&payload)?;
+
+    let operation = state.operations.begin()?;
+    let permit = state
+        .create_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| {
+            ApiError::busy(
+                format!(
+                    "Node is already running {} container operations",
+                    state.max_concurrent_creates
+                ),
+                5,
+            )
+        })?;
+
+    // A previous attempt that died halfway may have left its container behind
+    let container = format!("yunexal-install-{}", uuid);
+    let _ = state
+        .docker
+        .remove_container(
+            &container,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await;
+
+    let run = InstallRun {
+        container,
+        volume: data_volume(&uuid),
+        keep_volume: true,
+        server_id: Some(uuid),
+        timeout: state.install_timeout,
+    };
+    Ok(stream(state, run, payload, permit, operation))
+}
+
+/// Named volume holding a server's files: filled by its install script and mounted into
+/// its container at the image's working directory.
+pub fn data_volume(uuid: &str) -> String {
+    format!("yunexal-{}", uuid)
+}
+
+fn validate(payload: &InstallTestRequest) -> Result<(), ApiError> {
+    if payload.container.trim().is_empty() || payload.entrypoint.trim().is_empty() {
+        return Err(ApiError::bad_request(
+            "container and entrypoint are required",
+        ));
+    }
+    Ok(())
+}
+
+/// Where and for how long one script runs.
+struct InstallRun {
+    container: String,
+    volume: String,
+    /// Server installs keep their volume; tests throw theirs away
+    keep_volume: bool,
+    /// Labels the volume, so leftovers can be traced to their server
+    server_id: Option<String>,
+    timeout: u64,
+}
+
+fn stream(
+    state: NodeState,
+    run_config: InstallRun,
+    payload: InstallTestRequest,
+    permit: OwnedSemaphorePermit,
+    operation: OperationGuard,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<InstallTestEvent>(64);
+    tokio::spawn(run(state, run_config, payload, tx, permit, operation));
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Some((Ok::<_, std::convert::Infallible>(line), rx))
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+async fn run(
+    state: NodeState,
+    install: InstallRun,
+    payload: InstallTestRequest,
+    tx: mpsc::Sender<InstallTestEvent>,
+    _permit: OwnedSemaphorePermit,
+    _operation: OperationGuard,
+) {
+    let final_event = match execute(&state, &install, payload, &tx).await {
+        Ok(event) => event,
+        Err(message) => InstallTestEvent::Error { message },
+    };
+    let _ = tx.send(final_event).await;
+
+    // Always clean up, whatever happened above
+    let _ = state
+        .docker
+        .remove_container(
+            &install.container,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        )
+        .await;
+    if install.keep_volume {
+        return;
+    }
+    if let Err(e) = state
+        .docker
+        .remove_volume(&install.volume, Some(RemoveVolumeOptions { force: true }))
+        .await
+    {
+        eprintln!(
+            "Failed to remove install test volume {}: {}",
+            install.volume, e
+        );
+    }
+}
+
+async fn execute(
+    state: &NodeState,
+    install: &InstallRun,
+    payload: InstallTestRequest,
+    tx: &mpsc::Sender<InstallTestEvent>,
+) -> Result<InstallTestEvent, String> {
+    let name = install.container.as_str();
+    let mut labels = HashMap::new();
+    match &install.server_id {
+        Some(server_id) => labels.insert("yunexal.server_id".to_string(), server_id.clone()),
+        None => labels.insert("yunexal.install_test".to_string(), "true".to_string()),
+    };
+
+    // Creating a volume that already exists just returns it
+    state
+        .docker
+        .create_volume(CreateVolumeOptions {
+            name: install.volume.clone(),
+            labels: labels.clone(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| format!("Failed to create volume: {}", e))?;
+
+    let env: Vec<String> = payload
+        .environment
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+
+    let config = DockerConfig {
+        image: Some(payload.container.clone()),
+        labels: Some(labels),
+        env: Some(env),
+        entrypoint: Some(vec![payload.entrypoint, "-c".to_string(), payload.script]),
+        working_dir: Some("/mnt/server".to_string()),
+        host_config: Some(HostConfig {
+            binds: Some(vec![format!("{}:/mnt/server", install.volume)]),
+            memory: Some(payload.memory_limit * 1024 * 1024),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    state
+        .docker
+        .create_container(
+            Some(CreateContainerOptions {
+                name: name.to_string(),
+                platform: None,
+            }),
+            config,
+        )
+        .await
+        .map_err(|e| match e {
+            bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            } => {
+                format!(
+                    "Install image {} is not present on this node",
+                    payload.container
+                )
+            }
+            e => format!("Failed to create install container: {}", e),
+        })?;
+
+    state
+        .docker
+        .start_container(name, None::<StartContainerOptions<String>>)
+        .await
+        .map_err(|e| format!("Failed to start install container: {}", e))?;
+
+    let timeout = Duration::from_secs(install.timeout);
+    let finished = tokio::time::timeout(timeout, async {
+        let mut logs = state.docker.logs(
+            name,
+            Some(LogsOptions::<String> {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        );
+        while let Some(Ok(chunk)) = logs.next().await {
+            let data = match chunk {
+                LogOutput::StdOut { message }
+                | LogOutput::StdErr { message }
+                | LogOutput::Console { message } => String::from_utf8_lossy(&message).to_string(),
+                LogOutput::StdIn { .. } => continue,
+            };
+            // Caller went away; stop early rather than run the script for nobody
+            if tx.send(InstallTestEvent::Output { data }).await.is_err() {
+                return None;
+            }
+        }
+
+        let mut wait = state
+            .docker
+            .wait_container(name, None::<WaitContainerOptions<String>>);
+        match wait.next().await {
+            Some(Ok(res)) => Some(res.status_code),
+            Some(Err(bollard::errors::Error::DockerContainerWaitError { code, .. })) => Some(code),
+            _ => Some(-1),
+        }
+    })
+    .await;
+
+    match finished {
+        Ok(Some(code)) => Ok(InstallTestEvent::Exit {
+            code,
+            timed_out: false,
+        }),
+        Ok(None) => {
+            kill(state, name).await;
+            Err("Install aborted by the caller".to_string())
+        }
+        Err(_) => {
+            kill(state, name).await;
+            Ok(InstallTestEvent::Exit {
+                code: -1,
+                timed_out: true,
+            })
+        }
+    }
+}
+
+async fn kill(state: &NodeState, name: &str) {
+    let _ = state
+        .docker
+        .kill_container(name, Some(KillContainerOptions { signal: "SIGKILL" }))
+        .await;
+}
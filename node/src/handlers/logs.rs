@@ -0,0 +1,110 @@
+use crate::{
+    console,
+    error::ApiError,
+    log_store::valid_server_id,
+    models::{StoredLogsQuery, StoredLogsResponse},
+    state::NodeState,
+};
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+};
+
+const DEFAULT_STORED_LOG_LINES: usize = 200;
+const MAX_STORED_LOG_LINES: usize = 5000;
+
+/// `GET /servers/{uuid}/stored-logs?tail=`: the end of the output kept on disk for a server,
+/// which is there whether or not its container still exists.
+pub async fn stored_logs(
+    State(state): State<NodeState>,
+    Path(uuid): Path<String>,
+    Query(query): Query<StoredLogsQuery>,
+) -> Result<Json<StoredLogsResponse>, ApiError> {
+    if !valid_server_id(&uuid) {
+        return Err(ApiError::bad_request(format!("Invalid server id {}", uuid)));
+    }
+    let lines = query
+        .tail
+        .unwrap_or(DEFAULT_STORED_LOG_LINES)
+        .clamp(1, MAX_STORED_LOG_LINES);
+
+    match state.logs.tail(&uuid, lines).await {
+        Ok(Some((mut logs, size))) => {
+            let strip_ansi = match query.ansi.as_deref() {
+                Some("raw") => false,
+                Some("strip") => true,
+                _ => state.console_strip_ansi,
+            };
+            if strip_ansi {
+                logs = console::strip_ansi(&logs);
+            }
+            Ok(Json(StoredLogsResponse {
+                server_id: uuid,
+                logs,
+                size,
+            }))
+        }
+        Ok(None) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "no_stored_logs",
+            format!("No logs are stored for server {}", uuid),
+        )),
+        Err(e) => Err(ApiError::internal("stored_logs_unreadable", e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::LogStore;
+    use std::sync::Arc;
+
+    const SERVER: &str = "3f1c2a9e-5b7d-4e21-8c3a-1d2e3f4a5b6c";
+
+    async fn read(
+        state: &NodeState,
+        uuid: &str,
+        tail: Option<usize>,
+        ansi: Option<&str>,
+    ) -> Result<StoredLogsResponse, ApiError> {
+        let query = StoredLogsQuery {
+            tail,
+            ansi: ansi.map(str::to_string),
+        };
+        stored_logs(State(state.clone()), Path(uuid.to_string()), Query(query))
+            .await
+            .map(|Json(res)| res)
+    }
+
+    #[tokio::test]
+    async fn stored_logs_outlive_the_container() {
+        // The test state's Docker is unreachable, so there is no container to ask
+        let mut state = NodeState::for_tests("token", "http://127.0.0.1:1");
+        let dir = std::env::temp_dir().join(format!("yunexal-logs-{}", uuid::Uuid::new_v4()));
+        state.logs = Arc::new(LogStore::in_dir(dir.clone(), 1024));
+
+        let err = read(&state, SERVER, None, None).await.unwrap_err();
+        assert_eq!(err.code, "no_stored_logs");
+        let err = read(&state, "../etc", None, None).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+
+        let logs_dir = dir.join(SERVER).join("logs");
+        std::fs::create_dir_all(&logs_dir).unwrap();
+        std::fs::write(
+            logs_dir.join("latest.log"),
+            "--- container started ---\nloading\n\u{1b}[32mDone\u{1b}[0m\n",
+        )
+        .unwrap();
+
+        let res = read(&state, SERVER, Some(2), None).await.unwrap();
+        assert_eq!(res.logs, "loading\n\u{1b}[32mDone\u{1b}[0m");
+        assert_eq!(res.size, 48);
+        let res = read(&state, SERVER, Some(1), Some("strip")).await.unwrap();
+        assert_eq!(res.logs, "Done");
+        // A tail of 0 still shows the last line
+        let res = read(&state, SERVER, Some(0), Some("strip")).await.unwrap();
+        assert_eq!(res.logs, "Done");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
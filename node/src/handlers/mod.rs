@@ -1,4 +1,8 @@
 pub mod auth;
+pub mod config;
 pub mod docker;
 pub mod health;
+pub mod host;
+pub mod install_test;
+pub mod logs;
 pub mod update;
@@ -1,57 +1,129 @@
+use crate::{error::ApiError, models::UpdateReport, operations::UpdateRefused, state::NodeState};
 use axum::{
-    extract::State,
     Json,
+    extract::{Query, State},
+    http::StatusCode,
     response::IntoResponse,
 };
-use crate::state::NodeState;
+use serde::Deserialize;
 use serde_json::json;
 use std::env;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::time::Duration;
 
+/// Written next to config.yml before restarting into a new binary; holds the old version
+const UPDATE_MARKER: &str = "update-pending";
+
+#[derive(Deserialize)]
+pub struct SelfUpdateQuery {
+    /// Update even while operations are running, interrupting them
+    #[serde(default)]
+    pub force: bool,
+}
+
 pub async fn self_update_handler(
     State(state): State<NodeState>,
-) -> impl IntoResponse {
+    Query(query): Query<SelfUpdateQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let interrupted =
+        state
+            .operations
+            .begin_update(query.force)
+            .map_err(|refused| match refused {
+                UpdateRefused::Busy(active) => ApiError::operations_running(active),
+                UpdateRefused::InProgress => ApiError::new(
+                    StatusCode::CONFLICT,
+                    "update_in_progress",
+                    "An update is already running on this node",
+                ),
+            })?;
+    if interrupted > 0 {
+        println!(
+            "Forced update: {} running operations will be interrupted",
+            interrupted
+        );
+    }
+
     let panel_url = state.panel_url.clone();
-    
+    let token = state.token.read().await.clone();
+
     // Spawn the update process in the background so we can return a response immediately
     tokio::spawn(async move {
         println!("Starting background update process...");
         tokio::time::sleep(Duration::from_secs(1)).await; // Give time for response to flush
 
-        if let Err(e) = perform_update(&panel_url).await {
-            eprintln!("Update failed: {}", e);
-        } else {
-            println!("Update successful. Restarting...");
-            std::process::exit(0);
+        let version = env!("CARGO_PKG_VERSION").to_string();
+        match perform_update(&panel_url, &token).await {
+            Ok(()) => {
+                if let Err(e) = fs::write(UPDATE_MARKER, &version) {
+                    eprintln!("Failed to write {}: {}", UPDATE_MARKER, e);
+                }
+                println!("Update successful. Restarting...");
+                // The final heartbeat tells the panel we're updating rather than just gone
+                *state.update_report.lock().unwrap() = Some(UpdateReport {
+                    state: "updating",
+                    version,
+                    message: Some("Restarting into the new version".to_string()),
+                });
+                state.shutdown.cancel();
+            }
+            Err(e) => {
+                eprintln!("Update failed: {}", e);
+                state.operations.end_update();
+                *state.update_report.lock().unwrap() = Some(UpdateReport {
+                    state: "failed",
+                    version,
+                    message: Some(e.to_string()),
+                });
+            }
         }
     });
 
-    Json(json!({
-        "status": "success",
-        "message": "Update initiated. Node will restart shortly."
-    }))
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "status": "updating",
+            "message": "Update initiated. Node will restart shortly.",
+            "interrupted_operations": interrupted,
+        })),
+    ))
 }
 
-async fn perform_update(panel_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // Construct download URL. 
-    // Ensure panel_url does not have trailing slash to avoid double slash, 
+/// The "completed" report for the panel if the previous run restarted into an update.
+pub fn take_completed_update() -> Option<UpdateReport> {
+    let previous = fs::read_to_string(UPDATE_MARKER).ok()?;
+    if let Err(e) = fs::remove_file(UPDATE_MARKER) {
+        eprintln!("Failed to remove {}: {}", UPDATE_MARKER, e);
+    }
+    let version = env!("CARGO_PKG_VERSION").to_string();
+    println!("Update completed: {} -> {}", previous.trim(), version);
+    Some(UpdateReport {
+        state: "completed",
+        message: Some(format!("Updated from {}", previous.trim())),
+        version,
+    })
+}
+
+async fn perform_update(panel_url: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Construct download URL.
+    // Ensure panel_url does not have trailing slash to avoid double slash,
     // though most browsers/libs handle it.
     let base_url = panel_url.trim_end_matches('/');
     let url = format!("{}/downloads/yunexal-node", base_url);
-    
+
     println!("Downloading update from: {}", url);
 
     let client = reqwest::Client::new();
-    let response = client.get(&url).send().await?;
+    // The panel only hands the binary to callers holding a node token
+    let response = client.get(&url).bearer_auth(token).send().await?;
 
     if !response.status().is_success() {
         return Err(format!("Failed to download update: Status {}", response.status()).into());
     }
 
     let bytes = response.bytes().await?;
-    
+
     // Determine current executable path
     let current_exe = env::current_exe()?;
     let tmp_exe = current_exe.with_extension("tmp");
@@ -77,13 +149,13 @@ async fn perform_update(panel_url: &str) -> Result<(), Box<dyn std::error::Error
         // Rollback
         eprintln!("Installation failed, rolling back: {}", e);
         if backup_exe.exists() {
-             fs::rename(&backup_exe, &current_exe)?;
+            fs::rename(&backup_exe, &current_exe)?;
         }
         return Err(e.into());
     }
-    
+
     // Cleanup backup? Maybe keep one backup.
-    
+
     println!("Update installed successfully.");
     Ok(())
 }
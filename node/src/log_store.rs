@@ -0,0 +1,409 @@
+//! Server output kept on disk, so the panel can show what a server printed after its
+//! container stopped or was recreated. While a managed container runs, a follower appends
+//! its log to `SERVERS_DIR/<uuid>/logs/latest.log`; at its share of `stored_logs_limit` the
+//! file moves to `latest.log.1` (and that to `.2`, ...), and the oldest is dropped, so a
+//! server never holds more than the limit. Followers are started from the heartbeat, like
+//! the startup watchers, and end with the container's output.
+
+use bollard::Docker;
+use bollard::container::{LogOutput, LogsOptions};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::StreamExt;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+pub const SERVERS_DIR: &str = "/opt/yunexal-node/servers";
+
+const LATEST: &str = "latest.log";
+
+/// Files kept besides `latest.log`
+const ROTATED_FILES: u64 = 4;
+
+/// Which run a follower got to, and the Docker timestamp of the last chunk written, so a
+/// follower started again (after an agent restart) doesn't write the same output twice
+const CURSOR: &str = ".cursor";
+
+/// How often a follower records its cursor
+const CURSOR_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct LogStore {
+    dir: PathBuf,
+    /// Bytes kept per server; 0 stores nothing
+    limit: u64,
+    /// Runs (server UUID, start time) with a follower
+    following: Mutex<HashSet<(String, i64)>>,
+}
+
+/// Server UUIDs end up in paths, so only hex digits and dashes get through.
+pub fn valid_server_id(server_id: &str) -> bool {
+    !server_id.is_empty() && server_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+impl LogStore {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            dir: PathBuf::from(SERVERS_DIR),
+            limit,
+            following: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// A store under `dir` instead of `SERVERS_DIR`, for tests.
+    #[cfg(test)]
+    pub fn in_dir(dir: PathBuf, limit: u64) -> Self {
+        Self {
+            dir,
+            limit,
+            following: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    fn logs_dir(&self, server_id: &str) -> PathBuf {
+        self.dir.join(server_id).join("logs")
+    }
+
+    /// Starts a follower for a running container's run unless it already has one.
+    pub fn follow(
+        self: &Arc<Self>,
+        docker: &Docker,
+        container_id: &str,
+        server_id: &str,
+        started_at: i64,
+    ) {
+        if self.limit == 0 || started_at == 0 || !valid_server_id(server_id) {
+            return;
+        }
+        let run = (server_id.to_string(), started_at);
+        if !self.following.lock().unwrap().insert(run.clone()) {
+            return;
+        }
+
+        let store = Arc::clone(self);
+        let docker = docker.clone();
+        let container_id = container_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = store
+                .follow_run(&docker, &container_id, &run.0, started_at)
+                .await
+            {
+                eprintln!("Failed to store logs of server {}: {}", run.0, e);
+            }
+            store.following.lock().unwrap().remove(&run);
+        });
+    }
+
+    async fn follow_run(
+        &self,
+        docker: &Docker,
+        container_id: &str,
+        server_id: &str,
+        started_at: i64,
+    ) -> std::io::Result<()> {
+        let dir = self.logs_dir(server_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut file = LogFile::open(&dir, (self.limit / (ROTATED_FILES + 1)).max(1)).await?;
+
+        // Carry on where a follower of the same run stopped, or mark where a new run starts
+        let mut last = match read_cursor(&dir).await {
+            Some((run, last)) if run == started_at => Some(last),
+            _ => {
+                let started = Utc
+                    .timestamp_opt(started_at, 0)
+                    .single()
+                    .unwrap_or_else(Utc::now);
+                file.write(
+                    format!("--- container started {} ---\n", started.to_rfc3339()).as_bytes(),
+                )
+                .await?;
+                None
+            }
+        };
+
+        let mut logs = docker.logs(
+            container_id,
+            Some(LogsOptions::<String> {
+                follow: true,
+                stdout: true,
+                stderr: true,
+                timestamps: true,
+                since: last.map_or(started_at, |t| t.timestamp()),
+                ..Default::default()
+            }),
+        );
+
+        let mut saved = Instant::now();
+        while let Some(Ok(chunk)) = logs.next().await {
+            let message = match chunk {
+                LogOutput::StdOut { message }
+                | LogOutput::StdErr { message }
+                | LogOutput::Console { message } => message,
+                LogOutput::StdIn { .. } => continue,
+            };
+            // Each chunk comes as "<RFC 3339 timestamp> <output>"
+            let (stamp, output) = match message.iter().position(|b| *b == b' ') {
+                Some(space) => (&message[..space], &message[space + 1..]),
+                None => (&message[..], &[][..]),
+            };
+            let stamp = std::str::from_utf8(stamp)
+                .ok()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|t| t.with_timezone(&Utc));
+            if let (Some(stamp), Some(last)) = (stamp, last)
+                && stamp <= last
+            {
+                continue;
+            }
+
+            file.write(output).await?;
+            last = stamp.or(last);
+            if let Some(last) = last
+                && saved.elapsed() >= CURSOR_INTERVAL
+            {
+                write_cursor(&dir, started_at, last).await;
+                saved = Instant::now();
+            }
+        }
+
+        if let Some(last) = last {
+            write_cursor(&dir, started_at, last).await;
+        }
+        Ok(())
+    }
+
+    /// The last `lines` lines stored for a server and the bytes stored in total; `None`
+    /// when nothing is stored for it.
+    pub async fn tail(
+        &self,
+        server_id: &str,
+        lines: usize,
+    ) -> std::io::Result<Option<(String, u64)>> {
+        let dir = self.logs_dir(server_id);
+        let mut stored = Vec::new();
+        let mut found = false;
+        // Oldest first
+        for path in (1..=ROTATED_FILES)
+            .rev()
+            .map(|n| rotated(&dir, n))
+            .chain([dir.join(LATEST)])
+        {
+            match tokio::fs::read(&path).await {
+                Ok(bytes) => {
+                    found = true;
+                    stored.extend(bytes);
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if !found {
+            return Ok(None);
+        }
+
+        let size = stored.len() as u64;
+        let text = String::from_utf8_lossy(&stored);
+        let text = text.trim_end_matches('\n');
+        let start = text
+            .rmatch_indices('\n')
+            .nth(lines.saturating_sub(1))
+            .map_or(0, |(i, _)| i + 1);
+        Ok(Some((text[start..].to_string(), size)))
+    }
+
+    /// Deletes everything stored for a server, e.g. once it is purged.
+    pub async fn remove(&self, server_id: &str) {
+        if !valid_server_id(server_id) {
+            return;
+        }
+        match tokio::fs::remove_dir_all(self.dir.join(server_id)).await {
+            Ok(()) => println!("Removed stored logs of server {}", server_id),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => eprintln!(
+                "Failed to remove stored logs of server {}: {}",
+                server_id, e
+            ),
+        }
+    }
+}
+
+fn rotated(dir: &Path, n: u64) -> PathBuf {
+    dir.join(format!("{}.{}", LATEST, n))
+}
+
+/// `latest.log`, rotated once it would grow past `max_size`.
+struct LogFile {
+    dir: PathBuf,
+    file: tokio::fs::File,
+    size: u64,
+    max_size: u64,
+}
+
+impl LogFile {
+    async fn open(dir: &Path, max_size: u64) -> std::io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LATEST))
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        // A single chunk larger than a whole file keeps only its end
+        let bytes = &bytes[bytes.len().saturating_sub(self.max_size as usize)..];
+        if self.size > 0 && self.size + bytes.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+        self.file.write_all(bytes).await?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        for n in (1..ROTATED_FILES).rev() {
+            match tokio::fs::rename(rotated(&self.dir, n), rotated(&self.dir, n + 1)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(self.dir.join(LATEST), rotated(&self.dir, 1)).await?;
+        *self = Self::open(&self.dir, self.max_size).await?;
+        Ok(())
+    }
+}
+
+async fn read_cursor(dir: &Path) -> Option<(i64, DateTime<Utc>)> {
+    let content = tokio::fs::read_to_string(dir.join(CURSOR)).await.ok()?;
+    let (run, last) = content.trim().split_once(' ')?;
+    let last = DateTime::parse_from_rfc3339(last).ok()?.with_timezone(&Utc);
+    Some((run.parse().ok()?, last))
+}
+
+async fn write_cursor(dir: &Path, started_at: i64, last: DateTime<Utc>) {
+    let content = format!(
+        "{} {}\n",
+        started_at,
+        last.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+    );
+    if let Err(e) = tokio::fs::write(dir.join(CURSOR), content).await {
+        eprintln!(
+            "Failed to record stored log position in {}: {}",
+            dir.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &str = "3f1c2a9e-5b7d-4e21-8c3a-1d2e3f4a5b6c";
+
+    /// A store in a fresh directory under the system temp dir; removed by `cleanup`.
+    fn store(limit: u64) -> LogStore {
+        let dir = std::env::temp_dir().join(format!("yunexal-logs-{}", uuid::Uuid::new_v4()));
+        LogStore::in_dir(dir, limit)
+    }
+
+    fn cleanup(store: &LogStore) {
+        let _ = std::fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn server_ids_cant_leave_the_servers_dir() {
+        assert!(valid_server_id(SERVER));
+        for id in ["", "..", "../etc", "abc/def", "abc def", "zz"] {
+            assert!(!valid_server_id(id), "{:?}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn tail_reads_across_rotated_files_oldest_first() {
+        let store = store(50);
+        let dir = store.logs_dir(SERVER);
+        assert!(store.tail(SERVER, 10).await.unwrap().is_none());
+
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        // Room for one 8-byte line per file, five files
+        let mut file = LogFile::open(&dir, 10).await.unwrap();
+        for n in 0..12 {
+            file.write(format!("line {:02}\n", n).as_bytes())
+                .await
+                .unwrap();
+        }
+        assert!(rotated(&dir, ROTATED_FILES).exists());
+        assert!(!rotated(&dir, ROTATED_FILES + 1).exists());
+
+        // The oldest lines were dropped with the oldest file
+        let (logs, size) = store.tail(SERVER, 100).await.unwrap().unwrap();
+        assert_eq!(size, 40);
+        assert!(logs.starts_with("line 07\n"));
+        assert!(logs.ends_with("line 11"));
+        let (logs, _) = store.tail(SERVER, 2).await.unwrap().unwrap();
+        assert_eq!(logs, "line 10\nline 11");
+
+        cleanup(&store);
+    }
+
+    #[tokio::test]
+    async fn a_chunk_larger_than_a_file_keeps_its_end() {
+        let store = store(50);
+        let dir = store.logs_dir(SERVER);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut file = LogFile::open(&dir, 10).await.unwrap();
+        file.write(b"0123456789abcdef\n").await.unwrap();
+        let (logs, size) = store.tail(SERVER, 5).await.unwrap().unwrap();
+        assert_eq!((logs.as_str(), size), ("789abcdef", 10));
+
+        cleanup(&store);
+    }
+
+    #[tokio::test]
+    async fn cursor_remembers_the_run_and_last_timestamp() {
+        let store = store(50);
+        let dir = store.logs_dir(SERVER);
+        assert!(read_cursor(&dir).await.is_none());
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let last = Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap();
+        write_cursor(&dir, 1_699_999_000, last).await;
+        assert_eq!(read_cursor(&dir).await, Some((1_699_999_000, last)));
+
+        tokio::fs::write(dir.join(CURSOR), "garbage").await.unwrap();
+        assert!(read_cursor(&dir).await.is_none());
+
+        cleanup(&store);
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_what_is_stored() {
+        let store = store(50);
+        let dir = store.logs_dir(SERVER);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join(LATEST), "bye\n").await.unwrap();
+
+        store.remove("..").await;
+        assert!(store.tail(SERVER, 1).await.unwrap().is_some());
+        store.remove(SERVER).await;
+        assert!(store.tail(SERVER, 1).await.unwrap().is_none());
+        // Nothing left to remove is fine
+        store.remove(SERVER).await;
+
+        cleanup(&store);
+    }
+}
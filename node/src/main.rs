@@ -1,32 +1,52 @@
 #![allow(deprecated)]
-use bollard::Docker;
 use axum::{
+    Router, middleware,
     routing::{delete, get, post},
-    Router,
-    middleware,
 };
-use std::net::SocketAddr;
+use bollard::Docker;
 use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
+mod config_files;
+mod console;
+mod consoles;
+mod error;
+mod handlers;
+mod log_store;
 mod models;
+mod operations;
+mod startup;
 mod state;
-mod handlers;
 mod tasks;
 
-use models::NodeConfig;
-use state::NodeState;
 use handlers::{
     auth::{auth_middleware, update_token_handler},
-    docker::{console_handler, create_container, delete_container, list_containers},
-    health::health_check,
-    update::self_update_handler,
+    config::get_config,
+    docker::{
+        console_handler, container_state, create_container, delete_container, docker_summary,
+        free_ports, inspect_container, list_containers, port_bindings, power_container,
+        send_command, update_container_limits,
+    },
+    health::{health_check, version_handler},
+    host::reboot_host,
+    install_test::{run_install_test, run_server_install},
+    logs::stored_logs,
+    update::{self_update_handler, take_completed_update},
 };
+use models::NodeConfig;
+use state::NodeState;
 use tasks::start_heartbeat_task;
 
+/// How long shutdown waits for open requests, and then for the stopping event
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     // Load env if .env file exists (optional fallback)
-    dotenv::dotenv().ok(); 
+    dotenv::dotenv().ok();
+    tracing_subscriber::fmt().init();
 
     println!("Starting Yunexal Node Agent...");
 
@@ -34,17 +54,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     let config_content = fs::read_to_string("config.yml").unwrap_or_default();
     let config: Option<NodeConfig> = serde_yaml::from_str(&config_content).ok();
 
-    let (token, node_id, panel_url, port, ram_limit, disk_limit) = if let Some(mut cfg) = config {
+    let (
+        token,
+        node_id,
+        panel_url,
+        port,
+        ram_limit,
+        disk_limit,
+        legacy_auth_error,
+        max_concurrent_creates,
+        heartbeat_interval,
+        install_test_timeout,
+        max_concurrent_install_tests,
+        install_timeout,
+        reboot_command,
+        console_output_limit,
+        console_strip_ansi,
+        stored_logs_limit,
+    ) = if let Some(mut cfg) = config {
         println!("Loaded configuration from config.yml");
-        
+
         let mut sys = sysinfo::System::new_all();
         sys.refresh_all();
-        
+
         // Auto-configure RAM Limit (95%)
         if cfg.ram_limit == 0 {
-             let total_ram_mb = sys.total_memory() / 1024 / 1024;
-             cfg.ram_limit = (total_ram_mb as f64 * 0.95) as u64;
-             println!("Auto-configured RAM limit to {:.2} GB (95% of {:.2} GB)", cfg.ram_limit as f64 / 1024.0, total_ram_mb as f64 / 1024.0);
+            let total_ram_mb = sys.total_memory() / 1024 / 1024;
+            cfg.ram_limit = (total_ram_mb as f64 * 0.95) as u64;
+            println!(
+                "Auto-configured RAM limit to {:.2} GB (95% of {:.2} GB)",
+                cfg.ram_limit as f64 / 1024.0,
+                total_ram_mb as f64 / 1024.0
+            );
         }
 
         // Auto-configure Disk Limit (95%)
@@ -54,42 +95,131 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
             // Simple heuristic: Find largest available space or root
             let mut total_space_mb = 0;
             for disk in &disks {
-                 if disk.mount_point() == std::path::Path::new("/") {
-                     total_space_mb = disk.total_space() / 1024 / 1024;
-                     break;
-                 }
+                if disk.mount_point() == std::path::Path::new("/") {
+                    total_space_mb = disk.total_space() / 1024 / 1024;
+                    break;
+                }
             }
-             // Fallback if / not found
+            // Fallback if / not found
             if total_space_mb == 0 && !disks.is_empty() {
                 total_space_mb = disks[0].total_space() / 1024 / 1024;
             }
 
             cfg.disk_limit = (total_space_mb as f64 * 0.95) as u64;
-            println!("Auto-configured Disk limit to {:.2} GB (95% of {:.2} GB)", cfg.disk_limit as f64 / 1024.0, total_space_mb as f64 / 1024.0);
+            println!(
+                "Auto-configured Disk limit to {:.2} GB (95% of {:.2} GB)",
+                cfg.disk_limit as f64 / 1024.0,
+                total_space_mb as f64 / 1024.0
+            );
         }
 
-        (cfg.token, cfg.node_id, cfg.panel_url, cfg.port, cfg.ram_limit, cfg.disk_limit)
+        (
+            cfg.token,
+            cfg.node_id,
+            cfg.panel_url,
+            cfg.port,
+            cfg.ram_limit,
+            cfg.disk_limit,
+            cfg.legacy_auth_error,
+            cfg.max_concurrent_creates,
+            cfg.heartbeat_interval,
+            cfg.install_test_timeout,
+            cfg.max_concurrent_install_tests,
+            cfg.install_timeout,
+            cfg.reboot_command,
+            cfg.console_output_limit,
+            cfg.console_strip_ansi,
+            cfg.stored_logs_limit,
+        )
     } else {
         println!("config.yml not found or invalid, falling back to environment variables");
         let token = std::env::var("APP_KEY").expect("APP_KEY environment variable must be set");
         let node_id = std::env::var("NODE_ID").unwrap_or_else(|_| "unknown".to_string());
-        let panel_url = std::env::var("PANEL_URL").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string());
-        let port = std::env::var("PORT").unwrap_or("3001".to_string()).parse().unwrap_or(3001);
-        (token, node_id, panel_url, port, 0, 0)
+        let panel_url =
+            std::env::var("PANEL_URL").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string());
+        let port = std::env::var("PORT")
+            .unwrap_or("3001".to_string())
+            .parse()
+            .unwrap_or(3001);
+        let legacy_auth_error = std::env::var("LEGACY_AUTH_ERROR")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let max_concurrent_creates = std::env::var("MAX_CONCURRENT_CREATES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_max_concurrent_creates);
+        let heartbeat_interval = std::env::var("HEARTBEAT_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_heartbeat_interval);
+        let install_test_timeout = std::env::var("INSTALL_TEST_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_install_test_timeout);
+        let max_concurrent_install_tests = std::env::var("MAX_CONCURRENT_INSTALL_TESTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_max_concurrent_install_tests);
+        let install_timeout = std::env::var("INSTALL_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_install_timeout);
+        let reboot_command = std::env::var("REBOOT_COMMAND").unwrap_or_default();
+        let console_output_limit = std::env::var("CONSOLE_OUTPUT_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_console_output_limit);
+        let console_strip_ansi = std::env::var("CONSOLE_STRIP_ANSI")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        let stored_logs_limit = std::env::var("STORED_LOGS_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(models::default_stored_logs_limit);
+        (
+            token,
+            node_id,
+            panel_url,
+            port,
+            0,
+            0,
+            legacy_auth_error,
+            max_concurrent_creates,
+            heartbeat_interval,
+            install_test_timeout,
+            max_concurrent_install_tests,
+            install_timeout,
+            reboot_command,
+            console_output_limit,
+            console_strip_ansi,
+            stored_logs_limit,
+        )
     };
 
     println!("Node ID: {}", node_id);
     println!("Panel URL: {}", panel_url);
     println!("Port: {}", port);
+    let heartbeat_interval = heartbeat_interval.max(1);
+    println!("Heartbeat interval: {}s", heartbeat_interval);
+    let reboot_command = Some(reboot_command.trim().to_string()).filter(|c| !c.is_empty());
+    if let Some(command) = &reboot_command {
+        println!("Host reboot enabled: {}", command);
+    }
 
     // Connect to Docker
     let docker = Docker::connect_with_local_defaults()?;
-    
+
     // Verify connection
     let version = docker.version().await?;
-    println!("Connected to Docker daemon version: {:?}", version.version.unwrap_or_default());
+    println!(
+        "Connected to Docker daemon version: {:?}",
+        version.version.unwrap_or_default()
+    );
 
-    let state = NodeState { 
+    // SIGINT/SIGTERM stop the heartbeat loop and let in-flight requests finish
+    let shutdown = CancellationToken::new();
+
+    let state = NodeState {
         docker,
         token: std::sync::Arc::new(tokio::sync::RwLock::new(token)),
         node_id,
@@ -97,18 +227,59 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
         port,
         ram_limit,
         disk_limit,
+        legacy_auth_error,
+        max_concurrent_creates,
+        create_permits: std::sync::Arc::new(tokio::sync::Semaphore::new(
+            max_concurrent_creates.max(1),
+        )),
+        heartbeat_interval,
+        install_test_timeout: install_test_timeout.max(1),
+        max_concurrent_install_tests,
+        install_test_permits: std::sync::Arc::new(tokio::sync::Semaphore::new(
+            max_concurrent_install_tests.max(1),
+        )),
+        install_timeout: install_timeout.max(1),
+        reboot_command,
+        console_output_limit,
+        console_strip_ansi,
+        started_at: chrono::Utc::now().timestamp(),
+        net_counters: Default::default(),
+        startup: Default::default(),
+        consoles: Default::default(),
+        logs: std::sync::Arc::new(log_store::LogStore::new(stored_logs_limit)),
+        operations: Default::default(),
+        // Reported with the first heartbeat when we just restarted into an update
+        update_report: std::sync::Arc::new(std::sync::Mutex::new(take_completed_update())),
+        shutdown: shutdown.clone(),
     };
 
     // Build our application with routes
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/version", get(version_handler))
+        .route("/config", get(get_config))
+        .route("/docker-summary", get(docker_summary))
         .route("/containers", get(list_containers))
+        .route("/port-bindings", get(port_bindings))
+        .route("/free-ports", get(free_ports))
         .route("/containers", post(create_container))
-        .route("/containers/:uuid", delete(delete_container))
-        .route("/containers/:uuid/console", get(console_handler))
+        .route("/containers/{uuid}", delete(delete_container))
+        .route("/containers/{uuid}/limits", post(update_container_limits))
+        .route("/containers/{uuid}/state", get(container_state))
+        .route("/containers/{uuid}/inspect", get(inspect_container))
+        .route("/containers/{uuid}/power", post(power_container))
+        .route("/containers/{uuid}/console", get(console_handler))
+        .route("/containers/{uuid}/command", post(send_command))
+        .route("/containers/{uuid}/install", post(run_server_install))
+        .route("/servers/{uuid}/stored-logs", get(stored_logs))
+        .route("/install-test", post(run_install_test))
         .route("/update-token", post(update_token_handler))
         .route("/self-update", post(self_update_handler))
-        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
+        .route("/host/reboot", post(reboot_host))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
         .with_state(state.clone());
 
     // Run it on configured port
@@ -116,11 +287,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     println!("Node Agent listening on http://{}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
 
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
     // Start heartbeat task
-    tokio::spawn(start_heartbeat_task(state));
+    let heartbeat = tokio::spawn(start_heartbeat_task(state, shutdown.clone()));
+
+    let server =
+        axum::serve(listener, app).with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    // Console websockets never end on their own, so graceful shutdown gets a deadline
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(SHUTDOWN_GRACE).await;
+    };
+    tokio::select! {
+        res = server => res?,
+        _ = deadline => eprintln!("Connections still open after {}s, shutting down anyway", SHUTDOWN_GRACE.as_secs()),
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    if tokio::time::timeout(SHUTDOWN_GRACE, heartbeat)
+        .await
+        .is_err()
+    {
+        eprintln!("Heartbeat task did not stop in time");
+    }
+    println!("Node Agent stopped");
 
     Ok(())
 }
 
+/// Cancels `shutdown` on Ctrl+C or SIGTERM (what systemd and `docker stop` send).
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    println!("Shutting down...");
+    shutdown.cancel();
+}
@@ -12,6 +12,87 @@ pub struct NodeConfig {
     pub ram_limit: u64, // In MB
     #[serde(default)]
     pub disk_limit: u64, // In MB
+    #[serde(default)]
+    pub legacy_auth_error: bool,
+    #[serde(default = "default_max_concurrent_creates")]
+    pub max_concurrent_creates: usize,
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64, // In seconds
+    #[serde(default = "default_install_test_timeout")]
+    pub install_test_timeout: u64, // In seconds
+    #[serde(default = "default_max_concurrent_install_tests")]
+    pub max_concurrent_install_tests: usize,
+    #[serde(default = "default_install_timeout")]
+    pub install_timeout: u64, // In seconds
+    /// Shell command `POST /host/reboot` runs, e.g. `systemctl reboot`; empty disables it
+    #[serde(default)]
+    pub reboot_command: String,
+    /// Console output bytes per second sent to one connection; the rest is dropped. 0 = no cap
+    #[serde(default = "default_console_output_limit")]
+    pub console_output_limit: u64,
+    /// Remove ANSI escape sequences from console output unless the client asks for `ansi=raw`
+    #[serde(default)]
+    pub console_strip_ansi: bool,
+    /// Bytes of output kept on disk per server, across `latest.log` and its rotations. 0 = none
+    #[serde(default = "default_stored_logs_limit")]
+    pub stored_logs_limit: u64,
+}
+
+pub fn default_max_concurrent_creates() -> usize {
+    3
+}
+
+pub fn default_console_output_limit() -> u64 {
+    64 * 1024
+}
+
+pub fn default_stored_logs_limit() -> u64 {
+    10 * 1024 * 1024
+}
+
+pub fn default_heartbeat_interval() -> u64 {
+    5
+}
+
+pub fn default_install_test_timeout() -> u64 {
+    300
+}
+
+pub fn default_install_timeout() -> u64 {
+    1800
+}
+
+pub fn default_max_concurrent_install_tests() -> usize {
+    1
+}
+
+/// Body of `GET /version`.
+#[derive(Debug, Serialize)]
+pub struct VersionResponse {
+    pub version: String,
+}
+
+/// Non-secret view of the running config, served by `GET /config`.
+#[derive(Debug, Serialize)]
+pub struct AgentConfigResponse {
+    pub node_id: String,
+    pub panel_url: String,
+    pub port: u16,
+    pub ram_limit: u64,  // In MB
+    pub disk_limit: u64, // In MB
+    pub legacy_auth_error: bool,
+    pub max_concurrent_creates: usize,
+    pub heartbeat_interval: u64, // In seconds
+    /// Whether `reboot_command` is set, i.e. `POST /host/reboot` is allowed
+    pub reboot_enabled: bool,
+    pub console_output_limit: u64, // Bytes per second, 0 = no cap
+    pub console_strip_ansi: bool,
+    pub stored_logs_limit: u64, // Bytes per server, 0 = not stored
+    pub version: String,
+    /// "config.yml" or "environment"
+    pub source: String,
+    /// Always "[redacted]"
+    pub token: String,
 }
 
 use std::collections::HashMap;
@@ -24,19 +105,176 @@ pub struct CreateContainerRequest {
     pub environment: HashMap<String, String>,
     pub memory_limit: i64,
     pub swap_limit: i64,
-    pub cpu_limit: i64,
+    /// Percent of one core (100 = one core, 250 = two and a half), 0 = unlimited
+    pub cpu_limit: f64,
     pub io_weight: u16,
+    /// Omitted by the panel for portless (task) servers
+    #[serde(default)]
     pub ports: HashMap<String, String>, // "8080/tcp" -> "8080"
+    /// `user`, `uid` or `uid:gid` to run as; the image's default user when unset
+    #[serde(default)]
+    pub run_as_user: Option<String>,
+    /// Adds `no-new-privileges` so setuid binaries can't escalate inside the container
+    #[serde(default = "default_no_new_privileges")]
+    pub no_new_privileges: bool,
+    /// Egg `config_files` rules (file path -> parser and `find` table), applied before start
+    #[serde(default)]
+    pub config_files: Option<serde_json::Value>,
+    /// Docker's stop timeout for the container, used by `docker stop` and daemon shutdowns;
+    /// unset keeps Docker's 10 seconds
+    #[serde(default)]
+    pub stop_timeout: Option<u64>,
+    /// Whether to pull `image` before creating the container
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+    /// How `startup_command` becomes the container's command
+    #[serde(default)]
+    pub command_mode: CommandMode,
+    /// Mount the server's `yunexal-<uuid>` data volume (filled by `/containers/{uuid}/install`)
+    /// at the image's working directory
+    #[serde(default)]
+    pub data_volume: bool,
+    /// Console lines that mean the server finished starting; heartbeats report `ready`
+    /// once one shows up after a start
+    #[serde(default)]
+    pub startup_done: Vec<String>,
+    /// Start the container once it is created; `false` leaves it stopped
+    #[serde(default = "default_start")]
+    pub start: bool,
+}
+
+/// How `POST /containers` turns `startup_command` into the container's command.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandMode {
+    /// `/bin/sh -c <startup_command>`, so variables and pipes work
+    #[default]
+    Shell,
+    /// Split into arguments like a shell would, honouring quotes, but run without one
+    Argv,
+    /// No command at all: the image's own entrypoint and cmd run
+    Entrypoint,
+}
+
+/// When `POST /containers` pulls the image first.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PullPolicy {
+    /// Pull every time, so moving tags like `:latest` are refreshed
+    Always,
+    /// Pull only when the image isn't on the node yet
+    #[default]
+    IfNotPresent,
+    /// Never pull; a missing image fails with `image_not_found`
+    Never,
+}
+
+fn default_no_new_privileges() -> bool {
+    true
+}
+
+fn default_start() -> bool {
+    true
+}
+
+/// Body of `POST /containers/{uuid}/limits`, same units as `CreateContainerRequest`.
+#[derive(Deserialize)]
+pub struct UpdateLimitsRequest {
+    pub memory_limit: i64, // MB
+    pub swap_limit: i64,   // MB
+    pub cpu_limit: f64,    // percent of one core, 0 = unlimited
+    pub io_weight: u16,
+}
+
+#[derive(Serialize)]
+pub struct UpdateLimitsResponse {
+    /// Non-fatal problems, e.g. the new memory limit is below current usage
+    pub warnings: Vec<String>,
+}
+
+/// Query for `DELETE /containers/{uuid}`.
+#[derive(Deserialize)]
+pub struct DeleteContainerQuery {
+    /// Seconds to let the container stop before it is killed
+    pub grace: Option<u64>,
+    /// Also delete the server's data volume; without it the files stay for a later create
+    #[serde(default)]
+    pub purge: bool,
+}
+
+/// Power action for `POST /containers/{uuid}/power`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerAction {
+    Start,
+    Stop,
+    Restart,
+    Kill,
+}
+
+/// Query of `GET /containers/{uuid}/console`
+#[derive(Deserialize, Debug, Default)]
+pub struct ConsoleQuery {
+    /// `raw` keeps ANSI escapes, `strip` removes them; the node's `console_strip_ansi` otherwise
+    pub ansi: Option<String>,
+}
+
+/// Body of `POST /containers/{uuid}/power`.
+#[derive(Deserialize)]
+pub struct PowerRequest {
+    pub action: PowerAction,
+    /// Seconds `stop` and `restart` give the container before it is killed
+    pub grace: Option<u64>,
 }
 
+/// Body of `POST /containers/{uuid}/command`.
+#[derive(Deserialize)]
+pub struct CommandRequest {
+    /// One console line; a trailing newline is added when it is written
+    pub command: String,
+}
+
+#[derive(Serialize)]
+pub struct DeleteContainerResponse {
+    pub status: &'static str,
+    /// The container ignored the stop and had to be SIGKILLed
+    pub killed: bool,
+    /// The data volume was deleted (`purge=true`)
+    pub purged: bool,
+}
+
+/// Point-in-time Docker usage served by `GET /docker-summary`. Sizes are bytes.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct DockerSummaryResponse {
+    pub containers_total: u64,
+    pub containers_running: u64,
+    pub containers_stopped: u64,
+    /// Containers carrying the `yunexal.managed=true` label
+    pub containers_managed: u64,
+    pub containers_unmanaged: u64,
+    pub images: u64,
+    pub images_size: i64,
+    pub volumes: u64,
+    pub volumes_size: i64,
+    pub build_cache_size: i64,
+    /// Unused images + unreferenced volumes + build cache not in use
+    pub reclaimable: i64,
+}
+
+/// One mounted filesystem as the OS reports it. The panel groups these per physical disk
+/// and names them; the agent doesn't rename or merge anything.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DiskDetail {
+    /// Device path, e.g. `/dev/sda1` or `/dev/mapper/vg-root`
     pub name: String,
     pub mount_point: String,
     pub total_space: u64,
     pub available_space: u64,
     pub is_removable: bool,
+    /// `SSD`, `HDD` or `Unknown`
     pub type_: String,
+    /// e.g. `ext4`, `xfs`, `btrfs`
+    pub file_system: String,
 }
 
 #[derive(Serialize)]
@@ -50,6 +288,11 @@ pub struct HeartbeatPayload {
     pub uptime: u64,
     pub version: String,
     pub timestamp: i64,
+    /// Seconds between heartbeats; the panel derives its online window from it
+    pub heartbeat_interval: u64,
+    /// Logical cores; the panel checks CPU limits against `cpu_cores * 100`
+    #[serde(default)]
+    pub cpu_cores: usize,
     #[serde(default)]
     pub disk_read: u64,
     #[serde(default)]
@@ -60,6 +303,179 @@ pub struct HeartbeatPayload {
     pub net_tx: u64,
     #[serde(default)]
     pub disks: Vec<DiskDetail>,
+    /// State of every managed container; `None` when Docker couldn't be listed
+    pub containers: Option<Vec<ContainerState>>,
+    /// Self-update progress, sent once per change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateReport>,
+}
+
+/// Body of `POST /nodes/{id}/lifecycle` on the panel.
+#[derive(Serialize, Debug)]
+pub struct LifecycleEvent {
+    /// `starting` or `stopping`
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateReport>,
+}
+
+/// Where a self-update stands, reported to the panel with the next heartbeat.
+#[derive(Serialize, Debug, Clone)]
+pub struct UpdateReport {
+    /// `updating`, `completed` or `failed`
+    pub state: &'static str,
+    /// Version the agent is running
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// One managed container as reported in the heartbeat.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContainerState {
+    /// Panel server UUID (the `yunexal.server_id` label)
+    pub server_id: String,
+    /// Docker state: `running`, `exited`, `restarting`, ...
+    pub state: String,
+    /// Unix seconds the container last started; 0 unless running
+    pub started_at: i64,
+    /// Bytes received and sent since the agent started, kept across container restarts.
+    /// Only reported in heartbeats.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rx_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_bytes: Option<u64>,
+    /// Running and printed a `startup_done` marker since it started. Only reported in
+    /// heartbeats, and only for containers created with markers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>,
+}
+
+/// A managed container publishing a host port a create asked for, sent with `port_in_use`.
+#[derive(Serialize, Debug, Clone)]
+pub struct PortHolder {
+    pub port: u16,
+    /// Container name without the leading `/`
+    pub container: String,
+    /// Panel server UUID (the `yunexal.server_id` label)
+    pub server_id: String,
+    /// Docker state: `running`, `restarting`, ...
+    pub state: String,
+}
+
+/// A host port a managed container is set up to publish, from `GET /port-bindings`.
+#[derive(Serialize, Debug, Clone)]
+pub struct ContainerPortBinding {
+    pub host_port: u16,
+    /// Host address the port is bound on; empty for every interface
+    pub host_ip: String,
+    /// Container side, e.g. `25565/tcp`
+    pub container_port: String,
+    /// Container name without the leading `/`
+    pub container: String,
+    /// Panel server UUID (the `yunexal.server_id` label)
+    pub server_id: String,
+    /// Docker state: `running`, `exited`, ...
+    pub state: String,
+}
+
+/// `GET /free-ports?start=&end=`: an inclusive range of host ports to probe.
+#[derive(Deserialize, Debug)]
+pub struct FreePortsQuery {
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FreePortsResponse {
+    pub start: u16,
+    pub end: u16,
+    /// Ports in the range nothing is listening on, ascending
+    pub free: Vec<u16>,
+}
+
+/// `GET /servers/{uuid}/stored-logs?tail=`
+#[derive(Deserialize, Debug, Default)]
+pub struct StoredLogsQuery {
+    /// Lines from the end; 200 when missing, at most 5000
+    pub tail: Option<usize>,
+    /// `raw` or `strip`, as for the console
+    pub ansi: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StoredLogsResponse {
+    pub server_id: String,
+    /// The last `tail` lines, oldest first
+    pub logs: String,
+    /// Bytes stored for the server across all files
+    pub size: u64,
+}
+
+/// Running network totals for one managed container. Docker's counters restart with the
+/// container, so totals grow by the delta between samples instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetCounters {
+    /// Docker's counters at the previous sample
+    last_rx: u64,
+    last_tx: u64,
+    pub rx: u64,
+    pub tx: u64,
+}
+
+impl NetCounters {
+    /// Starts from the container's current counters, so traffic from before the agent
+    /// started (already reported by its predecessor) isn't counted again.
+    pub fn baseline(rx: u64, tx: u64) -> Self {
+        Self {
+            last_rx: rx,
+            last_tx: tx,
+            ..Default::default()
+        }
+    }
+
+    pub fn observe(&mut self, rx: u64, tx: u64) {
+        // A counter below the previous sample means the container restarted
+        self.rx += if rx >= self.last_rx {
+            rx - self.last_rx
+        } else {
+            rx
+        };
+        self.tx += if tx >= self.last_tx {
+            tx - self.last_tx
+        } else {
+            tx
+        };
+        self.last_rx = rx;
+        self.last_tx = tx;
+    }
+}
+
+/// Body of `POST /install-test`: run an image's install script in a throwaway container.
+#[derive(Deserialize)]
+pub struct InstallTestRequest {
+    /// Image the script runs in (the image's `install_container`)
+    pub container: String,
+    /// Shell used to run the script, e.g. `bash` or `ash`
+    pub entrypoint: String,
+    pub script: String,
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    #[serde(default = "default_install_test_memory")]
+    pub memory_limit: i64, // MB
+}
+
+fn default_install_test_memory() -> i64 {
+    1024
+}
+
+/// One line of the NDJSON stream `POST /install-test` answers with.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstallTestEvent {
+    Output { data: String },
+    Exit { code: i64, timed_out: bool },
+    Error { message: String },
 }
 
 #[derive(Deserialize)]
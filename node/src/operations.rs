@@ -0,0 +1,68 @@
+//! Node-wide operations lock. Creates, deletes, power actions and install tests each hold an
+//! `OperationGuard` while they run; a self-update only starts when none are running (unless
+//! forced) and from then on turns new ones away until the agent restarts.
+
+use crate::error::ApiError;
+use std::sync::{Arc, Mutex};
+
+/// Seconds a caller turned away during an update should wait; the restart is quick
+const UPDATE_RETRY_AFTER: u64 = 30;
+
+#[derive(Default)]
+pub struct Operations {
+    inner: Mutex<OperationsInner>,
+}
+
+#[derive(Default)]
+struct OperationsInner {
+    active: usize,
+    updating: bool,
+}
+
+/// Why `begin_update` refused to start.
+pub enum UpdateRefused {
+    /// Operations still running, and the update wasn't forced
+    Busy(usize),
+    /// Another update is already under way
+    InProgress,
+}
+
+/// Held for the length of one operation.
+pub struct OperationGuard(Arc<Operations>);
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.0.inner.lock().unwrap().active -= 1;
+    }
+}
+
+impl Operations {
+    /// Registers a new operation, or fails with `node_updating` while an update runs.
+    pub fn begin(self: &Arc<Self>) -> Result<OperationGuard, ApiError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.updating {
+            return Err(ApiError::updating(UPDATE_RETRY_AFTER));
+        }
+        inner.active += 1;
+        Ok(OperationGuard(self.clone()))
+    }
+
+    /// Blocks new operations for an update. With `force` it goes ahead while operations are
+    /// running; the count it returns is how many it will interrupt.
+    pub fn begin_update(&self, force: bool) -> Result<usize, UpdateRefused> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.updating {
+            return Err(UpdateRefused::InProgress);
+        }
+        if inner.active > 0 && !force {
+            return Err(UpdateRefused::Busy(inner.active));
+        }
+        inner.updating = true;
+        Ok(inner.active)
+    }
+
+    /// Lets operations in again after a failed update.
+    pub fn end_update(&self) {
+        self.inner.lock().unwrap().updating = false;
+    }
+}
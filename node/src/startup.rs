@@ -0,0 +1,155 @@
+//! "Startup done" detection. Images can name console lines that mean the server finished
+//! booting (Pterodactyl's `config.startup.done`); `POST /containers` stores them in a label,
+//! and a watcher follows each run's log from its start until one of them shows up. The
+//! heartbeat then reports the container as `ready`.
+
+use bollard::Docker;
+use bollard::container::{LogOutput, LogsOptions};
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Container label holding the markers as a JSON array of strings
+pub const DONE_LABEL: &str = "yunexal.startup_done";
+
+/// Log bytes kept while waiting for a line to end, so a marker split across chunks matches
+const MAX_PARTIAL_LINE: usize = 4096;
+
+#[derive(Default)]
+pub struct StartupWatch {
+    /// Server UUID -> start time of the run that printed a marker
+    ready: Mutex<HashMap<String, i64>>,
+    /// Runs (server UUID, start time) with a watcher following their log
+    watching: Mutex<HashSet<(String, i64)>>,
+}
+
+/// Markers from a container's labels; empty when the image has none.
+pub fn markers(labels: Option<&HashMap<String, String>>) -> Vec<String> {
+    labels
+        .and_then(|l| l.get(DONE_LABEL))
+        .and_then(|json| serde_json::from_str::<Vec<String>>(json).ok())
+        .unwrap_or_default()
+}
+
+impl StartupWatch {
+    /// Readiness of a running container for the heartbeat, starting a watcher for runs that
+    /// don't have one yet. `None` when the image has no markers.
+    pub fn ready(
+        self: &Arc<Self>,
+        docker: &Docker,
+        container_id: &str,
+        server_id: &str,
+        started_at: i64,
+        markers: Vec<String>,
+    ) -> Option<bool> {
+        if markers.is_empty() || started_at == 0 {
+            return None;
+        }
+        if self.ready.lock().unwrap().get(server_id) == Some(&started_at) {
+            return Some(true);
+        }
+
+        let run = (server_id.to_string(), started_at);
+        if self.watching.lock().unwrap().insert(run.clone()) {
+            let watch = Arc::clone(self);
+            let docker = docker.clone();
+            let container_id = container_id.to_string();
+            tokio::spawn(async move {
+                let found = follow_until_marker(&docker, &container_id, started_at, &markers).await;
+                if found {
+                    watch.ready.lock().unwrap().insert(run.0.clone(), run.1);
+                }
+                watch.watching.lock().unwrap().remove(&run);
+            });
+        }
+        Some(false)
+    }
+
+    /// Forgets servers whose containers are gone.
+    pub fn retain(&self, live: &HashSet<&str>) {
+        self.ready
+            .lock()
+            .unwrap()
+            .retain(|server_id, _| live.contains(server_id.as_str()));
+    }
+}
+
+/// Reads the container's log from `started_at` on. True once a line contains a marker;
+/// false when the log ends first (the container stopped) or can't be read.
+async fn follow_until_marker(
+    docker: &Docker,
+    container_id: &str,
+    started_at: i64,
+    markers: &[String],
+) -> bool {
+    let mut logs = docker.logs(
+        container_id,
+        Some(LogsOptions::<String> {
+            follow: true,
+            stdout: true,
+            stderr: true,
+            since: started_at,
+            ..Default::default()
+        }),
+    );
+
+    let mut line = String::new();
+    while let Some(Ok(chunk)) = logs.next().await {
+        let message = match chunk {
+            LogOutput::StdOut { message }
+            | LogOutput::StdErr { message }
+            | LogOutput::Console { message } => message,
+            LogOutput::StdIn { .. } => continue,
+        };
+        line.push_str(&String::from_utf8_lossy(&message));
+
+        // Check whole lines, plus whatever is pending, since prompts may never end one
+        if markers.iter().any(|m| line.contains(m.as_str())) {
+            return true;
+        }
+        if let Some(pos) = line.rfind('\n') {
+            line.drain(..=pos);
+        }
+        if line.len() > MAX_PARTIAL_LINE {
+            let cut = line.len() - MAX_PARTIAL_LINE;
+            let cut = (cut..line.len())
+                .find(|i| line.is_char_boundary(*i))
+                .unwrap_or(line.len());
+            line.drain(..cut);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_are_read_from_the_label() {
+        let labels = HashMap::from([(
+            DONE_LABEL.to_string(),
+            r#"["Done (", "For help"]"#.to_string(),
+        )]);
+        assert_eq!(markers(Some(&labels)), ["Done (", "For help"]);
+
+        let broken = HashMap::from([(DONE_LABEL.to_string(), "Done (".to_string())]);
+        assert!(markers(Some(&broken)).is_empty());
+        assert!(markers(Some(&HashMap::new())).is_empty());
+        assert!(markers(None).is_empty());
+    }
+
+    #[test]
+    fn readiness_is_only_tracked_for_started_runs_with_markers() {
+        let docker =
+            Docker::connect_with_http("http://127.0.0.1:1", 2, bollard::API_DEFAULT_VERSION)
+                .unwrap();
+        let watch = Arc::new(StartupWatch::default());
+        let done = vec!["Done (".to_string()];
+        assert_eq!(
+            watch.ready(&docker, "abc", "server", 1700000000, Vec::new()),
+            None
+        );
+        assert_eq!(watch.ready(&docker, "abc", "server", 0, done), None);
+    }
+}
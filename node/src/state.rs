@@ -1,6 +1,13 @@
+use crate::consoles::ConsoleHub;
+use crate::log_store::LogStore;
+use crate::models::{NetCounters, UpdateReport};
+use crate::operations::Operations;
+use crate::startup::StartupWatch;
 use bollard::Docker;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone)]
 #[allow(dead_code)]
@@ -12,4 +19,78 @@ pub struct NodeState {
     pub port: u16,
     pub ram_limit: u64,
     pub disk_limit: u64,
+    /// Answer auth failures with 500 instead of 401 (legacy behaviour)
+    pub legacy_auth_error: bool,
+    /// Container creates (and the image pulls they trigger) allowed at once
+    pub max_concurrent_creates: usize,
+    pub create_permits: Arc<Semaphore>,
+    /// Seconds between heartbeats when the panel is accepting our token
+    pub heartbeat_interval: u64,
+    /// Seconds before a `/install-test` container is killed
+    pub install_test_timeout: u64,
+    pub max_concurrent_install_tests: usize,
+    pub install_test_permits: Arc<Semaphore>,
+    /// Seconds before a server's install container (`/containers/{uuid}/install`) is killed
+    pub install_timeout: u64,
+    /// What `POST /host/reboot` runs; `None` keeps the endpoint disabled
+    pub reboot_command: Option<String>,
+    /// Console output bytes per second per connection, 0 = no cap
+    pub console_output_limit: u64,
+    /// Strip ANSI escapes from console output by default
+    pub console_strip_ansi: bool,
+    /// Unix seconds the agent started; containers started before it get a counter baseline
+    pub started_at: i64,
+    /// Network totals per server UUID, reported with every heartbeat
+    pub net_counters: Arc<Mutex<HashMap<String, NetCounters>>>,
+    /// Which running containers printed their image's startup-done marker
+    pub startup: Arc<StartupWatch>,
+    /// Shared console attaches, one per container with viewers
+    pub consoles: Arc<ConsoleHub>,
+    /// Output of managed containers kept on disk, per server
+    pub logs: Arc<LogStore>,
+    /// Running operations; a self-update waits for (or interrupts) them
+    pub operations: Arc<Operations>,
+    /// Self-update state not yet delivered to the panel
+    pub update_report: Arc<Mutex<Option<UpdateReport>>>,
+    /// Cancelled on SIGTERM/Ctrl+C, or to restart into a freshly installed binary
+    pub shutdown: CancellationToken,
+}
+
+#[cfg(test)]
+impl NodeState {
+    /// A state for handler tests: `token`, a panel at `panel_url`, default limits, and a
+    /// Docker client pointing at a closed port, so every Docker call fails fast.
+    pub fn for_tests(token: &str, panel_url: &str) -> Self {
+        let docker =
+            Docker::connect_with_http("http://127.0.0.1:1", 2, bollard::API_DEFAULT_VERSION)
+                .unwrap();
+        Self {
+            docker,
+            token: Arc::new(RwLock::new(token.to_string())),
+            node_id: "6b8e4d2e-1c1f-4a43-9d55-2f0c3b7a9e10".to_string(),
+            panel_url: panel_url.to_string(),
+            port: 8080,
+            ram_limit: 4096,
+            disk_limit: 10240,
+            legacy_auth_error: false,
+            max_concurrent_creates: 2,
+            create_permits: Arc::new(Semaphore::new(2)),
+            heartbeat_interval: 1,
+            install_test_timeout: 60,
+            max_concurrent_install_tests: 1,
+            install_test_permits: Arc::new(Semaphore::new(1)),
+            install_timeout: 60,
+            reboot_command: None,
+            console_output_limit: 0,
+            console_strip_ansi: false,
+            started_at: 0,
+            net_counters: Default::default(),
+            startup: Default::default(),
+            consoles: Default::default(),
+            logs: Arc::new(LogStore::new(0)),
+            operations: Default::default(),
+            update_report: Default::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
 }
@@ -1,145 +1,217 @@
-use sysinfo::{System, Disks};
-use crate::{state::NodeState, models::HeartbeatPayload};
+use crate::{
+    models::{DiskDetail, HeartbeatPayload, LifecycleEvent, NodeConfig},
+    state::NodeState,
+};
+use std::time::Duration;
+use sysinfo::{Disks, System};
+use tokio_util::sync::CancellationToken;
 
-pub async fn start_heartbeat_task(state: NodeState) {
+const MAX_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(300);
+/// Consecutive 401s before we assume the token is stale and start backing off
+const AUTH_FAILURE_THRESHOLD: u32 = 3;
+/// How long a lifecycle event may take; shutdown carries on without it
+const LIFECYCLE_EVENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Tracks consecutive auth failures and stretches the heartbeat interval while
+/// the panel keeps rejecting our token.
+#[derive(Debug)]
+pub struct HeartbeatBackoff {
+    base: Duration,
+    auth_failures: u32,
+}
+
+impl HeartbeatBackoff {
+    pub fn new(base: Duration) -> Self {
+        Self {
+            base,
+            auth_failures: 0,
+        }
+    }
+
+    /// Returns true once, when the failure count reaches the threshold.
+    pub fn record_auth_failure(&mut self) -> bool {
+        self.auth_failures += 1;
+        self.auth_failures == AUTH_FAILURE_THRESHOLD
+    }
+
+    /// Returns true if we were backing off before this success.
+    pub fn record_success(&mut self) -> bool {
+        let was_backing_off = self.is_backing_off();
+        self.auth_failures = 0;
+        was_backing_off
+    }
+
+    pub fn is_backing_off(&self) -> bool {
+        self.auth_failures >= AUTH_FAILURE_THRESHOLD
+    }
+
+    /// The configured interval normally; doubles with every failure past the threshold,
+    /// up to 5 minutes (or the configured interval, if that is longer).
+    pub fn interval(&self) -> Duration {
+        if !self.is_backing_off() {
+            return self.base;
+        }
+        let exp = (self.auth_failures - AUTH_FAILURE_THRESHOLD + 1).min(16);
+        (self.base * 2u32.pow(exp)).min(MAX_HEARTBEAT_INTERVAL.max(self.base))
+    }
+}
+
+/// Picks up a token written to config.yml behind our back (e.g. by a re-install).
+async fn reload_token_from_config(state: &NodeState) -> bool {
+    let Ok(content) = tokio::fs::read_to_string("config.yml").await else {
+        return false;
+    };
+    let Ok(cfg) = serde_yaml::from_str::<NodeConfig>(&content) else {
+        return false;
+    };
+
+    let mut token = state.token.write().await;
+    if cfg.token.is_empty() || *token == cfg.token {
+        return false;
+    }
+    *token = cfg.token;
+    true
+}
+
+/// Filesystem types that never hold server data: kernel interfaces, container layers and
+/// in-memory mounts.
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fuse.lxcfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "overlay",
+    "proc",
+    "pstore",
+    "ramfs",
+    "securityfs",
+    "squashfs",
+    "sysfs",
+    "tmpfs",
+    "tracefs",
+];
+
+/// One entry per mounted filesystem, pseudo-filesystems left out, plus the `(total, used)`
+/// aggregate still sent as `disk_total`/`disk_usage`. A device mounted more than once (bind
+/// mounts, btrfs subvolumes) is listed per mount but counted once.
+fn filesystems(disks: &Disks) -> (Vec<DiskDetail>, u64, u64) {
+    let mut details = Vec::new();
+    let mut counted = std::collections::HashSet::new();
+    let (mut total, mut used) = (0, 0);
+    for disk in disks {
+        let file_system = disk.file_system().to_string_lossy().to_string();
+        if disk.total_space() == 0 || PSEUDO_FILESYSTEMS.contains(&file_system.as_str()) {
+            continue;
+        }
+        let device = disk.name().to_string_lossy().to_string();
+        if counted.insert(device.clone()) {
+            total += disk.total_space();
+            used += disk.total_space().saturating_sub(disk.available_space());
+        }
+        let kind = match disk.kind() {
+            sysinfo::DiskKind::HDD => "HDD",
+            sysinfo::DiskKind::SSD => "SSD",
+            _ => "Unknown",
+        };
+        details.push(DiskDetail {
+            name: device,
+            mount_point: disk.mount_point().to_string_lossy().to_string(),
+            total_space: disk.total_space(),
+            available_space: disk.available_space(),
+            is_removable: disk.is_removable(),
+            type_: kind.to_string(),
+            file_system,
+        });
+    }
+    (details, total, used)
+}
+
+/// Sends heartbeats until `shutdown` is cancelled. A heartbeat already under way is
+/// finished first, then a `stopping` event tells the panel the node is going offline.
+pub async fn start_heartbeat_task(state: NodeState, shutdown: CancellationToken) {
     let client = reqwest::Client::new();
     let mut sys = System::new_all();
     let mut disks = Disks::new_with_refreshed_list();
     let mut networks = sysinfo::Networks::new();
     let version = env!("CARGO_PKG_VERSION").to_string();
-    
+
     // Disk I/O Tracking
     let mut prev_read_bytes = 0u64;
     let mut prev_write_bytes = 0u64;
-    
+
     // Network Tracking
     let mut prev_rx_bytes = 0u64;
     let mut prev_tx_bytes = 0u64;
-    
+
+    // Stats from our previous run may still be cached on the panel
+    send_lifecycle_event(&client, &state, "starting").await;
+
     let mut first_run = true;
-    
+    let base_interval = Duration::from_secs(state.heartbeat_interval);
+    let mut backoff = HeartbeatBackoff::new(base_interval);
+    // Seconds since the previous sample, for the per-second I/O rates
+    let mut tick_secs = base_interval.as_secs();
+
     loop {
         sys.refresh_all();
         disks.refresh(true);
         networks.refresh(true);
-        
+
         let cpu_usage = sys.global_cpu_usage();
         let ram_usage = sys.used_memory();
         let ram_total = sys.total_memory();
-        
-        let mut disk_total = 0; // Keeping aggregate for backward compatibility
-        let mut disk_usage = 0;
-        let mut detailed_disks = Vec::new();
-        let mut physical_disks: std::collections::HashMap<String, crate::models::DiskDetail> = std::collections::HashMap::new();
-        
-        for disk in &disks {
-            let name_str = disk.name().to_string_lossy().to_string(); // e.g. /dev/sda1
-            let mount_str = disk.mount_point().to_string_lossy().to_string();
-            
-            // 1. Filter: specific types only
-            if !name_str.starts_with("/dev/sd") && 
-               !name_str.starts_with("/dev/vd") && 
-               !name_str.starts_with("/dev/nvme") && 
-               mount_str != "/" {
-                continue;
-            }
 
-            disk_total += disk.total_space();
-            disk_usage += disk.total_space() - disk.available_space();
-            
-            // 2. Resolve Parent Device Name
-            let parent_name = if name_str.starts_with("/dev/sd") || name_str.starts_with("/dev/vd") {
-                // /dev/sda1 -> /dev/sda (Remove trailing digits)
-                name_str.trim_end_matches(char::is_numeric).to_string()
-            } else if name_str.starts_with("/dev/nvme") {
-                // /dev/nvme0n1p1 -> /dev/nvme0n1 (Remove 'p' + digits at end)
-                if let Some(idx) = name_str.rfind('p') {
-                    // Check if 'p' is followed only by digits (partition suffix)
-                    let suffix = &name_str[idx+1..];
-                    if suffix.chars().all(char::is_numeric) && idx > 10 { // naive check length
-                         name_str[..idx].to_string()
-                    } else {
-                        name_str.clone()
-                    }
-                } else {
-                    name_str.clone()
-                }
-            } else {
-                 name_str.clone()
-            };
-
-            let total = disk.total_space();
-            let available = disk.available_space();
-            
-            let kind_str = match disk.kind() {
-                sysinfo::DiskKind::HDD => "HDD",
-                sysinfo::DiskKind::SSD => "SSD",
-                _ => "Unknown", 
-            };
-            
-            // 3. Aggregate
-            physical_disks.entry(parent_name.clone())
-                .and_modify(|d| {
-                    d.total_space += total;
-                    d.available_space += available;
-                    // If any partition is root, mark this disk as System
-                    if mount_str == "/" {
-                        d.name = "System (Default)".to_string(); 
-                    }
-                })
-                .or_insert(crate::models::DiskDetail {
-                    name: if mount_str == "/" { "System (Default)".to_string() } else { parent_name }, // Temp name, replaced later by "Disk N"
-                    mount_point: "".to_string(), // Not used in aggregated view
-                    total_space: total,
-                    available_space: available,
-                    is_removable: disk.is_removable(),
-                    type_: kind_str.to_string(),
-                });
-        }
-        
-        // 4. Convert to List and Apply "Disk N" naming for non-system disks
-        let mut ext_counter = 0;
-        let mut sorted_keys: Vec<String> = physical_disks.keys().cloned().collect();
-        sorted_keys.sort(); // Consistent ordering (sda, sdb...)
-        
-        for key in sorted_keys {
-            if let Some(mut d) = physical_disks.remove(&key) {
-                 if d.name != "System (Default)" {
-                     ext_counter += 1;
-                     d.name = format!("Disk {} ({})", ext_counter, key.replace("/dev/", ""));
-                 }
-                 detailed_disks.push(d);
-            }
-        }
+        // Raw facts per filesystem; the panel groups and labels them
+        let (detailed_disks, disk_total, disk_usage) = filesystems(&disks);
 
         // Calculate Disk I/O from /proc/diskstats
         let mut current_read_bytes = 0u64;
         let mut current_write_bytes = 0u64;
-        
+
         if let Ok(content) = tokio::fs::read_to_string("/proc/diskstats").await {
             for line in content.lines() {
                 let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 14 {
                     let name = parts[2];
                     // Filter: sdX, vdX, nvmeXnY (skip partitions like sdX1, nvmeXnYpZ)
-                    let is_sd_vd = (name.starts_with("sd") || name.starts_with("vd")) 
-                                   && name.chars().last().map_or(false, |c| c.is_alphabetic());
+                    let is_sd_vd = (name.starts_with("sd") || name.starts_with("vd"))
+                        && name.chars().last().is_some_and(|c| c.is_alphabetic());
                     let is_nvme = name.starts_with("nvme") && !name.contains("p");
-             
-                    if is_sd_vd || is_nvme {
-                        if let (Ok(r_sec), Ok(w_sec)) = (parts[5].parse::<u64>(), parts[9].parse::<u64>()) {
-                           // Sector = 512 bytes usually
-                           current_read_bytes += r_sec * 512;
-                           current_write_bytes += w_sec * 512;
-                        }
+
+                    if (is_sd_vd || is_nvme)
+                        && let (Ok(r_sec), Ok(w_sec)) =
+                            (parts[5].parse::<u64>(), parts[9].parse::<u64>())
+                    {
+                        // Sector = 512 bytes usually
+                        current_read_bytes += r_sec * 512;
+                        current_write_bytes += w_sec * 512;
                     }
                 }
             }
         }
-        
-        let disk_read_speed = if first_run { 0 } else { (current_read_bytes.saturating_sub(prev_read_bytes)) / 5 };
-        let disk_write_speed = if first_run { 0 } else { (current_write_bytes.saturating_sub(prev_write_bytes)) / 5 };
-        
+
+        let disk_read_speed = if first_run {
+            0
+        } else {
+            (current_read_bytes.saturating_sub(prev_read_bytes)) / tick_secs
+        };
+        let disk_write_speed = if first_run {
+            0
+        } else {
+            (current_write_bytes.saturating_sub(prev_write_bytes)) / tick_secs
+        };
+
         prev_read_bytes = current_read_bytes;
         prev_write_bytes = current_write_bytes;
 
@@ -152,8 +224,16 @@ pub async fn start_heartbeat_task(state: NodeState) {
             current_tx_bytes += data.total_transmitted();
         }
 
-        let net_rx_speed = if first_run { 0 } else { (current_rx_bytes.saturating_sub(prev_rx_bytes)) / 5 };
-        let net_tx_speed = if first_run { 0 } else { (current_tx_bytes.saturating_sub(prev_tx_bytes)) / 5 };
+        let net_rx_speed = if first_run {
+            0
+        } else {
+            (current_rx_bytes.saturating_sub(prev_rx_bytes)) / tick_secs
+        };
+        let net_tx_speed = if first_run {
+            0
+        } else {
+            (current_tx_bytes.saturating_sub(prev_tx_bytes)) / tick_secs
+        };
 
         prev_rx_bytes = current_rx_bytes;
         prev_tx_bytes = current_tx_bytes;
@@ -173,38 +253,242 @@ pub async fn start_heartbeat_task(state: NodeState) {
             uptime,
             version: version.clone(),
             timestamp,
+            heartbeat_interval: state.heartbeat_interval,
+            cpu_cores: sys.cpus().len(),
             disk_read: disk_read_speed,
             disk_write: disk_write_speed,
             net_rx: net_rx_speed,
             net_tx: net_tx_speed,
             disks: detailed_disks,
+            containers: crate::handlers::docker::managed_container_states(&state).await,
+            update: state.update_report.lock().unwrap().take(),
         };
 
         // Assuming the panel has an endpoint /nodes/{id}/heartbeat
         let url = format!("{}/nodes/{}/heartbeat", state.panel_url, state.node_id);
-        let current_token = state.token.read().await;
+        let current_token = state.token.read().await.clone();
 
         println!("DEBUG: Sending heartbeat to: {}", url);
 
-        match client.post(&url)
-            .header("Authorization", format!("Bearer {}", *current_token))
+        match client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", current_token))
             .json(&payload)
             .send()
-            .await 
+            .await
         {
             Ok(resp) => {
                 println!("DEBUG: Heartbeat Response Status: {}", resp.status());
-                if !resp.status().is_success() {
-                    eprintln!("Heartbeat failed with status: {} | URL: {}", resp.status(), url);
-                    let body = resp.text().await.unwrap_or_else(|_| "Failed to read body".to_string());
+                if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+                    requeue_update_report(&state, &payload);
+                    if backoff.record_auth_failure() {
+                        eprintln!("==============================================================");
+                        eprintln!(
+                            "Panel rejected this node's token {} times in a row.",
+                            AUTH_FAILURE_THRESHOLD
+                        );
+                        eprintln!(
+                            "Token likely invalid: re-run the install command or rotate the token."
+                        );
+                        eprintln!("Backing off heartbeats until the panel accepts us again.");
+                        eprintln!("==============================================================");
+                    }
+                    if backoff.is_backing_off() && reload_token_from_config(&state).await {
+                        println!("Loaded a new token from config.yml, retrying at normal interval");
+                        backoff = HeartbeatBackoff::new(base_interval);
+                    }
+                } else if !resp.status().is_success() {
+                    eprintln!(
+                        "Heartbeat failed with status: {} | URL: {}",
+                        resp.status(),
+                        url
+                    );
+                    let body = resp
+                        .text()
+                        .await
+                        .unwrap_or_else(|_| "Failed to read body".to_string());
                     println!("DEBUG: Error Body: {}", body);
+                    requeue_update_report(&state, &payload);
                 } else {
                     println!("DEBUG: Heartbeat success: {}", resp.status());
+                    if backoff.record_success() {
+                        println!(
+                            "Panel accepted the token again, resuming normal heartbeat interval"
+                        );
+                    }
                 }
-            },
-            Err(e) => eprintln!("Failed to send heartbeat to {}: {}", url, e),
+            }
+            Err(e) => {
+                eprintln!("Failed to send heartbeat to {}: {}", url, e);
+                requeue_update_report(&state, &payload);
+            }
+        }
+
+        let interval = backoff.interval();
+        tick_secs = interval.as_secs();
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.cancelled() => {
+                send_lifecycle_event(&client, &state, "stopping").await;
+                return;
+            }
+        }
+    }
+}
+
+/// Puts back an update report the panel didn't get, unless a newer one has replaced it.
+fn requeue_update_report(state: &NodeState, payload: &HeartbeatPayload) {
+    if let Some(report) = &payload.update {
+        state
+            .update_report
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| report.clone());
+    }
+}
+
+/// Tells the panel the agent is `starting` or `stopping`, so it drops this node's stats
+/// right away instead of showing them until they expire. Best effort: the panel may be
+/// unreachable too.
+async fn send_lifecycle_event(client: &reqwest::Client, state: &NodeState, event: &'static str) {
+    let payload = LifecycleEvent {
+        event,
+        // Carries "updating" when we're restarting into a new binary
+        update: if event == "stopping" {
+            state.update_report.lock().unwrap().take()
+        } else {
+            None
+        },
+    };
+
+    let url = format!("{}/nodes/{}/lifecycle", state.panel_url, state.node_id);
+    let token = state.token.read().await.clone();
+    let sent = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .timeout(LIFECYCLE_EVENT_TIMEOUT)
+        .json(&payload)
+        .send()
+        .await;
+
+    match sent {
+        Ok(resp) if resp.status().is_success() => println!("Told the panel this node is {}", event),
+        Ok(resp) => eprintln!(
+            "Panel rejected the {} event with status: {}",
+            event,
+            resp.status()
+        ),
+        Err(e) => eprintln!("Failed to send {} event to {}: {}", event, url, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const BASE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn backs_off_only_after_the_threshold() {
+        let mut backoff = HeartbeatBackoff::new(BASE);
+        for _ in 1..AUTH_FAILURE_THRESHOLD {
+            assert!(!backoff.record_auth_failure());
+            assert!(!backoff.is_backing_off());
+            assert_eq!(backoff.interval(), BASE);
+        }
+        // Reported once, when the threshold is hit
+        assert!(backoff.record_auth_failure());
+        assert!(backoff.is_backing_off());
+        assert!(!backoff.record_auth_failure());
+    }
+
+    #[test]
+    fn interval_doubles_up_to_the_cap() {
+        let mut backoff = HeartbeatBackoff::new(BASE);
+        let mut intervals = Vec::new();
+        for _ in 0..AUTH_FAILURE_THRESHOLD + 6 {
+            backoff.record_auth_failure();
+            intervals.push(backoff.interval().as_secs());
+        }
+        assert_eq!(intervals, [5, 5, 10, 20, 40, 80, 160, 300, 300]);
+    }
+
+    #[test]
+    fn a_long_configured_interval_is_never_shortened() {
+        let base = Duration::from_secs(600);
+        let mut backoff = HeartbeatBackoff::new(base);
+        for _ in 0..AUTH_FAILURE_THRESHOLD + 3 {
+            backoff.record_auth_failure();
+        }
+        assert_eq!(backoff.interval(), base);
+    }
+
+    #[test]
+    fn success_resets_and_reports_the_recovery() {
+        let mut backoff = HeartbeatBackoff::new(BASE);
+        assert!(!backoff.record_success());
+        for _ in 0..AUTH_FAILURE_THRESHOLD + 2 {
+            backoff.record_auth_failure();
+        }
+        assert!(backoff.record_success());
+        assert!(!backoff.is_backing_off());
+        assert_eq!(backoff.interval(), BASE);
+        assert!(!backoff.record_success());
+    }
+
+    /// A panel that answers 200 to everything and records `heartbeat` or `lifecycle:<event>`.
+    async fn fake_panel() -> (String, Arc<Mutex<Vec<String>>>) {
+        use axum::{Json, Router, extract::Path, routing::post};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/nodes/{id}/heartbeat",
+                post({
+                    let seen = seen.clone();
+                    move || async move { seen.lock().unwrap().push("heartbeat".to_string()) }
+                }),
+            )
+            .route(
+                "/nodes/{id}/lifecycle",
+                post({
+                    let seen = seen.clone();
+                    move |Path(_): Path<String>, Json(body): Json<serde_json::Value>| async move {
+                        let event = body["event"].as_str().unwrap_or_default();
+                        seen.lock().unwrap().push(format!("lifecycle:{}", event));
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, seen)
+    }
+
+    #[tokio::test]
+    async fn shutdown_reports_stopping_and_ends_the_loop() {
+        let (panel_url, seen) = fake_panel().await;
+        let mut state = NodeState::for_tests("node-token", &panel_url);
+        // Long enough that only the shutdown can end the wait for the next heartbeat
+        state.heartbeat_interval = 600;
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(start_heartbeat_task(state, shutdown.clone()));
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while !seen.lock().unwrap().contains(&"heartbeat".to_string()) {
+            assert!(tokio::time::Instant::now() < deadline, "no heartbeat sent");
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("heartbeat loop kept running after shutdown")
+            .unwrap();
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["lifecycle:starting", "heartbeat", "lifecycle:stopping"]
+        );
     }
 }
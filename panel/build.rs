@@ -0,0 +1,4 @@
+// Re-embed migrations when the directory changes; `sqlx::migrate!` alone doesn't notice new files.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,219 @@
+-- Schema as the panel built it at startup before migrations existed. Every statement is
+-- idempotent so databases created by those older panels take this baseline as a no-op
+-- catch-up. Applied migrations are checksummed: never edit this file, add a new one.
+
+-- Nodes
+CREATE TABLE IF NOT EXISTS nodes (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    token TEXT NOT NULL
+);
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS token TEXT;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS sftp_port INTEGER DEFAULT 2022;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS ram_limit INTEGER DEFAULT 0;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS disk_limit INTEGER DEFAULT 0;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS cpu_limit INTEGER DEFAULT 0;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS version TEXT DEFAULT '';
+-- Pending token for rotations when Redis is unavailable
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS pending_token TEXT;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS pending_token_expires TIMESTAMPTZ;
+-- Shown next to the token on the node edit page
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS token_rotated_at TIMESTAMPTZ;
+-- Port range the panel may mint allocations from, e.g. "30000-31000"
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS auto_allocation_range TEXT DEFAULT '';
+
+-- Allocations
+CREATE TABLE IF NOT EXISTS allocations (
+    id UUID PRIMARY KEY,
+    node_id UUID NOT NULL REFERENCES nodes(id) ON DELETE CASCADE,
+    ip TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    server_id UUID,
+    UNIQUE(node_id, ip, port)
+);
+ALTER TABLE allocations ADD COLUMN IF NOT EXISTS auto_created BOOLEAN DEFAULT FALSE;
+-- Admin notes, and ports held back from auto-assignment (still pickable by hand)
+ALTER TABLE allocations ADD COLUMN IF NOT EXISTS notes TEXT NOT NULL DEFAULT '';
+ALTER TABLE allocations ADD COLUMN IF NOT EXISTS reserved BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Runtimes
+CREATE TABLE IF NOT EXISTS runtimes (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    color TEXT DEFAULT '#007bff'
+);
+ALTER TABLE runtimes ADD COLUMN IF NOT EXISTS color TEXT DEFAULT '#007bff';
+ALTER TABLE runtimes ADD COLUMN IF NOT EXISTS sort_order INTEGER DEFAULT 0;
+
+-- Images
+CREATE TABLE IF NOT EXISTS images (
+    id UUID PRIMARY KEY,
+    runtime_id UUID NOT NULL REFERENCES runtimes(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    docker_images TEXT NOT NULL,
+    description TEXT,
+    stop_command TEXT NOT NULL DEFAULT 'stop',
+    startup_command TEXT NOT NULL DEFAULT '',
+    log_config TEXT NOT NULL DEFAULT '{}',
+    config_files TEXT NOT NULL DEFAULT '[]',
+    start_config TEXT NOT NULL DEFAULT '{}',
+    requires_port BOOLEAN NOT NULL DEFAULT TRUE
+);
+ALTER TABLE images ADD COLUMN IF NOT EXISTS docker_images TEXT DEFAULT '';
+
+-- Very old schemas had a singular docker_image column: carry its data over, then drop it
+-- so its NOT NULL constraint can't fail inserts
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'images' AND column_name = 'docker_image'
+    ) THEN
+        UPDATE images SET docker_images = docker_image
+        WHERE (docker_images IS NULL OR docker_images = '') AND docker_image IS NOT NULL;
+        ALTER TABLE images DROP COLUMN docker_image;
+    END IF;
+END $$;
+
+ALTER TABLE images ADD COLUMN IF NOT EXISTS stop_command TEXT DEFAULT 'stop';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS startup_command TEXT DEFAULT '';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS log_config TEXT DEFAULT '{}';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS config_files TEXT DEFAULT '[]';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS start_config TEXT DEFAULT '{}';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS requires_port BOOLEAN DEFAULT TRUE;
+-- Minimum limits a server needs to run this image (0 = no minimum)
+ALTER TABLE images ADD COLUMN IF NOT EXISTS min_ram INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS min_disk INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS min_cpu INTEGER NOT NULL DEFAULT 0;
+-- Container hardening: optional user to run as, and no-new-privileges (on by default)
+ALTER TABLE images ADD COLUMN IF NOT EXISTS run_as_user TEXT NOT NULL DEFAULT '';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS no_new_privileges BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS allow_startup_override BOOLEAN DEFAULT TRUE;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS previous_startup_command TEXT DEFAULT '';
+-- Egg install scripts and variables
+ALTER TABLE images ADD COLUMN IF NOT EXISTS install_script TEXT DEFAULT '';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS install_container TEXT DEFAULT '';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS install_entrypoint TEXT DEFAULT 'bash';
+ALTER TABLE images ADD COLUMN IF NOT EXISTS variables TEXT DEFAULT '[]';
+
+-- Servers
+CREATE TABLE IF NOT EXISTS servers (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT,
+    owner_id TEXT NOT NULL,
+    node_id UUID NOT NULL REFERENCES nodes(id),
+    allocation_id UUID REFERENCES allocations(id),
+    image_id UUID NOT NULL REFERENCES images(id),
+
+    cpu_limit INTEGER DEFAULT 0,
+    ram_limit INTEGER DEFAULT 0,
+    disk_limit INTEGER DEFAULT 0,
+    swap_limit INTEGER DEFAULT 0,
+    backup_limit INTEGER DEFAULT 0,
+
+    io_weight INTEGER DEFAULT 500,
+    oom_killer BOOLEAN DEFAULT FALSE,
+    docker_image TEXT NOT NULL,
+    startup_command TEXT NOT NULL,
+
+    cpu_pinning TEXT,
+    status TEXT DEFAULT 'installing',
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+-- Allocations are optional for servers
+ALTER TABLE servers ALTER COLUMN allocation_id DROP NOT NULL;
+-- Last error from the node when container creation gave up
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS install_error TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+-- Janitor flags for servers stuck installing
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS flagged_at TIMESTAMPTZ;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS flag_reason TEXT;
+-- Changes that only take effect after the container is recreated
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS needs_recreate BOOLEAN DEFAULT FALSE;
+-- Opt-in unauthenticated status endpoint for embeds
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS public_status BOOLEAN NOT NULL DEFAULT FALSE;
+-- Non-secret variable values chosen at create time, so clones can copy them
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS variables JSONB NOT NULL DEFAULT '{}';
+-- cpu_limit is percent of one core (100 = one core) and 0 = unlimited; nodes reject negatives
+UPDATE servers SET cpu_limit = 0 WHERE cpu_limit < 0;
+
+-- Users and sessions
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'user',
+    permissions TEXT DEFAULT '{}',
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Named create-form presets (see services::server_presets)
+CREATE TABLE IF NOT EXISTS server_templates (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    config JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Background jobs (see services::jobs)
+CREATE TABLE IF NOT EXISTS jobs (
+    id UUID PRIMARY KEY,
+    kind TEXT NOT NULL,
+    target TEXT NOT NULL,
+    payload JSONB NOT NULL DEFAULT '{}',
+    status TEXT NOT NULL DEFAULT 'queued',
+    progress INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    heartbeat_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS jobs_status_idx ON jobs (status, created_at);
+CREATE INDEX IF NOT EXISTS jobs_target_idx ON jobs (target, created_at DESC);
+
+-- Server event log
+CREATE TABLE IF NOT EXISTS server_events (
+    id UUID PRIMARY KEY,
+    server_id UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Single-use tokens for the install command (see services::install_tokens)
+CREATE TABLE IF NOT EXISTS node_install_tokens (
+    token_sha256 TEXT PRIMARY KEY,
+    node_id UUID NOT NULL REFERENCES nodes(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ
+);
+
+-- Agent versions reported by heartbeats over time
+CREATE TABLE IF NOT EXISTS node_version_history (
+    id BIGSERIAL PRIMARY KEY,
+    node_id UUID NOT NULL REFERENCES nodes(id) ON DELETE CASCADE,
+    from_version TEXT,
+    to_version TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS node_version_history_node_idx ON node_version_history (node_id, changed_at DESC);
+
+-- Encrypted values of secret variables (see services::secrets)
+CREATE TABLE IF NOT EXISTS server_secrets (
+    server_id UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    env_variable TEXT NOT NULL,
+    ciphertext TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (server_id, env_variable)
+);
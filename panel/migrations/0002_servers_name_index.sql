@@ -0,0 +1,2 @@
+-- Backs the command palette's server lookup (`GET /api/search`), which matches lowercased names
+CREATE INDEX IF NOT EXISTS servers_name_idx ON servers (lower(name) text_pattern_ops);
@@ -0,0 +1,11 @@
+-- Seconds a container gets to stop before it is killed. Images carry the default; each
+-- server keeps its own copy so it can be overridden per server.
+ALTER TABLE images ADD COLUMN IF NOT EXISTS stop_timeout_seconds INTEGER NOT NULL DEFAULT 30;
+
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS stop_timeout_seconds INTEGER;
+UPDATE servers s SET stop_timeout_seconds = i.stop_timeout_seconds
+FROM images i
+WHERE s.image_id = i.id AND s.stop_timeout_seconds IS NULL;
+UPDATE servers SET stop_timeout_seconds = 30 WHERE stop_timeout_seconds IS NULL;
+ALTER TABLE servers ALTER COLUMN stop_timeout_seconds SET DEFAULT 30;
+ALTER TABLE servers ALTER COLUMN stop_timeout_seconds SET NOT NULL;
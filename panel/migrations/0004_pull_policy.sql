@@ -0,0 +1,11 @@
+-- When the node pulls a server's docker image before creating its container:
+-- 'always', 'if_not_present' or 'never'. Images carry the default, servers their own copy.
+ALTER TABLE images ADD COLUMN IF NOT EXISTS pull_policy TEXT NOT NULL DEFAULT 'if_not_present';
+
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS pull_policy TEXT;
+UPDATE servers s SET pull_policy = i.pull_policy
+FROM images i
+WHERE s.image_id = i.id AND s.pull_policy IS NULL;
+UPDATE servers SET pull_policy = 'if_not_present' WHERE pull_policy IS NULL;
+ALTER TABLE servers ALTER COLUMN pull_policy SET DEFAULT 'if_not_present';
+ALTER TABLE servers ALTER COLUMN pull_policy SET NOT NULL;
@@ -0,0 +1,24 @@
+-- Monthly network totals per server, built from the cumulative counters nodes report in
+-- heartbeats (see services::bandwidth). `period` is the first day of the month, UTC.
+CREATE TABLE IF NOT EXISTS server_bandwidth (
+    server_id UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    period DATE NOT NULL,
+    rx_bytes BIGINT NOT NULL DEFAULT 0,
+    tx_bytes BIGINT NOT NULL DEFAULT 0,
+    -- Set once the month's egress passed the server's cap, so the action fires once
+    cap_exceeded_at TIMESTAMPTZ,
+    PRIMARY KEY (server_id, period)
+);
+
+-- Last counters the node reported and the heartbeat (ms) they came from
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS net_rx_counter BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS net_tx_counter BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS net_counter_at BIGINT NOT NULL DEFAULT 0;
+
+-- Monthly egress cap in GB (0 = none) and what exceeding it does: 'event' or 'suspend'
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS bandwidth_limit INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS bandwidth_action TEXT NOT NULL DEFAULT 'event';
+
+-- Suspended servers can't be started or restarted until the suspension is lifted
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS suspended_at TIMESTAMPTZ;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS suspend_reason TEXT;
@@ -0,0 +1,5 @@
+-- Latest self-update status per node: 'requested' by the panel, then 'updating',
+-- 'completed' or 'failed' as reported by the agent in heartbeats
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS update_state TEXT;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS update_message TEXT;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS update_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Console buttons for servers of an image: JSON array of { "label", "command" }, where the
+-- command may contain {{name}} placeholders filled in when the button is used
+ALTER TABLE images ADD COLUMN IF NOT EXISTS console_macros TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,3 @@
+-- How the node turns a server's startup command into the container command:
+-- 'shell' (/bin/sh -c), 'argv' (split on quotes, no shell) or 'entrypoint' (image default)
+ALTER TABLE images ADD COLUMN IF NOT EXISTS command_mode TEXT NOT NULL DEFAULT 'shell';
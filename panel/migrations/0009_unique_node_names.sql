@@ -0,0 +1,24 @@
+-- Node names are how the dashboard tells nodes apart, so they must be unique regardless of
+-- case. Existing duplicates keep the first name (by id) and the rest get -2, -3, ...
+DO $$
+DECLARE
+    dup RECORD;
+    n INTEGER;
+    candidate TEXT;
+BEGIN
+    FOR dup IN
+        SELECT id, name, rn FROM (
+            SELECT id, name, row_number() OVER (PARTITION BY lower(name) ORDER BY id) AS rn FROM nodes
+        ) numbered WHERE rn > 1
+    LOOP
+        n := dup.rn;
+        LOOP
+            candidate := dup.name || '-' || n;
+            EXIT WHEN NOT EXISTS (SELECT 1 FROM nodes WHERE lower(name) = lower(candidate));
+            n := n + 1;
+        END LOOP;
+        UPDATE nodes SET name = candidate WHERE id = dup.id;
+    END LOOP;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS nodes_name_lower_key ON nodes (lower(name));
@@ -0,0 +1,3 @@
+-- Managed containers the node reported holding this server's ports on its last failed
+-- create (JSON array of {port, container, server_id, state}); NULL once a create succeeds
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS port_conflicts TEXT;
@@ -0,0 +1,6 @@
+-- Decommissioning: an admin starts it, the node's uninstall script finishes it by presenting
+-- a one-time token, which removes the node row
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS decommission_started_at TIMESTAMPTZ;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS decommission_script_fetched_at TIMESTAMPTZ;
+-- 'install' or 'uninstall'
+ALTER TABLE node_install_tokens ADD COLUMN IF NOT EXISTS purpose TEXT NOT NULL DEFAULT 'install';
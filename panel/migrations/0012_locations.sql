@@ -0,0 +1,17 @@
+-- Locations group nodes (regions, datacenters) so server placement can be scoped to one.
+-- Every node belongs to exactly one; the nil-UUID "Unassigned" location holds the rest and
+-- can't be renamed or deleted.
+CREATE TABLE IF NOT EXISTS locations (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+CREATE UNIQUE INDEX IF NOT EXISTS locations_name_lower_key ON locations (lower(name));
+
+INSERT INTO locations (id, name, description)
+VALUES ('00000000-0000-0000-0000-000000000000', 'Unassigned', 'Nodes not placed in a location yet')
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS location_id UUID NOT NULL
+    DEFAULT '00000000-0000-0000-0000-000000000000' REFERENCES locations (id);
+CREATE INDEX IF NOT EXISTS nodes_location_id_idx ON nodes (location_id);
@@ -0,0 +1,21 @@
+-- Maintenance windows: once a week (or daily) the panel stops a node's running servers,
+-- optionally updates the agent and reboots the host, then starts the servers again.
+-- 'daily HH:MM' or '<weekday> HH:MM', UTC; empty for none
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_window TEXT NOT NULL DEFAULT '';
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_update BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_reboot BOOLEAN NOT NULL DEFAULT FALSE;
+-- Start of the last window a run was queued for, so each window runs once
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_last_run TIMESTAMPTZ;
+-- Set when a run gave up and left servers stopped; cleared by an admin or the next good run
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS maintenance_alert TEXT;
+
+-- Node-level audit trail, the counterpart of server_events
+CREATE TABLE IF NOT EXISTS node_events (
+    id UUID PRIMARY KEY,
+    node_id UUID NOT NULL REFERENCES nodes (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS node_events_node_id_idx ON node_events (node_id, created_at DESC);
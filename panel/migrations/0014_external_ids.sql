@@ -0,0 +1,6 @@
+-- Optional caller-chosen keys for runtimes and images, so the JSON API can upsert
+-- definitions kept elsewhere (e.g. a git repo) without creating duplicates
+ALTER TABLE runtimes ADD COLUMN IF NOT EXISTS external_id TEXT;
+ALTER TABLE images ADD COLUMN IF NOT EXISTS external_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS runtimes_external_id_key ON runtimes (external_id) WHERE external_id IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS images_external_id_key ON images (external_id) WHERE external_id IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Names admins give a node's disks, keyed by the physical device the panel groups the
+-- agent's filesystems under (e.g. /dev/sdb). Disks without a row get a generated name.
+CREATE TABLE IF NOT EXISTS node_disk_labels (
+    node_id UUID NOT NULL REFERENCES nodes (id) ON DELETE CASCADE,
+    device TEXT NOT NULL,
+    label TEXT NOT NULL,
+    PRIMARY KEY (node_id, device)
+);
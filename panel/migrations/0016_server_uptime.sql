@@ -0,0 +1,13 @@
+-- Container state over time, built from heartbeats (see services::uptime). A segment is a
+-- stretch the server was seen continuously up or down; time no segment covers is unknown,
+-- usually because the node was offline.
+CREATE TABLE IF NOT EXISTS server_uptime_segments (
+    id BIGSERIAL PRIMARY KEY,
+    server_id UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    up BOOLEAN NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    last_seen_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS server_uptime_segments_server_seen_idx
+    ON server_uptime_segments (server_id, last_seen_at DESC);
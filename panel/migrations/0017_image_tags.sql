@@ -0,0 +1,3 @@
+-- Free-form labels on images (e.g. "minecraft", "proxy") for searching and filtering the
+-- image lists once a panel has more images than a runtime dropdown can show
+ALTER TABLE images ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,26 @@
+-- Servers keep pointing at the image they were created from, so an image in use must never
+-- go away. The baseline FK left this to the default (NO ACTION); it is now an explicit
+-- RESTRICT, and a database that predates the baseline gets one at all. Servers that already
+-- lost their image keep the constraint unvalidated instead of failing the migration.
+DO $$
+DECLARE
+    fk RECORD;
+BEGIN
+    FOR fk IN
+        SELECT c.conname FROM pg_constraint c
+        JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = ANY (c.conkey)
+        WHERE c.contype = 'f' AND c.conrelid = 'servers'::regclass
+          AND c.confrelid = 'images'::regclass AND a.attname = 'image_id'
+    LOOP
+        EXECUTE format('ALTER TABLE servers DROP CONSTRAINT %I', fk.conname);
+    END LOOP;
+
+    ALTER TABLE servers ADD CONSTRAINT servers_image_id_fkey
+        FOREIGN KEY (image_id) REFERENCES images(id) ON DELETE RESTRICT NOT VALID;
+
+    IF EXISTS (SELECT 1 FROM servers s WHERE NOT EXISTS (SELECT 1 FROM images i WHERE i.id = s.image_id)) THEN
+        RAISE WARNING 'Some servers reference images that no longer exist; servers_image_id_fkey left unvalidated';
+    ELSE
+        ALTER TABLE servers VALIDATE CONSTRAINT servers_image_id_fkey;
+    END IF;
+END $$;
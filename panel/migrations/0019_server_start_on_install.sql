@@ -0,0 +1,3 @@
+-- The create form's "Start Server When Installed" box; off leaves the container created
+-- but stopped after its install
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS start_on_install BOOLEAN NOT NULL DEFAULT TRUE;
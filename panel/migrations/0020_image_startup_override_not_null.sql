@@ -0,0 +1,5 @@
+-- `Image` reads allow_startup_override as a plain bool, so a NULL left by an old insert made
+-- the image unloadable; NULL always meant the default (overrides allowed)
+UPDATE images SET allow_startup_override = TRUE WHERE allow_startup_override IS NULL;
+ALTER TABLE images ALTER COLUMN allow_startup_override SET DEFAULT TRUE;
+ALTER TABLE images ALTER COLUMN allow_startup_override SET NOT NULL;
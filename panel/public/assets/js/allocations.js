@@ -0,0 +1,171 @@
+(function () {
+    function validatePorts(input) {
+        const feedback = document.getElementById('ports-feedback');
+        const submitBtn = document.getElementById('submit-btn');
+        const raw = input.value;
+        
+        let blocked = false;
+        let warning = false;
+        let errors = [];
+        let warnings = [];
+
+        // Simple regex split by comma
+        const parts = raw.split(',');
+
+        for (let part of parts) {
+            part = part.trim();
+            if(!part) continue;
+
+            if (part.includes('-')) {
+                // Range
+                const range = part.split('-');
+                if(range.length === 2) {
+                    const start = parseInt(range[0]);
+                    const end = parseInt(range[1]);
+
+                    if(!isNaN(start) && !isNaN(end)) {
+                         if (start < 0 || end > 65535 || start > end) {
+                            errors.push(`Invalid range: ${part}`);
+                            blocked = true;
+                         } else {
+                             // Check range logic
+                             for(let p = start; p <= end; p++) {
+                                 if (p <= 1023) {
+                                     blocked = true;
+                                     if(!errors.includes("Contains system ports (0-1023)")) errors.push("Contains system ports (0-1023)");
+                                 } else if (p >= 32768 && p <= 65535) {
+                                     warning = true;
+                                 }
+                             }
+                         }
+                    }
+                }
+            } else {
+                // Single
+                const p = parseInt(part);
+                if (!isNaN(p)) {
+                     if (p < 0 || p > 65535) {
+                        errors.push(`Invalid port: ${p}`);
+                        blocked = true;
+                     } else if (p <= 1023) {
+                        errors.push(`System port blocked: ${p}`);
+                        blocked = true; 
+                     } else if (p >= 32768 && p <= 65535) {
+                        warning = true;
+                        if (!warnings.includes("Contains ephemeral ports")) warnings.push("Contains ephemeral ports (32768-65535)");
+                     }
+                }
+            }
+        }
+
+        if (blocked) {
+            feedback.style.color = 'red';
+            feedback.innerHTML = '❌ ' + errors.join('<br>');
+            submitBtn.disabled = true;
+            submitBtn.style.opacity = '0.5';
+            submitBtn.style.cursor = 'not-allowed';
+        } else if (warning) {
+            feedback.style.color = 'orange';
+            feedback.innerHTML = '⚠️ Warning: ' + warnings.join('<br>') + '<br>Proceed with caution.';
+            submitBtn.disabled = false;
+            submitBtn.style.opacity = '1';
+            submitBtn.style.cursor = 'pointer';
+        } else {
+            feedback.textContent = '';
+            submitBtn.disabled = false;
+            submitBtn.style.opacity = '1';
+            submitBtn.style.cursor = 'pointer';
+        }
+    }
+
+    function toggleDeleteForm() {
+        const form = document.getElementById('bulk-delete-form');
+        form.style.display = form.style.display === 'none' ? 'block' : 'none';
+    }
+
+    // Dry run first so the confirm lists what goes; the token pins the delete to that list
+    async function previewDelete(event) {
+        const form = event.target;
+        if (form.dataset.previewed) return;
+        event.preventDefault();
+        try {
+            const res = await fetch(form.action + '?dry_run=true', {
+                method: 'POST',
+                body: new URLSearchParams(new FormData(form)),
+            });
+            if (!res.ok) {
+                alert('Failed to preview the delete: ' + await res.text());
+                return;
+            }
+            const p = await res.json();
+            if (!p.allocations.length) {
+                alert('None of these ports would be deleted.');
+                return;
+            }
+            let summary = 'Delete ' + p.allocations.length + ' allocation(s)?\n'
+                + p.allocations.map(a => a.ip + ':' + a.port).join(', ');
+            if (p.servers.length) {
+                summary += '\n\nAssigned to ' + p.servers.length + ' server(s), whose containers keep the port until recreated:\n'
+                    + p.servers.map(s => '  - ' + s.name).join('\n');
+            }
+            if (!confirm(summary)) return;
+            form.elements.token.value = p.token;
+            form.dataset.previewed = 'true';
+            form.submit();
+        } catch (e) {
+            alert('Request failed: ' + e);
+        }
+    }
+
+    // Appends ports to the add form's list, skipping ones it already names
+    function addPorts(ports, list) {
+        const current = ports.value.split(',').map(p => p.trim()).filter(Boolean);
+        list.map(String).forEach(p => {
+            if (!current.includes(p)) current.push(p);
+        });
+        ports.value = current.join(', ');
+        validatePorts(ports);
+    }
+
+    // The node probes the range on its host; each free port becomes a button that adds it
+    async function findFreePorts(ports) {
+        const box = document.getElementById('free-ports');
+        const results = document.getElementById('free-results');
+        const useAll = document.getElementById('free-use-all');
+        const start = document.getElementById('free-start').value;
+        const end = document.getElementById('free-end').value || start;
+        results.textContent = 'Checking...';
+        useAll.style.display = 'none';
+        try {
+            const res = await fetch(`${box.dataset.url}?start=${encodeURIComponent(start)}&end=${encodeURIComponent(end)}`);
+            const body = await res.json().catch(() => ({}));
+            if (!res.ok) {
+                results.textContent = 'Could not check: ' + (body.message || body.error || res.status);
+                return;
+            }
+            results.textContent = body.free.length ? '' : 'No free ports in this range.';
+            body.free.forEach(port => {
+                const chip = document.createElement('button');
+                chip.type = 'button';
+                chip.textContent = port;
+                chip.title = 'Add to ports';
+                chip.style.cssText = 'background: #e7f1ff; color: #0056b3; border: none; padding: 1px 8px; border-radius: 12px; cursor: pointer;';
+                chip.addEventListener('click', () => addPorts(ports, [port]));
+                results.appendChild(chip);
+            });
+            useAll.style.display = body.free.length ? '' : 'none';
+            useAll.onclick = () => addPorts(ports, body.free);
+        } catch (e) {
+            results.textContent = 'Request failed: ' + e;
+        }
+    }
+
+    const ports = document.getElementById('ports');
+    if (ports) ports.addEventListener('input', () => validatePorts(ports));
+    const freeFind = document.getElementById('free-find');
+    if (freeFind && ports) freeFind.addEventListener('click', () => findFreePorts(ports));
+    const toggle = document.getElementById('bulk-delete-toggle');
+    if (toggle) toggle.addEventListener('click', toggleDeleteForm);
+    const bulkDelete = document.getElementById('bulk-delete');
+    if (bulkDelete) bulkDelete.addEventListener('submit', previewDelete);
+})();
@@ -0,0 +1,198 @@
+// Shared helpers loaded once by layout.html. Page scripts are re-executed by htmx
+// on every boosted navigation, so anything global or bound to `document` lives here.
+
+function formatBytes(bytes, decimals = 2) {
+    if (!+bytes) return '0 B';
+    const k = 1024;
+    const dm = decimals < 0 ? 0 : decimals;
+    const sizes = ['B', 'KB', 'MB', 'GB', 'TB', 'PB', 'EB', 'ZB', 'YB'];
+    const i = Math.floor(Math.log(bytes) / Math.log(k));
+    return `${parseFloat((bytes / Math.pow(k, i)).toFixed(dm))} ${sizes[i]}`;
+}
+
+function applyByteFormatting() {
+    document.querySelectorAll('[data-format="bytes"]').forEach(el => {
+        if (el.dataset.formatted) return;
+        const raw = el.innerText;
+        const val = parseInt(raw);
+        if (!isNaN(val)) {
+            el.innerText = formatBytes(val);
+            el.dataset.formatted = "true";
+            el.title = raw + " bytes";
+        }
+    });
+}
+
+function toggleMainSidebar() {
+    const sidebar = document.getElementById('main-sidebar');
+    sidebar.style.display = sidebar.style.display === 'none' ? 'flex' : 'none';
+}
+
+function updateConnectionStatus(isError = false) {
+    const banner = document.getElementById('connection-lost-banner');
+    if (!banner) return;
+    if (navigator.onLine && !isError) {
+        banner.style.display = 'none';
+    } else {
+        banner.style.display = 'block';
+    }
+}
+
+// Update active class for preserved sidebar links
+document.addEventListener('htmx:afterSwap', function () {
+    applyByteFormatting();
+    // If a successful swap happens, we are back online
+    updateConnectionStatus(false);
+    const path = window.location.pathname;
+    document.querySelectorAll('#main-sidebar nav a').forEach(a => {
+        const href = a.getAttribute('href');
+        a.classList.remove('active');
+        if (href === '/' && path === '/') a.classList.add('active');
+        else if (href !== '/' && path.startsWith(href)) a.classList.add('active');
+    });
+});
+
+document.addEventListener('htmx:sendError', function () {
+    updateConnectionStatus(true);
+});
+window.addEventListener('online', () => updateConnectionStatus(false));
+window.addEventListener('offline', () => updateConnectionStatus(false));
+
+// Declarative replacements for the old inline handlers:
+//   data-action="toggle-sidebar" | "reload"
+//   data-show="#id" / data-hide="#id"  (display: flex / none)
+document.addEventListener('click', function (evt) {
+    const el = evt.target.closest('[data-action], [data-show], [data-hide]');
+    if (!el) return;
+
+    if (el.dataset.action === 'toggle-sidebar') {
+        toggleMainSidebar();
+    } else if (el.dataset.action === 'reload') {
+        window.location.reload();
+    }
+    if (el.dataset.show) {
+        document.querySelector(el.dataset.show).style.display = 'flex';
+    }
+    if (el.dataset.hide) {
+        document.querySelector(el.dataset.hide).style.display = 'none';
+    }
+});
+
+// Command palette (Ctrl+K / Cmd+K): searches /api/search and jumps to the picked result
+const paletteGroups = { server: 'Servers', node: 'Nodes', user: 'Users' };
+let paletteTimer = null;
+let paletteSeq = 0;
+
+function openPalette() {
+    const palette = document.getElementById('command-palette');
+    if (!palette) return;
+    palette.style.display = 'flex';
+    const input = document.getElementById('command-palette-input');
+    input.value = '';
+    document.getElementById('command-palette-results').innerHTML = '';
+    input.focus();
+}
+
+function closePalette() {
+    const palette = document.getElementById('command-palette');
+    if (palette) palette.style.display = 'none';
+}
+
+function renderPalette(results) {
+    const container = document.getElementById('command-palette-results');
+    container.innerHTML = '';
+    if (!results.length) {
+        container.innerHTML = '<p class="command-palette-empty">No matches</p>';
+        return;
+    }
+
+    Object.keys(paletteGroups).forEach(kind => {
+        const items = results.filter(r => r.kind === kind);
+        if (!items.length) return;
+        const heading = document.createElement('div');
+        heading.className = 'command-palette-group';
+        heading.textContent = paletteGroups[kind];
+        container.appendChild(heading);
+
+        items.forEach(item => {
+            const link = document.createElement('a');
+            link.className = 'command-palette-item';
+            link.href = item.url;
+            const title = document.createElement('span');
+            title.textContent = item.title;
+            const detail = document.createElement('small');
+            detail.textContent = item.detail;
+            link.append(title, detail);
+            container.appendChild(link);
+        });
+    });
+    const first = container.querySelector('.command-palette-item');
+    if (first) first.classList.add('selected');
+}
+
+function searchPalette(q) {
+    const seq = ++paletteSeq;
+    if (!q.trim()) {
+        document.getElementById('command-palette-results').innerHTML = '';
+        return;
+    }
+    fetch('/api/search?q=' + encodeURIComponent(q))
+        .then(response => response.ok ? response.json() : [])
+        .then(results => {
+            // A slower, older request must not overwrite newer results
+            if (seq === paletteSeq) renderPalette(results);
+        });
+}
+
+function movePaletteSelection(step) {
+    const items = Array.from(document.querySelectorAll('#command-palette-results .command-palette-item'));
+    if (!items.length) return;
+    const current = items.findIndex(el => el.classList.contains('selected'));
+    const next = (current + step + items.length) % items.length;
+    items.forEach(el => el.classList.remove('selected'));
+    items[next].classList.add('selected');
+    items[next].scrollIntoView({ block: 'nearest' });
+}
+
+document.addEventListener('keydown', function (evt) {
+    if ((evt.ctrlKey || evt.metaKey) && evt.key.toLowerCase() === 'k') {
+        evt.preventDefault();
+        openPalette();
+        return;
+    }
+
+    const palette = document.getElementById('command-palette');
+    if (!palette || palette.style.display === 'none') return;
+    if (evt.key === 'Escape') {
+        closePalette();
+    } else if (evt.key === 'ArrowDown' || evt.key === 'ArrowUp') {
+        evt.preventDefault();
+        movePaletteSelection(evt.key === 'ArrowDown' ? 1 : -1);
+    } else if (evt.key === 'Enter') {
+        const selected = palette.querySelector('.command-palette-item.selected');
+        if (selected) {
+            evt.preventDefault();
+            closePalette();
+            selected.click();
+        }
+    }
+});
+
+document.addEventListener('input', function (evt) {
+    if (evt.target.id !== 'command-palette-input') return;
+    clearTimeout(paletteTimer);
+    paletteTimer = setTimeout(() => searchPalette(evt.target.value), 200);
+});
+
+document.addEventListener('click', function (evt) {
+    if (evt.target.id === 'command-palette') {
+        closePalette();
+    } else if (evt.target.closest('.command-palette-item')) {
+        closePalette();
+    }
+});
+
+document.addEventListener('DOMContentLoaded', function () {
+    applyByteFormatting();
+    updateConnectionStatus();
+});
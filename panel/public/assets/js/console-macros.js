@@ -0,0 +1,59 @@
+// Console macro buttons for #console-macros. Each macro's {{name}} placeholders are asked
+// for before the command goes to POST /servers/{id}/command.
+(function () {
+    const bar = document.getElementById('console-macros');
+    if (!bar) return;
+    const serverId = bar.dataset.serverId;
+    let macros = [];
+    try {
+        macros = JSON.parse(bar.dataset.macros || '[]');
+    } catch (e) {
+        return;
+    }
+
+    function fill(command) {
+        const names = [...new Set([...command.matchAll(/\{\{\s*([\w.-]+)\s*\}\}/g)].map(m => m[1]))];
+        for (const name of names) {
+            const value = prompt('Value for ' + name + ':');
+            if (value === null) return null;
+            if (/[\r\n]/.test(value)) {
+                alert('Values must be a single line');
+                return null;
+            }
+            command = command.replace(new RegExp('\\{\\{\\s*' + name.replace(/[.-]/g, '\\$&') + '\\s*\\}\\}', 'g'), value);
+        }
+        return command;
+    }
+
+    async function run(macro, button) {
+        const command = fill(macro.command);
+        if (command === null) return;
+        button.disabled = true;
+        try {
+            const res = await fetch('/servers/' + serverId + '/command', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ command: command })
+            });
+            if (!res.ok) {
+                const body = await res.json().catch(() => ({}));
+                alert('Command failed: ' + (body.message || res.status));
+            }
+        } catch (e) {
+            alert('Request failed: ' + e);
+        } finally {
+            button.disabled = false;
+        }
+    }
+
+    for (const macro of macros) {
+        const button = document.createElement('button');
+        button.type = 'button';
+        button.className = 'btn btn-secondary';
+        button.style.fontSize = '0.85rem';
+        button.textContent = macro.label;
+        button.title = macro.command;
+        button.addEventListener('click', () => run(macro, button));
+        bar.appendChild(button);
+    }
+})();
@@ -0,0 +1,163 @@
+// Docker image rows, Monaco editors and validation shared by the image create/edit forms.
+(function () {
+    const form = document.querySelector('form[data-image-form]');
+    if (!form) return;
+
+    // Wait for the Monaco loader to be available
+    function waitForRequire(callback) {
+        if (typeof require !== 'undefined') {
+            callback();
+        } else {
+            setTimeout(() => waitForRequire(callback), 100);
+        }
+    }
+
+    waitForRequire(function () {
+        require.config({ paths: { 'vs': 'https://cdnjs.cloudflare.com/ajax/libs/monaco-editor/0.45.0/min/vs' } });
+
+        // Store editors globally to access later
+        window.monacoEditors = {};
+
+        require(['vs/editor/editor.main'], function () {
+            function initEditor(id, lang, textareaId) {
+                const container = document.getElementById(id);
+                const textarea = document.getElementById(textareaId);
+
+                if (!container || !textarea) return;
+
+                try {
+                    window.monacoEditors[textareaId] = monaco.editor.create(container, {
+                        value: textarea.value,
+                        language: lang,
+                        theme: 'vs',
+                        automaticLayout: true,
+                        minimap: { enabled: false }
+                    });
+
+                    // Update textarea on change (fallback)
+                    window.monacoEditors[textareaId].onDidChangeModelContent(() => {
+                        textarea.value = window.monacoEditors[textareaId].getValue();
+                    });
+                } catch (e) {
+                    console.error("Monaco init error for " + id + ": ", e);
+                }
+            }
+
+            initEditor('monaco_log_config', 'json', 'log_config');
+            initEditor('monaco_config_files', 'json', 'config_files');
+            initEditor('monaco_start_config', 'json', 'start_config');
+            initEditor('monaco_install_script', 'shell', 'install_script');
+            initEditor('monaco_variables', 'json', 'variables');
+            initEditor('monaco_console_macros', 'json', 'console_macros');
+        });
+    });
+
+    // Docker Image Management
+    function addDockerImageRow(name = '', url = '') {
+        const container = document.getElementById('docker_images_container');
+        if (!container) return;
+        const div = document.createElement('div');
+        div.className = 'docker-image-row';
+        div.style.display = 'flex';
+        div.style.gap = '0.5rem';
+        div.style.marginBottom = '0.5rem';
+
+        const nameInput = document.createElement('input');
+        nameInput.type = 'text';
+        nameInput.className = 'img-name';
+        nameInput.placeholder = 'Display Name (e.g. Minecraft 1.20, Rust)';
+        nameInput.value = name;
+        nameInput.required = true;
+        nameInput.style.flex = '1';
+
+        const urlInput = document.createElement('input');
+        urlInput.type = 'text';
+        urlInput.className = 'img-url';
+        urlInput.placeholder = 'Docker Image (e.g. ghcr.io/pterodactyl/yolks:java_17)';
+        urlInput.value = url;
+        urlInput.required = true;
+        urlInput.style.flex = '2';
+
+        const remove = document.createElement('button');
+        remove.type = 'button';
+        remove.className = 'btn btn-danger';
+        remove.style.padding = '0.5rem';
+        remove.textContent = '×';
+        remove.addEventListener('click', () => div.remove());
+
+        div.append(nameInput, urlInput, remove);
+        container.appendChild(div);
+    }
+
+    // Add novalidate via JS for custom validation (Progressive Enhancement)
+    // If JS fails, browser validation will still work.
+    form.setAttribute('novalidate', 'true');
+
+    // Seed rows from the stored value (empty on the create form)
+    const dockerImages = document.getElementById('docker_images');
+    try {
+        const existingImages = JSON.parse(dockerImages.value || '{}');
+        if (typeof existingImages === 'object' && existingImages !== null) {
+            for (const [name, url] of Object.entries(existingImages)) {
+                addDockerImageRow(name, url);
+            }
+        } else {
+            addDockerImageRow('Default', dockerImages.value);
+        }
+        if (Object.keys(existingImages).length === 0) addDockerImageRow();
+    } catch (e) {
+        const raw = dockerImages.value;
+        if (raw && raw.trim()) {
+            raw.split('\n').filter(line => line.trim()).forEach(line => {
+                addDockerImageRow('Default', line.trim());
+            });
+        } else {
+            addDockerImageRow();
+        }
+    }
+
+    document.getElementById('add-docker-image').addEventListener('click', () => addDockerImageRow());
+
+    // Capture phase so an invalid form never reaches htmx's boosted submit handler
+    form.addEventListener('submit', function (event) {
+        // Sync Docker Images to JSON
+        const imageRows = form.querySelectorAll('.docker-image-row');
+        let hasImage = false;
+        const imagesObj = {};
+        imageRows.forEach(row => {
+            const name = row.querySelector('.img-name').value;
+            const url = row.querySelector('.img-url').value;
+            if (name && url) {
+                imagesObj[name] = url;
+                hasImage = true;
+            }
+        });
+        dockerImages.value = JSON.stringify(imagesObj);
+
+        // Custom validation for Docker Images
+        const dockerError = document.getElementById('docker_images_error');
+        if (!hasImage) {
+            if (dockerError) dockerError.style.display = 'block';
+            form.classList.add('was-validated');
+            event.preventDefault();
+            event.stopImmediatePropagation();
+            return;
+        } else if (dockerError) {
+            dockerError.style.display = 'none';
+        }
+
+        // Ensure all Monaco content is synced to textareas before submit
+        if (window.monacoEditors) {
+            for (const [id, editor] of Object.entries(window.monacoEditors)) {
+                const el = document.getElementById(id);
+                if (el) el.value = editor.getValue();
+            }
+        }
+
+        if (!form.checkValidity()) {
+            event.preventDefault();
+            event.stopImmediatePropagation();
+        }
+        form.classList.add('was-validated');
+    }, true);
+})();
@@ -0,0 +1,64 @@
+(function () {
+    const panel = document.getElementById('install-log');
+    if (!panel) return;
+
+    const status = document.getElementById('install-log-status');
+    const output = document.getElementById('install-log-output');
+    // Queued and installing servers may not have started their script yet
+    const waiting = panel.dataset.waiting === 'true';
+    let source = null;
+
+    function finish(text, color) {
+        if (source) {
+            source.close();
+            source = null;
+        }
+        status.textContent = text;
+        status.style.color = color;
+    }
+
+    function connect() {
+        output.textContent = '';
+        source = new EventSource(panel.dataset.url);
+
+        source.addEventListener('output', function (e) {
+            panel.style.display = '';
+            status.textContent = 'Running...';
+            status.style.color = '#666';
+            const stick = output.scrollTop + output.clientHeight >= output.scrollHeight - 5;
+            output.textContent += JSON.parse(e.data).data;
+            if (stick) output.scrollTop = output.scrollHeight;
+        });
+
+        source.addEventListener('exit', function (e) {
+            const ev = JSON.parse(e.data);
+            panel.style.display = '';
+            if (ev.timed_out) {
+                finish('Killed after timeout', '#dc3545');
+            } else if (ev.code === 0) {
+                finish('Finished; creating the container...', '#28a745');
+            } else {
+                finish('Failed with exit code ' + ev.code, '#dc3545');
+            }
+        });
+
+        // No install output yet (or any more); check again while the server waits for one
+        source.addEventListener('idle', function () {
+            finish('', '#666');
+            if (waiting) setTimeout(connect, 3000);
+        });
+
+        // Named "error" events carry a message; bare connection errors do not. Closing
+        // either way keeps EventSource from reconnecting and replaying the log twice.
+        source.addEventListener('error', function (e) {
+            if (e.data) {
+                panel.style.display = '';
+                finish('Error: ' + JSON.parse(e.data).message, '#dc3545');
+            } else if (source) {
+                finish('Connection lost', '#dc3545');
+            }
+        });
+    }
+
+    connect();
+})();
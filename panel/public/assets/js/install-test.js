@@ -0,0 +1,66 @@
+(function () {
+    const panel = document.getElementById('install-test');
+    if (!panel) return;
+
+    const nodeSelect = document.getElementById('install-test-node');
+    const runBtn = document.getElementById('install-test-run');
+    const stopBtn = document.getElementById('install-test-stop');
+    const status = document.getElementById('install-test-status');
+    const output = document.getElementById('install-test-output');
+    let source = null;
+
+    function finish(text, color) {
+        if (source) {
+            source.close();
+            source = null;
+        }
+        status.textContent = text;
+        status.style.color = color;
+        runBtn.disabled = false;
+        stopBtn.style.display = 'none';
+    }
+
+    function run() {
+        if (source || !nodeSelect.value) return;
+
+        output.textContent = '';
+        output.style.display = 'block';
+        status.textContent = 'Running...';
+        status.style.color = '#666';
+        runBtn.disabled = true;
+        stopBtn.style.display = '';
+
+        source = new EventSource(panel.dataset.url + '?node_id=' + encodeURIComponent(nodeSelect.value));
+
+        source.addEventListener('output', function (e) {
+            const stick = output.scrollTop + output.clientHeight >= output.scrollHeight - 5;
+            output.textContent += JSON.parse(e.data).data;
+            if (stick) output.scrollTop = output.scrollHeight;
+        });
+
+        source.addEventListener('exit', function (e) {
+            const ev = JSON.parse(e.data);
+            if (ev.timed_out) {
+                finish('Killed after timeout', '#dc3545');
+            } else if (ev.code === 0) {
+                finish('Finished with exit code 0', '#28a745');
+            } else {
+                finish('Failed with exit code ' + ev.code, '#dc3545');
+            }
+        });
+
+        // Named "error" events carry a message; bare connection errors do not
+        source.addEventListener('error', function (e) {
+            if (e.data) {
+                finish('Error: ' + JSON.parse(e.data).message, '#dc3545');
+            } else if (source) {
+                finish('Connection lost', '#dc3545');
+            }
+        });
+    }
+
+    runBtn.addEventListener('click', run);
+    stopBtn.addEventListener('click', function () {
+        finish('Stopped', '#666');
+    });
+})();
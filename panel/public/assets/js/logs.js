@@ -0,0 +1,33 @@
+(function () {
+    const search = document.getElementById('log-search');
+    const level = document.getElementById('log-level');
+    if (!search || !level) return;
+
+    function filterLogs() {
+        const query = search.value.toLowerCase();
+        const levelClass = level.value;
+        const entries = document.querySelectorAll('#log-container .log-entry');
+
+        entries.forEach(entry => {
+            const text = entry.textContent.toLowerCase();
+            const hasLevel = levelClass === "" || entry.classList.contains(levelClass);
+            const hasText = query === "" || text.includes(query);
+
+            if (hasLevel && hasText) {
+                entry.style.display = "";
+            } else {
+                entry.style.display = "none";
+            }
+        });
+    }
+
+    function clearFilters() {
+        search.value = '';
+        level.value = '';
+        filterLogs();
+    }
+
+    search.addEventListener('input', filterLogs);
+    level.addEventListener('change', filterLogs);
+    document.getElementById('log-clear').addEventListener('click', clearFilters);
+})();
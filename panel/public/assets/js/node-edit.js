@@ -0,0 +1,66 @@
+(function () {
+    const actions = document.getElementById('node-actions');
+    if (!actions) return;
+    const id = actions.dataset.nodeId;
+
+    async function rotateToken() {
+        if (confirm('Rotate token for this node? This will update the node configuration immediately.')) {
+            const res = await fetch('/nodes/' + id + '/rotate-token', { method: 'POST' });
+            if (res.ok) {
+                alert('Token rotated successfully! Node needs restart.');
+                window.location.reload();
+            } else {
+                alert('Failed to rotate token: ' + await res.text());
+            }
+        }
+    }
+
+    async function updateNode() {
+        if (!confirm('Update this node agent? Expect short downtime.')) return;
+        try {
+            let res = await fetch('/nodes/' + id + '/trigger-update', { method: 'POST' });
+            let text = await res.text();
+            // 423: the node is busy; offer to interrupt what it is doing
+            if (res.status === 423) {
+                if (!confirm(text + '\n\nUpdate anyway and interrupt them?')) return;
+                res = await fetch('/nodes/' + id + '/trigger-update?force=true', { method: 'POST' });
+                text = await res.text();
+            }
+            alert(text);
+            if (res.ok) window.location.reload();
+        } catch (e) {
+            alert('Request failed: ' + e);
+        }
+    }
+
+    // Dry run first so the confirm lists what goes; the token pins the delete to that list
+    async function deleteNode() {
+        try {
+            const preview = await fetch('/nodes/' + id + '?dry_run=true', { method: 'DELETE' });
+            if (!preview.ok) {
+                alert('Failed to preview the delete: ' + await preview.text());
+                return;
+            }
+            const p = await preview.json();
+            let summary = 'Delete this node? This action cannot be undone.\n\n'
+                + p.allocations.length + ' allocation(s) freed.\n'
+                + p.servers.length + ' server(s) deleted, their containers removed';
+            summary += p.servers.length ? ':\n' + p.servers.map(s => '  - ' + s.name).join('\n') : '.';
+            if (!confirm(summary)) return;
+
+            const res = await fetch('/nodes/' + id + '?token=' + encodeURIComponent(p.token), { method: 'DELETE' });
+            if (!res.ok) {
+                alert(await res.text());
+                return;
+            }
+            window.location.href = res.headers.get('HX-Redirect') || '/nodes';
+        } catch (e) {
+            alert('Request failed: ' + e);
+        }
+    }
+
+    // Missing for read-only (auditor) accounts
+    document.getElementById('node-delete')?.addEventListener('click', deleteNode);
+    document.getElementById('node-rotate-token')?.addEventListener('click', rotateToken);
+    document.getElementById('node-update-agent')?.addEventListener('click', updateNode);
+})();
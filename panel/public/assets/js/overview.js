@@ -0,0 +1,91 @@
+(function () {
+    const tabs = document.getElementById('overviewTabs');
+    if (!tabs) return;
+
+    function openTab(link, tabName) {
+        // Hide all tab content
+        document.querySelectorAll('.tab-content').forEach(el => {
+            el.style.display = "none";
+        });
+
+        // Remove active styling from all tab links
+        tabs.querySelectorAll('.nav-link').forEach(el => {
+            el.style.backgroundColor = "";
+            el.style.color = "#007bff";
+            el.style.border = "1px solid transparent";
+        });
+
+        // Show the specific tab content
+        document.getElementById(tabName).style.display = "block";
+
+        // Add active styling to the link that opened the tab
+        link.style.backgroundColor = "white";
+        link.style.color = "#495057";
+        link.style.border = "1px solid #dee2e6";
+        link.style.borderBottomColor = "transparent";
+    }
+
+    tabs.querySelectorAll('.nav-link[data-tab]').forEach(link => {
+        link.addEventListener('click', function (evt) {
+            evt.preventDefault();
+            openTab(link, link.dataset.tab);
+        });
+    });
+
+    const fontUrl = document.getElementById('panel_font_url-input');
+    if (fontUrl) {
+        fontUrl.addEventListener('input', function (e) {
+            const val = e.target.value;
+            const warning = document.getElementById('font-warning');
+            if (val && !val.includes('cyrillic')) {
+                warning.style.display = 'block';
+            } else {
+                warning.style.display = 'none';
+            }
+        });
+    }
+
+    applyByteFormatting();
+
+    // Live stats: pushed over a WebSocket, polled every 5s if the socket can't be used
+    const container = document.getElementById('stats-container');
+    if (!container) return;
+
+    let pollTimer = null;
+
+    function detached() {
+        return !document.body.contains(container);
+    }
+
+    function startPolling() {
+        if (pollTimer) return;
+        pollTimer = setInterval(function () {
+            if (detached()) {
+                clearInterval(pollTimer);
+                return;
+            }
+            htmx.ajax('GET', container.dataset.poll, { target: container, swap: 'innerHTML' });
+        }, 5000);
+    }
+
+    if (!('WebSocket' in window)) {
+        startPolling();
+        return;
+    }
+
+    const scheme = location.protocol === 'https:' ? 'wss://' : 'ws://';
+    const socket = new WebSocket(scheme + location.host + container.dataset.ws);
+
+    socket.addEventListener('message', function (evt) {
+        if (detached()) {
+            socket.close();
+            return;
+        }
+        container.innerHTML = evt.data;
+        applyByteFormatting();
+    });
+
+    socket.addEventListener('close', function () {
+        if (!detached()) startPolling();
+    });
+})();
@@ -0,0 +1,65 @@
+(function () {
+    const list = document.getElementById('runtime-list');
+    if (!list) return;
+
+    // Runtimes without images stay expanded
+    list.querySelectorAll('details[data-locked]').forEach(el => {
+        el.addEventListener('click', evt => evt.preventDefault());
+    });
+    // Dragging must not toggle the <details>
+    list.querySelectorAll('.drag-handle').forEach(el => {
+        el.addEventListener('click', evt => evt.preventDefault());
+    });
+    // Action buttons live inside <summary>; keep clicks from toggling it
+    list.querySelectorAll('.runtime-actions').forEach(el => {
+        el.addEventListener('click', evt => {
+            evt.preventDefault();
+            evt.stopPropagation();
+        });
+    });
+
+    // Tag filter: hide images without the tag and runtimes left with none, open the rest
+    const tagFilter = document.getElementById('image-tag-filter');
+    if (tagFilter) {
+        tagFilter.addEventListener('change', () => {
+            const tag = tagFilter.value;
+            list.querySelectorAll('.runtime-item').forEach(item => {
+                let shown = 0;
+                item.querySelectorAll('.image-row').forEach(row => {
+                    const match = !tag || row.dataset.tags.split(',').includes(tag);
+                    row.style.display = match ? '' : 'none';
+                    if (match) shown++;
+                });
+                item.style.display = tag && !shown ? 'none' : '';
+                if (tag && shown) item.querySelector('details').open = true;
+            });
+        });
+    }
+
+    if (typeof Sortable === 'undefined') return;
+
+    new Sortable(list, {
+        handle: '.drag-handle',
+        animation: 150,
+        ghostClass: 'runtime-ghost',
+        onEnd: function () {
+            // Get all IDs in new order
+            const ids = Array.from(document.querySelectorAll('.runtime-item')).map(el => el.getAttribute('data-id'));
+
+            // Send to backend
+            fetch('/runtimes/reorder', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({ ids: ids }),
+            }).then(response => {
+                if (response.ok) {
+                    console.log('Order updated');
+                } else {
+                    console.error('Failed to update order');
+                }
+            });
+        }
+    });
+})();
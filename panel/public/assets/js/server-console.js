@@ -0,0 +1,62 @@
+// xterm console for #terminal-container. Connection details come from its
+// data-uuid / data-node-host / data-node-port attributes.
+(function () {
+    const container = document.getElementById('terminal-container');
+    if (!container || typeof Terminal === 'undefined') return;
+
+    const term = new Terminal({
+        cursorBlink: true,
+        theme: {
+            background: '#1e1e1e',
+            foreground: '#f8f8f2'
+        }
+    });
+
+    // Clear placeholder text
+    container.innerHTML = '';
+    term.open(container);
+    term.fit();
+
+    // Dynamic resize support
+    window.addEventListener('resize', function () {
+        term.fit();
+    });
+
+    const uuid = container.dataset.uuid;
+    const nodeUrl = "ws://" + container.dataset.nodeHost + ":" + container.dataset.nodePort;
+    const wsUrl = `${nodeUrl}/containers/${uuid}/console`;
+
+    term.writeln('> Connecting to server...');
+
+    let socket;
+
+    function connect() {
+        socket = new WebSocket(wsUrl);
+
+        socket.onopen = function () {
+            term.writeln('> Connected!');
+            term.write('\r\n');
+        };
+
+        socket.onmessage = function (msg) {
+            term.write(msg.data);
+        };
+
+        socket.onerror = function () {
+            term.writeln('\r\n> Connection Error');
+        };
+
+        socket.onclose = function () {
+            term.writeln('\r\n> Connection Closed. Reconnecting in 5s...');
+            setTimeout(connect, 5000);
+        };
+    }
+
+    term.on('data', function (data) {
+        if (socket && socket.readyState === WebSocket.OPEN) {
+            socket.send(data);
+        }
+    });
+
+    connect();
+})();
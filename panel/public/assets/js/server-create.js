@@ -0,0 +1,508 @@
+(function () {
+    if (!document.getElementById('images-data')) return;
+
+    // Embed data from backend
+    // We read from the hidden script tags to avoid template syntax errors in JS linters
+    const imagesData = JSON.parse(document.getElementById('images-data').textContent);
+    const allocationsData = JSON.parse(document.getElementById('allocations-data').textContent);
+    const freeCounts = JSON.parse(document.getElementById('free-counts-data').textContent);
+
+    // Nodes with no free port (and no auto-allocation range) can't host a port-requiring image.
+    // Nodes outside the picked location are hidden altogether.
+    function updateNodeAvailability(requiresPort) {
+        const nodeSelect = document.getElementById('node_id');
+        const warning = document.getElementById('node_port_warning');
+        const location = document.getElementById('location_id').value;
+        const full = [];
+
+        Array.from(nodeSelect.options).forEach(opt => {
+            if (!opt.value) return;
+            const outside = Boolean(location) && opt.dataset.location !== location;
+            opt.hidden = outside;
+            const free = freeCounts[opt.value] || 0;
+            const blocked = requiresPort && free === 0 && opt.dataset.autoRange !== 'true';
+            opt.disabled = blocked || outside;
+            if (outside) return;
+            opt.textContent = blocked ? `${opt.dataset.name} (no free ports)` : opt.dataset.name;
+            if (blocked) full.push(opt.dataset.name);
+        });
+
+        if (nodeSelect.selectedOptions[0] && nodeSelect.selectedOptions[0].disabled) {
+            nodeSelect.value = '';
+        }
+
+        warning.textContent = full.length
+            ? `This image needs a port. No free allocations on: ${full.join(', ')}.`
+            : '';
+        warning.style.display = full.length ? 'block' : 'none';
+    }
+
+    const runtimeSelect = document.getElementById('runtime_id');
+    const runtimeNames = {};
+    Array.from(runtimeSelect.options).forEach(opt => {
+        if (opt.value) runtimeNames[opt.value] = opt.textContent;
+    });
+    const runtimeOrder = Object.keys(runtimeNames);
+    const allImages = runtimeOrder.flatMap(id => imagesData[id] || []);
+
+    // Tag filter, only offered once some image has tags
+    const allTags = [...new Set(allImages.flatMap(img => img.tags || []))].sort();
+    const tagSelect = document.getElementById('image_tag');
+    allTags.forEach(tag => tagSelect.appendChild(new Option(tag, tag)));
+    if (!allTags.length) {
+        document.getElementById('image_tag_group').style.display = 'none';
+        document.querySelector('#image_group option[value="tag"]').remove();
+    }
+
+    // Searching, filtering by tag or grouping other than by nest picks from every nest;
+    // otherwise the nest select narrows the list as it always has
+    function browsingAll() {
+        return Boolean(document.getElementById('image_search').value.trim())
+            || Boolean(tagSelect.value)
+            || document.getElementById('image_group').value !== 'runtime';
+    }
+
+    function matchesFilters(img) {
+        const tag = tagSelect.value;
+        if (tag && !(img.tags || []).includes(tag)) return false;
+        const query = document.getElementById('image_search').value.trim().toLowerCase();
+        if (!query) return true;
+        return [img.name, img.description || '', runtimeNames[img.runtime_id] || '', ...(img.tags || [])]
+            .some(text => text.toLowerCase().includes(query));
+    }
+
+    // [label, images] pairs; a null label means no <optgroup>
+    function groupImages(images, all) {
+        const group = document.getElementById('image_group').value;
+        if (!all || group === 'none') return [[null, images]];
+        if (group === 'tag') {
+            const groups = allTags.map(tag => [tag, images.filter(img => (img.tags || []).includes(tag))]);
+            groups.push(['Untagged', images.filter(img => !(img.tags || []).length)]);
+            return groups.filter(([, imgs]) => imgs.length);
+        }
+        return runtimeOrder
+            .map(id => [runtimeNames[id], images.filter(img => img.runtime_id === id)])
+            .filter(([, imgs]) => imgs.length);
+    }
+
+    function imageOption(img) {
+        const opt = document.createElement('option');
+        opt.value = img.id;
+        opt.textContent = img.name;
+        // Store extra data
+        opt.dataset.runtime = img.runtime_id;
+        opt.dataset.docker = img.docker_images;
+        opt.dataset.startup = img.startup_command;
+        opt.dataset.variables = img.variables; // Pass variables JSON
+        opt.dataset.description = img.description || '';
+        opt.dataset.requiresPort = img.requires_port; // Boolean
+        opt.dataset.allowStartupOverride = img.allow_startup_override; // Boolean
+        return opt;
+    }
+
+    function updateImages() {
+        const runtimeId = runtimeSelect.value;
+        const imageSelect = document.getElementById('image_id');
+        const previous = imageSelect.value;
+        const all = browsingAll();
+
+        const candidates = all ? allImages : (imagesData[runtimeId] || []);
+        const direction = document.getElementById('image_sort').value === 'name_desc' ? -1 : 1;
+        const matches = candidates
+            .filter(matchesFilters)
+            .sort((a, b) => direction * a.name.localeCompare(b.name));
+
+        let placeholder = 'Select an Egg...';
+        if (!all && !runtimeId) placeholder = 'Select a Nest first...';
+        else if (!matches.length) placeholder = all ? 'No matching Eggs' : 'No Eggs in this Nest';
+        imageSelect.innerHTML = '';
+        imageSelect.appendChild(new Option(placeholder, '', true, true));
+        imageSelect.options[0].disabled = true;
+
+        groupImages(matches, all).forEach(([label, imgs]) => {
+            let parent = imageSelect;
+            if (label !== null) {
+                parent = document.createElement('optgroup');
+                parent.label = label;
+                imageSelect.appendChild(parent);
+            }
+            imgs.forEach(img => parent.appendChild(imageOption(img)));
+        });
+        imageSelect.disabled = !matches.length;
+
+        // Narrowing the list keeps the picked Egg (and its filled-in fields) if it still matches
+        if (previous && matches.some(img => img.id === previous)) {
+            imageSelect.value = previous;
+            return;
+        }
+
+        // Reset UI elements
+        document.getElementById('image_description_help').textContent = '';
+        document.getElementById('service_variables_container').innerHTML = '<p style="color: #666; font-style: italic;">Select an Egg to view service variables.</p>';
+        document.getElementById('docker_image').innerHTML = ''; // Clear docker images too
+    }
+
+    function updateDockerInfo() {
+        const imageSelect = document.getElementById('image_id');
+        const selectedOpt = imageSelect.options[imageSelect.selectedIndex];
+
+        if (!selectedOpt) return;
+        // Picked from a search across nests: the nest select follows the Egg
+        if (selectedOpt.dataset.runtime) runtimeSelect.value = selectedOpt.dataset.runtime;
+
+        const dockerImagesRaw = selectedOpt.dataset.docker || '';
+        const startupCmd = selectedOpt.dataset.startup || '';
+        const variablesRaw = selectedOpt.dataset.variables || '[]';
+        const description = selectedOpt.dataset.description || '';
+        const requiresPort = selectedOpt.dataset.requiresPort === 'true';
+
+        // Update Allocations Label
+        updateAllocations();
+        reserveAllocation();
+
+        // Show description
+        document.getElementById('image_description_help').textContent = description;
+
+        // Populate Docker Image Select
+        const dockerSelect = document.getElementById('docker_image');
+        dockerSelect.innerHTML = '';
+
+        try {
+            // Try parsing as JSON first (Pterodactyl often stores as {"Display Name": "image:tag"})
+            const dockerImagesJson = JSON.parse(dockerImagesRaw);
+            // If it's an object (map), iterate keys
+            if (typeof dockerImagesJson === 'object' && dockerImagesJson !== null && !Array.isArray(dockerImagesJson)) {
+                Object.entries(dockerImagesJson).forEach(([key, value]) => {
+                    const opt = document.createElement('option');
+                    opt.value = value; // The actual image
+                    opt.textContent = key; // The display name
+                    dockerSelect.appendChild(opt);
+                });
+            } else if (Array.isArray(dockerImagesJson)) {
+                // If it's an array, just use values
+                dockerImagesJson.forEach(img => {
+                    const opt = document.createElement('option');
+                    opt.value = img;
+                    opt.textContent = img;
+                    dockerSelect.appendChild(opt);
+                });
+            } else {
+                throw new Error("Not a JSON object/array");
+            }
+        } catch (e) {
+            // Fallback: Split by newline or common separators
+            const dockerImages = dockerImagesRaw.split(/[\r\n,]+/).map(s => s.trim()).filter(s => s);
+            dockerImages.forEach(img => {
+                const opt = document.createElement('option');
+                opt.value = img;
+                opt.textContent = img;
+                dockerSelect.appendChild(opt);
+            });
+        }
+
+        // Set Startup Command (read-only when the image locks it)
+        const startupInput = document.getElementById('startup_command');
+        startupInput.value = startupCmd;
+        startupInput.readOnly = selectedOpt.dataset.allowStartupOverride === 'false';
+
+        // Render Service Variables
+        renderVariables(variablesRaw);
+    }
+
+    // Egg rules in Laravel's "required|string|in:a,b" format
+    function ruleList(v) {
+        return (v.rules || '').split('|').map(r => r.trim()).filter(r => r);
+    }
+
+    function ruleArg(rules, name) {
+        const rule = rules.find(r => r.startsWith(name + ':'));
+        return rule === undefined ? null : rule.slice(name.length + 1);
+    }
+
+    // Same mapping as Variable::input on the server, which checks what these submit
+    function createVariableInput(v) {
+        const rules = ruleList(v);
+        const value = v.default_value || '';
+
+        if (v.field_type === 'boolean') {
+            const input = document.createElement('input');
+            input.type = 'checkbox';
+            input.value = '1';
+            input.checked = ['1', 'true', 'yes', 'on'].includes(value.toLowerCase());
+            return input;
+        }
+
+        const options = ruleArg(rules, 'in');
+        if (options !== null) {
+            const select = document.createElement('select');
+            const choices = options.split(',').map(o => o.trim());
+            if (!rules.includes('required')) choices.unshift('');
+            choices.forEach(choice => {
+                const opt = document.createElement('option');
+                opt.value = choice;
+                opt.textContent = choice;
+                select.appendChild(opt);
+            });
+            select.value = value;
+            return select;
+        }
+
+        const input = document.createElement('input');
+        input.type = 'text';
+        const integer = rules.includes('integer');
+        if (integer || rules.includes('numeric')) {
+            input.type = 'number';
+            input.step = integer ? '1' : 'any';
+            const min = ruleArg(rules, 'min');
+            const max = ruleArg(rules, 'max');
+            const between = (ruleArg(rules, 'between') || '').split(',');
+            if (between.length === 2) {
+                input.min = between[0].trim();
+                input.max = between[1].trim();
+            } else {
+                if (min !== null) input.min = min.trim();
+                if (max !== null) input.max = max.trim();
+            }
+        }
+        input.value = value;
+        return input;
+    }
+
+    function renderVariables(variablesJsonStr) {
+        const container = document.getElementById('service_variables_container');
+        if (!container) return;
+
+        container.innerHTML = ''; // Clear current
+
+        let variables = [];
+        try {
+            variables = JSON.parse(variablesJsonStr);
+        } catch (e) {
+            console.error("Failed to parse variables", e);
+            return;
+        }
+
+        if (!variables || variables.length === 0) {
+            container.innerHTML = '<p style="color: #666; font-style: italic;">No additional configuration required for this Egg.</p>';
+            return;
+        }
+
+        variables.forEach(v => {
+            const formGroup = document.createElement('div');
+            formGroup.className = 'form-group';
+            formGroup.style.marginBottom = '1.5rem';
+
+            const label = document.createElement('label');
+            label.htmlFor = 'var_' + v.env_variable;
+            label.textContent = v.name;
+            label.style.display = 'block';
+            label.style.marginBottom = '0.5rem';
+            label.style.fontWeight = '500';
+
+            const desc = document.createElement('small');
+            desc.style.display = 'block';
+            desc.style.color = '#666';
+            desc.style.marginBottom = '0.5rem';
+            desc.textContent = v.description;
+
+            const input = createVariableInput(v);
+            input.id = 'var_' + v.env_variable;
+            input.name = `environment[${v.env_variable}]`;
+
+            // Secrets are stored encrypted and never echoed back; empty means "use the default"
+            if (v.is_secret && input.tagName === 'INPUT' && input.type !== 'checkbox') {
+                input.type = 'password';
+                input.value = '';
+                input.autocomplete = 'new-password';
+                input.placeholder = v.default_value ? 'Leave empty to use the default' : '';
+            }
+
+            if (input.type === 'checkbox') {
+                input.style.width = 'auto';
+                if (v.user_editable) {
+                    // Unchecked boxes aren't submitted; the hidden field before it sends "0" instead
+                    const off = document.createElement('input');
+                    off.type = 'hidden';
+                    off.name = input.name;
+                    off.value = '0';
+                    formGroup.appendChild(off);
+                } else {
+                    // Not submitted at all; the image default applies
+                    input.disabled = true;
+                }
+            } else {
+                input.className = 'form-control'; // Assuming class exists or styles applied globally
+                input.style.width = '100%';
+                input.style.padding = '0.75rem';
+                input.style.border = '1px solid #ddd';
+                input.style.borderRadius = '4px';
+
+                if (!v.user_editable) {
+                    input.style.backgroundColor = '#f9f9f9';
+                    if (input.tagName === 'SELECT') {
+                        input.disabled = true;
+                    } else {
+                        input.readOnly = true;
+                    }
+                }
+
+                if (ruleList(v).includes('required')) {
+                    input.required = true;
+                    label.innerHTML += ' <span style="color: red">*</span>';
+                }
+            }
+
+            formGroup.appendChild(label);
+            formGroup.appendChild(desc);
+            formGroup.appendChild(input);
+            container.appendChild(formGroup);
+        });
+    }
+
+    function updateAllocations() {
+        const allocSelect = document.getElementById('default_allocation');
+
+        // Check if current image requires port
+        const imageSelect = document.getElementById('image_id');
+        const selectedOpt = imageSelect.options[imageSelect.selectedIndex];
+        // If no image selected, default to true (Auto-Assign)
+        const imageChosen = Boolean(selectedOpt && selectedOpt.value);
+        const requiresPort = imageChosen ? (selectedOpt.dataset.requiresPort === 'true') : true;
+        updateNodeAvailability(imageChosen && requiresPort);
+        const nodeId = document.getElementById('node_id').value;
+
+        // Portless (task) images get no allocation picker at all
+        document.getElementById('allocation_fields').style.display = requiresPort ? '' : 'none';
+        document.getElementById('no_network_note').style.display = requiresPort ? 'none' : '';
+        if (!requiresPort) {
+            document.getElementById('additional_ports').value = '';
+        }
+
+        allocSelect.innerHTML = '';
+
+        const defaultOpt = document.createElement('option');
+        defaultOpt.value = "";
+        defaultOpt.textContent = "Auto-Assign Port";
+        allocSelect.appendChild(defaultOpt);
+        updateReservedWarning();
+
+        if (!requiresPort) return;
+
+        if (nodeId && allocationsData[nodeId]) {
+            allocationsData[nodeId].forEach(alloc => {
+                // Only show free ports
+                if (!alloc.server_id) {
+                    const opt = document.createElement('option');
+                    opt.value = alloc.id;
+                    opt.textContent = `${alloc.ip}:${alloc.port}`;
+                    if (alloc.reserved) {
+                        opt.textContent += alloc.notes ? ` (reserved: ${alloc.notes})` : ' (reserved)';
+                        opt.dataset.reserved = 'true';
+                        opt.dataset.notes = alloc.notes || '';
+                    } else if (alloc.notes) {
+                        opt.title = alloc.notes;
+                    }
+                    allocSelect.appendChild(opt);
+                }
+            });
+        }
+    }
+
+    // Hold the picked port while this form is open, so another create can't take it first
+    let heldAllocation = '';
+    function reserveAllocation() {
+        const allocSelect = document.getElementById('default_allocation');
+        const warning = document.getElementById('allocation_reserved_warning');
+        if (allocSelect.value === heldAllocation) return;
+        heldAllocation = allocSelect.value;
+        const body = new URLSearchParams({
+            reservation_token: document.getElementById('reservation_token').value,
+            allocation_id: allocSelect.value,
+        });
+
+        fetch('/servers/new/reserve-allocation', { method: 'POST', body: body }).then(response => {
+            if (response.status !== 409) return;
+            heldAllocation = '';
+            const opt = allocSelect.selectedOptions[0];
+            if (opt) opt.disabled = true;
+            allocSelect.value = '';
+            warning.textContent = 'That port was just picked on another create form. Choose another one.';
+            warning.style.display = 'block';
+        });
+    }
+
+    // Reserved ports are never auto-assigned, but an admin may still pick one on purpose
+    function updateReservedWarning() {
+        const allocSelect = document.getElementById('default_allocation');
+        const warning = document.getElementById('allocation_reserved_warning');
+        const opt = allocSelect.selectedOptions[0];
+        const reserved = Boolean(opt && opt.dataset.reserved === 'true');
+        warning.textContent = reserved
+            ? `This port is reserved${opt.dataset.notes ? ` (${opt.dataset.notes})` : ''}. Only use it if you mean to.`
+            : '';
+        warning.style.display = reserved ? 'block' : 'none';
+    }
+
+    // Clone / template: fill in config, leave name, node and allocation to the user
+    function applyPrefill(preset) {
+        const imageSelect = document.getElementById('image_id');
+
+        runtimeSelect.value = preset.runtime_id;
+        if (runtimeSelect.value !== preset.runtime_id) return;
+        updateImages();
+        imageSelect.value = preset.image_id;
+        if (imageSelect.value !== preset.image_id) return;
+        updateDockerInfo();
+
+        const dockerSelect = document.getElementById('docker_image');
+        dockerSelect.value = preset.docker_image;
+        if (dockerSelect.value !== preset.docker_image) {
+            document.getElementById('custom_docker_image').value = preset.docker_image;
+        }
+
+        const startupInput = document.getElementById('startup_command');
+        if (!startupInput.readOnly) startupInput.value = preset.startup_command;
+
+        ['cpu_limit', 'ram_limit', 'disk_limit', 'swap_limit', 'backup_limit', 'io_weight'].forEach(field => {
+            document.getElementById(field).value = preset[field];
+        });
+        document.getElementById('cpu_pinning').value = preset.cpu_pinning || '';
+        document.getElementById('description').value = preset.description || '';
+        document.getElementById('oom_killer').checked = preset.oom_killer;
+
+        Object.entries(preset.variables || {}).forEach(([name, value]) => {
+            const input = document.getElementById('var_' + name);
+            if (!input || input.readOnly || input.disabled || input.type === 'password') return;
+            if (input.type === 'checkbox') {
+                input.checked = value === '1';
+            } else {
+                input.value = value;
+            }
+        });
+    }
+
+    document.getElementById('node_id').addEventListener('change', () => {
+        updateAllocations();
+        reserveAllocation();
+    });
+    document.getElementById('location_id').addEventListener('change', () => {
+        updateAllocations();
+        reserveAllocation();
+    });
+    document.getElementById('default_allocation').addEventListener('change', () => {
+        updateReservedWarning();
+        reserveAllocation();
+    });
+    document.getElementById('runtime_id').addEventListener('change', updateImages);
+    document.getElementById('image_search').addEventListener('input', updateImages);
+    ['image_tag', 'image_group', 'image_sort'].forEach(id => {
+        document.getElementById(id).addEventListener('change', updateImages);
+    });
+    document.getElementById('image_id').addEventListener('change', updateDockerInfo);
+
+    const prefill = JSON.parse(document.getElementById('prefill-data').textContent);
+    if (prefill) {
+        applyPrefill(prefill);
+        document.getElementById('name').focus();
+    }
+})();
@@ -0,0 +1,8 @@
+(function () {
+    const reset = document.getElementById('startup-reset');
+    if (!reset) return;
+
+    reset.addEventListener('click', function () {
+        document.getElementById('startup_command').value = reset.dataset.default;
+    });
+})();
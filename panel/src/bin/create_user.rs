@@ -1,8 +1,8 @@
-use bcrypt::{hash, DEFAULT_COST};
-use sqlx::postgres::PgPoolOptions;
-use uuid::Uuid;
+use bcrypt::{DEFAULT_COST, hash};
 use chrono::Utc;
+use sqlx::postgres::PgPoolOptions;
 use std::env;
+use uuid::Uuid;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -16,27 +16,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .connect(&db_url)
         .await?;
 
-    let username = env::var("ADMIN_USERNAME")
-        .unwrap_or_else(|_| "admin".to_string());
-    let email = env::var("ADMIN_EMAIL")
-        .unwrap_or_else(|_| "admin@mail.com".to_string());
-    let password = env::var("ADMIN_PASSWORD")
-        .unwrap_or_else(|_| "qwerty123456".to_string());
+    let username = env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_string());
+    let email = env::var("ADMIN_EMAIL").unwrap_or_else(|_| "admin@mail.com".to_string());
+    let password = env::var("ADMIN_PASSWORD").unwrap_or_else(|_| "qwerty123456".to_string());
 
     println!("Creating user '{}' with email '{}'...", username, email);
 
     let hashed_password = hash(&password, DEFAULT_COST)?;
     let user_id = Uuid::new_v4();
     let created_at = Utc::now();
-    let role = "admin";
+    let role = env::var("ADMIN_ROLE").unwrap_or_else(|_| "admin".to_string());
+    if !["admin", "user", "auditor"].contains(&role.as_str()) {
+        return Err(format!("ADMIN_ROLE must be admin, user or auditor, not '{}'", role).into());
+    }
 
-    let exists = sqlx::query(
-        "SELECT id FROM users WHERE email = $1 OR username = $2"
-    )
-    .bind(&email)
-    .bind(&username)
-    .fetch_optional(&pool)
-    .await?;
+    let exists = sqlx::query("SELECT id FROM users WHERE email = $1 OR username = $2")
+        .bind(&email)
+        .bind(&username)
+        .fetch_optional(&pool)
+        .await?;
 
     if exists.is_some() {
         println!("User already exists!");
@@ -48,13 +46,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         INSERT INTO users
         (id, username, email, password_hash, role, permissions, created_at)
         VALUES ($1, $2, $3, $4, $5, $6, $7)
-        "#
+        "#,
     )
     .bind(user_id)
-    .bind(&username)        
-    .bind(&email)           
+    .bind(&username)
+    .bind(&email)
     .bind(&hashed_password)
-    .bind(role)
+    .bind(&role)
     .bind("{}")
     .bind(created_at)
     .execute(&pool)
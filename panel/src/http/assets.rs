@@ -0,0 +1,72 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Directory served under `/public`.
+pub const PUBLIC_DIR: &str = "panel/public";
+
+/// Relative path ("assets/js/app.js") -> short content hash, built once at startup.
+static MANIFEST: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Hashes every file under `PUBLIC_DIR`. Call once before serving requests.
+pub fn init() {
+    let mut manifest = HashMap::new();
+    collect(Path::new(PUBLIC_DIR), Path::new(PUBLIC_DIR), &mut manifest);
+    tracing::info!("Asset manifest built with {} files", manifest.len());
+    let _ = MANIFEST.set(manifest);
+}
+
+fn collect(root: &Path, dir: &Path, manifest: &mut HashMap<String, String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(root, &path, manifest);
+            continue;
+        }
+        let (Ok(bytes), Ok(rel)) = (std::fs::read(&path), path.strip_prefix(root)) else {
+            continue;
+        };
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&bytes);
+        let key = rel.to_string_lossy().replace('\\', "/");
+        manifest.insert(key, format!("{:08x}", hasher.finish() as u32));
+    }
+}
+
+/// URL for a file under `/public` with its content hash appended, for use in templates:
+/// `{{ crate::http::assets::asset("assets/js/app.js") }}`
+pub fn asset(path: &str) -> String {
+    match MANIFEST.get().and_then(|m| m.get(path)) {
+        Some(hash) => format!("/public/{}?v={}", path, hash),
+        None => format!("/public/{}", path),
+    }
+}
+
+/// Hashed asset URLs never change content, so let browsers keep them.
+pub async fn cache_control_middleware(req: Request, next: Next) -> Response {
+    let versioned = req
+        .uri()
+        .query()
+        .is_some_and(|q| q.split('&').any(|p| p.starts_with("v=")));
+
+    let mut response = next.run(req).await;
+    if versioned && response.status().is_success() {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=31536000, immutable"),
+        );
+    }
+    response
+}
@@ -1,15 +1,22 @@
-use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::Viewer;
+use crate::http::handlers::{HtmlTemplate, delete_preview_response};
+use crate::services::delete_previews;
+use crate::services::host_ports::{self, PortClaim, Verdict};
+use crate::services::{node_api, port_audit};
 use crate::{
-    models::{Allocation, CreateAllocationRequest, DeleteAllocationRequest, Node},
+    models::{
+        Allocation, CreateAllocationRequest, DeleteAllocationRequest, Node, UpdateAllocationRequest,
+    },
     state::AppState,
 };
 use askama::Template;
 use axum::{
     extract::{Form, Path, Query, State},
-    response::{IntoResponse, Redirect},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
 };
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 #[derive(Template)]
@@ -25,16 +32,68 @@ struct AllocationsTemplate {
     allocations: Vec<Allocation>,
     page: u32,
     has_more: bool,
+    /// Ports the last add refused, with what holds them
+    rejected: Option<String>,
+    /// Ports the last add created despite a possible clash
+    warned: Option<String>,
+    error: Option<String>,
+    can_modify: bool,
+}
+
+#[derive(Template)]
+#[template(path = "node_port_audit.html")]
+struct PortAuditTemplate {
+    panel_name: String,
+    panel_font: String,
+    panel_font_url: String,
+    panel_version: String,
+    execution_time: f64,
+    active_tab: String,
+    node: Node,
+    rows: Vec<AuditRow>,
+    /// Bindings the node reported, to tell "all in step" from "nothing to compare"
+    binding_count: usize,
+    /// Why the node's bindings couldn't be read
+    error: Option<String>,
+    can_modify: bool,
+}
+
+/// A mismatch with server ids resolved to names where the panel still has the server.
+struct AuditRow {
+    port: u16,
+    kind: &'static str,
+    allocated_to: String,
+    container: String,
+    bound_by: String,
+    container_state: String,
+}
+
+/// One row of the allocations table, re-rendered after an inline edit.
+#[derive(Template)]
+#[template(path = "allocation_row.html")]
+struct AllocationRowTemplate {
+    alloc: Allocation,
+}
+
+/// Longest note kept; the table shows it as a tooltip.
+const MAX_NOTE_LEN: usize = 200;
+
+fn clean_note(notes: &str) -> String {
+    notes.trim().chars().take(MAX_NOTE_LEN).collect()
 }
 
 #[derive(Deserialize)]
 pub struct PaginationQuery {
     page: Option<u32>,
+    rejected: Option<String>,
+    warned: Option<String>,
+    error: Option<String>,
 }
 
 pub async fn allocations_page_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    viewer: Viewer,
+    Path(id): Path<Uuid>,
     Query(params): Query<PaginationQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
@@ -47,8 +106,8 @@ pub async fn allocations_page_handler(
     let limit = 50;
     let offset = (page - 1) * limit;
 
-    let node_opt = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
+    let node_opt = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1")
+        .bind(id)
         .fetch_optional(&state.db)
         .await
         .unwrap_or(None);
@@ -58,8 +117,8 @@ pub async fn allocations_page_handler(
     }
     let node = node_opt.unwrap();
 
-    let allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text FROM allocations WHERE node_id = $1::uuid ORDER BY port ASC LIMIT $2 OFFSET $3")
-        .bind(&id)
+    let allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text, auto_created, notes, reserved FROM allocations WHERE node_id = $1 ORDER BY port ASC LIMIT $2 OFFSET $3")
+        .bind(id)
         .bind((limit + 1) as i32) // Fetch one more. Postgres needs i32/i64 not u32.
         .bind(offset as i32)
         .fetch_all(&state.db)
@@ -77,6 +136,7 @@ pub async fn allocations_page_handler(
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(AllocationsTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -87,67 +147,326 @@ pub async fn allocations_page_handler(
         allocations: display_allocations,
         page,
         has_more,
+        rejected: params.rejected,
+        warned: params.warned,
+        error: params.error,
     })
     .into_response()
 }
 
+/// Cross-references the node's allocations with the ports its containers publish.
+pub async fn port_audit_page_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let start_time = std::time::Instant::now();
+    let panel_version = env!("CARGO_PKG_VERSION").to_string();
+    let panel_name = state.panel_name.read().await.clone();
+    let panel_font = state.panel_font.read().await.clone();
+    let panel_font_url = state.panel_font_url.read().await.clone();
+
+    let Some(node) = state.get_node_with_token(&id.to_string()).await else {
+        return Redirect::to("/nodes").into_response();
+    };
+
+    let allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text, auto_created, notes, reserved FROM allocations WHERE node_id = $1 ORDER BY port ASC")
+        .bind(id)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+
+    let (mismatches, binding_count, error) =
+        match node_api::port_bindings(&state.http_client, &node, &state.node_retry).await {
+            Ok(bindings) => (
+                port_audit::audit(&allocations, &bindings),
+                bindings.len(),
+                None,
+            ),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read port bindings from node {}: {}",
+                    node.name,
+                    e
+                );
+                (Vec::new(), 0, Some(e))
+            }
+        };
+
+    let names: HashMap<String, String> =
+        sqlx::query_as::<_, (String, String)>("SELECT id::text, name FROM servers")
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+    let server = |id: &Option<String>| match id {
+        Some(id) => names
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| format!("{} (deleted)", id)),
+        None => "-".to_string(),
+    };
+    let rows = mismatches
+        .iter()
+        .map(|m| AuditRow {
+            port: m.port,
+            kind: m.kind.label(),
+            allocated_to: server(&m.allocated_to),
+            container: m.container.clone().unwrap_or_else(|| "-".to_string()),
+            bound_by: server(&m.bound_by),
+            container_state: m.container_state.clone().unwrap_or_default(),
+        })
+        .collect();
+
+    HtmlTemplate(PortAuditTemplate {
+        can_modify: viewer.can_modify,
+        panel_name,
+        panel_font,
+        panel_font_url,
+        panel_version,
+        execution_time: start_time.elapsed().as_secs_f64() * 1000.0,
+        active_tab: "nodes".to_string(),
+        node,
+        rows,
+        binding_count,
+        error,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FreePortsQuery {
+    start: u16,
+    end: u16,
+}
+
+/// Ports in a range the node reports free on its host, minus any the panel already has a
+/// claim on (the node's own allocations; allocations, agent and SFTP ports on the same IP);
+/// suggestions for the add form.
+pub async fn free_ports_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<FreePortsQuery>,
+) -> Response {
+    let Some(node) = state.get_node_with_token(&id.to_string()).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(serde_json::json!({ "error": "node_not_found" })),
+        )
+            .into_response();
+    };
+
+    let free = match node_api::free_ports(
+        &state.http_client,
+        &node,
+        query.start,
+        query.end,
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(free) => free,
+        Err(e) => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                axum::Json(serde_json::json!({ "error": "node_error", "message": e })),
+            )
+                .into_response();
+        }
+    };
+    let mut claims = host_ports::claims(&state.db, state.listen_port, &node.ip, None)
+        .await
+        .unwrap_or_default();
+    // Containers publish on the node's host whatever IP the allocation names
+    let own: Vec<i32> = sqlx::query_scalar("SELECT port FROM allocations WHERE node_id = $1")
+        .bind(id)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+    for port in own {
+        claims
+            .entry(port)
+            .or_insert_with(|| PortClaim::Allocation(node.name.clone()));
+    }
+    let free: Vec<u16> = free
+        .into_iter()
+        .filter(|p| !claims.contains_key(&(*p as i32)))
+        .collect();
+    axum::Json(serde_json::json!({ "free": free })).into_response()
+}
+
 pub async fn create_allocations_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path(id): Path<Uuid>,
     Form(payload): Form<CreateAllocationRequest>,
 ) -> Redirect {
     let ports = parse_ports(&payload.ports);
+    let notes = clean_note(&payload.notes);
 
     // Deduplicate
-    let unique_ports: HashSet<i32> = ports.into_iter().collect();
+    let mut unique_ports: Vec<i32> = ports
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    unique_ports.sort_unstable();
+
+    // Ports that would clash with the panel, this node's agent or other nodes on the same IP.
+    // The node's own allocations are left to ON CONFLICT below.
+    let node: Option<(String, String, i32, Option<i32>)> =
+        sqlx::query_as("SELECT name, ip, port, sftp_port FROM nodes WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+    let mut claims =
+        match host_ports::claims(&state.db, state.listen_port, &payload.ip, Some(id)).await {
+            Ok(claims) => claims,
+            Err(e) => {
+                tracing::error!("Failed to look up claimed ports on {}: {}", payload.ip, e);
+                Default::default()
+            }
+        };
+    let mut panel_host = host_ports::is_panel_host(&payload.ip);
+    if let Some((name, ip, port, sftp_port)) = node {
+        // Containers publish on the node's host whatever IP the allocation names
+        if let Some(sftp_port) = sftp_port {
+            claims.insert(sftp_port, PortClaim::NodeSftp(name.clone()));
+        }
+        claims.insert(port, PortClaim::NodeDaemon(name));
+        panel_host |= host_ports::is_panel_host(&ip);
+    }
 
+    let mut rejected = Vec::new();
+    let mut warned = Vec::new();
     for port in unique_ports {
         // Enforce port restrictions server-side
-        if port >= 0 && port <= 1023 {
+        if (0..=1023).contains(&port) {
             continue; // Skip system ports
         }
 
-        if port >= 0 && port <= 65535 {
-            let _ = sqlx::query("INSERT INTO allocations (id, node_id, ip, port) VALUES ($1::uuid, $2::uuid, $3, $4) ON CONFLICT DO NOTHING")
-                .bind(Uuid::new_v4().to_string())
-                .bind(&id)
+        match host_ports::verdict(&claims, port, panel_host) {
+            Verdict::Reject(claim) => {
+                rejected.push(format!("{} ({})", port, claim));
+                continue;
+            }
+            Verdict::Warn(claim) => warned.push(format!("{} ({})", port, claim)),
+            Verdict::Free => {}
+        }
+
+        if (0..=65535).contains(&port) {
+            let _ = sqlx::query("INSERT INTO allocations (id, node_id, ip, port, notes) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING")
+                .bind(Uuid::new_v4())
+                .bind(id)
                 .bind(&payload.ip)
                 .bind(port)
+                .bind(&notes)
                 .execute(&state.db)
                 .await;
         }
     }
 
-    Redirect::to(&format!("/nodes/{}/allocations", id))
+    let mut notices = Vec::new();
+    if !rejected.is_empty() {
+        notices.push(("rejected", rejected.join(", ")));
+    }
+    if !warned.is_empty() {
+        notices.push(("warned", warned.join(", ")));
+    }
+    if notices.is_empty() {
+        return Redirect::to(&format!("/nodes/{}/allocations", id));
+    }
+    let query = serde_urlencoded::to_string(&notices).unwrap_or_default();
+    Redirect::to(&format!("/nodes/{}/allocations?{}", id, query))
+}
+
+/// Saves an allocation's note and reserved flag from the inline form and returns the updated row.
+pub async fn update_allocation_handler(
+    State(state): State<AppState>,
+    Path((node_id, allocation_id)): Path<(Uuid, Uuid)>,
+    Form(payload): Form<UpdateAllocationRequest>,
+) -> axum::response::Response {
+    let updated = sqlx::query_as::<_, Allocation>(
+        "UPDATE allocations SET notes = $1, reserved = $2 WHERE id = $3 AND node_id = $4 \
+         RETURNING id::text, node_id::text, ip, port, server_id::text, auto_created, notes, reserved",
+    )
+    .bind(clean_note(&payload.notes))
+    .bind(payload.reserved.is_some())
+    .bind(allocation_id)
+    .bind(node_id)
+    .fetch_optional(&state.db)
+    .await;
+
+    match updated {
+        Ok(Some(alloc)) => HtmlTemplate(AllocationRowTemplate { alloc }).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "Allocation not found").into_response(),
+        Err(e) => {
+            tracing::error!("Failed to update allocation {}: {}", allocation_id, e);
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to save allocation",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DryRunQuery {
+    #[serde(default)]
+    dry_run: bool,
 }
 
+/// Deletes the given ports; without `force`, ports assigned to a server are kept. With
+/// `?dry_run=true` it only reports what would go, plus the token to send with the delete.
 pub async fn delete_allocations_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DryRunQuery>,
+    headers: HeaderMap,
     Form(payload): Form<DeleteAllocationRequest>,
-) -> Redirect {
+) -> Response {
     let ports_to_delete = parse_ports(&payload.ports);
+    let back = |error: &str| {
+        Redirect::to(&format!("/nodes/{}/allocations?error={}", id, error)).into_response()
+    };
 
-    if payload.force {
-        for port in ports_to_delete {
-            let _ = sqlx::query("DELETE FROM allocations WHERE node_id = $1::uuid AND port = $2")
-                .bind(&id)
-                .bind(port)
-                .execute(&state.db)
-                .await;
-        }
-    } else {
-        // Safe delete (only if server_id is NULL)
-        for port in ports_to_delete {
-            let _ = sqlx::query("DELETE FROM allocations WHERE node_id = $1::uuid AND port = $2 AND server_id IS NULL")
-                .bind(&id)
-                .bind(port)
-                .execute(&state.db)
-                .await;
-        }
+    let preview =
+        match delete_previews::allocations(&state.db, id, &ports_to_delete, payload.force).await {
+            Ok(preview) => preview,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to resolve allocations to delete on node {}: {}",
+                    id,
+                    e
+                );
+                if query.dry_run {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+                }
+                return back("db_error");
+            }
+        };
+    if query.dry_run {
+        return delete_preview_response(preview, &headers);
+    }
+    if !preview.matches(payload.token.as_deref()) {
+        return back("preview_stale");
+    }
+
+    // Exactly the previewed rows; a port assigned since is still spared without force
+    let deleted =
+        sqlx::query("DELETE FROM allocations WHERE id = ANY($1) AND ($2 OR server_id IS NULL)")
+            .bind(preview.allocation_ids())
+            .bind(payload.force)
+            .execute(&state.db)
+            .await;
+    if let Err(e) = deleted {
+        tracing::error!("Failed to delete allocations on node {}: {}", id, e);
+        return back("delete_failed");
     }
 
-    Redirect::to(&format!("/nodes/{}/allocations", id))
+    Redirect::to(&format!("/nodes/{}/allocations", id)).into_response()
 }
 
 fn parse_ports(input: &str) -> Vec<i32> {
@@ -162,16 +481,15 @@ fn parse_ports(input: &str) -> Vec<i32> {
 
         if trimmed.contains('-') {
             let range_parts: Vec<&str> = trimmed.split('-').collect();
-            if range_parts.len() == 2 {
-                if let (Ok(start), Ok(end)) = (
+            if range_parts.len() == 2
+                && let (Ok(start), Ok(end)) = (
                     range_parts[0].trim().parse::<i32>(),
                     range_parts[1].trim().parse::<i32>(),
-                ) {
-                    if start <= end {
-                        for p in start..=end {
-                            result.push(p);
-                        }
-                    }
+                )
+                && start <= end
+            {
+                for p in start..=end {
+                    result.push(p);
                 }
             }
         } else {
@@ -1,152 +1,307 @@
+use crate::{
+    models::{HeartbeatPayload, MAX_SANE_HEARTBEAT_INTERVAL, NodeLifecycleEvent},
+    services::{bandwidth, node_tokens, node_versions, uptime},
+    state::AppState,
+};
 use axum::{
-    extract::{State, Path, Json},
+    extract::{Json, Path, Query, State},
     http::{HeaderMap, StatusCode},
 };
-use tracing::{info, error};
-use crate::{state::AppState, models::{Node, HeartbeatPayload}};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+use uuid::Uuid;
 
-pub async fn heartbeat_handler(
-    State(state): State<AppState>,
-    Path(id): Path<String>,
-    headers: HeaderMap,
-    Json(payload): Json<HeartbeatPayload>,
-) -> StatusCode {
-    // [TRACE] Entry
-    info!("[TRACE] -> heartbeat_handler triggered for ID: {}", id);
-    
-    // Verify Token
-    let auth_header = headers.get("Authorization")
+/// What `node:{id}:cache` holds for heartbeat auth: a hash of the token, not the token.
+#[derive(Serialize, Deserialize)]
+struct NodeAuth {
+    token_sha256: String,
+    version: String,
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")
         .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "));
-
-    if let Some(token) = auth_header {
-        info!("[TRACE] Token received: {}...", &token.chars().take(5).collect::<String>());
-        let mut node_opt: Option<Node> = None;
-
-        // 1. Try Cache
-        if let Some(manager) = &state.redis {
-             let mut con = manager.clone();
-             let key = format!("node:{}:cache", id);
-             let cached: Result<String, _> = redis::AsyncCommands::get(&mut con, key).await;
-             if let Ok(json) = cached {
-                 info!("[TRACE] Node found in Redis Cache");
-                 if let Ok(n) = serde_json::from_str::<Node>(&json) {
-                     node_opt = Some(n);
-                 }
-             } else {
-                 info!("[TRACE] Node NOT in Redis Cache");
-             }
-        }
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
 
-        // 2. Fallback to Memory Cache (Avoid DB Hit)
-        if node_opt.is_none() {
-             let nodes_lock = state.nodes_cache.read().await;
-             if let Some(nodes) = &*nodes_lock {
-                 if let Some(n) = nodes.iter().find(|n| n.id == id) {
-                     info!("[TRACE] Node found in Memory Cache");
-                     node_opt = Some(n.clone());
-                 }
-             }
-        }
+/// Checks a node's token against the stored token (decrypted, then cached as a hash) and any
+/// pending rotation, always in constant time. Heartbeats, lifecycle callbacks and the
+/// install script's check share it.
+/// `Some(version)` is the stored agent version when the current token matched; `None` for
+/// a pending token. Unknown nodes and wrong tokens get the same 401.
+async fn authenticate_node(
+    state: &AppState,
+    node_id: Uuid,
+    token: Option<&str>,
+) -> Result<Option<String>, StatusCode> {
+    let id = node_id.to_string();
+    let mut stored_version = None;
 
-        // 3. Fallback to DB
-        if node_opt.is_none() {
-            info!("[TRACE] Fallback to DB Lookup for node: {}", id);
-            node_opt = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1::uuid")
-                .bind(&id)
-                .fetch_optional(&state.db)
-                .await
-                .unwrap_or(None);
-            
-            // Re-populate Memory Cache if found
-            if let Some(ref _n) = node_opt {
-                // Trigger background cache refresh? 
-                // For now just rely on next dashboard load to populate it.
+    if let Some(token) = token {
+        let presented = node_tokens::sha256(token);
+        let mut auth_opt: Option<NodeAuth> = None;
+
+        // 1. Try Cache (only the token hash is cached, never the token itself)
+        if let Some(mut con) = state.redis.connection() {
+            let key = format!("node:{}:cache", id);
+            let cached: Result<String, _> = redis::AsyncCommands::get(&mut con, key).await;
+            state.redis.observe(&cached);
+            if let Ok(json) = cached
+                && let Ok(a) = serde_json::from_str::<NodeAuth>(&json)
+            {
+                auth_opt = Some(a);
             }
+        }
+
+        // 2. Fallback to DB
+        if auth_opt.is_none() {
+            let row: Option<(String, String)> = sqlx::query_as(
+                "SELECT COALESCE(token, ''), COALESCE(version, '') FROM nodes WHERE id = $1",
+            )
+            .bind(node_id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+            auth_opt = row.map(|(stored, version)| {
+                let token = node_tokens::open_or_empty(&state.secrets_key, &id, &stored);
+                // No usable token: an empty hash, which no presented token's hash equals
+                let token_sha256 = if token.is_empty() {
+                    String::new()
+                } else {
+                    node_tokens::sha256(&token)
+                };
+                NodeAuth {
+                    token_sha256,
+                    version,
+                }
+            });
 
             // Cache result if found (Redis)
-            if let Some(ref n) = node_opt {
-                info!("[TRACE] Node found in DB, caching...");
-                if let Some(manager) = &state.redis {
-                    let mut con = manager.clone();
-                    let key = format!("node:{}:cache", id);
-                    if let Ok(json) = serde_json::to_string(n) {
-                        let _: Result<(), _> = redis::AsyncCommands::set_ex(&mut con, key, json, 60).await;
-                    }
+            if let Some(ref a) = auth_opt
+                && let Some(mut con) = state.redis.connection()
+            {
+                let key = format!("node:{}:cache", id);
+                if let Ok(json) = serde_json::to_string(a) {
+                    let res: Result<(), _> =
+                        redis::AsyncCommands::set_ex(&mut con, key, json, 60).await;
+                    state.redis.observe(&res);
                 }
-            } else {
-                info!("[TRACE] Node NOT found in DB");
             }
         }
 
         let mut authorized = false;
-        if let Some(node) = node_opt {
-             if node.token == token {
-                info!("[TRACE] Token MATCH - Authorized");
+        if let Some(auth) = auth_opt {
+            if node_tokens::constant_time_eq(auth.token_sha256.as_bytes(), presented.as_bytes()) {
                 authorized = true;
-                // Update Version in DB if changed
-                if node.version != payload.version {
-                     info!("[TRACE] Updating version from {} to {}", node.version, payload.version);
-                     let _ = sqlx::query("UPDATE nodes SET version = $1 WHERE id = $2::uuid")
-                        .bind(&payload.version)
-                        .bind(&id)
-                        .execute(&state.db)
-                        .await;
-                     
-                     // Invalidate cache to force refresh on next heartbeat
-                     if let Some(manager) = &state.redis {
-                         let mut con = manager.clone();
-                         let key = format!("node:{}:cache", id);
-                         let _: Result<(), _> = redis::AsyncCommands::del(&mut con, key).await;
-                     }
-                }
+                stored_version = Some(auth.version);
             } else {
-                error!("[TRACE] Token mismatch for node {}. Expected: {}, Got: {}", id, node.token, token);
+                debug!("Token mismatch for node {}", id);
             }
         }
 
         // Check Pending Token (if DB check failed)
+        if !authorized && let Some(mut con) = state.redis.connection() {
+            let key = format!("node:{}:pending_token", id);
+            let pending: Result<String, _> = redis::AsyncCommands::get(&mut con, key).await;
+            state.redis.observe(&pending);
+            if let Ok(pending_token) = pending
+                && node_tokens::matches(
+                    token,
+                    &node_tokens::open_or_empty(&state.secrets_key, &id, &pending_token),
+                )
+            {
+                authorized = true;
+            }
+        }
+
+        // Pending token persisted in the DB (rotation without Redis)
         if !authorized {
-            if let Some(manager) = &state.redis {
-                let mut con = manager.clone();
-                let key = format!("node:{}:pending_token", id);
-                let pending: Result<String, _> = redis::AsyncCommands::get(&mut con, key).await;
-                if let Ok(pending_token) = pending {
-                    if pending_token == token {
-                        info!("[TRACE] Pending Token MATCH - Authorized");
-                        authorized = true;
-                    }
-                }
+            let pending: Option<String> = sqlx::query_scalar(
+                "SELECT pending_token FROM nodes WHERE id = $1 AND pending_token_expires > NOW()",
+            )
+            .bind(node_id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None)
+            .flatten();
+
+            if pending.is_some_and(|p| {
+                node_tokens::matches(
+                    token,
+                    &node_tokens::open_or_empty(&state.secrets_key, &id, &p),
+                )
+            }) {
+                authorized = true;
             }
         }
 
         if !authorized {
-            info!("[TRACE] Returning 401 UNAUTHORIZED");
-            return StatusCode::UNAUTHORIZED;
+            return Err(StatusCode::UNAUTHORIZED);
         }
     } else {
-        error!("[TRACE] Missing authorization header for node {}", id);
-        return StatusCode::UNAUTHORIZED;
+        debug!("Missing authorization header for node {}", id);
+        return Err(StatusCode::UNAUTHORIZED);
     }
 
-    if let Some(manager) = &state.redis {
+    Ok(stored_version)
+}
+
+pub async fn heartbeat_handler(
+    State(state): State<AppState>,
+    Path(node_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<HeartbeatPayload>,
+) -> StatusCode {
+    // Caches and heartbeat events are keyed by the string form
+    let id = node_id.to_string();
+
+    let stored_version = match authenticate_node(&state, node_id, bearer_token(&headers)).await {
+        Ok(v) => v,
+        Err(status) => return status,
+    };
+    // Version changes are persisted in the background, never on the heartbeat path
+    if let Some(stored) = stored_version {
+        match node_versions::sanitize(&payload.version) {
+            Some(version) if version != stored => {
+                tokio::spawn(node_versions::record_change(
+                    state.clone(),
+                    id.clone(),
+                    version.to_string(),
+                ));
+            }
+            Some(_) => {}
+            None => tracing::warn!(
+                "Node {} reported an invalid version string, ignoring it",
+                id
+            ),
+        }
+    }
+
+    if payload.heartbeat_interval > MAX_SANE_HEARTBEAT_INTERVAL {
+        tracing::warn!(
+            "Node {} reports a {}s heartbeat interval (max recommended {}s); it will be slow to show as offline",
+            id,
+            payload.heartbeat_interval,
+            MAX_SANE_HEARTBEAT_INTERVAL
+        );
+    }
+
+    if let Some(report) = payload.update.clone() {
+        tokio::spawn(node_versions::record_update(
+            state.clone(),
+            id.clone(),
+            report,
+        ));
+    }
+
+    // Bandwidth counters are written in the background, same as version changes
+    if let Some(containers) = payload.containers.clone()
+        && containers.iter().any(|c| c.tx_bytes.is_some())
+    {
+        tokio::spawn(bandwidth::record(
+            state.clone(),
+            id.clone(),
+            payload.timestamp,
+            containers,
+        ));
+    }
+    if let Some(containers) = payload.containers.clone() {
+        tokio::spawn(uptime::record(
+            state.clone(),
+            id.clone(),
+            containers,
+            payload.stale_after_secs(),
+        ));
+    }
+
+    if let Some(mut con) = state.redis.connection() {
         let key = format!("node:{}:stats", id);
-        info!("[TRACE] Writing stats to Redis Key: {}", key);
         let json = serde_json::to_string(&payload).unwrap_or_default();
-        
-        let mut con = manager.clone();
-        let res: Result<(), _> = redis::AsyncCommands::set_ex(&mut con, key, json, 15).await;
-        match res {
-            Ok(_) => info!("[TRACE] Redis Write SUCCESS"),
-            Err(e) => {
-                error!("[TRACE] Redis Write FAILED: {}, falling back to memory", e);
-                state.heartbeats_cache.write().await.insert(id.clone(), payload);
-            }
+
+        let ttl = payload.stale_after_secs();
+        let res: Result<(), _> = redis::AsyncCommands::set_ex(&mut con, key, json, ttl).await;
+        state.redis.observe(&res);
+        if let Err(e) = res {
+            // RedisCache logs the outage once; this fires on every heartbeat until then
+            debug!("Redis write failed: {}, falling back to memory", e);
+            state
+                .heartbeats_cache
+                .write()
+                .await
+                .insert(id.clone(), payload);
         }
     } else {
-        // info!("[TRACE] Redis not available to save heartbeat for {}, using memory cache", id);
-        state.heartbeats_cache.write().await.insert(id.clone(), payload);
+        state
+            .heartbeats_cache
+            .write()
+            .await
+            .insert(id.clone(), payload);
+    }
+    // Nobody listening is fine; the send only fails without subscribers
+    let _ = state.heartbeat_events.send(id);
+
+    StatusCode::OK
+}
+
+/// Drops a node's cached stats so dashboards show it offline right away instead of once
+/// the stats key expires.
+async fn clear_node_stats(state: &AppState, id: &str) {
+    if let Some(mut con) = state.redis.connection() {
+        let res: Result<(), _> =
+            redis::AsyncCommands::del(&mut con, format!("node:{}:stats", id)).await;
+        state.redis.observe(&res);
     }
-    info!("[TRACE] Returning 200 OK");
+    state.heartbeats_cache.write().await.remove(id);
+    let _ = state.heartbeat_events.send(id.to_string());
+}
+
+/// `POST /nodes/{id}/lifecycle`: the agent announces it is starting (stats from its previous
+/// run are stale) or stopping. Both clear the node's stats; the next heartbeat brings it back.
+pub async fn node_lifecycle_handler(
+    State(state): State<AppState>,
+    Path(node_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(payload): Json<NodeLifecycleEvent>,
+) -> StatusCode {
+    let id = node_id.to_string();
+    if let Err(status) = authenticate_node(&state, node_id, bearer_token(&headers)).await {
+        return status;
+    }
+
+    match payload.event.as_str() {
+        "starting" => info!("Node {} is starting, clearing its stats", id),
+        "stopping" => info!("Node {} is shutting down, marking it offline", id),
+        _ => return StatusCode::BAD_REQUEST,
+    }
+    if let Some(report) = payload.update {
+        tokio::spawn(node_versions::record_update(
+            state.clone(),
+            id.clone(),
+            report,
+        ));
+    }
+    clear_node_stats(&state, &id).await;
     StatusCode::OK
 }
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    token: Option<String>,
+}
+
+/// Lets the install script check its node ID and token before touching the host, and
+/// again once the agent runs. 204 when they pair up, 401 otherwise, whether or not the
+/// node exists. The token may come as `?token=` or as a bearer header like heartbeats.
+pub async fn verify_node_handler(
+    State(state): State<AppState>,
+    Path(node_id): Path<Uuid>,
+    Query(query): Query<VerifyQuery>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let token = bearer_token(&headers).or(query.token.as_deref());
+    match authenticate_node(&state, node_id, token).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(status) => status,
+    }
+}
@@ -1,19 +1,29 @@
+use crate::{
+    http::handlers::{HtmlTemplate, html_escape},
+    models::{Node, User},
+    services::{
+        node_api::{self, read_node_error},
+        node_tokens,
+    },
+    state::AppState,
+};
+use askama::Template;
 use axum::{
-    extract::{State, Path, Form, ConnectInfo},
-    http::{StatusCode},
-    response::{IntoResponse, Redirect, Response},
+    Json, Router,
+    extract::{ConnectInfo, Form, FromRequestParts, Path, Request, State},
+    http::{HeaderMap, Method, StatusCode, header, request::Parts},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
     routing::{get, post},
-    Router,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
-use crate::{state::AppState, models::{Node, User}, http::handlers::HtmlTemplate};
-use askama::Template;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use bcrypt::verify;
-use chrono::{Utc, Duration};
+use chrono::{Duration, Utc};
+use futures_util::stream::{self, StreamExt};
 use rand::Rng;
-use serde::Deserialize;
-use uuid::Uuid;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use uuid::Uuid;
 
 pub fn auth_routes() -> Router<AppState> {
     Router::new()
@@ -38,8 +48,8 @@ pub struct LoginRequest {
 pub async fn login_page(State(state): State<AppState>) -> impl IntoResponse {
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
-    
-    HtmlTemplate(LoginTemplate { 
+
+    HtmlTemplate(LoginTemplate {
         error: None,
         panel_font,
         panel_font_url,
@@ -50,6 +60,7 @@ pub async fn login_handler(
     State(state): State<AppState>,
     jar: CookieJar,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Form(payload): Form<LoginRequest>,
 ) -> Response {
     let user_opt = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
@@ -64,126 +75,866 @@ pub async fn login_handler(
     if let Some(user) = user_opt {
         // Allow passwordless login if from localhost
         let is_localhost = addr.ip().is_loopback();
-        
+
         if is_localhost || verify(&payload.password, &user.password_hash).unwrap_or(false) {
             // Create session
             let session_id = Uuid::new_v4();
             let expires_at_chrono = Utc::now() + Duration::days(7); // chrono duration
-            
+
             // Store session in DB
-            let res = sqlx::query("INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, $3)")
-                .bind(session_id)
-                .bind(user.id)
-                .bind(expires_at_chrono)
-                .execute(&state.db)
-                .await;
+            let res =
+                sqlx::query("INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, $3)")
+                    .bind(session_id)
+                    .bind(user.id)
+                    .bind(expires_at_chrono)
+                    .execute(&state.db)
+                    .await;
 
             if res.is_ok() {
-                let cookie = Cookie::build(("session_id", session_id.to_string()))
-                    .path("/")
-                    .http_only(true)
-                    .secure(false) // Set to true in prod
-                    .max_age(time::Duration::days(7));
+                let https = state.request_scheme(&headers) == "https";
+                let cookie = state.session_cookie.build(session_id.to_string(), https);
 
                 return (jar.add(cookie), Redirect::to("/")).into_response();
             }
         }
     }
 
-    HtmlTemplate(LoginTemplate { 
+    HtmlTemplate(LoginTemplate {
         error: Some("Invalid email or password".into()),
         panel_font,
         panel_font_url,
-    }).into_response()
+    })
+    .into_response()
 }
 
 pub async fn logout_handler(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
     if let Some(session_cookie) = jar.get("session_id") {
-         // Delete from DB
-         if let Ok(session_uuid) = Uuid::parse_str(session_cookie.value()) {
+        // Delete from DB
+        if let Ok(session_uuid) = Uuid::parse_str(session_cookie.value()) {
             let _ = sqlx::query("DELETE FROM sessions WHERE id = $1")
                 .bind(session_uuid)
                 .execute(&state.db)
                 .await;
-         }
+        }
     }
-    
-    (jar.remove(Cookie::from("session_id")), Redirect::to("/auth/login"))
+
+    (
+        jar.remove(Cookie::from("session_id")),
+        Redirect::to("/auth/login"),
+    )
 }
 
+/// Sent while another rotation or force-set holds the node's token lock
+const TOKEN_BUSY: &str = "Another token change for this node is in progress; try again shortly";
+
 pub async fn rotate_token_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> StatusCode {
-    let node_opt = sqlx::query_as::<_, Node>("SELECT id, name, ip, port, token FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
-        .fetch_optional(&state.db)
-        .await
-        .unwrap_or(None);
+    Path(id): Path<Uuid>,
+) -> (StatusCode, String) {
+    let Some(lock) = state.token_locks.acquire(id).await else {
+        return (StatusCode::CONFLICT, TOKEN_BUSY.to_string());
+    };
+    let result = rotate_token(&state, id).await;
+    state.token_locks.release(lock).await;
+    result
+}
+
+async fn rotate_token(state: &AppState, id: Uuid) -> (StatusCode, String) {
+    let node_opt = state.get_node_with_token(&id.to_string()).await;
 
     if let Some(node) = node_opt {
-        let new_token: String = rand::rng()
-            .sample_iter(&rand::distr::Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
-
-        if let Some(manager) = &state.redis {
-            let mut con = manager.clone();
+        let new_token = generate_token();
+        let sealed = node_tokens::seal(&state.secrets_key, &new_token);
+
+        // The node verifies the new token with a heartbeat before accepting it,
+        // so the panel has to recognise it for a short window (60s).
+        let mut pending_stored = false;
+        if let Some(mut con) = state.redis.connection() {
             let key = format!("node:{}:pending_token", id);
-            let _: Result<(), _> = redis::AsyncCommands::set_ex(&mut con, key, &new_token, 60).await;
+            let res: Result<(), _> = redis::AsyncCommands::set_ex(&mut con, key, &sealed, 60).await;
+            state.redis.observe(&res);
+            pending_stored = res.is_ok();
+        }
+
+        // Without Redis, keep the pending token in the nodes table instead
+        if !pending_stored {
+            let res = sqlx::query("UPDATE nodes SET pending_token = $1, pending_token_expires = NOW() + INTERVAL '60 seconds' WHERE id = $2")
+                .bind(&sealed)
+                .bind(id)
+                .execute(&state.db)
+                .await;
+
+            if let Err(e) = res {
+                tracing::error!("Failed to store pending token for node {}: {}", id, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to store pending token".to_string(),
+                );
+            }
         }
 
         let url = format!("http://{}:{}/update-token", node.ip, node.port);
         let payload = serde_json::json!({ "token": new_token });
 
-        let resp = state.http_client.post(&url)
+        let resp = state
+            .http_client
+            .post(&url)
             .header("Authorization", format!("Bearer {}", node.token))
             .json(&payload)
             .send()
             .await;
 
-        match resp {
+        let result = match resp {
             Ok(res) if res.status().is_success() => {
-                let _ = sqlx::query("UPDATE nodes SET token = $1 WHERE id = $2::uuid")
-                    .bind(&new_token)
-                    .bind(&id)
+                let _ = sqlx::query("UPDATE nodes SET token = $1, token_rotated_at = NOW(), pending_token = NULL, pending_token_expires = NULL WHERE id = $2")
+                    .bind(&sealed)
+                    .bind(id)
+                    .execute(&state.db)
+                    .await;
+
+                (StatusCode::OK, "Token rotated".to_string())
+            }
+            other => {
+                let _ = sqlx::query("UPDATE nodes SET pending_token = NULL, pending_token_expires = NULL WHERE id = $1")
+                    .bind(id)
                     .execute(&state.db)
                     .await;
-                
-                return StatusCode::OK;
+
+                match other {
+                    Ok(res) => {
+                        let err = read_node_error(res).await;
+                        tracing::error!("Token rotation for node {} failed: {}", id, err);
+                        if err.is_auth_failure() {
+                            (
+                                StatusCode::BAD_GATEWAY,
+                                "Node rejected the current token; re-run the install command"
+                                    .to_string(),
+                            )
+                        } else if err.code == "token_verification_failed" {
+                            (
+                                StatusCode::BAD_GATEWAY,
+                                format!(
+                                    "Node could not verify the new token with the panel: {}",
+                                    err.message
+                                ),
+                            )
+                        } else {
+                            (StatusCode::BAD_GATEWAY, format!("Node error: {}", err))
+                        }
+                    }
+                    Err(e) => (StatusCode::BAD_GATEWAY, format!("Connection failed: {}", e)),
+                }
             }
-            _ => return StatusCode::INTERNAL_SERVER_ERROR
+        };
+
+        // Drop cached node rows and the heartbeat token hash
+        state.invalidate_nodes_cache().await;
+        if let Some(mut con) = state.redis.connection() {
+            let key = format!("node:{}:cache", id);
+            let res: Result<(), _> = redis::AsyncCommands::del(&mut con, key).await;
+            state.redis.observe(&res);
         }
+
+        return result;
     }
-    StatusCode::NOT_FOUND
+    (StatusCode::NOT_FOUND, "Node not found".to_string())
 }
 
-use axum::{
-    middleware::Next,
-    extract::Request,
-};
+/// Nodes `rotate_all_tokens_handler` rotates at the same time
+const BULK_ROTATION_CONCURRENCY: usize = 4;
 
-pub async fn auth_middleware(
+#[derive(Serialize)]
+pub struct BulkRotationResult {
+    node_id: String,
+    name: String,
+    /// `rotated`, `failed`, `skipped` (offline) or `busy` (another token change running)
+    status: &'static str,
+    message: String,
+}
+
+/// `POST /nodes/rotate-all` (admins, or anyone with `AUTH_MODE=off`): rotates every node's token, a few nodes at a time,
+/// each under its token lock. Nodes without a recent heartbeat are skipped: the node checks
+/// a new token with a heartbeat before keeping it, so rotating one that can't reach the
+/// panel would leave it on a token the panel no longer accepts. Answers JSON, or an HTML
+/// summary for htmx.
+pub async fn rotate_all_tokens_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
     jar: CookieJar,
-    request: Request,
-    next: Next,
-) -> Result<Response, Redirect> {
-    let session_cookie = jar.get("session_id");
-    if let Some(cookie) = session_cookie {
-        if let Ok(session_id) = Uuid::parse_str(cookie.value()) {
-             let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE id = $1 AND expires_at > NOW()")
-                .bind(session_id)
+) -> Response {
+    // With AUTH_MODE=off there are no admins, and every node can be rotated one by one anyway
+    if state.auth_mode != AuthMode::Off
+        && !session_user(&state, &jar)
+            .await
+            .is_some_and(|u| u.is_admin())
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            "Only admins can rotate every node's token",
+        )
+            .into_response();
+    }
+
+    let nodes = state.get_nodes().await;
+    let state_ref = &state;
+    let mut results: Vec<BulkRotationResult> = stream::iter(nodes)
+        .map(|node| async move {
+            let (status, message) = rotate_if_online(state_ref, &node).await;
+            BulkRotationResult {
+                node_id: node.id,
+                name: node.name,
+                status,
+                message,
+            }
+        })
+        .buffer_unordered(BULK_ROTATION_CONCURRENCY)
+        .collect()
+        .await;
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+    let (rotated, failed, skipped) = (
+        count("rotated"),
+        count("failed"),
+        count("skipped") + count("busy"),
+    );
+    tracing::warn!(
+        "Bulk token rotation: {} rotated, {} failed, {} skipped",
+        rotated,
+        failed,
+        skipped
+    );
+
+    if !headers.contains_key("HX-Request") {
+        return Json(serde_json::json!({
+            "rotated": rotated,
+            "failed": failed,
+            "skipped": skipped,
+            "results": results,
+        }))
+        .into_response();
+    }
+    let rows: String = results
+        .iter()
+        .map(|r| {
+            let color = match r.status {
+                "rotated" => "#28a745",
+                "failed" => "#dc3545",
+                _ => "#856404",
+            };
+            format!(
+                r#"<li><strong>{}</strong>: <span style="color: {};">{}</span> {}</li>"#,
+                html_escape(&r.name),
+                color,
+                r.status,
+                html_escape(&r.message)
+            )
+        })
+        .collect();
+    Html(format!(
+        r#"<p style="margin: 0 0 0.5rem;">{} rotated, {} failed, {} skipped.</p><ul style="margin: 0; padding-left: 1.25rem; font-size: 0.9em;">{}</ul>"#,
+        rotated, failed, skipped, rows
+    ))
+    .into_response()
+}
+
+async fn rotate_if_online(state: &AppState, node: &Node) -> (&'static str, String) {
+    let Ok(id) = Uuid::parse_str(&node.id) else {
+        return ("failed", "Invalid node id".to_string());
+    };
+    if state.node_stats(&node.id).await.is_none() {
+        return (
+            "skipped",
+            "Offline: no recent heartbeat, so the node could not confirm a new token".to_string(),
+        );
+    }
+    let Some(lock) = state.token_locks.acquire(id).await else {
+        return ("busy", TOKEN_BUSY.to_string());
+    };
+    let (code, message) = rotate_token(state, id).await;
+    state.token_locks.release(lock).await;
+    if code.is_success() {
+        ("rotated", message)
+    } else {
+        ("failed", message)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevealTokenRequest {
+    password: String,
+}
+
+/// User behind the request's session cookie, if the session is still valid.
+pub async fn session_user(state: &AppState, jar: &CookieJar) -> Option<User> {
+    let session_id = Uuid::parse_str(jar.get("session_id")?.value()).ok()?;
+    sqlx::query_as::<_, User>("SELECT u.* FROM users u JOIN sessions s ON s.user_id = u.id WHERE s.id = $1 AND s.expires_at > NOW()")
+        .bind(session_id)
+        .fetch_optional(&state.db)
+        .await
+        .unwrap_or(None)
+}
+
+/// Shows a node's token once the signed-in user re-enters their password.
+/// Unlike login, there is no localhost bypass here.
+pub async fn reveal_token_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    jar: CookieJar,
+    Form(payload): Form<RevealTokenRequest>,
+) -> Response {
+    let message = |color: &str, text: &str| {
+        Html(format!(
+            r#"<span style="color: {}; font-size: 0.9em;">{}</span>"#,
+            color, text
+        ))
+        .into_response()
+    };
+
+    let Some(user) = session_user(&state, &jar).await else {
+        return message("#dc3545", "Session expired, please log in again");
+    };
+
+    if !verify(&payload.password, &user.password_hash).unwrap_or(false) {
+        tracing::warn!("Failed token reveal for node {} by {}", id, user.username);
+        return message("#dc3545", "Incorrect password");
+    }
+
+    let token: Option<String> = sqlx::query_scalar("SELECT token FROM nodes WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+        .unwrap_or(None)
+        .flatten();
+
+    let Some(token) = token else {
+        return message("#dc3545", "Node not found");
+    };
+    let Ok(token) = node_tokens::open(&state.secrets_key, &token) else {
+        return message(
+            "#dc3545",
+            "The stored token can't be decrypted with the current APP_KEY",
+        );
+    };
+
+    tracing::info!("Node {} token revealed to {}", id, user.username);
+    let token = html_escape(&token);
+
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(format!(
+            r#"<code style="background: #f4f4f4; padding: 0.25rem 0.5rem; border-radius: 4px; user-select: all;">{}</code>"#,
+            token
+        )),
+    )
+        .into_response()
+}
+
+/// Shortest token the force-set action takes
+const MIN_TOKEN_LEN: usize = 16;
+const MAX_TOKEN_LEN: usize = 128;
+
+fn generate_token() -> String {
+    rand::rng()
+        .sample_iter(&rand::distr::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Drops a node's pending token (Redis and DB), cached node rows and heartbeat token hash.
+async fn clear_token_state(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE nodes SET pending_token = NULL, pending_token_expires = NULL WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&state.db)
+    .await?;
+    state.invalidate_nodes_cache().await;
+    if let Some(mut con) = state.redis.connection() {
+        let keys = [
+            format!("node:{}:pending_token", id),
+            format!("node:{}:cache", id),
+        ];
+        let res: Result<(), _> = redis::AsyncCommands::del(&mut con, &keys).await;
+        state.redis.observe(&res);
+    }
+    Ok(())
+}
+
+/// The signed-in admin behind a token recovery action once `password` checks out, or why
+/// the action is refused. Always needs a session, even with `AUTH_MODE=off`.
+async fn recovery_admin(
+    state: &AppState,
+    jar: &CookieJar,
+    password: &str,
+) -> Result<User, &'static str> {
+    let Some(user) = session_user(state, jar).await else {
+        return Err("Session expired, please log in again");
+    };
+    if !user.is_admin() {
+        return Err("Only admins can recover node tokens");
+    }
+    if !verify(password, &user.password_hash).unwrap_or(false) {
+        return Err("Incorrect password");
+    }
+    Ok(user)
+}
+
+#[derive(Deserialize)]
+pub struct ForceTokenRequest {
+    password: String,
+    /// Token to set; blank generates one
+    #[serde(default)]
+    token: String,
+}
+
+/// Recovery for a node whose token no longer matches the panel's, e.g. after a rotation
+/// the node accepted but the panel failed to store. Writes the token straight to the DB,
+/// then pushes it to the node with every token the node might still hold: the stored one,
+/// then any pending one left by a rotation. Needs a signed-in admin and their password.
+pub async fn force_token_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    jar: CookieJar,
+    Form(payload): Form<ForceTokenRequest>,
+) -> Response {
+    let message = |color: &str, text: &str| {
+        Html(format!(
+            r#"<span style="color: {}; font-size: 0.9em;">{}</span>"#,
+            color, text
+        ))
+        .into_response()
+    };
+
+    let user = match recovery_admin(&state, &jar, &payload.password).await {
+        Ok(user) => user,
+        Err(refusal) => {
+            tracing::warn!("Refused token force-set for node {}: {}", id, refusal);
+            return message("#dc3545", refusal);
+        }
+    };
+
+    let chosen = payload.token.trim();
+    let new_token = if chosen.is_empty() {
+        generate_token()
+    } else if (MIN_TOKEN_LEN..=MAX_TOKEN_LEN).contains(&chosen.len())
+        && chosen
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        chosen.to_string()
+    } else {
+        return message(
+            "#dc3545",
+            &format!(
+                "Tokens are {}-{} letters, digits, '-' or '_'",
+                MIN_TOKEN_LEN, MAX_TOKEN_LEN
+            ),
+        );
+    };
+
+    let Some(lock) = state.token_locks.acquire(id).await else {
+        return message("#dc3545", TOKEN_BUSY);
+    };
+    let response = push_forced_token(&state, id, &new_token, &user.username).await;
+    state.token_locks.release(lock).await;
+    response
+}
+
+async fn push_forced_token(
+    state: &AppState,
+    id: Uuid,
+    new_token: &str,
+    username: &str,
+) -> Response {
+    let message = |color: &str, text: &str| {
+        Html(format!(
+            r#"<span style="color: {}; font-size: 0.9em;">{}</span>"#,
+            color, text
+        ))
+        .into_response()
+    };
+
+    let row: Option<(Node, Option<String>)> = async {
+        let node = state.get_node_with_token(&id.to_string()).await?;
+        let pending: Option<String> =
+            sqlx::query_scalar("SELECT pending_token FROM nodes WHERE id = $1")
+                .bind(id)
                 .fetch_one(&state.db)
                 .await
-                .unwrap_or(0);
-            
-            if count > 0 {
-                return Ok(next.run(request).await);
+                .ok()?;
+        Some((node, pending))
+    }
+    .await;
+    let Some((mut node, mut pending)) = row else {
+        return message("#dc3545", "Node not found");
+    };
+    if pending.is_none()
+        && let Some(mut con) = state.redis.connection()
+    {
+        let res: Result<Option<String>, _> =
+            redis::AsyncCommands::get(&mut con, format!("node:{}:pending_token", id)).await;
+        state.redis.observe(&res);
+        pending = res.ok().flatten();
+    }
+    let pending = pending.map(|p| node_tokens::open_or_empty(&state.secrets_key, &node.id, &p));
+    let mut candidates = vec![node.token.clone()];
+    candidates.extend(pending.filter(|p| !candidates.contains(p)));
+
+    // The node verifies the new token against the panel before keeping it
+    let res = sqlx::query("UPDATE nodes SET token = $1, token_rotated_at = NOW() WHERE id = $2")
+        .bind(node_tokens::seal(&state.secrets_key, new_token))
+        .bind(id)
+        .execute(&state.db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to force-set token of node {}: {}", id, e);
+        return message("#dc3545", "Failed to store the token; check the panel logs");
+    }
+    if let Err(e) = clear_token_state(state, id).await {
+        tracing::error!("Failed to clear pending token of node {}: {}", id, e);
+    }
+    tracing::warn!("Node {} token force-set by {}", id, username);
+
+    let mut last_error = String::new();
+    for candidate in candidates.into_iter().filter(|c| !c.is_empty()) {
+        node.token = candidate;
+        match node_api::update_token(&state.http_client, &node, new_token, &state.node_retry).await
+        {
+            Ok(()) => return message("#28a745", "Token set on the panel and the node"),
+            Err(e) if e.is_auth_failure() => {
+                last_error = "the node rejected every token the panel knew".to_string()
+            }
+            Err(e) => {
+                last_error = e.to_string();
+                break;
+            }
+        }
+    }
+    tracing::error!(
+        "Force-set token of node {} stored but not pushed: {}",
+        id,
+        last_error
+    );
+    message(
+        "#856404",
+        &format!(
+            "Token saved in the panel, but not on the node ({}). Put it in the node's config.yml by hand or re-provision the node.",
+            html_escape(&last_error)
+        ),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ClearPendingTokenRequest {
+    password: String,
+}
+
+/// Forgets a half-finished rotation so only the stored token authenticates the node.
+/// Gated like `force_token_handler`.
+pub async fn clear_pending_token_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    jar: CookieJar,
+    Form(payload): Form<ClearPendingTokenRequest>,
+) -> Response {
+    let user = match recovery_admin(&state, &jar, &payload.password).await {
+        Ok(user) => user,
+        Err(refusal) => {
+            tracing::warn!("Refused pending token clear for node {}: {}", id, refusal);
+            return Html(format!(
+                r#"<span style="color: #dc3545; font-size: 0.9em;">{}</span>"#,
+                refusal
+            ))
+            .into_response();
+        }
+    };
+    match clear_token_state(&state, id).await {
+        Ok(()) => {
+            tracing::info!("Cleared pending token of node {} for {}", id, user.username);
+            Html(r#"<span style="color: #28a745; font-size: 0.9em;">Pending token cleared</span>"#)
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to clear pending token of node {}: {}", id, e);
+            Html(r#"<span style="color: #dc3545; font-size: 0.9em;">Failed to clear the pending token</span>"#).into_response()
+        }
+    }
+}
+
+/// `AUTH_MODE`: which of the panel's routes need a session.
+///
+/// - `all` (default): every page and action.
+/// - `actions_only`: the overview, node and server lists are public (`PUBLIC_PAGES`); every other
+///   page and every action needs a session.
+/// - `off`: no session checks at all; only for panels behind another auth layer (VPN, reverse proxy).
+///
+/// Handlers that check the session themselves (token reveal and recovery, install tests) keep doing so in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    All,
+    ActionsOnly,
+    Off,
+}
+
+/// Pages `actions_only` serves without a session. Only exact paths: node and server pages below
+/// them can mint install tokens or show logs and config, so they always need a login.
+const PUBLIC_PAGES: [&str; 4] = ["/", "/nodes", "/servers", "/overview/stats"];
+
+impl AuthMode {
+    pub fn from_env() -> Self {
+        match std::env::var("AUTH_MODE").as_deref() {
+            Ok("actions_only") => AuthMode::ActionsOnly,
+            Ok("off") => AuthMode::Off,
+            Ok("all") | Err(_) => AuthMode::All,
+            Ok(other) => {
+                tracing::warn!("Unknown AUTH_MODE '{}', falling back to 'all'", other);
+                AuthMode::All
+            }
+        }
+    }
+
+    pub fn requires_session(self, method: &Method, path: &str) -> bool {
+        match self {
+            AuthMode::All => true,
+            AuthMode::ActionsOnly => {
+                !(matches!(*method, Method::GET | Method::HEAD) && PUBLIC_PAGES.contains(&path))
+            }
+            AuthMode::Off => false,
+        }
+    }
+}
+
+/// Flags on the session cookie: `COOKIE_SECURE` (`true`, `false` or `auto`; default `true`)
+/// and `COOKIE_SAMESITE` (`lax`, `strict` or `none`; default `lax`).
+#[derive(Debug, Clone, Copy)]
+pub struct SessionCookieConfig {
+    pub secure: CookieSecure,
+    pub same_site: SameSite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieSecure {
+    Always,
+    Never,
+    /// Only when the login request arrived over HTTPS, directly or via a proxy
+    Auto,
+}
+
+impl SessionCookieConfig {
+    pub fn from_env() -> Self {
+        let secure = match std::env::var("COOKIE_SECURE").as_deref().map(str::trim) {
+            Ok("true") | Err(_) => CookieSecure::Always,
+            Ok("false") => CookieSecure::Never,
+            Ok("auto") => CookieSecure::Auto,
+            Ok(other) => {
+                tracing::warn!("Unknown COOKIE_SECURE '{}', falling back to 'true'", other);
+                CookieSecure::Always
             }
+        };
+        let same_site = match std::env::var("COOKIE_SAMESITE")
+            .map(|v| v.trim().to_ascii_lowercase())
+            .as_deref()
+        {
+            Ok("lax") | Err(_) => SameSite::Lax,
+            Ok("strict") => SameSite::Strict,
+            Ok("none") => SameSite::None,
+            Ok(other) => {
+                tracing::warn!("Unknown COOKIE_SAMESITE '{}', falling back to 'lax'", other);
+                SameSite::Lax
+            }
+        };
+        Self { secure, same_site }
+    }
+
+    /// The `session_id` cookie for a login that arrived over HTTPS or not.
+    pub fn build(&self, session_id: String, https: bool) -> Cookie<'static> {
+        let secure = match self.secure {
+            CookieSecure::Always => true,
+            CookieSecure::Never => false,
+            CookieSecure::Auto => https,
+        };
+        Cookie::build(("session_id", session_id))
+            .path("/")
+            .http_only(true)
+            .secure(secure)
+            .same_site(self.same_site)
+            .max_age(time::Duration::days(7))
+            .build()
+    }
+}
+
+/// What the signed-in user may do, for pages that hide controls they can't use.
+/// `auth_middleware` sets it (read-only without a session); requests it never saw
+/// (`AUTH_MODE=off`) get full access.
+#[derive(Debug, Clone, Copy)]
+pub struct Viewer {
+    /// False for auditors
+    pub can_modify: bool,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Viewer {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Viewer>()
+            .copied()
+            .unwrap_or(Viewer { can_modify: true }))
+    }
+}
+
+/// Whether a request changes anything. Besides non-GET methods that's the GET routes
+/// with side effects: running an install test and minting a node install token.
+fn is_mutation(request: &Request) -> bool {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return true;
+    }
+    let path = request.uri().path();
+    path.ends_with("/test-install") || (path.starts_with("/nodes/") && path.ends_with("/setup"))
+}
+
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let user = session_user(&state, &jar).await;
+    let Some(user) = user else {
+        if state
+            .auth_mode
+            .requires_session(request.method(), request.uri().path())
+        {
+            return Redirect::to("/auth/login").into_response();
+        }
+        // Anonymous readers get the pages without the controls they can't use anyway
+        request
+            .extensions_mut()
+            .insert(Viewer { can_modify: false });
+        return next.run(request).await;
+    };
+
+    if user.is_auditor() && is_mutation(&request) {
+        tracing::warn!(
+            "Auditor {} tried {} {}",
+            user.username,
+            request.method(),
+            request.uri().path()
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            "This account has read-only (auditor) access and can't change anything",
+        )
+            .into_response();
+    }
+
+    request.extensions_mut().insert(Viewer {
+        can_modify: !user.is_auditor(),
+    });
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_only_serves_listed_pages_without_a_session() {
+        for path in PUBLIC_PAGES {
+            assert!(
+                !AuthMode::ActionsOnly.requires_session(&Method::GET, path),
+                "{}",
+                path
+            );
+            assert!(
+                !AuthMode::ActionsOnly.requires_session(&Method::HEAD, path),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn actions_only_requires_a_session_for_token_and_log_pages() {
+        let id = "7f4b2a52-3c1e-4d59-9a57-0c6f1e2d3b4a";
+        for path in [
+            format!("/nodes/{}/setup", id),
+            format!("/nodes/{}/edit", id),
+            format!("/nodes/{}/agent-config", id),
+            format!("/servers/{}/stored-logs", id),
+            format!("/servers/{}/install-log", id),
+            format!("/api/jobs/{}", id),
+            "/api/search".to_string(),
+            "/nodes/".to_string(),
+        ] {
+            assert!(
+                AuthMode::ActionsOnly.requires_session(&Method::GET, &path),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn actions_only_requires_a_session_for_actions_on_public_pages() {
+        for method in [Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            assert!(AuthMode::ActionsOnly.requires_session(&method, "/servers"));
+            assert!(AuthMode::ActionsOnly.requires_session(&method, "/nodes"));
+        }
+    }
+
+    #[test]
+    fn all_and_off_ignore_the_path() {
+        assert!(AuthMode::All.requires_session(&Method::GET, "/"));
+        assert!(AuthMode::All.requires_session(&Method::GET, "/nodes"));
+        assert!(!AuthMode::Off.requires_session(&Method::POST, "/nodes"));
+        assert!(!AuthMode::Off.requires_session(&Method::GET, "/api/jobs/1"));
+    }
+
+    fn cookie(secure: CookieSecure, same_site: SameSite, https: bool) -> Cookie<'static> {
+        SessionCookieConfig { secure, same_site }.build("abc123".to_string(), https)
+    }
+
+    #[test]
+    fn session_cookie_is_scoped_and_http_only() {
+        let c = cookie(CookieSecure::Always, SameSite::Lax, true);
+        assert_eq!(c.name(), "session_id");
+        assert_eq!(c.value(), "abc123");
+        assert_eq!(c.path(), Some("/"));
+        assert_eq!(c.http_only(), Some(true));
+        assert_eq!(c.max_age(), Some(time::Duration::days(7)));
+    }
+
+    #[test]
+    fn session_cookie_secure_flag_follows_the_config() {
+        assert_eq!(
+            cookie(CookieSecure::Always, SameSite::Lax, false).secure(),
+            Some(true)
+        );
+        assert_eq!(
+            cookie(CookieSecure::Never, SameSite::Lax, true).secure(),
+            Some(false)
+        );
+        assert_eq!(
+            cookie(CookieSecure::Auto, SameSite::Lax, true).secure(),
+            Some(true)
+        );
+        assert_eq!(
+            cookie(CookieSecure::Auto, SameSite::Lax, false).secure(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn session_cookie_uses_the_configured_same_site() {
+        for same_site in [SameSite::Lax, SameSite::Strict, SameSite::None] {
+            assert_eq!(
+                cookie(CookieSecure::Always, same_site, true).same_site(),
+                Some(same_site)
+            );
         }
     }
-    
-    Err(Redirect::to("/auth/login"))
 }
@@ -0,0 +1,109 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::CookieJar;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::http::handlers::auth::{AuthMode, session_user};
+use crate::models::{MAX_CONSOLE_COMMAND_LEN, PERMISSION_SERVER_COMMAND};
+use crate::services::{node_api, server_events};
+use crate::state::AppState;
+
+#[derive(Deserialize)]
+pub struct CommandRequest {
+    pub command: String,
+}
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": code, "message": message.into() })),
+    )
+        .into_response()
+}
+
+/// `POST /servers/{id}/command`: sends one line to the server's console without an
+/// interactive session. Needs the `server.command` permission, which plain console
+/// access doesn't grant. Every command is written to the server's event log.
+pub async fn command_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    jar: CookieJar,
+    Json(payload): Json<CommandRequest>,
+) -> Response {
+    let user = session_user(&state, &jar).await;
+    let allowed = match &user {
+        Some(user) => user.can(PERMISSION_SERVER_COMMAND),
+        None => state.auth_mode == AuthMode::Off,
+    };
+    if !allowed {
+        return error(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "You are not allowed to send console commands",
+        );
+    }
+
+    let command = payload.command.trim();
+    if command.is_empty()
+        || command.contains(['\r', '\n'])
+        || command.len() > MAX_CONSOLE_COMMAND_LEN
+    {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "invalid_command",
+            format!(
+                "Command must be a single line of at most {} characters",
+                MAX_CONSOLE_COMMAND_LEN
+            ),
+        );
+    }
+
+    let node_id: Option<String> =
+        sqlx::query_scalar("SELECT node_id::text FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+    let Some(node_id) = node_id else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "server_not_found",
+            "Server not found",
+        );
+    };
+    let Some(node) = state.get_node_with_token(&node_id).await else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "node_not_found",
+            "The server's node no longer exists",
+        );
+    };
+
+    if let Err(e) = node_api::send_command(
+        &state.http_client,
+        &node,
+        &id.to_string(),
+        command,
+        &state.node_retry,
+    )
+    .await
+    {
+        tracing::error!("Console command for server {} failed: {}", id, e);
+        return error(StatusCode::BAD_GATEWAY, "command_failed", e);
+    }
+
+    let by = user.map_or_else(|| "anonymous".to_string(), |u| u.username);
+    server_events::record(
+        &state.db,
+        id,
+        "command",
+        &format!("{} ran: {}", by, command),
+    )
+    .await;
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -1,8 +1,22 @@
 use crate::http::handlers::HtmlTemplate;
-use crate::{models::HeartbeatPayload, state::AppState};
+use crate::http::handlers::auth::Viewer;
+use crate::{
+    models::{HeartbeatPayload, Location, Node},
+    services::{
+        disks::{self, DiskGroup},
+        locations, maintenance,
+        versions::{self, VersionStatus},
+    },
+    state::AppState,
+};
 use askama::Template;
-use axum::{extract::State, http::HeaderMap, response::IntoResponse};
-use tracing::info;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Template)]
 #[template(path = "nodes.html")]
@@ -14,6 +28,22 @@ struct NodesTemplate {
     execution_time: f64,
     active_tab: String,
     nodes: Vec<NodeViewModel>,
+    locations: Vec<Location>,
+    /// Selected location filter, empty for all
+    location: String,
+    query: NodesPageQuery,
+    can_modify: bool,
+}
+
+/// Outcome of a node deletion, passed along by `delete_node_handler`'s redirect.
+#[derive(Deserialize, Default)]
+pub struct NodesPageQuery {
+    pub error: Option<String>,
+    pub removed: Option<usize>,
+    pub killed: Option<usize>,
+    pub failed: Option<usize>,
+    /// Only show nodes in this location
+    pub location: Option<String>,
 }
 
 struct NodeViewModel {
@@ -23,6 +53,9 @@ struct NodeViewModel {
     name: String,
     ip: String,
     port: i32,
+    location_name: String,
+    /// Set when a maintenance run gave up and left servers stopped
+    maintenance_alert: Option<String>,
     status_color: String,
     status_text: String,
     is_online: bool,
@@ -31,14 +64,17 @@ struct NodeViewModel {
     ram_total: u64,
     uptime_formatted: String,
     version: String,
+    version_status: VersionStatus,
     disk_usage: u64,
     disk_total: u64,
-    disks: Vec<crate::models::DiskDetail>,
+    disks: Vec<DiskGroup>,
 }
 
 pub async fn nodes_page_handler(
     State(state): State<AppState>,
-    _headers: HeaderMap,
+    viewer: Viewer,
+    Query(query): Query<NodesPageQuery>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
@@ -46,7 +82,38 @@ pub async fn nodes_page_handler(
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
 
-    let nodes_data = state.get_nodes().await;
+    let locations = locations::list(&state.db).await;
+    let location = locations::parse_filter(query.location.as_deref()).map(|id| id.to_string());
+    let mut maintenance_alerts = maintenance::alerts(&state.db).await;
+    let disk_labels = disks::all_labels(&state.db).await;
+    let mut nodes_data = state.get_nodes().await;
+    if let Some(location) = &location {
+        nodes_data.retain(|n| &n.location_id == location);
+    }
+
+    let mut stats: HashMap<String, HeartbeatPayload> = HashMap::new();
+    for node in &nodes_data {
+        if let Some(payload) = state.node_stats(&node.id).await {
+            stats.insert(node.id.clone(), payload);
+        }
+    }
+
+    // Nodes without a heartbeat get a quick /health probe on full page loads only;
+    // the 5s htmx refresh relies on heartbeats alone.
+    let is_htmx_refresh = headers.contains_key("HX-Request") && !headers.contains_key("HX-Boosted");
+    let reachable: HashSet<String> = if is_htmx_refresh {
+        HashSet::new()
+    } else {
+        let silent: Vec<Node> = nodes_data
+            .iter()
+            .filter(|n| !stats.contains_key(&n.id))
+            .cloned()
+            .collect();
+        state
+            .health_probes
+            .probe_all(&state.http_client, silent)
+            .await
+    };
 
     let mut view_nodes = Vec::new();
 
@@ -61,36 +128,14 @@ pub async fn nodes_page_handler(
         let mut disk_total = 0;
         let mut disks = Vec::new();
         let mut uptime_formatted = "0s".to_string();
-        let mut version = node.version.clone();
-
-        let mut payload_opt: Option<HeartbeatPayload> = None;
-
-        // 1. Try Redis
-        if let Some(manager) = &state.redis {
-            let mut con = manager.clone();
-            let key = format!("node:{}:stats", node.id);
-            // info!("[TRACE] Dashboard Querying Key: {}", key);
-            let stats_json: Result<String, _> = redis::AsyncCommands::get(&mut con, &key).await;
-
-            if let Ok(json_str) = stats_json {
-                info!("[TRACE] Redis HIT for {}: {}", node.id, json_str);
-                if let Ok(payload) = serde_json::from_str::<HeartbeatPayload>(&json_str) {
-                    payload_opt = Some(payload);
-                }
-            }
-        }
 
-        // 2. Fallback to Memory
-        if payload_opt.is_none() {
-            let sub_lock = state.heartbeats_cache.read().await;
-            if let Some(payload) = sub_lock.get(&node.id) {
-                // Check if timestamp is fresh (e.g. < 20 seconds old)
-                let now = chrono::Utc::now().timestamp_millis();
-                if (now - payload.timestamp) < 20000 {
-                    info!("[TRACE] Memory Cache HIT for {}", node.id);
-                    payload_opt = Some(payload.clone());
-                }
-            }
+        let payload_opt = stats.remove(&node.id);
+        let version = versions::current_version(&node.version, payload_opt.as_ref());
+        let version_status = VersionStatus::of(&version);
+
+        if payload_opt.is_none() && reachable.contains(&node.id) {
+            status_color = "orange".to_string();
+            status_text = "Reachable (no heartbeat)".to_string();
         }
 
         if let Some(payload) = payload_opt {
@@ -102,7 +147,10 @@ pub async fn nodes_page_handler(
             ram_total = payload.ram_total / 1024 / 1024;
             disk_usage = payload.disk_usage / 1024 / 1024;
             disk_total = payload.disk_total / 1024 / 1024;
-            disks = payload.disks.clone();
+            disks = disks::group(
+                &payload.disks,
+                disk_labels.get(&node.id).unwrap_or(&HashMap::new()),
+            );
 
             // Format Uptime
             if payload.uptime < 60 {
@@ -116,11 +164,17 @@ pub async fn nodes_page_handler(
                 let mins = (payload.uptime % 3600) / 60;
                 uptime_formatted = format!("{}h {}m", hours, mins);
             }
-
-            version = payload.version;
         }
 
+        let location_name = locations
+            .iter()
+            .find(|l| l.id == node.location_id)
+            .map(|l| l.name.clone())
+            .unwrap_or_default();
+
         view_nodes.push(NodeViewModel {
+            location_name,
+            maintenance_alert: maintenance_alerts.remove(&node.id),
             id: node.id.clone(),
             id_short: node.id[..8].to_string(),
             name: node.name,
@@ -134,6 +188,7 @@ pub async fn nodes_page_handler(
             ram_total,
             uptime_formatted,
             version,
+            version_status,
             disk_usage,
             disk_total,
             disks,
@@ -144,6 +199,7 @@ pub async fn nodes_page_handler(
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(NodesTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -151,5 +207,8 @@ pub async fn nodes_page_handler(
         execution_time,
         active_tab: "nodes".to_string(),
         nodes: view_nodes,
+        locations,
+        location: location.unwrap_or_default(),
+        query,
     })
 }
@@ -0,0 +1,178 @@
+use crate::http::handlers::runtimes::egg_export;
+use crate::models::Image;
+use crate::services::images;
+use crate::services::node_tokens;
+use crate::services::signed_urls::{self, DownloadKind, SignedUrlError};
+use crate::state::AppState;
+use axum::{
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+/// Controlled downloads (the node agent binary). Unlike `/public`, nothing in here is served
+/// without a node token or a signed link.
+pub const ARTIFACTS_DIR: &str = "panel/artifacts";
+
+#[derive(Deserialize)]
+pub struct ArtifactQuery {
+    /// Signed token from `signed_urls` (kind `artifact`), as embedded in the install script
+    t: Option<String>,
+}
+
+/// Serves a signed `/download/{token}` URL. No session required; the token is the credential.
+pub async fn download_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Response {
+    let secret = state.download_secret.read().await.clone();
+    let resource = match signed_urls::verify(&secret, &token, chrono::Utc::now().timestamp()) {
+        Ok(r) => r,
+        Err(SignedUrlError::Expired) => {
+            return (StatusCode::GONE, "This download link has expired").into_response();
+        }
+        Err(e) => {
+            tracing::warn!("Rejected download token: {:?}", e);
+            return (StatusCode::FORBIDDEN, "Invalid download link").into_response();
+        }
+    };
+
+    match resource.kind {
+        DownloadKind::Artifact => {
+            Redirect::to(&format!("/downloads/{}?t={}", resource.id, token)).into_response()
+        }
+        DownloadKind::ImageExport => {
+            let image = sqlx::query_as::<_, Image>(&format!(
+                "SELECT {} FROM images WHERE id = $1::uuid",
+                images::IMAGE_COLUMNS
+            ))
+            .bind(&resource.id)
+            .fetch_optional(&state.db)
+            .await;
+
+            let image = match image {
+                Ok(Some(i)) => i,
+                Ok(None) => return (StatusCode::NOT_FOUND, "Image not found").into_response(),
+                Err(e) => {
+                    tracing::error!("Failed to load image {} for export: {}", resource.id, e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+                }
+            };
+
+            let body = serde_json::to_string_pretty(&egg_export(&image)).unwrap_or_default();
+            let filename: String = image
+                .name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '-'
+                    }
+                })
+                .collect();
+
+            (
+                [
+                    (header::CONTENT_TYPE, "application/json".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"egg-{}.json\"", filename),
+                    ),
+                    (header::CACHE_CONTROL, "no-store".to_string()),
+                ],
+                body,
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Serves `/downloads/{file}` from `ARTIFACTS_DIR` to a node (bearer node token, used by
+/// self-update) or to a signed link for that file (install script). Range requests,
+/// content type and length come from `ServeFile`, so interrupted downloads can resume.
+pub async fn artifact_handler(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    Query(query): Query<ArtifactQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> Response {
+    // One plain file name; no dotfiles, no traversal, no directories to list
+    let valid_name = !file.starts_with('.')
+        && file
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !valid_name {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+
+    if !artifact_access_allowed(&state, &file, &headers, query.t.as_deref()).await {
+        tracing::warn!("Rejected unauthenticated download of {}", file);
+        return (
+            StatusCode::UNAUTHORIZED,
+            "A node token or a signed link is required",
+        )
+            .into_response();
+    }
+
+    let path = std::path::Path::new(ARTIFACTS_DIR).join(&file);
+    let Ok(mut res) = ServeFile::new(path).oneshot(request).await;
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    res.map(Body::new)
+}
+
+async fn artifact_access_allowed(
+    state: &AppState,
+    file: &str,
+    headers: &HeaderMap,
+    signed: Option<&str>,
+) -> bool {
+    if let Some(token) = signed {
+        let secret = state.download_secret.read().await.clone();
+        return matches!(
+            signed_urls::verify(&secret, token, chrono::Utc::now().timestamp()),
+            Ok(r) if r.kind == DownloadKind::Artifact && r.id == file
+        );
+    }
+
+    let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .filter(|t| !t.is_empty())
+    else {
+        return false;
+    };
+    // Tokens are sealed with a random nonce, so they can't be matched in SQL
+    let nodes: Vec<(String, String)> =
+        sqlx::query_as("SELECT id::text, COALESCE(token, '') FROM nodes")
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default();
+    nodes.iter().any(|(id, stored)| {
+        node_tokens::matches(
+            token,
+            &node_tokens::open_or_empty(&state.secrets_key, id, stored),
+        )
+    })
+}
+
+/// Replaces the signing secret, invalidating every download URL handed out so far.
+pub async fn rotate_download_secret_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let secret = signed_urls::generate_secret();
+    signed_urls::persist_secret(&secret);
+    *state.download_secret.write().await = secret;
+    tracing::info!("Download signing secret rotated");
+
+    Html(
+        r#"<div id="download-secret-message" hx-swap-oob="true" style="color: #28a745; margin-top: 10px; font-weight: bold;">
+            Secret rotated. All previously issued download links are now invalid.
+        </div>"#,
+    )
+}
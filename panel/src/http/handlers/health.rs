@@ -0,0 +1,23 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+use crate::state::AppState;
+
+/// `GET /health`: whether the panel can reach Postgres, plus the panel version and the
+/// schema version it migrated to at startup. Answers 503 while the database is down.
+pub async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let database = sqlx::query("SELECT 1").execute(&state.db).await.is_ok();
+    let status = if database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if database { "ok" } else { "database_unreachable" },
+            "version": env!("CARGO_PKG_VERSION"),
+            "schema_version": state.schema_version,
+        })),
+    )
+}
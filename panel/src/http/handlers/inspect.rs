@@ -0,0 +1,142 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::CookieJar;
+use uuid::Uuid;
+
+use crate::http::handlers::auth::session_user;
+use crate::models::Variable;
+use crate::services::node_api;
+use crate::state::AppState;
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": code, "message": message.into() })),
+    )
+        .into_response()
+}
+
+/// Blanks the values of the image's secret variables in `Config.Env` (`NAME=value` strings).
+fn redact_secrets(inspect: &mut serde_json::Value, secret_names: &[String]) {
+    let Some(env) = inspect
+        .pointer_mut("/Config/Env")
+        .and_then(|e| e.as_array_mut())
+    else {
+        return;
+    };
+    for entry in env {
+        let Some((name, _)) = entry.as_str().and_then(|e| e.split_once('=')) else {
+            continue;
+        };
+        if secret_names.iter().any(|s| s == name) {
+            *entry = serde_json::Value::String(format!("{}=[redacted]", name));
+        }
+    }
+}
+
+/// `GET /servers/{id}/inspect`: the container's `docker inspect` from its node, so operators
+/// don't need a shell on the node to debug it. Admin only; secret variables are redacted.
+pub async fn inspect_server_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    jar: CookieJar,
+) -> Response {
+    if !session_user(&state, &jar)
+        .await
+        .is_some_and(|u| u.is_admin())
+    {
+        return error(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "Only admins can inspect containers",
+        );
+    }
+
+    let row: Option<(String, String)> = sqlx::query_as(
+        "SELECT s.node_id::text, COALESCE(i.variables::text, '[]') FROM servers s \
+         LEFT JOIN images i ON i.id = s.image_id WHERE s.id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    let Some((node_id, variables)) = row else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "server_not_found",
+            "Server not found",
+        );
+    };
+    let Some(node) = state.get_node_with_token(&node_id).await else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "node_not_found",
+            "The server's node no longer exists",
+        );
+    };
+
+    match node_api::inspect_container(
+        &state.http_client,
+        &node,
+        &id.to_string(),
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(Some(mut inspect)) => {
+            let secret_names: Vec<String> = serde_json::from_str::<Vec<Variable>>(&variables)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|v| v.is_secret)
+                .map(|v| v.env_variable)
+                .collect();
+            redact_secrets(&mut inspect, &secret_names);
+            Json(inspect).into_response()
+        }
+        Ok(None) => error(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            "The node has no container for this server",
+        ),
+        Err(e) => {
+            tracing::error!("Inspecting the container of server {} failed: {}", id, e);
+            error(StatusCode::BAD_GATEWAY, "inspect_failed", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_secret_values_are_redacted() {
+        let mut inspect = serde_json::json!({
+            "Config": { "Env": ["RCON_PASSWORD=hunter2", "MOTD=a=b", "PATH=/usr/bin", "BROKEN"] }
+        });
+        redact_secrets(
+            &mut inspect,
+            &["RCON_PASSWORD".to_string(), "MOTD".to_string()],
+        );
+        assert_eq!(
+            inspect["Config"]["Env"],
+            serde_json::json!([
+                "RCON_PASSWORD=[redacted]",
+                "MOTD=[redacted]",
+                "PATH=/usr/bin",
+                "BROKEN"
+            ])
+        );
+    }
+
+    #[test]
+    fn inspect_without_env_is_left_alone() {
+        let mut inspect = serde_json::json!({ "Config": { "Env": null } });
+        redact_secrets(&mut inspect, &["RCON_PASSWORD".to_string()]);
+        assert_eq!(inspect, serde_json::json!({ "Config": { "Env": null } }));
+    }
+}
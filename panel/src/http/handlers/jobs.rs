@@ -0,0 +1,36 @@
+use crate::{http::handlers::HtmlTemplate, models::Job, services::jobs, state::AppState};
+use askama::Template;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "job_status.html")]
+struct JobStatusTemplate {
+    job: Job,
+}
+
+/// `GET /api/jobs/{id}`: job status as JSON.
+pub async fn job_status_handler(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    match jobs::get(&state.db, id).await {
+        Some(job) => Json(job).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "job_not_found" })),
+        )
+            .into_response(),
+    }
+}
+
+/// htmx fragment showing a job's progress. It polls itself until the job finishes, so
+/// pages embed it with `hx-get="/jobs/{id}" hx-trigger="load"`.
+pub async fn job_fragment_handler(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    match jobs::get(&state.db, id).await {
+        Some(job) => HtmlTemplate(JobStatusTemplate { job }).into_response(),
+        None => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::Viewer;
+use crate::{
+    models::Location,
+    services::locations::{self, MAX_NAME_LEN, UNASSIGNED},
+    state::AppState,
+};
+use askama::Template;
+use axum::{
+    extract::{Form, Path, Query, State},
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "locations.html")]
+struct LocationsTemplate {
+    panel_name: String,
+    panel_font: String,
+    panel_font_url: String,
+    panel_version: String,
+    execution_time: f64,
+    active_tab: String,
+    locations: Vec<Location>,
+    unassigned_id: String,
+    query: LocationsPageQuery,
+    can_modify: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LocationsPageQuery {
+    pub error: Option<String>,
+    /// The name that was taken, for `name_taken`
+    pub name: Option<String>,
+    /// Nodes moved to "Unassigned" by the last delete
+    pub moved: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct LocationForm {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl LocationForm {
+    fn cleaned(&self) -> Option<(String, String)> {
+        let name: String = self.name.trim().chars().take(MAX_NAME_LEN).collect();
+        if name.is_empty() {
+            return None;
+        }
+        Some((name, self.description.trim().to_string()))
+    }
+}
+
+/// `error=name_taken&name=...` for a redirect back to the page
+fn name_taken_query(name: &str) -> String {
+    serde_urlencoded::to_string([("error", "name_taken"), ("name", name)])
+        .unwrap_or_else(|_| "error=name_taken".to_string())
+}
+
+pub async fn locations_page_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<LocationsPageQuery>,
+) -> impl IntoResponse {
+    let start_time = std::time::Instant::now();
+    let panel_version = env!("CARGO_PKG_VERSION").to_string();
+    let panel_name = state.panel_name.read().await.clone();
+    let panel_font = state.panel_font.read().await.clone();
+    let panel_font_url = state.panel_font_url.read().await.clone();
+
+    let locations = locations::list(&state.db).await;
+
+    let elapsed = start_time.elapsed();
+    let execution_time = elapsed.as_secs_f64() * 1000.0;
+
+    HtmlTemplate(LocationsTemplate {
+        can_modify: viewer.can_modify,
+        panel_name,
+        panel_font,
+        panel_font_url,
+        panel_version,
+        execution_time,
+        active_tab: "nodes".to_string(),
+        locations,
+        unassigned_id: UNASSIGNED.to_string(),
+        query,
+    })
+}
+
+pub async fn create_location_handler(
+    State(state): State<AppState>,
+    Form(form): Form<LocationForm>,
+) -> Redirect {
+    let Some((name, description)) = form.cleaned() else {
+        return Redirect::to("/locations?error=name_required");
+    };
+    match locations::create(&state.db, &name, &description).await {
+        Ok(id) => tracing::info!("Created location {} ({})", name, id),
+        Err(e) if locations::is_duplicate_name(&e) => {
+            return Redirect::to(&format!("/locations?{}", name_taken_query(&name)));
+        }
+        Err(e) => {
+            tracing::error!("Failed to create location {}: {}", name, e);
+            return Redirect::to("/locations?error=save_failed");
+        }
+    }
+    Redirect::to("/locations")
+}
+
+pub async fn update_location_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<LocationForm>,
+) -> Redirect {
+    let Some((name, description)) = form.cleaned() else {
+        return Redirect::to("/locations?error=name_required");
+    };
+    match locations::update(&state.db, id, &name, &description).await {
+        Ok(_) => {}
+        Err(e) if locations::is_duplicate_name(&e) => {
+            return Redirect::to(&format!("/locations?{}", name_taken_query(&name)));
+        }
+        Err(e) => {
+            tracing::error!("Failed to update location {}: {}", id, e);
+            return Redirect::to("/locations?error=save_failed");
+        }
+    }
+    Redirect::to("/locations")
+}
+
+/// Deletes a location; its nodes move to "Unassigned" rather than going with it.
+pub async fn delete_location_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Redirect {
+    if id == UNASSIGNED {
+        return Redirect::to("/locations?error=builtin");
+    }
+    match locations::delete(&state.db, id).await {
+        Ok(moved) => {
+            tracing::info!(
+                "Deleted location {}; {} nodes moved to Unassigned",
+                id,
+                moved
+            );
+            if moved > 0 {
+                state.invalidate_nodes_cache().await;
+            }
+            Redirect::to(&format!("/locations?moved={}", moved))
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete location {}: {}", id, e);
+            Redirect::to("/locations?error=delete_failed")
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::Viewer;
 use crate::state::AppState;
 use askama::Template;
 use axum::{
@@ -36,10 +37,12 @@ struct LogsTemplate {
     has_logs: bool,
     log_content: String,
     file_list: Vec<LogFile>,
+    can_modify: bool,
 }
 
 pub async fn logs_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
     Query(query): Query<LogsQuery>,
 ) -> Response {
     info!("[TRACE] -> logs_handler triggered");
@@ -65,10 +68,10 @@ pub async fn logs_handler(
             let mut files: Vec<String> = entries
                 .filter_map(|e| e.ok())
                 .filter_map(|e| {
-                    if let Some(name) = e.file_name().to_str() {
-                        if name.starts_with("panel.log") {
-                            return Some(name.to_string());
-                        }
+                    if let Some(name) = e.file_name().to_str()
+                        && name.starts_with("panel.log")
+                    {
+                        return Some(name.to_string());
                     }
                     None
                 })
@@ -197,6 +200,7 @@ pub async fn logs_handler(
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(LogsTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -1,20 +1,39 @@
-pub mod dashboard;
-pub mod nodes;
 pub mod allocations;
-pub mod auth;
 pub mod api;
-pub mod scripts;
+pub mod auth;
+pub mod command;
+pub mod dashboard;
+pub mod downloads;
+pub mod health;
+pub mod inspect;
+pub mod jobs;
+pub mod locations;
 pub mod logs;
+pub mod nodes;
 pub mod overview;
-pub mod servers;
+pub mod power;
+pub mod public_status;
 pub mod runtimes;
+pub mod runtimes_api;
+pub mod scripts;
+pub mod search;
+pub mod servers;
 
-use axum::response::{Html, IntoResponse, Response};
 use askama::Template;
 use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
 
 pub struct HtmlTemplate<T>(pub T);
 
+/// For values put into hand-written HTML, text or attribute, outside a template.
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 impl<T> IntoResponse for HtmlTemplate<T>
 where
     T: Template,
@@ -31,4 +50,35 @@ where
     }
 }
 
+/// The confirm fragment for a delete dry run.
+#[derive(Template)]
+#[template(path = "delete_preview.html")]
+struct DeletePreviewTemplate {
+    preview: crate::services::delete_previews::DeletePreview,
+}
+
+/// Answers a delete dry run: the confirm fragment for htmx, JSON for everyone else.
+pub fn delete_preview_response(
+    preview: crate::services::delete_previews::DeletePreview,
+    headers: &axum::http::HeaderMap,
+) -> Response {
+    if headers.contains_key("HX-Request") {
+        HtmlTemplate(DeletePreviewTemplate { preview }).into_response()
+    } else {
+        axum::Json(preview).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn html_escape_covers_text_and_attributes() {
+        assert_eq!(
+            html_escape(r#"<a href="x" title='y'>&amp;</a>"#),
+            "&lt;a href=&quot;x&quot; title=&#39;y&#39;&gt;&amp;amp;&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("plain text"), "plain text");
+    }
+}
@@ -1,13 +1,33 @@
+use crate::http::handlers::auth::Viewer;
+use crate::http::handlers::{HtmlTemplate, delete_preview_response};
+use crate::{
+    models::{
+        CreateNodeRequest, DockerSummary, Location, Node, NodeAgentConfig, NodeEvent,
+        NodeMaintenance, NodeUpdateStatus, NodeVersionChange, UpdateNodeRequest,
+    },
+    services::{
+        allocations::parse_port_range,
+        delete_previews,
+        disks::{self, DiskGroup},
+        host_ports::{self, PortClaim, Verdict},
+        install_tokens::{self, TokenPurpose},
+        locations,
+        maintenance::{self, Window},
+        node_api::{self, read_node_error},
+        node_cleanup, node_events, node_tokens, node_versions,
+        versions::{self, VersionStatus},
+    },
+    state::AppState,
+};
+use askama::Template;
 use axum::{
-    extract::{State, Path, Form},
-    response::{Redirect, IntoResponse},
-    http::HeaderMap,
+    extract::{Form, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Redirect},
 };
-use std::collections::HashSet;
-use crate::{state::AppState, models::{Node, CreateNodeRequest, UpdateNodeRequest}};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
-use askama::Template;
-use crate::http::handlers::HtmlTemplate;
 
 #[derive(Template)]
 #[template(path = "node_create.html")]
@@ -18,6 +38,12 @@ struct CreateNodeTemplate {
     panel_version: String,
     execution_time: f64,
     active_tab: String,
+    error: Option<String>,
+    /// The name that was taken, for `name_taken`
+    taken_name: Option<String>,
+    port_conflict: Option<String>,
+    locations: Vec<Location>,
+    can_modify: bool,
 }
 
 #[derive(Template)]
@@ -32,7 +58,114 @@ struct EditNodeTemplate {
     node: Node,
     found: bool,
     install_cmd: String,
+    /// Only while decommissioning; carries a one-time uninstall token
     uninstall_cmd: String,
+    decommission: Option<DecommissionStatus>,
+    server_count: i64,
+    token_rotated_at: Option<String>,
+    version_history: Vec<NodeVersionChange>,
+    update_status: Option<NodeUpdateStatus>,
+    agent_version: String,
+    version_status: VersionStatus,
+    expected_version: &'static str,
+    error: Option<String>,
+    taken_name: Option<String>,
+    port_conflict: Option<String>,
+    locations: Vec<Location>,
+    maintenance: NodeMaintenance,
+    /// Next window start, when one is set and enabled
+    maintenance_next: Option<String>,
+    node_events: Vec<NodeEvent>,
+    /// Disks from the latest heartbeat, for labelling
+    disks: Vec<DiskGroup>,
+    can_modify: bool,
+}
+
+/// Progress of a node being decommissioned, for the edit page.
+struct DecommissionStatus {
+    started_at: String,
+    script_fetched_at: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NodeFormQuery {
+    pub error: Option<String>,
+    pub name: Option<String>,
+    /// What the ports clash with, for `port_conflict`
+    pub detail: Option<String>,
+}
+
+/// Case-insensitive unique index on node names (migration 0009)
+const NODE_NAME_INDEX: &str = "nodes_name_lower_key";
+
+fn is_duplicate_name(e: &sqlx::Error) -> bool {
+    e.as_database_error().and_then(|d| d.constraint()) == Some(NODE_NAME_INDEX)
+}
+
+/// `error=name_taken&name=...` for a redirect back to the form
+fn name_taken_query(name: &str) -> String {
+    serde_urlencoded::to_string([("error", "name_taken"), ("name", name)])
+        .unwrap_or_else(|_| "error=name_taken".to_string())
+}
+
+/// Daemon, SFTP and initial allocation ports of a node at `ip` that something else on that
+/// host already holds: the panel (only on a loopback IP, where it surely shares the host),
+/// other nodes' agents or their allocations. `node` is the node being edited, if any.
+async fn port_conflicts(
+    state: &AppState,
+    node: Option<Uuid>,
+    ip: &str,
+    port: i32,
+    sftp_port: i32,
+    allocations: &[i32],
+) -> Vec<String> {
+    let mut claims = match host_ports::claims(&state.db, state.listen_port, ip, node).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            tracing::error!("Failed to look up claimed ports on {}: {}", ip, e);
+            return Vec::new();
+        }
+    };
+    let panel_host = host_ports::is_panel_host(ip);
+
+    let mut conflicts = Vec::new();
+    for (what, port) in [("Daemon port", port), ("SFTP port", sftp_port)] {
+        if let Verdict::Reject(claim) = host_ports::verdict(&claims, port, panel_host) {
+            conflicts.push(format!("{} {} is {}", what, port, claim));
+        }
+    }
+    claims.insert(port, PortClaim::NodeDaemon("this node".to_string()));
+    claims.insert(sftp_port, PortClaim::NodeSftp("this node".to_string()));
+    for &allocation in allocations {
+        if let Verdict::Reject(claim) = host_ports::verdict(&claims, allocation, panel_host) {
+            conflicts.push(format!("Allocation {} is {}", allocation, claim));
+        }
+    }
+    conflicts
+}
+
+/// `error=port_conflict&detail=...` for a redirect back to the form
+fn port_conflict_query(conflicts: &[String]) -> String {
+    serde_urlencoded::to_string([
+        ("error", "port_conflict"),
+        ("detail", &conflicts.join("; ")),
+    ])
+    .unwrap_or_else(|_| "error=port_conflict".to_string())
+}
+
+#[derive(Template)]
+#[template(path = "node_agent_config.html")]
+struct NodeAgentConfigTemplate {
+    node: Node,
+    config: Option<NodeAgentConfig>,
+    error: String,
+}
+
+#[derive(Template)]
+#[template(path = "node_docker_summary.html")]
+struct NodeDockerSummaryTemplate {
+    summary: Option<DockerSummary>,
+    error: String,
 }
 
 #[derive(Template)]
@@ -47,26 +180,35 @@ struct SetupNodeTemplate {
     node: Node,
     install_cmd: String,
     found: bool,
+    can_modify: bool,
 }
 
 pub async fn create_node_page_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<NodeFormQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone(); // Added
+    let locations = locations::list(&state.db).await;
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(CreateNodeTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url, // Added
         panel_version,
         execution_time,
         active_tab: "nodes".to_string(),
+        error: query.error,
+        taken_name: query.name,
+        port_conflict: query.detail,
+        locations,
     })
 }
 
@@ -75,7 +217,9 @@ pub async fn create_node_handler(
     Form(payload): Form<CreateNodeRequest>,
 ) -> Redirect {
     // Validate Port
-    if (payload.port >= 0 && payload.port <= 1023) || (payload.sftp_port >= 0 && payload.sftp_port <= 1023) {
+    if (payload.port >= 0 && payload.port <= 1023)
+        || (payload.sftp_port >= 0 && payload.sftp_port <= 1023)
+    {
         eprintln!("Blocked attempt to create node on restricted port");
         return Redirect::to("/nodes/new");
     }
@@ -86,40 +230,65 @@ pub async fn create_node_handler(
         return Redirect::to("/nodes/new");
     }
 
+    let allocation_ports: Vec<i32> = payload
+        .allocation_ports
+        .as_deref()
+        .map(parse_ports)
+        .unwrap_or_default();
+    let conflicts = port_conflicts(
+        &state,
+        None,
+        &payload.ip,
+        payload.port,
+        payload.sftp_port,
+        &allocation_ports,
+    )
+    .await;
+    if !conflicts.is_empty() {
+        return Redirect::to(&format!("/nodes/new?{}", port_conflict_query(&conflicts)));
+    }
+
     let id = Uuid::new_v4().to_string();
-    let token = Uuid::new_v4().to_string();
+    let token = node_tokens::seal(&state.secrets_key, &Uuid::new_v4().to_string());
 
-    if let Err(e) = sqlx::query("INSERT INTO nodes (id, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit) VALUES ($1::uuid, $2, $3, $4, $5, $6, $7, $8, $9)")
+    if let Err(e) = sqlx::query("INSERT INTO nodes (id, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, location_id) VALUES ($1::uuid, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
         .bind(&id)
         .bind(&payload.name)
         .bind(&payload.ip)
-        .bind(&payload.port)
+        .bind(payload.port)
         .bind(&token)
-        .bind(&payload.sftp_port)
+        .bind(payload.sftp_port)
         .bind(payload.ram_limit.unwrap_or(0))
         .bind(payload.disk_limit.unwrap_or(0))
         .bind(payload.cpu_limit.unwrap_or(0))
+        .bind(payload.location_id.unwrap_or(locations::UNASSIGNED))
         .execute(&state.db)
-        .await 
+        .await
     {
+        if is_duplicate_name(&e) {
+            return Redirect::to(&format!("/nodes/new?{}", name_taken_query(&payload.name)));
+        }
         eprintln!("Failed to insert node: {}", e);
         return Redirect::to("/nodes");
     }
 
     // Process initial allocations if provided
-    if let Some(ports_str) = &payload.allocation_ports {
-        let ports = parse_ports(ports_str);
-        
+    if !allocation_ports.is_empty() {
+        let ports = allocation_ports;
+
         for port in &ports {
             if *port >= 0 && *port <= 1023 {
-                eprintln!("Blocked attempt to use restricted allocation port: {}", port);
+                eprintln!(
+                    "Blocked attempt to use restricted allocation port: {}",
+                    port
+                );
                 return Redirect::to("/nodes/new");
             }
         }
 
         let unique_ports: HashSet<i32> = ports.into_iter().collect();
         for port in unique_ports {
-             if port >= 0 && port <= 65535 {
+            if (0..=65535).contains(&port) {
                 let _ = sqlx::query("INSERT INTO allocations (id, node_id, ip, port) VALUES ($1::uuid, $2::uuid, $3, $4) ON CONFLICT DO NOTHING")
                     .bind(Uuid::new_v4().to_string())
                     .bind(&id)
@@ -127,7 +296,7 @@ pub async fn create_node_handler(
                     .bind(port)
                     .execute(&state.db)
                     .await;
-             }
+            }
         }
     }
 
@@ -139,147 +308,556 @@ pub async fn create_node_handler(
 
 pub async fn setup_node_page_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    viewer: Viewer,
+    Path(id): Path<Uuid>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
-    let host = headers.get("host").and_then(|h| h.to_str().ok()).unwrap_or("127.0.0.1:3000");
+    let base_url = state.base_url(&headers);
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone(); // Added
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
 
-    let node_result = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
+    let node_result = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1")
+        .bind(id)
         .fetch_optional(&state.db)
         .await;
 
     let (node, found, install_cmd) = match node_result {
         Ok(Some(n)) => {
-            let cmd = format!("curl -sSL http://{}/install/{} | sudo bash", host, n.id);
+            let cmd = install_command(&state, &base_url, &n.id).await;
             (n, true, cmd)
-        },
+        }
         _ => (
-            Node { 
-                id: "".to_string(), 
-                name: "".to_string(), 
-                ip: "".to_string(), 
-                port: 0, 
+            Node {
+                id: "".to_string(),
+                name: "".to_string(),
+                ip: "".to_string(),
+                port: 0,
                 token: "".to_string(),
                 sftp_port: 0,
                 ram_limit: 0,
                 disk_limit: 0,
                 cpu_limit: 0,
-                version: "".to_string()
+                version: "".to_string(),
+                auto_allocation_range: "".to_string(),
+                location_id: "".to_string(),
             },
             false,
-            "".to_string()
+            "".to_string(),
         ),
     };
 
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
-    HtmlTemplate(SetupNodeTemplate {
-        panel_font,
-        panel_font_url, // Added
-        panel_name,
-        panel_version,
-        execution_time,
-        active_tab: "nodes".to_string(),
-        node,
-        found,
-        install_cmd,
-    })
+    // The install command carries a live install token
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        HtmlTemplate(SetupNodeTemplate {
+            can_modify: viewer.can_modify,
+            panel_font,
+            panel_font_url, // Added
+            panel_name,
+            panel_version,
+            execution_time,
+            active_tab: "nodes".to_string(),
+            node,
+            found,
+            install_cmd,
+        }),
+    )
 }
 
-pub async fn delete_node_handler(
+#[derive(Template)]
+#[template(path = "node_install_command.html")]
+struct InstallCommandTemplate {
+    install_cmd: String,
+}
+
+/// Re-provisions a node for a fresh install (e.g. a host migration): rotates the node token so
+/// the old agent is locked out, then hands back a new single-use install command.
+pub async fn reprovision_node_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
-) -> impl axum::response::IntoResponse {
-    let _ = sqlx::query("DELETE FROM nodes WHERE id = $1::uuid")
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let base_url = state.base_url(&headers);
+    let new_token: String = rand::rng()
+        .sample_iter(&rand::distr::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    // A rotation finishing after this would store its own token over the new one
+    let Some(lock) = state.token_locks.acquire(id).await else {
+        return (
+            StatusCode::CONFLICT,
+            "A token rotation for this node is in progress; try again shortly",
+        )
+            .into_response();
+    };
+    let updated = sqlx::query("UPDATE nodes SET token = $1, token_rotated_at = NOW(), pending_token = NULL, pending_token_expires = NULL WHERE id = $2")
+        .bind(node_tokens::seal(&state.secrets_key, &new_token))
         .bind(id)
         .execute(&state.db)
         .await;
-    
+    state.token_locks.release(lock).await;
+
+    match updated {
+        Ok(r) if r.rows_affected() > 0 => {}
+        Ok(_) => return (StatusCode::NOT_FOUND, "Node not found").into_response(),
+        Err(e) => {
+            tracing::error!(
+                "Failed to rotate token for node {} during reprovision: {}",
+                id,
+                e
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to rotate the node token",
+            )
+                .into_response();
+        }
+    }
+    tracing::warn!(
+        "Node {} reprovisioned; its previous agent token is revoked",
+        id
+    );
+
+    // Drop cached node rows and the heartbeat token hash, same as a token rotation
+    state.invalidate_nodes_cache().await;
+    if let Some(mut con) = state.redis.connection() {
+        let key = format!("node:{}:cache", id);
+        let res: Result<(), _> = redis::AsyncCommands::del(&mut con, key).await;
+        state.redis.observe(&res);
+    }
+
+    let install_cmd = install_command(&state, &base_url, &id.to_string()).await;
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        HtmlTemplate(InstallCommandTemplate { install_cmd }),
+    )
+        .into_response()
+}
+
+/// `curl | bash` line for a node, pointing at a fresh single-use install token rather than
+/// the node token itself.
+async fn install_command(state: &AppState, base_url: &str, node_id: &str) -> String {
+    match install_tokens::issue(&state.db, node_id, TokenPurpose::Install).await {
+        Some(token) => format!(
+            "curl -sSL '{}/install/{}?t={}' | sudo bash",
+            base_url, node_id, token
+        ),
+        None => "Failed to issue an install token, reload the page".to_string(),
+    }
+}
+
+async fn uninstall_command(state: &AppState, base_url: &str, node_id: &str) -> String {
+    match install_tokens::issue(&state.db, node_id, TokenPurpose::Uninstall).await {
+        Some(token) => format!(
+            "curl -sSL '{}/uninstall/{}?t={}' | sudo bash",
+            base_url, node_id, token
+        ),
+        None => "Failed to issue an uninstall token, reload the page".to_string(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DecommissionForm {
+    /// Delete the node's remaining servers (and their containers) first
+    #[serde(default)]
+    pub force: Option<String>,
+}
+
+/// Starts decommissioning: the node takes no new servers and its edit page offers an
+/// uninstall command. The node row goes once the script reports back (see
+/// `scripts::complete_decommission_handler`). Servers still on the node block this unless
+/// `force` deletes them first.
+pub async fn decommission_node_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<DecommissionForm>,
+) -> Redirect {
+    let servers: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM servers WHERE node_id = $1")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .unwrap_or(0);
+
+    if servers > 0 {
+        if form.force.is_none() {
+            return Redirect::to(&format!("/nodes/{}/edit?error=servers_remain", id));
+        }
+        let summary = node_cleanup::remove_containers(&state, &id.to_string()).await;
+        if !summary.failed.is_empty() {
+            tracing::warn!(
+                "Decommissioning node {}: {} containers could not be removed",
+                id,
+                summary.failed.len()
+            );
+        }
+        if let Err(e) = sqlx::query("DELETE FROM servers WHERE node_id = $1")
+            .bind(id)
+            .execute(&state.db)
+            .await
+        {
+            tracing::error!("Failed to delete servers of node {}: {}", id, e);
+            return Redirect::to(&format!("/nodes/{}/edit?error=decommission_failed", id));
+        }
+    }
+
+    let res = sqlx::query(
+        "UPDATE nodes SET decommission_started_at = COALESCE(decommission_started_at, NOW()), decommission_script_fetched_at = NULL WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&state.db)
+    .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to start decommissioning node {}: {}", id, e);
+        return Redirect::to(&format!("/nodes/{}/edit?error=decommission_failed", id));
+    }
+    tracing::info!("Decommissioning node {}", id);
+    Redirect::to(&format!("/nodes/{}/edit", id))
+}
+
+/// Takes the node back into service and voids any uninstall command shown so far.
+pub async fn cancel_decommission_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Redirect {
+    let res = async {
+        let mut tx = state.db.begin().await?;
+        sqlx::query("UPDATE nodes SET decommission_started_at = NULL, decommission_script_fetched_at = NULL WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM node_install_tokens WHERE node_id = $1 AND purpose = 'uninstall'")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to cancel decommissioning node {}: {}", id, e);
+        return Redirect::to(&format!("/nodes/{}/edit?error=decommission_failed", id));
+    }
+    Redirect::to(&format!("/nodes/{}/edit", id))
+}
+
+#[derive(serde::Deserialize)]
+pub struct MaintenanceForm {
+    /// `daily HH:MM` or `<weekday> HH:MM`, UTC; empty turns the window off
+    #[serde(default)]
+    pub window: String,
+    #[serde(default)]
+    pub enabled: Option<String>,
+    #[serde(default)]
+    pub update: Option<String>,
+    #[serde(default)]
+    pub reboot: Option<String>,
+}
+
+/// Saves the node's maintenance window; see `services::maintenance`.
+pub async fn update_maintenance_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<MaintenanceForm>,
+) -> Redirect {
+    let raw = form.window.trim();
+    let window = match Window::parse(raw) {
+        Some(window) => Some(window),
+        None if raw.is_empty() => None,
+        None => return Redirect::to(&format!("/nodes/{}/edit?error=maintenance_window", id)),
+    };
+    let res = maintenance::save_settings(
+        &state.db,
+        id,
+        window,
+        form.enabled.is_some(),
+        form.update.is_some(),
+        form.reboot.is_some(),
+    )
+    .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to save maintenance window of node {}: {}", id, e);
+        return Redirect::to(&format!("/nodes/{}/edit?error=maintenance_failed", id));
+    }
+    Redirect::to(&format!("/nodes/{}/edit", id))
+}
+
+/// Disk labels by parent device (`/dev/sdb=Backups`); a blank one restores the generated name.
+pub async fn update_disk_labels_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(labels): Form<HashMap<String, String>>,
+) -> Redirect {
+    if let Err(e) = disks::save_labels(&state.db, id, &labels).await {
+        tracing::error!("Failed to save disk labels of node {}: {}", id, e);
+        return Redirect::to(&format!("/nodes/{}/edit?error=disk_labels_failed", id));
+    }
+    Redirect::to(&format!("/nodes/{}/edit", id))
+}
+
+/// Clears the alert a failed maintenance run left; the servers it names stay as they are.
+pub async fn dismiss_maintenance_alert_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Redirect {
+    if let Err(e) = maintenance::dismiss_alert(&state.db, id).await {
+        tracing::error!("Failed to dismiss maintenance alert of node {}: {}", id, e);
+        return Redirect::to(&format!("/nodes/{}/edit?error=maintenance_failed", id));
+    }
+    node_events::record(&state.db, id, "maintenance", "Alert dismissed").await;
+    Redirect::to(&format!("/nodes/{}/edit", id))
+}
+
+/// Removes the node's containers first (see `node_cleanup`), then its servers and the node itself.
+/// Containers that could not be removed don't block the deletion; the nodes page lists the counts.
+#[derive(serde::Deserialize)]
+pub struct DeleteNodeQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// From a dry run; the delete is refused if the node's servers or ports changed since
+    token: Option<String>,
+}
+
+/// Deletes the node with its servers and allocations. `?dry_run=true` only reports what
+/// would go, plus the token to send with the delete.
+pub async fn delete_node_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DeleteNodeQuery>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let preview = match delete_previews::node(&state.db, id).await {
+        Ok(preview) => preview,
+        Err(e) => {
+            tracing::error!("Failed to resolve what deleting node {} removes: {}", id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+    if query.dry_run {
+        return delete_preview_response(preview, &headers);
+    }
+    if !preview.matches(query.token.as_deref()) {
+        return (
+            StatusCode::CONFLICT,
+            "The node's servers or allocations changed since the preview. Nothing was deleted.",
+        )
+            .into_response();
+    }
+
+    let summary = node_cleanup::remove_containers(&state, &id.to_string()).await;
+
+    let result = async {
+        let mut tx = state.db.begin().await?;
+        sqlx::query("DELETE FROM servers WHERE node_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM nodes WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+
     // Invalidate Cache
     state.invalidate_nodes_cache().await;
-    
-    // Return empty string with 200 OK so that HTMX swaps the element with nothing (removing it)
-    ""
+
+    let location = match result {
+        Ok(()) => {
+            tracing::info!(
+                "Deleted node {}: {} containers removed ({} killed), {} failed",
+                id,
+                summary.removed,
+                summary.killed,
+                summary.failed.len()
+            );
+            format!(
+                "/nodes?removed={}&killed={}&failed={}",
+                summary.removed,
+                summary.killed,
+                summary.failed.len()
+            )
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete node {}: {}", id, e);
+            "/nodes?error=delete_failed".to_string()
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Redirect", location.parse().unwrap());
+    (headers, "Deleted").into_response()
 }
 
 pub async fn edit_node_page_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    viewer: Viewer,
+    Path(id): Path<Uuid>,
+    Query(query): Query<NodeFormQuery>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    let start_time = std::time::Instant::now(); 
+    let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone(); // Added
 
-    let node_res = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
+    let node_res = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, sftp_port, ram_limit, disk_limit, cpu_limit, version, auto_allocation_range, location_id::text FROM nodes WHERE id = $1")
+        .bind(id)
         .fetch_optional(&state.db)
         .await;
-    
+
     // Log error if DB failure
     if let Err(ref e) = node_res {
         tracing::error!("Failed to fetch node for edit: {}", e);
     }
     let node = node_res.unwrap_or(None);
 
-    let host = "127.0.0.1:3000";
+    let token_rotated_at: Option<chrono::DateTime<chrono::Utc>> =
+        sqlx::query_scalar("SELECT token_rotated_at FROM nodes WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None)
+            .flatten();
+    let token_rotated_at = token_rotated_at.map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string());
+    let version_history = node_versions::recent(&state.db, &id.to_string(), 5).await;
+    let update_status = node_versions::update_status(&state.db, &id.to_string()).await;
+
+    // Heartbeat first, then the stored version, then ask the agent directly
+    let mut agent_version = match &node {
+        Some(n) => versions::current_version(&n.version, state.node_stats(&n.id).await.as_ref()),
+        None => String::new(),
+    };
+    if agent_version.is_empty()
+        && let Some(n) = &node
+        && let Some(v) = node_api::fetch_version(&state.http_client, n).await
+    {
+        agent_version = v;
+    }
+    let version_status = VersionStatus::of(&agent_version);
+
+    let base_url = state.base_url(&headers);
+
+    type Timestamp = Option<chrono::DateTime<chrono::Utc>>;
+    let progress: Option<(Timestamp, Timestamp, i64)> = sqlx::query_as(
+        "SELECT decommission_started_at, decommission_script_fetched_at,
+                (SELECT COUNT(*) FROM servers WHERE node_id = nodes.id)
+             FROM nodes WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    let (decommission_started_at, script_fetched_at, server_count) =
+        progress.unwrap_or((None, None, 0));
+    let format_time = |t: chrono::DateTime<chrono::Utc>| t.format("%Y-%m-%d %H:%M UTC").to_string();
+    let decommission = decommission_started_at.map(|started| DecommissionStatus {
+        started_at: format_time(started),
+        script_fetched_at: script_fetched_at.map(format_time),
+    });
 
     let (node_val, found, install_cmd, uninstall_cmd) = if let Some(n) = node {
-        let install = format!("curl -sSL http://{}/install/{} | sudo bash", host, n.id);
-        let uninstall = format!("systemctl stop yunexal-node-{} && rm -rf /etc/yunexal/node-{}", n.id, n.id);
+        // Auditors don't get install or uninstall tokens
+        let install = if viewer.can_modify {
+            install_command(&state, &base_url, &n.id).await
+        } else {
+            String::new()
+        };
+        let uninstall = if viewer.can_modify && decommission.is_some() {
+            uninstall_command(&state, &base_url, &n.id).await
+        } else {
+            String::new()
+        };
         (n, true, install, uninstall)
     } else {
         (
-            Node { 
-                id: "".to_string(), 
-                name: "".to_string(), 
-                ip: "".to_string(), 
-                port: 0, 
+            Node {
+                id: "".to_string(),
+                name: "".to_string(),
+                ip: "".to_string(),
+                port: 0,
                 token: "".to_string(),
                 sftp_port: 0,
                 ram_limit: 0,
                 disk_limit: 0,
                 cpu_limit: 0,
-                version: "".to_string()
+                version: "".to_string(),
+                auto_allocation_range: "".to_string(),
+                location_id: "".to_string(),
             },
             false,
             "".to_string(),
-            "".to_string()
+            "".to_string(),
         )
     };
 
+    let locations = locations::list(&state.db).await;
+    let maintenance = maintenance::settings(&state.db, id).await;
+    let maintenance_next = Window::parse(&maintenance.window)
+        .filter(|_| maintenance.enabled)
+        .map(|w| format_time(w.next_start(chrono::Utc::now())));
+    let node_events = node_events::recent(&state.db, id, 10).await;
+    // Agents that predate raw filesystem reports send named disks that can't be labelled
+    let mut node_disks = match state.node_stats(&id.to_string()).await {
+        Some(payload) => disks::group(&payload.disks, &disks::labels(&state.db, id).await),
+        None => Vec::new(),
+    };
+    node_disks.retain(|d| !d.mounts.is_empty());
+
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
-    HtmlTemplate(EditNodeTemplate {
-        panel_name,
-        panel_font,
-        panel_font_url, // Added
-        panel_version,
-        execution_time,
-        active_tab: "nodes".to_string(),
-        node: node_val,
-        found,
-        install_cmd,
-        uninstall_cmd,
-    })
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        HtmlTemplate(EditNodeTemplate {
+            can_modify: viewer.can_modify,
+            panel_name,
+            panel_font,
+            panel_font_url, // Added
+            panel_version,
+            execution_time,
+            active_tab: "nodes".to_string(),
+            node: node_val,
+            found,
+            install_cmd,
+            uninstall_cmd,
+            decommission,
+            server_count,
+            token_rotated_at,
+            version_history,
+            update_status,
+            agent_version,
+            version_status,
+            expected_version: versions::expected_node_version(),
+            error: query.error,
+            taken_name: query.name,
+            port_conflict: query.detail,
+            locations,
+            maintenance,
+            maintenance_next,
+            node_events,
+            disks: node_disks,
+        }),
+    )
 }
 
 pub async fn update_node_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path(id): Path<Uuid>,
     Form(payload): Form<UpdateNodeRequest>,
 ) -> Redirect {
     // Validate Ports (duplicated logic from create, could be shared)
-    if (payload.port >= 0 && payload.port <= 1023) || (payload.sftp_port >= 0 && payload.sftp_port <= 1023) {
+    if (payload.port >= 0 && payload.port <= 1023)
+        || (payload.sftp_port >= 0 && payload.sftp_port <= 1023)
+    {
         eprintln!("Blocked attempt to update node to restricted port");
         return Redirect::to(&format!("/nodes/{}/edit", id));
     }
@@ -287,90 +865,244 @@ pub async fn update_node_handler(
         eprintln!("Daemon Port and SFTP Port cannot be the same");
         return Redirect::to(&format!("/nodes/{}/edit", id));
     }
+    let auto_range = payload.auto_allocation_range.trim();
+    if !auto_range.is_empty() && parse_port_range(auto_range).is_none() {
+        eprintln!("Invalid auto-allocation range: {}", auto_range);
+        return Redirect::to(&format!("/nodes/{}/edit", id));
+    }
+    let conflicts = port_conflicts(
+        &state,
+        Some(id),
+        &payload.ip,
+        payload.port,
+        payload.sftp_port,
+        &[],
+    )
+    .await;
+    if !conflicts.is_empty() {
+        return Redirect::to(&format!(
+            "/nodes/{}/edit?{}",
+            id,
+            port_conflict_query(&conflicts)
+        ));
+    }
 
-    let _ = sqlx::query("UPDATE nodes SET name = $1, ip = $2, port = $3, sftp_port = $4, ram_limit = $5, disk_limit = $6, cpu_limit = $7 WHERE id = $8::uuid")
+    let res = sqlx::query("UPDATE nodes SET name = $1, ip = $2, port = $3, sftp_port = $4, ram_limit = $5, disk_limit = $6, cpu_limit = $7, auto_allocation_range = $8, location_id = COALESCE($10, location_id) WHERE id = $9")
         .bind(&payload.name)
         .bind(&payload.ip)
-        .bind(&payload.port)
-        .bind(&payload.sftp_port)
+        .bind(payload.port)
+        .bind(payload.sftp_port)
         .bind(payload.ram_limit.unwrap_or(0))
         .bind(payload.disk_limit.unwrap_or(0))
         .bind(payload.cpu_limit.unwrap_or(0))
-        .bind(&id)
+        .bind(auto_range)
+        .bind(id)
+        .bind(payload.location_id)
         .execute(&state.db)
         .await;
-    
+    if let Err(e) = res {
+        if is_duplicate_name(&e) {
+            return Redirect::to(&format!(
+                "/nodes/{}/edit?{}",
+                id,
+                name_taken_query(&payload.name)
+            ));
+        }
+        eprintln!("Failed to update node: {}", e);
+    }
+
     // Invalidate Cache
     state.invalidate_nodes_cache().await;
-    
+
     Redirect::to("/")
 }
 
+#[derive(serde::Deserialize)]
+pub struct TriggerUpdateQuery {
+    /// Update even if the node is running operations
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Asks the agent to update itself. A node with running operations answers 423 unless
+/// `force` is set; progress then arrives with the node's heartbeats.
 pub async fn trigger_node_update(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<TriggerUpdateQuery>,
 ) -> impl IntoResponse {
-    let node_opt = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
-        .fetch_optional(&state.db)
-        .await
-        .unwrap_or(None);
-
-    if let Some(node) = node_opt {
-        let url = format!("http://{}:{}/self-update", node.ip, node.port);
-        let client = reqwest::Client::new();
-        
-        let res = client.post(&url)
-            .header("Authorization", &format!("Bearer {}", node.token))
-            .send()
-            .await;
-            
-        return match res {
-            Ok(r) => {
-                 if r.status().is_success() {
-                     axum::response::Response::builder()
-                        .status(200)
-                        .body(axum::body::Body::from("Update initiated"))
-                        .unwrap()
-                 } else {
-                     axum::response::Response::builder()
-                        .status(500)
-                        .body(axum::body::Body::from(format!("Node error: {}", r.status())))
-                        .unwrap()
-                 }
-            },
-            Err(e) => {
-                axum::response::Response::builder()
-                    .status(500)
-                    .body(axum::body::Body::from(format!("Connection failed: {}", e)))
-                    .unwrap()
+    let node_opt = state.get_node_with_token(&id.to_string()).await;
+
+    let Some(node) = node_opt else {
+        return (StatusCode::NOT_FOUND, "Node not found".to_string());
+    };
+
+    let mut url = format!("http://{}:{}/self-update", node.ip, node.port);
+    if query.force {
+        url.push_str("?force=true");
+    }
+    let res = state
+        .http_client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await;
+
+    match res {
+        Ok(r) if r.status().is_success() => {
+            let interrupted = r
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|v| v["interrupted_operations"].as_u64())
+                .unwrap_or(0);
+            let message = if interrupted > 0 {
+                format!(
+                    "Update started, interrupting {} running operations. The node will restart shortly.",
+                    interrupted
+                )
+            } else {
+                "Update started. The node will restart shortly.".to_string()
+            };
+            node_versions::set_update_state(&state.db, &node.id, "requested", Some(&message)).await;
+            (StatusCode::ACCEPTED, message)
+        }
+        Ok(r) => {
+            let err = read_node_error(r).await;
+            match err.code.as_str() {
+                "operations_running" => (StatusCode::LOCKED, err.message),
+                "update_in_progress" | "node_updating" => (
+                    StatusCode::CONFLICT,
+                    "An update is already running on this node".to_string(),
+                ),
+                _ if err.is_auth_failure() => (
+                    StatusCode::BAD_GATEWAY,
+                    "Node rejected the panel token; rotate or re-install the node".to_string(),
+                ),
+                _ => (StatusCode::BAD_GATEWAY, format!("Node error: {}", err)),
             }
-        };
+        }
+        Err(e) => (StatusCode::BAD_GATEWAY, format!("Connection failed: {}", e)),
     }
-    
-    axum::response::Response::builder()
-        .status(404)
-        .body(axum::body::Body::from("Node not found"))
-        .unwrap()
+}
+
+/// htmx fragment for the node edit page: what the agent is actually running with.
+pub async fn node_agent_config_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let node_opt = state.get_node_with_token(&id.to_string()).await;
+
+    let Some(node) = node_opt else {
+        return (axum::http::StatusCode::NOT_FOUND, "Node not found").into_response();
+    };
+
+    let url = format!("http://{}:{}/config", node.ip, node.port);
+    let res = state
+        .http_client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(std::time::Duration::from_secs(5))
+        .send()
+        .await;
+
+    let (config, error) = match res {
+        Ok(r) if r.status().is_success() => match r.json::<NodeAgentConfig>().await {
+            Ok(cfg) => (Some(cfg), String::new()),
+            Err(e) => (None, format!("Unexpected response: {}", e)),
+        },
+        Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => (
+            None,
+            "This agent version does not expose its config; update the agent".to_string(),
+        ),
+        Ok(r) => {
+            let err = read_node_error(r).await;
+            if err.is_auth_failure() {
+                (
+                    None,
+                    "Node rejected the panel token; rotate or re-install the node".to_string(),
+                )
+            } else {
+                (None, format!("Node error: {}", err))
+            }
+        }
+        Err(e) => (None, format!("Connection failed: {}", e)),
+    };
+
+    HtmlTemplate(NodeAgentConfigTemplate {
+        node,
+        config,
+        error,
+    })
+    .into_response()
+}
+
+/// htmx fragment for the node edit page: live container/image/volume usage from Docker.
+pub async fn node_docker_summary_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let Some(node) = state.get_node_with_token(&id.to_string()).await else {
+        return (axum::http::StatusCode::NOT_FOUND, "Node not found").into_response();
+    };
+
+    let url = format!("http://{}:{}/docker-summary", node.ip, node.port);
+    let res = state
+        .http_client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await;
+
+    let (summary, error) = match res {
+        Ok(r) if r.status().is_success() => match r.json::<DockerSummary>().await {
+            Ok(s) => (Some(s), String::new()),
+            Err(e) => (None, format!("Unexpected response: {}", e)),
+        },
+        Ok(r) if r.status() == reqwest::StatusCode::NOT_FOUND => (
+            None,
+            "This agent version does not report Docker usage; update the agent".to_string(),
+        ),
+        Ok(r) => {
+            let err = read_node_error(r).await;
+            if err.is_auth_failure() {
+                (
+                    None,
+                    "Node rejected the panel token; rotate or re-install the node".to_string(),
+                )
+            } else {
+                (None, format!("Node error: {}", err))
+            }
+        }
+        Err(e) => (None, format!("Connection failed: {}", e)),
+    };
+
+    HtmlTemplate(NodeDockerSummaryTemplate { summary, error }).into_response()
 }
 
 fn parse_ports(input: &str) -> Vec<i32> {
     let mut result = Vec::new();
     let parts: Vec<&str> = input.split(',').collect();
-    
+
     for part in parts {
         let trimmed = part.trim();
-        if trimmed.is_empty() { continue; }
-        
+        if trimmed.is_empty() {
+            continue;
+        }
+
         if trimmed.contains('-') {
             let range_parts: Vec<&str> = trimmed.split('-').collect();
-            if range_parts.len() == 2 {
-                if let (Ok(start), Ok(end)) = (range_parts[0].trim().parse::<i32>(), range_parts[1].trim().parse::<i32>()) {
-                    if start <= end {
-                        for p in start..=end {
-                            result.push(p);
-                        }
-                    }
+            if range_parts.len() == 2
+                && let (Ok(start), Ok(end)) = (
+                    range_parts[0].trim().parse::<i32>(),
+                    range_parts[1].trim().parse::<i32>(),
+                )
+                && start <= end
+            {
+                for p in start..=end {
+                    result.push(p);
                 }
             }
         } else {
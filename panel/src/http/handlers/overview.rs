@@ -1,13 +1,18 @@
-use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::Viewer;
+use crate::http::handlers::{HtmlTemplate, html_escape};
 use crate::models::HeartbeatPayload;
 use crate::state::AppState;
 use askama::Template;
 use axum::{
-    extract::{Form, State},
+    extract::{
+        Form, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
     response::{Html, IntoResponse},
 };
 use serde::Deserialize;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
 
 #[derive(Template)]
 #[template(path = "overview_stats.html")]
@@ -23,6 +28,10 @@ struct OverviewStatsTemplate {
     disk_write_speed: u64,
     net_rx_speed: u64,
     net_tx_speed: u64,
+    total_allocations: i64,
+    free_allocations: i64,
+    node_rows: Vec<NodeRow>,
+    sort: NodeSort,
 }
 
 #[derive(Template)]
@@ -43,8 +52,16 @@ struct OverviewTemplate {
     disk_write_speed: u64,
     net_rx_speed: u64,
     net_tx_speed: u64,
+    total_allocations: i64,
+    free_allocations: i64,
+    node_rows: Vec<NodeRow>,
+    sort: NodeSort,
     execution_time: f64,
     active_tab: String,
+    redis_enabled: bool,
+    /// Redis is configured but unreachable; caches run from memory until it reconnects
+    redis_degraded: bool,
+    can_modify: bool,
 }
 
 #[derive(Deserialize)]
@@ -55,6 +72,98 @@ pub struct UpdateSettingsRequest {
     panel_font_url: String, // Added
 }
 
+/// One node's line in the overview breakdown. Usage fields are zero for offline nodes.
+struct NodeRow {
+    id: String,
+    name: String,
+    online: bool,
+    cpu: f32,
+    ram_used: u64,
+    ram_total: u64,
+    disk_used: u64,
+    disk_total: u64,
+    net_rx: u64,
+    net_tx: u64,
+}
+
+/// `?sort=` key and header of each breakdown column
+const SORT_COLUMNS: [(&str, &str); 6] = [
+    ("name", "Node"),
+    ("status", "Status"),
+    ("cpu", "CPU"),
+    ("ram", "RAM"),
+    ("disk", "Disk"),
+    ("net", "Network"),
+];
+
+/// Breakdown column picked with `?sort=`. Usage columns put the busiest node first.
+#[derive(Clone, Copy, PartialEq)]
+pub enum NodeSort {
+    Name,
+    Status,
+    Cpu,
+    Ram,
+    Disk,
+    Net,
+}
+
+impl NodeSort {
+    /// Unknown values sort by name rather than failing the page
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("status") => NodeSort::Status,
+            Some("cpu") => NodeSort::Cpu,
+            Some("ram") => NodeSort::Ram,
+            Some("disk") => NodeSort::Disk,
+            Some("net") => NodeSort::Net,
+            _ => NodeSort::Name,
+        }
+    }
+
+    fn sort(self, rows: &mut [NodeRow]) {
+        let name = |a: &NodeRow, b: &NodeRow| a.name.to_lowercase().cmp(&b.name.to_lowercase());
+        rows.sort_by(|a, b| {
+            let order = match self {
+                NodeSort::Name => std::cmp::Ordering::Equal,
+                NodeSort::Status => b.online.cmp(&a.online),
+                NodeSort::Cpu => b.cpu.total_cmp(&a.cpu),
+                NodeSort::Ram => b.ram_used.cmp(&a.ram_used),
+                NodeSort::Disk => b.disk_used.cmp(&a.disk_used),
+                NodeSort::Net => (b.net_rx + b.net_tx).cmp(&(a.net_rx + a.net_tx)),
+            };
+            order.then_with(|| name(a, b))
+        });
+    }
+
+    /// Whether this is the column named `key` in the breakdown's header links
+    fn is(self, key: &str) -> bool {
+        self == NodeSort::parse(Some(key))
+    }
+
+    /// `?sort=` to keep on the live-update URLs; empty for the default
+    fn query(self) -> &'static str {
+        match self {
+            NodeSort::Name => "",
+            NodeSort::Status => "?sort=status",
+            NodeSort::Cpu => "?sort=cpu",
+            NodeSort::Ram => "?sort=ram",
+            NodeSort::Disk => "?sort=disk",
+            NodeSort::Net => "?sort=net",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OverviewQuery {
+    sort: Option<String>,
+}
+
+impl OverviewQuery {
+    fn sort(&self) -> NodeSort {
+        NodeSort::parse(self.sort.as_deref())
+    }
+}
+
 struct CalculatedStats {
     total_nodes: usize,
     online_nodes: usize,
@@ -67,9 +176,12 @@ struct CalculatedStats {
     disk_write_speed: u64,
     net_rx_speed: u64,
     net_tx_speed: u64,
+    total_allocations: i64,
+    free_allocations: i64,
+    node_rows: Vec<NodeRow>,
 }
 
-async fn calculate_overview_stats(state: &AppState) -> CalculatedStats {
+async fn calculate_overview_stats(state: &AppState, sort: NodeSort) -> CalculatedStats {
     let nodes = state.get_nodes().await;
 
     let total_nodes = nodes.len();
@@ -84,19 +196,20 @@ async fn calculate_overview_stats(state: &AppState) -> CalculatedStats {
     let mut disk_write_speed = 0u64;
     let mut net_rx_speed = 0u64;
     let mut net_tx_speed = 0u64;
+    let mut node_rows = Vec::with_capacity(nodes.len());
 
     for node in &nodes {
         let mut stats: Option<HeartbeatPayload> = None;
 
         // 1. Try Redis
-        if let Some(manager) = &state.redis {
-            let mut con = manager.clone();
+        if let Some(mut con) = state.redis.connection() {
             let key = format!("node:{}:stats", node.id);
             let cached: Result<String, _> = redis::AsyncCommands::get(&mut con, &key).await;
-            if let Ok(json) = cached {
-                if let Ok(payload) = serde_json::from_str::<HeartbeatPayload>(&json) {
-                    stats = Some(payload);
-                }
+            state.redis.observe(&cached);
+            if let Ok(json) = cached
+                && let Ok(payload) = serde_json::from_str::<HeartbeatPayload>(&json)
+            {
+                stats = Some(payload);
             }
         }
 
@@ -108,15 +221,34 @@ async fn calculate_overview_stats(state: &AppState) -> CalculatedStats {
             }
         }
 
+        let mut row = NodeRow {
+            id: node.id.clone(),
+            name: node.name.clone(),
+            online: false,
+            cpu: 0.0,
+            ram_used: 0,
+            ram_total: 0,
+            disk_used: 0,
+            disk_total: 0,
+            net_rx: 0,
+            net_tx: 0,
+        };
+
         // 3. Process Stats if available
         if let Some(payload) = stats {
-            // Check recency (e.g., within last 60 seconds)
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-            // Allow some grace period
-            if payload.timestamp > (now as i64 - 60) {
+            // Online within a few of the node's own heartbeat intervals
+            if payload.is_fresh(chrono::Utc::now().timestamp_millis()) {
+                row = NodeRow {
+                    online: true,
+                    cpu: payload.cpu_usage,
+                    ram_used: payload.ram_usage,
+                    ram_total: payload.ram_total,
+                    disk_used: payload.disk_usage,
+                    disk_total: payload.disk_total,
+                    net_rx: payload.net_rx,
+                    net_tx: payload.net_tx,
+                    ..row
+                };
                 online_nodes += 1;
                 used_ram += payload.ram_usage;
                 total_ram += payload.ram_total;
@@ -129,7 +261,17 @@ async fn calculate_overview_stats(state: &AppState) -> CalculatedStats {
                 net_tx_speed += payload.net_tx;
             }
         }
+        node_rows.push(row);
     }
+    sort.sort(&mut node_rows);
+
+    // Port capacity across the fleet, in one pass over allocations
+    let (total_allocations, free_allocations): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COUNT(*) FILTER (WHERE server_id IS NULL) FROM allocations",
+    )
+    .fetch_one(&state.db)
+    .await
+    .unwrap_or((0, 0));
 
     CalculatedStats {
         total_nodes,
@@ -143,40 +285,105 @@ async fn calculate_overview_stats(state: &AppState) -> CalculatedStats {
         disk_write_speed,
         net_rx_speed,
         net_tx_speed,
+        total_allocations,
+        free_allocations,
+        node_rows,
     }
 }
 
-pub async fn overview_stats_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let stats = calculate_overview_stats(&state).await;
+impl OverviewStatsTemplate {
+    fn new(stats: CalculatedStats, sort: NodeSort) -> Self {
+        Self {
+            total_nodes: stats.total_nodes,
+            online_nodes: stats.online_nodes,
+            total_ram: stats.total_ram,
+            used_ram: stats.used_ram,
+            total_disk: stats.total_disk,
+            used_disk: stats.used_disk,
+            total_cpu: stats.total_cpu,
+            disk_read_speed: stats.disk_read_speed,
+            disk_write_speed: stats.disk_write_speed,
+            net_rx_speed: stats.net_rx_speed,
+            net_tx_speed: stats.net_tx_speed,
+            total_allocations: stats.total_allocations,
+            free_allocations: stats.free_allocations,
+            node_rows: stats.node_rows,
+            sort,
+        }
+    }
+}
 
-    HtmlTemplate(OverviewStatsTemplate {
-        total_nodes: stats.total_nodes,
-        online_nodes: stats.online_nodes,
-        total_ram: stats.total_ram,
-        used_ram: stats.used_ram,
-        total_disk: stats.total_disk,
-        used_disk: stats.used_disk,
-        total_cpu: stats.total_cpu,
-        disk_read_speed: stats.disk_read_speed,
-        disk_write_speed: stats.disk_write_speed,
-        net_rx_speed: stats.net_rx_speed,
-        net_tx_speed: stats.net_tx_speed,
-    })
+pub async fn overview_stats_handler(
+    State(state): State<AppState>,
+    Query(query): Query<OverviewQuery>,
+) -> impl IntoResponse {
+    let stats = calculate_overview_stats(&state, query.sort()).await;
+    HtmlTemplate(OverviewStatsTemplate::new(stats, query.sort()))
+}
+
+async fn render_stats_fragment(state: &AppState, sort: NodeSort) -> Option<String> {
+    let stats = calculate_overview_stats(state, sort).await;
+    OverviewStatsTemplate::new(stats, sort).render().ok()
+}
+
+/// Pushes the stats fragment to the overview page when heartbeats arrive.
+pub async fn overview_ws_handler(
+    State(state): State<AppState>,
+    Query(query): Query<OverviewQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| stream_overview_stats(socket, state, query.sort()))
 }
 
-pub async fn overview_handler(State(state): State<AppState>) -> impl IntoResponse {
+async fn stream_overview_stats(mut socket: WebSocket, state: AppState, sort: NodeSort) {
+    let mut heartbeats = state.heartbeat_events.subscribe();
+    // Also re-render on a slow tick so nodes that go silent drop to offline
+    let mut tick = tokio::time::interval(Duration::from_secs(15));
+
+    loop {
+        tokio::select! {
+            event = heartbeats.recv() => match event {
+                Ok(_) | Err(RecvError::Lagged(_)) => {
+                    // Coalesce a burst of heartbeats into one render
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    while heartbeats.try_recv().is_ok() {}
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = tick.tick() => {}
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => continue,
+            },
+        }
+
+        let Some(html) = render_stats_fragment(&state, sort).await else {
+            continue;
+        };
+        if socket.send(Message::Text(html.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+pub async fn overview_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<OverviewQuery>,
+) -> impl IntoResponse {
     let start_time = Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone(); // Added
 
-    let stats = calculate_overview_stats(&state).await;
+    let stats = calculate_overview_stats(&state, query.sort()).await;
 
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(OverviewTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url, // Added
@@ -192,28 +399,100 @@ pub async fn overview_handler(State(state): State<AppState>) -> impl IntoRespons
         disk_write_speed: stats.disk_write_speed,
         net_rx_speed: stats.net_rx_speed,
         net_tx_speed: stats.net_tx_speed,
+        total_allocations: stats.total_allocations,
+        free_allocations: stats.free_allocations,
+        node_rows: stats.node_rows,
+        sort: query.sort(),
         execution_time,
         active_tab: "overview".to_string(),
+        redis_enabled: state.redis.is_configured(),
+        redis_degraded: state.redis.is_degraded(),
     })
 }
 
+/// Longest panel name and font family taken; they show in the sidebar and every title
+pub const MAX_PANEL_TEXT_CHARS: usize = 64;
+
+pub const DEFAULT_PANEL_NAME: &str = "Yunexal Panel";
+pub const DEFAULT_PANEL_FONT: &str = "Google Sans Flex";
+
+/// Comma-separated hosts `PANEL_FONT_URL` may point at, e.g. `fonts.googleapis.com`; any
+/// host when unset.
+fn allowed_font_hosts() -> Vec<String> {
+    std::env::var("PANEL_FONT_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|h| h.trim().to_ascii_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// `PANEL_FONT_URL` ends up in every page's `<link href>` and in `.env`, so only a plain
+/// https URL (or nothing) is taken, on an allowed host when `PANEL_FONT_HOSTS` is set.
+/// Templates escape it as well; this keeps markup out of `.env` and the settings form.
+pub fn check_font_url(url: &str) -> Result<(), String> {
+    if url.is_empty() {
+        return Ok(());
+    }
+    // The URL parser quietly drops tabs and newlines; .env lines must not gain any
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err("Font URL must not contain spaces or line breaks.".to_string());
+    }
+    // It would percent-encode these, but the value is stored as typed
+    if url.contains(['"', '\'', '`', '<', '>', '\\']) {
+        return Err("Font URL must not contain quotes, backslashes or angle brackets.".to_string());
+    }
+    let host = match reqwest::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "https" => match parsed.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return Err("Font URL must start with https://.".to_string()),
+        },
+        Ok(_) => return Err("Font URL must start with https://.".to_string()),
+        Err(_) => return Err("Font URL is not a valid URL.".to_string()),
+    };
+    let allowed = allowed_font_hosts();
+    if !allowed.is_empty() && !allowed.contains(&host) {
+        return Err(format!(
+            "Font URL host {} is not in PANEL_FONT_HOSTS.",
+            host
+        ));
+    }
+    Ok(())
+}
+
+/// Panel name or font family as stored: control characters (line breaks would split the
+/// `.env` line) dropped, trimmed and cut to `MAX_PANEL_TEXT_CHARS`. `fallback` when nothing
+/// is left. The font family also loses quotes, backslashes and angle brackets, since it sits
+/// inside a CSS string.
+pub fn clean_panel_text(value: &str, fallback: &str, font: bool) -> String {
+    let cleaned: String = value
+        .chars()
+        .filter(|c| !c.is_control())
+        .filter(|c| !font || !matches!(c, '"' | '\'' | '\\' | '<' | '>'))
+        .collect();
+    let cleaned: String = cleaned.trim().chars().take(MAX_PANEL_TEXT_CHARS).collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        fallback.to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
 pub async fn update_settings_handler(
     State(state): State<AppState>,
     Form(payload): Form<UpdateSettingsRequest>,
 ) -> impl IntoResponse {
-    let new_name = if payload.panel_name.trim().is_empty() {
-        "Yunexal Panel".to_string()
-    } else {
-        payload.panel_name.trim().to_string()
-    };
-
-    let new_font = if payload.panel_font.trim().is_empty() {
-        "Google Sans Flex".to_string()
-    } else {
-        payload.panel_font.trim().to_string()
-    };
+    let new_name = clean_panel_text(&payload.panel_name, DEFAULT_PANEL_NAME, false);
+    let new_font = clean_panel_text(&payload.panel_font, DEFAULT_PANEL_FONT, true);
 
     let new_font_url = payload.panel_font_url.trim().to_string();
+    if let Err(message) = check_font_url(&new_font_url) {
+        return Html(format!(
+            r#"<div id="settings-message" hx-swap-oob="true" style="color: #dc3545; margin-top: 10px; font-weight: bold;">{} Settings were not saved.</div>"#,
+            html_escape(&message)
+        ));
+    }
 
     // 1. Update In-Memory State
     {
@@ -274,6 +553,106 @@ pub async fn update_settings_handler(
         <h2 id="sidebar-panel-name" hx-swap-oob="true">{}</h2>
         <input id="panel_name-input" name="panel_name" value="{}" hx-swap-oob="true" style="max-width: 400px; padding: 0.5rem; box-sizing: border-box; border: 1px solid #ccc; border-radius: 4px; width: 100%;">
     "#,
-        new_name, new_name
+        html_escape(&new_name),
+        html_escape(&new_name)
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn font_url_is_empty_or_https() {
+        assert!(check_font_url("").is_ok());
+        assert!(
+            check_font_url("https://fonts.googleapis.com/css2?family=Inter&display=swap").is_ok()
+        );
+        for url in [
+            "http://fonts.googleapis.com/css2",
+            "javascript:alert(1)",
+            "data:text/css,body{}",
+            "fonts.googleapis.com/css2",
+            "https://",
+        ] {
+            assert!(check_font_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn font_url_cannot_carry_whitespace_or_line_breaks() {
+        for url in [
+            "https://fonts.example.com/a b",
+            "https://fonts.example.com/a\nPANEL_NAME=x",
+            "https://fonts.example.com/\ta",
+            "https://fonts.example.com/\u{7}",
+        ] {
+            assert_eq!(
+                check_font_url(url),
+                Err("Font URL must not contain spaces or line breaks.".to_string()),
+                "{:?}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn font_url_cannot_carry_quotes_or_markup() {
+        for url in [
+            "https://fonts.example.com/\"onload=alert(1)",
+            "https://fonts.example.com/'x",
+            "https://fonts.example.com/`x`",
+            "https://fonts.example.com/<style>",
+            "https://fonts.example.com/a>b",
+            "https://fonts.example.com\\evil.com/css",
+        ] {
+            assert_eq!(
+                check_font_url(url),
+                Err("Font URL must not contain quotes, backslashes or angle brackets.".to_string()),
+                "{:?}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn panel_text_drops_control_characters_and_is_capped() {
+        assert_eq!(
+            clean_panel_text("  My\nPanel\r\u{0}  ", DEFAULT_PANEL_NAME, false),
+            "MyPanel"
+        );
+        assert_eq!(
+            clean_panel_text("\"Quoted\" <b>", DEFAULT_PANEL_NAME, false),
+            "\"Quoted\" <b>"
+        );
+        let long = "é".repeat(MAX_PANEL_TEXT_CHARS + 10);
+        assert_eq!(
+            clean_panel_text(&long, DEFAULT_PANEL_NAME, false)
+                .chars()
+                .count(),
+            MAX_PANEL_TEXT_CHARS
+        );
+        for blank in ["", "   ", "\n\t"] {
+            assert_eq!(
+                clean_panel_text(blank, DEFAULT_PANEL_NAME, false),
+                DEFAULT_PANEL_NAME
+            );
+        }
+    }
+
+    #[test]
+    fn font_family_loses_what_would_end_its_css_string() {
+        assert_eq!(
+            clean_panel_text("Inter\"; } body { display: none", DEFAULT_PANEL_FONT, true),
+            "Inter; } body { display: none"
+        );
+        assert_eq!(
+            clean_panel_text("'Fira Code' \\ </style>", DEFAULT_PANEL_FONT, true),
+            "Fira Code  /style"
+        );
+        assert_eq!(
+            clean_panel_text("\"'<>", DEFAULT_PANEL_FONT, true),
+            DEFAULT_PANEL_FONT
+        );
+    }
+}
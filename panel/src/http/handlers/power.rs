@@ -0,0 +1,163 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{ContainerState, MAX_STOP_TIMEOUT, PowerAction, PowerRequest};
+use crate::services::{node_api, server_events};
+use crate::state::AppState;
+
+/// Seconds `?wait=true` waits for the target state when the caller doesn't say.
+const DEFAULT_WAIT_SECS: u64 = 30;
+/// Upper bound for `?timeout=`, so one request can't hold a connection for ages.
+const MAX_WAIT_SECS: u64 = 120;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct PowerQuery {
+    /// Answer only once the container reached the action's target state
+    #[serde(default)]
+    pub wait: bool,
+    /// Seconds to wait with `wait=true` (default 30, at most 120)
+    pub timeout: Option<u64>,
+}
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": code, "message": message.into() })),
+    )
+        .into_response()
+}
+
+/// `POST /api/servers/{id}/power`: start, stop, restart or kill a server's container.
+///
+/// Without `wait` the answer is `202` with the state right after the action. With
+/// `wait=true` the container is polled until it is `running` (start, restart) or stopped
+/// (stop, kill): `200` with the final state, or `504` with the last state seen.
+/// Suspended servers answer `409` to start and restart.
+pub async fn power_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<PowerQuery>,
+    Json(mut payload): Json<PowerRequest>,
+) -> Response {
+    let row: Option<(String, i32, Option<String>)> = sqlx::query_as(
+        "SELECT node_id::text, stop_timeout_seconds, suspend_reason FROM servers WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    let Some((node_id, stop_timeout, suspend_reason)) = row else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "server_not_found",
+            "Server not found",
+        );
+    };
+    if let Some(reason) = suspend_reason
+        && matches!(payload.action, PowerAction::Start | PowerAction::Restart)
+    {
+        return error(
+            StatusCode::CONFLICT,
+            "server_suspended",
+            format!("Server is suspended ({}); it can only be stopped", reason),
+        );
+    }
+    let Some(node) = state.get_node_with_token(&node_id).await else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "node_not_found",
+            "The server's node no longer exists",
+        );
+    };
+
+    // Slow-stopping servers (databases, big modpacks) get their configured time, not the node's default
+    payload
+        .grace
+        .get_or_insert(stop_timeout.clamp(1, MAX_STOP_TIMEOUT) as u64);
+
+    let action = payload.action;
+    let uuid = id.to_string();
+    let mut current = match node_api::power_container(
+        &state.http_client,
+        &node,
+        &uuid,
+        &payload,
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(current) => current,
+        Err(e) => {
+            tracing::error!(
+                "Power action {} for server {} failed: {}",
+                action.as_str(),
+                id,
+                e
+            );
+            return error(StatusCode::BAD_GATEWAY, "power_action_failed", e);
+        }
+    };
+    server_events::record(
+        &state.db,
+        id,
+        "power",
+        &format!("Power action: {}", action.as_str()),
+    )
+    .await;
+
+    let body = |current: &ContainerState| {
+        Json(serde_json::json!({
+            "action": action.as_str(),
+            "state": current.state,
+            "started_at": current.started_at,
+        }))
+    };
+
+    if !query.wait {
+        return (StatusCode::ACCEPTED, body(&current)).into_response();
+    }
+
+    let wait = Duration::from_secs(
+        query
+            .timeout
+            .unwrap_or(DEFAULT_WAIT_SECS)
+            .min(MAX_WAIT_SECS),
+    );
+    let deadline = Instant::now() + wait;
+    while !action.reached(&current.state) {
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return (
+                StatusCode::GATEWAY_TIMEOUT,
+                Json(serde_json::json!({
+                    "error": "timeout",
+                    "message": format!("Server did not finish '{}' within {}s", action.as_str(), wait.as_secs()),
+                    "action": action.as_str(),
+                    "state": current.state,
+                })),
+            )
+                .into_response();
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        // A blip on one poll isn't an answer; keep trying until the deadline
+        match node_api::container_state(&state.http_client, &node, &uuid, &state.node_retry).await {
+            Ok(state) => current = state,
+            Err(e) => tracing::debug!(
+                "Polling server {} after {} failed: {}",
+                id,
+                action.as_str(),
+                e
+            ),
+        }
+    }
+
+    body(&current).into_response()
+}
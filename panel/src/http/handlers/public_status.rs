@@ -0,0 +1,174 @@
+use axum::{
+    Json,
+    extract::{ConnectInfo, Form, Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use crate::models::{ContainerState, DEFAULT_HEARTBEAT_INTERVAL};
+use crate::services::server_events;
+use crate::state::AppState;
+
+/// Everything `/public/servers/{id}/status` reveals about a server.
+#[derive(Serialize)]
+pub struct PublicStatus {
+    /// `online`, `starting`, `offline`, `installing` or `unknown`
+    pub status: &'static str,
+    /// Seconds since the container started, while online
+    pub uptime: Option<i64>,
+    /// Primary allocation as `ip:port`
+    pub address: Option<String>,
+}
+
+/// Player-facing status from the server row and the node's latest heartbeat.
+fn derive_status(
+    server_status: &str,
+    containers: Option<&[ContainerState]>,
+    server_id: &str,
+    now: i64,
+) -> (&'static str, Option<i64>) {
+    if matches!(server_status, "queued" | "installing") {
+        return ("installing", None);
+    }
+    // Agents that predate container states in heartbeats
+    let Some(containers) = containers else {
+        return ("unknown", None);
+    };
+
+    match containers.iter().find(|c| c.server_id == server_id) {
+        Some(c) if c.is_starting() => ("starting", None),
+        Some(c) if c.state == "running" => {
+            let uptime = (c.started_at > 0).then(|| (now - c.started_at).max(0));
+            ("online", uptime)
+        }
+        _ => ("offline", None),
+    }
+}
+
+/// Unauthenticated status for servers that opted in. Anything else, including malformed
+/// ids, gets the same 404 so the endpoint can't be used to probe for server ids.
+pub async fn public_status_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+
+    if let Err(retry_after) = state.public_status_limiter.check(addr.ip()) {
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            headers,
+            Json(serde_json::json!({ "error": "rate_limited" })),
+        )
+            .into_response();
+    }
+
+    let not_found = |mut headers: HeaderMap| {
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=60"),
+        );
+        (
+            StatusCode::NOT_FOUND,
+            headers,
+            Json(serde_json::json!({ "error": "not_found" })),
+        )
+            .into_response()
+    };
+
+    let Ok(id) = Uuid::parse_str(&id) else {
+        return not_found(headers);
+    };
+
+    let row = sqlx::query_as::<_, (String, String, Option<String>, Option<i32>)>(
+        "SELECT s.status, s.node_id::text, a.ip, a.port FROM servers s \
+         LEFT JOIN allocations a ON a.id = s.allocation_id \
+         WHERE s.id = $1 AND s.public_status",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to load public status for server {}: {}", id, e);
+        None
+    });
+
+    let Some((server_status, node_id, ip, port)) = row else {
+        return not_found(headers);
+    };
+
+    // Served from the last heartbeat; the node is never contacted for this
+    let heartbeat = state.node_stats(&node_id).await;
+    let (status, uptime) = match &heartbeat {
+        Some(hb) => derive_status(
+            &server_status,
+            hb.containers.as_deref(),
+            &id.to_string(),
+            chrono::Utc::now().timestamp(),
+        ),
+        None => ("offline", None),
+    };
+
+    // Nothing newer can exist until the next heartbeat
+    let max_age = heartbeat.map_or(DEFAULT_HEARTBEAT_INTERVAL, |hb| hb.interval_secs());
+    if let Ok(value) = HeaderValue::from_str(&format!("public, max-age={}", max_age)) {
+        headers.insert(header::CACHE_CONTROL, value);
+    }
+
+    let address = ip.zip(port).map(|(ip, port)| format!("{}:{}", ip, port));
+    (
+        headers,
+        Json(PublicStatus {
+            status,
+            uptime,
+            address,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PublicStatusForm {
+    /// Checkbox: present when enabling
+    pub enabled: Option<String>,
+}
+
+pub async fn toggle_public_status_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(payload): Form<PublicStatusForm>,
+) -> Redirect {
+    let enabled = payload.enabled.is_some();
+    let res = sqlx::query("UPDATE servers SET public_status = $1 WHERE id = $2")
+        .bind(enabled)
+        .bind(id)
+        .execute(&state.db)
+        .await;
+
+    match res {
+        Ok(_) => {
+            let message = if enabled {
+                "Public status page enabled"
+            } else {
+                "Public status page disabled"
+            };
+            server_events::record(&state.db, id, "public_status", message).await;
+            Redirect::to(&format!("/servers/{}/manage", id))
+        }
+        Err(e) => {
+            tracing::error!("Failed to update public status for server {}: {}", id, e);
+            Redirect::to(&format!(
+                "/servers/{}/manage?error=public_status_failed",
+                id
+            ))
+        }
+    }
+}
@@ -1,13 +1,26 @@
 use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::{Viewer, session_user};
+use crate::http::upload::read_text_field;
 use crate::{
-    models::{Image, Runtime},
+    models::{
+        Image, InstallTestEvent, InstallTestRequest, Node, Runtime, Variable, parse_console_macros,
+        parse_tags,
+    },
+    services::images::{self, DeleteError, ImageInput, RuntimeInput, WriteError},
+    services::node_api,
+    services::signed_urls::{self, DownloadKind},
     state::AppState,
 };
 use askama::Template;
 use axum::{
-    extract::{Form, Json, State},
-    response::{IntoResponse, Redirect},
+    extract::{Form, Json, Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Redirect, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use axum_extra::extract::cookie::CookieJar;
 use uuid::Uuid;
 
 #[derive(Template)]
@@ -21,6 +34,9 @@ struct RuntimesTemplate {
     active_tab: String,
     // Using a tuple struct or wrapper for logic
     runtimes: Vec<RuntimeWithImages>,
+    /// Every tag in use, for the filter
+    tags: Vec<String>,
+    can_modify: bool,
 }
 
 struct RuntimeWithImages {
@@ -38,6 +54,7 @@ struct CreateRuntimeTemplate {
     panel_version: String,
     execution_time: f64,
     active_tab: String,
+    can_modify: bool,
 }
 
 #[derive(Template)]
@@ -50,11 +67,30 @@ struct EditRuntimeTemplate {
     execution_time: f64,
     active_tab: String,
     runtime: Runtime,
+    query: CatalogFormQuery,
+    can_modify: bool,
+}
+
+/// `?error=` on the runtime and image forms: `invalid` (with `detail`), `in_use` (with the
+/// server count in `detail`) or `invalid_replacement` (a forced image delete without a
+/// usable image to move the servers to).
+#[derive(serde::Deserialize, Default)]
+pub struct CatalogFormQuery {
+    pub error: Option<String>,
+    pub detail: Option<String>,
+}
+
+/// `error=...&detail=...` for a redirect back to a form
+fn error_query(error: &str, detail: &str) -> String {
+    serde_urlencoded::to_string([("error", error), ("detail", detail)])
+        .unwrap_or_else(|_| format!("error={}", error))
 }
 
 pub async fn edit_runtime_page_handler(
     State(state): State<AppState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
+    viewer: Viewer,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Query(query): Query<CatalogFormQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
@@ -62,19 +98,15 @@ pub async fn edit_runtime_page_handler(
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
 
-    let runtime = sqlx::query_as::<_, Runtime>(
-        "SELECT id::text, name, description, color, sort_order FROM runtimes WHERE id = $1::uuid",
-    )
-    .bind(&id)
-    .fetch_one(&state.db)
-    .await;
+    let runtime = images::get_runtime(&state.db, id).await;
 
     match runtime {
-        Ok(r) => {
+        Some(r) => {
             let elapsed = start_time.elapsed();
             let execution_time = elapsed.as_secs_f64() * 1000.0;
 
             HtmlTemplate(EditRuntimeTemplate {
+                can_modify: viewer.can_modify,
                 panel_name,
                 panel_font,
                 panel_font_url,
@@ -82,56 +114,70 @@ pub async fn edit_runtime_page_handler(
                 execution_time,
                 active_tab: "runtimes".to_string(),
                 runtime: r,
+                query,
             })
             .into_response()
         }
-        Err(_) => Redirect::to("/runtimes").into_response(),
+        None => Redirect::to("/runtimes").into_response(),
     }
 }
 
 pub async fn update_runtime_handler(
     State(state): State<AppState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
     Form(payload): Form<UpdateRuntimeRequest>,
 ) -> Redirect {
-    let _ = sqlx::query(
-        "UPDATE runtimes SET name = $1, description = $2, color = $3 WHERE id = $4::uuid",
-    )
-    .bind(&payload.name)
-    .bind(&payload.description)
-    .bind(&payload.color)
-    .bind(&id)
-    .execute(&state.db)
-    .await;
-
-    Redirect::to("/runtimes")
+    let input = RuntimeInput {
+        name: payload.name,
+        description: payload.description,
+        color: payload.color,
+    };
+    match images::update_runtime(&state.db, id, &input, None).await {
+        Ok(_) => Redirect::to("/runtimes"),
+        Err(WriteError::Invalid(message)) => Redirect::to(&format!(
+            "/runtimes/{}/edit?{}",
+            id,
+            error_query("invalid", &message)
+        )),
+        Err(e) => {
+            tracing::error!("Failed to update runtime {}: {}", id, e);
+            Redirect::to("/runtimes")
+        }
+    }
 }
 
+/// Deletes a runtime with its images, unless servers still use one of them.
 pub async fn delete_runtime_handler(
     State(state): State<AppState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
 ) -> impl IntoResponse {
-    let _ = sqlx::query("DELETE FROM runtimes WHERE id = $1::uuid")
-        .bind(&id)
-        .execute(&state.db)
-        .await;
-
-    // Return empty 200 OK for hx-delete to just work, but we are pushing URL /runtimes,
-    // so ideally we should redirect or return a script.
-    // Since hx-target="body", we might want to return the redirect header for HTMX.
+    let location = match images::delete_runtime(&state.db, id).await {
+        Ok(()) | Err(DeleteError::NotFound) => "/runtimes".to_string(),
+        Err(DeleteError::InUse(servers)) => {
+            format!(
+                "/runtimes/{}/edit?{}",
+                id,
+                error_query("in_use", &servers.to_string())
+            )
+        }
+        // Only image deletes take a replacement
+        Err(DeleteError::InvalidReplacement) => "/runtimes".to_string(),
+        Err(DeleteError::Db(e)) => {
+            tracing::error!("Failed to delete runtime {}: {}", id, e);
+            "/runtimes".to_string()
+        }
+    };
 
+    // hx-delete targets the body; send the browser on with a full redirect
     let mut headers = axum::http::HeaderMap::new();
-    headers.insert("HX-Redirect", "/runtimes".parse().unwrap());
+    headers.insert("HX-Redirect", location.parse().unwrap());
     (headers, "Deleted")
 }
 
 #[derive(serde::Deserialize)]
 pub struct CreateRuntimeRequest {
-    #[allow(dead_code)]
     pub name: String,
-    #[allow(dead_code)]
     pub description: Option<String>,
-    #[allow(dead_code)]
     #[serde(default = "default_color")]
     pub color: String,
 }
@@ -144,7 +190,7 @@ pub struct UpdateRuntimeRequest {
     pub color: String,
 }
 
-fn default_color() -> String {
+pub fn default_color() -> String {
     "#007bff".to_string()
 }
 
@@ -158,6 +204,8 @@ struct CreateImageTemplate {
     execution_time: f64,
     active_tab: String,
     runtime_id: String,
+    query: CatalogFormQuery,
+    can_modify: bool,
 }
 
 #[derive(serde::Deserialize)]
@@ -169,6 +217,8 @@ pub struct CreateImageRequest {
     pub stop_command: String,
     #[serde(default)]
     pub requires_port: bool,
+    #[serde(default)]
+    pub allow_startup_override: bool,
     pub log_config: String,
     pub config_files: String,
     pub start_config: String,
@@ -181,15 +231,69 @@ pub struct CreateImageRequest {
     pub install_entrypoint: String,
     #[serde(default = "default_array_json")]
     pub variables: String,
+    #[serde(default, deserialize_with = "crate::models::empty_string_as_none")]
+    pub min_ram: Option<i32>,
+    #[serde(default, deserialize_with = "crate::models::empty_string_as_none")]
+    pub min_disk: Option<i32>,
+    #[serde(default, deserialize_with = "crate::models::empty_string_as_none")]
+    pub min_cpu: Option<i32>,
+    #[serde(default)]
+    pub run_as_user: String,
+    #[serde(default)]
+    pub no_new_privileges: bool,
+    #[serde(default, deserialize_with = "crate::models::empty_string_as_none")]
+    pub stop_timeout_seconds: Option<i32>,
+    #[serde(default)]
+    pub pull_policy: Option<String>,
+    #[serde(default = "default_array_json")]
+    pub console_macros: String,
+    #[serde(default)]
+    pub command_mode: Option<String>,
+    /// Comma separated
+    #[serde(default)]
+    pub tags: String,
 }
 
 fn default_array_json() -> String {
     "[]".to_string()
 }
 
+impl From<CreateImageRequest> for ImageInput {
+    fn from(form: CreateImageRequest) -> Self {
+        ImageInput {
+            name: form.name,
+            docker_images: form.docker_images,
+            description: form.description,
+            startup_command: form.startup_command,
+            stop_command: form.stop_command,
+            requires_port: form.requires_port,
+            allow_startup_override: form.allow_startup_override,
+            log_config: form.log_config,
+            config_files: form.config_files,
+            start_config: form.start_config,
+            install_script: form.install_script,
+            install_container: form.install_container,
+            install_entrypoint: form.install_entrypoint,
+            variables: form.variables,
+            min_ram: form.min_ram,
+            min_disk: form.min_disk,
+            min_cpu: form.min_cpu,
+            run_as_user: form.run_as_user,
+            no_new_privileges: form.no_new_privileges,
+            stop_timeout_seconds: form.stop_timeout_seconds,
+            pull_policy: form.pull_policy,
+            console_macros: form.console_macros,
+            command_mode: form.command_mode,
+            tags: parse_tags(&form.tags),
+        }
+    }
+}
+
 pub async fn create_image_page_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
     axum::extract::Path(runtime_id): axum::extract::Path<String>,
+    Query(query): Query<CatalogFormQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
@@ -201,6 +305,7 @@ pub async fn create_image_page_handler(
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(CreateImageTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -208,39 +313,33 @@ pub async fn create_image_page_handler(
         execution_time,
         active_tab: "runtimes".to_string(),
         runtime_id,
+        query,
     })
 }
 
 pub async fn create_image_handler(
     State(state): State<AppState>,
-    axum::extract::Path(runtime_id): axum::extract::Path<String>,
+    axum::extract::Path(runtime_id): axum::extract::Path<Uuid>,
     Form(payload): Form<CreateImageRequest>,
 ) -> Redirect {
-    let id = Uuid::new_v4().to_string();
-
-    let _ = sqlx::query("INSERT INTO images (id, runtime_id, name, docker_images, description, startup_command, stop_command, requires_port, log_config, config_files, start_config, install_script, install_container, install_entrypoint, variables) VALUES ($1::uuid, $2::uuid, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)")
-        .bind(&id)
-        .bind(&runtime_id)
-        .bind(&payload.name)
-        .bind(&payload.docker_images)
-        .bind(&payload.description)
-        .bind(&payload.startup_command)
-        .bind(&payload.stop_command)
-        .bind(payload.requires_port)
-        .bind(&payload.log_config)
-        .bind(&payload.config_files)
-        .bind(&payload.start_config)
-        .bind(&payload.install_script)
-        .bind(&payload.install_container)
-        .bind(&payload.install_entrypoint)
-        .bind(&payload.variables)
-        .execute(&state.db)
-        .await;
-
-    Redirect::to("/runtimes")
+    match images::create_image(&state.db, runtime_id, &payload.into(), None).await {
+        Ok(_) => Redirect::to("/runtimes"),
+        Err(WriteError::Invalid(message)) => Redirect::to(&format!(
+            "/runtimes/{}/images/new?{}",
+            runtime_id,
+            error_query("invalid", &message)
+        )),
+        Err(e) => {
+            tracing::error!("Failed to create image in runtime {}: {}", runtime_id, e);
+            Redirect::to("/runtimes")
+        }
+    }
 }
 
-pub async fn runtimes_page_handler(State(state): State<AppState>) -> impl IntoResponse {
+pub async fn runtimes_page_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
@@ -252,10 +351,18 @@ pub async fn runtimes_page_handler(State(state): State<AppState>) -> impl IntoRe
         .await
         .unwrap_or_default();
 
-    let images_db = sqlx::query_as::<_, Image>("SELECT id::text, runtime_id::text, name, docker_images, description, stop_command, startup_command, log_config, config_files, start_config, requires_port, install_script::text, install_container::text, install_entrypoint::text, variables::text FROM images")
-        .fetch_all(&state.db)
-        .await
-        .unwrap_or_default();
+    let images_db =
+        sqlx::query_as::<_, Image>(&format!("SELECT {} FROM images", images::IMAGE_COLUMNS))
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default();
+
+    let mut tags: Vec<String> = images_db
+        .iter()
+        .flat_map(|i| i.tags.iter().cloned())
+        .collect();
+    tags.sort();
+    tags.dedup();
 
     // Group images by runtime
     let mut runtimes = Vec::new();
@@ -279,6 +386,7 @@ pub async fn runtimes_page_handler(State(state): State<AppState>) -> impl IntoRe
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(RuntimesTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -286,10 +394,14 @@ pub async fn runtimes_page_handler(State(state): State<AppState>) -> impl IntoRe
         execution_time,
         active_tab: "runtimes".to_string(),
         runtimes,
+        tags,
     })
 }
 
-pub async fn create_runtime_page_handler(State(state): State<AppState>) -> impl IntoResponse {
+pub async fn create_runtime_page_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
@@ -300,6 +412,7 @@ pub async fn create_runtime_page_handler(State(state): State<AppState>) -> impl
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(CreateRuntimeTemplate {
+        can_modify: viewer.can_modify,
         panel_font,
         panel_name,
         panel_font_url,
@@ -313,17 +426,14 @@ pub async fn create_runtime_handler(
     State(state): State<AppState>,
     Form(payload): Form<CreateRuntimeRequest>,
 ) -> Redirect {
-    let id = Uuid::new_v4().to_string();
-
-    let _ = sqlx::query(
-        "INSERT INTO runtimes (id, name, description, color) VALUES ($1::uuid, $2, $3, $4)",
-    )
-    .bind(&id)
-    .bind(&payload.name)
-    .bind(&payload.description)
-    .bind(&payload.color)
-    .execute(&state.db)
-    .await;
+    let input = RuntimeInput {
+        name: payload.name,
+        description: payload.description,
+        color: payload.color,
+    };
+    if let Err(e) = images::create_runtime(&state.db, &input, None).await {
+        tracing::error!("Failed to create runtime: {}", e);
+    }
 
     Redirect::to("/runtimes")
 }
@@ -367,6 +477,15 @@ struct EggConfig {
     logs: Option<serde_json::Value>,
     #[serde(default)]
     stop: Option<String>,
+    /// Not part of Pterodactyl's format; written by `egg_export`
+    #[serde(default)]
+    stop_timeout_seconds: Option<i32>,
+    /// Not part of Pterodactyl's format; written by `egg_export`
+    #[serde(default)]
+    pull_policy: Option<String>,
+    /// Not part of Pterodactyl's format; written by `egg_export`
+    #[serde(default)]
+    command_mode: Option<String>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -381,120 +500,199 @@ struct Egg {
     scripts: Option<EggScripts>,
     #[serde(default)]
     variables: Option<Vec<EggVariable>>,
+    /// Not part of Pterodactyl's format; written by `egg_export`
+    #[serde(default)]
+    requirements: Option<EggRequirements>,
+    /// Not part of Pterodactyl's format; written by `egg_export`
+    #[serde(default)]
+    security: Option<EggSecurity>,
+    /// Not part of Pterodactyl's format: `[{ "label", "command" }]` as `egg_export` writes
+    /// it, or `{ "label": "command" }`
+    #[serde(default)]
+    console_macros: Option<serde_json::Value>,
+    /// Not part of Pterodactyl's format; Pelican eggs and `egg_export` carry it
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+/// Container hardening, same fields as the image form.
+#[derive(serde::Deserialize, Debug)]
+struct EggSecurity {
+    #[serde(default)]
+    run_as_user: String,
+    #[serde(default = "default_true")]
+    no_new_privileges: bool,
+}
+
+impl Default for EggSecurity {
+    fn default() -> Self {
+        Self {
+            run_as_user: String::new(),
+            no_new_privileges: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Minimum server limits, in the same units as the image form.
+#[derive(serde::Deserialize, Debug, Default)]
+struct EggRequirements {
+    #[serde(default)]
+    min_ram: i32,
+    #[serde(default)]
+    min_disk: i32,
+    #[serde(default)]
+    min_cpu: i32,
+}
+
+/// Parses a Pterodactyl-style egg (or one `egg_export` wrote) into an image. Nothing
+/// touches the database until the whole egg has parsed.
+pub fn parse_egg(body: &[u8]) -> Result<ImageInput, serde_json::Error> {
+    serde_json::from_slice::<Egg>(body).map(egg_image_input)
+}
+
+fn egg_image_input(egg: Egg) -> ImageInput {
+    // Extract docker images
+    // Store as JSON string: {"Display Name": "ghcr.io/..."}
+    let images_str = serde_json::to_string(&egg.docker_images).unwrap_or("{}".to_string());
+
+    // Helper to normalize JSON fields formats (handles stringified JSON which Pterodactyl sometimes exports)
+    let normalize_json = |v: Option<serde_json::Value>| -> String {
+        match v {
+            Some(serde_json::Value::String(s)) => {
+                match serde_json::from_str::<serde_json::Value>(&s) {
+                    Ok(parsed) => serde_json::to_string_pretty(&parsed).unwrap_or(s),
+                    Err(_) => s,
+                }
+            }
+            Some(v) => serde_json::to_string_pretty(&v).unwrap_or(v.to_string()),
+            None => "{}".to_string(),
+        }
+    };
+
+    // Script details
+    let (script, container, entry) = if let Some(scripts) = egg.scripts {
+        if let Some(inst) = scripts.installation {
+            (inst.script, inst.container, inst.entrypoint)
+        } else {
+            (String::new(), String::new(), "bash".to_string())
+        }
+    } else {
+        (String::new(), String::new(), "bash".to_string())
+    };
+
+    // Variables
+    let vars_json = if let Some(vars) = egg.variables {
+        // Map Pterodactyl vars to strictly string types
+        let mapped_vars: Vec<serde_json::Value> = vars
+            .into_iter()
+            .map(|v| {
+                serde_json::json!({
+                    "name": v.name,
+                    "description": v.description,
+                    "env_variable": v.env_variable,
+                    "default_value": v.default_value,
+                    "user_viewable": v.user_viewable,
+                    "user_editable": v.user_editable,
+                    "rules": v.rules,
+                    // Kept as given: `boolean` turns into a checkbox on the create form
+                    "field_type": v.field_type.filter(|t| !t.trim().is_empty()).unwrap_or("text".to_string())
+                })
+            })
+            .collect();
+        serde_json::to_string(&mapped_vars).unwrap_or("[]".to_string())
+    } else {
+        "[]".to_string()
+    };
+
+    let console_macros = serde_json::to_string(
+        &egg.console_macros
+            .as_ref()
+            .map(parse_console_macros)
+            .unwrap_or_default(),
+    )
+    .unwrap_or_else(|_| "[]".to_string());
+    let requirements = egg.requirements.unwrap_or_default();
+    let security = egg.security.unwrap_or_default();
+
+    ImageInput {
+        name: egg.name,
+        docker_images: images_str,
+        description: egg.description,
+        startup_command: egg.startup,
+        stop_command: egg.config.stop.unwrap_or_else(|| "stop".to_string()),
+        requires_port: true,
+        allow_startup_override: false,
+        log_config: normalize_json(egg.config.logs),
+        config_files: normalize_json(egg.config.files),
+        start_config: normalize_json(egg.config.startup),
+        install_script: script,
+        install_container: container,
+        install_entrypoint: entry,
+        variables: vars_json,
+        min_ram: Some(requirements.min_ram),
+        min_disk: Some(requirements.min_disk),
+        min_cpu: Some(requirements.min_cpu),
+        run_as_user: security.run_as_user,
+        no_new_privileges: security.no_new_privileges,
+        stop_timeout_seconds: egg.config.stop_timeout_seconds,
+        pull_policy: egg.config.pull_policy,
+        console_macros,
+        command_mode: egg.config.command_mode,
+        tags: parse_tags(&egg.tags.unwrap_or_default().join(",")),
+    }
 }
 
 pub async fn import_egg_handler(
     State(state): State<AppState>,
-    axum::extract::Path(runtime_id): axum::extract::Path<String>,
+    axum::extract::Path(runtime_id): axum::extract::Path<Uuid>,
     mut multipart: axum::extract::Multipart,
 ) -> impl IntoResponse {
     tracing::info!("Starting Egg Import for Runtime ID: {}", runtime_id);
 
-    while let Ok(Some(field)) = multipart.next_field().await {
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (e.status(), format!("Failed to read upload: {}", e)).into_response(),
+        };
         let name = field.name().unwrap_or("").to_string();
         if name == "egg_file" {
-            let data = match field.bytes().await {
-                Ok(d) => d,
-                Err(e) => return format!("Failed to read file data: {}", e).into_response(),
-            };
-
-            let egg: Egg = match serde_json::from_slice(&data) {
+            let upload =
+                match read_text_field(field, state.upload_limits.egg, "EGG_UPLOAD_SIZE").await {
+                    Ok(upload) => upload,
+                    Err(e) => return e.into_response(),
+                };
+
+            // Nothing touches the database until the whole egg has parsed
+            let egg: Egg = match upload.parse_json().await {
                 Ok(e) => e,
                 Err(e) => {
-                    tracing::error!("Failed to parse Egg JSON: {}", e);
-                    // Try to print a snippet of the json for debugging
-                    let snippet = String::from_utf8_lossy(&data)
-                        .chars()
-                        .take(200)
-                        .collect::<String>();
-                    tracing::error!("JSON Snippet: {}", snippet);
-                    return format!("Failed to parse Egg JSON: {}", e).into_response();
+                    tracing::warn!("Rejected egg import for runtime {}: {}", runtime_id, e);
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to parse Egg JSON: {}", e),
+                    )
+                        .into_response();
                 }
             };
 
             tracing::info!("Parsed Egg: {}", egg.name);
-
-            let id = Uuid::new_v4().to_string();
-
-            // Extract docker images
-            // Store as JSON string: {"Display Name": "ghcr.io/..."}
-            let images_str = serde_json::to_string(&egg.docker_images).unwrap_or("{}".to_string());
-
-            let stop_cmd = egg.config.stop.unwrap_or_else(|| "stop".to_string());
-
-            // Helper to normalize JSON fields formats (handles stringified JSON which Pterodactyl sometimes exports)
-            let normalize_json = |v: Option<serde_json::Value>| -> String {
-                match v {
-                    Some(serde_json::Value::String(s)) => {
-                        match serde_json::from_str::<serde_json::Value>(&s) {
-                            Ok(parsed) => serde_json::to_string_pretty(&parsed).unwrap_or(s),
-                            Err(_) => s,
-                        }
-                    }
-                    Some(v) => serde_json::to_string_pretty(&v).unwrap_or(v.to_string()),
-                    None => "{}".to_string(),
+            let name = egg.name.clone();
+            match images::create_image(&state.db, runtime_id, &egg_image_input(egg), None).await {
+                Ok(_) => tracing::info!("Successfully imported egg: {}", name),
+                Err(WriteError::Invalid(message)) => {
+                    tracing::warn!(
+                        "Rejected egg import for runtime {}: {}",
+                        runtime_id,
+                        message
+                    );
+                    return (StatusCode::BAD_REQUEST, format!("Invalid egg: {}", message))
+                        .into_response();
                 }
-            };
-
-            let log_cfg = normalize_json(egg.config.logs);
-            let start_cfg = normalize_json(egg.config.startup);
-            let file_cfg = normalize_json(egg.config.files);
-
-            // Script details
-            let (script, container, entry) = if let Some(scripts) = egg.scripts {
-                if let Some(inst) = scripts.installation {
-                    (inst.script, inst.container, inst.entrypoint)
-                } else {
-                    (String::new(), String::new(), "bash".to_string())
-                }
-            } else {
-                (String::new(), String::new(), "bash".to_string())
-            };
-
-            // Variables
-            let vars_json = if let Some(vars) = egg.variables {
-                // Map Pterodactyl vars to strictly string types
-                let mapped_vars: Vec<serde_json::Value> = vars
-                    .into_iter()
-                    .map(|v| {
-                        serde_json::json!({
-                            "name": v.name,
-                            "description": v.description,
-                            "env_variable": v.env_variable,
-                            "default_value": v.default_value,
-                            "user_viewable": v.user_viewable,
-                            "user_editable": v.user_editable,
-                            "rules": v.rules,
-                            "field_type": v.field_type.unwrap_or("text".to_string())
-                        })
-                    })
-                    .collect();
-                serde_json::to_string(&mapped_vars).unwrap_or("[]".to_string())
-            } else {
-                "[]".to_string()
-            };
-
-            let q_res = sqlx::query("INSERT INTO images (id, runtime_id, name, docker_images, description, startup_command, stop_command, requires_port, log_config, config_files, start_config, install_script, install_container, install_entrypoint, variables) VALUES ($1::uuid, $2::uuid, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)")
-                .bind(&id)
-                .bind(&runtime_id)
-                .bind(&egg.name)
-                .bind(&images_str)
-                .bind(&egg.description)
-                .bind(&egg.startup)
-                .bind(&stop_cmd)
-                .bind(true)
-                .bind(&log_cfg)
-                .bind(&file_cfg)
-                .bind(&start_cfg)
-                .bind(&script)
-                .bind(&container)
-                .bind(&entry)
-                .bind(&vars_json)
-                .execute(&state.db)
-                .await;
-
-            match q_res {
-                Ok(_) => tracing::info!("Successfully imported egg: {}", egg.name),
                 Err(e) => {
                     tracing::error!("Database error importing egg: {}", e);
                     return format!("Database Error: {}", e).into_response();
@@ -520,11 +718,19 @@ struct EditImageTemplate {
     active_tab: String,
     runtime_id: String,
     image: Image,
+    stale_servers: i64,
+    /// (id, "Runtime / Image") the servers of an image in use can be moved to before a delete
+    replacements: Vec<(String, String)>,
+    nodes: Vec<Node>,
+    query: CatalogFormQuery,
+    can_modify: bool,
 }
 
 pub async fn edit_image_page_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
     axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(String, String)>,
+    Query(query): Query<CatalogFormQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_version = env!("CARGO_PKG_VERSION").to_string();
@@ -532,10 +738,13 @@ pub async fn edit_image_page_handler(
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
 
-    let image = sqlx::query_as::<_, Image>("SELECT id::text, runtime_id::text, name, docker_images, description, stop_command, startup_command, log_config, config_files, start_config, requires_port, install_script::text, install_container::text, install_entrypoint::text, variables::text FROM images WHERE id = $1::uuid")
-        .bind(&image_id)
-        .fetch_one(&state.db)
-        .await;
+    let image = sqlx::query_as::<_, Image>(&format!(
+        "SELECT {} FROM images WHERE id = $1::uuid",
+        images::IMAGE_COLUMNS
+    ))
+    .bind(&image_id)
+    .fetch_one(&state.db)
+    .await;
 
     match image {
         Ok(mut img) => {
@@ -587,11 +796,31 @@ pub async fn edit_image_page_handler(
             img.log_config = smart_prettify(&img.log_config);
             img.start_config = smart_prettify(&img.start_config);
             img.variables = smart_prettify(&img.variables);
+            img.console_macros = smart_prettify(&img.console_macros);
+
+            // Servers still running the startup command this image used before its last edit
+            let stale_servers: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM servers s JOIN images i ON s.image_id = i.id WHERE i.id = $1::uuid AND i.previous_startup_command <> '' AND s.startup_command = i.previous_startup_command")
+                .bind(&image_id)
+                .fetch_one(&state.db)
+                .await
+                .unwrap_or(0);
+
+            // Offered by the "in use" banner for the forced delete
+            let replacements: Vec<(String, String)> = if query.error.as_deref() == Some("in_use") {
+                sqlx::query_as("SELECT i.id::text, r.name || ' / ' || i.name FROM images i JOIN runtimes r ON r.id = i.runtime_id WHERE i.id <> $1::uuid ORDER BY r.sort_order, r.name, i.name")
+                    .bind(&image_id)
+                    .fetch_all(&state.db)
+                    .await
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
 
             let elapsed = start_time.elapsed();
             let execution_time = elapsed.as_secs_f64() * 1000.0;
 
             HtmlTemplate(EditImageTemplate {
+                can_modify: viewer.can_modify,
                 panel_name,
                 panel_font,
                 panel_font_url,
@@ -600,6 +829,10 @@ pub async fn edit_image_page_handler(
                 active_tab: "runtimes".to_string(),
                 runtime_id,
                 image: img,
+                stale_servers,
+                replacements,
+                nodes: state.get_nodes().await,
+                query,
             })
             .into_response()
         }
@@ -609,41 +842,308 @@ pub async fn edit_image_page_handler(
 
 pub async fn update_image_handler(
     State(state): State<AppState>,
-    axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(String, String)>,
+    axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(Uuid, Uuid)>,
     Form(payload): Form<CreateImageRequest>, // Reusing request struct since fields are same
 ) -> Redirect {
-    let _ = sqlx::query("UPDATE images SET name = $1, docker_images = $2, description = $3, startup_command = $4, stop_command = $5, requires_port = $6, log_config = $7, config_files = $8, start_config = $9, install_script = $10, install_container = $11, install_entrypoint = $12, variables = $13 WHERE id = $14::uuid")
-        .bind(&payload.name)
-        .bind(&payload.docker_images)
-        .bind(&payload.description)
-        .bind(&payload.startup_command)
-        .bind(&payload.stop_command)
-        .bind(payload.requires_port)
-        .bind(&payload.log_config)
-        .bind(&payload.config_files)
-        .bind(&payload.start_config)
-        .bind(&payload.install_script)
-        .bind(&payload.install_container)
-        .bind(&payload.install_entrypoint)
-        .bind(&payload.variables)
+    let edit_page = format!("/runtimes/{}/images/{}/edit", runtime_id, image_id);
+    match images::update_image(&state.db, image_id, None, &payload.into(), None).await {
+        Ok(_) => {}
+        Err(WriteError::Invalid(message)) => {
+            return Redirect::to(&format!(
+                "{}?{}",
+                edit_page,
+                error_query("invalid", &message)
+            ));
+        }
+        Err(e) => tracing::error!("Failed to update image {}: {}", image_id, e),
+    }
+
+    Redirect::to(&format!("/runtimes/{}/edit", runtime_id))
+}
+
+pub async fn propagate_startup_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(String, String)>,
+) -> Redirect {
+    let res = sqlx::query("UPDATE servers s SET startup_command = i.startup_command FROM images i WHERE s.image_id = i.id AND i.id = $1::uuid AND i.previous_startup_command <> '' AND s.startup_command = i.previous_startup_command")
         .bind(&image_id)
         .execute(&state.db)
         .await;
 
-    Redirect::to(&format!("/runtimes/{}/edit", runtime_id))
+    match res {
+        Ok(r) => tracing::info!(
+            "Propagated startup command of image {} to {} servers",
+            image_id,
+            r.rows_affected()
+        ),
+        Err(e) => tracing::error!(
+            "Failed to propagate startup command of image {}: {}",
+            image_id,
+            e
+        ),
+    }
+
+    Redirect::to(&format!(
+        "/runtimes/{}/images/{}/edit",
+        runtime_id, image_id
+    ))
 }
 
+/// Sends the browser to a short-lived signed URL for the image's egg export,
+/// so the link can be shared or fetched without a session.
+pub async fn export_image_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((_runtime_id, image_id)): axum::extract::Path<(String, Uuid)>,
+) -> Redirect {
+    let secret = state.download_secret.read().await.clone();
+    let path = signed_urls::signed_path(
+        &secret,
+        DownloadKind::ImageExport,
+        &image_id.to_string(),
+        signed_urls::default_ttl(),
+    );
+    Redirect::to(&path)
+}
+
+#[derive(serde::Deserialize)]
+pub struct InstallTestQuery {
+    pub node_id: String,
+}
+
+/// Relays an install test of the image's saved script on the chosen node as SSE.
+/// Admin only: the script runs arbitrary shell in a container on the node's Docker host.
+pub async fn test_install_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((_runtime_id, image_id)): axum::extract::Path<(String, Uuid)>,
+    Query(query): Query<InstallTestQuery>,
+    jar: CookieJar,
+) -> Response {
+    if !session_user(&state, &jar)
+        .await
+        .is_some_and(|u| u.is_admin())
+    {
+        return (StatusCode::FORBIDDEN, "Only admins can run install tests").into_response();
+    }
+
+    let image = sqlx::query_as::<_, Image>(&format!(
+        "SELECT {} FROM images WHERE id = $1",
+        images::IMAGE_COLUMNS
+    ))
+    .bind(image_id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    let Some(image) = image else {
+        return (StatusCode::NOT_FOUND, "Image not found").into_response();
+    };
+    let Some(node) = state.get_node_with_token(&query.node_id).await else {
+        return (StatusCode::NOT_FOUND, "Node not found").into_response();
+    };
+
+    // Variables get their defaults, the same values a fresh server would start with
+    let environment = serde_json::from_str::<Vec<Variable>>(&image.variables)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| (v.env_variable, v.default_value))
+        .collect();
+
+    let request = InstallTestRequest {
+        container: image.install_container.clone(),
+        entrypoint: if image.install_entrypoint.trim().is_empty() {
+            "bash".to_string()
+        } else {
+            image.install_entrypoint.clone()
+        },
+        script: image.install_script.clone(),
+        environment,
+        memory_limit: None,
+    };
+
+    tracing::info!(
+        "Install test of image {} started on node {}",
+        image.name,
+        node.name
+    );
+    let first = match node_api::start_install_test(&state.http_client, &node, &request).await {
+        Ok(res) => InstallRelay::Streaming(res, Vec::new()),
+        Err(e) => InstallRelay::Failed(e),
+    };
+
+    let events = futures_util::stream::unfold(first, next_install_event);
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+enum InstallRelay {
+    /// Node response plus bytes of a line that hasn't been terminated yet
+    Streaming(reqwest::Response, Vec<u8>),
+    /// Events already parsed from the last chunk, then carry on reading
+    Buffered(
+        std::collections::VecDeque<InstallTestEvent>,
+        Box<InstallRelay>,
+    ),
+    Failed(String),
+    Done,
+}
+
+async fn next_install_event(
+    mut relay: InstallRelay,
+) -> Option<(Result<Event, std::convert::Infallible>, InstallRelay)> {
+    let sse = |event: &InstallTestEvent| {
+        Event::default()
+            .event(event.kind())
+            .json_data(event)
+            .unwrap_or_default()
+    };
+
+    loop {
+        relay = match relay {
+            InstallRelay::Done => return None,
+            InstallRelay::Failed(message) => {
+                let event = InstallTestEvent::Error { message };
+                return Some((Ok(sse(&event)), InstallRelay::Done));
+            }
+            InstallRelay::Buffered(mut queue, rest) => match queue.pop_front() {
+                Some(event) => {
+                    let finished = !matches!(event, InstallTestEvent::Output { .. });
+                    let next = if finished {
+                        InstallRelay::Done
+                    } else {
+                        InstallRelay::Buffered(queue, rest)
+                    };
+                    return Some((Ok(sse(&event)), next));
+                }
+                None => *rest,
+            },
+            InstallRelay::Streaming(mut res, mut pending) => match res.chunk().await {
+                Ok(Some(bytes)) => {
+                    pending.extend_from_slice(&bytes);
+                    let mut queue = std::collections::VecDeque::new();
+                    while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = pending.drain(..=pos).collect();
+                        match serde_json::from_slice::<InstallTestEvent>(&line) {
+                            Ok(event) => queue.push_back(event),
+                            Err(e) => tracing::warn!("Unreadable install test event: {}", e),
+                        }
+                    }
+                    InstallRelay::Buffered(queue, Box::new(InstallRelay::Streaming(res, pending)))
+                }
+                // The node always ends with exit/error, so EOF here means the stream was cut
+                Ok(None) => InstallRelay::Failed(
+                    "Node closed the stream before the script finished".to_string(),
+                ),
+                Err(e) => InstallRelay::Failed(format!("Lost connection to node: {}", e)),
+            },
+        };
+    }
+}
+
+/// Builds a Pterodactyl-style egg that `import_egg_handler` can read back.
+pub fn egg_export(image: &Image) -> serde_json::Value {
+    let parse = |s: &str, fallback: serde_json::Value| -> serde_json::Value {
+        serde_json::from_str(s).unwrap_or(fallback)
+    };
+
+    serde_json::json!({
+        "meta": { "version": "PTDL_v2" },
+        "exported_at": chrono::Utc::now().to_rfc3339(),
+        "name": image.name,
+        "description": image.description,
+        "docker_images": parse(&image.docker_images, serde_json::json!({})),
+        "startup": image.startup_command,
+        "config": {
+            "files": image.config_files,
+            "startup": image.start_config,
+            "logs": image.log_config,
+            "stop": image.stop_command,
+            "stop_timeout_seconds": image.stop_timeout_seconds,
+            "pull_policy": image.pull_policy,
+            "command_mode": image.command_mode,
+        },
+        "scripts": {
+            "installation": {
+                "script": image.install_script,
+                "container": image.install_container,
+                "entrypoint": image.install_entrypoint,
+            }
+        },
+        "variables": parse(&image.variables, serde_json::json!([])),
+        "requirements": {
+            "min_ram": image.min_ram,
+            "min_disk": image.min_disk,
+            "min_cpu": image.min_cpu,
+        },
+        "security": {
+            "run_as_user": image.run_as_user,
+            "no_new_privileges": image.no_new_privileges,
+        },
+        "console_macros": parse(&image.console_macros, serde_json::json!([])),
+        "tags": image.tags,
+    })
+}
+
+/// Deletes an image, unless servers were created from it.
 pub async fn delete_image_handler(
     State(state): State<AppState>,
-    axum::extract::Path((_runtime_id, image_id)): axum::extract::Path<(String, String)>,
+    axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(Uuid, Uuid)>,
 ) -> impl IntoResponse {
-    let _ = sqlx::query("DELETE FROM images WHERE id = $1::uuid")
-        .bind(&image_id)
-        .execute(&state.db)
-        .await;
+    let result = images::delete_image(&state.db, image_id).await;
+    image_delete_redirect(runtime_id, image_id, result)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReassignImageForm {
+    pub replacement_id: String,
+}
+
+/// The forced delete from the "in use" banner: moves the image's servers onto another image
+/// (flagged for a recreate), then deletes it.
+pub async fn reassign_delete_image_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((runtime_id, image_id)): axum::extract::Path<(Uuid, Uuid)>,
+    Form(form): Form<ReassignImageForm>,
+) -> impl IntoResponse {
+    let replacement = Uuid::parse_str(&form.replacement_id).unwrap_or_default();
+    let result = images::delete_image_reassigning(&state.db, image_id, replacement).await;
+    if let Ok(moved) = &result {
+        tracing::info!(
+            "Deleted image {}, moving {} server(s) to image {}",
+            image_id,
+            moved,
+            replacement
+        );
+    }
+    image_delete_redirect(runtime_id, image_id, result.map(|_| ()))
+}
+
+/// Where the browser goes after an image delete: the runtimes list, or back to the image
+/// with the reason it is still there
+fn image_delete_redirect(
+    runtime_id: Uuid,
+    image_id: Uuid,
+    result: Result<(), DeleteError>,
+) -> impl IntoResponse {
+    let edit = |error: &str, detail: &str| {
+        format!(
+            "/runtimes/{}/images/{}/edit?{}",
+            runtime_id,
+            image_id,
+            error_query(error, detail)
+        )
+    };
+    let location = match result {
+        Ok(()) | Err(DeleteError::NotFound) => "/runtimes".to_string(),
+        Err(DeleteError::InUse(servers)) => edit("in_use", &servers.to_string()),
+        Err(DeleteError::InvalidReplacement) => edit("invalid_replacement", ""),
+        Err(DeleteError::Db(e)) => {
+            tracing::error!("Failed to delete image {}: {}", image_id, e);
+            "/runtimes".to_string()
+        }
+    };
 
     let mut headers = axum::http::HeaderMap::new();
-    headers.insert("HX-Redirect", "/runtimes".parse().unwrap());
+    headers.insert("HX-Redirect", location.parse().unwrap());
     (headers, "Deleted")
 }
 
@@ -0,0 +1,463 @@
+//! `/api/v1/runtimes` and `/api/v1/images`: the runtime and image catalog as JSON, for
+//! clients that keep their eggs in git and sync them from CI. Writes go through
+//! `services::images` like the forms do. A `POST` carrying an `external_id` that is already
+//! known updates that runtime or image instead of adding a second one, so a sync can be
+//! re-run as often as needed.
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::http::handlers::runtimes::{default_color, parse_egg};
+use crate::models::{Image, Runtime, parse_tags};
+use crate::services::images::{
+    self, DeleteError, IMAGE_COLUMNS, ImageInput, RUNTIME_COLUMNS, RuntimeInput, WriteError,
+};
+use crate::state::AppState;
+
+fn error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": code, "message": message.into() })),
+    )
+        .into_response()
+}
+
+fn write_error(e: WriteError) -> Response {
+    match e {
+        WriteError::Invalid(message) => error(StatusCode::UNPROCESSABLE_ENTITY, "invalid", message),
+        WriteError::ExternalIdTaken => error(
+            StatusCode::CONFLICT,
+            "external_id_taken",
+            "Another resource already uses this external_id",
+        ),
+        WriteError::Db(e) => {
+            tracing::error!("Catalog API write failed: {}", e);
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                "Could not save; see the panel log",
+            )
+        }
+    }
+}
+
+fn delete_error(e: DeleteError, what: &str) -> Response {
+    match e {
+        DeleteError::InUse(servers) => error(
+            StatusCode::CONFLICT,
+            "in_use",
+            format!("The {} is used by {} server(s)", what, servers),
+        ),
+        DeleteError::NotFound => error(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            format!("No such {}", what),
+        ),
+        DeleteError::InvalidReplacement => error(
+            StatusCode::BAD_REQUEST,
+            "invalid_replacement",
+            "reassign_to must name another existing image",
+        ),
+        DeleteError::Db(e) => {
+            tracing::error!("Catalog API delete of {} failed: {}", what, e);
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                "Could not delete; see the panel log",
+            )
+        }
+    }
+}
+
+/// The saved runtime as the answer to a write: `201` for a new one, `200` otherwise.
+async fn runtime_response(state: &AppState, id: Uuid, status: StatusCode) -> Response {
+    match images::get_runtime(&state.db, id).await {
+        Some(runtime) => (status, Json(runtime)).into_response(),
+        None => error(StatusCode::NOT_FOUND, "not_found", "No such runtime"),
+    }
+}
+
+async fn image_response(state: &AppState, id: Uuid, status: StatusCode) -> Response {
+    match images::get_image(&state.db, id).await {
+        Some(image) => (status, Json(image)).into_response(),
+        None => error(StatusCode::NOT_FOUND, "not_found", "No such image"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CatalogListQuery {
+    pub external_id: Option<String>,
+    /// Images only: limit the list to one runtime
+    pub runtime_id: Option<Uuid>,
+    /// Images only: limit the list to images with this tag
+    pub tag: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RuntimeBody {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default = "default_color")]
+    pub color: String,
+    pub external_id: Option<String>,
+}
+
+impl RuntimeBody {
+    fn input(&self) -> RuntimeInput {
+        RuntimeInput {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            color: self.color.clone(),
+        }
+    }
+}
+
+/// `GET /api/v1/runtimes`, optionally `?external_id=`
+pub async fn list_runtimes_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CatalogListQuery>,
+) -> Response {
+    let sql = format!(
+        "SELECT {} FROM runtimes WHERE ($1::text IS NULL OR external_id = $1) ORDER BY sort_order ASC, name ASC",
+        RUNTIME_COLUMNS
+    );
+    match sqlx::query_as::<_, Runtime>(&sql)
+        .bind(query.external_id)
+        .fetch_all(&state.db)
+        .await
+    {
+        Ok(runtimes) => Json(runtimes).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list runtimes: {}", e);
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                "Could not list runtimes",
+            )
+        }
+    }
+}
+
+pub async fn get_runtime_handler(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    runtime_response(&state, id, StatusCode::OK).await
+}
+
+/// `POST /api/v1/runtimes`: creates a runtime, or updates the one with the same `external_id`.
+pub async fn upsert_runtime_handler(
+    State(state): State<AppState>,
+    Json(body): Json<RuntimeBody>,
+) -> Response {
+    let input = body.input();
+    let external_id = body.external_id.as_deref();
+    if let Some(id) = match external_id {
+        Some(ext) => images::runtime_by_external_id(&state.db, ext).await,
+        None => None,
+    } {
+        return match images::update_runtime(&state.db, id, &input, external_id).await {
+            Ok(_) => runtime_response(&state, id, StatusCode::OK).await,
+            Err(e) => write_error(e),
+        };
+    }
+    match images::create_runtime(&state.db, &input, external_id).await {
+        Ok(id) => runtime_response(&state, id, StatusCode::CREATED).await,
+        Err(e) => write_error(e),
+    }
+}
+
+/// `PUT /api/v1/runtimes/{id}`; `external_id` is left alone unless given.
+pub async fn update_runtime_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<RuntimeBody>,
+) -> Response {
+    match images::update_runtime(&state.db, id, &body.input(), body.external_id.as_deref()).await {
+        Ok(true) => runtime_response(&state, id, StatusCode::OK).await,
+        Ok(false) => error(StatusCode::NOT_FOUND, "not_found", "No such runtime"),
+        Err(e) => write_error(e),
+    }
+}
+
+/// `DELETE /api/v1/runtimes/{id}`: `409` while a server uses one of its images.
+pub async fn delete_runtime_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Response {
+    match images::delete_runtime(&state.db, id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => delete_error(e, "runtime"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportEggQuery {
+    pub external_id: Option<String>,
+}
+
+/// `POST /api/v1/runtimes/{id}/import-egg`: the egg JSON is the request body. With
+/// `?external_id=` a re-import updates the image imported before.
+pub async fn import_egg_handler(
+    State(state): State<AppState>,
+    Path(runtime_id): Path<Uuid>,
+    Query(query): Query<ImportEggQuery>,
+    body: Bytes,
+) -> Response {
+    if images::get_runtime(&state.db, runtime_id).await.is_none() {
+        return error(StatusCode::NOT_FOUND, "not_found", "No such runtime");
+    }
+    let input = match parse_egg(&body) {
+        Ok(input) => input,
+        Err(e) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "invalid_egg",
+                format!("Invalid egg: {}", e),
+            );
+        }
+    };
+    upsert_image(&state, runtime_id, &input, query.external_id.as_deref()).await
+}
+
+async fn upsert_image(
+    state: &AppState,
+    runtime_id: Uuid,
+    input: &ImageInput,
+    external_id: Option<&str>,
+) -> Response {
+    if let Some(id) = match external_id {
+        Some(ext) => images::image_by_external_id(&state.db, ext).await,
+        None => None,
+    } {
+        return match images::update_image(&state.db, id, Some(runtime_id), input, external_id).await
+        {
+            Ok(_) => image_response(state, id, StatusCode::OK).await,
+            Err(e) => write_error(e),
+        };
+    }
+    match images::create_image(&state.db, runtime_id, input, external_id).await {
+        Ok(id) => image_response(state, id, StatusCode::CREATED).await,
+        Err(e) => write_error(e),
+    }
+}
+
+/// An image as JSON. The JSON-typed columns (`docker_images`, `log_config`, `config_files`,
+/// `start_config`, `variables`, `console_macros`) take either a string, as the forms send
+/// them, or the JSON value itself.
+#[derive(Deserialize)]
+pub struct ImageBody {
+    /// The runtime the image belongs to; required to create, optional to update
+    pub runtime_id: Option<Uuid>,
+    /// Alternative to `runtime_id` for clients that only know their own keys
+    pub runtime_external_id: Option<String>,
+    pub external_id: Option<String>,
+    pub name: String,
+    pub docker_images: Value,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub startup_command: String,
+    #[serde(default)]
+    pub stop_command: String,
+    #[serde(default)]
+    pub requires_port: bool,
+    #[serde(default)]
+    pub allow_startup_override: bool,
+    pub log_config: Option<Value>,
+    pub config_files: Option<Value>,
+    pub start_config: Option<Value>,
+    #[serde(default)]
+    pub install_script: String,
+    #[serde(default)]
+    pub install_container: String,
+    #[serde(default)]
+    pub install_entrypoint: String,
+    pub variables: Option<Value>,
+    pub min_ram: Option<i32>,
+    pub min_disk: Option<i32>,
+    pub min_cpu: Option<i32>,
+    #[serde(default)]
+    pub run_as_user: String,
+    #[serde(default = "default_true")]
+    pub no_new_privileges: bool,
+    pub stop_timeout_seconds: Option<i32>,
+    pub pull_policy: Option<String>,
+    pub console_macros: Option<Value>,
+    pub command_mode: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A JSON column as stored: strings as they are, anything else serialized.
+fn json_text(value: Option<&Value>, default: &str) -> String {
+    match value {
+        None | Some(Value::Null) => default.to_string(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+impl ImageBody {
+    fn input(&self) -> ImageInput {
+        ImageInput {
+            name: self.name.clone(),
+            docker_images: json_text(Some(&self.docker_images), ""),
+            description: self.description.clone(),
+            startup_command: self.startup_command.clone(),
+            stop_command: self.stop_command.clone(),
+            requires_port: self.requires_port,
+            allow_startup_override: self.allow_startup_override,
+            log_config: json_text(self.log_config.as_ref(), "{}"),
+            config_files: json_text(self.config_files.as_ref(), "{}"),
+            start_config: json_text(self.start_config.as_ref(), "{}"),
+            install_script: self.install_script.clone(),
+            install_container: self.install_container.clone(),
+            install_entrypoint: self.install_entrypoint.clone(),
+            variables: json_text(self.variables.as_ref(), "[]"),
+            min_ram: self.min_ram,
+            min_disk: self.min_disk,
+            min_cpu: self.min_cpu,
+            run_as_user: self.run_as_user.clone(),
+            no_new_privileges: self.no_new_privileges,
+            stop_timeout_seconds: self.stop_timeout_seconds,
+            pull_policy: self.pull_policy.clone(),
+            console_macros: json_text(self.console_macros.as_ref(), "[]"),
+            command_mode: self.command_mode.clone(),
+            tags: parse_tags(&self.tags.join(",")),
+        }
+    }
+
+    /// The runtime named by `runtime_id` or `runtime_external_id`, if either is given.
+    /// `Err` holds the response for one that doesn't exist.
+    async fn runtime(&self, state: &AppState) -> Result<Option<Uuid>, Response> {
+        let id = match (self.runtime_id, self.runtime_external_id.as_deref()) {
+            (Some(id), _) => images::get_runtime(&state.db, id).await.map(|_| id),
+            (None, Some(ext)) => images::runtime_by_external_id(&state.db, ext).await,
+            (None, None) => return Ok(None),
+        };
+        id.map(Some).ok_or_else(|| {
+            error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "runtime_not_found",
+                "No such runtime",
+            )
+        })
+    }
+}
+
+/// `GET /api/v1/images`, optionally `?runtime_id=`, `?external_id=` and `?tag=`
+pub async fn list_images_handler(
+    State(state): State<AppState>,
+    Query(query): Query<CatalogListQuery>,
+) -> Response {
+    let sql = format!(
+        "SELECT {} FROM images WHERE ($1::text IS NULL OR external_id = $1) \
+         AND ($2::uuid IS NULL OR runtime_id = $2) AND ($3::text IS NULL OR $3 = ANY(tags)) ORDER BY name ASC",
+        IMAGE_COLUMNS
+    );
+    let rows = sqlx::query_as::<_, Image>(&sql)
+        .bind(query.external_id)
+        .bind(query.runtime_id)
+        .bind(query.tag.map(|t| t.trim().to_lowercase()))
+        .fetch_all(&state.db)
+        .await;
+    match rows {
+        Ok(images) => Json(images).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list images: {}", e);
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                "Could not list images",
+            )
+        }
+    }
+}
+
+pub async fn get_image_handler(State(state): State<AppState>, Path(id): Path<Uuid>) -> Response {
+    image_response(&state, id, StatusCode::OK).await
+}
+
+/// `POST /api/v1/images`: creates an image, or updates the one with the same `external_id`.
+pub async fn upsert_image_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ImageBody>,
+) -> Response {
+    let runtime_id = match body.runtime(&state).await {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            return error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid",
+                "runtime_id or runtime_external_id is required",
+            );
+        }
+        Err(response) => return response,
+    };
+    upsert_image(
+        &state,
+        runtime_id,
+        &body.input(),
+        body.external_id.as_deref(),
+    )
+    .await
+}
+
+/// `PUT /api/v1/images/{id}`; the runtime and `external_id` are left alone unless given.
+pub async fn update_image_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ImageBody>,
+) -> Response {
+    let runtime_id = match body.runtime(&state).await {
+        Ok(runtime_id) => runtime_id,
+        Err(response) => return response,
+    };
+    match images::update_image(
+        &state.db,
+        id,
+        runtime_id,
+        &body.input(),
+        body.external_id.as_deref(),
+    )
+    .await
+    {
+        Ok(true) => image_response(&state, id, StatusCode::OK).await,
+        Ok(false) => error(StatusCode::NOT_FOUND, "not_found", "No such image"),
+        Err(e) => write_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeleteImageQuery {
+    /// Forces the delete: the image's servers move to this image first
+    pub reassign_to: Option<Uuid>,
+}
+
+/// `DELETE /api/v1/images/{id}`: `409` while servers use the image, unless `?reassign_to=`
+/// names an image to move them to (flagged for a recreate).
+pub async fn delete_image_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DeleteImageQuery>,
+) -> Response {
+    let result = match query.reassign_to {
+        Some(replacement) => images::delete_image_reassigning(&state.db, id, replacement)
+            .await
+            .map(|_| ()),
+        None => images::delete_image(&state.db, id).await,
+    };
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => delete_error(e, "image"),
+    }
+}
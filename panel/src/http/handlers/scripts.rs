@@ -1,8 +1,17 @@
+use crate::{
+    services::{
+        install_tokens::{self, TokenPurpose},
+        signed_urls::{self, DownloadKind},
+    },
+    state::AppState,
+};
 use axum::{
-    extract::{State, Path},
-    http::HeaderMap,
+    extract::{Form, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
 };
-use crate::{state::AppState, models::Node};
+use serde::Deserialize;
+use uuid::Uuid;
 
 const LOGO: &str = r#"
 ............................+@@@#+:..............................+%@@@@*............................
@@ -32,31 +41,86 @@ const LOGO: &str = r#"
 ...........................@@@@@@@@@@@:.......................#@@@@@@@@@@...........................
 "#;
 
+#[derive(Deserialize)]
+pub struct InstallScriptQuery {
+    t: Option<String>,
+}
+
 pub async fn install_script_handler(
     State(state): State<AppState>,
-    Path(id): Path<String>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<InstallScriptQuery>,
     headers: HeaderMap,
-) -> String {
-    let host = headers.get("host").and_then(|h| h.to_str().ok()).unwrap_or("127.0.0.1:3000");
+) -> impl IntoResponse {
+    let base_url = state.base_url(&headers);
 
-    // Fetch node to get configured port and token
-    let node_result = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token FROM nodes WHERE id = $1::uuid")
-        .bind(&id)
-        .fetch_optional(&state.db)
-        .await;
-    
-    let (port, token) = match node_result {
-        Ok(Some(n)) => (n.port, n.token),
-        _ => (3001, "unknown".to_string()),
+    // The script embeds the node token, so it is only served against a valid install token
+    let redeemed = match query.t.as_deref() {
+        Some(t) => {
+            install_tokens::redeem(&state.db, &id.to_string(), t, TokenPurpose::Install).await
+        }
+        None => false,
     };
+    if !redeemed {
+        tracing::warn!(
+            "Install script for node {} requested without a valid install token",
+            id
+        );
+        return (
+            [(header::CACHE_CONTROL, "no-store")],
+            "#!/bin/bash\necho 'Install token is invalid, expired or already used. Copy a fresh command from the panel.' >&2\nexit 1\n".to_string(),
+        );
+    }
+
+    // Fetch node to get configured port and current token
+    let (port, token) = match state.get_node_with_token(&id.to_string()).await {
+        Some(n) => (n.port, n.token),
+        None => (3001, "unknown".to_string()),
+    };
+
+    // The binary is not public; the script carries a short-lived link to it
+    let secret = state.download_secret.read().await.clone();
+    let expires_at = chrono::Utc::now().timestamp() + signed_urls::default_ttl();
+    let binary_path = format!(
+        "/downloads/yunexal-node?t={}",
+        signed_urls::sign(&secret, DownloadKind::Artifact, "yunexal-node", expires_at)
+    );
 
-    format!(r#"#!/bin/bash
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        format!(
+            r#"#!/bin/bash
 # Yunexal Node Installer
 
 cat << "EOF"
 {}
 EOF
 
+PANEL_URL='{base_url}'
+NODE_ID='{id}'
+NODE_TOKEN='{token}'
+
+# Asks the panel whether this node ID and token still pair up
+verify_node() {{
+    local status
+    status=$(curl -sS -o /dev/null -w '%{{http_code}}' --max-time 15 \
+        -H "Authorization: Bearer $NODE_TOKEN" "$PANEL_URL/api/nodes/$NODE_ID/verify")
+    case "$status" in
+        204) return 0 ;;
+        401) echo "Token rejected by the panel. Has the node been recreated, or its token rotated, in the panel? Copy a fresh install command from the node page." >&2 ;;
+        000) echo "Could not reach the panel at $PANEL_URL from this machine. Check the panel URL, DNS and firewalls." >&2 ;;
+        *) echo "The panel at $PANEL_URL answered the token check with HTTP $status." >&2 ;;
+    esac
+    return 1
+}}
+
+# 0. Check the pairing before changing anything on this machine
+echo "Checking the node token with the panel..."
+if ! verify_node; then
+    echo "Nothing was installed." >&2
+    exit 1
+fi
+
 echo "Installing Yunexal Node..."
 
 # 1. Install Docker if not present
@@ -74,13 +138,13 @@ cd /opt/yunexal-node
 cat <<EOF > config.yml
 token: "{}"
 node_id: "{}"
-panel_url: "http://{}"
+panel_url: "{}"
 port: {}
 EOF
 
 # 4. Download and run the node agent
 echo "Downloading Node Agent..."
-curl -L -o yunexal-node http://{}/downloads/yunexal-node
+curl -fL -o yunexal-node '{}{}'
 chmod +x yunexal-node
 
 # 5. Create systemd service
@@ -106,17 +170,72 @@ systemctl daemon-reload
 systemctl enable yunexal-node
 systemctl restart yunexal-node
 
+# 7. Confirm the panel is reachable from here with the installed token
+if ! verify_node; then
+    echo "The agent is installed, but the panel check failed; it won't be able to send heartbeats until this is fixed." >&2
+    exit 1
+fi
+
 echo "Node installed and started!"
-"#, LOGO, token, id, host, port, host)
+"#,
+            LOGO,
+            token,
+            id,
+            base_url,
+            port,
+            base_url,
+            binary_path,
+            base_url = base_url,
+            id = id,
+            token = token
+        ),
+    )
 }
 
+/// Served only to a decommissioning node's uninstall command. The script removes the agent,
+/// then hands its token back to `/nodes/{id}/decommission/complete`; the token stays valid
+/// until then, so a script that fails halfway can be run again.
 pub async fn uninstall_script_handler(
-    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<InstallScriptQuery>,
     headers: HeaderMap,
-) -> String {
-    let host = headers.get("host").and_then(|h| h.to_str().ok()).unwrap_or("127.0.0.1:3000");
+) -> impl IntoResponse {
+    let base_url = state.base_url(&headers);
+
+    let token = match query.t {
+        Some(t)
+            if install_tokens::is_valid(
+                &state.db,
+                &id.to_string(),
+                &t,
+                TokenPurpose::Uninstall,
+            )
+            .await =>
+        {
+            t
+        }
+        _ => {
+            tracing::warn!(
+                "Uninstall script for node {} requested without a valid uninstall token",
+                id
+            );
+            return (
+                [(header::CACHE_CONTROL, "no-store")],
+                "#!/bin/bash\necho 'Uninstall token is invalid, expired or already used. Copy a fresh command from the node page in the panel.' >&2\nexit 1\n".to_string(),
+            );
+        }
+    };
 
-    format!(r#"#!/bin/bash
+    let _ = sqlx::query("UPDATE nodes SET decommission_script_fetched_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await;
+
+    (
+        [(header::CACHE_CONTROL, "no-store")],
+        format!(
+            r#"#!/bin/bash
 cat << "EOF"
 {}
 EOF
@@ -138,10 +257,83 @@ systemctl daemon-reload
 # Remove application directory
 rm -rf /opt/yunexal-node
 
-# Notify panel to delete node from database
+# Hand the uninstall token back so the panel removes the node
 echo "Notifying panel to remove node..."
-curl -X DELETE http://{}/nodes/{}
+if ! curl -fsS -X POST --data-urlencode 't={}' '{}/nodes/{}/decommission/complete'; then
+    echo "The panel did not confirm the removal; the node is still listed there. Run a fresh uninstall command from the node page to retry." >&2
+    exit 1
+fi
 
+echo
 echo "Node uninstalled successfully."
-"#, LOGO, host, id)
+"#,
+            LOGO, token, base_url, id
+        ),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct CompleteDecommissionForm {
+    t: String,
+}
+
+/// Called by the uninstall script once the agent is gone. Uses up the uninstall token and
+/// removes the node; expired or reused tokens, and nodes no longer being decommissioned,
+/// are refused.
+pub async fn complete_decommission_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Form(form): Form<CompleteDecommissionForm>,
+) -> impl IntoResponse {
+    let result = async {
+        let mut tx = state.db.begin().await?;
+        if !install_tokens::redeem(&mut *tx, &id.to_string(), &form.t, TokenPurpose::Uninstall)
+            .await
+        {
+            return Ok((
+                StatusCode::FORBIDDEN,
+                "Uninstall token is invalid, expired or already used",
+            ));
+        }
+        // Creates refuse decommissioning nodes, but a server that slipped in must keep its node
+        let deleted = sqlx::query(
+            "DELETE FROM nodes WHERE id = $1 AND decommission_started_at IS NOT NULL
+             AND NOT EXISTS (SELECT 1 FROM servers WHERE node_id = $1)",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if deleted.rows_affected() == 0 {
+            return Ok((
+                StatusCode::CONFLICT,
+                "Node is not being decommissioned or still has servers",
+            ));
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>((StatusCode::OK, "Node removed from the panel"))
+    }
+    .await;
+
+    match result {
+        Ok((StatusCode::OK, message)) => {
+            tracing::info!("Node {} decommissioned", id);
+            state.invalidate_nodes_cache().await;
+            (StatusCode::OK, message)
+        }
+        Ok((status, message)) => {
+            tracing::warn!(
+                "Refused to complete decommissioning node {}: {}",
+                id,
+                message
+            );
+            (status, message)
+        }
+        Err(e) => {
+            tracing::error!("Failed to complete decommissioning node {}: {}", id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to remove the node",
+            )
+        }
+    }
 }
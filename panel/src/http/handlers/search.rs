@@ -0,0 +1,150 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::CookieJar;
+use serde::{Deserialize, Serialize};
+
+use crate::http::handlers::auth::{AuthMode, session_user};
+use crate::state::AppState;
+
+/// Results per category, so a broad query stays one short round trip.
+const MAX_PER_KIND: i64 = 8;
+/// Longer queries are cut; nobody types a 100 character server name into a palette.
+const MAX_QUERY_LEN: usize = 64;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct SearchResult {
+    /// `server`, `node` or `user`
+    pub kind: String,
+    pub id: String,
+    pub title: String,
+    /// Secondary line: a server's description, a node's IP, a user's email
+    pub detail: String,
+    pub url: String,
+}
+
+/// What the caller may see. Admins see everything; auditors every server and node, since
+/// they can read every page, but not users; other users only their own servers. Without a session (`AUTH_MODE=off`) the panel pages are readable anyway, so servers and
+/// nodes are searchable but users are not.
+struct Scope {
+    owner: Option<String>,
+    nodes: bool,
+    users: bool,
+}
+
+/// Escapes `%`, `_` and `\` so the query matches literally inside a LIKE pattern.
+fn like_literal(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `GET /api/search?q=`: servers (name or id prefix), nodes (name or IP) and users
+/// (admins only), grouped by kind, each with the URL to jump to.
+pub async fn search_handler(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let scope = match session_user(&state, &jar).await {
+        Some(user) if user.is_admin() => Scope {
+            owner: None,
+            nodes: true,
+            users: true,
+        },
+        Some(user) if user.is_auditor() => Scope {
+            owner: None,
+            nodes: true,
+            users: false,
+        },
+        Some(user) => Scope {
+            owner: Some(user.id.to_string()),
+            nodes: false,
+            users: false,
+        },
+        None if state.auth_mode != AuthMode::Off => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": "unauthorized" })),
+            )
+                .into_response();
+        }
+        None => Scope {
+            owner: None,
+            nodes: true,
+            users: false,
+        },
+    };
+
+    let q: String = query
+        .q
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_QUERY_LEN)
+        .collect();
+    if q.is_empty() {
+        return Json(Vec::<SearchResult>::new()).into_response();
+    }
+    let literal = like_literal(&q);
+    let contains = format!("%{}%", literal);
+    let prefix = format!("{}%", literal);
+
+    // One round trip; each branch is capped on its own and prefix matches sort first
+    let results = sqlx::query_as::<_, SearchResult>(
+        r#"
+        (SELECT 'server' AS kind, id::text AS id, name AS title, COALESCE(description, '') AS detail,
+                '/servers/' || id::text || '/manage' AS url
+         FROM servers
+         WHERE (lower(name) LIKE $1 OR id::text LIKE $2) AND ($3::text IS NULL OR owner_id = $3)
+         ORDER BY lower(name) LIKE $2 DESC, lower(name)
+         LIMIT $6)
+        UNION ALL
+        (SELECT 'node', id::text, name, ip, '/nodes/' || id::text || '/edit'
+         FROM nodes
+         WHERE $4 AND (lower(name) LIKE $1 OR ip LIKE $2)
+         ORDER BY lower(name) LIKE $2 DESC, lower(name)
+         LIMIT $6)
+        UNION ALL
+        (SELECT 'user', id::text, username, email, '/servers?owner=' || id::text
+         FROM users
+         WHERE $5 AND (lower(username) LIKE $1 OR lower(email) LIKE $1)
+         ORDER BY lower(username) LIKE $2 DESC, lower(username)
+         LIMIT $6)
+        "#,
+    )
+    .bind(&contains)
+    .bind(&prefix)
+    .bind(&scope.owner)
+    .bind(scope.nodes)
+    .bind(scope.users)
+    .bind(MAX_PER_KIND)
+    .fetch_all(&state.db)
+    .await;
+
+    match results {
+        Ok(results) => Json(results).into_response(),
+        Err(e) => {
+            tracing::error!("Search for '{}' failed: {}", q, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "search_failed" })),
+            )
+                .into_response()
+        }
+    }
+}
@@ -1,16 +1,32 @@
 use crate::http::handlers::HtmlTemplate;
+use crate::http::handlers::auth::{AuthMode, Viewer, session_user};
 use crate::models::{
-    Allocation, CreateServerRequest, DeleteServerRequest, Image, Node, Runtime, Server,
-    UpdateServerRequest,
+    Allocation, BANDWIDTH_ACTIONS, CreateServerRequest, DeleteServerRequest, Image,
+    InstallTestEvent, Location, Node, PERMISSION_SERVER_COMMAND, Runtime, Server, ServerEvent,
+    ServerTemplate, UpdateLimitsRequest, UpdateServerRequest, Variable,
+    enforce_image_docker_images, parse_console_macros, parse_tags, pull_policy_or_default,
+    stop_timeout_or_default,
+};
+use crate::services::allocations::{self, AutoAllocationNode, mint_allocation};
+use crate::services::provisioning::{self, CreateContainerJob, PortConflict, RecreateContainerJob};
+use crate::services::{
+    bandwidth, images, jobs, locations, node_api, placement, server_events, server_presets,
+    server_secrets, uptime,
 };
 use crate::state::AppState;
 use askama::Template;
 use axum::{
-    extract::{Form, Query, State},
-    response::{IntoResponse, Redirect},
+    extract::{Form, Query, RawForm, State},
+    response::{
+        IntoResponse, Redirect,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use axum_extra::extract::cookie::CookieJar;
+use futures_util::StreamExt;
 use serde::Deserialize;
 use std::collections::HashMap;
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
 
 #[derive(Template)]
@@ -24,6 +40,15 @@ struct ServersTemplate {
     active_tab: String,
     has_nodes: bool,
     servers: Vec<Server>,
+    active_tag: Option<String>,
+    queued: usize,
+    duplicate_name: Option<String>,
+    addresses: HashMap<String, String>,
+    /// This month's (rx, tx) bytes per server id
+    bandwidth: HashMap<String, (i64, i64)>,
+    /// Rolling uptime per server id
+    uptime: HashMap<String, uptime::Uptime>,
+    can_modify: bool,
 }
 
 #[derive(Template)]
@@ -36,10 +61,19 @@ struct CreateServerTemplate {
     execution_time: f64,
     active_tab: String,
     nodes: Vec<Node>,
+    locations: Vec<Location>,
     runtimes: Vec<Runtime>,
     images_json: String,
     allocations_json: String,
+    free_counts_json: String,
     error: Option<String>,
+    templates: Vec<ServerTemplate>,
+    /// `ServerPreset` JSON to pre-fill the form with, or `null`
+    prefill_json: String,
+    prefill_source: Option<String>,
+    /// Identifies this form's allocation reservation (see services::reservations)
+    reservation_token: String,
+    can_modify: bool,
 }
 
 #[derive(Template)]
@@ -52,6 +86,29 @@ struct ManageServerTemplate {
     execution_time: f64,
     active_tab: String,
     server: Server,
+    queued_behind: usize,
+    address: Option<String>,
+    events: Vec<ServerEvent>,
+    error: Option<String>,
+    install_job: Option<Uuid>,
+    recreate_job: Option<Uuid>,
+    /// Absolute `/public/servers/{id}/status` URL for the embed snippet
+    status_url: String,
+    /// Bytes received and sent this month
+    bandwidth_rx: i64,
+    bandwidth_tx: i64,
+    /// Rolling uptime over 24h, 7d and 30d
+    uptime: uptime::Uptime,
+    /// The image's console buttons, as JSON for the page script
+    console_macros_json: String,
+    has_console_macros: bool,
+    can_send_commands: bool,
+    /// The owner has another server with this name (set after a rename)
+    duplicate_name: bool,
+    /// Containers the last failed create found on this server's ports
+    port_conflicts: Vec<PortConflict>,
+    has_leftovers: bool,
+    can_modify: bool,
 }
 
 #[derive(Template)]
@@ -64,14 +121,60 @@ struct EditServerTemplate {
     execution_time: f64,
     active_tab: String,
     server: Server,
+    image_startup_command: String,
+    allow_startup_override: bool,
+    error: Option<String>,
+    secrets: Vec<SecretField>,
+    allocations: Vec<Allocation>,
+    free_allocations: Vec<Allocation>,
+    primary_allocation_id: String,
+    can_modify: bool,
+}
+
+/// Secret variable on the edit form. The value itself never reaches a template.
+struct SecretField {
+    name: String,
+    env_variable: String,
+    is_set: bool,
 }
 
 #[derive(Deserialize)]
 pub struct ServerCreateQuery {
     pub error: Option<String>,
+    /// Set after a rename to a name the owner already uses
+    #[serde(default)]
+    pub duplicate_name: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CreateServerPageQuery {
+    pub error: Option<String>,
+    /// Pre-fill from an existing server's config
+    pub clone: Option<Uuid>,
+    /// Pre-fill from a saved server template
+    pub template: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct SaveTemplateRequest {
+    pub name: String,
 }
 
-pub async fn servers_page_handler(State(state): State<AppState>) -> impl IntoResponse {
+#[derive(Deserialize)]
+pub struct ServersQuery {
+    pub tag: Option<String>,
+    /// Only servers owned by this user id (linked from search results)
+    pub owner: Option<String>,
+    pub queued: Option<usize>,
+    /// Name of a just-created server its owner already had another server called
+    pub duplicate_name: Option<String>,
+}
+
+pub async fn servers_page_handler(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<ServersQuery>,
+) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
@@ -82,15 +185,39 @@ pub async fn servers_page_handler(State(state): State<AppState>) -> impl IntoRes
     let nodes = state.get_nodes().await;
     let has_nodes = !nodes.is_empty();
 
-    let servers = sqlx::query_as::<_, Server>("SELECT * FROM servers ORDER BY created_at DESC")
-        .fetch_all(&state.db)
-        .await
-        .unwrap_or_default();
+    let active_tag = query
+        .tag
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty());
+
+    let servers = sqlx::query_as::<_, Server>(
+        "SELECT * FROM servers WHERE ($1::text IS NULL OR $1 = ANY(tags)) AND ($2::text IS NULL OR owner_id = $2) ORDER BY created_at DESC",
+    )
+    .bind(&active_tag)
+    .bind(query.owner.as_deref().filter(|o| !o.is_empty()))
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_default();
+
+    // Primary "ip:port" per server; portless servers are simply absent
+    let addresses: HashMap<String, String> = sqlx::query_as::<_, (String, String, i32)>(
+        "SELECT s.id::text, a.ip, a.port FROM servers s JOIN allocations a ON a.id = s.allocation_id",
+    )
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(id, ip, port)| (id, format!("{}:{}", ip, port)))
+    .collect();
+
+    let bandwidth = bandwidth::current_usage(&state.db).await;
+    let uptime = uptime::all(&state.db).await;
 
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(ServersTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -99,12 +226,19 @@ pub async fn servers_page_handler(State(state): State<AppState>) -> impl IntoRes
         active_tab: "servers".to_string(),
         has_nodes,
         servers,
+        active_tag,
+        queued: query.queued.unwrap_or(0),
+        duplicate_name: query.duplicate_name,
+        addresses,
+        bandwidth,
+        uptime,
     })
 }
 
 pub async fn create_server_page_handler(
     State(state): State<AppState>,
-    Query(query): Query<ServerCreateQuery>,
+    viewer: Viewer,
+    Query(query): Query<CreateServerPageQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_name = state.panel_name.read().await.clone();
@@ -114,22 +248,33 @@ pub async fn create_server_page_handler(
 
     // Fetch Data
     let nodes = state.get_nodes().await;
+    let locations = locations::list(&state.db).await;
 
     let runtimes = sqlx::query_as::<_, Runtime>("SELECT id::text, name, description, color, sort_order FROM runtimes ORDER BY sort_order ASC")
         .fetch_all(&state.db)
         .await
         .unwrap_or_default();
 
-    let images = sqlx::query_as::<_, Image>("SELECT id::text, runtime_id::text, name, docker_images, description, stop_command, startup_command, log_config, config_files, start_config, requires_port, install_script::text, install_container::text, install_entrypoint::text, variables::text FROM images")
-        .fetch_all(&state.db)
-        .await
-        .unwrap_or_default();
+    let images =
+        sqlx::query_as::<_, Image>(&format!("SELECT {} FROM images", images::IMAGE_COLUMNS))
+            .fetch_all(&state.db)
+            .await
+            .unwrap_or_default();
 
-    let allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text FROM allocations WHERE server_id IS NULL")
+    let mut allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text, notes, reserved FROM allocations WHERE server_id IS NULL")
         .fetch_all(&state.db)
         .await
         .unwrap_or_default();
 
+    // Ports picked on another open create form aren't offered until that form lets go
+    let reservation_token = Uuid::new_v4().to_string();
+    let ids: Vec<String> = allocations.iter().map(|a| a.id.clone()).collect();
+    let held = state
+        .allocation_reservations
+        .held_by_others(&ids, None)
+        .await;
+    allocations.retain(|a| !held.contains(&a.id));
+
     // Prepare JSON for frontend
     // Group images by runtime_id
     let mut images_map: HashMap<String, Vec<Image>> = HashMap::new();
@@ -141,6 +286,9 @@ pub async fn create_server_page_handler(
     }
     let images_json = serde_json::to_string(&images_map).unwrap_or("{}".to_string());
 
+    let free_counts = free_allocation_counts(&nodes, &allocations);
+    let free_counts_json = serde_json::to_string(&free_counts).unwrap_or("{}".to_string());
+
     // Group allocations by node_id
     let mut allocations_map: HashMap<String, Vec<Allocation>> = HashMap::new();
     for alloc in allocations {
@@ -151,10 +299,35 @@ pub async fn create_server_page_handler(
     }
     let allocations_json = serde_json::to_string(&allocations_map).unwrap_or("{}".to_string());
 
+    let templates = server_presets::list_templates(&state.db).await;
+    let (prefill, prefill_source) = if let Some(id) = query.clone {
+        let name: Option<String> = sqlx::query_scalar("SELECT name FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+        (
+            server_presets::from_server(&state.db, id).await,
+            name.map(|n| format!("Cloning the configuration of \"{}\"", n)),
+        )
+    } else if let Some(id) = query.template {
+        match server_presets::load_template(&state.db, id).await {
+            Some((name, preset)) => (Some(preset), Some(format!("Using template \"{}\"", name))),
+            None => (None, None),
+        }
+    } else {
+        (None, None)
+    };
+    // Embedded in a <script> block, so keep values from closing it
+    let prefill_json = serde_json::to_string(&prefill)
+        .unwrap_or_else(|_| "null".to_string())
+        .replace("</", "<\\/");
+
     let elapsed = start_time.elapsed();
     let execution_time = elapsed.as_secs_f64() * 1000.0;
 
     HtmlTemplate(CreateServerTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -162,33 +335,164 @@ pub async fn create_server_page_handler(
         execution_time,
         active_tab: "servers".to_string(),
         nodes,
+        locations,
         runtimes,
         images_json,
         allocations_json,
+        free_counts_json,
         error: query.error,
+        templates,
+        prefill_json,
+        prefill_source,
+        reservation_token,
     })
 }
 
+#[derive(Deserialize)]
+pub struct ReserveAllocationForm {
+    pub reservation_token: String,
+    /// Empty when the form switched back to auto-assign
+    pub allocation_id: Option<String>,
+}
+
+/// Holds the allocation picked on the create form for `ALLOCATION_RESERVATION_TTL` seconds.
+/// 409 when another form already holds it.
+pub async fn reserve_allocation_handler(
+    State(state): State<AppState>,
+    Form(payload): Form<ReserveAllocationForm>,
+) -> axum::http::StatusCode {
+    let allocation_id = payload.allocation_id.as_deref().filter(|s| !s.is_empty());
+    if state
+        .allocation_reservations
+        .select(&payload.reservation_token, allocation_id)
+        .await
+    {
+        axum::http::StatusCode::NO_CONTENT
+    } else {
+        axum::http::StatusCode::CONFLICT
+    }
+}
+
+/// Saves a server's config (no allocation, volume or secrets) as a named create-form template.
+pub async fn save_server_template_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Form(payload): Form<SaveTemplateRequest>,
+) -> Redirect {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Redirect::to(&format!("/servers/{}/manage", id));
+    }
+    let Some(preset) = server_presets::from_server(&state.db, id).await else {
+        return Redirect::to("/servers");
+    };
+
+    match server_presets::save_template(&state.db, name, &preset).await {
+        Ok(template_id) => Redirect::to(&format!("/servers/new?template={}", template_id)),
+        Err(e) => {
+            tracing::error!("Failed to save server {} as template: {}", id, e);
+            Redirect::to(&format!("/servers/{}/manage?error=template_failed", id))
+        }
+    }
+}
+
+pub async fn delete_server_template_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> &'static str {
+    server_presets::delete_template(&state.db, id).await;
+    // Empty body: htmx swaps the list entry away
+    ""
+}
+
+/// Free allocations per node, with an explicit 0 for nodes that have none
+fn free_allocation_counts(nodes: &[Node], free: &[Allocation]) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = nodes.iter().map(|n| (n.id.clone(), 0)).collect();
+    for alloc in free {
+        *counts.entry(alloc.node_id.clone()).or_default() += 1;
+    }
+    counts
+}
+
 pub async fn create_server_handler(
     State(state): State<AppState>,
-    Form(payload): Form<CreateServerRequest>,
+    RawForm(raw): RawForm,
 ) -> Redirect {
+    // Parsed by hand: the typed fields plus the image's dynamic `environment[...]` inputs
+    let Ok(payload) = serde_urlencoded::from_bytes::<CreateServerRequest>(&raw) else {
+        return Redirect::to("/servers/new?error=invalid_form");
+    };
+    let redirect = create_server(&state, &raw, &payload).await;
+
+    // Submitted either way: a retry after an error renders a fresh form with a new token
+    if let Some(token) = &payload.reservation_token {
+        state.allocation_reservations.release(token).await;
+    }
+    redirect
+}
+
+/// The node to mint a create's port on when no allocation is free: the first in placement
+/// order (`node`, if given, or any in `location`) with a free port in its auto-allocation
+/// range. Decommissioning nodes are left out, as are offline ones unless `allow_offline`.
+async fn auto_allocation_node(
+    state: &AppState,
+    node: Option<Uuid>,
+    location: Option<Uuid>,
+    ram_needed: i64,
+    allow_offline: bool,
+) -> Result<Option<AutoAllocationNode>, sqlx::Error> {
+    let mut ranged: HashMap<String, AutoAllocationNode> = sqlx::query_as::<_, AutoAllocationNode>(
+        "SELECT id::text, ip, port, sftp_port, auto_allocation_range FROM nodes
+             WHERE COALESCE(auto_allocation_range, '') <> '' AND decommission_started_at IS NULL
+             AND ($1::uuid IS NULL OR id = $1::uuid)",
+    )
+    .bind(node)
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(|n| (n.id.clone(), n))
+    .collect();
+
+    let candidates: Vec<_> = placement::candidates(state, location)
+        .await
+        .into_iter()
+        .filter(|c| (c.online || allow_offline) && ranged.contains_key(&c.id))
+        .collect();
+    for id in placement::rank_nodes(&candidates, ram_needed) {
+        let Some(node) = ranged.remove(&id) else {
+            continue;
+        };
+        if allocations::has_free_port(&state.db, &node).await? {
+            return Ok(Some(node));
+        }
+        tracing::debug!("Auto-allocation range of node {} is used up", node.id);
+    }
+    Ok(None)
+}
+
+async fn create_server(state: &AppState, raw: &[u8], payload: &CreateServerRequest) -> Redirect {
+    let submitted_env = server_secrets::form_environment(raw);
     let server_id = Uuid::new_v4().to_string();
 
     // 0. Fetch Image to check requires_port
-    let image = match sqlx::query_as::<_, Image>(
-        "SELECT id::text, runtime_id::text, name, docker_images, description, stop_command, startup_command, log_config, config_files, start_config, requires_port, install_script::text, install_container::text, install_entrypoint::text, variables::text FROM images WHERE id = $1::uuid"
-    )
+    let image = match sqlx::query_as::<_, Image>(&format!(
+        "SELECT {} FROM images WHERE id = $1::uuid",
+        images::IMAGE_COLUMNS
+    ))
     .bind(&payload.image_id)
     .fetch_optional(&state.db)
-    .await {
+    .await
+    {
         Ok(Some(img)) => img,
         Ok(None) => return Redirect::to("/servers/new?error=invalid_image"),
         Err(_) => return Redirect::to("/servers/new?error=db_error"),
     };
 
-    let allocation_id: Option<String>;
+    let mut allocation_id: Option<String>;
     let node_id_resolved: String;
+    let mut auto_node: Option<AutoAllocationNode> = None;
+    // Auto-selection only looks inside this location; explicit picks are checked against it below
+    let location = locations::parse_filter(payload.location_id.as_deref());
 
     // Check if user specifically selected an allocation (Manual Override)
     let user_selected_alloc = payload.default_allocation.clone().filter(|s| !s.is_empty());
@@ -206,120 +510,231 @@ pub async fn create_server_handler(
         };
 
         if !alloc_valid {
-            eprintln!("Invalid or occupied allocation selected");
+            tracing::warn!("Allocation {} is unknown or already in use", alloc_id);
+            return Redirect::to("/servers/new?error=invalid_allocation");
+        }
+
+        // Free, but picked on another create form that is still open
+        let held = state
+            .allocation_reservations
+            .held_by_others(
+                std::slice::from_ref(&alloc_id),
+                payload.reservation_token.as_deref(),
+            )
+            .await;
+        if !held.is_empty() {
+            tracing::warn!("Allocation {} is reserved by another create form", alloc_id);
             return Redirect::to("/servers/new?error=invalid_allocation");
         }
-        
+
         allocation_id = Some(alloc_id);
         node_id_resolved = nid;
-
     } else if image.requires_port {
         // CASE B: Image REQUIRES a port, and user selected "Auto". We MUST find one.
-        
-        // If specific node requested:
-        let query = if let Some(node_id) = payload.node_id.filter(|s| !s.is_empty()) {
-            format!(
-                "SELECT id::text FROM allocations WHERE node_id = '{}' AND server_id IS NULL LIMIT 1",
-                node_id
-            )
-        } else {
-            // Any node
-            "SELECT id::text FROM allocations WHERE server_id IS NULL LIMIT 1".to_string()
-        };
 
-        let auto_alloc_id = match sqlx::query_scalar::<_, String>(&query)
-            .fetch_optional(&state.db)
-            .await
+        // On the requested node, or any node, skipping ports held by other open create forms
+        let node_filter = payload.node_id.clone().filter(|s| !s.is_empty());
+        let held = state
+            .allocation_reservations
+            .held_ids(payload.reservation_token.as_deref())
+            .await;
+        let auto_alloc_id = match sqlx::query_scalar::<_, String>(
+            "SELECT id::text FROM allocations WHERE ($1::uuid IS NULL OR node_id = $1::uuid)
+             AND ($2::uuid IS NULL OR node_id IN (SELECT id FROM nodes WHERE location_id = $2))
+             AND server_id IS NULL AND NOT reserved AND NOT (id::text = ANY($3)) ORDER BY port LIMIT 1",
+        )
+        .bind(node_filter.as_ref().and_then(|s| Uuid::parse_str(s).ok()))
+        .bind(location)
+        .bind(&held)
+        .fetch_optional(&state.db)
+        .await
         {
-            Ok(Some(id)) => id,
-            Ok(None) => {
-                eprintln!("No free allocations available");
-                return Redirect::to("/servers/new?error=no_allocations");
-            }
+            Ok(id) => id,
             Err(e) => {
-                eprintln!("DB Error finding allocation: {}", e);
+                tracing::error!(
+                    "Failed to find a free allocation (node {:?}, location {:?}): {}",
+                    node_filter,
+                    location,
+                    e
+                );
                 return Redirect::to("/servers/new?error=db_error");
             }
         };
 
-        // Get Node ID from this auto-assigned allocation
-        let alloc_res = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text FROM allocations WHERE id = $1::uuid")
-            .bind(&auto_alloc_id)
-            .fetch_optional(&state.db)
+        if let Some(auto_alloc_id) = auto_alloc_id {
+            // Get Node ID from this auto-assigned allocation
+            let alloc_res = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text FROM allocations WHERE id = $1::uuid")
+                .bind(&auto_alloc_id)
+                .fetch_optional(&state.db)
+                .await;
+
+            let (nid, alloc_valid) = match alloc_res {
+                Ok(Some(a)) => (a.node_id, a.server_id.is_none()),
+                _ => (String::new(), false),
+            };
+
+            // This theoretically shouldn't happen if the previous query found it, but concurrency safe check
+            if !alloc_valid {
+                return Redirect::to("/servers/new?error=allocation_race_condition");
+            }
+
+            allocation_id = Some(auto_alloc_id);
+            node_id_resolved = nid;
+        } else {
+            // No free port: fall back to a node that can mint one from its auto-allocation range
+            let candidate = auto_allocation_node(
+                state,
+                node_filter.as_ref().and_then(|s| Uuid::parse_str(s).ok()),
+                location,
+                payload.ram_limit.unwrap_or(0) as i64,
+                payload.allow_offline.is_some(),
+            )
             .await;
-            
-        let (nid, alloc_valid) = match alloc_res {
-            Ok(Some(a)) => (a.node_id, a.server_id.is_none()),
-            _ => (String::new(), false),
-        };
-        
-        // This theoretically shouldn't happen if the previous query found it, but concurrency safe check
-        if !alloc_valid {
-             return Redirect::to("/servers/new?error=allocation_race_condition");
-        }
 
-        allocation_id = Some(auto_alloc_id);
-        node_id_resolved = nid;
-        
+            match candidate {
+                Ok(Some(n)) => {
+                    node_id_resolved = n.id.clone();
+                    auto_node = Some(n);
+                }
+                Ok(None) => {
+                    tracing::warn!("No free allocations and no node range with a free port");
+                    return Redirect::to("/servers/new?error=no_allocations");
+                }
+                Err(e) => {
+                    tracing::error!("Failed to find a node to auto-allocate on: {}", e);
+                    return Redirect::to("/servers/new?error=db_error");
+                }
+            }
+            allocation_id = None;
+        }
     } else {
-        // CASE C: Image DOES NOT require a port, and user selected "Auto/None". 
+        // CASE C: Image DOES NOT require a port, and user selected "Auto/None".
         // We do NOT assign a port.
         allocation_id = None;
-        
+
         // But we DO need to resolve a Node ID.
-        if let Some(nid) = payload.node_id.filter(|s| !s.is_empty()) {
-             node_id_resolved = nid;
+        if let Some(nid) = payload.node_id.clone().filter(|s| !s.is_empty()) {
+            node_id_resolved = nid;
         } else {
-            // Auto-select node (simplistic: pick first available node)
-            let q = "SELECT id::text FROM nodes LIMIT 1";
-            match sqlx::query_scalar::<_, String>(q).fetch_optional(&state.db).await {
-                Ok(Some(nid)) => node_id_resolved = nid,
-                Ok(_) => return Redirect::to("/servers/new?error=no_nodes_available"),
-                Err(_) => return Redirect::to("/servers/new?error=db_error"),
+            // Prefer an online node with RAM to spare; deterministic when nothing is online
+            let candidates = placement::candidates(state, location).await;
+            match placement::pick_node(&candidates, payload.ram_limit.unwrap_or(0) as i64) {
+                Some(nid) => node_id_resolved = nid,
+                None => return Redirect::to("/servers/new?error=no_nodes_available"),
             }
         }
     }
 
+    // A node or port picked by hand must still be in the chosen location
+    if let Some(location) = location {
+        let inside: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM nodes WHERE id = $1::uuid AND location_id = $2)",
+        )
+        .bind(&node_id_resolved)
+        .bind(location)
+        .fetch_one(&state.db)
+        .await
+        .unwrap_or(false);
+        if !inside {
+            tracing::warn!(
+                "Refused create on node {} outside location {}",
+                node_id_resolved,
+                location
+            );
+            return Redirect::to("/servers/new?error=node_outside_location");
+        }
+    }
+
+    // Its uninstall script would remove the node from under the new server
+    let decommissioning: bool = sqlx::query_scalar(
+        "SELECT decommission_started_at IS NOT NULL FROM nodes WHERE id = $1::uuid",
+    )
+    .bind(&node_id_resolved)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None)
+    .unwrap_or(false);
+    if decommissioning {
+        return Redirect::to("/servers/new?error=node_decommissioning");
+    }
+
+    // An offline node would leave the server installing until the job gives up
+    if payload.allow_offline.is_none() && state.node_stats(&node_id_resolved).await.is_none() {
+        return Redirect::to("/servers/new?error=node_offline");
+    }
+
     // 2. Prepare Data
-    let docker_image = if let Some(custom) = payload.custom_docker_image.filter(|s| !s.is_empty()) {
-        custom
-    } else {
-        payload.docker_image.unwrap_or_default()
+    if let Err(code) =
+        check_cpu_limit(state, &node_id_resolved, payload.cpu_limit.unwrap_or(0)).await
+    {
+        return Redirect::to(&format!("/servers/new?error={}", code));
+    }
+    if let Err(message) = check_image_minimums(
+        &image,
+        payload.ram_limit.unwrap_or(0),
+        payload.disk_limit.unwrap_or(0),
+        payload.cpu_limit.unwrap_or(0),
+        payload.oom_killer.is_some(),
+    ) {
+        return Redirect::to(&format!("/servers/new?{}", error_query(&message)));
+    }
+    let config = match validated_config(&image, payload, &submitted_env) {
+        Ok(c) => c,
+        Err(message) => return Redirect::to(&format!("/servers/new?{}", error_query(&message))),
     };
 
-    let start_status = if payload.start_on_install.is_some() {
-        "installing"
-    } else {
-        "installing"
-    }; // Always installing first? Or 'created'? Logic usually is installing.
+    let start_status = "installing";
 
     // 3. Transactions
     let mut tx = match state.db.begin().await {
         Ok(t) => t,
         Err(e) => {
-            eprintln!("Failed to start transaction: {}", e);
+            tracing::error!("Failed to start the create transaction: {}", e);
             return Redirect::to("/servers/new?error=db_error");
         }
     };
 
+    // Mint the allocation in the same transaction so a failed create leaves no orphan port
+    if let Some(node) = &auto_node {
+        match mint_allocation(&mut tx, node).await {
+            Ok(Some(id)) => allocation_id = Some(id),
+            Ok(None) => {
+                tracing::warn!("Auto-allocation range exhausted on node {}", node.id);
+                let _ = tx.rollback().await;
+                return Redirect::to("/servers/new?error=no_allocations");
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to auto-create an allocation on node {}: {}",
+                    node.id,
+                    e
+                );
+                let _ = tx.rollback().await;
+                return Redirect::to("/servers/new?error=alloc_failed");
+            }
+        }
+    }
+
     // Create Server
     let q = sqlx::query(
         r#"
         INSERT INTO servers (
             id, name, description, owner_id, node_id, allocation_id, image_id,
             cpu_limit, ram_limit, disk_limit, swap_limit, backup_limit,
-            io_weight, oom_killer, docker_image, startup_command, cpu_pinning, status
+            io_weight, oom_killer, docker_image, startup_command, cpu_pinning, status, variables,
+            stop_timeout_seconds, pull_policy, start_on_install
         ) VALUES (
             $1::uuid, $2, $3, $4, $5::uuid, $6, $7::uuid,
             $8, $9, $10, $11, $12,
-            $13, $14, $15, $16, $17, $18
+            $13, $14, $15, $16, $17, $18, $19::jsonb,
+            $20, $21, $22
         )
     "#,
     )
     .bind(&server_id)
     .bind(&payload.name)
     .bind(&payload.description)
-    .bind(payload.owner_id.unwrap_or("1".to_string()))
+    .bind(payload.owner_id.clone().unwrap_or("1".to_string()))
     .bind(&node_id_resolved)
     .bind(allocation_id.as_ref().map(|s| Uuid::parse_str(s).unwrap())) // bind Option<Uuid>
     .bind(&payload.image_id)
@@ -330,19 +745,57 @@ pub async fn create_server_handler(
     .bind(payload.backup_limit.unwrap_or(0))
     .bind(payload.io_weight.unwrap_or(500))
     .bind(payload.oom_killer.is_some())
-    .bind(&docker_image)
-    .bind(payload.startup_command.unwrap_or_default())
+    .bind(&config.docker_image)
+    .bind(&config.startup_command)
     .bind(&payload.cpu_pinning)
     .bind(start_status)
+    .bind(serde_json::to_string(&config.values).unwrap_or_else(|_| "{}".to_string()))
+    .bind(image.stop_timeout_seconds)
+    .bind(pull_policy_or_default(Some(&image.pull_policy)))
+    .bind(payload.start_on_install.is_some())
     .execute(&mut *tx)
     .await;
 
     if let Err(e) = q {
-        eprintln!("Failed to create server: {}", e);
+        tracing::error!(
+            "Failed to create server {} on node {}: {}",
+            server_id,
+            node_id_resolved,
+            e
+        );
         let _ = tx.rollback().await;
         return Redirect::to("/servers/new?error=create_failed");
     }
 
+    // Secret variables are only ever stored encrypted
+    for var in config.variables.iter().filter(|v| v.is_secret) {
+        let Some(value) = submitted_env
+            .get(&var.env_variable)
+            .filter(|v| !v.is_empty())
+        else {
+            continue;
+        };
+        let server_uuid = Uuid::parse_str(&server_id).unwrap_or_default();
+        if let Err(e) = server_secrets::store(
+            &mut *tx,
+            &state.secrets_key,
+            server_uuid,
+            &var.env_variable,
+            value,
+        )
+        .await
+        {
+            tracing::error!(
+                "Failed to store secret {} of server {}: {}",
+                var.env_variable,
+                server_id,
+                e
+            );
+            let _ = tx.rollback().await;
+            return Redirect::to("/servers/new?error=create_failed");
+        }
+    }
+
     if let Some(alloc_id) = allocation_id {
         // Update Allocation
         let q2 = sqlx::query("UPDATE allocations SET server_id = $1::uuid WHERE id = $2::uuid")
@@ -352,7 +805,12 @@ pub async fn create_server_handler(
             .await;
 
         if let Err(e) = q2 {
-            eprintln!("Failed to assign allocation: {}", e);
+            tracing::error!(
+                "Failed to assign allocation {} to server {}: {}",
+                alloc_id,
+                server_id,
+                e
+            );
             let _ = tx.rollback().await;
             return Redirect::to("/servers/new?error=alloc_failed");
         }
@@ -369,7 +827,13 @@ pub async fn create_server_handler(
                     .await;
 
                 if let Err(e) = q3 {
-                    eprintln!("Failed to assign additional ports: {}", e);
+                    tracing::error!(
+                        "Failed to assign additional ports {:?} on node {} to server {}: {}",
+                        ports,
+                        node_id_resolved,
+                        server_id,
+                        e
+                    );
                     let _ = tx.rollback().await;
                     return Redirect::to("/servers/new?error=additional_alloc_failed");
                 }
@@ -381,11 +845,176 @@ pub async fn create_server_handler(
     let _ = &payload.runtime_id;
 
     if let Err(e) = tx.commit().await {
-        eprintln!("Failed to commit transaction: {}", e);
+        tracing::error!("Failed to commit the create of server {}: {}", server_id, e);
         return Redirect::to("/servers/new?error=commit_failed");
     }
 
-    Redirect::to("/servers")
+    // 4. Ask the node to build the container, via the job queue so failures are recorded
+    let queued_behind = state.node_ops.queued_behind(&node_id_resolved);
+    let job = CreateContainerJob {
+        server_id: Uuid::parse_str(&server_id).unwrap_or_default(),
+    };
+    if let Err(e) = jobs::enqueue(state, jobs::CREATE_CONTAINER, &server_id, &job).await {
+        tracing::error!(
+            "Failed to queue container creation for {}: {}",
+            server_id,
+            e
+        );
+        let _ = sqlx::query(
+            "UPDATE servers SET status = 'install_failed', install_error = $1 WHERE id = $2::uuid",
+        )
+        .bind(format!("Could not queue the install: {}", e))
+        .bind(&server_id)
+        .execute(&state.db)
+        .await;
+    }
+
+    let mut notices = Vec::new();
+    if queued_behind > 0 {
+        notices.push(("queued", queued_behind.to_string()));
+    }
+    let owner_id = payload.owner_id.as_deref().unwrap_or("1");
+    if name_in_use(&state.db, owner_id, &payload.name, &server_id).await {
+        notices.push(("duplicate_name", payload.name.clone()));
+    }
+    if notices.is_empty() {
+        return Redirect::to("/servers");
+    }
+    Redirect::to(&format!(
+        "/servers?{}",
+        serde_urlencoded::to_string(&notices).unwrap_or_default()
+    ))
+}
+
+/// Whether the owner has another server with this name, ignoring case. Allowed, since only
+/// the owner is confused by it, but creates and renames warn about it.
+async fn name_in_use(db: &sqlx::PgPool, owner_id: &str, name: &str, except_id: &str) -> bool {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM servers WHERE owner_id = $1 AND lower(name) = lower($2) AND id <> $3::uuid)",
+    )
+    .bind(owner_id)
+    .bind(name)
+    .bind(except_id)
+    .fetch_one(db)
+    .await
+    .unwrap_or(false)
+}
+
+/// Image-dependent parts of a new server, checked against the image. Every way of creating
+/// a server (blank form, clone, template) submits the same form and ends up here.
+struct ServerConfig {
+    docker_image: String,
+    startup_command: String,
+    variables: Vec<Variable>,
+    /// Non-secret variable values, persisted on the server for cloning
+    values: HashMap<String, String>,
+}
+
+/// Builds the server's config from the form, or the `?error=` code or message to redirect with.
+fn validated_config(
+    image: &Image,
+    payload: &CreateServerRequest,
+    submitted_env: &HashMap<String, String>,
+) -> Result<ServerConfig, String> {
+    let docker_image = if let Some(custom) = payload
+        .custom_docker_image
+        .clone()
+        .filter(|s| !s.is_empty())
+    {
+        custom
+    } else {
+        let chosen = payload.docker_image.clone().unwrap_or_default();
+        // A custom image is an explicit admin override; a picked one must come from the image
+        if enforce_image_docker_images() && !image.allowed_docker_images().contains(&chosen) {
+            return Err("docker_image_not_allowed".to_string());
+        }
+        chosen
+    };
+
+    // Images can lock the startup command to their own default
+    let startup_command = if image.allow_startup_override {
+        payload.startup_command.clone().unwrap_or_default()
+    } else {
+        image.startup_command.clone()
+    };
+
+    let variables = serde_json::from_str::<Vec<Variable>>(&image.variables).unwrap_or_default();
+    // Values the form can't change are ignored later anyway; an empty secret keeps the default
+    for var in variables.iter().filter(|v| v.user_editable) {
+        match submitted_env.get(&var.env_variable) {
+            Some(value) if !(var.is_secret && value.is_empty()) => var.check_value(value)?,
+            _ => {}
+        }
+    }
+    let values = server_presets::plain_values(&variables, submitted_env);
+
+    Ok(ServerConfig {
+        docker_image,
+        startup_command,
+        variables,
+        values,
+    })
+}
+
+/// `cpu_limit` is a percentage of one core: 100 = one core, 250 = two and a half, 0 = unlimited.
+/// Checked against the core count in the node's latest heartbeat; the node re-checks on its side.
+async fn check_cpu_limit(
+    state: &AppState,
+    node_id: &str,
+    cpu_limit: i32,
+) -> Result<(), &'static str> {
+    if cpu_limit < 0 {
+        return Err("invalid_cpu_limit");
+    }
+    if let Some(stats) = state.node_stats(node_id).await
+        && stats.cpu_cores > 0
+        && cpu_limit as i64 > stats.cpu_cores as i64 * 100
+    {
+        return Err("cpu_limit_exceeds_cores");
+    }
+    Ok(())
+}
+
+/// Rejects limits below the image's minimums (0 = no minimum). A limit of 0 is unlimited
+/// and always passes. The message names the image and what it needs.
+fn check_image_minimums(
+    image: &Image,
+    ram: i32,
+    disk: i32,
+    cpu: i32,
+    oom_killer: bool,
+) -> Result<(), String> {
+    let below = |limit: i32, min: i32| min > 0 && limit > 0 && limit < min;
+
+    if below(ram, image.min_ram) {
+        let mut message = format!(
+            "{} needs at least {} MB of RAM (got {} MB).",
+            image.name, image.min_ram, ram
+        );
+        if !oom_killer {
+            message.push_str(" With the OOM killer disabled, a server this small can exhaust the node's memory and take other servers down with it.");
+        }
+        return Err(message);
+    }
+    if below(disk, image.min_disk) {
+        return Err(format!(
+            "{} needs at least {} MB of disk (got {} MB).",
+            image.name, image.min_disk, disk
+        ));
+    }
+    if below(cpu, image.min_cpu) {
+        return Err(format!(
+            "{} needs at least {}% CPU (got {}%).",
+            image.name, image.min_cpu, cpu
+        ));
+    }
+    Ok(())
+}
+
+/// `error=<message>` for a redirect, encoded so free text survives the query string.
+fn error_query(message: &str) -> String {
+    serde_urlencoded::to_string([("error", message)])
+        .unwrap_or_else(|_| "error=invalid_limits".to_string())
 }
 
 fn parse_ports(input: &str) -> Vec<i32> {
@@ -396,11 +1025,11 @@ fn parse_ports(input: &str) -> Vec<i32> {
             continue;
         }
         if let Some((start, end)) = part.split_once('-') {
-            if let (Ok(s), Ok(e)) = (start.parse::<i32>(), end.parse::<i32>()) {
-                if s <= e {
-                    for p in s..=e {
-                        ports.push(p);
-                    }
+            if let (Ok(s), Ok(e)) = (start.parse::<i32>(), end.parse::<i32>())
+                && s <= e
+            {
+                for p in s..=e {
+                    ports.push(p);
                 }
             }
         } else if let Ok(p) = part.parse::<i32>() {
@@ -410,11 +1039,164 @@ fn parse_ports(input: &str) -> Vec<i32> {
     ports
 }
 
+/// Output of the server's install, for the manage page: everything so far, then live lines
+/// until the script exits. Servers with no install running or recently finished get a
+/// single `idle` event.
+pub async fn install_log_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> impl IntoResponse {
+    let Some(view) = state.install_logs.view(id) else {
+        let idle = Event::default().event("idle").data("{}");
+        let events =
+            futures_util::stream::once(async move { Ok::<_, std::convert::Infallible>(idle) });
+        return Sse::new(events.boxed()).into_response();
+    };
+
+    let backlog = futures_util::stream::iter(view.backlog);
+    let live = futures_util::stream::unfold(view.live, |live| async move {
+        let mut live = live?;
+        loop {
+            match live.recv().await {
+                Ok(event) => {
+                    let finished = !matches!(event, InstallTestEvent::Output { .. });
+                    return Some((event, (!finished).then_some(live)));
+                }
+                // A slow page misses some lines rather than the end of the install
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = backlog
+        .chain(live)
+        .map(|event| Ok::<_, std::convert::Infallible>(install_log_event(&event)));
+    Sse::new(events.boxed())
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn install_log_event(event: &InstallTestEvent) -> Event {
+    Event::default()
+        .event(event.kind())
+        .json_data(event)
+        .unwrap_or_default()
+}
+
+#[derive(Template)]
+#[template(path = "server_startup_badge.html")]
+struct StartupBadgeTemplate {
+    server_id: Uuid,
+    label: &'static str,
+    style: &'static str,
+}
+
+/// Live badge next to the server's status on the manage page, from the node's latest
+/// heartbeat: `Starting…` until the image's startup-done marker shows up in the console,
+/// then `Ready`. Images without a marker show `Running`. Re-polls itself every few seconds.
+pub async fn startup_badge_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> impl IntoResponse {
+    const GREEN: &str = "background: #d4edda; color: #155724;";
+    const YELLOW: &str = "background: #fff3cd; color: #856404;";
+    const GREY: &str = "background: #e2e8f0; color: #4a5568;";
+
+    let node_id: Option<String> =
+        sqlx::query_scalar("SELECT node_id::text FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+    let heartbeat = match node_id {
+        Some(node_id) => state.node_stats(&node_id).await,
+        None => None,
+    };
+    let container = heartbeat
+        .as_ref()
+        .and_then(|hb| hb.containers.as_deref())
+        .and_then(|containers| containers.iter().find(|c| c.server_id == id.to_string()));
+
+    let (label, style) = match container {
+        _ if heartbeat.is_none() => ("Node offline", GREY),
+        Some(c) if c.is_starting() => ("Starting…", YELLOW),
+        Some(c) if c.state == "running" && c.ready == Some(true) => ("Ready", GREEN),
+        Some(c) if c.state == "running" => ("Running", GREEN),
+        Some(_) => ("Stopped", GREY),
+        None => ("No container", GREY),
+    };
+    HtmlTemplate(StartupBadgeTemplate {
+        server_id: id,
+        label,
+        style,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct StoredLogsQuery {
+    pub tail: Option<usize>,
+}
+
+#[derive(Template)]
+#[template(path = "server_stored_logs.html")]
+struct StoredLogsTemplate {
+    logs: Option<crate::models::StoredLogs>,
+    error: Option<String>,
+}
+
+/// Output the server's node kept on disk, for the manage page. Unlike the console it is
+/// there when the container is stopped or was recreated.
+pub async fn stored_logs_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Query(query): Query<StoredLogsQuery>,
+) -> impl IntoResponse {
+    let tail = query.tail.unwrap_or(200).clamp(1, 5000);
+    let node_id: Option<String> =
+        sqlx::query_scalar("SELECT node_id::text FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None);
+    let node = match node_id {
+        Some(node_id) => state.get_node_with_token(&node_id).await,
+        None => None,
+    };
+    let Some(node) = node else {
+        return HtmlTemplate(StoredLogsTemplate {
+            logs: None,
+            error: Some("The server's node no longer exists".to_string()),
+        });
+    };
+
+    let (logs, error) = match node_api::stored_logs(
+        &state.http_client,
+        &node,
+        &id.to_string(),
+        tail,
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(logs) => (logs, None),
+        Err(e) => {
+            tracing::warn!("Fetching stored logs of server {} failed: {}", id, e);
+            (None, Some(e))
+        }
+    };
+    HtmlTemplate(StoredLogsTemplate { logs, error })
+}
+
 pub async fn manage_server_page_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Query(query): Query<ServerCreateQuery>,
+    headers: axum::http::HeaderMap,
+    jar: CookieJar,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
+    let base_url = state.base_url(&headers);
     let panel_name = state.panel_name.read().await.clone();
     let panel_font = state.panel_font.read().await.clone();
     let panel_font_url = state.panel_font_url.read().await.clone();
@@ -432,14 +1214,85 @@ pub async fn manage_server_page_handler(
         }
     };
 
-    let template = ManageServerTemplate {
-        panel_name,
-        panel_font,
-        panel_font_url,
+    // Operations on the server's node still ahead of this one, if it is waiting
+    let queued_behind = if server.status == "queued" {
+        state
+            .node_ops
+            .pending(&server.node_id.to_string())
+            .saturating_sub(1)
+    } else {
+        0
+    };
+
+    let address = match server.allocation_id {
+        Some(alloc_id) => {
+            sqlx::query_as::<_, (String, i32)>("SELECT ip, port FROM allocations WHERE id = $1")
+                .bind(alloc_id)
+                .fetch_optional(&state.db)
+                .await
+                .unwrap_or(None)
+                .map(|(ip, port)| format!("{}:{}", ip, port))
+        }
+        None => None,
+    };
+
+    let events = server_events::recent(&state.db, server.id, 10).await;
+    let (bandwidth_rx, bandwidth_tx) = bandwidth::server_usage(&state.db, server.id).await;
+    let uptime = uptime::server(&state.db, server.id, server.created_at).await;
+    let install_job = jobs::latest_for(&state.db, jobs::CREATE_CONTAINER, &server.id.to_string())
+        .await
+        .map(|j| j.id);
+    let recreate_job =
+        jobs::latest_for(&state.db, jobs::RECREATE_CONTAINER, &server.id.to_string())
+            .await
+            .map(|j| j.id);
+
+    let console_macros: String =
+        sqlx::query_scalar("SELECT console_macros FROM images WHERE id = $1")
+            .bind(server.image_id)
+            .fetch_optional(&state.db)
+            .await
+            .unwrap_or(None)
+            .unwrap_or_else(|| "[]".to_string());
+    let console_macros = serde_json::from_str(&console_macros)
+        .map(|v| parse_console_macros(&v))
+        .unwrap_or_default();
+    let can_send_commands = match session_user(&state, &jar).await {
+        Some(user) => user.can(PERMISSION_SERVER_COMMAND),
+        None => state.auth_mode == AuthMode::Off,
+    };
+    let port_conflicts = if server.status == "install_failed" {
+        provisioning::port_conflicts(&state.db, server.id).await
+    } else {
+        Vec::new()
+    };
+
+    let template = ManageServerTemplate {
+        can_modify: viewer.can_modify,
+        panel_name,
+        panel_font,
+        panel_font_url,
         panel_version: "0.1.0".to_string(),
         execution_time: start_time.elapsed().as_secs_f64(),
         active_tab: "servers".to_string(),
         server,
+        queued_behind,
+        address,
+        events,
+        error: query.error,
+        duplicate_name: query.duplicate_name,
+        install_job,
+        recreate_job,
+        status_url: format!("{}/public/servers/{}/status", base_url, id),
+        bandwidth_rx,
+        bandwidth_tx,
+        uptime,
+        has_console_macros: !console_macros.is_empty(),
+        console_macros_json: serde_json::to_string(&console_macros)
+            .unwrap_or_else(|_| "[]".to_string()),
+        can_send_commands,
+        has_leftovers: port_conflicts.iter().any(|c| c.leftover),
+        port_conflicts,
     };
 
     HtmlTemplate(template).into_response()
@@ -447,7 +1300,9 @@ pub async fn manage_server_page_handler(
 
 pub async fn edit_server_page_handler(
     State(state): State<AppState>,
+    viewer: Viewer,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Query(query): Query<ServerCreateQuery>,
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     let panel_name = state.panel_name.read().await.clone();
@@ -467,7 +1322,50 @@ pub async fn edit_server_page_handler(
         }
     };
 
+    let (image_startup_command, allow_startup_override, variables) = sqlx::query_as::<_, (String, bool, String)>(
+        "SELECT startup_command, allow_startup_override, COALESCE(variables::text, '[]') FROM images WHERE id = $1",
+    )
+    .bind(server.image_id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None)
+    .unwrap_or_else(|| (server.startup_command.clone(), true, "[]".to_string()));
+
+    let set = server_secrets::set_names(&state.db, server.id).await;
+    let secrets = serde_json::from_str::<Vec<Variable>>(&variables)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|v| v.is_secret)
+        .map(|v| SecretField {
+            is_set: set.contains(&v.env_variable),
+            name: v.name,
+            env_variable: v.env_variable,
+        })
+        .collect();
+
+    let allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text FROM allocations WHERE server_id = $1 ORDER BY port")
+        .bind(server.id)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+    let mut free_allocations = sqlx::query_as::<_, Allocation>("SELECT id::text, node_id::text, ip, port, server_id::text, notes, reserved FROM allocations WHERE node_id = $1 AND server_id IS NULL ORDER BY port")
+        .bind(server.node_id)
+        .fetch_all(&state.db)
+        .await
+        .unwrap_or_default();
+    let ids: Vec<String> = free_allocations.iter().map(|a| a.id.clone()).collect();
+    let held = state
+        .allocation_reservations
+        .held_by_others(&ids, None)
+        .await;
+    free_allocations.retain(|a| !held.contains(&a.id));
+    let primary_allocation_id = server
+        .allocation_id
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
     let template = EditServerTemplate {
+        can_modify: viewer.can_modify,
         panel_name,
         panel_font,
         panel_font_url,
@@ -475,66 +1373,745 @@ pub async fn edit_server_page_handler(
         execution_time: start_time.elapsed().as_secs_f64(),
         active_tab: "servers".to_string(),
         server,
+        image_startup_command,
+        allow_startup_override,
+        error: query.error,
+        secrets,
+        allocations,
+        free_allocations,
+        primary_allocation_id,
     };
 
     HtmlTemplate(template).into_response()
 }
 
+#[derive(Deserialize)]
+pub struct AssignAllocationRequest {
+    pub allocation_id: Uuid,
+}
+
+/// Adds a free allocation on the server's node. Port bindings are fixed at container
+/// creation, so the server is flagged for a recreate.
+pub async fn assign_allocation_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+    Form(payload): Form<AssignAllocationRequest>,
+) -> Redirect {
+    match allocations::assign_to_server(&state.db, id, payload.allocation_id).await {
+        Ok(alloc) => {
+            flag_port_change(
+                &state,
+                id,
+                "allocation_added",
+                &format!("Port {}:{} assigned", alloc.ip, alloc.port),
+            )
+            .await;
+            Redirect::to(&format!("/servers/{}/edit", id))
+        }
+        Err(e) => {
+            if let allocations::AllocationChangeError::Db(msg) = &e {
+                tracing::error!("Failed to assign allocation to server {}: {}", id, msg);
+            }
+            Redirect::to(&format!("/servers/{}/edit?error={}", id, e.code()))
+        }
+    }
+}
+
+pub async fn release_allocation_handler(
+    State(state): State<AppState>,
+    axum::extract::Path((id, allocation_id)): axum::extract::Path<(Uuid, Uuid)>,
+) -> Redirect {
+    match allocations::release_from_server(&state.db, id, allocation_id).await {
+        Ok(alloc) => {
+            flag_port_change(
+                &state,
+                id,
+                "allocation_released",
+                &format!("Port {}:{} released", alloc.ip, alloc.port),
+            )
+            .await;
+            Redirect::to(&format!("/servers/{}/edit", id))
+        }
+        Err(e) => {
+            if let allocations::AllocationChangeError::Db(msg) = &e {
+                tracing::error!("Failed to release allocation from server {}: {}", id, msg);
+            }
+            Redirect::to(&format!("/servers/{}/edit?error={}", id, e.code()))
+        }
+    }
+}
+
+async fn flag_port_change(state: &AppState, id: Uuid, kind: &str, message: &str) {
+    let _ = sqlx::query("UPDATE servers SET needs_recreate = TRUE WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await;
+    server_events::record(
+        &state.db,
+        id,
+        kind,
+        &format!("{}; recreate the container to apply", message),
+    )
+    .await;
+}
+
+/// `POST /servers/{id}/recreate`: queues a `recreate_container` job that rebuilds the
+/// container from the server's current settings. Ignored while the server is still being
+/// created or another recreate is pending.
+pub async fn recreate_server_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Redirect {
+    let status: Option<String> =
+        match sqlx::query_scalar("SELECT status FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+        {
+            Ok(status) => status,
+            Err(e) => {
+                tracing::error!("Failed to load server {} for recreate: {}", id, e);
+                return Redirect::to(&format!("/servers/{}/manage?error=recreate_failed", id));
+            }
+        };
+    let Some(status) = status else {
+        return Redirect::to("/servers");
+    };
+    if status != "running" {
+        return Redirect::to(&format!(
+            "/servers/{}/manage?error=recreate_not_installed",
+            id
+        ));
+    }
+    let pending = jobs::latest_for(&state.db, jobs::RECREATE_CONTAINER, &id.to_string())
+        .await
+        .is_some_and(|j| j.status == "queued" || j.status == "running");
+    if pending {
+        return Redirect::to(&format!("/servers/{}/manage", id));
+    }
+
+    let job = RecreateContainerJob { server_id: id };
+    if let Err(e) = jobs::enqueue(&state, jobs::RECREATE_CONTAINER, &id.to_string(), &job).await {
+        tracing::error!("Failed to queue the recreate of server {}: {}", id, e);
+        return Redirect::to(&format!("/servers/{}/manage?error=recreate_failed", id));
+    }
+    Redirect::to(&format!("/servers/{}/manage", id))
+}
+
+/// Stores non-empty secret fields from the edit form. Empty fields keep the current value.
+/// Only the image's secret variables are accepted, so the form cannot inject arbitrary env.
+async fn store_submitted_secrets(state: &AppState, id: Uuid, raw: &[u8]) {
+    let submitted = server_secrets::form_environment(raw);
+    if submitted.values().all(|v| v.is_empty()) {
+        return;
+    }
+
+    let variables: String = sqlx::query_scalar(
+        "SELECT COALESCE(i.variables::text, '[]') FROM servers s JOIN images i ON s.image_id = i.id WHERE s.id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None)
+    .unwrap_or_else(|| "[]".to_string());
+
+    let mut updated = Vec::new();
+    for var in serde_json::from_str::<Vec<Variable>>(&variables).unwrap_or_default() {
+        if !var.is_secret {
+            continue;
+        }
+        let Some(value) = submitted.get(&var.env_variable).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        match server_secrets::store(&state.db, &state.secrets_key, id, &var.env_variable, value)
+            .await
+        {
+            Ok(()) => updated.push(var.env_variable),
+            Err(e) => tracing::error!(
+                "Failed to store secret {} for server {}: {}",
+                var.env_variable,
+                id,
+                e
+            ),
+        }
+    }
+
+    if updated.is_empty() {
+        return;
+    }
+    // The container's env is fixed at creation, so new values only apply after a recreate
+    let _ = sqlx::query("UPDATE servers SET needs_recreate = TRUE WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await;
+    for name in updated {
+        server_events::record(
+            &state.db,
+            id,
+            "secret_updated",
+            &format!("Secret {} updated", name),
+        )
+        .await;
+    }
+}
+
 pub async fn update_server_handler(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
-    Form(payload): Form<UpdateServerRequest>,
+    jar: CookieJar,
+    RawForm(raw): RawForm,
 ) -> impl IntoResponse {
-     let q = sqlx::query(
+    let Ok(payload) = serde_urlencoded::from_bytes::<UpdateServerRequest>(&raw) else {
+        return Redirect::to(&format!("/servers/{}/edit?error=invalid_form", id)).into_response();
+    };
+
+    let previous = match sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => return Redirect::to("/servers").into_response(),
+        Err(e) => {
+            eprintln!("Error fetching server: {}", e);
+            return Redirect::to(&format!("/servers/{}/edit?error=update_failed", id))
+                .into_response();
+        }
+    };
+
+    // Images that disallow overrides always run their own startup command
+    let locked_startup: Option<String> = sqlx::query_scalar(
+        "SELECT i.startup_command FROM servers s JOIN images i ON s.image_id = i.id WHERE s.id = $1 AND i.allow_startup_override = FALSE",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    let startup_command = locked_startup.or(payload.startup_command.clone());
+
+    // Ownership only moves when an admin picks someone else; the form always posts an owner
+    let owner_id = match payload
+        .owner_id
+        .as_ref()
+        .filter(|o| **o != previous.owner_id)
+    {
+        Some(owner)
+            if session_user(&state, &jar)
+                .await
+                .is_some_and(|u| u.is_admin()) =>
+        {
+            Some(owner.clone())
+        }
+        Some(_) => {
+            tracing::warn!(
+                "Ignoring owner change for server {} from a non-admin session",
+                id
+            );
+            None
+        }
+        None => None,
+    };
+
+    // Checks run against what the server will look like, not just the submitted fields
+    let cpu_limit = payload.cpu_limit.unwrap_or(previous.cpu_limit);
+    if let Err(code) = check_cpu_limit(&state, &previous.node_id.to_string(), cpu_limit).await {
+        return Redirect::to(&format!("/servers/{}/edit?error={}", id, code)).into_response();
+    }
+
+    if let Ok(Some(image)) = sqlx::query_as::<_, Image>(&format!(
+        "SELECT {} FROM images WHERE id = $1",
+        images::IMAGE_COLUMNS
+    ))
+    .bind(previous.image_id)
+    .fetch_optional(&state.db)
+    .await
+        && let Err(message) = check_image_minimums(
+            &image,
+            payload.ram_limit.unwrap_or(previous.ram_limit),
+            payload.disk_limit.unwrap_or(previous.disk_limit),
+            cpu_limit,
+            payload.oom_killer().unwrap_or(previous.oom_killer),
+        )
+    {
+        return Redirect::to(&format!("/servers/{}/edit?{}", id, error_query(&message)))
+            .into_response();
+    }
+
+    // Keeping the current image is always fine, even if it was a custom override
+    if enforce_image_docker_images()
+        && let Some(docker_image) = &payload.docker_image
+        && previous.docker_image != *docker_image
+    {
+        let image = sqlx::query_as::<_, Image>(&format!(
+            "SELECT {} FROM images WHERE id = $1",
+            images::IMAGE_COLUMNS
+        ))
+        .bind(previous.image_id)
+        .fetch_optional(&state.db)
+        .await
+        .unwrap_or(None);
+        if !image.is_some_and(|i| i.allowed_docker_images().contains(docker_image)) {
+            return Redirect::to(&format!(
+                "/servers/{}/edit?error=docker_image_not_allowed",
+                id
+            ))
+            .into_response();
+        }
+    }
+
+    // NULL keeps the stored value, so a partial form only touches what it sent
+    let q = sqlx::query(
         r#"
         UPDATE servers SET
-            name = $2,
-            description = $3,
-            owner_id = $4,
-            cpu_limit = $5,
-            ram_limit = $6,
-            disk_limit = $7,
-            swap_limit = $8,
-            backup_limit = $9,
-            io_weight = $10,
-            oom_killer = $11,
-            docker_image = $12,
-            startup_command = $13
+            name = COALESCE($2, name),
+            description = COALESCE($3, description),
+            owner_id = COALESCE($4, owner_id),
+            cpu_limit = COALESCE($5, cpu_limit),
+            ram_limit = COALESCE($6, ram_limit),
+            disk_limit = COALESCE($7, disk_limit),
+            swap_limit = COALESCE($8, swap_limit),
+            backup_limit = COALESCE($9, backup_limit),
+            io_weight = COALESCE($10, io_weight),
+            oom_killer = COALESCE($11, oom_killer),
+            docker_image = COALESCE($12, docker_image),
+            startup_command = COALESCE($13, startup_command),
+            tags = COALESCE($14, tags),
+            stop_timeout_seconds = COALESCE($15, stop_timeout_seconds),
+            pull_policy = COALESCE($16, pull_policy),
+            bandwidth_limit = COALESCE($17, bandwidth_limit),
+            bandwidth_action = COALESCE($18, bandwidth_action)
         WHERE id = $1
     "#,
     )
     .bind(id)
     .bind(&payload.name)
     .bind(&payload.description)
-    .bind(payload.owner_id.unwrap_or("1".to_string()))
-    .bind(payload.cpu_limit.unwrap_or(0))
-    .bind(payload.ram_limit.unwrap_or(0))
-    .bind(payload.disk_limit.unwrap_or(0))
-    .bind(payload.swap_limit.unwrap_or(0))
-    .bind(payload.backup_limit.unwrap_or(0))
-    .bind(payload.io_weight.unwrap_or(500))
-    .bind(payload.oom_killer.is_some())
+    .bind(owner_id)
+    .bind(payload.cpu_limit)
+    .bind(payload.ram_limit)
+    .bind(payload.disk_limit)
+    .bind(payload.swap_limit)
+    .bind(payload.backup_limit)
+    .bind(payload.io_weight)
+    .bind(payload.oom_killer())
     .bind(&payload.docker_image)
-    .bind(&payload.startup_command)
+    .bind(&startup_command)
+    .bind(payload.tags.as_deref().map(parse_tags))
+    .bind(
+        payload
+            .stop_timeout_seconds
+            .map(|t| stop_timeout_or_default(Some(t))),
+    )
+    .bind(
+        payload
+            .pull_policy
+            .as_deref()
+            .map(|p| pull_policy_or_default(Some(p))),
+    )
+    .bind(payload.bandwidth_limit.map(|l| l.max(0)))
+    .bind(
+        payload
+            .bandwidth_action
+            .as_deref()
+            .filter(|a| BANDWIDTH_ACTIONS.contains(a)),
+    )
     .execute(&state.db)
     .await;
-    
+
     match q {
-        Ok(_) => Redirect::to(&format!("/servers/{}/manage", id)).into_response(),
+        Ok(_) => {
+            store_submitted_secrets(&state, id, &raw).await;
+            let updated = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&state.db)
+                .await
+                .unwrap_or(None);
+            let mut location = format!("/servers/{}/manage", id);
+            if let Some(updated) = updated {
+                apply_live_changes(&state, &previous, &updated).await;
+                if updated.name != previous.name
+                    && name_in_use(&state.db, &updated.owner_id, &updated.name, &id.to_string())
+                        .await
+                {
+                    location.push_str("?duplicate_name=true");
+                }
+            }
+            Redirect::to(&location).into_response()
+        }
         Err(e) => {
             eprintln!("Failed to update server: {}", e);
-             Redirect::to(&format!("/servers/{}/edit?error=update_failed", id)).into_response()
+            Redirect::to(&format!("/servers/{}/edit?error=update_failed", id)).into_response()
+        }
+    }
+}
+
+/// Pushes RAM/CPU/swap/IO changes to the running container and flags changes
+/// Docker can't apply live (image, startup command) for a recreate.
+async fn apply_live_changes(state: &AppState, before: &Server, after: &Server) {
+    if before.bandwidth_limit != after.bandwidth_limit
+        || before.bandwidth_action != after.bandwidth_action
+    {
+        bandwidth::cap_changed(&state.db, after.id).await;
+    }
+
+    if before.docker_image != after.docker_image || before.startup_command != after.startup_command
+    {
+        let _ = sqlx::query("UPDATE servers SET needs_recreate = TRUE WHERE id = $1")
+            .bind(after.id)
+            .execute(&state.db)
+            .await;
+        server_events::record(
+            &state.db,
+            after.id,
+            "needs_recreate",
+            "Image or startup command changed; recreate the container to apply",
+        )
+        .await;
+    }
+
+    let limits_changed = before.ram_limit != after.ram_limit
+        || before.swap_limit != after.swap_limit
+        || before.cpu_limit != after.cpu_limit
+        || before.io_weight != after.io_weight;
+    if !limits_changed {
+        return;
+    }
+
+    let summary = format!(
+        "RAM {} -> {} MB, swap {} -> {} MB, CPU {} -> {}%, IO {} -> {}",
+        before.ram_limit,
+        after.ram_limit,
+        before.swap_limit,
+        after.swap_limit,
+        before.cpu_limit,
+        after.cpu_limit,
+        before.io_weight,
+        after.io_weight
+    );
+
+    let node_id = after.node_id.to_string();
+    let Some(node) = state.get_node_with_token(&node_id).await else {
+        server_events::record(
+            &state.db,
+            after.id,
+            "limits_update_failed",
+            &format!("{}: node not found", summary),
+        )
+        .await;
+        return;
+    };
+
+    let limits = UpdateLimitsRequest {
+        memory_limit: after.ram_limit as i64,
+        swap_limit: after.swap_limit as i64,
+        cpu_limit: after.cpu_limit as i64,
+        io_weight: after.io_weight.clamp(10, 1000) as u16,
+    };
+
+    match node_api::update_container_limits(
+        &state.http_client,
+        &node,
+        &after.id.to_string(),
+        &limits,
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(warnings) => {
+            server_events::record(
+                &state.db,
+                after.id,
+                "limits_updated",
+                &format!("Applied live: {}", summary),
+            )
+            .await;
+            for warning in warnings {
+                server_events::record(&state.db, after.id, "warning", &warning).await;
+            }
+        }
+        Err(e) => {
+            tracing::error!("Live limit update for server {} failed: {}", after.id, e);
+            let _ = sqlx::query("UPDATE servers SET needs_recreate = TRUE WHERE id = $1")
+                .bind(after.id)
+                .execute(&state.db)
+                .await;
+            server_events::record(
+                &state.db,
+                after.id,
+                "limits_update_failed",
+                &format!("{}: {} (applies on recreate)", summary, e),
+            )
+            .await;
         }
     }
 }
 
+/// Stops and removes a server's container on its node, and with `purge` its data volume
+/// too. A server that is already gone is fine.
+async fn remove_server_container(state: &AppState, id: Uuid, purge: bool) -> Result<(), String> {
+    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(server) = server else {
+        return Ok(());
+    };
+    let node = state
+        .get_node_with_token(&server.node_id.to_string())
+        .await
+        .ok_or_else(|| "node not found".to_string())?;
+
+    let _permit = state.node_ops.acquire(&node.id).await;
+    let killed = node_api::stop_and_delete_container(
+        &state.http_client,
+        &node,
+        &id.to_string(),
+        Some(server.stop_grace()),
+        purge,
+        &state.node_retry,
+    )
+    .await?;
+    if killed {
+        tracing::warn!(
+            "Server {} did not stop within {}s and was killed",
+            id,
+            server.stop_grace()
+        );
+    }
+    if purge {
+        tracing::info!(
+            "Wiped the data volume of server {} ({}) on node {}",
+            id,
+            server.name,
+            node.name
+        );
+    }
+    Ok(())
+}
+
+/// Removes the containers of deleted servers that the last failed create found on this
+/// server's ports, then queues the create again. Containers of servers the panel still has
+/// are left alone; those are a double-booked allocation, not a leftover.
+pub async fn repair_ports_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> impl IntoResponse {
+    let server = match sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => return Redirect::to("/servers"),
+        Err(e) => {
+            tracing::error!("Failed to load server {} for port repair: {}", id, e);
+            return Redirect::to(&format!("/servers/{}/manage?error=repair_failed", id));
+        }
+    };
+    if server.status != "install_failed" {
+        return Redirect::to(&format!("/servers/{}/manage", id));
+    }
+    let Some(node) = state.get_node_with_token(&server.node_id.to_string()).await else {
+        return Redirect::to(&format!("/servers/{}/manage?error=repair_failed", id));
+    };
+
+    let leftovers: Vec<PortConflict> = provisioning::port_conflicts(&state.db, id)
+        .await
+        .into_iter()
+        .filter(|c| c.leftover)
+        .collect();
+    {
+        let _permit = state.node_ops.acquire(&node.id).await;
+        for leftover in &leftovers {
+            if let Err(e) = node_api::stop_and_delete_container(
+                &state.http_client,
+                &node,
+                &leftover.server_id,
+                None,
+                true,
+                &state.node_retry,
+            )
+            .await
+            {
+                tracing::error!(
+                    "Failed to remove leftover container {} on node {}: {}",
+                    leftover.container,
+                    node.name,
+                    e
+                );
+                return Redirect::to(&format!("/servers/{}/manage?error=repair_failed", id));
+            }
+            server_events::record(
+                &state.db,
+                id,
+                "port_repair",
+                &format!(
+                    "Removed leftover container {} holding port {}",
+                    leftover.container, leftover.port
+                ),
+            )
+            .await;
+        }
+    }
+
+    queue_install_retry(&state, id).await;
+    Redirect::to(&format!("/servers/{}/manage", id))
+}
+
+/// Sends a failed install to the node again. The node treats a container that already
+/// exists under the server's name as created, so this is safe after a create that got
+/// through but whose answer was lost.
+pub async fn retry_install_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Redirect {
+    let status: Option<String> = sqlx::query_scalar("SELECT status FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+        .unwrap_or(None);
+    match status.as_deref() {
+        None => return Redirect::to("/servers"),
+        Some("install_failed") => {}
+        Some(_) => return Redirect::to(&format!("/servers/{}/manage", id)),
+    }
+    server_events::record(&state.db, id, "install", "Install retried").await;
+    queue_install_retry(&state, id).await;
+    Redirect::to(&format!("/servers/{}/manage", id))
+}
+
+/// Values to keep after a reset: each editable variable the image still has keeps the
+/// server's value, or gets the image default; values for variables the image dropped or
+/// locked go. Secrets live in `server_secrets` and aren't touched.
+fn reset_variable_values(
+    variables: &[Variable],
+    current: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    variables
+        .iter()
+        .filter(|v| v.user_editable && !v.is_secret)
+        .map(|v| {
+            let value = current.get(&v.env_variable).unwrap_or(&v.default_value);
+            (v.env_variable.clone(), value.clone())
+        })
+        .collect()
+}
+
+/// Brings a server's startup command and variables back in line with its image after the
+/// image was updated, keeping the user's values for variables that still exist. Takes
+/// effect when the container is recreated.
+pub async fn reset_to_image_handler(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> Redirect {
+    let row: Option<(String, String, String)> = match sqlx::query_as(
+        "SELECT i.startup_command, COALESCE(i.variables::text, '[]'), COALESCE(s.variables::text, '{}') \
+         FROM servers s JOIN images i ON s.image_id = i.id WHERE s.id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            tracing::error!("Failed to load image defaults for server {}: {}", id, e);
+            return Redirect::to(&format!("/servers/{}/edit?error=db_error", id));
+        }
+    };
+    let Some((startup_command, image_variables, values)) = row else {
+        return Redirect::to("/servers");
+    };
+
+    let variables = serde_json::from_str::<Vec<Variable>>(&image_variables).unwrap_or_default();
+    let current = serde_json::from_str::<HashMap<String, String>>(&values).unwrap_or_default();
+    let values = reset_variable_values(&variables, &current);
+    let dropped = current.keys().filter(|k| !values.contains_key(*k)).count();
+
+    let updated = sqlx::query(
+        "UPDATE servers SET startup_command = $1, variables = $2::jsonb, needs_recreate = TRUE WHERE id = $3",
+    )
+    .bind(&startup_command)
+    .bind(serde_json::to_string(&values).unwrap_or_else(|_| "{}".to_string()))
+    .bind(id)
+    .execute(&state.db)
+    .await;
+    if let Err(e) = updated {
+        tracing::error!("Failed to reset server {} to its image defaults: {}", id, e);
+        return Redirect::to(&format!("/servers/{}/edit?error=db_error", id));
+    }
+
+    let mut message = format!(
+        "Startup command and variables reset to the image ({} kept",
+        values.len()
+    );
+    if dropped > 0 {
+        message.push_str(&format!(", {} no longer in the image removed", dropped));
+    }
+    message.push_str("); recreate the container to apply");
+    server_events::record(&state.db, id, "needs_recreate", &message).await;
+    Redirect::to(&format!("/servers/{}/manage", id))
+}
+
+/// Resets a failed install and queues the create job again.
+async fn queue_install_retry(state: &AppState, id: Uuid) {
+    let _ = sqlx::query(
+        "UPDATE servers SET status = 'installing', install_error = NULL, port_conflicts = NULL WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&state.db)
+    .await;
+    let job = CreateContainerJob { server_id: id };
+    if let Err(e) = jobs::enqueue(state, jobs::CREATE_CONTAINER, &id.to_string(), &job).await {
+        tracing::error!("Failed to queue container creation for {}: {}", id, e);
+        let _ = sqlx::query(
+            "UPDATE servers SET status = 'install_failed', install_error = $1 WHERE id = $2",
+        )
+        .bind(format!("Could not queue the install: {}", e))
+        .bind(id)
+        .execute(&state.db)
+        .await;
+    }
+}
+
 pub async fn delete_server_handler(
     State(state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
     Form(payload): Form<DeleteServerRequest>,
 ) -> impl IntoResponse {
     let force = payload.force.is_some();
-    
+    let purge = payload.purge.is_some();
+
+    // Wiping the files can't be undone, so it takes the server's name typed out
+    if purge {
+        let name: Option<String> = sqlx::query_scalar("SELECT name FROM servers WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await
+            .ok()
+            .flatten();
+        let confirmed = payload.purge_confirm.as_deref().map(str::trim);
+        if name.is_none() || name.as_deref() != confirmed {
+            return Redirect::to(&format!("/servers/{}/edit?error=purge_unconfirmed", id));
+        }
+    }
+
+    // Stop the container first, giving it the server's stop timeout to flush its data
+    if let Err(e) = remove_server_container(&state, id, purge).await {
+        if !force {
+            tracing::error!("Failed to remove the container of server {}: {}", id, e);
+            return Redirect::to(&format!(
+                "/servers/{}/edit?error=container_cleanup_failed",
+                id
+            ));
+        }
+        tracing::warn!(
+            "Force-deleting server {} despite container cleanup failure: {}",
+            id,
+            e
+        );
+    }
+
     let mut tx = match state.db.begin().await {
         Ok(t) => t,
         Err(_) => return Redirect::to(&format!("/servers/{}/edit?error=db_error", id)),
@@ -545,37 +2122,37 @@ pub async fn delete_server_handler(
         .bind(id)
         .execute(&mut *tx)
         .await;
-        
+
     if let Err(e) = q1 {
         eprintln!("Failed to free allocations: {}", e);
         if !force {
-             let _ = tx.rollback().await;
-             return Redirect::to(&format!("/servers/{}/edit?error=alloc_cleanup_failed", id));
+            let _ = tx.rollback().await;
+            return Redirect::to(&format!("/servers/{}/edit?error=alloc_cleanup_failed", id));
         }
     }
-    
+
     // Delete server
     let q2 = sqlx::query("DELETE FROM servers WHERE id = $1")
         .bind(id)
         .execute(&mut *tx)
         .await;
-        
+
     if let Err(e) = q2 {
         eprintln!("Failed to delete server: {}", e);
         let _ = tx.rollback().await;
         // In force mode, do we still rollback? "Force" usually means delete the record.
-        // If DELETE fails, it's usually constraint or DB error. 
+        // If DELETE fails, it's usually constraint or DB error.
         // If force, we might not be able to do much.
         // But for "safety vs forcibly", force usually applies to external checks (like daemon).
-        // For DB, if DELETE fails, we assume it's critical. 
+        // For DB, if DELETE fails, we assume it's critical.
         return Redirect::to(&format!("/servers/{}/edit?error=delete_failed", id));
     }
-    
+
     if let Err(e) = tx.commit().await {
-         eprintln!("Failed to commit delete: {}", e);
+        eprintln!("Failed to commit delete: {}", e);
         return Redirect::to(&format!("/servers/{}/edit?error=commit_failed", id));
     }
-    
+
     Redirect::to("/servers")
 }
 
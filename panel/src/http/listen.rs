@@ -0,0 +1,242 @@
+//! Where the panel serves: `LISTEN_ADDR`/`LISTEN_PORT`, plain HTTP or HTTPS straight from
+//! `TLS_CERT_PATH`/`TLS_KEY_PATH` for installs without a reverse proxy. With TLS on,
+//! `TLS_REDIRECT_PORT` adds a plain-HTTP port that only redirects to HTTPS.
+
+use axum::{
+    Router,
+    extract::Request,
+    http::{StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+    serve::Listener,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+
+/// Clients that connect but never finish the handshake are dropped after this long
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Finished handshakes waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 128;
+
+pub struct ListenConfig {
+    pub addr: SocketAddr,
+    pub tls: Option<TlsFiles>,
+    /// Plain-HTTP port that redirects to the HTTPS one; only used with TLS
+    pub redirect_port: Option<u16>,
+}
+
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl ListenConfig {
+    /// `LISTEN_ADDR` (default `0.0.0.0`) and `LISTEN_PORT` (default 3000). The older
+    /// `PANEL_HOST`/`PANEL_PORT` names are still read when the new ones are unset.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str, legacy: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var(legacy))
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let ip = match var("LISTEN_ADDR", "PANEL_HOST") {
+            Some(v) => v
+                .parse::<IpAddr>()
+                .map_err(|_| format!("LISTEN_ADDR '{}' is not an IP address", v))?,
+            None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        let port = match var("LISTEN_PORT", "PANEL_PORT") {
+            Some(v) => v
+                .parse::<u16>()
+                .map_err(|_| format!("LISTEN_PORT '{}' is not a port number", v))?,
+            None => 3000,
+        };
+
+        // TLS_CERT/TLS_KEY are accepted as shorter spellings
+        let path = |name: &str, short: &str| var(name, short).map(PathBuf::from);
+        let tls = match (
+            path("TLS_CERT_PATH", "TLS_CERT"),
+            path("TLS_KEY_PATH", "TLS_KEY"),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsFiles { cert, key }),
+            (None, None) => None,
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        };
+
+        let redirect_port = match std::env::var("TLS_REDIRECT_PORT")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        {
+            Some(v) => Some(
+                v.trim()
+                    .parse::<u16>()
+                    .map_err(|_| format!("TLS_REDIRECT_PORT '{}' is not a port number", v))?,
+            ),
+            None => None,
+        };
+        if redirect_port.is_some() && tls.is_none() {
+            return Err("TLS_REDIRECT_PORT needs TLS_CERT_PATH and TLS_KEY_PATH".to_string());
+        }
+        if redirect_port == Some(port) {
+            return Err("TLS_REDIRECT_PORT must differ from LISTEN_PORT".to_string());
+        }
+
+        Ok(Self {
+            addr: SocketAddr::new(ip, port),
+            tls,
+            redirect_port,
+        })
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() { "https" } else { "http" }
+    }
+}
+
+impl TlsFiles {
+    /// Reads the PEM certificate chain and private key. Errors name the file and what is
+    /// wrong with it, since they end up as the panel's last words at startup.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, String> {
+        let cert_pem = std::fs::read(&self.cert)
+            .map_err(|e| format!("Can't read TLS_CERT_PATH {}: {}", self.cert.display(), e))?;
+        let certs = CertificateDer::pem_slice_iter(&cert_pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                format!(
+                    "TLS_CERT_PATH {} is not a valid PEM certificate: {}",
+                    self.cert.display(),
+                    e
+                )
+            })?;
+        if certs.is_empty() {
+            return Err(format!(
+                "TLS_CERT_PATH {} contains no certificates",
+                self.cert.display()
+            ));
+        }
+
+        let key_pem = std::fs::read(&self.key)
+            .map_err(|e| format!("Can't read TLS_KEY_PATH {}: {}", self.key.display(), e))?;
+        let key = PrivateKeyDer::from_pem_slice(&key_pem).map_err(|e| {
+            format!(
+                "TLS_KEY_PATH {} has no usable PEM private key: {}",
+                self.key.display(),
+                e
+            )
+        })?;
+
+        // Both ring and aws-lc-rs end up linked, so the provider can't be picked implicitly
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("TLS setup failed: {}", e))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| {
+                format!(
+                    "TLS_CERT_PATH and TLS_KEY_PATH don't form a usable pair: {}",
+                    e
+                )
+            })?;
+        // axum::serve speaks HTTP/1.1 here (no http2 feature)
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// HTTPS listener for `axum::serve`. Handshakes run on their own tasks, so one slow or
+/// stalled client doesn't hold up everyone else's connections.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, connections) = mpsc::channel(ACCEPT_BACKLOG);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        // Usually out of file descriptors; back off instead of spinning
+                        tracing::error!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls)) => {
+                            let _ = tx.send((tls, addr)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(conn) => conn,
+            // The accept task only ends with the process
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Plain-HTTP app for `TLS_REDIRECT_PORT`: every request gets a permanent redirect to the
+/// same host and path on the HTTPS port.
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |req: Request| async move { redirect_to_https(req, https_port) })
+}
+
+fn redirect_to_https(req: Request, https_port: u16) -> Response {
+    let Some(host) = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    // Drop the port the client used for HTTP; keep IPv6 brackets intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let url = if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    };
+    Redirect::permanent(&url).into_response()
+}
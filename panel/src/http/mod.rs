@@ -1 +1,6 @@
+pub mod assets;
 pub mod handlers;
+pub mod listen;
+pub mod routes;
+pub mod security;
+pub mod upload;
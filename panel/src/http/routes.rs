@@ -0,0 +1,330 @@
+//! The panel's route table, shared by `main.rs` and the integration tests.
+
+use axum::{
+    Router,
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post},
+};
+use tower_http::services::ServeDir;
+
+use crate::http;
+use crate::http::handlers::{
+    allocations::{
+        allocations_page_handler, create_allocations_handler, delete_allocations_handler,
+        free_ports_handler, port_audit_page_handler, update_allocation_handler,
+    },
+    api::{heartbeat_handler, node_lifecycle_handler},
+    auth::{
+        self, auth_routes, clear_pending_token_handler, force_token_handler, reveal_token_handler,
+        rotate_all_tokens_handler, rotate_token_handler,
+    },
+    dashboard::nodes_page_handler,
+    logs::logs_handler,
+    nodes::{
+        cancel_decommission_handler, create_node_handler, create_node_page_handler,
+        decommission_node_handler, delete_node_handler, dismiss_maintenance_alert_handler,
+        edit_node_page_handler, node_agent_config_handler, node_docker_summary_handler,
+        reprovision_node_handler, setup_node_page_handler, trigger_node_update,
+        update_disk_labels_handler, update_maintenance_handler, update_node_handler,
+    },
+    overview::{overview_handler, overview_stats_handler},
+    runtimes::{
+        create_image_handler, create_image_page_handler, create_runtime_handler,
+        create_runtime_page_handler, delete_image_handler, delete_runtime_handler,
+        edit_image_page_handler, edit_runtime_page_handler, import_egg_handler,
+        propagate_startup_handler, reorder_runtimes_handler, runtimes_page_handler,
+        update_image_handler, update_runtime_handler,
+    },
+    runtimes_api,
+    scripts::{complete_decommission_handler, install_script_handler, uninstall_script_handler},
+    servers::{
+        assign_allocation_handler, create_server_handler, create_server_page_handler,
+        delete_server_handler, delete_server_template_handler, edit_server_page_handler,
+        manage_server_page_handler, recreate_server_handler, release_allocation_handler,
+        repair_ports_handler, reserve_allocation_handler, reset_to_image_handler,
+        retry_install_handler, save_server_template_handler, servers_page_handler,
+        update_server_handler,
+    },
+};
+use crate::state::AppState;
+
+/// Every page and API route, with the auth, body limit and CSP layers `main.rs` serves.
+pub fn router(state: AppState) -> Router {
+    // Request body limit (bytes). Upload routes get their own caps from state.upload_limits.
+    let max_body_size = std::env::var("MAX_BODY_SIZE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(2 * 1024 * 1024);
+    let upload_limits = state.upload_limits;
+
+    // Build our application with a route
+    let protected_routes = Router::new()
+        .route("/", get(overview_handler))
+        .route("/overview/stats", get(overview_stats_handler))
+        .route(
+            "/overview/ws",
+            get(http::handlers::overview::overview_ws_handler),
+        )
+        .route(
+            "/settings/update",
+            post(http::handlers::overview::update_settings_handler),
+        )
+        .route(
+            "/settings/rotate-download-secret",
+            post(http::handlers::downloads::rotate_download_secret_handler),
+        )
+        .route("/nodes", get(nodes_page_handler).post(create_node_handler))
+        .route(
+            "/servers",
+            get(servers_page_handler).post(create_server_handler),
+        )
+        .route("/servers/new", get(create_server_page_handler))
+        .route(
+            "/servers/new/reserve-allocation",
+            post(reserve_allocation_handler),
+        )
+        .route(
+            "/servers/templates/{id}",
+            delete(delete_server_template_handler),
+        )
+        .route(
+            "/servers/{id}/save-template",
+            post(save_server_template_handler),
+        )
+        .route(
+            "/servers/{id}/public-status",
+            post(http::handlers::public_status::toggle_public_status_handler),
+        )
+        .route("/servers/{id}/manage", get(manage_server_page_handler))
+        .route(
+            "/servers/{id}/install-log",
+            get(http::handlers::servers::install_log_handler),
+        )
+        .route(
+            "/servers/{id}/startup-badge",
+            get(http::handlers::servers::startup_badge_handler),
+        )
+        .route("/servers/{id}/edit", get(edit_server_page_handler))
+        .route(
+            "/servers/{id}/inspect",
+            get(http::handlers::inspect::inspect_server_handler),
+        )
+        .route(
+            "/servers/{id}/stored-logs",
+            get(http::handlers::servers::stored_logs_handler),
+        )
+        .route(
+            "/servers/{id}/command",
+            post(http::handlers::command::command_handler),
+        )
+        .route("/servers/{id}/update", post(update_server_handler))
+        .route("/servers/{id}/allocations", post(assign_allocation_handler))
+        .route(
+            "/servers/{id}/allocations/{allocation_id}/release",
+            post(release_allocation_handler),
+        )
+        .route("/servers/{id}/delete", post(delete_server_handler))
+        .route("/servers/{id}/repair-ports", post(repair_ports_handler))
+        .route("/servers/{id}/retry-install", post(retry_install_handler))
+        .route("/servers/{id}/reset-to-image", post(reset_to_image_handler))
+        .route("/servers/{id}/recreate", post(recreate_server_handler))
+        .route(
+            "/runtimes",
+            get(runtimes_page_handler).post(create_runtime_handler),
+        )
+        .route("/runtimes/reorder", post(reorder_runtimes_handler))
+        .route("/runtimes/new", get(create_runtime_page_handler))
+        .route("/runtimes/{id}/edit", get(edit_runtime_page_handler))
+        .route("/runtimes/{id}/update", post(update_runtime_handler))
+        .route("/runtimes/{id}/images/new", get(create_image_page_handler))
+        .route("/runtimes/{id}/images", post(create_image_handler))
+        .route(
+            "/runtimes/{id}/images/import",
+            post(import_egg_handler).layer(DefaultBodyLimit::max(
+                upload_limits.egg + http::upload::MULTIPART_OVERHEAD,
+            )),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/edit",
+            get(edit_image_page_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/update",
+            post(update_image_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/propagate-startup",
+            post(propagate_startup_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/export",
+            get(http::handlers::runtimes::export_image_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/test-install",
+            get(http::handlers::runtimes::test_install_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}",
+            delete(delete_image_handler),
+        )
+        .route(
+            "/runtimes/{runtime_id}/images/{image_id}/reassign-delete",
+            post(http::handlers::runtimes::reassign_delete_image_handler),
+        )
+        .route("/runtimes/{id}", delete(delete_runtime_handler))
+        .route("/logs", get(logs_handler))
+        .route(
+            "/api/jobs/{id}",
+            get(http::handlers::jobs::job_status_handler),
+        )
+        .route(
+            "/api/servers/{id}/power",
+            post(http::handlers::power::power_handler),
+        )
+        .route("/api/search", get(http::handlers::search::search_handler))
+        .route(
+            "/api/v1/runtimes",
+            get(runtimes_api::list_runtimes_handler).post(runtimes_api::upsert_runtime_handler),
+        )
+        .route(
+            "/api/v1/runtimes/{id}",
+            get(runtimes_api::get_runtime_handler)
+                .put(runtimes_api::update_runtime_handler)
+                .delete(runtimes_api::delete_runtime_handler),
+        )
+        .route(
+            "/api/v1/runtimes/{id}/import-egg",
+            post(runtimes_api::import_egg_handler).layer(DefaultBodyLimit::max(upload_limits.egg)),
+        )
+        .route(
+            "/api/v1/images",
+            get(runtimes_api::list_images_handler).post(runtimes_api::upsert_image_handler),
+        )
+        .route(
+            "/api/v1/images/{id}",
+            get(runtimes_api::get_image_handler)
+                .put(runtimes_api::update_image_handler)
+                .delete(runtimes_api::delete_image_handler),
+        )
+        .route(
+            "/jobs/{id}",
+            get(http::handlers::jobs::job_fragment_handler),
+        )
+        .route("/nodes/new", get(create_node_page_handler))
+        .route(
+            "/locations",
+            get(http::handlers::locations::locations_page_handler)
+                .post(http::handlers::locations::create_location_handler),
+        )
+        .route(
+            "/locations/{id}/update",
+            post(http::handlers::locations::update_location_handler),
+        )
+        .route(
+            "/locations/{id}/delete",
+            post(http::handlers::locations::delete_location_handler),
+        )
+        .route("/nodes/{id}/setup", get(setup_node_page_handler))
+        .route("/nodes/{id}/reprovision", post(reprovision_node_handler))
+        .route("/nodes/{id}/edit", get(edit_node_page_handler))
+        .route(
+            "/nodes/{id}/allocations",
+            get(allocations_page_handler).post(create_allocations_handler),
+        )
+        .route(
+            "/nodes/{id}/allocations/audit",
+            get(port_audit_page_handler),
+        )
+        .route(
+            "/nodes/{id}/allocations/free-ports",
+            get(free_ports_handler),
+        )
+        .route(
+            "/nodes/{id}/allocations/delete",
+            post(delete_allocations_handler),
+        )
+        .route(
+            "/nodes/{id}/allocations/{allocation_id}",
+            post(update_allocation_handler),
+        )
+        .route("/nodes/{id}/update", post(update_node_handler))
+        .route("/nodes/{id}/agent-config", get(node_agent_config_handler))
+        .route(
+            "/nodes/{id}/docker-summary",
+            get(node_docker_summary_handler),
+        )
+        .route("/nodes/{id}/trigger-update", post(trigger_node_update))
+        .route("/nodes/rotate-all", post(rotate_all_tokens_handler))
+        .route("/nodes/{id}/rotate-token", post(rotate_token_handler))
+        .route("/nodes/{id}/reveal-token", post(reveal_token_handler))
+        .route("/nodes/{id}/force-token", post(force_token_handler))
+        .route(
+            "/nodes/{id}/clear-pending-token",
+            post(clear_pending_token_handler),
+        )
+        .route("/nodes/{id}/decommission", post(decommission_node_handler))
+        .route(
+            "/nodes/{id}/decommission/cancel",
+            post(cancel_decommission_handler),
+        )
+        .route("/nodes/{id}/maintenance", post(update_maintenance_handler))
+        .route(
+            "/nodes/{id}/maintenance/dismiss",
+            post(dismiss_maintenance_alert_handler),
+        )
+        .route("/nodes/{id}/disk-labels", post(update_disk_labels_handler))
+        .route("/nodes/{id}", delete(delete_node_handler));
+
+    let protected_routes = if state.auth_mode == auth::AuthMode::Off {
+        protected_routes
+    } else {
+        protected_routes.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::auth_middleware,
+        ))
+    };
+
+    let public_routes = Router::new()
+        .route("/health", get(http::handlers::health::health_handler))
+        .route("/nodes/{id}/heartbeat", post(heartbeat_handler))
+        .route("/nodes/{id}/lifecycle", post(node_lifecycle_handler))
+        .route(
+            "/api/nodes/{id}/verify",
+            get(http::handlers::api::verify_node_handler),
+        )
+        .route("/install/{id}", get(install_script_handler))
+        .route("/uninstall/{id}", get(uninstall_script_handler))
+        .route(
+            "/nodes/{id}/decommission/complete",
+            post(complete_decommission_handler),
+        )
+        .route(
+            "/download/{token}",
+            get(http::handlers::downloads::download_handler),
+        )
+        .route(
+            "/downloads/{file}",
+            get(http::handlers::downloads::artifact_handler),
+        )
+        .nest("/auth", auth_routes())
+        .nest(
+            "/public",
+            Router::new()
+                .route(
+                    "/servers/{id}/status",
+                    get(http::handlers::public_status::public_status_handler),
+                )
+                .fallback_service(ServeDir::new(http::assets::PUBLIC_DIR))
+                .layer(axum::middleware::from_fn(
+                    http::assets::cache_control_middleware,
+                )),
+        );
+
+    Router::new()
+        .merge(protected_routes)
+        .merge(public_routes)
+        .layer(DefaultBodyLimit::max(max_body_size))
+        .layer(axum::middleware::from_fn(http::security::csp_middleware))
+        .with_state(state)
+}
@@ -0,0 +1,48 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::OnceLock;
+
+/// Scripts come only from /public and the CDNs the templates load (htmx, Monaco, Sortable, xterm).
+/// Inline styles are still used throughout the templates, so style-src keeps 'unsafe-inline'.
+const DEFAULT_POLICY: &str = "default-src 'self'; \
+    script-src 'self' https://unpkg.com https://cdnjs.cloudflare.com https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https:; \
+    font-src 'self' https: data:; \
+    img-src 'self' data:; \
+    connect-src 'self' ws: wss:; \
+    worker-src 'self' blob:; \
+    frame-ancestors 'none'; \
+    base-uri 'self'; \
+    form-action 'self'";
+
+static POLICY: OnceLock<Option<HeaderValue>> = OnceLock::new();
+
+/// `CONTENT_SECURITY_POLICY` overrides the default; set it empty to disable the header.
+fn policy() -> Option<&'static HeaderValue> {
+    POLICY
+        .get_or_init(|| {
+            let value = std::env::var("CONTENT_SECURITY_POLICY")
+                .unwrap_or_else(|_| DEFAULT_POLICY.to_string());
+            if value.trim().is_empty() {
+                return None;
+            }
+            HeaderValue::from_str(&value)
+                .map_err(|e| tracing::error!("Invalid CONTENT_SECURITY_POLICY: {}", e))
+                .ok()
+        })
+        .as_ref()
+}
+
+pub async fn csp_middleware(req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    if let Some(value) = policy() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_SECURITY_POLICY, value.clone());
+    }
+    response
+}
@@ -0,0 +1,191 @@
+//! Multipart upload fields read under a size cap. Small fields stay in memory; past
+//! `SPOOL_THRESHOLD` they go to an anonymous temp file, so a big upload costs disk rather
+//! than panel memory. `MAX_UPLOAD_SIZE` is the ceiling for every upload route, and routes
+//! with small payloads (egg imports) get their own, lower limit under it.
+
+use crate::models::format_bytes;
+use axum::extract::multipart::{Field, MultipartError};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use std::io::Seek;
+use tokio::io::AsyncWriteExt;
+
+/// Fields larger than this are written to a temp file instead of kept in memory
+const SPOOL_THRESHOLD: usize = 1024 * 1024;
+/// Room for multipart boundaries and part headers on top of a route's field limit, so the
+/// field check and its clearer message fire before the raw body limit does
+pub const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct UploadLimits {
+    /// Egg JSON imports: `EGG_UPLOAD_SIZE` (default 4 MiB), never above `MAX_UPLOAD_SIZE`
+    pub egg: usize,
+}
+
+impl UploadLimits {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .unwrap_or(default)
+        };
+        let max = var("MAX_UPLOAD_SIZE", 50 * 1024 * 1024);
+        Self {
+            egg: var("EGG_UPLOAD_SIZE", 4 * 1024 * 1024).min(max),
+        }
+    }
+}
+
+pub enum UploadError {
+    /// Over the limit; carries the limit and the setting that configures it
+    TooLarge(usize, &'static str),
+    NotUtf8,
+    Read(MultipartError),
+    Spool(std::io::Error),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match self {
+            UploadError::TooLarge(limit, setting) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Upload is larger than the {} limit ({} bytes, set by {})",
+                    format_bytes(&(limit as i64)),
+                    limit,
+                    setting
+                ),
+            )
+                .into_response(),
+            UploadError::NotUtf8 => {
+                (StatusCode::BAD_REQUEST, "Upload is not UTF-8 text").into_response()
+            }
+            UploadError::Read(e) => {
+                (e.status(), format!("Failed to read upload: {}", e)).into_response()
+            }
+            UploadError::Spool(e) => {
+                tracing::error!("Failed to spool upload to a temp file: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store upload").into_response()
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::Spool(e)
+    }
+}
+
+/// A field that was read in full.
+pub enum Upload {
+    Memory(Vec<u8>),
+    /// Temp file, removed by the OS once dropped
+    Spooled(std::fs::File),
+}
+
+impl Upload {
+    pub async fn parse_json<T: DeserializeOwned + Send + 'static>(
+        self,
+    ) -> Result<T, serde_json::Error> {
+        match self {
+            Upload::Memory(data) => serde_json::from_slice(&data),
+            Upload::Spooled(mut file) => tokio::task::spawn_blocking(move || {
+                file.rewind().map_err(serde_json::Error::io)?;
+                serde_json::from_reader(std::io::BufReader::new(file))
+            })
+            .await
+            .unwrap_or_else(|e| Err(serde_json::Error::io(std::io::Error::other(e)))),
+        }
+    }
+}
+
+/// Reads a text field, refusing it once it passes `limit` bytes or stops being UTF-8.
+/// `setting` names the env var behind the limit for the 413 message.
+pub async fn read_text_field(
+    mut field: Field<'_>,
+    limit: usize,
+    setting: &'static str,
+) -> Result<Upload, UploadError> {
+    let mut data = Vec::new();
+    let mut spool: Option<tokio::fs::File> = None;
+    let mut utf8 = Utf8Check::default();
+    let mut total = 0;
+
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            // The route's body limit ran out before the field did
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return Err(UploadError::TooLarge(limit, setting));
+            }
+            Err(e) => return Err(UploadError::Read(e)),
+        };
+        total += chunk.len();
+        if total > limit {
+            return Err(UploadError::TooLarge(limit, setting));
+        }
+        utf8.feed(&chunk)?;
+
+        match &mut spool {
+            Some(file) => file.write_all(&chunk).await?,
+            None => {
+                data.extend_from_slice(&chunk);
+                if data.len() > SPOOL_THRESHOLD {
+                    let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+                    file.write_all(&data).await?;
+                    data = Vec::new();
+                    spool = Some(file);
+                }
+            }
+        }
+    }
+    utf8.finish()?;
+
+    match spool {
+        None => Ok(Upload::Memory(data)),
+        Some(mut file) => {
+            file.flush().await?;
+            Ok(Upload::Spooled(file.into_std().await))
+        }
+    }
+}
+
+/// UTF-8 check over a stream of chunks; a character split between two chunks is held
+/// back until the next one arrives.
+#[derive(Default)]
+struct Utf8Check {
+    pending: Vec<u8>,
+}
+
+impl Utf8Check {
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        let joined;
+        let bytes = if self.pending.is_empty() {
+            chunk
+        } else {
+            self.pending.extend_from_slice(chunk);
+            joined = std::mem::take(&mut self.pending);
+            &joined[..]
+        };
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Ok(()),
+            Err(e) if e.error_len().is_none() => {
+                self.pending = bytes[e.valid_up_to()..].to_vec();
+                Ok(())
+            }
+            Err(_) => Err(UploadError::NotUtf8),
+        }
+    }
+
+    fn finish(&self) -> Result<(), UploadError> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(UploadError::NotUtf8)
+        }
+    }
+}
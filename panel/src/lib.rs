@@ -0,0 +1,8 @@
+//! The panel as a library: `main.rs` serves it, and the integration tests under `tests/`
+//! run the same router against a scratch database and a mock node agent.
+
+pub mod http;
+pub mod models;
+pub mod repositories;
+pub mod services;
+pub mod state;
@@ -15,6 +15,10 @@ pub struct Runtime {
     pub color: Option<String>,
     #[sqlx(default)]
     pub sort_order: i32,
+    /// Key set by API clients for upserts; see `services::images`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub external_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -39,6 +43,8 @@ pub struct Image {
     #[sqlx(default)]
     pub requires_port: bool,
     #[sqlx(default)]
+    pub allow_startup_override: bool,
+    #[sqlx(default)]
     pub install_script: String,
     #[sqlx(default)]
     pub install_container: String,
@@ -46,6 +52,173 @@ pub struct Image {
     pub install_entrypoint: String,
     #[sqlx(default)]
     pub variables: String, // json array of Variable struct
+    /// Smallest limits a server of this image may have; 0 = no minimum
+    #[sqlx(default)]
+    pub min_ram: i32, // MB
+    #[sqlx(default)]
+    pub min_disk: i32, // MB
+    #[sqlx(default)]
+    pub min_cpu: i32, // percent of one core
+    /// User (name, uid or uid:gid) containers run as; empty = the Docker image's default
+    #[sqlx(default)]
+    pub run_as_user: String,
+    #[sqlx(default)]
+    pub no_new_privileges: bool,
+    /// Default seconds a server of this image gets to stop before it is killed
+    #[sqlx(default)]
+    pub stop_timeout_seconds: i32,
+    /// Default `pull_policy` for servers of this image (see `PULL_POLICIES`)
+    #[sqlx(default)]
+    pub pull_policy: String,
+    /// JSON array of `ConsoleMacro`
+    #[sqlx(default)]
+    pub console_macros: String,
+    /// How the startup command becomes the container command (see `COMMAND_MODES`)
+    #[sqlx(default)]
+    pub command_mode: String,
+    /// Key set by API clients for upserts; see `services::images`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Labels for searching and filtering image lists, see `parse_tags`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Image {
+    /// Docker images this image offers, see `parse_docker_images`.
+    pub fn allowed_docker_images(&self) -> Vec<String> {
+        parse_docker_images(&self.docker_images)
+    }
+}
+
+/// Values of a JSON map, entries of a JSON array, or a newline/comma separated list (same
+/// formats the create form accepts).
+pub fn parse_docker_images(raw: &str) -> Vec<String> {
+    match serde_json::from_str::<serde_json::Value>(raw) {
+        Ok(serde_json::Value::Object(map)) => map
+            .values()
+            .filter_map(|v| v.as_str().map(|s| s.trim().to_string()))
+            .collect(),
+        Ok(serde_json::Value::Array(list)) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(|s| s.trim().to_string()))
+            .collect(),
+        _ => raw
+            .split(['\n', '\r', ','])
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+    }
+}
+
+/// Stop timeout for images that don't set one, in seconds.
+pub const DEFAULT_STOP_TIMEOUT: i32 = 30;
+/// Longest stop timeout the node accepts; it caps `grace` at the same value.
+pub const MAX_STOP_TIMEOUT: i32 = 300;
+
+/// Clamps a submitted stop timeout to what the node honours; empty means the default.
+pub fn stop_timeout_or_default(seconds: Option<i32>) -> i32 {
+    seconds
+        .unwrap_or(DEFAULT_STOP_TIMEOUT)
+        .clamp(1, MAX_STOP_TIMEOUT)
+}
+
+/// When the node pulls a server's docker image before creating its container.
+pub const PULL_POLICIES: [&str; 3] = ["always", "if_not_present", "never"];
+pub const DEFAULT_PULL_POLICY: &str = "if_not_present";
+
+/// A submitted pull policy if it is one of `PULL_POLICIES`, else the default.
+pub fn pull_policy_or_default(policy: Option<&str>) -> &'static str {
+    policy
+        .and_then(|p| PULL_POLICIES.iter().find(|known| **known == p.trim()))
+        .copied()
+        .unwrap_or(DEFAULT_PULL_POLICY)
+}
+
+/// A console button on the server page. `command` may contain `{{name}}` placeholders that
+/// are asked for when the button is clicked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsoleMacro {
+    pub label: String,
+    pub command: String,
+}
+
+/// Most macros one image may define
+pub const MAX_CONSOLE_MACROS: usize = 32;
+/// Longest console line the panel sends to a node
+pub const MAX_CONSOLE_COMMAND_LEN: usize = 1024;
+
+/// Macros from an image form, an egg or the database: an array of `{ label, command }` or
+/// an object mapping label to command. Entries without a label or with a multi-line
+/// command are dropped.
+pub fn parse_console_macros(value: &serde_json::Value) -> Vec<ConsoleMacro> {
+    let pairs: Vec<(String, String)> = match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| {
+                let label = item.get("label")?.as_str()?;
+                let command = item.get("command")?.as_str()?;
+                Some((label.to_string(), command.to_string()))
+            })
+            .collect(),
+        serde_json::Value::Object(map) => map
+            .iter()
+            .filter_map(|(label, command)| Some((label.clone(), command.as_str()?.to_string())))
+            .collect(),
+        // Stringified JSON, as some egg exporters write nested fields
+        serde_json::Value::String(s) => {
+            return serde_json::from_str(s)
+                .map(|v| parse_console_macros(&v))
+                .unwrap_or_default();
+        }
+        _ => Vec::new(),
+    };
+
+    pairs
+        .into_iter()
+        .filter_map(|(label, command)| {
+            let label = label.trim();
+            let command = command.trim();
+            let valid = !label.is_empty()
+                && !command.is_empty()
+                && !command.contains(['\r', '\n'])
+                && command.len() <= MAX_CONSOLE_COMMAND_LEN;
+            valid.then(|| ConsoleMacro {
+                label: label.chars().take(64).collect(),
+                command: command.to_string(),
+            })
+        })
+        .take(MAX_CONSOLE_MACROS)
+        .collect()
+}
+
+/// Normalised JSON for the `console_macros` column; anything unparsable stores no macros.
+pub fn console_macros_json(raw: &str) -> String {
+    let macros = serde_json::from_str(raw)
+        .map(|v| parse_console_macros(&v))
+        .unwrap_or_default();
+    serde_json::to_string_pretty(&macros).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// How the node runs a server's startup command: through `/bin/sh -c`, split into argv
+/// without a shell, or not at all (the docker image's own entrypoint).
+pub const COMMAND_MODES: [&str; 3] = ["shell", "argv", "entrypoint"];
+pub const DEFAULT_COMMAND_MODE: &str = "shell";
+
+/// A submitted command mode if it is one of `COMMAND_MODES`, else the default.
+pub fn command_mode_or_default(mode: Option<&str>) -> &'static str {
+    mode.and_then(|m| COMMAND_MODES.iter().find(|known| **known == m.trim()))
+        .copied()
+        .unwrap_or(DEFAULT_COMMAND_MODE)
+}
+
+/// `ENFORCE_IMAGE_DOCKER_IMAGES=true` rejects docker images outside the image's allowed set.
+pub fn enforce_image_docker_images() -> bool {
+    std::env::var("ENFORCE_IMAGE_DOCKER_IMAGES")
+        .map(|v| v == "true")
+        .unwrap_or(false)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,8 +230,124 @@ pub struct Variable {
     pub default_value: String,
     pub user_viewable: bool,
     pub user_editable: bool,
+    #[serde(default)]
     pub rules: String,
-    pub field_type: String, // text, boolean, etc.
+    /// `text` or `boolean`; see [`Variable::input`] for how the form renders it
+    #[serde(default)]
+    pub field_type: String,
+    /// Value is stored encrypted per server and never rendered back
+    #[serde(default)]
+    pub is_secret: bool,
+}
+
+/// The form control a variable gets on the create form.
+#[derive(Debug, PartialEq)]
+pub enum VariableInput {
+    /// Checkbox submitting `1` or `0`
+    Boolean,
+    /// Options from an `in:a,b,c` rule
+    Select(Vec<String>),
+    /// `integer` or `numeric` rules, bounded by `between:`, `min:` and `max:`
+    Number {
+        integer: bool,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Text,
+}
+
+impl Variable {
+    /// Egg rules in Laravel's `required|string|in:a,b` format.
+    fn rule_list(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .split('|')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+    }
+
+    fn rule_arg(&self, name: &str) -> Option<&str> {
+        self.rule_list()
+            .find_map(|r| r.strip_prefix(name)?.strip_prefix(':'))
+    }
+
+    pub fn required(&self) -> bool {
+        self.rule_list().any(|r| r == "required")
+    }
+
+    pub fn input(&self) -> VariableInput {
+        if self.field_type == "boolean" {
+            return VariableInput::Boolean;
+        }
+        if let Some(options) = self.rule_arg("in") {
+            return VariableInput::Select(
+                options.split(',').map(|o| o.trim().to_string()).collect(),
+            );
+        }
+        let integer = self.rule_list().any(|r| r == "integer");
+        if integer || self.rule_list().any(|r| r == "numeric") {
+            let bound = |arg: Option<&str>| arg.and_then(|a| a.trim().parse::<f64>().ok());
+            let (mut min, mut max) = (bound(self.rule_arg("min")), bound(self.rule_arg("max")));
+            if let Some((lo, hi)) = self.rule_arg("between").and_then(|a| a.split_once(',')) {
+                min = bound(Some(lo)).or(min);
+                max = bound(Some(hi)).or(max);
+            }
+            return VariableInput::Number { integer, min, max };
+        }
+        VariableInput::Text
+    }
+
+    /// Checks a submitted value against what [`Variable::input`] lets the form send.
+    /// The message names the variable, for the create form's error banner.
+    pub fn check_value(&self, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return match self.input() {
+                VariableInput::Boolean => {
+                    Err(format!("{} must be checked or unchecked.", self.name))
+                }
+                _ if self.required() => Err(format!("{} is required.", self.name)),
+                _ => Ok(()),
+            };
+        }
+        match self.input() {
+            VariableInput::Boolean if value != "1" && value != "0" => {
+                Err(format!("{} must be 1 or 0.", self.name))
+            }
+            VariableInput::Select(options) if !options.iter().any(|o| o == value) => Err(format!(
+                "{} must be one of: {}.",
+                self.name,
+                options.join(", ")
+            )),
+            VariableInput::Number { integer, min, max } => {
+                let number = if integer {
+                    value.parse::<i64>().ok().map(|n| n as f64)
+                } else {
+                    value.parse::<f64>().ok().filter(|n| n.is_finite())
+                };
+                let Some(number) = number else {
+                    let kind = if integer {
+                        "a whole number"
+                    } else {
+                        "a number"
+                    };
+                    return Err(format!("{} must be {}.", self.name, kind));
+                };
+                match (min, max) {
+                    (Some(min), Some(max)) if number < min || number > max => Err(format!(
+                        "{} must be between {} and {}.",
+                        self.name, min, max
+                    )),
+                    (Some(min), _) if number < min => {
+                        Err(format!("{} must be at least {}.", self.name, min))
+                    }
+                    (_, Some(max)) if number > max => {
+                        Err(format!("{} must be at most {}.", self.name, max))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -68,12 +357,34 @@ pub struct Allocation {
     pub ip: String,
     pub port: i32,
     pub server_id: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub auto_created: bool,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub notes: String,
+    /// Skipped by auto-assignment; can still be picked by hand
+    #[sqlx(default)]
+    #[serde(default)]
+    pub reserved: bool,
 }
 
 #[derive(Deserialize)]
 pub struct CreateAllocationRequest {
     pub ip: String,
     pub ports: String,
+    /// Applied to every allocation created by this request
+    #[serde(default)]
+    pub notes: String,
+}
+
+/// Inline edit of one allocation's note and reserved flag.
+#[derive(Deserialize)]
+pub struct UpdateAllocationRequest {
+    #[serde(default)]
+    pub notes: String,
+    /// Checkbox: present only when ticked
+    pub reserved: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -81,6 +392,8 @@ pub struct DeleteAllocationRequest {
     pub ports: String,
     #[serde(default)]
     pub force: bool,
+    /// From a dry run; the delete is refused if the affected set changed since
+    pub token: Option<String>,
 }
 
 #[derive(sqlx::FromRow, serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -89,16 +402,45 @@ pub struct User {
     pub username: String,
     pub email: String,
     pub password_hash: String,
-    pub role: String,        // "admin", "user"
+    pub role: String,                // "admin", "user", "auditor"
     pub permissions: Option<String>, // JSON or comma-separated
     pub created_at: DateTime<Utc>,
 }
 
+/// Lets a user send console commands (`POST /servers/{id}/command`); viewing the console
+/// doesn't need it.
+pub const PERMISSION_SERVER_COMMAND: &str = "server.command";
+
+/// Sees every page (overview, nodes, servers, logs, event history) but can't change
+/// anything; `auth_middleware` rejects its mutating requests.
+pub const ROLE_AUDITOR: &str = "auditor";
+
 impl User {
-    #[allow(dead_code)]
     pub fn is_admin(&self) -> bool {
         self.role == "admin"
     }
+
+    pub fn is_auditor(&self) -> bool {
+        self.role == ROLE_AUDITOR
+    }
+
+    /// Admins can do everything and auditors nothing; other users need `permission` in
+    /// their `permissions` (a JSON array or a comma-separated list).
+    pub fn can(&self, permission: &str) -> bool {
+        if self.is_admin() {
+            return true;
+        }
+        if self.is_auditor() {
+            return false;
+        }
+        let Some(granted) = self.permissions.as_deref() else {
+            return false;
+        };
+        match serde_json::from_str::<Vec<String>>(granted) {
+            Ok(list) => list.iter().any(|p| p == permission),
+            Err(_) => granted.split(',').any(|p| p.trim() == permission),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -107,7 +449,9 @@ pub struct Node {
     pub name: String,
     pub ip: String,
     pub port: i32,
+    /// Only loaded where the panel talks to the node; never written to a cache
     #[sqlx(default)]
+    #[serde(skip_serializing, default)]
     pub token: String,
     #[sqlx(default)]
     pub sftp_port: i32,
@@ -119,6 +463,23 @@ pub struct Node {
     pub cpu_limit: i32,
     #[sqlx(default)]
     pub version: String,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub auto_allocation_range: String,
+    /// Empty only for rows loaded without the column; see `services::locations`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub location_id: String,
+}
+
+/// A group of nodes that server placement can be scoped to.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Location {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    #[sqlx(default)]
+    pub node_count: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -148,6 +509,71 @@ pub struct Server {
     pub cpu_pinning: Option<String>,
     pub status: String, // installing, running, stopped, etc.
     pub created_at: DateTime<Utc>,
+    #[sqlx(default)]
+    pub install_error: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Set by the janitor when the server is stuck installing
+    #[sqlx(default)]
+    pub flagged_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub flag_reason: Option<String>,
+    /// Config changed in a way Docker can't apply live (image, startup, env)
+    #[sqlx(default)]
+    pub needs_recreate: bool,
+    /// Exposes `/public/servers/{id}/status` without authentication
+    #[sqlx(default)]
+    #[serde(default)]
+    pub public_status: bool,
+    /// Seconds stops, restarts and deletes wait before killing the container;
+    /// copied from the image on create, overridable per server
+    #[sqlx(default)]
+    #[serde(default)]
+    pub stop_timeout_seconds: i32,
+    /// `always`, `if_not_present` or `never`; copied from the image on create
+    #[sqlx(default)]
+    #[serde(default)]
+    pub pull_policy: String,
+    /// Monthly egress cap in GB; 0 = none
+    #[sqlx(default)]
+    #[serde(default)]
+    pub bandwidth_limit: i32,
+    /// `event` or `suspend`: what exceeding `bandwidth_limit` does
+    #[sqlx(default)]
+    #[serde(default)]
+    pub bandwidth_action: String,
+    /// Set while starts and restarts are refused, e.g. after a bandwidth cap
+    #[sqlx(default)]
+    pub suspended_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub suspend_reason: Option<String>,
+    /// Start the container once its create (and install) finishes
+    #[sqlx(default)]
+    #[serde(default)]
+    pub start_on_install: bool,
+}
+
+/// What exceeding a server's monthly `bandwidth_limit` does
+pub const BANDWIDTH_ACTIONS: [&str; 2] = ["event", "suspend"];
+
+impl Server {
+    /// `grace` to send the node for stops, restarts and deletes.
+    pub fn stop_grace(&self) -> u64 {
+        self.stop_timeout_seconds.clamp(1, MAX_STOP_TIMEOUT) as u64
+    }
+}
+
+/// Normalizes a comma-separated tag input: trimmed, lowercased, deduplicated, max 32 chars each.
+pub fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(',') {
+        let tag: String = tag.trim().to_lowercase().chars().take(32).collect();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
 }
 
 #[derive(Deserialize)]
@@ -157,11 +583,17 @@ pub struct CreateServerRequest {
     pub description: Option<String>,
     pub owner_id: Option<String>,
     pub start_on_install: Option<String>, // Checkbox sends "on" or nothing
+    /// Create anyway when the node isn't heartbeating; the install job runs (or fails) later
+    pub allow_offline: Option<String>,
 
     // Allocations
+    /// Scopes auto-selected nodes and allocations; empty means any location
+    pub location_id: Option<String>,
     pub node_id: Option<String>,
     pub default_allocation: Option<String>, // Allocation ID
     pub additional_ports: Option<String>,
+    /// Holder of the form's allocation reservation
+    pub reservation_token: Option<String>,
 
     // Feature Limits
     pub backup_limit: Option<i32>,
@@ -190,6 +622,225 @@ pub struct CreateServerRequest {
     // or use a wrapper. For now, let's assume we handle them dynamically or add a field if needed.
 }
 
+/// Reusable server configuration that pre-fills the create form, taken from an existing
+/// server (clone) or a saved template. Config only: never an allocation, volume or secret.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerPreset {
+    pub runtime_id: String,
+    pub image_id: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub cpu_limit: i32,
+    pub ram_limit: i32,
+    pub disk_limit: i32,
+    pub swap_limit: i32,
+    pub backup_limit: i32,
+    pub io_weight: i32,
+    pub oom_killer: bool,
+    #[serde(default)]
+    pub cpu_pinning: Option<String>,
+    pub docker_image: String,
+    pub startup_command: String,
+    /// Non-secret variable values by env name
+    #[serde(default)]
+    pub variables: std::collections::HashMap<String, String>,
+}
+
+/// Named preset admins can pick on the create page.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServerTemplate {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /containers` on the node agent (mirrors the node's model).
+#[derive(Debug, Clone, Serialize)]
+pub struct CreateContainerRequest {
+    pub uuid: String,
+    pub image: String,
+    pub startup_command: String,
+    pub environment: std::collections::HashMap<String, String>,
+    pub memory_limit: i64,
+    pub swap_limit: i64,
+    pub cpu_limit: i64,
+    pub io_weight: u16,
+    /// Left out entirely for servers whose image does not require a port
+    #[serde(skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub ports: std::collections::HashMap<String, String>, // "8080/tcp" -> "8080"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_as_user: Option<String>,
+    pub no_new_privileges: bool,
+    /// The image's egg `config_files` rules, rewritten by the node before the container starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_files: Option<serde_json::Value>,
+    /// Docker's own stop timeout for the container, used by stops outside the panel
+    pub stop_timeout: u64,
+    /// Whether the node pulls `image` first: `always`, `if_not_present` or `never`
+    pub pull_policy: String,
+    /// `shell`, `argv` or `entrypoint` (see `COMMAND_MODES`)
+    pub command_mode: String,
+    /// Mount the data volume the server's install script filled
+    pub data_volume: bool,
+    /// Console lines that mean the server finished starting (egg `config.startup.done`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub startup_done: Vec<String>,
+    /// Start the container after creating it; off leaves it stopped
+    pub start: bool,
+}
+
+/// Body of `POST /containers/{uuid}/limits` on the node agent (live limit update).
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateLimitsRequest {
+    pub memory_limit: i64,
+    pub swap_limit: i64,
+    pub cpu_limit: i64,
+    pub io_weight: u16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateLimitsResponse {
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Power action for `POST /api/servers/{id}/power`, forwarded to the node as is.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerAction {
+    Start,
+    Stop,
+    Restart,
+    Kill,
+}
+
+impl PowerAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PowerAction::Start => "start",
+            PowerAction::Stop => "stop",
+            PowerAction::Restart => "restart",
+            PowerAction::Kill => "kill",
+        }
+    }
+
+    /// Whether a container in Docker state `state` has finished this action.
+    pub fn reached(self, state: &str) -> bool {
+        match self {
+            PowerAction::Start | PowerAction::Restart => state == "running",
+            PowerAction::Stop | PowerAction::Kill => matches!(state, "exited" | "dead" | "created"),
+        }
+    }
+}
+
+/// Body of `POST /containers/{uuid}/power` on the node agent, and of the panel's power API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerRequest {
+    pub action: PowerAction,
+    /// Seconds `stop`/`restart` give the server before it is killed; the panel's power API
+    /// fills in the server's stop timeout when the caller leaves it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grace: Option<u64>,
+}
+
+/// Body of `POST /install-test` and `POST /containers/{uuid}/install` on the node agent.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallTestRequest {
+    pub container: String,
+    pub entrypoint: String,
+    pub script: String,
+    pub environment: std::collections::HashMap<String, String>,
+    /// MB; the node's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<i64>,
+}
+
+/// One line of the node's install NDJSON stream, relayed to the browser as SSE.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstallTestEvent {
+    Output { data: String },
+    Exit { code: i64, timed_out: bool },
+    Error { message: String },
+}
+
+impl InstallTestEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InstallTestEvent::Output { .. } => "output",
+            InstallTestEvent::Exit { .. } => "exit",
+            InstallTestEvent::Error { .. } => "error",
+        }
+    }
+}
+
+/// Row of the `jobs` table as exposed by `GET /api/jobs/{id}` (payload excluded).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    /// What the job acts on, e.g. a server id
+    pub target: String,
+    /// queued, running, succeeded or failed
+    pub status: String,
+    /// 0-100
+    pub progress: i32,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        self.status == "succeeded" || self.status == "failed"
+    }
+}
+
+/// Agent version change, shown on the node edit page.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct NodeVersionChange {
+    pub from_version: Option<String>,
+    pub to_version: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Latest self-update state of a node, shown on its edit page.
+#[derive(Debug, Clone, FromRow)]
+pub struct NodeUpdateStatus {
+    pub state: String,
+    pub message: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Node-level audit trail entry (maintenance runs), shown on the node's edit page.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct NodeEvent {
+    pub kind: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A node's maintenance window settings and state, see `services::maintenance`.
+#[derive(Debug, Clone, Default, FromRow)]
+pub struct NodeMaintenance {
+    pub window: String,
+    pub enabled: bool,
+    pub update: bool,
+    pub reboot: bool,
+    pub last_run: Option<DateTime<Utc>>,
+    /// Why the last run gave up, naming the servers it left stopped
+    pub alert: Option<String>,
+}
+
+/// Audit trail entry shown on the server's manage page.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServerEvent {
+    pub kind: String,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Deserialize)]
 pub struct CreateNodeRequest {
     pub name: String,
@@ -205,12 +856,17 @@ pub struct CreateNodeRequest {
     pub cpu_limit: Option<i32>,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub allocation_ports: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub location_id: Option<Uuid>,
 }
 
 fn default_sftp_port() -> i32 {
     2022
 }
 
+/// One disk entry from a heartbeat. Current agents send one per mounted filesystem with the
+/// raw device in `name`; older ones sent disks already grouped and named, with an empty
+/// `mount_point`. `services::disks` turns either into what the nodes page shows.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskDetail {
     pub name: String,
@@ -219,6 +875,9 @@ pub struct DiskDetail {
     pub available_space: u64,
     pub is_removable: bool,
     pub type_: String,
+    /// Empty from agents that predate the field
+    #[serde(default)]
+    pub file_system: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -236,6 +895,12 @@ pub struct HeartbeatPayload {
     pub version: String,
     #[serde(default)]
     pub timestamp: i64,
+    /// Seconds between heartbeats; 0 from agents that predate the field
+    #[serde(default)]
+    pub heartbeat_interval: u64,
+    /// Logical cores; 0 from agents that predate the field
+    #[serde(default)]
+    pub cpu_cores: u32,
     #[serde(default)]
     pub disk_read: u64,
     #[serde(default)]
@@ -246,6 +911,192 @@ pub struct HeartbeatPayload {
     pub net_tx: u64,
     #[serde(default)]
     pub disks: Vec<DiskDetail>,
+    /// Managed containers; `None` from agents that predate the field or couldn't list Docker
+    #[serde(default)]
+    pub containers: Option<Vec<ContainerState>>,
+    /// Self-update progress; only present when it changed. Not kept with cached stats.
+    #[serde(default, skip_serializing)]
+    pub update: Option<NodeUpdateReport>,
+}
+
+/// Body of `POST /nodes/{id}/lifecycle`.
+#[derive(Debug, Deserialize)]
+pub struct NodeLifecycleEvent {
+    /// `starting` or `stopping`
+    pub event: String,
+    /// Sent with `stopping` when the agent restarts into an update
+    #[serde(default)]
+    pub update: Option<NodeUpdateReport>,
+}
+
+/// Self-update progress reported by the agent.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeUpdateReport {
+    /// `updating`, `completed` or `failed`
+    pub state: String,
+    pub version: String,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// One managed container from a heartbeat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerState {
+    pub server_id: String,
+    /// Docker state: `running`, `exited`, `restarting`, ...
+    pub state: String,
+    /// Unix seconds the container last started; 0 unless running
+    #[serde(default)]
+    pub started_at: i64,
+    /// Bytes received and sent since the agent started (heartbeats only; see services::bandwidth)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_bytes: Option<u64>,
+    /// Whether a running container has printed its image's startup-done marker since it
+    /// started; `None` when the image has no marker or the agent doesn't track it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>,
+}
+
+impl ContainerState {
+    /// Running, but its image's startup-done marker hasn't shown up yet
+    pub fn is_starting(&self) -> bool {
+        self.state == "restarting" || (self.state == "running" && self.ready == Some(false))
+    }
+}
+
+/// Heartbeat interval assumed for agents that don't report one
+pub const DEFAULT_HEARTBEAT_INTERVAL: u64 = 5;
+/// Intervals above this make online detection sluggish; the panel warns about them
+pub const MAX_SANE_HEARTBEAT_INTERVAL: u64 = 60;
+/// Missed heartbeats before a node is considered offline
+const HEARTBEAT_GRACE_MULTIPLIER: u64 = 3;
+
+impl HeartbeatPayload {
+    pub fn interval_secs(&self) -> u64 {
+        if self.heartbeat_interval == 0 {
+            DEFAULT_HEARTBEAT_INTERVAL
+        } else {
+            self.heartbeat_interval
+        }
+    }
+
+    /// How long these stats stay valid: the Redis TTL and the in-memory freshness window.
+    pub fn stale_after_secs(&self) -> u64 {
+        self.interval_secs() * HEARTBEAT_GRACE_MULTIPLIER
+    }
+
+    /// `now_ms` and `timestamp` are both Unix milliseconds.
+    pub fn is_fresh(&self, now_ms: i64) -> bool {
+        now_ms - self.timestamp < (self.stale_after_secs() * 1000) as i64
+    }
+}
+
+/// Effective node agent config from `GET /config` (token is redacted by the node)
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeAgentConfig {
+    pub node_id: String,
+    pub panel_url: String,
+    pub port: u16,
+    pub ram_limit: u64,
+    pub disk_limit: u64,
+    #[serde(default)]
+    pub legacy_auth_error: bool,
+    #[serde(default)]
+    pub max_concurrent_creates: usize,
+    #[serde(default)]
+    pub heartbeat_interval: u64,
+    /// `reboot_command` is set, so maintenance windows can reboot the host
+    #[serde(default)]
+    pub reboot_enabled: bool,
+    pub version: String,
+    pub source: String,
+}
+
+/// Docker usage from the node's `GET /docker-summary`. Sizes are bytes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DockerSummary {
+    pub containers_total: u64,
+    pub containers_running: u64,
+    pub containers_stopped: u64,
+    pub containers_managed: u64,
+    pub containers_unmanaged: u64,
+    pub images: u64,
+    pub images_size: i64,
+    pub volumes: u64,
+    pub volumes_size: i64,
+    pub build_cache_size: i64,
+    pub reclaimable: i64,
+}
+
+/// Human readable byte size, e.g. `1.5 GB`. Takes a reference because templates pass one.
+pub fn format_bytes(bytes: &i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let bytes = *bytes;
+    let mut value = bytes.max(0) as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes.max(0))
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Error body returned by the node agent's REST API (see node/API.md)
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeErrorResponse {
+    pub error: String,
+    pub code: String,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub holders: Vec<PortHolder>,
+}
+
+/// A managed container the node found publishing a port a create asked for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortHolder {
+    pub port: u16,
+    pub container: String,
+    /// The `yunexal.server_id` label; may name a server the panel has since deleted
+    pub server_id: String,
+    #[serde(default)]
+    pub state: String,
+}
+
+/// The node's `GET /free-ports` answer
+#[derive(Debug, Clone, Deserialize)]
+pub struct FreePorts {
+    pub free: Vec<u16>,
+}
+
+/// The node's `GET /servers/{uuid}/stored-logs` answer: the end of a server's output kept on disk
+#[derive(Debug, Clone, Deserialize)]
+pub struct StoredLogs {
+    pub logs: String,
+    /// Bytes stored for the server across all files
+    pub size: i64,
+}
+
+/// A host port a managed container is set up to publish, from the node's `GET /port-bindings`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerPortBinding {
+    pub host_port: u16,
+    #[serde(default)]
+    pub host_ip: String,
+    #[serde(default)]
+    pub container_port: String,
+    pub container: String,
+    /// The `yunexal.server_id` label; may name a server the panel has since deleted
+    #[serde(default)]
+    pub server_id: String,
+    #[serde(default)]
+    pub state: String,
 }
 
 #[derive(Deserialize)]
@@ -261,9 +1112,13 @@ pub struct UpdateNodeRequest {
     pub disk_limit: Option<i32>,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub cpu_limit: Option<i32>,
+    #[serde(default)]
+    pub auto_allocation_range: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub location_id: Option<Uuid>,
 }
 
-fn empty_string_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+pub(crate) fn empty_string_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
 where
     D: Deserializer<'de>,
     T: FromStr,
@@ -276,13 +1131,18 @@ where
     }
 }
 
-
+/// Edit form for a server. Every field is optional: fields missing from the form, and
+/// empty numeric or required-text fields, leave the stored value alone.
 #[derive(Deserialize)]
 pub struct UpdateServerRequest {
-    pub name: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub name: Option<String>,
+    /// Present but empty clears the description
     pub description: Option<String>,
+    /// Only applied when an admin picks a different owner
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     pub owner_id: Option<String>,
-    
+
     // Limits
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub cpu_limit: Option<i32>,
@@ -299,11 +1159,86 @@ pub struct UpdateServerRequest {
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub io_weight: Option<i32>,
     pub oom_killer: Option<String>, // "on"
-    pub docker_image: String,
-    pub startup_command: String,
+    /// Hidden marker sent next to the `oom_killer` checkbox, since an unchecked box sends nothing
+    pub oom_killer_present: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub docker_image: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub startup_command: Option<String>,
+    pub tags: Option<String>, // comma separated
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub stop_timeout_seconds: Option<i32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub pull_policy: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub bandwidth_limit: Option<i32>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub bandwidth_action: Option<String>,
+}
+
+impl UpdateServerRequest {
+    /// `None` when the form didn't include the OOM killer checkbox at all.
+    pub fn oom_killer(&self) -> Option<bool> {
+        if self.oom_killer.is_some() {
+            Some(true)
+        } else {
+            self.oom_killer_present.as_ref().map(|_| false)
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct DeleteServerRequest {
     pub force: Option<String>,
+    /// Also wipe the server's data volume on the node
+    pub purge: Option<String>,
+    /// The server's name, typed out to confirm a purge
+    pub purge_confirm: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_trimmed_lowercased_and_deduplicated() {
+        assert_eq!(parse_tags(" Prod, eu ,,PROD, "), ["prod", "eu"]);
+        assert!(parse_tags("").is_empty());
+        assert_eq!(parse_tags(&"x".repeat(40))[0].len(), 32);
+    }
+
+    #[test]
+    fn docker_images_parse_from_every_form_format() {
+        let both = ["ghcr.io/a:1", "ghcr.io/b:2"];
+        assert_eq!(
+            parse_docker_images(r#"{"Java 1": "ghcr.io/a:1", "Java 2": " ghcr.io/b:2 "}"#),
+            both
+        );
+        assert_eq!(
+            parse_docker_images(r#"["ghcr.io/a:1", "ghcr.io/b:2"]"#),
+            both
+        );
+        assert_eq!(parse_docker_images("ghcr.io/a:1\r\n ghcr.io/b:2,"), both);
+        assert_eq!(parse_docker_images("ghcr.io/a:1, ghcr.io/b:2"), both);
+        assert!(parse_docker_images("").is_empty());
+    }
+
+    #[test]
+    fn power_actions_reach_running_or_a_stopped_state() {
+        assert!(PowerAction::Restart.reached("running"));
+        assert!(!PowerAction::Start.reached("restarting"));
+        for stopped in ["exited", "dead", "created"] {
+            assert!(PowerAction::Kill.reached(stopped));
+        }
+        assert!(!PowerAction::Stop.reached("running"));
+    }
+
+    #[test]
+    fn byte_sizes_use_one_decimal_above_a_kilobyte() {
+        assert_eq!(format_bytes(&-5), "0 B");
+        assert_eq!(format_bytes(&1023), "1023 B");
+        assert_eq!(format_bytes(&1536), "1.5 KB");
+        assert_eq!(format_bytes(&(3 * 1024 * 1024 * 1024)), "3.0 GB");
+        assert_eq!(format_bytes(&(2048 * 1024_i64.pow(4))), "2048.0 TB");
+    }
 }
@@ -0,0 +1,193 @@
+use crate::models::Allocation;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Parses a node's `auto_allocation_range` ("30000-31000").
+/// Returns None for empty or malformed ranges and ranges touching system ports.
+pub fn parse_port_range(input: &str) -> Option<(i32, i32)> {
+    let (start, end) = input.trim().split_once('-')?;
+    let start = start.trim().parse::<i32>().ok()?;
+    let end = end.trim().parse::<i32>().ok()?;
+
+    if start > end || start <= 1023 || end > 65535 {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// A node that can mint allocations from its configured range.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AutoAllocationNode {
+    pub id: String,
+    pub ip: String,
+    pub port: i32,
+    pub sftp_port: i32,
+    pub auto_allocation_range: String,
+}
+
+/// Creates the next unused allocation in the node's range inside `tx` and returns its id.
+/// Skips ports already allocated on the node, and the daemon/SFTP ports and allocations of
+/// every node sharing its IP (itself included).
+pub async fn mint_allocation(
+    tx: &mut Transaction<'_, Postgres>,
+    node: &AutoAllocationNode,
+) -> Result<Option<String>, sqlx::Error> {
+    let Some((start, end)) = parse_port_range(&node.auto_allocation_range) else {
+        return Ok(None);
+    };
+
+    // Serialize concurrent mints for the same node
+    sqlx::query("SELECT id FROM nodes WHERE id = $1::uuid FOR UPDATE")
+        .bind(&node.id)
+        .execute(&mut **tx)
+        .await?;
+
+    let Some(port) = next_free_port(tx, node, start, end).await? else {
+        return Ok(None);
+    };
+
+    let id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO allocations (id, node_id, ip, port, auto_created) VALUES ($1::uuid, $2::uuid, $3, $4, TRUE)",
+    )
+    .bind(&id)
+    .bind(&node.id)
+    .bind(&node.ip)
+    .bind(port)
+    .execute(&mut **tx)
+    .await?;
+
+    tracing::info!(
+        "Auto-created allocation {}:{} on node {} from range {}",
+        node.ip,
+        port,
+        node.id,
+        node.auto_allocation_range
+    );
+
+    Ok(Some(id))
+}
+
+/// Whether `mint_allocation` would find a port for `node` right now. A concurrent mint can
+/// still take the last one first.
+pub async fn has_free_port(db: &PgPool, node: &AutoAllocationNode) -> Result<bool, sqlx::Error> {
+    let Some((start, end)) = parse_port_range(&node.auto_allocation_range) else {
+        return Ok(false);
+    };
+    let mut con = db.acquire().await?;
+    Ok(next_free_port(&mut con, node, start, end).await?.is_some())
+}
+
+async fn next_free_port(
+    con: &mut PgConnection,
+    node: &AutoAllocationNode,
+    start: i32,
+    end: i32,
+) -> Result<Option<i32>, sqlx::Error> {
+    let taken: Vec<i32> = sqlx::query_scalar(
+        "SELECT port FROM allocations WHERE (node_id = $1::uuid OR ip = $4) AND port BETWEEN $2 AND $3
+         UNION SELECT port FROM nodes WHERE ip = $4
+         UNION SELECT sftp_port FROM nodes WHERE ip = $4 AND sftp_port IS NOT NULL",
+    )
+    .bind(&node.id)
+    .bind(start)
+    .bind(end)
+    .bind(&node.ip)
+    .fetch_all(con)
+    .await?;
+
+    Ok((start..=end).find(|p| *p != node.port && *p != node.sftp_port && !taken.contains(p)))
+}
+
+/// Why an allocation change on an existing server was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AllocationChangeError {
+    /// Taken meanwhile, on another node, or gone
+    Unavailable,
+    /// The primary allocation stays until the server is deleted
+    Primary,
+    Db(String),
+}
+
+impl AllocationChangeError {
+    /// `?error=` code for the edit page
+    pub fn code(&self) -> &'static str {
+        match self {
+            AllocationChangeError::Unavailable => "allocation_unavailable",
+            AllocationChangeError::Primary => "primary_allocation",
+            AllocationChangeError::Db(_) => "db_error",
+        }
+    }
+}
+
+/// Assigns a free allocation on the server's own node. A server without a primary
+/// allocation (portless until now) gets this one as its primary.
+pub async fn assign_to_server(
+    db: &PgPool,
+    server_id: Uuid,
+    allocation_id: Uuid,
+) -> Result<Allocation, AllocationChangeError> {
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|e| AllocationChangeError::Db(e.to_string()))?;
+
+    let allocation = sqlx::query_as::<_, Allocation>(
+        r#"
+        UPDATE allocations a SET server_id = s.id
+        FROM servers s
+        WHERE a.id = $2 AND s.id = $1 AND a.node_id = s.node_id AND a.server_id IS NULL
+        RETURNING a.id::text, a.node_id::text, a.ip, a.port, a.server_id::text
+    "#,
+    )
+    .bind(server_id)
+    .bind(allocation_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| AllocationChangeError::Db(e.to_string()))?
+    .ok_or(AllocationChangeError::Unavailable)?;
+
+    sqlx::query("UPDATE servers SET allocation_id = $2 WHERE id = $1 AND allocation_id IS NULL")
+        .bind(server_id)
+        .bind(allocation_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AllocationChangeError::Db(e.to_string()))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| AllocationChangeError::Db(e.to_string()))?;
+    Ok(allocation)
+}
+
+/// Frees one of the server's additional allocations. The primary one is never released here.
+pub async fn release_from_server(
+    db: &PgPool,
+    server_id: Uuid,
+    allocation_id: Uuid,
+) -> Result<Allocation, AllocationChangeError> {
+    let primary: Option<Uuid> =
+        sqlx::query_scalar("SELECT allocation_id FROM servers WHERE id = $1")
+            .bind(server_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| AllocationChangeError::Db(e.to_string()))?
+            .flatten();
+    if primary == Some(allocation_id) {
+        return Err(AllocationChangeError::Primary);
+    }
+
+    sqlx::query_as::<_, Allocation>(
+        r#"
+        UPDATE allocations SET server_id = NULL
+        WHERE id = $2 AND server_id = $1
+        RETURNING id::text, node_id::text, ip, port, server_id::text
+    "#,
+    )
+    .bind(server_id)
+    .bind(allocation_id)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| AllocationChangeError::Db(e.to_string()))?
+    .ok_or(AllocationChangeError::Unavailable)
+}
@@ -0,0 +1,251 @@
+//! Monthly network accounting. Nodes report cumulative rx/tx per container in heartbeats;
+//! the panel turns them into deltas against the last counters it saw and adds those to the
+//! server's row for the current month (UTC). A counter below the previous one means the
+//! agent restarted, so the new value is all new traffic.
+
+use crate::models::{ContainerState, MAX_STOP_TIMEOUT, PowerAction, PowerRequest};
+use crate::services::{node_api, server_events};
+use crate::state::AppState;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Bytes per GB of `bandwidth_limit`
+const BYTES_PER_GB: i64 = 1024 * 1024 * 1024;
+
+/// How often the rollover job checks for a new month
+const ROLLOVER_INTERVAL: Duration = Duration::from_secs(300);
+
+/// First day of the current month, UTC
+const CURRENT_PERIOD: &str = "date_trunc('month', NOW() AT TIME ZONE 'UTC')::date";
+
+/// Runs off the heartbeat path. Counters are only applied when the heartbeat is newer than
+/// the one they were last taken from, so a slow, overlapping heartbeat can't count twice.
+pub async fn record(
+    state: AppState,
+    node_id: String,
+    timestamp: i64,
+    containers: Vec<ContainerState>,
+) {
+    for container in containers {
+        let (Some(rx), Some(tx)) = (container.rx_bytes, container.tx_bytes) else {
+            continue;
+        };
+        let Ok(server_id) = Uuid::parse_str(&container.server_id) else {
+            continue;
+        };
+
+        let row: Result<Option<(i64, i32, String, bool)>, sqlx::Error> = sqlx::query_as(&format!(
+            "WITH prev AS (
+                SELECT id, net_rx_counter, net_tx_counter FROM servers
+                WHERE id = $1 AND node_id = $2::uuid AND net_counter_at < $5
+                FOR UPDATE
+            ), counted AS (
+                UPDATE servers s SET net_rx_counter = $3, net_tx_counter = $4, net_counter_at = $5
+                FROM prev WHERE s.id = prev.id
+                RETURNING s.id, s.bandwidth_limit, s.bandwidth_action,
+                    CASE WHEN $3 >= prev.net_rx_counter THEN $3 - prev.net_rx_counter ELSE $3 END AS rx,
+                    CASE WHEN $4 >= prev.net_tx_counter THEN $4 - prev.net_tx_counter ELSE $4 END AS tx
+            ), usage AS (
+                INSERT INTO server_bandwidth (server_id, period, rx_bytes, tx_bytes)
+                SELECT id, {CURRENT_PERIOD}, rx, tx FROM counted
+                ON CONFLICT (server_id, period) DO UPDATE SET
+                    rx_bytes = server_bandwidth.rx_bytes + EXCLUDED.rx_bytes,
+                    tx_bytes = server_bandwidth.tx_bytes + EXCLUDED.tx_bytes
+                RETURNING server_id, tx_bytes, cap_exceeded_at IS NOT NULL AS exceeded
+            )
+            SELECT usage.tx_bytes, counted.bandwidth_limit, counted.bandwidth_action, usage.exceeded
+            FROM usage JOIN counted ON counted.id = usage.server_id"
+        ))
+        .bind(server_id)
+        .bind(&node_id)
+        .bind(rx as i64)
+        .bind(tx as i64)
+        .bind(timestamp)
+        .fetch_optional(&state.db)
+        .await;
+
+        let (tx_total, limit, action, exceeded) = match row {
+            Ok(Some(row)) => row,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::error!("Failed to record bandwidth for server {}: {}", server_id, e);
+                continue;
+            }
+        };
+
+        if limit > 0 && !exceeded && tx_total >= limit as i64 * BYTES_PER_GB {
+            cap_exceeded(&state, server_id, limit, &action).await;
+        }
+    }
+}
+
+/// Fires the server's cap action once per month.
+async fn cap_exceeded(state: &AppState, server_id: Uuid, limit: i32, action: &str) {
+    let marked = sqlx::query(&format!(
+        "UPDATE server_bandwidth SET cap_exceeded_at = NOW()
+         WHERE server_id = $1 AND period = {CURRENT_PERIOD} AND cap_exceeded_at IS NULL"
+    ))
+    .bind(server_id)
+    .execute(&state.db)
+    .await;
+    if !matches!(marked, Ok(ref r) if r.rows_affected() == 1) {
+        return;
+    }
+
+    if action != "suspend" {
+        server_events::record(
+            &state.db,
+            server_id,
+            "bandwidth",
+            &format!("Monthly bandwidth cap of {} GB exceeded", limit),
+        )
+        .await;
+        return;
+    }
+
+    let row: Option<(String, i32)> = sqlx::query_as(
+        "UPDATE servers SET suspended_at = NOW(), suspend_reason = 'bandwidth'
+         WHERE id = $1 AND suspended_at IS NULL
+         RETURNING node_id::text, stop_timeout_seconds",
+    )
+    .bind(server_id)
+    .fetch_optional(&state.db)
+    .await
+    .unwrap_or(None);
+    server_events::record(
+        &state.db,
+        server_id,
+        "bandwidth",
+        &format!(
+            "Monthly bandwidth cap of {} GB exceeded, server suspended until next month",
+            limit
+        ),
+    )
+    .await;
+
+    let Some((node_id, stop_timeout)) = row else {
+        return;
+    };
+    let Some(node) = state.get_node_with_token(&node_id).await else {
+        return;
+    };
+    let payload = PowerRequest {
+        action: PowerAction::Stop,
+        grace: Some(stop_timeout.clamp(1, MAX_STOP_TIMEOUT) as u64),
+    };
+    if let Err(e) = node_api::power_container(
+        &state.http_client,
+        &node,
+        &server_id.to_string(),
+        &payload,
+        &state.node_retry,
+    )
+    .await
+    {
+        tracing::error!(
+            "Failed to stop server {} after it exceeded its bandwidth cap: {}",
+            server_id,
+            e
+        );
+    }
+}
+
+/// This month's (rx, tx) bytes per server id.
+pub async fn current_usage(db: &sqlx::PgPool) -> std::collections::HashMap<String, (i64, i64)> {
+    sqlx::query_as::<_, (String, i64, i64)>(&format!(
+        "SELECT server_id::text, rx_bytes, tx_bytes FROM server_bandwidth WHERE period = {CURRENT_PERIOD}"
+    ))
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(id, rx, tx)| (id, (rx, tx)))
+    .collect()
+}
+
+/// This month's (rx, tx) bytes for one server.
+pub async fn server_usage(db: &sqlx::PgPool, server_id: Uuid) -> (i64, i64) {
+    sqlx::query_as::<_, (i64, i64)>(&format!(
+        "SELECT rx_bytes, tx_bytes FROM server_bandwidth WHERE server_id = $1 AND period = {CURRENT_PERIOD}"
+    ))
+    .bind(server_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+    .unwrap_or((0, 0))
+}
+
+/// After the cap or action was edited: re-arms the cap if this month's usage is under it
+/// again, and lifts a bandwidth suspension the new settings no longer call for.
+pub async fn cap_changed(db: &sqlx::PgPool, server_id: Uuid) {
+    let rearmed = sqlx::query(&format!(
+        "UPDATE server_bandwidth b SET cap_exceeded_at = NULL FROM servers s
+         WHERE b.server_id = s.id AND s.id = $1 AND b.period = {CURRENT_PERIOD} AND b.cap_exceeded_at IS NOT NULL
+           AND (s.bandwidth_limit = 0 OR b.tx_bytes < s.bandwidth_limit::bigint * $2)"
+    ))
+    .bind(server_id)
+    .bind(BYTES_PER_GB)
+    .execute(db)
+    .await;
+    if let Err(e) = rearmed {
+        tracing::error!(
+            "Failed to re-arm bandwidth cap for server {}: {}",
+            server_id,
+            e
+        );
+    }
+
+    let lifted = sqlx::query(&format!(
+        "UPDATE servers s SET suspended_at = NULL, suspend_reason = NULL
+         WHERE s.id = $1 AND s.suspend_reason = 'bandwidth'
+           AND (s.bandwidth_action <> 'suspend' OR NOT EXISTS (
+               SELECT 1 FROM server_bandwidth b
+               WHERE b.server_id = s.id AND b.period = {CURRENT_PERIOD} AND b.cap_exceeded_at IS NOT NULL
+           ))"
+    ))
+    .bind(server_id)
+    .execute(db)
+    .await;
+    if matches!(lifted, Ok(ref r) if r.rows_affected() == 1) {
+        server_events::record(
+            db,
+            server_id,
+            "bandwidth",
+            "Bandwidth cap changed, suspension lifted",
+        )
+        .await;
+    }
+}
+
+/// Lifts bandwidth suspensions from earlier months. Usage itself needs no reset: a new
+/// month simply starts a new `server_bandwidth` row.
+pub async fn run_rollover(state: AppState) {
+    loop {
+        let lifted: Vec<Uuid> = match sqlx::query_scalar(&format!(
+            "UPDATE servers SET suspended_at = NULL, suspend_reason = NULL
+             WHERE suspend_reason = 'bandwidth' AND (suspended_at AT TIME ZONE 'UTC')::date < {CURRENT_PERIOD}
+             RETURNING id"
+        ))
+        .fetch_all(&state.db)
+        .await
+        {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::error!("Bandwidth rollover failed: {}", e);
+                Vec::new()
+            }
+        };
+
+        for id in lifted {
+            server_events::record(
+                &state.db,
+                id,
+                "bandwidth",
+                "New billing month, bandwidth suspension lifted",
+            )
+            .await;
+        }
+
+        tokio::time::sleep(ROLLOVER_INTERVAL).await;
+    }
+}
@@ -0,0 +1,126 @@
+//! Dry runs for bulk deletes. Resolving what a delete would touch is shared between the
+//! preview and the real request, and the preview hands out a token (a hash of the affected
+//! ids) that the real request sends back: if the set changed in between, the delete is
+//! refused instead of removing something the operator never saw.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AffectedAllocation {
+    pub id: String,
+    pub ip: String,
+    pub port: i32,
+    pub server_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AffectedServer {
+    pub id: String,
+    pub name: String,
+}
+
+/// Everything a delete would remove or leave dangling.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletePreview {
+    /// Ports freed
+    pub allocations: Vec<AffectedAllocation>,
+    /// Servers deleted, or left without a port they were assigned
+    pub servers: Vec<AffectedServer>,
+    /// Containers removed from the node (node delete) or left running on a port the panel
+    /// no longer tracks (forced allocation delete)
+    pub containers: Vec<String>,
+    /// Send back with the real request to pin it to this set
+    pub token: String,
+}
+
+impl DeletePreview {
+    fn new(allocations: Vec<AffectedAllocation>, servers: Vec<AffectedServer>) -> Self {
+        let containers = servers
+            .iter()
+            .map(|s| format!("yunexal-{}", s.id))
+            .collect();
+        let mut ids: Vec<String> = allocations
+            .iter()
+            .map(|a| format!("allocation:{}", a.id))
+            .chain(servers.iter().map(|s| format!("server:{}", s.id)))
+            .collect();
+        ids.sort();
+        let token = Sha256::digest(ids.join(",").as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Self {
+            allocations,
+            servers,
+            containers,
+            token,
+        }
+    }
+
+    /// Whether a token from an earlier preview still describes this set. No token (an API
+    /// caller that skipped the preview) is accepted.
+    pub fn matches(&self, token: Option<&str>) -> bool {
+        token.is_none_or(|t| t.trim().is_empty() || t.trim() == self.token)
+    }
+
+    pub fn allocation_ids(&self) -> Vec<Uuid> {
+        self.allocations
+            .iter()
+            .filter_map(|a| a.id.parse().ok())
+            .collect()
+    }
+}
+
+/// What deleting `ports` on a node would remove. Without `force`, ports assigned to a
+/// server are skipped, exactly like the delete itself.
+pub async fn allocations(
+    db: &PgPool,
+    node_id: Uuid,
+    ports: &[i32],
+    force: bool,
+) -> Result<DeletePreview, sqlx::Error> {
+    let allocations: Vec<AffectedAllocation> = sqlx::query_as(
+        "SELECT id::text, ip, port, server_id::text FROM allocations
+         WHERE node_id = $1 AND port = ANY($2) AND ($3 OR server_id IS NULL)
+         ORDER BY port, ip",
+    )
+    .bind(node_id)
+    .bind(ports)
+    .bind(force)
+    .fetch_all(db)
+    .await?;
+
+    let server_ids: Vec<Uuid> = allocations
+        .iter()
+        .filter_map(|a| a.server_id.as_deref()?.parse().ok())
+        .collect();
+    let servers: Vec<AffectedServer> = if server_ids.is_empty() {
+        Vec::new()
+    } else {
+        sqlx::query_as("SELECT id::text, name FROM servers WHERE id = ANY($1) ORDER BY name")
+            .bind(&server_ids)
+            .fetch_all(db)
+            .await?
+    };
+    Ok(DeletePreview::new(allocations, servers))
+}
+
+/// What deleting a node would remove: its servers with their containers, and every
+/// allocation on it.
+pub async fn node(db: &PgPool, node_id: Uuid) -> Result<DeletePreview, sqlx::Error> {
+    let allocations: Vec<AffectedAllocation> = sqlx::query_as(
+        "SELECT id::text, ip, port, server_id::text FROM allocations WHERE node_id = $1 ORDER BY port, ip",
+    )
+    .bind(node_id)
+    .fetch_all(db)
+    .await?;
+    let servers: Vec<AffectedServer> =
+        sqlx::query_as("SELECT id::text, name FROM servers WHERE node_id = $1 ORDER BY name")
+            .bind(node_id)
+            .fetch_all(db)
+            .await?;
+    Ok(DeletePreview::new(allocations, servers))
+}
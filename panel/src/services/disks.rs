@@ -0,0 +1,196 @@
+//! Turns the filesystems a node reports into the disks the nodes page shows. Agents send
+//! raw facts (device, mount point, sizes); partitions are grouped under their physical
+//! device here and named either by an admin-set label (`node_disk_labels`) or as
+//! "System (Default)" for the disk holding `/` and "Disk N (sdb)" for the rest.
+
+use crate::models::DiskDetail;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use uuid::Uuid;
+
+pub const MAX_LABEL_LEN: usize = 64;
+const SYSTEM_LABEL: &str = "System (Default)";
+
+/// A physical disk with the filesystems mounted from it.
+#[derive(Debug, Clone)]
+pub struct DiskGroup {
+    /// Parent device, e.g. `/dev/sda`; the key labels are stored under
+    pub device: String,
+    /// The admin-set label, else `default_name`
+    pub name: String,
+    pub default_name: String,
+    pub total_space: u64,
+    pub available_space: u64,
+    pub is_removable: bool,
+    pub type_: String,
+    pub mounts: Vec<String>,
+}
+
+impl DiskGroup {
+    /// The label as set by an admin; empty when the generated name is used
+    pub fn label(&self) -> &str {
+        if self.name == self.default_name {
+            ""
+        } else {
+            &self.name
+        }
+    }
+
+    pub fn used_space(&self) -> u64 {
+        self.total_space.saturating_sub(self.available_space)
+    }
+}
+
+/// The disk a partition lives on: `/dev/sda1` -> `/dev/sda`, `/dev/nvme0n1p2` ->
+/// `/dev/nvme0n1`, `/dev/mmcblk0p1` -> `/dev/mmcblk0`. Anything else (LVM volumes, md
+/// arrays, network shares) is its own disk.
+pub fn parent_device(device: &str) -> &str {
+    let Some(base) = device.strip_prefix("/dev/") else {
+        return device;
+    };
+    if base.starts_with("nvme") || base.starts_with("mmcblk") {
+        let trimmed = device.trim_end_matches(|c: char| c.is_ascii_digit());
+        if let Some(disk) = trimmed.strip_suffix('p')
+            && disk.ends_with(|c: char| c.is_ascii_digit())
+        {
+            return disk;
+        }
+        return device;
+    }
+    if ["sd", "vd", "xvd", "hd"]
+        .iter()
+        .any(|p| base.starts_with(p))
+    {
+        return device.trim_end_matches(|c: char| c.is_ascii_digit());
+    }
+    device
+}
+
+/// Groups reported filesystems per physical disk. Sizes count each device once, so a
+/// device mounted several times (bind mounts, btrfs subvolumes) isn't added up twice.
+/// `labels` maps parent devices to admin-set names. Entries from older agents, which
+/// arrive grouped and named already, are passed through.
+pub fn group(disks: &[DiskDetail], labels: &HashMap<String, String>) -> Vec<DiskGroup> {
+    if disks.iter().all(|d| d.mount_point.is_empty()) {
+        return disks
+            .iter()
+            .map(|d| DiskGroup {
+                device: d.name.clone(),
+                name: d.name.clone(),
+                default_name: d.name.clone(),
+                total_space: d.total_space,
+                available_space: d.available_space,
+                is_removable: d.is_removable,
+                type_: d.type_.clone(),
+                mounts: Vec::new(),
+            })
+            .collect();
+    }
+
+    let mut groups: BTreeMap<&str, DiskGroup> = BTreeMap::new();
+    let mut counted = HashSet::new();
+    for disk in disks {
+        let device = parent_device(&disk.name);
+        let group = groups.entry(device).or_insert_with(|| DiskGroup {
+            device: device.to_string(),
+            name: String::new(),
+            default_name: String::new(),
+            total_space: 0,
+            available_space: 0,
+            is_removable: disk.is_removable,
+            type_: disk.type_.clone(),
+            mounts: Vec::new(),
+        });
+        if counted.insert(disk.name.as_str()) {
+            group.total_space += disk.total_space;
+            group.available_space += disk.available_space;
+        }
+        group.mounts.push(disk.mount_point.clone());
+    }
+
+    let mut system = Vec::new();
+    let mut others = Vec::new();
+    for group in groups.into_values() {
+        if group.mounts.iter().any(|m| m == "/") {
+            system.push(group);
+        } else {
+            others.push(group);
+        }
+    }
+    for group in &mut system {
+        group.default_name = SYSTEM_LABEL.to_string();
+    }
+    for (n, group) in others.iter_mut().enumerate() {
+        group.default_name = format!(
+            "Disk {} ({})",
+            n + 1,
+            group.device.trim_start_matches("/dev/")
+        );
+    }
+
+    let mut all: Vec<DiskGroup> = system.into_iter().chain(others).collect();
+    for group in &mut all {
+        group.name = labels
+            .get(&group.device)
+            .unwrap_or(&group.default_name)
+            .clone();
+    }
+    all
+}
+
+/// The node's disk labels, by parent device.
+pub async fn labels(db: &PgPool, node_id: Uuid) -> HashMap<String, String> {
+    sqlx::query_as::<_, (String, String)>(
+        "SELECT device, label FROM node_disk_labels WHERE node_id = $1",
+    )
+    .bind(node_id)
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .collect()
+}
+
+/// Every node's disk labels, by node id, for the nodes page.
+pub async fn all_labels(db: &PgPool) -> HashMap<String, HashMap<String, String>> {
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT node_id::text, device, label FROM node_disk_labels")
+            .fetch_all(db)
+            .await
+            .unwrap_or_default();
+    let mut labels: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (node_id, device, label) in rows {
+        labels.entry(node_id).or_default().insert(device, label);
+    }
+    labels
+}
+
+/// Stores labels by parent device; a blank label goes back to the generated name.
+pub async fn save_labels(
+    db: &PgPool,
+    node_id: Uuid,
+    labels: &HashMap<String, String>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+    for (device, label) in labels {
+        let label: String = label.trim().chars().take(MAX_LABEL_LEN).collect();
+        if label.is_empty() {
+            sqlx::query("DELETE FROM node_disk_labels WHERE node_id = $1 AND device = $2")
+                .bind(node_id)
+                .bind(device)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query(
+                "INSERT INTO node_disk_labels (node_id, device, label) VALUES ($1, $2, $3)
+                 ON CONFLICT (node_id, device) DO UPDATE SET label = EXCLUDED.label",
+            )
+            .bind(node_id)
+            .bind(device)
+            .bind(&label)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    tx.commit().await
+}
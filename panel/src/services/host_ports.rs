@@ -0,0 +1,95 @@
+//! Ports already spoken for on a host: node agents' daemon and SFTP ports and allocations
+//! of every node sharing the IP, plus the panel's own listen port. Small installs run the
+//! panel and a node on one machine, where a clash only shows up later as an obscure
+//! failure on the node.
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What already holds a port.
+#[derive(Debug, Clone)]
+pub enum PortClaim {
+    /// The panel's `LISTEN_PORT`. Only a clash when the node runs on the panel's host,
+    /// which the panel can't always tell, so callers mostly warn about it.
+    Panel,
+    NodeDaemon(String),
+    NodeSftp(String),
+    Allocation(String),
+}
+
+impl std::fmt::Display for PortClaim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortClaim::Panel => write!(f, "the panel's listen port"),
+            PortClaim::NodeDaemon(node) => write!(f, "the daemon port of node {}", node),
+            PortClaim::NodeSftp(node) => write!(f, "the SFTP port of node {}", node),
+            PortClaim::Allocation(node) => write!(f, "an allocation on node {}", node),
+        }
+    }
+}
+
+/// Node IPs that certainly are the panel's host.
+pub fn is_panel_host(ip: &str) -> bool {
+    ip == "localhost"
+        || ip
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Every claimed port on `ip`. `skip_node` is the node being created or edited: its own
+/// daemon and SFTP ports and allocations are left out, since it may keep those.
+pub async fn claims(
+    db: &sqlx::PgPool,
+    panel_port: u16,
+    ip: &str,
+    skip_node: Option<Uuid>,
+) -> Result<HashMap<i32, PortClaim>, sqlx::Error> {
+    let mut claims = HashMap::new();
+    claims.insert(panel_port as i32, PortClaim::Panel);
+
+    let allocations: Vec<(i32, String)> = sqlx::query_as(
+        "SELECT a.port, n.name FROM allocations a JOIN nodes n ON n.id = a.node_id
+         WHERE a.ip = $1 AND ($2::uuid IS NULL OR a.node_id <> $2)",
+    )
+    .bind(ip)
+    .bind(skip_node)
+    .fetch_all(db)
+    .await?;
+    for (port, node) in allocations {
+        claims.insert(port, PortClaim::Allocation(node));
+    }
+
+    // Agent ports last: they are what actually breaks when taken
+    let nodes: Vec<(String, i32, Option<i32>)> = sqlx::query_as(
+        "SELECT name, port, sftp_port FROM nodes WHERE ip = $1 AND ($2::uuid IS NULL OR id <> $2)",
+    )
+    .bind(ip)
+    .bind(skip_node)
+    .fetch_all(db)
+    .await?;
+    for (name, port, sftp_port) in nodes {
+        if let Some(sftp_port) = sftp_port {
+            claims.insert(sftp_port, PortClaim::NodeSftp(name.clone()));
+        }
+        claims.insert(port, PortClaim::NodeDaemon(name));
+    }
+    Ok(claims)
+}
+
+/// What to do about a requested port.
+pub enum Verdict {
+    Free,
+    /// Taken by the panel on a host that may not be the panel's; allowed, but worth a note
+    Warn(PortClaim),
+    Reject(PortClaim),
+}
+
+/// `panel_host` says the port is on the panel's own machine, where the panel port is a
+/// hard conflict rather than a maybe.
+pub fn verdict(claims: &HashMap<i32, PortClaim>, port: i32, panel_host: bool) -> Verdict {
+    match claims.get(&port) {
+        None => Verdict::Free,
+        Some(PortClaim::Panel) if !panel_host => Verdict::Warn(PortClaim::Panel),
+        Some(claim) => Verdict::Reject(claim.clone()),
+    }
+}
@@ -0,0 +1,447 @@
+//! Runtimes and images as both the forms and the JSON API (`/api/v1`) write them. Both go
+//! through the validation and SQL here, so an image synced by a CI job ends up exactly like
+//! one saved from the edit page. API clients may also set an `external_id`, which makes
+//! repeated syncs update the same row instead of adding another.
+
+use crate::models::{
+    Image, Runtime, Variable, command_mode_or_default, console_macros_json, parse_docker_images,
+    pull_policy_or_default, stop_timeout_or_default,
+};
+use crate::services::server_events;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const RUNTIME_COLUMNS: &str = "id::text, name, description, color, sort_order, external_id";
+pub const IMAGE_COLUMNS: &str = "id::text, runtime_id::text, name, docker_images, description, stop_command, \
+    startup_command, log_config, config_files, start_config, requires_port, allow_startup_override, \
+    install_script::text, install_container::text, install_entrypoint::text, variables::text, min_ram, \
+    min_disk, min_cpu, run_as_user, no_new_privileges, stop_timeout_seconds, pull_policy, console_macros, \
+    command_mode, external_id, tags";
+
+/// Unique indexes on `external_id` (migration 0014)
+const EXTERNAL_ID_INDEXES: [&str; 2] = ["runtimes_external_id_key", "images_external_id_key"];
+const MAX_EXTERNAL_ID_LEN: usize = 200;
+const MAX_TAGS: usize = 20;
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// Input the form or API must reject, with a message for the user
+    Invalid(String),
+    /// Another runtime or image already has this `external_id`
+    ExternalIdTaken,
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for WriteError {
+    fn from(e: sqlx::Error) -> Self {
+        let taken = e
+            .as_database_error()
+            .and_then(|d| d.constraint())
+            .is_some_and(|c| EXTERNAL_ID_INDEXES.contains(&c));
+        if taken {
+            WriteError::ExternalIdTaken
+        } else {
+            WriteError::Db(e)
+        }
+    }
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Invalid(message) => write!(f, "{}", message),
+            WriteError::ExternalIdTaken => write!(f, "external_id is already in use"),
+            WriteError::Db(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+/// A blank `external_id` counts as none.
+fn clean_external_id(external_id: Option<&str>) -> Result<Option<String>, WriteError> {
+    match external_id.map(str::trim).filter(|s| !s.is_empty()) {
+        Some(id) if id.len() > MAX_EXTERNAL_ID_LEN => Err(WriteError::Invalid(format!(
+            "external_id is longer than {} bytes",
+            MAX_EXTERNAL_ID_LEN
+        ))),
+        other => Ok(other.map(str::to_string)),
+    }
+}
+
+pub struct RuntimeInput {
+    pub name: String,
+    pub description: Option<String>,
+    pub color: String,
+}
+
+impl RuntimeInput {
+    fn validate(&self) -> Result<(), WriteError> {
+        if self.name.trim().is_empty() {
+            return Err(WriteError::Invalid("A runtime needs a name".to_string()));
+        }
+        let hex = self.color.strip_prefix('#').unwrap_or("");
+        if !matches!(hex.len(), 3 | 6) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(WriteError::Invalid(format!(
+                "Color must look like #1a2b3c, got '{}'",
+                self.color
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub async fn create_runtime(
+    db: &PgPool,
+    input: &RuntimeInput,
+    external_id: Option<&str>,
+) -> Result<Uuid, WriteError> {
+    input.validate()?;
+    let external_id = clean_external_id(external_id)?;
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO runtimes (id, name, description, color, external_id) VALUES ($1, $2, $3, $4, $5)")
+        .bind(id)
+        .bind(input.name.trim())
+        .bind(&input.description)
+        .bind(&input.color)
+        .bind(external_id)
+        .execute(db)
+        .await?;
+    Ok(id)
+}
+
+/// Updates a runtime; `external_id` is only changed when given. False if there is no such runtime.
+pub async fn update_runtime(
+    db: &PgPool,
+    id: Uuid,
+    input: &RuntimeInput,
+    external_id: Option<&str>,
+) -> Result<bool, WriteError> {
+    input.validate()?;
+    let external_id = clean_external_id(external_id)?;
+    let res = sqlx::query(
+        "UPDATE runtimes SET name = $2, description = $3, color = $4, external_id = COALESCE($5, external_id) WHERE id = $1",
+    )
+    .bind(id)
+    .bind(input.name.trim())
+    .bind(&input.description)
+    .bind(&input.color)
+    .bind(external_id)
+    .execute(db)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// An image as submitted. Defaults and clamping (stop timeout, pull policy, command mode,
+/// console macros, minimum limits) are applied on write, the same for every caller.
+pub struct ImageInput {
+    pub name: String,
+    pub docker_images: String,
+    pub description: Option<String>,
+    pub startup_command: String,
+    pub stop_command: String,
+    pub requires_port: bool,
+    pub allow_startup_override: bool,
+    pub log_config: String,
+    pub config_files: String,
+    pub start_config: String,
+    pub install_script: String,
+    pub install_container: String,
+    pub install_entrypoint: String,
+    pub variables: String,
+    pub min_ram: Option<i32>,
+    pub min_disk: Option<i32>,
+    pub min_cpu: Option<i32>,
+    pub run_as_user: String,
+    pub no_new_privileges: bool,
+    pub stop_timeout_seconds: Option<i32>,
+    pub pull_policy: Option<String>,
+    pub console_macros: String,
+    pub command_mode: Option<String>,
+    /// Already normalized, see `models::parse_tags`
+    pub tags: Vec<String>,
+}
+
+impl ImageInput {
+    fn validate(&self) -> Result<(), WriteError> {
+        let invalid = |message: String| Err(WriteError::Invalid(message));
+        if self.name.trim().is_empty() {
+            return invalid("An image needs a name".to_string());
+        }
+        if parse_docker_images(&self.docker_images)
+            .iter()
+            .all(|i| i.is_empty())
+        {
+            return invalid("At least one docker image is required".to_string());
+        }
+        if let Err(e) = serde_json::from_str::<Vec<Variable>>(&self.variables) {
+            return invalid(format!(
+                "variables must be a JSON array of variables: {}",
+                e
+            ));
+        }
+        if self.tags.len() > MAX_TAGS {
+            return invalid(format!("An image can have at most {} tags", MAX_TAGS));
+        }
+        Ok(())
+    }
+}
+
+pub async fn create_image(
+    db: &PgPool,
+    runtime_id: Uuid,
+    input: &ImageInput,
+    external_id: Option<&str>,
+) -> Result<Uuid, WriteError> {
+    input.validate()?;
+    let external_id = clean_external_id(external_id)?;
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO images (id, runtime_id, name, docker_images, description, startup_command, stop_command, requires_port, allow_startup_override, log_config, config_files, start_config, install_script, install_container, install_entrypoint, variables, min_ram, min_disk, min_cpu, run_as_user, no_new_privileges, stop_timeout_seconds, pull_policy, console_macros, command_mode, external_id, tags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27)")
+        .bind(id)
+        .bind(runtime_id)
+        .bind(input.name.trim())
+        .bind(&input.docker_images)
+        .bind(&input.description)
+        .bind(&input.startup_command)
+        .bind(&input.stop_command)
+        .bind(input.requires_port)
+        .bind(input.allow_startup_override)
+        .bind(&input.log_config)
+        .bind(&input.config_files)
+        .bind(&input.start_config)
+        .bind(&input.install_script)
+        .bind(&input.install_container)
+        .bind(&input.install_entrypoint)
+        .bind(&input.variables)
+        .bind(input.min_ram.unwrap_or(0).max(0))
+        .bind(input.min_disk.unwrap_or(0).max(0))
+        .bind(input.min_cpu.unwrap_or(0).max(0))
+        .bind(input.run_as_user.trim())
+        .bind(input.no_new_privileges)
+        .bind(stop_timeout_or_default(input.stop_timeout_seconds))
+        .bind(pull_policy_or_default(input.pull_policy.as_deref()))
+        .bind(console_macros_json(&input.console_macros))
+        .bind(command_mode_or_default(input.command_mode.as_deref()))
+        .bind(external_id)
+        .bind(&input.tags)
+        .execute(db)
+        .await?;
+    Ok(id)
+}
+
+/// Updates an image, remembering the old startup command when it changes so servers still
+/// on it can be offered the new one. `runtime_id` and `external_id` are only changed when
+/// given. False if there is no such image.
+pub async fn update_image(
+    db: &PgPool,
+    id: Uuid,
+    runtime_id: Option<Uuid>,
+    input: &ImageInput,
+    external_id: Option<&str>,
+) -> Result<bool, WriteError> {
+    input.validate()?;
+    let external_id = clean_external_id(external_id)?;
+    let mut tx = db.begin().await?;
+    sqlx::query("UPDATE images SET previous_startup_command = startup_command WHERE id = $1 AND startup_command <> $2")
+        .bind(id)
+        .bind(&input.startup_command)
+        .execute(&mut *tx)
+        .await?;
+    let res = sqlx::query("UPDATE images SET name = $1, docker_images = $2, description = $3, startup_command = $4, stop_command = $5, requires_port = $6, allow_startup_override = $7, log_config = $8, config_files = $9, start_config = $10, install_script = $11, install_container = $12, install_entrypoint = $13, variables = $14, min_ram = $15, min_disk = $16, min_cpu = $17, run_as_user = $18, no_new_privileges = $19, stop_timeout_seconds = $20, pull_policy = $21, console_macros = $22, command_mode = $23, runtime_id = COALESCE($25, runtime_id), external_id = COALESCE($26, external_id), tags = $27 WHERE id = $24")
+        .bind(input.name.trim())
+        .bind(&input.docker_images)
+        .bind(&input.description)
+        .bind(&input.startup_command)
+        .bind(&input.stop_command)
+        .bind(input.requires_port)
+        .bind(input.allow_startup_override)
+        .bind(&input.log_config)
+        .bind(&input.config_files)
+        .bind(&input.start_config)
+        .bind(&input.install_script)
+        .bind(&input.install_container)
+        .bind(&input.install_entrypoint)
+        .bind(&input.variables)
+        .bind(input.min_ram.unwrap_or(0).max(0))
+        .bind(input.min_disk.unwrap_or(0).max(0))
+        .bind(input.min_cpu.unwrap_or(0).max(0))
+        .bind(input.run_as_user.trim())
+        .bind(input.no_new_privileges)
+        .bind(stop_timeout_or_default(input.stop_timeout_seconds))
+        .bind(pull_policy_or_default(input.pull_policy.as_deref()))
+        .bind(console_macros_json(&input.console_macros))
+        .bind(command_mode_or_default(input.command_mode.as_deref()))
+        .bind(id)
+        .bind(runtime_id)
+        .bind(external_id)
+        .bind(&input.tags)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn get_runtime(db: &PgPool, id: Uuid) -> Option<Runtime> {
+    sqlx::query_as::<_, Runtime>(&format!(
+        "SELECT {} FROM runtimes WHERE id = $1",
+        RUNTIME_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+}
+
+pub async fn get_image(db: &PgPool, id: Uuid) -> Option<Image> {
+    sqlx::query_as::<_, Image>(&format!(
+        "SELECT {} FROM images WHERE id = $1",
+        IMAGE_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+}
+
+pub async fn runtime_by_external_id(db: &PgPool, external_id: &str) -> Option<Uuid> {
+    sqlx::query_scalar("SELECT id FROM runtimes WHERE external_id = $1")
+        .bind(external_id.trim())
+        .fetch_optional(db)
+        .await
+        .unwrap_or(None)
+}
+
+pub async fn image_by_external_id(db: &PgPool, external_id: &str) -> Option<Uuid> {
+    sqlx::query_scalar("SELECT id FROM images WHERE external_id = $1")
+        .bind(external_id.trim())
+        .fetch_optional(db)
+        .await
+        .unwrap_or(None)
+}
+
+/// Servers created from the image; an image in use can't be deleted.
+pub async fn image_server_count(db: &PgPool, id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM servers WHERE image_id = $1")
+        .bind(id)
+        .fetch_one(db)
+        .await
+}
+
+/// Servers created from any of the runtime's images; deleting the runtime would take those
+/// images with it.
+pub async fn runtime_server_count(db: &PgPool, id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM servers s JOIN images i ON i.id = s.image_id WHERE i.runtime_id = $1",
+    )
+    .bind(id)
+    .fetch_one(db)
+    .await
+}
+
+/// Why a delete didn't happen.
+pub enum DeleteError {
+    /// Servers still use it; carries how many
+    InUse(i64),
+    NotFound,
+    /// The image a forced delete was to move servers onto is missing or the one being deleted
+    InvalidReplacement,
+    Db(sqlx::Error),
+}
+
+/// Keeps servers from losing their image (`ON DELETE RESTRICT`)
+const SERVER_IMAGE_FK: &str = "servers_image_id_fkey";
+
+/// A delete the FK refused because a server was created in between the count and the delete.
+async fn delete_refused(
+    e: sqlx::Error,
+    count: impl std::future::Future<Output = Result<i64, sqlx::Error>>,
+) -> DeleteError {
+    if e.as_database_error().and_then(|d| d.constraint()) != Some(SERVER_IMAGE_FK) {
+        return DeleteError::Db(e);
+    }
+    DeleteError::InUse(count.await.unwrap_or(1).max(1))
+}
+
+pub async fn delete_image(db: &PgPool, id: Uuid) -> Result<(), DeleteError> {
+    match image_server_count(db, id).await {
+        Ok(0) => {}
+        Ok(n) => return Err(DeleteError::InUse(n)),
+        Err(e) => return Err(DeleteError::Db(e)),
+    }
+    let res = sqlx::query("DELETE FROM images WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => Err(DeleteError::NotFound),
+        Ok(_) => Ok(()),
+        Err(e) => Err(delete_refused(e, image_server_count(db, id)).await),
+    }
+}
+
+/// The forced delete: moves the image's servers onto `replacement` and deletes the image.
+/// The servers keep their own Docker image, startup command and variables, and are flagged
+/// for a recreate so their containers pick up the replacement's settings. Returns how many
+/// servers moved.
+pub async fn delete_image_reassigning(
+    db: &PgPool,
+    id: Uuid,
+    replacement: Uuid,
+) -> Result<usize, DeleteError> {
+    if replacement == id {
+        return Err(DeleteError::InvalidReplacement);
+    }
+    let mut tx = db.begin().await.map_err(DeleteError::Db)?;
+    let name: Option<String> = sqlx::query_scalar("SELECT name FROM images WHERE id = $1")
+        .bind(replacement)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(DeleteError::Db)?;
+    let Some(name) = name else {
+        return Err(DeleteError::InvalidReplacement);
+    };
+
+    let moved: Vec<Uuid> = sqlx::query_scalar(
+        "UPDATE servers SET image_id = $1, needs_recreate = TRUE WHERE image_id = $2 RETURNING id",
+    )
+    .bind(replacement)
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(DeleteError::Db)?;
+    let res = sqlx::query("DELETE FROM images WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => return Err(DeleteError::NotFound),
+        Ok(_) => {}
+        Err(e) => return Err(delete_refused(e, image_server_count(db, id)).await),
+    }
+    tx.commit().await.map_err(DeleteError::Db)?;
+
+    let message = format!(
+        "Image deleted; moved to {}. Recreate the container to apply",
+        name
+    );
+    for server_id in &moved {
+        server_events::record(db, *server_id, "needs_recreate", &message).await;
+    }
+    Ok(moved.len())
+}
+
+/// Deletes a runtime along with its images, unless a server uses one of them.
+pub async fn delete_runtime(db: &PgPool, id: Uuid) -> Result<(), DeleteError> {
+    match runtime_server_count(db, id).await {
+        Ok(0) => {}
+        Ok(n) => return Err(DeleteError::InUse(n)),
+        Err(e) => return Err(DeleteError::Db(e)),
+    }
+    let res = sqlx::query("DELETE FROM runtimes WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => Err(DeleteError::NotFound),
+        Ok(_) => Ok(()),
+        Err(e) => Err(delete_refused(e, runtime_server_count(db, id)).await),
+    }
+}
@@ -0,0 +1,86 @@
+//! Live output of server installs. The create job pushes the node's install events here,
+//! and the manage page's `/servers/{id}/install-log` stream replays what it missed and then
+//! follows along. Memory only: a panel restart loses the log, not the install.
+
+use crate::models::InstallTestEvent;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Output kept per install; the oldest lines go first
+const MAX_LOG_BYTES: usize = 256 * 1024;
+/// How long a finished log stays around for a page that opens late
+const KEEP_FINISHED: Duration = Duration::from_secs(600);
+
+#[derive(Default)]
+pub struct InstallLogs {
+    logs: Mutex<HashMap<Uuid, InstallLog>>,
+}
+
+struct InstallLog {
+    events: Vec<InstallTestEvent>,
+    bytes: usize,
+    live: broadcast::Sender<InstallTestEvent>,
+    finished_at: Option<Instant>,
+}
+
+/// What a viewer gets: everything so far, and the live feed while the install runs.
+pub struct InstallLogView {
+    pub backlog: Vec<InstallTestEvent>,
+    pub live: Option<broadcast::Receiver<InstallTestEvent>>,
+}
+
+impl InstallLogs {
+    /// Starts a fresh log for the server, dropping the one from an earlier attempt.
+    pub fn begin(&self, server_id: Uuid) {
+        let mut logs = self.logs.lock().unwrap();
+        logs.retain(|_, log| {
+            log.finished_at
+                .is_none_or(|at| at.elapsed() < KEEP_FINISHED)
+        });
+        logs.insert(
+            server_id,
+            InstallLog {
+                events: Vec::new(),
+                bytes: 0,
+                live: broadcast::channel(256).0,
+                finished_at: None,
+            },
+        );
+    }
+
+    /// Records one event; `exit` and `error` end the log.
+    pub fn push(&self, server_id: Uuid, event: InstallTestEvent) {
+        let mut logs = self.logs.lock().unwrap();
+        let Some(log) = logs
+            .get_mut(&server_id)
+            .filter(|log| log.finished_at.is_none())
+        else {
+            return;
+        };
+        if let InstallTestEvent::Output { data } = &event {
+            log.bytes += data.len();
+            while log.bytes > MAX_LOG_BYTES && !log.events.is_empty() {
+                if let InstallTestEvent::Output { data } = log.events.remove(0) {
+                    log.bytes -= data.len();
+                }
+            }
+        } else {
+            log.finished_at = Some(Instant::now());
+        }
+        // Nobody watching is fine
+        let _ = log.live.send(event.clone());
+        log.events.push(event);
+    }
+
+    pub fn view(&self, server_id: Uuid) -> Option<InstallLogView> {
+        let logs = self.logs.lock().unwrap();
+        let log = logs.get(&server_id)?;
+        Some(InstallLogView {
+            backlog: log.events.clone(),
+            live: log.finished_at.is_none().then(|| log.live.subscribe()),
+        })
+    }
+}
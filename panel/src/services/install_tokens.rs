@@ -0,0 +1,111 @@
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+/// Lifetime of install tokens in seconds (`INSTALL_TOKEN_TTL`, default 1 hour).
+pub fn ttl() -> i64 {
+    std::env::var("INSTALL_TOKEN_TTL")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(3600)
+}
+
+/// What a token unlocks. Uninstall tokens are minted while a node is being decommissioned
+/// and let its uninstall script confirm the removal.
+#[derive(Debug, Clone, Copy)]
+pub enum TokenPurpose {
+    Install,
+    Uninstall,
+}
+
+impl TokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenPurpose::Install => "install",
+            TokenPurpose::Uninstall => "uninstall",
+        }
+    }
+}
+
+fn sha256_hex(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Mints a single-use token for `/install/{id}?t=...`. Pages show this instead of the node
+/// token; the install script resolves the node's current token when it is fetched, so a
+/// displayed command survives token rotation. Only a hash is stored.
+pub async fn issue(db: &PgPool, node_id: &str, purpose: TokenPurpose) -> Option<String> {
+    let token: String = rand::rng()
+        .sample_iter(&rand::distr::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    // Keep the table small; every page view mints one
+    let _ = sqlx::query(
+        "DELETE FROM node_install_tokens WHERE expires_at < NOW() OR used_at IS NOT NULL",
+    )
+    .execute(db)
+    .await;
+
+    let res = sqlx::query(
+        "INSERT INTO node_install_tokens (token_sha256, node_id, expires_at, purpose) VALUES ($1, $2::uuid, NOW() + make_interval(secs => $3), $4)",
+    )
+    .bind(sha256_hex(&token))
+    .bind(node_id)
+    .bind(ttl() as f64)
+    .bind(purpose.as_str())
+    .execute(db)
+    .await;
+
+    match res {
+        Ok(_) => Some(token),
+        Err(e) => {
+            tracing::error!(
+                "Failed to issue {} token for node {}: {}",
+                purpose.as_str(),
+                node_id,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Marks the token used and returns whether it was valid for this node.
+pub async fn redeem<'e>(
+    db: impl sqlx::PgExecutor<'e>,
+    node_id: &str,
+    token: &str,
+    purpose: TokenPurpose,
+) -> bool {
+    sqlx::query(
+        "UPDATE node_install_tokens SET used_at = NOW()
+         WHERE token_sha256 = $1 AND node_id = $2::uuid AND purpose = $3 AND used_at IS NULL AND expires_at > NOW()",
+    )
+    .bind(sha256_hex(token))
+    .bind(node_id)
+    .bind(purpose.as_str())
+    .execute(db)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .unwrap_or(false)
+}
+
+/// Whether the token is still good for this node, without using it up.
+pub async fn is_valid(db: &PgPool, node_id: &str, token: &str, purpose: TokenPurpose) -> bool {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM node_install_tokens
+         WHERE token_sha256 = $1 AND node_id = $2::uuid AND purpose = $3 AND used_at IS NULL AND expires_at > NOW())",
+    )
+    .bind(sha256_hex(token))
+    .bind(node_id)
+    .bind(purpose.as_str())
+    .fetch_one(db)
+    .await
+    .unwrap_or(false)
+}
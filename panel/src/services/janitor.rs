@@ -0,0 +1,142 @@
+use crate::models::MAX_STOP_TIMEOUT;
+use crate::services::node_api;
+use crate::state::AppState;
+use std::time::Duration;
+
+/// Opt-in cleanup of servers that never finished installing.
+/// Only `queued`, `installing` and `install_failed` servers are considered; anything that
+/// reached `running` (or any other state) is never touched.
+#[derive(Debug, Clone)]
+pub struct JanitorConfig {
+    pub interval: Duration,
+    /// Age (from `created_at`) after which an unfinished install counts as stuck
+    pub stuck_after: Duration,
+    /// Delete stuck servers (container + allocations) instead of only flagging them
+    pub auto_delete: bool,
+}
+
+impl JanitorConfig {
+    /// None unless `JANITOR_ENABLED=true`.
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("JANITOR_ENABLED")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let secs = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+
+        Some(Self {
+            interval: Duration::from_secs(secs("JANITOR_INTERVAL", 300)),
+            stuck_after: Duration::from_secs(secs("JANITOR_STUCK_TIMEOUT", 3600)),
+            auto_delete: std::env::var("JANITOR_AUTO_DELETE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        })
+    }
+}
+
+pub async fn run(state: AppState, config: JanitorConfig) {
+    tracing::info!(
+        "Janitor enabled: every {}s, stuck after {}s, auto-delete {}",
+        config.interval.as_secs(),
+        config.stuck_after.as_secs(),
+        config.auto_delete
+    );
+
+    loop {
+        tokio::time::sleep(config.interval).await;
+        if let Err(e) = sweep(&state, &config).await {
+            tracing::error!("Janitor sweep failed: {}", e);
+        }
+    }
+}
+
+/// One pass over the unfinished installs older than `stuck_after`.
+pub async fn sweep(state: &AppState, config: &JanitorConfig) -> Result<(), sqlx::Error> {
+    let stuck: Vec<(String, String, String, i32)> = sqlx::query_as(
+        "SELECT id::text, node_id::text, status, stop_timeout_seconds FROM servers WHERE status IN ('queued', 'installing', 'install_failed') AND created_at < NOW() - make_interval(secs => $1)",
+    )
+    .bind(config.stuck_after.as_secs() as f64)
+    .fetch_all(&state.db)
+    .await?;
+
+    for (server_id, node_id, status, stop_timeout) in stuck {
+        if config.auto_delete {
+            delete_stuck(state, &server_id, &node_id, &status, stop_timeout).await;
+            continue;
+        }
+
+        let flagged = sqlx::query(
+            "UPDATE servers SET flagged_at = NOW(), flag_reason = $2 WHERE id = $1::uuid AND flagged_at IS NULL",
+        )
+        .bind(&server_id)
+        .bind(format!(
+            "Stuck in '{}' for over {} minutes",
+            status,
+            config.stuck_after.as_secs() / 60
+        ))
+        .execute(&state.db)
+        .await?;
+
+        if flagged.rows_affected() > 0 {
+            tracing::warn!("Janitor flagged server {} stuck in '{}'", server_id, status);
+        }
+    }
+
+    Ok(())
+}
+
+async fn delete_stuck(
+    state: &AppState,
+    server_id: &str,
+    node_id: &str,
+    status: &str,
+    stop_timeout: i32,
+) {
+    let grace = stop_timeout.clamp(1, MAX_STOP_TIMEOUT) as u64;
+    if let Some(node) = state.get_node_with_token(node_id).await
+        && let Err(e) = node_api::stop_and_delete_container(
+            &state.http_client,
+            &node,
+            server_id,
+            Some(grace),
+            true,
+            &state.node_retry,
+        )
+        .await
+    {
+        // Keep the row so a later sweep (or an admin) can retry the container cleanup
+        tracing::error!("Janitor could not remove container {}: {}", server_id, e);
+        return;
+    }
+
+    let result = async {
+        let mut tx = state.db.begin().await?;
+        sqlx::query("UPDATE allocations SET server_id = NULL WHERE server_id = $1::uuid")
+            .bind(server_id)
+            .execute(&mut *tx)
+            .await?;
+        // Re-check the status so a server that came up meanwhile survives
+        sqlx::query(
+            "DELETE FROM servers WHERE id = $1::uuid AND status IN ('queued', 'installing', 'install_failed')",
+        )
+        .bind(server_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => tracing::warn!("Janitor deleted server {} stuck in '{}'", server_id, status),
+        Err(e) => tracing::error!("Janitor failed to delete server {}: {}", server_id, e),
+    }
+}
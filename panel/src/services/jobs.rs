@@ -0,0 +1,227 @@
+use crate::models::Job;
+use crate::services::{maintenance, provisioning};
+use crate::state::AppState;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+/// Kinds the worker knows how to run. Anything else fails immediately.
+pub const CREATE_CONTAINER: &str = "create_container";
+pub const NODE_MAINTENANCE: &str = "node_maintenance";
+pub const RECREATE_CONTAINER: &str = "recreate_container";
+
+/// Worker settings for the panel-side job queue.
+#[derive(Debug, Clone)]
+pub struct JobConfig {
+    /// Jobs executed at once (`JOB_WORKERS`, default 4)
+    pub workers: usize,
+    /// Poll interval when nobody wakes the worker (`JOB_POLL_INTERVAL`, default 5s)
+    pub poll_interval: Duration,
+    /// How often a running job refreshes its heartbeat
+    pub heartbeat_interval: Duration,
+    /// Running jobs whose heartbeat is older than this are failed (`JOB_STALE_AFTER`, default 60s)
+    pub stale_after: Duration,
+}
+
+impl JobConfig {
+    pub fn from_env() -> Self {
+        let num = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+
+        let stale_after = Duration::from_secs(num("JOB_STALE_AFTER", 60));
+        Self {
+            workers: num("JOB_WORKERS", 4) as usize,
+            poll_interval: Duration::from_secs(num("JOB_POLL_INTERVAL", 5)),
+            heartbeat_interval: stale_after / 4,
+            stale_after,
+        }
+    }
+}
+
+/// Handle passed to a running job for reporting progress.
+#[derive(Clone)]
+pub struct JobContext {
+    pub state: AppState,
+    pub id: Uuid,
+}
+
+impl JobContext {
+    pub async fn progress(&self, percent: i32) {
+        let _ = sqlx::query("UPDATE jobs SET progress = $2 WHERE id = $1")
+            .bind(self.id)
+            .bind(percent.clamp(0, 100))
+            .execute(&self.state.db)
+            .await;
+    }
+}
+
+/// Queues a job and wakes the worker. `target` is what the job acts on (e.g. a server id)
+/// and is what pages look jobs up by.
+pub async fn enqueue(
+    state: &AppState,
+    kind: &str,
+    target: &str,
+    payload: &impl Serialize,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO jobs (id, kind, target, payload) VALUES ($1, $2, $3, $4::jsonb)")
+        .bind(id)
+        .bind(kind)
+        .bind(target)
+        .bind(serde_json::to_string(payload).unwrap_or_else(|_| "{}".to_string()))
+        .execute(&state.db)
+        .await?;
+    state.jobs_wake.notify_one();
+    Ok(id)
+}
+
+pub async fn get(db: &PgPool, id: Uuid) -> Option<Job> {
+    sqlx::query_as::<_, Job>(
+        "SELECT id, kind, target, status, progress, error, created_at, started_at, finished_at FROM jobs WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+}
+
+/// Most recent job of a kind for a target, e.g. the install of a server.
+pub async fn latest_for(db: &PgPool, kind: &str, target: &str) -> Option<Job> {
+    sqlx::query_as::<_, Job>(
+        "SELECT id, kind, target, status, progress, error, created_at, started_at, finished_at FROM jobs WHERE kind = $1 AND target = $2 ORDER BY created_at DESC LIMIT 1",
+    )
+    .bind(kind)
+    .bind(target)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+}
+
+pub async fn run(state: AppState, config: JobConfig) {
+    tracing::info!(
+        "Job worker started: {} at once, stale after {}s",
+        config.workers,
+        config.stale_after.as_secs()
+    );
+    let permits = Arc::new(Semaphore::new(config.workers));
+
+    loop {
+        fail_stale(&state.db, config.stale_after).await;
+
+        // Claim as many jobs as there are free slots
+        while let Ok(permit) = permits.clone().try_acquire_owned() {
+            let Some((id, kind, payload)) = claim(&state.db).await else {
+                break;
+            };
+            let ctx = JobContext {
+                state: state.clone(),
+                id,
+            };
+            let heartbeat_interval = config.heartbeat_interval;
+            tokio::spawn(async move {
+                let _permit = permit;
+                execute(ctx, kind, payload, heartbeat_interval).await;
+            });
+        }
+
+        tokio::select! {
+            _ = state.jobs_wake.notified() => {}
+            _ = tokio::time::sleep(config.poll_interval) => {}
+        }
+    }
+}
+
+/// Oldest queued job, marked running. SKIP LOCKED keeps concurrent claimers apart.
+async fn claim(db: &PgPool) -> Option<(Uuid, String, String)> {
+    sqlx::query_as(
+        r#"
+        UPDATE jobs SET status = 'running', started_at = NOW(), heartbeat_at = NOW()
+        WHERE id = (
+            SELECT id FROM jobs WHERE status = 'queued' ORDER BY created_at
+            FOR UPDATE SKIP LOCKED LIMIT 1
+        )
+        RETURNING id, kind, payload::text
+    "#,
+    )
+    .fetch_optional(db)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to claim job: {}", e);
+        None
+    })
+}
+
+/// Jobs whose worker went away (panel restart, crash) would otherwise stay running forever.
+/// They are failed rather than retried, since not every kind is safe to run twice.
+async fn fail_stale(db: &PgPool, stale_after: Duration) {
+    let res = sqlx::query(
+        "UPDATE jobs SET status = 'failed', error = 'Worker stopped responding', finished_at = NOW()
+         WHERE status = 'running' AND heartbeat_at < NOW() - make_interval(secs => $1)",
+    )
+    .bind(stale_after.as_secs_f64())
+    .execute(db)
+    .await;
+
+    match res {
+        Ok(r) if r.rows_affected() > 0 => {
+            tracing::warn!("Failed {} stale job(s)", r.rows_affected())
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to sweep stale jobs: {}", e),
+    }
+}
+
+async fn execute(ctx: JobContext, kind: String, payload: String, heartbeat_interval: Duration) {
+    let db = ctx.state.db.clone();
+    let id = ctx.id;
+    let heartbeat = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(heartbeat_interval).await;
+            let _ = sqlx::query("UPDATE jobs SET heartbeat_at = NOW() WHERE id = $1")
+                .bind(id)
+                .execute(&db)
+                .await;
+        }
+    });
+
+    let result = match kind.as_str() {
+        CREATE_CONTAINER => match serde_json::from_str(&payload) {
+            Ok(p) => provisioning::create_container_job(&ctx, p).await,
+            Err(e) => Err(format!("Invalid payload: {}", e)),
+        },
+        NODE_MAINTENANCE => match serde_json::from_str(&payload) {
+            Ok(p) => maintenance::maintenance_job(&ctx, p).await,
+            Err(e) => Err(format!("Invalid payload: {}", e)),
+        },
+        RECREATE_CONTAINER => match serde_json::from_str(&payload) {
+            Ok(p) => provisioning::recreate_container_job(&ctx, p).await,
+            Err(e) => Err(format!("Invalid payload: {}", e)),
+        },
+        other => Err(format!("Unknown job kind {}", other)),
+    };
+    heartbeat.abort();
+
+    let (status, error) = match result {
+        Ok(()) => ("succeeded", None),
+        Err(e) => {
+            tracing::error!("Job {} ({}) failed: {}", ctx.id, kind, e);
+            ("failed", Some(e))
+        }
+    };
+    let _ = sqlx::query(
+        "UPDATE jobs SET status = $2, error = $3, progress = CASE WHEN $2 = 'succeeded' THEN 100 ELSE progress END, finished_at = NOW() WHERE id = $1",
+    )
+    .bind(ctx.id)
+    .bind(status)
+    .bind(error)
+    .execute(&ctx.state.db)
+    .await;
+}
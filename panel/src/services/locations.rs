@@ -0,0 +1,94 @@
+//! Locations group nodes for placement (migration 0012). Every node has one; nodes nobody
+//! placed sit in the built-in "Unassigned" location, which keeps its name and can't be
+//! deleted.
+
+use crate::models::Location;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// The built-in "Unassigned" location
+pub const UNASSIGNED: Uuid = Uuid::nil();
+
+/// Case-insensitive unique index on location names
+const NAME_INDEX: &str = "locations_name_lower_key";
+
+/// Longest name kept
+pub const MAX_NAME_LEN: usize = 64;
+
+pub fn is_duplicate_name(e: &sqlx::Error) -> bool {
+    e.as_database_error().and_then(|d| d.constraint()) == Some(NAME_INDEX)
+}
+
+/// Every location with its node count, "Unassigned" first and the rest by name.
+pub async fn list(db: &PgPool) -> Vec<Location> {
+    sqlx::query_as::<_, Location>(
+        "SELECT l.id::text, l.name, l.description,
+            (SELECT COUNT(*) FROM nodes n WHERE n.location_id = l.id) AS node_count
+         FROM locations l ORDER BY l.id <> $1, lower(l.name)",
+    )
+    .bind(UNASSIGNED)
+    .fetch_all(db)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to list locations: {}", e);
+        Vec::new()
+    })
+}
+
+pub async fn create(db: &PgPool, name: &str, description: &str) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO locations (id, name, description) VALUES ($1, $2, $3)")
+        .bind(id)
+        .bind(name)
+        .bind(description)
+        .execute(db)
+        .await?;
+    Ok(id)
+}
+
+/// Renames a location; "Unassigned" only takes a new description.
+pub async fn update(
+    db: &PgPool,
+    id: Uuid,
+    name: &str,
+    description: &str,
+) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE locations SET name = CASE WHEN id = $4 THEN name ELSE $2 END, description = $3 WHERE id = $1",
+    )
+    .bind(id)
+    .bind(name)
+    .bind(description)
+    .bind(UNASSIGNED)
+    .execute(db)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Deletes a location, moving its nodes to "Unassigned". Returns how many nodes moved.
+pub async fn delete(db: &PgPool, id: Uuid) -> Result<u64, sqlx::Error> {
+    if id == UNASSIGNED {
+        return Ok(0);
+    }
+    let mut tx = db.begin().await?;
+    let moved = sqlx::query("UPDATE nodes SET location_id = $2 WHERE location_id = $1")
+        .bind(id)
+        .bind(UNASSIGNED)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    sqlx::query("DELETE FROM locations WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
+/// A location id from a form or query, or `None` for "any location". Well-formed ids of
+/// locations that don't exist are kept and simply match no node.
+pub fn parse_filter(raw: Option<&str>) -> Option<Uuid> {
+    raw.map(str::trim)
+        .filter(|s| !s.is_empty())
+        .and_then(|s| Uuid::parse_str(s).ok())
+}
@@ -0,0 +1,569 @@
+//! Per-node maintenance windows (migration 0013). Once the window opens, the scheduler
+//! queues a `node_maintenance` job that stops the node's running servers (stop command
+//! first, then a graceful stop), optionally updates the agent and reboots the host, waits
+//! for the node to come back and starts the same servers again. Every step lands in the
+//! node's event log, and each server it touches gets a server event.
+//!
+//! If the node doesn't come back within `MAINTENANCE_RETURN_TIMEOUT`, the run gives up and
+//! leaves the servers stopped: starting them on a half-rebooted host is worse than an
+//! admin looking at it. The node then carries an alert until someone dismisses it.
+
+use crate::models::{MAX_STOP_TIMEOUT, Node, NodeMaintenance, PowerAction, PowerRequest};
+use crate::services::jobs::{self, JobContext};
+use crate::services::{node_api, node_events, node_versions, server_events};
+use crate::state::AppState;
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often the scheduler looks for windows that opened
+const TICK: Duration = Duration::from_secs(60);
+/// A window the panel noticed later than this (it was down) is skipped, not run late
+const LATE_GRACE: chrono::Duration = chrono::Duration::minutes(30);
+/// Poll interval while waiting on containers and the node
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// When a node's maintenance runs: every day or one weekday, at a UTC time.
+/// Written as `daily 05:00` or `Sunday 05:00` (`sun 05:00` works too).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    day: Option<Weekday>,
+    time: NaiveTime,
+}
+
+impl Window {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut parts = raw.split_whitespace();
+        let (day, time) = (parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        let day = if day.eq_ignore_ascii_case("daily") {
+            None
+        } else {
+            Some(day.parse::<Weekday>().ok()?)
+        };
+        let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+        Some(Self { day, time })
+    }
+
+    /// Start of the most recent window at or before `now`.
+    pub fn last_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let mut start = now.date_naive().and_time(self.time).and_utc();
+        if start > now {
+            start -= chrono::Duration::days(1);
+        }
+        if let Some(day) = self.day {
+            while start.weekday() != day {
+                start -= chrono::Duration::days(1);
+            }
+        }
+        start
+    }
+
+    pub fn next_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let period = if self.day.is_some() { 7 } else { 1 };
+        self.last_start(now) + chrono::Duration::days(period)
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = self.time.format("%H:%M");
+        match self.day {
+            // NaiveDate's %A has the full day name; Weekday's Display only the short one
+            Some(day) => {
+                let date = chrono::NaiveDate::from_isoywd_opt(2024, 1, day).unwrap_or_default();
+                write!(f, "{} {}", date.format("%A"), time)
+            }
+            None => write!(f, "daily {}", time),
+        }
+    }
+}
+
+/// How long a run waits for the node to come back (`MAINTENANCE_RETURN_TIMEOUT`, seconds, default 900).
+fn return_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("MAINTENANCE_RETURN_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(900),
+    )
+}
+
+/// Payload of a `node_maintenance` job.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MaintenancePayload {
+    pub node_id: Uuid,
+    pub update: bool,
+    pub reboot: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ScheduledNode {
+    id: Uuid,
+    window: String,
+    update: bool,
+    reboot: bool,
+    last_run: Option<DateTime<Utc>>,
+}
+
+pub async fn run_scheduler(state: AppState) {
+    tracing::info!("Maintenance scheduler started");
+    loop {
+        schedule_due(&state).await;
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+/// Queues a run for every node whose window opened within `LATE_GRACE` and hasn't had one.
+async fn schedule_due(state: &AppState) {
+    let nodes = sqlx::query_as::<_, ScheduledNode>(
+        "SELECT id, maintenance_window AS window, maintenance_update AS update, maintenance_reboot AS reboot,
+            maintenance_last_run AS last_run
+         FROM nodes WHERE maintenance_enabled AND maintenance_window <> '' AND decommission_started_at IS NULL",
+    )
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to load maintenance windows: {}", e);
+        Vec::new()
+    });
+
+    let now = Utc::now();
+    for node in nodes {
+        let Some(window) = Window::parse(&node.window) else {
+            continue;
+        };
+        let start = window.last_start(now);
+        if now - start > LATE_GRACE || node.last_run.is_some_and(|t| t >= start) {
+            continue;
+        }
+        // Claim the window; with several panels only one gets the row
+        let claimed = sqlx::query(
+            "UPDATE nodes SET maintenance_last_run = $2
+             WHERE id = $1 AND (maintenance_last_run IS NULL OR maintenance_last_run < $2)",
+        )
+        .bind(node.id)
+        .bind(start)
+        .execute(&state.db)
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .unwrap_or(false);
+        if !claimed {
+            continue;
+        }
+
+        let payload = MaintenancePayload {
+            node_id: node.id,
+            update: node.update,
+            reboot: node.reboot,
+        };
+        match jobs::enqueue(
+            state,
+            jobs::NODE_MAINTENANCE,
+            &node.id.to_string(),
+            &payload,
+        )
+        .await
+        {
+            Ok(job) => tracing::info!("Queued maintenance of node {} as job {}", node.id, job),
+            Err(e) => tracing::error!("Failed to queue maintenance of node {}: {}", node.id, e),
+        }
+    }
+}
+
+/// Stores a node's window settings. The window counts as already run up to now, so saving
+/// during a window doesn't stop servers on the spot.
+pub async fn save_settings(
+    db: &sqlx::PgPool,
+    node_id: Uuid,
+    window: Option<Window>,
+    enabled: bool,
+    update: bool,
+    reboot: bool,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query(
+        "UPDATE nodes SET maintenance_window = $2, maintenance_enabled = $3, maintenance_update = $4,
+            maintenance_reboot = $5, maintenance_last_run = $6
+         WHERE id = $1",
+    )
+    .bind(node_id)
+    .bind(window.map(|w| w.to_string()).unwrap_or_default())
+    .bind(enabled && window.is_some())
+    .bind(update)
+    .bind(reboot)
+    .bind(window.map(|w| w.last_start(now)))
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub async fn settings(db: &sqlx::PgPool, node_id: Uuid) -> NodeMaintenance {
+    sqlx::query_as::<_, NodeMaintenance>(
+        "SELECT maintenance_window AS window, maintenance_enabled AS enabled, maintenance_update AS update,
+            maintenance_reboot AS reboot, maintenance_last_run AS last_run, maintenance_alert AS alert
+         FROM nodes WHERE id = $1",
+    )
+    .bind(node_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+    .unwrap_or_default()
+}
+
+/// Alerts of every node that has one, by node id.
+pub async fn alerts(db: &sqlx::PgPool) -> std::collections::HashMap<String, String> {
+    sqlx::query_as::<_, (String, String)>(
+        "SELECT id::text, maintenance_alert FROM nodes WHERE maintenance_alert IS NOT NULL",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .collect()
+}
+
+pub async fn dismiss_alert(db: &sqlx::PgPool, node_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE nodes SET maintenance_alert = NULL WHERE id = $1")
+        .bind(node_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+async fn set_alert(db: &sqlx::PgPool, node_id: Uuid, alert: Option<&str>) {
+    let res = sqlx::query("UPDATE nodes SET maintenance_alert = $2 WHERE id = $1")
+        .bind(node_id)
+        .bind(alert)
+        .execute(db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to store maintenance alert of node {}: {}",
+            node_id,
+            e
+        );
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MaintenanceServer {
+    id: Uuid,
+    name: String,
+    stop_command: String,
+    stop_timeout_seconds: i32,
+}
+
+impl MaintenanceServer {
+    fn grace(&self) -> u64 {
+        self.stop_timeout_seconds.clamp(1, MAX_STOP_TIMEOUT) as u64
+    }
+}
+
+/// The `node_maintenance` job.
+pub async fn maintenance_job(ctx: &JobContext, payload: MaintenancePayload) -> Result<(), String> {
+    let state = &ctx.state;
+    let node_id = payload.node_id;
+    let node = state
+        .get_node_with_token(&node_id.to_string())
+        .await
+        .ok_or_else(|| "Node no longer exists".to_string())?;
+    let event = |message: String| async move {
+        tracing::info!("Maintenance of node {}: {}", node_id, message);
+        node_events::record(&state.db, node_id, "maintenance", &message).await;
+    };
+    event("Maintenance window started".to_string()).await;
+
+    let servers = sqlx::query_as::<_, MaintenanceServer>(
+        "SELECT s.id, s.name, COALESCE(i.stop_command, '') AS stop_command, s.stop_timeout_seconds
+         FROM servers s LEFT JOIN images i ON i.id = s.image_id
+         WHERE s.node_id = $1 ORDER BY s.name",
+    )
+    .bind(node_id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| format!("Failed to load servers: {}", e))?;
+
+    // Only servers that are up now get started again afterwards
+    let mut running = Vec::new();
+    for server in servers {
+        match node_api::container_state(
+            &state.http_client,
+            &node,
+            &server.id.to_string(),
+            &state.node_retry,
+        )
+        .await
+        {
+            Ok(current) if current.state == "running" => running.push(server),
+            Ok(_) => {}
+            Err(e) => {
+                let message = format!(
+                    "Node unreachable before stopping anything ({}); maintenance skipped",
+                    e
+                );
+                event(message.clone()).await;
+                return Err(message);
+            }
+        }
+    }
+    let total = running.len();
+    event(format!("Stopping {} running server(s)", total)).await;
+
+    let mut stopped = Vec::new();
+    let mut stop_failed = false;
+    for server in running {
+        match stop_server(state, &node, &server).await {
+            Ok(()) => {
+                server_events::record(
+                    &state.db,
+                    server.id,
+                    "maintenance",
+                    "Stopped for node maintenance",
+                )
+                .await;
+                stopped.push(server);
+            }
+            Err(e) => {
+                event(format!(
+                    "Could not stop {} ({}); skipping update and reboot",
+                    server.name, e
+                ))
+                .await;
+                stop_failed = true;
+                break;
+            }
+        }
+        ctx.progress((30 * stopped.len() / total) as i32).await;
+    }
+    ctx.progress(30).await;
+
+    if !stop_failed && payload.update {
+        let requested_at = Utc::now();
+        match node_api::request_self_update(&state.http_client, &node, &state.node_retry).await {
+            Ok(()) => {
+                node_versions::set_update_state(
+                    &state.db,
+                    &node.id,
+                    "requested",
+                    Some("Requested by maintenance window"),
+                )
+                .await;
+                event("Agent update requested".to_string()).await;
+                match wait_for_update(state, &node.id, requested_at).await {
+                    Some(status) => event(format!("Agent update {}", status)).await,
+                    None => return give_up(state, node_id, &stopped, "the agent update").await,
+                }
+            }
+            Err(e) => event(format!("Agent update not started: {}", e)).await,
+        }
+    }
+    ctx.progress(50).await;
+
+    if !stop_failed && payload.reboot {
+        let requested_at = Instant::now();
+        match node_api::reboot_host(&state.http_client, &node, &state.node_retry).await {
+            Ok(()) => {
+                event("Host reboot requested".to_string()).await;
+                if !wait_for_reboot(state, &node.id, requested_at).await {
+                    return give_up(state, node_id, &stopped, "the host reboot").await;
+                }
+                event("Node is back after the reboot".to_string()).await;
+            }
+            Err(e) if e.code == "reboot_disabled" => {
+                event("Host reboot skipped: not enabled in the node's config.yml".to_string()).await
+            }
+            Err(e) => event(format!("Host reboot not started: {}", e)).await,
+        }
+    }
+    ctx.progress(70).await;
+
+    let mut failed = Vec::new();
+    for server in &stopped {
+        match start_server(state, &node, server).await {
+            Ok(()) => {
+                server_events::record(
+                    &state.db,
+                    server.id,
+                    "maintenance",
+                    "Started again after node maintenance",
+                )
+                .await
+            }
+            Err(e) => {
+                event(format!("Could not start {}: {}", server.name, e)).await;
+                failed.push(server.name.clone());
+            }
+        }
+    }
+    ctx.progress(100).await;
+
+    if failed.is_empty() {
+        set_alert(&state.db, node_id, None).await;
+        event(format!(
+            "Maintenance finished; {} server(s) started again",
+            stopped.len()
+        ))
+        .await;
+        Ok(())
+    } else {
+        let alert = format!(
+            "Maintenance could not start these servers again: {}",
+            failed.join(", ")
+        );
+        set_alert(&state.db, node_id, Some(&alert)).await;
+        event(alert.clone()).await;
+        Err(alert)
+    }
+}
+
+/// The node didn't come back: leave the servers stopped and raise the alert.
+async fn give_up(
+    state: &AppState,
+    node_id: Uuid,
+    stopped: &[MaintenanceServer],
+    step: &str,
+) -> Result<(), String> {
+    let names: Vec<&str> = stopped.iter().map(|s| s.name.as_str()).collect();
+    let alert = format!(
+        "Node did not come back within {}s after {}; left stopped: {}",
+        return_timeout().as_secs(),
+        step,
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    );
+    set_alert(&state.db, node_id, Some(&alert)).await;
+    node_events::record(&state.db, node_id, "maintenance", &alert).await;
+    for server in stopped {
+        server_events::record(
+            &state.db,
+            server.id,
+            "maintenance",
+            "Left stopped: node maintenance was aborted",
+        )
+        .await;
+    }
+    Err(alert)
+}
+
+/// Image stop command first, then the node's graceful stop for whatever is still up.
+async fn stop_server(
+    state: &AppState,
+    node: &Node,
+    server: &MaintenanceServer,
+) -> Result<(), String> {
+    let uuid = server.id.to_string();
+    let command = server.stop_command.trim();
+    if !command.is_empty() {
+        match node_api::send_command(&state.http_client, node, &uuid, command, &state.node_retry)
+            .await
+        {
+            Ok(()) => {
+                let deadline = Instant::now() + Duration::from_secs(server.grace());
+                while Instant::now() < deadline {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    if let Ok(current) = node_api::container_state(
+                        &state.http_client,
+                        node,
+                        &uuid,
+                        &state.node_retry,
+                    )
+                    .await
+                        && PowerAction::Stop.reached(&current.state)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(e) => tracing::warn!(
+                "Stop command for server {} failed, stopping it instead: {}",
+                uuid,
+                e
+            ),
+        }
+    }
+    let request = PowerRequest {
+        action: PowerAction::Stop,
+        grace: Some(server.grace()),
+    };
+    node_api::power_container(&state.http_client, node, &uuid, &request, &state.node_retry)
+        .await
+        .map(|_| ())
+}
+
+/// Docker can take a moment after the agent is back, so a failed start is retried a few times.
+async fn start_server(
+    state: &AppState,
+    node: &Node,
+    server: &MaintenanceServer,
+) -> Result<(), String> {
+    let uuid = server.id.to_string();
+    let request = PowerRequest {
+        action: PowerAction::Start,
+        grace: None,
+    };
+    let mut attempt = 0;
+    loop {
+        match node_api::power_container(
+            &state.http_client,
+            node,
+            &uuid,
+            &request,
+            &state.node_retry,
+        )
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt >= 3 => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                tokio::time::sleep(POLL_INTERVAL * 2).await;
+            }
+        }
+    }
+}
+
+/// Final update state ("completed", "failed") reported after `requested_at`, or `None`
+/// when the node stays silent past the timeout.
+async fn wait_for_update(
+    state: &AppState,
+    node_id: &str,
+    requested_at: DateTime<Utc>,
+) -> Option<String> {
+    let deadline = Instant::now() + return_timeout();
+    while Instant::now() < deadline {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if let Some(status) = node_versions::update_status(&state.db, node_id).await
+            && status.updated_at > requested_at
+            && matches!(status.state.as_str(), "completed" | "failed")
+        {
+            return Some(match status.message {
+                Some(message) => format!("{}: {}", status.state, message),
+                None => status.state,
+            });
+        }
+    }
+    None
+}
+
+/// Whether a heartbeat from a freshly booted host arrived before the timeout: its uptime
+/// must be shorter than the time since the reboot was requested.
+async fn wait_for_reboot(state: &AppState, node_id: &str, requested_at: Instant) -> bool {
+    let deadline = requested_at + return_timeout();
+    while Instant::now() < deadline {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if let Some(stats) = state.node_stats(node_id).await
+            && stats.uptime < requested_at.elapsed().as_secs()
+        {
+            return true;
+        }
+    }
+    false
+}
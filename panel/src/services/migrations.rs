@@ -0,0 +1,48 @@
+//! Schema migrations from `panel/migrations`, embedded into the binary at build time.
+//! Schema changes ship as a new numbered `.sql` file there, never as runtime DDL.
+
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// `RUN_MIGRATIONS=false` leaves the schema to operators who migrate out-of-band.
+fn enabled_from_env() -> bool {
+    !matches!(
+        std::env::var("RUN_MIGRATIONS").as_deref(),
+        Ok("false") | Ok("0") | Ok("no")
+    )
+}
+
+/// Applies pending migrations (unless disabled) and returns the schema version the
+/// database is at afterwards.
+pub async fn run(pool: &PgPool) -> Option<i64> {
+    if enabled_from_env() {
+        MIGRATOR
+            .run(pool)
+            .await
+            .expect("Failed to run database migrations");
+    } else {
+        tracing::info!("RUN_MIGRATIONS=false, not touching the database schema");
+    }
+
+    let applied = applied_version(pool).await;
+    let latest = MIGRATOR.iter().map(|m| m.version).max();
+    if applied < latest {
+        tracing::warn!(
+            "Database schema is at version {:?} but this panel expects {:?}; apply the pending migrations",
+            applied,
+            latest
+        );
+    }
+    applied
+}
+
+/// Latest successfully applied migration, `None` when the database was never migrated.
+pub async fn applied_version(pool: &PgPool) -> Option<i64> {
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await
+        .ok()
+        .flatten()
+}
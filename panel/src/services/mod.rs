@@ -0,0 +1,33 @@
+pub mod allocations;
+pub mod bandwidth;
+pub mod delete_previews;
+pub mod disks;
+pub mod host_ports;
+pub mod images;
+pub mod install_logs;
+pub mod install_tokens;
+pub mod janitor;
+pub mod jobs;
+pub mod locations;
+pub mod maintenance;
+pub mod migrations;
+pub mod node_api;
+pub mod node_cleanup;
+pub mod node_events;
+pub mod node_tokens;
+pub mod node_versions;
+pub mod nodes_cache;
+pub mod placement;
+pub mod port_audit;
+pub mod provisioning;
+pub mod rate_limit;
+pub mod redis_cache;
+pub mod reservations;
+pub mod secrets;
+pub mod server_events;
+pub mod server_presets;
+pub mod server_secrets;
+pub mod signed_urls;
+pub mod token_locks;
+pub mod uptime;
+pub mod versions;
@@ -0,0 +1,817 @@
+use crate::models::{
+    ContainerPortBinding, ContainerState, CreateContainerRequest, FreePorts, InstallTestRequest,
+    Node, NodeErrorResponse, PortHolder, PowerRequest, StoredLogs, UpdateLimitsRequest,
+    UpdateLimitsResponse,
+};
+use futures_util::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Retry policy for panel -> node calls that must not be lost to a network blip.
+#[derive(Debug, Clone)]
+pub struct NodeRetryConfig {
+    pub attempts: u32,
+    pub timeout: Duration,
+    pub base_delay: Duration,
+}
+
+impl NodeRetryConfig {
+    pub fn from_env() -> Self {
+        let attempts = std::env::var("NODE_REQUEST_RETRIES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(5)
+            .max(1);
+        let timeout = std::env::var("NODE_REQUEST_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(10);
+
+        Self {
+            attempts,
+            timeout: Duration::from_secs(timeout),
+            base_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// Exponential backoff: base, 2x base, 4x base ... capped at 30s.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        (self.base_delay * factor).min(Duration::from_secs(30))
+    }
+}
+
+/// Caps how many container operations the panel runs against one node at a time.
+/// Extra callers wait in line instead of stampeding a small host.
+pub struct NodeOpLimiter {
+    max: usize,
+    nodes: Mutex<HashMap<String, NodeQueue>>,
+}
+
+struct NodeQueue {
+    permits: Arc<Semaphore>,
+    waiting: usize,
+}
+
+impl NodeOpLimiter {
+    pub fn from_env() -> Self {
+        let max = std::env::var("NODE_MAX_CONCURRENT_OPS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(3)
+            .max(1);
+
+        Self {
+            max,
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Operations running or waiting on this node.
+    pub fn pending(&self, node_id: &str) -> usize {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .get(node_id)
+            .map_or(0, |q| self.max - q.permits.available_permits() + q.waiting)
+    }
+
+    /// How many operations a new one would have to wait behind (0 = runs immediately).
+    pub fn queued_behind(&self, node_id: &str) -> usize {
+        let pending = self.pending(node_id);
+        if pending < self.max {
+            0
+        } else {
+            pending - self.max + 1
+        }
+    }
+
+    pub async fn acquire(&self, node_id: &str) -> OwnedSemaphorePermit {
+        let permits = {
+            let mut nodes = self.nodes.lock().unwrap();
+            let queue = nodes
+                .entry(node_id.to_string())
+                .or_insert_with(|| NodeQueue {
+                    permits: Arc::new(Semaphore::new(self.max)),
+                    waiting: 0,
+                });
+            queue.waiting += 1;
+            queue.permits.clone()
+        };
+
+        let permit = permits
+            .acquire_owned()
+            .await
+            .expect("node semaphore closed");
+
+        if let Some(queue) = self.nodes.lock().unwrap().get_mut(node_id) {
+            queue.waiting -= 1;
+        }
+        permit
+    }
+}
+
+/// A failed call to a node's REST API, decoded from its JSON error body.
+#[derive(Debug)]
+pub struct NodeError {
+    pub status: reqwest::StatusCode,
+    pub code: String,
+    pub message: String,
+    /// `Retry-After` seconds sent with 429 responses from a busy node
+    pub retry_after: Option<u64>,
+    /// Occupied host ports reported with `port_in_use`
+    pub ports: Vec<u16>,
+    /// Managed containers publishing some of `ports`
+    pub holders: Vec<PortHolder>,
+}
+
+impl NodeError {
+    /// Node rejected the panel's token. Older agents answer 500 without a body.
+    pub fn is_auth_failure(&self) -> bool {
+        self.code == "unauthorized" || self.status == reqwest::StatusCode::UNAUTHORIZED
+    }
+}
+
+impl std::fmt::Display for NodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.status, self.code, self.message)
+    }
+}
+
+pub async fn read_node_error(resp: reqwest::Response) -> NodeError {
+    let status = resp.status();
+    let retry_after = resp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let body = resp.text().await.unwrap_or_default();
+
+    match serde_json::from_str::<NodeErrorResponse>(&body) {
+        Ok(err) => NodeError {
+            status,
+            code: err.code,
+            message: err.error,
+            retry_after,
+            ports: err.ports,
+            holders: err.holders,
+        },
+        Err(_) => NodeError {
+            status,
+            code: "unknown".to_string(),
+            message: body,
+            retry_after,
+            ports: Vec::new(),
+            holders: Vec::new(),
+        },
+    }
+}
+
+/// Extra time a container create may take when the node might pull the image
+/// (`NODE_IMAGE_PULL_TIMEOUT`, seconds).
+fn image_pull_timeout() -> Duration {
+    Duration::from_secs(
+        std::env::var("NODE_IMAGE_PULL_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(300),
+    )
+}
+
+/// A container create that failed for good.
+#[derive(Debug)]
+pub struct CreateContainerError {
+    pub message: String,
+    /// Managed containers the node said hold the requested ports
+    pub holders: Vec<PortHolder>,
+}
+
+/// Asks the node to create (and start) a container, retrying transient failures.
+/// Auth failures and other 4xx answers are final: retrying won't change the answer.
+pub async fn create_container(
+    client: &reqwest::Client,
+    node: &Node,
+    payload: &CreateContainerRequest,
+    retry: &NodeRetryConfig,
+) -> Result<(), CreateContainerError> {
+    let url = format!("http://{}:{}/containers", node.ip, node.port);
+    let mut last_error = String::new();
+    let mut holders = Vec::new();
+    let mut retry_hint = None;
+    // The node answers only after pulling the image, which can take minutes
+    let timeout = if payload.pull_policy == "never" {
+        retry.timeout
+    } else {
+        retry.timeout + image_pull_timeout()
+    };
+
+    for attempt in 1..=retry.attempts {
+        let res = client
+            .post(&url)
+            .bearer_auth(&node.token)
+            .timeout(timeout)
+            .json(payload)
+            .send()
+            .await;
+
+        match res {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => {
+                let err = read_node_error(resp).await;
+                last_error = if err.ports.is_empty() {
+                    err.to_string()
+                } else {
+                    let ports: Vec<String> = err.ports.iter().map(|p| p.to_string()).collect();
+                    format!(
+                        "Port(s) {} already in use on node {}",
+                        ports.join(", "),
+                        node.name
+                    )
+                };
+                // Auth failures and the node's 4xx verdicts (ports taken, image missing) are final
+                if err.is_auth_failure()
+                    || (err.status.is_client_error()
+                        && err.status != reqwest::StatusCode::TOO_MANY_REQUESTS)
+                {
+                    holders = err.holders;
+                    break;
+                }
+                // Busy node tells us when to come back
+                retry_hint = err.retry_after.map(Duration::from_secs);
+            }
+            Err(e) => last_error = e.to_string(),
+        }
+
+        tracing::warn!(
+            "Container create on node {} failed (attempt {}/{}): {}",
+            node.name,
+            attempt,
+            retry.attempts,
+            last_error
+        );
+
+        if attempt < retry.attempts {
+            let delay = retry_hint
+                .take()
+                .unwrap_or_else(|| retry.delay_for(attempt));
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    Err(CreateContainerError {
+        message: last_error,
+        holders,
+    })
+}
+
+/// Removes a server's container from its node, giving it `grace` seconds to stop before the
+/// node kills it. With `purge` the server's data volume is deleted as well. Returns whether
+/// the node had to kill it. A container the node doesn't know is already gone.
+pub async fn stop_and_delete_container(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    grace: Option<u64>,
+    purge: bool,
+    retry: &NodeRetryConfig,
+) -> Result<bool, String> {
+    let mut url = format!(
+        "http://{}:{}/containers/{}?purge={}",
+        node.ip, node.port, uuid, purge
+    );
+    if let Some(grace) = grace {
+        url.push_str(&format!("&grace={}", grace));
+    }
+    // The node waits out the grace period (plus a little slack) before it answers
+    let timeout = retry.timeout + Duration::from_secs(grace.unwrap_or(10) + 5);
+    let res = client
+        .delete(&url)
+        .bearer_auth(&node.token)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status().is_success() {
+        // Older agents answer with the bare string "deleted"
+        let killed = res
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|v| v.get("killed").and_then(|k| k.as_bool()))
+            .unwrap_or(false);
+        return Ok(killed);
+    }
+    let err = read_node_error(res).await;
+    if err.code == "container_not_found" {
+        return Ok(false);
+    }
+    Err(err.to_string())
+}
+
+/// Applies new resource limits to a running container. Returns the node's warnings.
+pub async fn update_container_limits(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    limits: &UpdateLimitsRequest,
+    retry: &NodeRetryConfig,
+) -> Result<Vec<String>, String> {
+    let url = format!(
+        "http://{}:{}/containers/{}/limits",
+        node.ip, node.port, uuid
+    );
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .json(limits)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    res.json::<UpdateLimitsResponse>()
+        .await
+        .map(|r| r.warnings)
+        .map_err(|e| e.to_string())
+}
+
+/// Writes one console line to a server's container (`POST /containers/{uuid}/command`).
+pub async fn send_command(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    command: &str,
+    retry: &NodeRetryConfig,
+) -> Result<(), String> {
+    let url = format!(
+        "http://{}:{}/containers/{}/command",
+        node.ip, node.port, uuid
+    );
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .json(&serde_json::json!({ "command": command }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    Ok(())
+}
+
+/// Sends a power action to a server's container. Returns the state the node saw right after.
+pub async fn power_container(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    request: &PowerRequest,
+    retry: &NodeRetryConfig,
+) -> Result<ContainerState, String> {
+    let url = format!("http://{}:{}/containers/{}/power", node.ip, node.port, uuid);
+    // Stop and restart answer only once the grace period has run its course
+    let timeout = retry.timeout + Duration::from_secs(request.grace.unwrap_or(10) + 5);
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(timeout)
+        .json(request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    res.json::<ContainerState>()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Current Docker state of a server's container (`GET /containers/{uuid}/state`).
+pub async fn container_state(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    retry: &NodeRetryConfig,
+) -> Result<ContainerState, String> {
+    let url = format!("http://{}:{}/containers/{}/state", node.ip, node.port, uuid);
+    let res = client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    res.json::<ContainerState>()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Raw `docker inspect` of a server's container, or `None` when the node has no such container.
+pub async fn inspect_container(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    retry: &NodeRetryConfig,
+) -> Result<Option<serde_json::Value>, String> {
+    let url = format!(
+        "http://{}:{}/containers/{}/inspect",
+        node.ip, node.port, uuid
+    );
+    let res = client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        let err = read_node_error(res).await;
+        if err.code == "container_not_found" {
+            return Ok(None);
+        }
+        return Err(err.to_string());
+    }
+    res.json::<serde_json::Value>()
+        .await
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Host ports the node's managed containers publish, stopped ones included.
+pub async fn port_bindings(
+    client: &reqwest::Client,
+    node: &Node,
+    retry: &NodeRetryConfig,
+) -> Result<Vec<ContainerPortBinding>, String> {
+    let url = format!("http://{}:{}/port-bindings", node.ip, node.port);
+    let res = client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err("the node agent is too old to report port bindings; update it".to_string());
+    }
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    res.json::<Vec<ContainerPortBinding>>()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Host ports in `start..=end` nothing on the node listens on.
+pub async fn free_ports(
+    client: &reqwest::Client,
+    node: &Node,
+    start: u16,
+    end: u16,
+    retry: &NodeRetryConfig,
+) -> Result<Vec<u16>, String> {
+    let url = format!(
+        "http://{}:{}/free-ports?start={}&end={}",
+        node.ip, node.port, start, end
+    );
+    let res = client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err("the node agent is too old to report free ports; update it".to_string());
+    }
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await.to_string());
+    }
+    res.json::<FreePorts>()
+        .await
+        .map(|f| f.free)
+        .map_err(|e| e.to_string())
+}
+
+/// The last `tail` lines of a server's output the node kept on disk, or `None` when it has
+/// none stored.
+pub async fn stored_logs(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    tail: usize,
+    retry: &NodeRetryConfig,
+) -> Result<Option<StoredLogs>, String> {
+    let url = format!(
+        "http://{}:{}/servers/{}/stored-logs?tail={}&ansi=strip",
+        node.ip, node.port, uuid, tail
+    );
+    let res = client
+        .get(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        let err = read_node_error(res).await;
+        if err.code == "no_stored_logs" {
+            return Ok(None);
+        }
+        if err.status == reqwest::StatusCode::NOT_FOUND {
+            return Err("the node agent is too old to store logs; update it".to_string());
+        }
+        return Err(err.to_string());
+    }
+    res.json::<StoredLogs>()
+        .await
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Starts an install script test on the node. The returned response body is the node's
+/// NDJSON event stream; no timeout is set since the node enforces its own.
+pub async fn start_install_test(
+    client: &reqwest::Client,
+    node: &Node,
+    request: &InstallTestRequest,
+) -> Result<reqwest::Response, String> {
+    let url = format!("http://{}:{}/install-test", node.ip, node.port);
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .json(request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let err = read_node_error(res).await;
+    if err.code == "node_busy" {
+        return Err(format!(
+            "{} is busy with another install test, try again shortly",
+            node.name
+        ));
+    }
+    Err(err.to_string())
+}
+
+/// Starts a server's install script on the node (`POST /containers/{uuid}/install`). Like
+/// `start_install_test`, the body is the NDJSON event stream and the node enforces the timeout.
+pub async fn start_server_install(
+    client: &reqwest::Client,
+    node: &Node,
+    uuid: &str,
+    request: &InstallTestRequest,
+) -> Result<reqwest::Response, String> {
+    let url = format!(
+        "http://{}:{}/containers/{}/install",
+        node.ip, node.port, uuid
+    );
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .json(request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    Err(read_node_error(res).await.to_string())
+}
+
+/// Asks the agent to update itself (`POST /self-update`), without interrupting running
+/// operations. Progress arrives with the node's heartbeats, see `node_versions::record_update`.
+pub async fn request_self_update(
+    client: &reqwest::Client,
+    node: &Node,
+    retry: &NodeRetryConfig,
+) -> Result<(), NodeError> {
+    let url = format!("http://{}:{}/self-update", node.ip, node.port);
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(connection_error)?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await);
+    }
+    Ok(())
+}
+
+/// Asks the agent to reboot its host (`POST /host/reboot`). Agents answer `reboot_disabled`
+/// unless their config sets a `reboot_command`.
+pub async fn reboot_host(
+    client: &reqwest::Client,
+    node: &Node,
+    retry: &NodeRetryConfig,
+) -> Result<(), NodeError> {
+    let url = format!("http://{}:{}/host/reboot", node.ip, node.port);
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .send()
+        .await
+        .map_err(connection_error)?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await);
+    }
+    Ok(())
+}
+
+/// Hands the node a new token (`POST /update-token`), authenticated with `node.token`. The
+/// node checks the new token with a heartbeat before it keeps it, so the panel must already
+/// accept it.
+pub async fn update_token(
+    client: &reqwest::Client,
+    node: &Node,
+    new_token: &str,
+    retry: &NodeRetryConfig,
+) -> Result<(), NodeError> {
+    let url = format!("http://{}:{}/update-token", node.ip, node.port);
+    let res = client
+        .post(&url)
+        .bearer_auth(&node.token)
+        .timeout(retry.timeout)
+        .json(&serde_json::json!({ "token": new_token }))
+        .send()
+        .await
+        .map_err(connection_error)?;
+
+    if !res.status().is_success() {
+        return Err(read_node_error(res).await);
+    }
+    Ok(())
+}
+
+/// A request that never got an answer, shaped like a node error
+fn connection_error(e: reqwest::Error) -> NodeError {
+    NodeError {
+        status: reqwest::StatusCode::BAD_GATEWAY,
+        code: "connection_failed".to_string(),
+        message: e.to_string(),
+        retry_after: None,
+        ports: Vec::new(),
+        holders: Vec::new(),
+    }
+}
+
+/// Asks a node for its agent version (`GET /version`, no token needed). None for agents
+/// that predate the endpoint or can't be reached in time.
+pub async fn fetch_version(client: &reqwest::Client, node: &Node) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct VersionResponse {
+        version: String,
+    }
+
+    let url = format!("http://{}:{}/version", node.ip, node.port);
+    let res = client
+        .get(&url)
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await
+        .ok()
+        .filter(|r| r.status().is_success())?;
+    res.json::<VersionResponse>().await.ok().map(|v| v.version)
+}
+
+/// `/health` probes for nodes that stopped sending heartbeats. Probes run concurrently
+/// with a short timeout, and failures are remembered briefly so page reloads don't
+/// keep waiting on dead hosts.
+pub struct HealthProbes {
+    timeout: Duration,
+    concurrency: usize,
+    negative_ttl: Duration,
+    failed: Mutex<HashMap<String, Instant>>,
+}
+
+impl HealthProbes {
+    pub fn from_env() -> Self {
+        let env = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            timeout: Duration::from_millis(env("NODE_HEALTH_TIMEOUT_MS", 750)),
+            concurrency: env("NODE_HEALTH_CONCURRENCY", 8).max(1) as usize,
+            negative_ttl: Duration::from_secs(env("NODE_HEALTH_NEGATIVE_TTL", 30)),
+            failed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn recently_failed(&self, node_id: &str) -> bool {
+        let failed = self.failed.lock().unwrap();
+        failed
+            .get(node_id)
+            .is_some_and(|at| at.elapsed() < self.negative_ttl)
+    }
+
+    /// Ids of the given nodes that answered `/health`.
+    pub async fn probe_all(&self, client: &reqwest::Client, nodes: Vec<Node>) -> HashSet<String> {
+        let to_probe: Vec<Node> = nodes
+            .into_iter()
+            .filter(|n| !self.recently_failed(&n.id))
+            .collect();
+
+        let results: Vec<(String, bool)> = stream::iter(to_probe)
+            .map(|node| async move {
+                let url = format!("http://{}:{}/health", node.ip, node.port);
+                let ok = client
+                    .get(&url)
+                    .timeout(self.timeout)
+                    .send()
+                    .await
+                    .is_ok_and(|r| r.status().is_success());
+                (node.id, ok)
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        let mut failed = self.failed.lock().unwrap();
+        let mut reachable = HashSet::new();
+        for (id, ok) in results {
+            if ok {
+                failed.remove(&id);
+                reachable.insert(id);
+            } else {
+                failed.insert(id, Instant::now());
+            }
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_thirty_seconds() {
+        let retry = NodeRetryConfig {
+            attempts: 10,
+            timeout: Duration::from_secs(10),
+            base_delay: Duration::from_secs(1),
+        };
+        let delays: Vec<u64> = (1..=7).map(|a| retry.delay_for(a).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(retry.delay_for(u32::MAX), Duration::from_secs(30));
+    }
+
+    fn node_response(status: u16, body: &str) -> reqwest::Response {
+        axum::http::Response::builder()
+            .status(status)
+            .body(body.to_string())
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn reads_port_conflicts_from_the_node() {
+        let body = r#"{"error": "Ports already bound", "code": "port_in_use", "ports": [25565],
+            "holders": [{"port": 25565, "container": "yunexal-survival", "server_id": "abc"}]}"#;
+        let err = read_node_error(node_response(409, body)).await;
+        assert_eq!(err.status, reqwest::StatusCode::CONFLICT);
+        assert_eq!(err.code, "port_in_use");
+        assert_eq!(err.ports, [25565]);
+        assert_eq!(err.holders[0].container, "yunexal-survival");
+        assert!(!err.is_auth_failure());
+    }
+
+    #[tokio::test]
+    async fn keeps_a_body_that_is_not_a_node_error() {
+        let err = read_node_error(node_response(502, "Bad Gateway")).await;
+        assert_eq!(err.code, "unknown");
+        assert_eq!(err.message, "Bad Gateway");
+        assert!(err.ports.is_empty());
+    }
+}
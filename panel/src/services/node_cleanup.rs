@@ -0,0 +1,100 @@
+use crate::models::MAX_STOP_TIMEOUT;
+use crate::services::node_api;
+use crate::state::AppState;
+
+/// Optional cap on each server's stop timeout when its node is deleted
+/// (`NODE_DELETE_STOP_GRACE`), so removing a node full of slow stoppers doesn't take ages.
+/// The node kills whatever is still running afterwards.
+pub fn stop_grace_cap() -> Option<u64> {
+    std::env::var("NODE_DELETE_STOP_GRACE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
+/// What happened to a node's containers during node deletion.
+#[derive(Debug, Default)]
+pub struct CleanupSummary {
+    pub removed: usize,
+    /// Removed, but only after ignoring the stop and being killed
+    pub killed: usize,
+    /// `(server id, error)` for containers that may still be on the host
+    pub failed: Vec<(String, String)>,
+}
+
+/// Stops and removes the container of every server on the node. A container that fails
+/// to go away is recorded and skipped, so one hung container can't block node removal.
+pub async fn remove_containers(state: &AppState, node_id: &str) -> CleanupSummary {
+    let mut summary = CleanupSummary::default();
+
+    let servers: Vec<(String, i32)> = match sqlx::query_as(
+        "SELECT id::text, stop_timeout_seconds FROM servers WHERE node_id = $1::uuid",
+    )
+    .bind(node_id)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("Failed to list servers on node {}: {}", node_id, e);
+            return summary;
+        }
+    };
+    if servers.is_empty() {
+        return summary;
+    }
+
+    let Some(node) = state.get_node_with_token(node_id).await else {
+        summary.failed = servers
+            .into_iter()
+            .map(|(id, _)| (id, "node not found".to_string()))
+            .collect();
+        return summary;
+    };
+
+    let cap = stop_grace_cap();
+    let removals = servers.iter().map(|(server_id, stop_timeout)| {
+        let node = &node;
+        let grace = (*stop_timeout).clamp(1, MAX_STOP_TIMEOUT) as u64;
+        let grace = cap.map_or(grace, |cap| grace.min(cap));
+        async move {
+            // Same per-node cap as every other container operation
+            let _permit = state.node_ops.acquire(&node.id).await;
+            let result = node_api::stop_and_delete_container(
+                &state.http_client,
+                node,
+                server_id,
+                Some(grace),
+                true,
+                &state.node_retry,
+            )
+            .await;
+            (server_id.clone(), result)
+        }
+    });
+
+    for (server_id, result) in futures_util::future::join_all(removals).await {
+        match result {
+            Ok(killed) => {
+                summary.removed += 1;
+                if killed {
+                    summary.killed += 1;
+                    tracing::warn!(
+                        "Container {} ignored the stop on node deletion and was killed",
+                        server_id
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to remove container {} from node {}: {}",
+                    server_id,
+                    node_id,
+                    e
+                );
+                summary.failed.push((server_id, e));
+            }
+        }
+    }
+
+    summary
+}
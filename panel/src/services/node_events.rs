@@ -0,0 +1,36 @@
+use crate::models::NodeEvent;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Appends an entry to the node's event log. Failures are logged, never surfaced.
+pub async fn record(db: &PgPool, node_id: Uuid, kind: &str, message: &str) {
+    let res =
+        sqlx::query("INSERT INTO node_events (id, node_id, kind, message) VALUES ($1, $2, $3, $4)")
+            .bind(Uuid::new_v4())
+            .bind(node_id)
+            .bind(kind)
+            .bind(message)
+            .execute(db)
+            .await;
+
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to record {} event for node {}: {}",
+            kind,
+            node_id,
+            e
+        );
+    }
+}
+
+/// Newest first.
+pub async fn recent(db: &PgPool, node_id: Uuid, limit: i64) -> Vec<NodeEvent> {
+    sqlx::query_as::<_, NodeEvent>(
+        "SELECT kind, message, created_at FROM node_events WHERE node_id = $1 ORDER BY created_at DESC LIMIT $2",
+    )
+    .bind(node_id)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+}
@@ -0,0 +1,55 @@
+//! Node API tokens at rest. `nodes.token`, `nodes.pending_token` and the Redis
+//! `node:{id}:pending_token` key hold tokens encrypted under `APP_KEY`, so a database dump or
+//! a Redis snapshot doesn't hand over the nodes. Tokens are only decrypted where the panel
+//! presents or checks one: calls to the agent, the install script, the reveal and force-set
+//! actions and node authentication. Rows written before encryption stay plaintext, and keep
+//! working, until the `encrypt_node_tokens` binary runs.
+//!
+//! Also compiled into the `rotate_app_key` and `encrypt_node_tokens` binaries, so it must
+//! only depend on `secrets`.
+
+use super::secrets::{self, SecretError};
+use sha2::{Digest, Sha256};
+
+pub fn seal(key: &[u8], token: &str) -> String {
+    secrets::encrypt(key, token)
+}
+
+/// The token a stored value holds; plaintext from before encryption comes back as it is.
+pub fn open(key: &[u8], stored: &str) -> Result<String, SecretError> {
+    if secrets::is_encrypted(stored) {
+        secrets::decrypt(key, stored)
+    } else {
+        Ok(stored.to_string())
+    }
+}
+
+/// Like `open`, but an unreadable value (e.g. after a botched key rotation) is logged and
+/// comes back empty, which no presented token matches and no node accepts.
+pub fn open_or_empty(key: &[u8], node_id: &str, stored: &str) -> String {
+    open(key, stored).unwrap_or_else(|e| {
+        tracing::error!("Token of node {}: {}", node_id, e);
+        String::new()
+    })
+}
+
+/// Hex SHA-256 of a token, the only form authentication caches.
+pub fn sha256(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Compares without stopping at the first differing byte, so the time taken says nothing
+/// about how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether a presented token is the expected one, in constant time. An empty expected
+/// token matches nothing.
+pub fn matches(presented: &str, expected: &str) -> bool {
+    !expected.is_empty()
+        && constant_time_eq(sha256(presented).as_bytes(), sha256(expected).as_bytes())
+}
@@ -0,0 +1,168 @@
+use crate::{
+    models::{NodeUpdateReport, NodeUpdateStatus, NodeVersionChange},
+    state::AppState,
+};
+use sqlx::PgPool;
+
+/// Longest agent version string the panel will store.
+pub const MAX_VERSION_LEN: usize = 64;
+
+/// The reported version if it looks like one (`1.2.3`, `0.4.0-beta+abc`), otherwise `None`.
+/// It comes from the node and is rendered on several pages, so anything odd is dropped.
+pub fn sanitize(version: &str) -> Option<&str> {
+    let version = version.trim();
+    let valid = !version.is_empty()
+        && version.len() <= MAX_VERSION_LEN
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'));
+    valid.then_some(version)
+}
+
+/// Persists a changed agent version and appends it to the node's history.
+///
+/// Runs off the heartbeat path. The row is locked and compared inside a transaction, so
+/// overlapping heartbeats reporting the same new version record it only once.
+pub async fn record_change(state: AppState, node_id: String, version: String) {
+    let mut tx = match state.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            tracing::error!("Failed to start version update for node {}: {}", node_id, e);
+            return;
+        }
+    };
+
+    let current: Option<Option<String>> =
+        match sqlx::query_scalar("SELECT version FROM nodes WHERE id = $1::uuid FOR UPDATE")
+            .bind(&node_id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("Failed to read version of node {}: {}", node_id, e);
+                return;
+            }
+        };
+    let Some(current) = current else {
+        return;
+    };
+    if current.as_deref() == Some(version.as_str()) {
+        return;
+    }
+
+    let res = async {
+        sqlx::query("UPDATE nodes SET version = $1 WHERE id = $2::uuid")
+            .bind(&version)
+            .bind(&node_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO node_version_history (node_id, from_version, to_version) VALUES ($1::uuid, $2, $3)")
+            .bind(&node_id)
+            .bind(current.as_deref().filter(|v| !v.is_empty()))
+            .bind(&version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to record version change for node {}: {}",
+            node_id,
+            e
+        );
+        return;
+    }
+
+    tracing::info!(
+        "Node {} agent version {} -> {}",
+        node_id,
+        current.as_deref().unwrap_or("unknown"),
+        version
+    );
+
+    // Heartbeat auth cache carries the version; node lists show it
+    if let Some(mut con) = state.redis.connection() {
+        let key = format!("node:{}:cache", node_id);
+        let res: Result<(), _> = redis::AsyncCommands::del(&mut con, key).await;
+        state.redis.observe(&res);
+    }
+    state.invalidate_nodes_cache().await;
+}
+
+/// Newest first.
+pub async fn recent(db: &PgPool, node_id: &str, limit: i64) -> Vec<NodeVersionChange> {
+    sqlx::query_as::<_, NodeVersionChange>(
+        "SELECT from_version, to_version, changed_at FROM node_version_history WHERE node_id = $1::uuid ORDER BY changed_at DESC LIMIT $2",
+    )
+    .bind(node_id)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+}
+
+/// Stores the update state the panel or agent last reported for a node.
+pub async fn set_update_state(db: &PgPool, node_id: &str, state: &str, message: Option<&str>) {
+    let res = sqlx::query("UPDATE nodes SET update_state = $1, update_message = $2, update_at = NOW() WHERE id = $3::uuid")
+        .bind(state)
+        .bind(message)
+        .bind(node_id)
+        .execute(db)
+        .await;
+    if let Err(e) = res {
+        tracing::error!("Failed to store update state of node {}: {}", node_id, e);
+    }
+}
+
+/// Runs off the heartbeat path, like `record_change`.
+pub async fn record_update(state: AppState, node_id: String, report: NodeUpdateReport) {
+    if !matches!(report.state.as_str(), "updating" | "completed" | "failed") {
+        tracing::warn!(
+            "Node {} reported an unknown update state, ignoring it",
+            node_id
+        );
+        return;
+    }
+    let version = sanitize(&report.version).unwrap_or("unknown");
+    // Agent-supplied and shown on the edit page; keep it short
+    let detail: Option<String> = report.message.map(|m| m.chars().take(500).collect());
+
+    match report.state.as_str() {
+        "updating" => tracing::info!("Node {} is restarting into a new agent version", node_id),
+        "completed" => tracing::info!(
+            "Node {} finished updating, now running {}",
+            node_id,
+            version
+        ),
+        _ => tracing::warn!(
+            "Node {} failed to update: {}",
+            node_id,
+            detail.as_deref().unwrap_or("no details")
+        ),
+    }
+
+    let message = match (report.state.as_str(), detail) {
+        ("completed", Some(detail)) => {
+            format!("Update completed, version {} ({})", version, detail)
+        }
+        ("completed", None) => format!("Update completed, version {}", version),
+        (_, Some(detail)) => detail,
+        ("updating", None) => "Updating, back soon".to_string(),
+        (_, None) => "Update failed".to_string(),
+    };
+    set_update_state(&state.db, &node_id, &report.state, Some(&message)).await;
+}
+
+pub async fn update_status(db: &PgPool, node_id: &str) -> Option<NodeUpdateStatus> {
+    sqlx::query_as::<_, NodeUpdateStatus>(
+        "SELECT update_state AS state, update_message AS message, update_at AS updated_at
+         FROM nodes WHERE id = $1::uuid AND update_state IS NOT NULL AND update_at IS NOT NULL",
+    )
+    .bind(node_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or(None)
+}
@@ -0,0 +1,57 @@
+//! Keeps every panel instance's nodes cache current. Invalidations are published on Redis
+//! so other instances drop their RAM copy too, and a periodic reload picks up rows edited
+//! straight in the DB (or invalidations missed while Redis was down).
+
+use crate::state::AppState;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub const INVALIDATE_CHANNEL: &str = "cache:nodes:invalidate";
+
+/// `NODES_CACHE_REFRESH` seconds between reloads (default 60, 0 turns them off).
+pub fn refresh_interval() -> Option<Duration> {
+    let secs = std::env::var("NODES_CACHE_REFRESH")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(60);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+pub async fn run(state: AppState) {
+    let mut invalidations = state.redis.subscribe(INVALIDATE_CHANNEL);
+    let mut refresh = refresh_interval().map(|every| {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+
+    loop {
+        tokio::select! {
+            Some(_) = next_message(&mut invalidations) => state.clear_local_nodes_cache().await,
+            _ = tick(&mut refresh) => state.refresh_nodes_cache().await,
+        }
+    }
+}
+
+/// Pends forever without Redis, or once the subscription task is gone.
+async fn next_message(rx: &mut Option<mpsc::Receiver<String>>) -> Option<String> {
+    match rx {
+        Some(inner) => match inner.recv().await {
+            Some(msg) => Some(msg),
+            None => {
+                *rx = None;
+                std::future::pending().await
+            }
+        },
+        None => std::future::pending().await,
+    }
+}
+
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,148 @@
+use crate::state::AppState;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What placement knows about a node when choosing where a new server goes.
+#[derive(Debug, Clone)]
+pub struct NodeCandidate {
+    pub id: String,
+    pub name: String,
+    pub online: bool,
+    /// Node RAM limit in MB; 0 means unlimited
+    pub ram_limit: i64,
+    /// Sum of `ram_limit` over servers already placed on the node
+    pub ram_committed: i64,
+}
+
+impl NodeCandidate {
+    fn ram_free(&self) -> i64 {
+        if self.ram_limit == 0 {
+            i64::MAX
+        } else {
+            self.ram_limit - self.ram_committed
+        }
+    }
+
+    fn fits(&self, ram_needed: i64) -> bool {
+        self.ram_free() >= ram_needed
+    }
+}
+
+/// Orders candidates: online with room, then online without room, then offline.
+/// Within each group the node with the most free RAM wins; ties break on name, then id,
+/// so the same inputs always give the same node.
+pub fn pick_node(candidates: &[NodeCandidate], ram_needed: i64) -> Option<String> {
+    rank_nodes(candidates, ram_needed).into_iter().next()
+}
+
+/// Every candidate's id, best first, in `pick_node`'s order.
+pub fn rank_nodes(candidates: &[NodeCandidate], ram_needed: i64) -> Vec<String> {
+    let rank = |c: &NodeCandidate| match (c.online, c.fits(ram_needed)) {
+        (true, true) => 0,
+        (true, false) => 1,
+        (false, _) => 2,
+    };
+    let mut ranked: Vec<&NodeCandidate> = candidates.iter().collect();
+    ranked.sort_by(|a, b| {
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| b.ram_free().cmp(&a.ram_free()))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    ranked.into_iter().map(|c| c.id.clone()).collect()
+}
+
+/// RAM committed to servers per node, in MB.
+pub async fn committed_ram(db: &sqlx::PgPool) -> HashMap<String, i64> {
+    sqlx::query_as::<_, (String, i64)>(
+        "SELECT node_id::text, COALESCE(SUM(ram_limit), 0)::bigint FROM servers GROUP BY node_id",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .collect()
+}
+
+/// Builds candidates from the cache, live heartbeats and committed RAM: every known node,
+/// or only those in `location` when the create form picked one.
+pub async fn candidates(state: &AppState, location: Option<Uuid>) -> Vec<NodeCandidate> {
+    let committed = committed_ram(&state.db).await;
+    let location = location.map(|id| id.to_string());
+    let mut out = Vec::new();
+    for node in state.get_nodes().await {
+        if location.as_ref().is_some_and(|l| *l != node.location_id) {
+            continue;
+        }
+        let online = state.node_stats(&node.id).await.is_some();
+        out.push(NodeCandidate {
+            ram_committed: committed.get(&node.id).copied().unwrap_or(0),
+            ram_limit: node.ram_limit as i64,
+            online,
+            name: node.name,
+            id: node.id,
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, online: bool, ram_limit: i64, ram_committed: i64) -> NodeCandidate {
+        NodeCandidate {
+            id: id.to_string(),
+            name: format!("node-{}", id),
+            online,
+            ram_limit,
+            ram_committed,
+        }
+    }
+
+    #[test]
+    fn online_nodes_with_room_come_first() {
+        let candidates = [
+            node("offline", false, 0, 0),
+            node("full", true, 4096, 3072),
+            node("roomy", true, 8192, 2048),
+            node("tight", true, 4096, 2048),
+        ];
+        assert_eq!(
+            rank_nodes(&candidates, 2048),
+            ["roomy", "tight", "full", "offline"]
+        );
+        assert_eq!(pick_node(&candidates, 2048).as_deref(), Some("roomy"));
+    }
+
+    #[test]
+    fn unlimited_nodes_have_the_most_free_ram() {
+        let candidates = [
+            node("limited", true, 65536, 0),
+            node("unlimited", true, 0, 9000),
+        ];
+        assert_eq!(pick_node(&candidates, 1024).as_deref(), Some("unlimited"));
+    }
+
+    #[test]
+    fn ties_break_on_name_then_id() {
+        let mut b = node("b", true, 4096, 0);
+        b.name = "same".to_string();
+        let mut a = node("a", true, 4096, 0);
+        a.name = "same".to_string();
+        let c = node("c", true, 4096, 0);
+        assert_eq!(
+            rank_nodes(&[c.clone(), b.clone(), a.clone()], 0),
+            ["c", "a", "b"]
+        );
+        assert_eq!(rank_nodes(&[a, b, c], 0), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn offline_nodes_are_picked_only_when_nothing_is_online() {
+        assert_eq!(pick_node(&[], 0), None);
+        let candidates = [node("small", false, 1024, 0), node("big", false, 8192, 0)];
+        assert_eq!(pick_node(&candidates, 512).as_deref(), Some("big"));
+    }
+}
@@ -0,0 +1,183 @@
+//! Compares a node's allocations with the host ports its containers actually publish.
+//! Crashes, half-finished deletes or someone running `docker` by hand leave the two out of
+//! step; the audit lists where they differ so an admin can reconcile.
+
+use crate::models::{Allocation, ContainerPortBinding};
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// Assigned to a server whose container doesn't publish it
+    Unbound,
+    /// Published by a container, but not an allocation on this node
+    Unallocated,
+    /// Published by a container of a server other than the one it is assigned to
+    WrongServer,
+}
+
+impl MismatchKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            MismatchKind::Unbound => "Allocated, not bound",
+            MismatchKind::Unallocated => "Bound, not allocated",
+            MismatchKind::WrongServer => "Bound by another server",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortMismatch {
+    pub port: u16,
+    pub kind: MismatchKind,
+    /// Server the allocation is assigned to
+    pub allocated_to: Option<String>,
+    /// Container publishing the port, with its server and Docker state
+    pub container: Option<String>,
+    pub bound_by: Option<String>,
+    pub container_state: Option<String>,
+}
+
+/// Every port where `allocations` and `bindings` disagree, by port.
+pub fn audit(allocations: &[Allocation], bindings: &[ContainerPortBinding]) -> Vec<PortMismatch> {
+    let mut mismatches = Vec::new();
+
+    // IPv4 and IPv6 bindings list the same port twice
+    let mut seen = HashSet::new();
+    let bindings: Vec<&ContainerPortBinding> = bindings
+        .iter()
+        .filter(|b| seen.insert((b.host_port, b.container.as_str())))
+        .collect();
+
+    for alloc in allocations {
+        let Some(server_id) = alloc.server_id.as_deref() else {
+            continue;
+        };
+        let bound = bindings
+            .iter()
+            .any(|b| i32::from(b.host_port) == alloc.port && b.server_id == server_id);
+        if !bound && let Ok(port) = u16::try_from(alloc.port) {
+            mismatches.push(PortMismatch {
+                port,
+                kind: MismatchKind::Unbound,
+                allocated_to: Some(server_id.to_string()),
+                container: None,
+                bound_by: None,
+                container_state: None,
+            });
+        }
+    }
+
+    for binding in bindings {
+        let allocation = allocations
+            .iter()
+            .find(|a| a.port == i32::from(binding.host_port));
+        let kind = match allocation {
+            None => MismatchKind::Unallocated,
+            Some(a) if a.server_id.as_deref() != Some(binding.server_id.as_str()) => {
+                MismatchKind::WrongServer
+            }
+            Some(_) => continue,
+        };
+        mismatches.push(PortMismatch {
+            port: binding.host_port,
+            kind,
+            allocated_to: allocation.and_then(|a| a.server_id.clone()),
+            container: Some(binding.container.clone()),
+            bound_by: Some(binding.server_id.clone()).filter(|s| !s.is_empty()),
+            container_state: Some(binding.state.clone()),
+        });
+    }
+
+    mismatches.sort_by_key(|m| m.port);
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(port: i32, server_id: Option<&str>) -> Allocation {
+        Allocation {
+            id: format!("alloc-{}", port),
+            node_id: "node".to_string(),
+            ip: "0.0.0.0".to_string(),
+            port,
+            server_id: server_id.map(str::to_string),
+            auto_created: false,
+            notes: String::new(),
+            reserved: false,
+        }
+    }
+
+    fn binding(host_port: u16, host_ip: &str, server_id: &str) -> ContainerPortBinding {
+        ContainerPortBinding {
+            host_port,
+            host_ip: host_ip.to_string(),
+            container_port: format!("{}/tcp", host_port),
+            container: format!("yunexal-{}", server_id),
+            server_id: server_id.to_string(),
+            state: "running".to_string(),
+        }
+    }
+
+    fn kinds(mismatches: &[PortMismatch]) -> Vec<(u16, MismatchKind)> {
+        mismatches.iter().map(|m| (m.port, m.kind)).collect()
+    }
+
+    #[test]
+    fn matching_bindings_and_free_allocations_are_fine() {
+        let allocations = [allocation(25565, Some("a")), allocation(25566, None)];
+        // IPv4 and IPv6 of the same container count once
+        let bindings = [binding(25565, "0.0.0.0", "a"), binding(25565, "::", "a")];
+        assert!(audit(&allocations, &bindings).is_empty());
+    }
+
+    #[test]
+    fn each_kind_of_mismatch_is_reported_by_port() {
+        let allocations = [
+            allocation(25567, Some("b")),
+            allocation(25565, Some("a")),
+            allocation(25566, Some("c")),
+        ];
+        let bindings = [
+            binding(25566, "0.0.0.0", "a"),
+            binding(30000, "0.0.0.0", "gone"),
+        ];
+        let mismatches = audit(&allocations, &bindings);
+        assert_eq!(
+            kinds(&mismatches),
+            [
+                (25565, MismatchKind::Unbound),
+                (25566, MismatchKind::Unbound),
+                (25566, MismatchKind::WrongServer),
+                (25567, MismatchKind::Unbound),
+                (30000, MismatchKind::Unallocated),
+            ]
+        );
+
+        let wrong = &mismatches[2];
+        assert_eq!(wrong.allocated_to.as_deref(), Some("c"));
+        assert_eq!(wrong.bound_by.as_deref(), Some("a"));
+        assert_eq!(wrong.container.as_deref(), Some("yunexal-a"));
+        let unallocated = &mismatches[4];
+        assert_eq!(unallocated.allocated_to, None);
+        assert_eq!(unallocated.bound_by.as_deref(), Some("gone"));
+    }
+
+    #[test]
+    fn bindings_on_free_allocations_are_flagged_too() {
+        let allocations = [allocation(25565, None)];
+        let bindings = [binding(25565, "0.0.0.0", ""), binding(25570, "0.0.0.0", "")];
+        let mismatches = audit(&allocations, &bindings);
+        assert_eq!(
+            kinds(&mismatches),
+            [
+                (25565, MismatchKind::WrongServer),
+                (25570, MismatchKind::Unallocated),
+            ]
+        );
+        assert!(mismatches.iter().all(|m| m.bound_by.is_none()));
+    }
+}
@@ -0,0 +1,586 @@
+use crate::models::Node;
+use crate::models::{
+    CreateContainerRequest, InstallTestEvent, InstallTestRequest, PortHolder, Server, Variable,
+    command_mode_or_default, pull_policy_or_default,
+};
+use crate::services::jobs::JobContext;
+use crate::services::{node_api, server_events, server_secrets};
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Payload of a `create_container` job. Everything else is read back from the DB when the
+/// job runs, so no secret ever sits in the jobs table.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateContainerJob {
+    pub server_id: Uuid,
+}
+
+/// Payload of a `recreate_container` job; like `CreateContainerJob`, the rest comes from the DB.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecreateContainerJob {
+    pub server_id: Uuid,
+}
+
+/// Env the panel fills in itself, following Pterodactyl: `SERVER_MEMORY` (MB), `SERVER_IP` and
+/// `SERVER_PORT` (primary allocation, empty for portless servers) and `P_SERVER_UUID`.
+/// These names are reserved and win over an image variable of the same name.
+fn reserved_environment(
+    server: &Server,
+    primary: Option<(String, i32)>,
+) -> HashMap<String, String> {
+    let (ip, port) = primary
+        .map(|(ip, port)| (ip, port.to_string()))
+        .unwrap_or_default();
+    HashMap::from([
+        ("SERVER_MEMORY".to_string(), server.ram_limit.to_string()),
+        ("SERVER_IP".to_string(), ip),
+        ("SERVER_PORT".to_string(), port),
+        ("P_SERVER_UUID".to_string(), server.id.to_string()),
+    ])
+}
+
+/// The image's `config_files` as the node expects them: an object of file path -> rules.
+/// Empty (`{}` / `[]`) or malformed JSON is left out, so nothing is rewritten.
+fn config_file_rules(server: &Server, raw: &str) -> Option<serde_json::Value> {
+    match serde_json::from_str::<serde_json::Value>(raw) {
+        Ok(serde_json::Value::Object(files)) => {
+            (!files.is_empty()).then_some(serde_json::Value::Object(files))
+        }
+        // The column's old default
+        Ok(serde_json::Value::Array(rules)) if rules.is_empty() => None,
+        _ => {
+            tracing::warn!(
+                "Server {}: image config_files is not an object of file paths, skipping config rewrites",
+                server.id
+            );
+            None
+        }
+    }
+}
+
+/// Marker lines from the image's `start_config` (egg `config.startup`): `done` as a string
+/// or a list of strings. Anything else means the image has no marker.
+fn startup_done_markers(raw: &str) -> Vec<String> {
+    let done = serde_json::from_str::<serde_json::Value>(raw)
+        .ok()
+        .and_then(|config| config.get("done").cloned());
+    let markers = match done {
+        Some(serde_json::Value::String(marker)) => vec![marker],
+        Some(serde_json::Value::Array(markers)) => markers
+            .into_iter()
+            .filter_map(|m| m.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    };
+    markers
+        .into_iter()
+        .filter(|m| !m.trim().is_empty())
+        .collect()
+}
+
+/// Node request for a server as currently stored: limits, ports and environment.
+/// Secrets are decrypted here and nowhere earlier.
+pub async fn container_request(
+    state: &AppState,
+    server: &Server,
+) -> Result<CreateContainerRequest, String> {
+    let ports: Vec<i32> =
+        sqlx::query_scalar("SELECT port FROM allocations WHERE server_id = $1 ORDER BY port")
+            .bind(server.id)
+            .fetch_all(&state.db)
+            .await
+            .map_err(|e| format!("Failed to load allocations: {}", e))?;
+
+    let primary: Option<(String, i32)> = match server.allocation_id {
+        Some(allocation_id) => sqlx::query_as("SELECT ip, port FROM allocations WHERE id = $1")
+            .bind(allocation_id)
+            .fetch_optional(&state.db)
+            .await
+            .map_err(|e| format!("Failed to load primary allocation: {}", e))?,
+        None => None,
+    };
+
+    #[derive(sqlx::FromRow)]
+    struct ImageRow {
+        image_variables: String,
+        values: String,
+        run_as_user: String,
+        no_new_privileges: bool,
+        config_files: String,
+        command_mode: String,
+        start_config: String,
+    }
+
+    let ImageRow { image_variables, values, run_as_user, no_new_privileges, config_files, command_mode, start_config } = sqlx::query_as(
+        "SELECT COALESCE(i.variables::text, '[]') AS image_variables, COALESCE(s.variables::text, '{}') AS values, i.run_as_user, i.no_new_privileges, \
+                COALESCE(i.config_files, '{}') AS config_files, i.command_mode, COALESCE(i.start_config, '{}') AS start_config \
+         FROM servers s JOIN images i ON s.image_id = i.id WHERE s.id = $1",
+    )
+    .bind(server.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| format!("Failed to load variables: {}", e))?
+    .unwrap_or_else(|| ImageRow {
+        image_variables: "[]".to_string(),
+        values: "{}".to_string(),
+        run_as_user: String::new(),
+        no_new_privileges: true,
+        config_files: "{}".to_string(),
+        command_mode: String::new(),
+        start_config: "{}".to_string(),
+    });
+
+    let variables = serde_json::from_str::<Vec<Variable>>(&image_variables).unwrap_or_default();
+    let values = serde_json::from_str::<HashMap<String, String>>(&values).unwrap_or_default();
+    let mut secrets = server_secrets::decrypted(&state.db, &state.secrets_key, server.id).await;
+
+    let mut environment: HashMap<String, String> = variables
+        .into_iter()
+        .map(|v| {
+            let value = if v.is_secret {
+                secrets.remove(&v.env_variable).unwrap_or(v.default_value)
+            } else if v.user_editable {
+                values
+                    .get(&v.env_variable)
+                    .cloned()
+                    .unwrap_or(v.default_value)
+            } else {
+                v.default_value
+            };
+            (v.env_variable, value)
+        })
+        .collect();
+
+    for (name, value) in reserved_environment(server, primary) {
+        if environment.contains_key(&name) {
+            tracing::warn!(
+                "Server {}: image variable {} is reserved, using the panel's value",
+                server.id,
+                name
+            );
+        }
+        environment.insert(name, value);
+    }
+
+    Ok(CreateContainerRequest {
+        uuid: server.id.to_string(),
+        image: server.docker_image.clone(),
+        startup_command: server.startup_command.clone(),
+        environment,
+        memory_limit: server.ram_limit as i64,
+        swap_limit: server.swap_limit as i64,
+        cpu_limit: server.cpu_limit as i64,
+        io_weight: server.io_weight.clamp(10, 1000) as u16,
+        ports: ports
+            .iter()
+            .map(|p| (format!("{}/tcp", p), p.to_string()))
+            .collect(),
+        run_as_user: Some(run_as_user).filter(|u| !u.is_empty()),
+        no_new_privileges,
+        config_files: config_file_rules(server, &config_files),
+        stop_timeout: server.stop_grace(),
+        pull_policy: pull_policy_or_default(Some(&server.pull_policy)).to_string(),
+        command_mode: command_mode_or_default(Some(&command_mode)).to_string(),
+        data_volume: false,
+        startup_done: startup_done_markers(&start_config),
+        start: server.start_on_install,
+    })
+}
+
+/// The image's install script as a node request, run with the server's own environment.
+/// None when the image has no script.
+async fn install_request(
+    state: &AppState,
+    server: &Server,
+    container: &CreateContainerRequest,
+) -> Result<Option<InstallTestRequest>, String> {
+    let row: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT COALESCE(i.install_script::text, ''), COALESCE(i.install_container::text, ''), COALESCE(i.install_entrypoint::text, '') \
+         FROM servers s JOIN images i ON s.image_id = i.id WHERE s.id = $1",
+    )
+    .bind(server.id)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| format!("Failed to load install script: {}", e))?;
+
+    let Some((script, image, entrypoint)) = row.filter(|(script, _, _)| !script.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+    Ok(Some(InstallTestRequest {
+        // Eggs without an install container run the script in the server's own image
+        container: if image.trim().is_empty() {
+            container.image.clone()
+        } else {
+            image
+        },
+        entrypoint: if entrypoint.trim().is_empty() {
+            "bash".to_string()
+        } else {
+            entrypoint
+        },
+        script,
+        environment: container.environment.clone(),
+        memory_limit: Some(server.ram_limit as i64).filter(|m| *m > 0),
+    }))
+}
+
+/// Runs the install script on the node, feeding its output to `install_logs` for the manage
+/// page. A non-zero exit, a timeout or a lost stream fails the create.
+async fn run_install(
+    state: &AppState,
+    node: &Node,
+    server_id: Uuid,
+    request: &InstallTestRequest,
+) -> Result<(), String> {
+    state.install_logs.begin(server_id);
+    let result = stream_install(state, node, server_id, request).await;
+    if let Err(message) = &result {
+        state.install_logs.push(
+            server_id,
+            InstallTestEvent::Error {
+                message: message.clone(),
+            },
+        );
+    }
+    result
+}
+
+async fn stream_install(
+    state: &AppState,
+    node: &Node,
+    server_id: Uuid,
+    request: &InstallTestRequest,
+) -> Result<(), String> {
+    let mut res =
+        node_api::start_server_install(&state.http_client, node, &server_id.to_string(), request)
+            .await?;
+    let mut pending: Vec<u8> = Vec::new();
+    loop {
+        let chunk = res
+            .chunk()
+            .await
+            .map_err(|e| format!("Lost connection to node during install: {}", e))?
+            .ok_or("Node closed the install stream before the script finished")?;
+        pending.extend_from_slice(&chunk);
+
+        while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            let event = match serde_json::from_slice::<InstallTestEvent>(&line) {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("Unreadable install event for server {}: {}", server_id, e);
+                    continue;
+                }
+            };
+            match event {
+                InstallTestEvent::Output { .. } => state.install_logs.push(server_id, event),
+                InstallTestEvent::Exit { code, timed_out } => {
+                    state.install_logs.push(server_id, event);
+                    return match (code, timed_out) {
+                        (_, true) => Err("Install script timed out".to_string()),
+                        (0, false) => Ok(()),
+                        (code, false) => Err(format!("Install script exited with code {}", code)),
+                    };
+                }
+                InstallTestEvent::Error { message } => {
+                    return Err(format!("Install failed: {}", message));
+                }
+            }
+        }
+    }
+}
+
+/// Builds a new server's container on its node, waiting its turn behind other operations
+/// on that node. The server's status and install error follow the outcome.
+pub async fn create_container_job(ctx: &JobContext, job: CreateContainerJob) -> Result<(), String> {
+    let state = &ctx.state;
+    let result = create_container(ctx, job.server_id).await;
+
+    let (status, error) = match &result {
+        Ok(()) => ("running", None),
+        Err(e) => ("install_failed", Some(e.clone())),
+    };
+    let _ = sqlx::query(
+        "UPDATE servers SET status = $1, install_error = $2, flagged_at = NULL, flag_reason = NULL WHERE id = $3",
+    )
+    .bind(status)
+    .bind(error)
+    .bind(job.server_id)
+    .execute(&state.db)
+    .await;
+    if let Err(e) = &result {
+        server_events::record(
+            &state.db,
+            job.server_id,
+            "install",
+            &format!("Install failed: {}", e),
+        )
+        .await;
+    }
+
+    result
+}
+
+async fn create_container(ctx: &JobContext, server_id: Uuid) -> Result<(), String> {
+    let state = &ctx.state;
+    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| format!("Failed to load server: {}", e))?
+        .ok_or("Server no longer exists")?;
+
+    let node_id = server.node_id.to_string();
+    let node = state
+        .get_node_with_token(&node_id)
+        .await
+        .ok_or_else(|| format!("Node {} not found", node_id))?;
+
+    let mut container = container_request(state, &server).await?;
+    let install = install_request(state, &server, &container).await?;
+    ctx.progress(10).await;
+
+    let queued_behind = state.node_ops.queued_behind(&node.id);
+    if queued_behind > 0 {
+        let _ = sqlx::query("UPDATE servers SET status = 'queued' WHERE id = $1")
+            .bind(server_id)
+            .execute(&state.db)
+            .await;
+    }
+    let _permit = state.node_ops.acquire(&node.id).await;
+    if queued_behind > 0 {
+        let _ = sqlx::query("UPDATE servers SET status = 'installing' WHERE id = $1")
+            .bind(server_id)
+            .execute(&state.db)
+            .await;
+    }
+    ctx.progress(30).await;
+
+    // Whatever the last attempt found holding our ports is stale now
+    let _ = sqlx::query("UPDATE servers SET port_conflicts = NULL WHERE id = $1")
+        .bind(server_id)
+        .execute(&state.db)
+        .await;
+
+    if let Some(install) = &install {
+        run_install(state, &node, server_id, install).await?;
+        container.data_volume = true;
+        ctx.progress(60).await;
+    }
+
+    match node_api::create_container(&state.http_client, &node, &container, &state.node_retry).await
+    {
+        Ok(()) => Ok(()),
+        Err(e) if e.holders.is_empty() => Err(e.message),
+        Err(e) => Err(record_port_conflicts(state, server_id, e).await),
+    }
+}
+
+/// A managed container holding a port a create needed, with the server it belongs to.
+pub struct PortConflict {
+    pub port: u16,
+    pub container: String,
+    pub server_id: String,
+    pub server_name: Option<String>,
+    /// The panel has no such server: a leftover from a delete whose container removal
+    /// failed, safe to remove. Unknown (lookup failed) counts as not leftover.
+    pub leftover: bool,
+}
+
+/// Stores what the node found on our ports for the manage page and names it in the error.
+async fn record_port_conflicts(
+    state: &AppState,
+    server_id: Uuid,
+    err: node_api::CreateContainerError,
+) -> String {
+    let _ = sqlx::query("UPDATE servers SET port_conflicts = $1 WHERE id = $2")
+        .bind(serde_json::to_string(&err.holders).unwrap_or_else(|_| "[]".to_string()))
+        .bind(server_id)
+        .execute(&state.db)
+        .await;
+
+    let mut message = err.message;
+    for conflict in resolve_holders(&state.db, err.holders).await {
+        let owner = match &conflict.server_name {
+            Some(name) => format!("server \"{}\"", name),
+            None if conflict.leftover => format!("deleted server {}", conflict.server_id),
+            None => format!("server {}", conflict.server_id),
+        };
+        tracing::warn!(
+            "Create of server {}: port {} is held by container {} of {}",
+            server_id,
+            conflict.port,
+            conflict.container,
+            owner
+        );
+        message.push_str(&format!(
+            ". Port {} is held by container {} of {}",
+            conflict.port, conflict.container, owner
+        ));
+    }
+    message
+}
+
+/// The containers the last failed create found on this server's ports.
+pub async fn port_conflicts(db: &sqlx::PgPool, server_id: Uuid) -> Vec<PortConflict> {
+    let raw: Option<String> =
+        sqlx::query_scalar("SELECT port_conflicts FROM servers WHERE id = $1")
+            .bind(server_id)
+            .fetch_optional(db)
+            .await
+            .unwrap_or(None)
+            .flatten();
+    let holders: Vec<PortHolder> = raw
+        .and_then(|r| serde_json::from_str(&r).ok())
+        .unwrap_or_default();
+    resolve_holders(db, holders).await
+}
+
+async fn resolve_holders(db: &sqlx::PgPool, holders: Vec<PortHolder>) -> Vec<PortConflict> {
+    let mut conflicts = Vec::with_capacity(holders.len());
+    for holder in holders {
+        let lookup = match Uuid::parse_str(&holder.server_id) {
+            Ok(id) => sqlx::query_scalar::<_, String>("SELECT name FROM servers WHERE id = $1")
+                .bind(id)
+                .fetch_optional(db)
+                .await
+                .ok(),
+            Err(_) => None,
+        };
+        conflicts.push(PortConflict {
+            port: holder.port,
+            container: holder.container,
+            server_id: holder.server_id,
+            leftover: matches!(lookup, Some(None)),
+            server_name: lookup.flatten(),
+        });
+    }
+    conflicts
+}
+
+/// Replaces a server's container with one built from its current settings (image, startup
+/// command, variables, secrets, allocations), keeping its data volume. A running server is
+/// started again; a stopped one stays stopped. Clears `needs_recreate`, or sets it again
+/// when the recreate fails.
+pub async fn recreate_container_job(
+    ctx: &JobContext,
+    job: RecreateContainerJob,
+) -> Result<(), String> {
+    let state = &ctx.state;
+    // Cleared up front, so an edit made while the job runs flags the server again
+    let _ = sqlx::query("UPDATE servers SET needs_recreate = FALSE WHERE id = $1")
+        .bind(job.server_id)
+        .execute(&state.db)
+        .await;
+
+    let result = recreate_container(ctx, job.server_id).await;
+    match &result {
+        Ok(()) => {
+            server_events::record(
+                &state.db,
+                job.server_id,
+                "recreated",
+                "Container recreated with the current settings",
+            )
+            .await;
+        }
+        Err(e) => {
+            let _ = sqlx::query("UPDATE servers SET needs_recreate = TRUE WHERE id = $1")
+                .bind(job.server_id)
+                .execute(&state.db)
+                .await;
+            server_events::record(
+                &state.db,
+                job.server_id,
+                "recreate_failed",
+                &format!("Recreate failed: {}", e),
+            )
+            .await;
+        }
+    }
+    result
+}
+
+async fn recreate_container(ctx: &JobContext, server_id: Uuid) -> Result<(), String> {
+    let state = &ctx.state;
+    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| format!("Failed to load server: {}", e))?
+        .ok_or("Server no longer exists")?;
+
+    let node_id = server.node_id.to_string();
+    let node = state
+        .get_node_with_token(&node_id)
+        .await
+        .ok_or_else(|| format!("Node {} not found", node_id))?;
+
+    let mut container = container_request(state, &server).await?;
+    // Servers that ran an install script keep their files in the data volume
+    container.data_volume = install_request(state, &server, &container).await?.is_some();
+    ctx.progress(10).await;
+
+    let _permit = state.node_ops.acquire(&node.id).await;
+    let uuid = server_id.to_string();
+    container.start = match node_api::container_state(
+        &state.http_client,
+        &node,
+        &uuid,
+        &state.node_retry,
+    )
+    .await
+    {
+        Ok(current) => current.state == "running",
+        Err(_) => false,
+    };
+    ctx.progress(30).await;
+
+    let killed = node_api::stop_and_delete_container(
+        &state.http_client,
+        &node,
+        &uuid,
+        Some(server.stop_grace()),
+        false,
+        &state.node_retry,
+    )
+    .await?;
+    if killed {
+        tracing::warn!(
+            "Server {} did not stop within {}s and was killed",
+            server_id,
+            server.stop_grace()
+        );
+    }
+    ctx.progress(60).await;
+
+    match node_api::create_container(&state.http_client, &node, &container, &state.node_retry).await
+    {
+        Ok(()) => Ok(()),
+        Err(e) if e.holders.is_empty() => Err(e.message),
+        Err(e) => Err(record_port_conflicts(state, server_id, e).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_markers_come_from_done_as_a_string_or_list() {
+        assert_eq!(
+            startup_done_markers(r#"{"done": ")! For help, type "}"#),
+            [")! For help, type "]
+        );
+        assert_eq!(
+            startup_done_markers(r#"{"done": ["Server started", "  ", 5, "Listening"]}"#),
+            ["Server started", "Listening"]
+        );
+        for raw in ["", "{}", r#"{"done": 1}"#, "not json"] {
+            assert!(startup_done_markers(raw).is_empty(), "{}", raw);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracked IPs before expired windows are swept, so a scan can't grow the map forever.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Fixed one-minute window per client IP, for unauthenticated endpoints.
+pub struct RateLimiter {
+    per_minute: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Requests per minute from `key`, or `default`. 0 disables the limit.
+    pub fn from_env(key: &str, default: u32) -> Self {
+        let per_minute = std::env::var(key)
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(default);
+
+        Self {
+            per_minute,
+            window: Duration::from_secs(60),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request from `ip`. `Err` carries the seconds until its window resets.
+    pub fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= SWEEP_THRESHOLD {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = clients.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.per_minute {
+            let reset = self.window.saturating_sub(now.duration_since(*start));
+            return Err(reset.as_secs().max(1));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
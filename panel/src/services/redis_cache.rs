@@ -0,0 +1,223 @@
+//! Optional Redis behind the panel's caches. Every cache already has an in-memory or DB
+//! fallback, so when Redis stops answering the panel stops asking it (degraded) and a
+//! background task reconnects, instead of every request paying for the failure.
+
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client as RedisClient, RedisError, RedisResult};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Consecutive connection errors before the panel stops using Redis
+const FAILURE_THRESHOLD: u32 = 3;
+/// Per-command and connect timeout, so a dead Redis fails fast instead of hanging requests
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct RedisCache {
+    client: Option<RedisClient>,
+    manager: RwLock<Option<ConnectionManager>>,
+    degraded: AtomicBool,
+    failures: AtomicU32,
+    /// Seconds between reconnect attempts while degraded (`REDIS_RETRY_INTERVAL`)
+    retry_interval: Duration,
+}
+
+impl RedisCache {
+    /// Connects to `REDIS_URL` if set. A Redis that is down at startup leaves the cache
+    /// degraded rather than disabled, so `spawn_reconnect` can pick it up later.
+    pub async fn from_env() -> Self {
+        let retry_interval = Duration::from_secs(
+            std::env::var("REDIS_RETRY_INTERVAL")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(15),
+        );
+
+        let Some(url) = std::env::var("REDIS_URL").ok() else {
+            tracing::warn!("REDIS_URL not set, running without Redis cache.");
+            return Self::new(None, None, retry_interval);
+        };
+
+        let client = match RedisClient::open(url.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("Failed to open Redis client: {}", e);
+                return Self::new(None, None, retry_interval);
+            }
+        };
+
+        let manager = match connect(&client).await {
+            Ok(manager) => {
+                tracing::info!("Connected to Redis at {}", url);
+                Some(manager)
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to connect to Redis: {}. Using in-memory caches, retrying every {}s",
+                    e,
+                    retry_interval.as_secs()
+                );
+                None
+            }
+        };
+        Self::new(Some(client), manager, retry_interval)
+    }
+
+    fn new(
+        client: Option<RedisClient>,
+        manager: Option<ConnectionManager>,
+        retry_interval: Duration,
+    ) -> Self {
+        let degraded = client.is_some() && manager.is_none();
+        Self {
+            client,
+            manager: RwLock::new(manager),
+            degraded: AtomicBool::new(degraded),
+            failures: AtomicU32::new(0),
+            retry_interval,
+        }
+    }
+
+    /// Connection to use, or `None` when Redis is not configured or currently degraded.
+    pub fn connection(&self) -> Option<ConnectionManager> {
+        if self.is_degraded() {
+            return None;
+        }
+        self.manager.read().unwrap().clone()
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Configured, but the panel is running on its in-memory fallbacks.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Feeds a command result into the health tracking. Only connection-level errors
+    /// count; a missing key or a type mismatch says nothing about Redis being up.
+    pub fn observe<T>(&self, result: &RedisResult<T>) {
+        match result {
+            Ok(_) => {
+                self.failures.store(0, Ordering::Relaxed);
+            }
+            Err(e) if is_connection_error(e) => {
+                let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= FAILURE_THRESHOLD && !self.degraded.swap(true, Ordering::Relaxed) {
+                    tracing::error!(
+                        "Redis unreachable after {} failed commands ({}). Falling back to in-memory caches, retrying every {}s",
+                        failures,
+                        e,
+                        self.retry_interval.as_secs()
+                    );
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Reconnects in the background whenever the cache is degraded.
+    pub fn spawn_reconnect(self: &Arc<Self>) {
+        let Some(client) = self.client.clone() else {
+            return;
+        };
+        let cache = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(cache.retry_interval).await;
+                if !cache.is_degraded() {
+                    continue;
+                }
+
+                let mut manager = match connect(&client).await {
+                    Ok(manager) => manager,
+                    Err(e) => {
+                        tracing::debug!("Redis still unreachable: {}", e);
+                        continue;
+                    }
+                };
+
+                // Invalidations skipped while degraded may have left stale entries behind
+                let stale: RedisResult<Vec<String>> = redis::cmd("KEYS")
+                    .arg("node:*:cache")
+                    .query_async(&mut manager)
+                    .await;
+                let mut keys = stale.unwrap_or_default();
+                keys.push("cache:nodes".to_string());
+                let _: RedisResult<()> = redis::AsyncCommands::del(&mut manager, keys).await;
+
+                *cache.manager.write().unwrap() = Some(manager);
+                cache.failures.store(0, Ordering::Relaxed);
+                cache.degraded.store(false, Ordering::Relaxed);
+                tracing::info!("Redis reachable again, using it for caches");
+            }
+        });
+    }
+}
+
+impl RedisCache {
+    /// Best effort: a message lost while Redis is down is covered by the receivers' own
+    /// periodic refresh.
+    pub async fn publish(&self, channel: &str, message: &str) {
+        if let Some(mut con) = self.connection() {
+            let res: RedisResult<()> =
+                redis::AsyncCommands::publish(&mut con, channel, message).await;
+            self.observe(&res);
+        }
+    }
+
+    /// Payloads published on `channel`, resubscribing whenever the connection drops.
+    /// `None` when Redis isn't configured.
+    pub fn subscribe(self: &Arc<Self>, channel: &'static str) -> Option<mpsc::Receiver<String>> {
+        let client = self.client.clone()?;
+        let retry_interval = self.retry_interval;
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            use futures_util::StreamExt;
+            loop {
+                match tokio::time::timeout(COMMAND_TIMEOUT, client.get_async_pubsub()).await {
+                    Ok(Ok(mut pubsub)) => match pubsub.subscribe(channel).await {
+                        Ok(()) => {
+                            let mut messages = pubsub.into_on_message();
+                            while let Some(msg) = messages.next().await {
+                                let payload = msg.get_payload::<String>().unwrap_or_default();
+                                if tx.send(payload).await.is_err() {
+                                    return;
+                                }
+                            }
+                            tracing::warn!(
+                                "Redis subscription to {} dropped, resubscribing",
+                                channel
+                            );
+                        }
+                        Err(e) => tracing::debug!("Failed to subscribe to {}: {}", channel, e),
+                    },
+                    Ok(Err(e)) => tracing::debug!("Redis pub/sub connection failed: {}", e),
+                    Err(_) => tracing::debug!("Redis pub/sub connection timed out"),
+                }
+                if tx.is_closed() {
+                    return;
+                }
+                tokio::time::sleep(retry_interval).await;
+            }
+        });
+        Some(rx)
+    }
+}
+
+async fn connect(client: &RedisClient) -> RedisResult<ConnectionManager> {
+    let config = ConnectionManagerConfig::new()
+        .set_connection_timeout(Some(COMMAND_TIMEOUT))
+        .set_response_timeout(Some(COMMAND_TIMEOUT))
+        .set_number_of_retries(1);
+    let mut manager = client.get_connection_manager_with_config(config).await?;
+    redis::cmd("PING").query_async::<()>(&mut manager).await?;
+    Ok(manager)
+}
+
+fn is_connection_error(e: &RedisError) -> bool {
+    e.is_io_error() || e.is_timeout() || e.is_connection_dropped() || e.is_connection_refusal()
+}
@@ -0,0 +1,264 @@
+//! Short-lived holds on free allocations while someone fills in the create-server form, so
+//! a concurrent create (manual pick or auto-assign) doesn't take the port they selected.
+//! Held in Redis (`alloc:{id}:reserved`) so every panel instance sees them, or in memory
+//! while Redis is unset or degraded.
+
+use crate::services::redis_cache::RedisCache;
+use redis::AsyncCommands;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub struct AllocationReservations {
+    redis: Arc<RedisCache>,
+    /// How long a selection holds its allocation (`ALLOCATION_RESERVATION_TTL`)
+    ttl: Duration,
+    /// allocation id -> (holder token, expiry), used without Redis
+    local: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+fn reservation_key(allocation_id: &str) -> String {
+    format!("alloc:{}:reserved", allocation_id)
+}
+
+/// Which allocation a holder currently has, so a new selection drops the old one
+fn holder_key(holder: &str) -> String {
+    format!("alloc:holder:{}", holder)
+}
+
+impl AllocationReservations {
+    /// Seconds from `ALLOCATION_RESERVATION_TTL` (default 120). 0 disables reservations.
+    pub fn from_env(redis: Arc<RedisCache>) -> Self {
+        let ttl = std::env::var("ALLOCATION_RESERVATION_TTL")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(120);
+
+        Self::new(redis, Duration::from_secs(ttl))
+    }
+
+    /// Holds that last `ttl`; a zero `ttl` disables reservations.
+    pub fn new(redis: Arc<RedisCache>, ttl: Duration) -> Self {
+        Self {
+            redis,
+            ttl,
+            local: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// Makes `allocation_id` the holder's reservation, dropping any previous one, or just
+    /// drops it when `None`. Returns false when someone else holds the allocation.
+    pub async fn select(&self, holder: &str, allocation_id: Option<&str>) -> bool {
+        if !self.enabled() || holder.is_empty() {
+            return true;
+        }
+
+        if let Some(mut con) = self.redis.connection() {
+            let previous: redis::RedisResult<Option<String>> = con.get(holder_key(holder)).await;
+            self.redis.observe(&previous);
+            if let Ok(previous) = previous {
+                return self
+                    .select_redis(&mut con, holder, previous, allocation_id)
+                    .await;
+            }
+        }
+
+        let now = Instant::now();
+        let mut local = self.local.lock().unwrap();
+        local.retain(|_, (h, expires)| *expires > now && h != holder);
+        let Some(allocation_id) = allocation_id else {
+            return true;
+        };
+        if local.contains_key(allocation_id) {
+            return false;
+        }
+        local.insert(
+            allocation_id.to_string(),
+            (holder.to_string(), now + self.ttl),
+        );
+        true
+    }
+
+    async fn select_redis(
+        &self,
+        con: &mut redis::aio::ConnectionManager,
+        holder: &str,
+        previous: Option<String>,
+        allocation_id: Option<&str>,
+    ) -> bool {
+        let ttl = self.ttl.as_secs();
+
+        if let Some(previous) = previous.filter(|p| Some(p.as_str()) != allocation_id) {
+            self.release_redis(con, holder, &previous).await;
+        }
+
+        let Some(allocation_id) = allocation_id else {
+            let res: redis::RedisResult<()> = con.del(holder_key(holder)).await;
+            self.redis.observe(&res);
+            return true;
+        };
+
+        let key = reservation_key(allocation_id);
+        let set: redis::RedisResult<Option<String>> = redis::cmd("SET")
+            .arg(&key)
+            .arg(holder)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(con)
+            .await;
+        self.redis.observe(&set);
+
+        let acquired = match set {
+            Ok(Some(_)) => true,
+            // Already held: fine if it's ours (re-selecting refreshes the TTL)
+            Ok(None) => {
+                let current: redis::RedisResult<Option<String>> = con.get(&key).await;
+                let ours = matches!(current, Ok(Some(ref h)) if h == holder);
+                if ours {
+                    let res: redis::RedisResult<()> = con.expire(&key, ttl as i64).await;
+                    self.redis.observe(&res);
+                }
+                ours
+            }
+            // Redis failed mid-way; don't block the form over a best-effort hold
+            Err(_) => return true,
+        };
+
+        if acquired {
+            let res: redis::RedisResult<()> =
+                con.set_ex(holder_key(holder), allocation_id, ttl).await;
+            self.redis.observe(&res);
+        }
+        acquired
+    }
+
+    async fn release_redis(
+        &self,
+        con: &mut redis::aio::ConnectionManager,
+        holder: &str,
+        allocation_id: &str,
+    ) {
+        let key = reservation_key(allocation_id);
+        let current: redis::RedisResult<Option<String>> = con.get(&key).await;
+        self.redis.observe(&current);
+        if matches!(current, Ok(Some(ref h)) if h == holder) {
+            let res: redis::RedisResult<()> = con.del(&key).await;
+            self.redis.observe(&res);
+        }
+    }
+
+    /// Drops whatever the holder has reserved; called once the form is submitted.
+    pub async fn release(&self, holder: &str) {
+        self.select(holder, None).await;
+    }
+
+    /// The subset of `allocation_ids` currently reserved by anyone but `holder`.
+    pub async fn held_by_others(
+        &self,
+        allocation_ids: &[String],
+        holder: Option<&str>,
+    ) -> HashSet<String> {
+        if !self.enabled() || allocation_ids.is_empty() {
+            return HashSet::new();
+        }
+
+        if let Some(mut con) = self.redis.connection() {
+            let keys: Vec<String> = allocation_ids
+                .iter()
+                .map(|id| reservation_key(id))
+                .collect();
+            let holders: redis::RedisResult<Vec<Option<String>>> =
+                redis::cmd("MGET").arg(&keys).query_async(&mut con).await;
+            self.redis.observe(&holders);
+            if let Ok(holders) = holders {
+                return allocation_ids
+                    .iter()
+                    .zip(holders)
+                    .filter(|(_, h)| matches!(h, Some(h) if Some(h.as_str()) != holder))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+            }
+        }
+
+        let now = Instant::now();
+        let local = self.local.lock().unwrap();
+        allocation_ids
+            .iter()
+            .filter(|id| {
+                matches!(local.get(id.as_str()), Some((h, expires)) if *expires > now && Some(h.as_str()) != holder)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Every allocation currently reserved by anyone but `holder`, for queries that have to
+    /// leave them out up front (auto-assign).
+    pub async fn held_ids(&self, holder: Option<&str>) -> Vec<String> {
+        if !self.enabled() {
+            return Vec::new();
+        }
+
+        if let Some(mut con) = self.redis.connection() {
+            let held = self.held_ids_redis(&mut con, holder).await;
+            if let Ok(held) = held {
+                return held;
+            }
+        }
+
+        let now = Instant::now();
+        let local = self.local.lock().unwrap();
+        local
+            .iter()
+            .filter(|(_, (h, expires))| *expires > now && Some(h.as_str()) != holder)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    async fn held_ids_redis(
+        &self,
+        con: &mut redis::aio::ConnectionManager,
+        holder: Option<&str>,
+    ) -> redis::RedisResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let page: redis::RedisResult<(u64, Vec<String>)> = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(reservation_key("*"))
+                .arg("COUNT")
+                .arg(500)
+                .query_async(con)
+                .await;
+            self.redis.observe(&page);
+            let (next, page) = page?;
+            keys.extend(page);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let holders: redis::RedisResult<Vec<Option<String>>> =
+            redis::cmd("MGET").arg(&keys).query_async(con).await;
+        self.redis.observe(&holders);
+        Ok(keys
+            .iter()
+            .zip(holders?)
+            .filter(|(_, h)| matches!(h, Some(h) if Some(h.as_str()) != holder))
+            .filter_map(|(key, _)| {
+                key.strip_prefix("alloc:")?
+                    .strip_suffix(":reserved")
+                    .map(str::to_string)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,121 @@
+//! Encryption at rest for secret server variables and node tokens (AES-256-GCM under the
+//! panel's `APP_KEY`).
+//!
+//! Also compiled into the `rotate_app_key` and `encrypt_node_tokens` binaries, so it must not
+//! depend on the rest of the crate.
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::RngCore;
+
+/// `.env` key holding the base64 master key (32 bytes).
+pub const KEY_ENV: &str = "APP_KEY";
+
+/// Stored values look like `v1:<base64(nonce || ciphertext)>`.
+const PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecretError {
+    Malformed,
+    /// Wrong key, or the value was tampered with
+    Decrypt,
+}
+
+impl std::fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretError::Malformed => write!(f, "stored secret is malformed"),
+            SecretError::Decrypt => write!(
+                f,
+                "secret could not be decrypted with the current {}",
+                KEY_ENV
+            ),
+        }
+    }
+}
+
+/// Decodes a base64 key, accepting only exactly 32 bytes.
+pub fn parse_key(value: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(value.trim()).ok().filter(|k| k.len() == 32)
+}
+
+pub fn encode_key(key: &[u8]) -> String {
+    STANDARD.encode(key)
+}
+
+pub fn generate_key() -> Vec<u8> {
+    let mut key = vec![0u8; 32];
+    rand::rng().fill_bytes(&mut key);
+    key
+}
+
+/// Reads `APP_KEY`, generating and persisting one to `.env` if it is unset or still the placeholder.
+pub fn load_or_create_key() -> Vec<u8> {
+    if let Some(key) = std::env::var(KEY_ENV).ok().and_then(|v| parse_key(&v)) {
+        return key;
+    }
+
+    tracing::warn!(
+        "{} missing or not 32 bytes of base64, generating a new one",
+        KEY_ENV
+    );
+    let key = generate_key();
+    persist_key(&key);
+    key
+}
+
+/// Writes the key to `.env`. Losing it makes every stored secret unreadable.
+pub fn persist_key(key: &[u8]) {
+    let env_path = std::path::Path::new(".env");
+    let prefix = format!("{}=", KEY_ENV);
+
+    let content = std::fs::read_to_string(env_path).unwrap_or_default();
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|l| !l.starts_with(&prefix))
+        .map(|l| l.to_string())
+        .collect();
+    lines.push(format!("{}{}", prefix, encode_key(key)));
+
+    if let Err(e) = std::fs::write(env_path, lines.join("\n")) {
+        tracing::error!("Failed to persist {}: {}", KEY_ENV, e);
+    }
+}
+
+fn cipher(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new_from_slice(key).expect("APP_KEY is validated to 32 bytes")
+}
+
+pub fn encrypt(key: &[u8], plaintext: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher(key)
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .expect("AES-GCM encryption does not fail for in-memory buffers");
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    format!("{}{}", PREFIX, STANDARD.encode(out))
+}
+
+/// Whether a stored value is `encrypt` output rather than plaintext.
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+pub fn decrypt(key: &[u8], stored: &str) -> Result<String, SecretError> {
+    let raw = stored
+        .strip_prefix(PREFIX)
+        .and_then(|b| STANDARD.decode(b).ok())
+        .filter(|r| r.len() > NONCE_LEN)
+        .ok_or(SecretError::Malformed)?;
+    let (nonce, ciphertext) = raw.split_at(NONCE_LEN);
+
+    let plaintext = cipher(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| SecretError::Decrypt)?;
+    String::from_utf8(plaintext).map_err(|_| SecretError::Malformed)
+}
@@ -0,0 +1,37 @@
+use crate::models::ServerEvent;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Appends an entry to the server's event log. Failures are logged, never surfaced.
+pub async fn record(db: &PgPool, server_id: Uuid, kind: &str, message: &str) {
+    let res = sqlx::query(
+        "INSERT INTO server_events (id, server_id, kind, message) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(server_id)
+    .bind(kind)
+    .bind(message)
+    .execute(db)
+    .await;
+
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to record {} event for server {}: {}",
+            kind,
+            server_id,
+            e
+        );
+    }
+}
+
+/// Newest first.
+pub async fn recent(db: &PgPool, server_id: Uuid, limit: i64) -> Vec<ServerEvent> {
+    sqlx::query_as::<_, ServerEvent>(
+        "SELECT kind, message, created_at FROM server_events WHERE server_id = $1 ORDER BY created_at DESC LIMIT $2",
+    )
+    .bind(server_id)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+}
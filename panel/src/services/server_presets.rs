@@ -0,0 +1,135 @@
+use crate::models::{ServerPreset, ServerTemplate, Variable};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Values worth remembering for a server: submitted values of editable, non-secret variables.
+/// Secrets live in `server_secrets` and are never copied anywhere else.
+pub fn plain_values(
+    variables: &[Variable],
+    submitted: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    variables
+        .iter()
+        .filter(|v| v.user_editable && !v.is_secret)
+        .filter_map(|v| {
+            submitted
+                .get(&v.env_variable)
+                .map(|value| (v.env_variable.clone(), value.clone()))
+        })
+        .collect()
+}
+
+/// Create-form preset copied from a live server. The allocation and volume stay behind.
+pub async fn from_server(db: &PgPool, server_id: Uuid) -> Option<ServerPreset> {
+    #[derive(sqlx::FromRow)]
+    struct Row {
+        runtime_id: String,
+        image_id: String,
+        description: Option<String>,
+        cpu_limit: i32,
+        ram_limit: i32,
+        disk_limit: i32,
+        swap_limit: i32,
+        backup_limit: i32,
+        io_weight: i32,
+        oom_killer: bool,
+        cpu_pinning: Option<String>,
+        docker_image: String,
+        startup_command: String,
+        values: String,
+        image_variables: String,
+    }
+
+    let row = sqlx::query_as::<_, Row>(
+        r#"
+        SELECT i.runtime_id::text, s.image_id::text, s.description,
+               s.cpu_limit, s.ram_limit, s.disk_limit, s.swap_limit, s.backup_limit,
+               s.io_weight, s.oom_killer, s.cpu_pinning, s.docker_image, s.startup_command,
+               COALESCE(s.variables::text, '{}') AS values,
+               COALESCE(i.variables::text, '[]') AS image_variables
+        FROM servers s JOIN images i ON s.image_id = i.id
+        WHERE s.id = $1
+    "#,
+    )
+    .bind(server_id)
+    .fetch_optional(db)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to load server {} for cloning: {}", server_id, e);
+        None
+    })?;
+
+    // Filter again in case a variable was flagged secret after the server was created
+    let variables = serde_json::from_str::<Vec<Variable>>(&row.image_variables).unwrap_or_default();
+    let values = serde_json::from_str::<HashMap<String, String>>(&row.values).unwrap_or_default();
+
+    Some(ServerPreset {
+        runtime_id: row.runtime_id,
+        image_id: row.image_id,
+        description: row.description,
+        cpu_limit: row.cpu_limit,
+        ram_limit: row.ram_limit,
+        disk_limit: row.disk_limit,
+        swap_limit: row.swap_limit,
+        backup_limit: row.backup_limit,
+        io_weight: row.io_weight,
+        oom_killer: row.oom_killer,
+        cpu_pinning: row.cpu_pinning,
+        docker_image: row.docker_image,
+        startup_command: row.startup_command,
+        variables: plain_values(&variables, &values),
+    })
+}
+
+pub async fn list_templates(db: &PgPool) -> Vec<ServerTemplate> {
+    sqlx::query_as::<_, ServerTemplate>(
+        "SELECT id, name, created_at FROM server_templates ORDER BY name",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+}
+
+/// Name and preset of a saved template.
+pub async fn load_template(db: &PgPool, id: Uuid) -> Option<(String, ServerPreset)> {
+    let (name, config): (String, String) =
+        sqlx::query_as("SELECT name, config::text FROM server_templates WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .unwrap_or(None)?;
+
+    match serde_json::from_str::<ServerPreset>(&config) {
+        Ok(preset) => Some((name, preset)),
+        Err(e) => {
+            tracing::error!("Server template {} is unreadable: {}", id, e);
+            None
+        }
+    }
+}
+
+pub async fn save_template(
+    db: &PgPool,
+    name: &str,
+    preset: &ServerPreset,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO server_templates (id, name, config) VALUES ($1, $2, $3::jsonb)")
+        .bind(id)
+        .bind(name)
+        .bind(serde_json::to_string(preset).unwrap_or_else(|_| "{}".to_string()))
+        .execute(db)
+        .await?;
+    Ok(id)
+}
+
+pub async fn delete_template(db: &PgPool, id: Uuid) {
+    if let Err(e) = sqlx::query("DELETE FROM server_templates WHERE id = $1")
+        .bind(id)
+        .execute(db)
+        .await
+    {
+        tracing::error!("Failed to delete server template {}: {}", id, e);
+    }
+}
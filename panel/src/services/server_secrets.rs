@@ -0,0 +1,72 @@
+use crate::services::secrets;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// `environment[NAME]` fields from a form body. The create form renders one per image variable;
+/// the edit form uses them for overwriting secrets.
+pub fn form_environment(raw: &[u8]) -> HashMap<String, String> {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(raw)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(k, v)| {
+            k.strip_prefix("environment[")
+                .and_then(|k| k.strip_suffix(']'))
+                .map(|k| (k.to_string(), v))
+        })
+        .collect()
+}
+
+/// Encrypts and stores (or replaces) a secret variable for a server.
+pub async fn store<'e>(
+    db: impl sqlx::PgExecutor<'e>,
+    key: &[u8],
+    server_id: Uuid,
+    env_variable: &str,
+    value: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO server_secrets (server_id, env_variable, ciphertext, updated_at) VALUES ($1, $2, $3, NOW())
+         ON CONFLICT (server_id, env_variable) DO UPDATE SET ciphertext = EXCLUDED.ciphertext, updated_at = NOW()",
+    )
+    .bind(server_id)
+    .bind(env_variable)
+    .bind(secrets::encrypt(key, value))
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Names of the secret variables that have a value; that is all templates ever learn.
+pub async fn set_names(db: &PgPool, server_id: Uuid) -> HashSet<String> {
+    sqlx::query_scalar::<_, String>("SELECT env_variable FROM server_secrets WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_all(db)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .collect()
+}
+
+/// Plaintext secrets for building the node's environment map. Values that fail to decrypt
+/// (e.g. after a botched key rotation) are skipped and logged rather than sent garbled.
+pub async fn decrypted(db: &PgPool, key: &[u8], server_id: Uuid) -> HashMap<String, String> {
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT env_variable, ciphertext FROM server_secrets WHERE server_id = $1")
+            .bind(server_id)
+            .fetch_all(db)
+            .await
+            .unwrap_or_default();
+
+    rows.into_iter()
+        .filter_map(
+            |(name, ciphertext)| match secrets::decrypt(key, &ciphertext) {
+                Ok(value) => Some((name, value)),
+                Err(e) => {
+                    tracing::error!("Secret {} of server {}: {}", name, server_id, e);
+                    None
+                }
+            },
+        )
+        .collect()
+}
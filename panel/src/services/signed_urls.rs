@@ -0,0 +1,158 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `.env` key holding the base64 signing secret. Rotating it revokes every outstanding URL.
+pub const SECRET_ENV: &str = "DOWNLOAD_SIGNING_SECRET";
+
+/// What a signed download URL points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadKind {
+    /// Egg JSON export of an image, rendered by the panel
+    ImageExport,
+    /// File under `ARTIFACTS_DIR` (node agent binary), served on `/downloads/{file}`
+    Artifact,
+}
+
+impl DownloadKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            DownloadKind::ImageExport => "image_export",
+            DownloadKind::Artifact => "artifact",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "image_export" => Some(DownloadKind::ImageExport),
+            "artifact" => Some(DownloadKind::Artifact),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SignedResource {
+    pub kind: DownloadKind,
+    pub id: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignedUrlError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+/// Lifetime of new download URLs in seconds (`DOWNLOAD_URL_TTL`, default 15 minutes).
+pub fn default_ttl() -> i64 {
+    std::env::var("DOWNLOAD_URL_TTL")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(900)
+}
+
+/// Reads the signing secret from the environment, generating and persisting one to `.env` if unset.
+pub fn load_or_create_secret() -> Vec<u8> {
+    if let Some(secret) = std::env::var(SECRET_ENV)
+        .ok()
+        .and_then(|v| URL_SAFE_NO_PAD.decode(v.trim()).ok())
+        .filter(|s| s.len() >= 32)
+    {
+        return secret;
+    }
+
+    tracing::info!(
+        "{} not set, generating a new download signing secret",
+        SECRET_ENV
+    );
+    let secret = generate_secret();
+    persist_secret(&secret);
+    secret
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Writes the secret to `.env` so signed URLs survive restarts.
+pub fn persist_secret(secret: &[u8]) {
+    let env_path = std::path::Path::new(".env");
+    let line = format!("{}={}", SECRET_ENV, URL_SAFE_NO_PAD.encode(secret));
+    let prefix = format!("{}=", SECRET_ENV);
+
+    let content = std::fs::read_to_string(env_path).unwrap_or_default();
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|l| !l.starts_with(&prefix))
+        .map(|l| l.to_string())
+        .collect();
+    lines.push(line);
+
+    if let Err(e) = std::fs::write(env_path, lines.join("\n")) {
+        tracing::error!("Failed to persist {}: {}", SECRET_ENV, e);
+    }
+}
+
+fn mac(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Token for `/download/{token}`: base64(kind:id:expiry) "." base64(HMAC-SHA256).
+pub fn sign(secret: &[u8], kind: DownloadKind, id: &str, expires_at: i64) -> String {
+    let payload = format!("{}:{}:{}", kind.as_str(), id, expires_at);
+    let signature = mac(secret, &payload).finalize().into_bytes();
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+/// Signed `/download/...` path valid for `ttl` seconds from now.
+pub fn signed_path(secret: &[u8], kind: DownloadKind, id: &str, ttl: i64) -> String {
+    let expires_at = chrono::Utc::now().timestamp() + ttl;
+    format!("/download/{}", sign(secret, kind, id, expires_at))
+}
+
+/// Checks the signature (in constant time) before looking at the expiry.
+pub fn verify(secret: &[u8], token: &str, now: i64) -> Result<SignedResource, SignedUrlError> {
+    let (payload_b64, signature_b64) = token.split_once('.').ok_or(SignedUrlError::Malformed)?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload_b64)
+        .ok()
+        .and_then(|p| String::from_utf8(p).ok())
+        .ok_or(SignedUrlError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature_b64)
+        .map_err(|_| SignedUrlError::Malformed)?;
+
+    mac(secret, &payload)
+        .verify_slice(&signature)
+        .map_err(|_| SignedUrlError::BadSignature)?;
+
+    let (kind, rest) = payload.split_once(':').ok_or(SignedUrlError::Malformed)?;
+    let (id, expires_at) = rest.rsplit_once(':').ok_or(SignedUrlError::Malformed)?;
+    let kind = DownloadKind::parse(kind).ok_or(SignedUrlError::Malformed)?;
+    let expires_at = expires_at
+        .parse::<i64>()
+        .map_err(|_| SignedUrlError::Malformed)?;
+
+    if now > expires_at {
+        return Err(SignedUrlError::Expired);
+    }
+
+    Ok(SignedResource {
+        kind,
+        id: id.to_string(),
+    })
+}
@@ -0,0 +1,93 @@
+//! One token change per node at a time. A rotation stores a pending token, asks the node to
+//! switch, then stores the new token; two of them interleaving can leave the panel with one
+//! token and the node with the other. Held in Redis (`node:{id}:token_lock`) so every panel
+//! instance sees it, or in memory while Redis is unset or degraded. A lock nobody releases
+//! (a panel that died mid-rotation) expires after `LOCK_TTL`.
+
+use crate::services::redis_cache::RedisCache;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Longer than a rotation takes, node retries included
+const LOCK_TTL: Duration = Duration::from_secs(120);
+
+fn lock_key(node_id: Uuid) -> String {
+    format!("node:{}:token_lock", node_id)
+}
+
+/// Proof of holding a node's token lock; hand it back to `TokenLocks::release`.
+pub struct TokenLock {
+    node_id: Uuid,
+    holder: String,
+}
+
+pub struct TokenLocks {
+    redis: Arc<RedisCache>,
+    /// node id -> (holder, expiry), used without Redis
+    local: Mutex<HashMap<Uuid, (String, Instant)>>,
+}
+
+impl TokenLocks {
+    pub fn new(redis: Arc<RedisCache>) -> Self {
+        Self {
+            redis,
+            local: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes the node's lock, or `None` while another token change for it is running.
+    pub async fn acquire(&self, node_id: Uuid) -> Option<TokenLock> {
+        let holder = Uuid::new_v4().to_string();
+
+        if let Some(mut con) = self.redis.connection() {
+            let set: redis::RedisResult<Option<String>> = redis::cmd("SET")
+                .arg(lock_key(node_id))
+                .arg(&holder)
+                .arg("NX")
+                .arg("EX")
+                .arg(LOCK_TTL.as_secs())
+                .query_async(&mut con)
+                .await;
+            self.redis.observe(&set);
+            match set {
+                Ok(Some(_)) => return Some(TokenLock { node_id, holder }),
+                Ok(None) => return None,
+                // Redis failed mid-way; fall back to this instance's locks
+                Err(_) => {}
+            }
+        }
+
+        let now = Instant::now();
+        let mut local = self.local.lock().unwrap();
+        local.retain(|_, (_, expires)| *expires > now);
+        if local.contains_key(&node_id) {
+            return None;
+        }
+        local.insert(node_id, (holder.clone(), now + LOCK_TTL));
+        Some(TokenLock { node_id, holder })
+    }
+
+    /// Gives the lock back, unless it already expired and someone else took it.
+    pub async fn release(&self, lock: TokenLock) {
+        {
+            let mut local = self.local.lock().unwrap();
+            if matches!(local.get(&lock.node_id), Some((h, _)) if *h == lock.holder) {
+                local.remove(&lock.node_id);
+                return;
+            }
+        }
+
+        if let Some(mut con) = self.redis.connection() {
+            let key = lock_key(lock.node_id);
+            let current: redis::RedisResult<Option<String>> = con.get(&key).await;
+            self.redis.observe(&current);
+            if matches!(current, Ok(Some(ref h)) if *h == lock.holder) {
+                let res: redis::RedisResult<()> = con.del(&key).await;
+                self.redis.observe(&res);
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Server availability from heartbeat container states. Every heartbeat either extends the
+//! server's current segment (`server_uptime_segments`) or starts a new one when the state
+//! flipped, the container restarted in between, or heartbeats stopped for longer than the
+//! node's stale window. Time between segments is unknown: by default it is left out of the
+//! percentage, with `UPTIME_UNKNOWN_AS_DOWN=true` it counts as downtime.
+
+use crate::models::ContainerState;
+use crate::state::AppState;
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Longest window shown; older segments are pruned
+const RETENTION_DAYS: i64 = 31;
+
+/// How often old segments are pruned
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+fn unknown_as_down() -> bool {
+    std::env::var("UPTIME_UNKNOWN_AS_DOWN")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Segment {
+    pub server_id: String,
+    pub up: bool,
+    pub started_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct OpenSegment {
+    id: i64,
+    server_id: String,
+    up: bool,
+    last_seen_at: DateTime<Utc>,
+}
+
+/// Runs off the heartbeat path. Servers the heartbeat doesn't list have no container and
+/// count as down; servers still installing aren't tracked yet.
+pub async fn record(
+    state: AppState,
+    node_id: String,
+    containers: Vec<ContainerState>,
+    stale_after_secs: u64,
+) {
+    let now = Utc::now();
+    let stale_after = ChronoDuration::seconds(stale_after_secs as i64);
+
+    let servers: Vec<String> = match sqlx::query_scalar(
+        "SELECT id::text FROM servers WHERE node_id = $1::uuid AND status NOT IN ('queued', 'installing')",
+    )
+    .bind(&node_id)
+    .fetch_all(&state.db)
+    .await
+    {
+        Ok(ids) => ids,
+        Err(e) => {
+            tracing::error!("Failed to list servers for uptime on node {}: {}", node_id, e);
+            return;
+        }
+    };
+    if servers.is_empty() {
+        return;
+    }
+
+    let open: HashMap<String, OpenSegment> = sqlx::query_as::<_, OpenSegment>(
+        "SELECT DISTINCT ON (server_id) id, server_id::text, up, last_seen_at FROM server_uptime_segments
+         WHERE server_id = ANY($1::uuid[]) ORDER BY server_id, last_seen_at DESC",
+    )
+    .bind(&servers)
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|s| (s.server_id.clone(), s))
+    .collect();
+
+    let mut extended = Vec::new();
+    for server_id in servers {
+        let container = containers.iter().find(|c| c.server_id == server_id);
+        let up = container.is_some_and(|c| c.state == "running");
+        let started_at = container
+            .filter(|_| up)
+            .and_then(|c| Utc.timestamp_opt(c.started_at, 0).single());
+
+        let new_segments = match open.get(&server_id) {
+            // Heartbeats stopped for a while: leave the gap unknown
+            Some(prev) if now - prev.last_seen_at > stale_after => vec![(up, now)],
+            // Up both times, but the container started since: it was down in between
+            Some(prev)
+                if prev.up
+                    && up
+                    && started_at.is_some_and(|s| s > prev.last_seen_at && s <= now) =>
+            {
+                let started_at = started_at.unwrap_or(now);
+                vec![(false, prev.last_seen_at), (up, started_at)]
+            }
+            Some(prev) if prev.up == up => {
+                extended.push(prev.id);
+                continue;
+            }
+            // The state flipped somewhere since the last heartbeat
+            Some(prev) => vec![(up, prev.last_seen_at)],
+            None => vec![(up, now)],
+        };
+
+        let Ok(id) = Uuid::parse_str(&server_id) else {
+            continue;
+        };
+        let mut segments = new_segments.into_iter().peekable();
+        while let Some((seg_up, seg_start)) = segments.next() {
+            let seg_end = segments.peek().map_or(now, |(_, next_start)| *next_start);
+            let res = sqlx::query(
+                "INSERT INTO server_uptime_segments (server_id, up, started_at, last_seen_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(id)
+            .bind(seg_up)
+            .bind(seg_start)
+            .bind(seg_end)
+            .execute(&state.db)
+            .await;
+            if let Err(e) = res {
+                tracing::error!("Failed to record uptime for server {}: {}", server_id, e);
+            }
+        }
+    }
+
+    if !extended.is_empty() {
+        let res = sqlx::query("UPDATE server_uptime_segments SET last_seen_at = $2 WHERE id = ANY($1) AND last_seen_at < $2")
+            .bind(&extended)
+            .bind(now)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!(
+                "Failed to extend uptime segments on node {}: {}",
+                node_id,
+                e
+            );
+        }
+    }
+}
+
+/// Share of `[window_start, now]` the server was up, from its segments. The window starts
+/// no earlier than the server was created. `None` when nothing in the window is known.
+pub fn percentage(
+    segments: &[&Segment],
+    window_start: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    unknown_as_down: bool,
+) -> Option<f64> {
+    let start = window_start.max(created_at);
+    if start >= now {
+        return None;
+    }
+
+    let mut up = 0i64;
+    let mut known = 0i64;
+    for seg in segments {
+        let from = seg.started_at.max(start);
+        let to = seg.last_seen_at.min(now);
+        if to <= from {
+            continue;
+        }
+        let ms = (to - from).num_milliseconds();
+        known += ms;
+        if seg.up {
+            up += ms;
+        }
+    }
+
+    let total = if unknown_as_down {
+        (now - start).num_milliseconds()
+    } else {
+        known
+    };
+    (total > 0).then(|| up as f64 * 100.0 / total as f64)
+}
+
+/// Rolling uptime over the last 24 hours, 7 days and 30 days.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uptime {
+    pub day: Option<f64>,
+    pub week: Option<f64>,
+    pub month: Option<f64>,
+}
+
+impl Uptime {
+    fn from_segments(segments: &[&Segment], created_at: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        let unknown_as_down = unknown_as_down();
+        let over = |days: i64| {
+            percentage(
+                segments,
+                now - ChronoDuration::days(days),
+                created_at,
+                now,
+                unknown_as_down,
+            )
+        };
+        Self {
+            day: over(1),
+            week: over(7),
+            month: over(30),
+        }
+    }
+}
+
+/// `99.2%`, or `-` when unknown.
+pub fn format(pct: &Option<f64>) -> String {
+    match *pct {
+        // Don't round a bad day up to a perfect score
+        Some(p) if (99.95..100.0).contains(&p) => "99.9%".to_string(),
+        Some(p) => format!("{:.1}%", p),
+        None => "-".to_string(),
+    }
+}
+
+async fn segments_since(
+    db: &sqlx::PgPool,
+    server_id: Option<Uuid>,
+    since: DateTime<Utc>,
+) -> Vec<Segment> {
+    sqlx::query_as::<_, Segment>(
+        "SELECT server_id::text, up, started_at, last_seen_at FROM server_uptime_segments
+         WHERE last_seen_at > $1 AND ($2::uuid IS NULL OR server_id = $2)",
+    )
+    .bind(since)
+    .bind(server_id)
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+}
+
+/// Uptime for every server, by server id; for the servers list.
+pub async fn all(db: &sqlx::PgPool) -> HashMap<String, Uptime> {
+    let now = Utc::now();
+    let segments = segments_since(db, None, now - ChronoDuration::days(30)).await;
+    let created: Vec<(String, DateTime<Utc>)> =
+        sqlx::query_as("SELECT id::text, created_at FROM servers")
+            .fetch_all(db)
+            .await
+            .unwrap_or_default();
+
+    let mut by_server: HashMap<&str, Vec<&Segment>> = HashMap::new();
+    for seg in &segments {
+        by_server
+            .entry(seg.server_id.as_str())
+            .or_default()
+            .push(seg);
+    }
+    created
+        .into_iter()
+        .filter_map(|(id, created_at)| {
+            let segments = by_server.get(id.as_str())?;
+            let uptime = Uptime::from_segments(segments, created_at, now);
+            Some((id, uptime))
+        })
+        .collect()
+}
+
+/// Uptime for one server.
+pub async fn server(db: &sqlx::PgPool, server_id: Uuid, created_at: DateTime<Utc>) -> Uptime {
+    let now = Utc::now();
+    let segments = segments_since(db, Some(server_id), now - ChronoDuration::days(30)).await;
+    let segments: Vec<&Segment> = segments.iter().collect();
+    Uptime::from_segments(&segments, created_at, now)
+}
+
+/// Drops segments older than any window shown.
+pub async fn run_prune(state: AppState) {
+    loop {
+        let res = sqlx::query("DELETE FROM server_uptime_segments WHERE last_seen_at < NOW() - make_interval(days => $1)")
+            .bind(RETENTION_DAYS as i32)
+            .execute(&state.db)
+            .await;
+        if let Err(e) = res {
+            tracing::error!("Failed to prune uptime segments: {}", e);
+        }
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap() + ChronoDuration::minutes(minutes)
+    }
+
+    fn segment(up: bool, from: i64, to: i64) -> Segment {
+        Segment {
+            server_id: "server".to_string(),
+            up,
+            started_at: at(from),
+            last_seen_at: at(to),
+        }
+    }
+
+    #[test]
+    fn unknown_time_is_left_out_unless_it_counts_as_down() {
+        // 30 up, 10 down, 20 unknown
+        let segments = [segment(true, 0, 30), segment(false, 30, 40)];
+        let segments: Vec<&Segment> = segments.iter().collect();
+        assert_eq!(
+            percentage(&segments, at(0), at(0), at(60), false),
+            Some(75.0)
+        );
+        assert_eq!(
+            percentage(&segments, at(0), at(0), at(60), true),
+            Some(50.0)
+        );
+    }
+
+    #[test]
+    fn window_is_clipped_to_the_servers_creation() {
+        let segments = [segment(false, 0, 20), segment(true, 20, 60)];
+        let segments: Vec<&Segment> = segments.iter().collect();
+        assert_eq!(
+            percentage(&segments, at(0), at(-100), at(60), false),
+            Some(200.0 / 3.0)
+        );
+        // Created at 40, so only 40..60 counts
+        assert_eq!(
+            percentage(&segments, at(0), at(40), at(60), true),
+            Some(100.0)
+        );
+        assert_eq!(percentage(&segments, at(50), at(0), at(40), false), None);
+    }
+
+    #[test]
+    fn nothing_known_is_no_percentage() {
+        assert_eq!(percentage(&[], at(0), at(0), at(60), false), None);
+        // Nothing recorded counts as all down when asked to
+        assert_eq!(percentage(&[], at(0), at(0), at(60), true), Some(0.0));
+        let outside = segment(true, -30, -10);
+        assert_eq!(percentage(&[&outside], at(0), at(0), at(60), false), None);
+    }
+
+    #[test]
+    fn format_never_rounds_up_to_a_perfect_score() {
+        assert_eq!(format(&Some(100.0)), "100.0%");
+        assert_eq!(format(&Some(99.97)), "99.9%");
+        assert_eq!(format(&Some(99.94)), "99.9%");
+        assert_eq!(format(&Some(50.0)), "50.0%");
+        assert_eq!(format(&None), "-");
+    }
+}
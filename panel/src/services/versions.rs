@@ -0,0 +1,128 @@
+use crate::models::HeartbeatPayload;
+use std::cmp::Ordering;
+
+/// Version the panel expects its node agents to run. Both crates are released together,
+/// so this is simply the panel's own version.
+pub fn expected_node_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Parses `1.2.3`, `v1.2.3` and `1.2.3-dev`; anything else is not comparable.
+fn parse(version: &str) -> Option<semver::Version> {
+    let version = version.trim();
+    semver::Version::parse(version.strip_prefix('v').unwrap_or(version)).ok()
+}
+
+/// Semver ordering of `current` against `expected`, pre-release tags included
+/// (`1.2.0-dev` < `1.2.0`). None when either side is empty or not semver.
+pub fn compare_versions(current: &str, expected: &str) -> Option<Ordering> {
+    Some(parse(current)?.cmp_precedence(&parse(expected)?))
+}
+
+/// Badge shown next to a node's agent version on the dashboard and the node page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionStatus {
+    UpToDate,
+    UpdateAvailable,
+    /// Newer than the panel, e.g. a dev build
+    Ahead,
+    Unknown,
+}
+
+impl VersionStatus {
+    pub fn of(current: &str) -> Self {
+        match compare_versions(current, expected_node_version()) {
+            Some(Ordering::Equal) => VersionStatus::UpToDate,
+            Some(Ordering::Less) => VersionStatus::UpdateAvailable,
+            Some(Ordering::Greater) => VersionStatus::Ahead,
+            None => VersionStatus::Unknown,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VersionStatus::UpToDate => "up to date",
+            VersionStatus::UpdateAvailable => "update available",
+            VersionStatus::Ahead => "ahead/dev",
+            VersionStatus::Unknown => "unknown",
+        }
+    }
+
+    /// Background and text colour for the badge
+    pub fn colors(&self) -> (&'static str, &'static str) {
+        match self {
+            VersionStatus::UpToDate => ("#d4edda", "#155724"),
+            VersionStatus::UpdateAvailable => ("#fff3cd", "#856404"),
+            VersionStatus::Ahead => ("#e2e3f3", "#4b4f9c"),
+            VersionStatus::Unknown => ("#f4f4f4", "#666"),
+        }
+    }
+}
+
+/// Best known agent version: the live heartbeat, else what the node last reported (`nodes.version`).
+pub fn current_version(stored: &str, heartbeat: Option<&HeartbeatPayload>) -> String {
+    heartbeat
+        .map(|h| h.version.trim())
+        .filter(|v| !v.is_empty())
+        .unwrap_or(stored.trim())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_release_versions() {
+        assert_eq!(compare_versions("1.2.3", "1.2.3"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("1.2.3", "1.3.0"), Some(Ordering::Less));
+        assert_eq!(compare_versions("1.10.0", "1.9.9"), Some(Ordering::Greater));
+        assert_eq!(
+            compare_versions("2.0.0", "1.99.99"),
+            Some(Ordering::Greater)
+        );
+    }
+
+    #[test]
+    fn accepts_a_v_prefix_and_whitespace() {
+        assert_eq!(compare_versions("v1.2.3", "1.2.3"), Some(Ordering::Equal));
+        assert_eq!(compare_versions(" 1.2.3\n", "v1.2.4"), Some(Ordering::Less));
+    }
+
+    #[test]
+    fn pre_releases_sort_before_their_release() {
+        assert_eq!(compare_versions("1.2.0-dev", "1.2.0"), Some(Ordering::Less));
+        assert_eq!(
+            compare_versions("1.2.0", "1.2.0-dev"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare_versions("1.2.0-alpha", "1.2.0-beta"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare_versions("1.2.0-dev", "1.2.0-dev"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            compare_versions("1.3.0-dev", "1.2.9"),
+            Some(Ordering::Greater)
+        );
+    }
+
+    #[test]
+    fn build_metadata_does_not_count() {
+        assert_eq!(
+            compare_versions("1.2.3+abc", "1.2.3+def"),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn unparsable_versions_are_not_comparable() {
+        assert_eq!(compare_versions("", "1.2.3"), None);
+        assert_eq!(compare_versions("1.2.3", ""), None);
+        assert_eq!(compare_versions("1.2", "1.2.0"), None);
+        assert_eq!(compare_versions("latest", "1.2.0"), None);
+    }
+}
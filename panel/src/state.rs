@@ -1,24 +1,159 @@
+use crate::http::handlers::auth::{AuthMode, SessionCookieConfig};
+use crate::http::handlers::overview;
+use crate::http::upload::UploadLimits;
 use crate::models::{HeartbeatPayload, Node};
-use redis::aio::ConnectionManager;
+use crate::services::install_logs::InstallLogs;
+use crate::services::node_api::{HealthProbes, NodeOpLimiter, NodeRetryConfig};
+use crate::services::node_tokens;
+use crate::services::rate_limit::RateLimiter;
+use crate::services::redis_cache::RedisCache;
+use crate::services::reservations::AllocationReservations;
+use crate::services::token_locks::TokenLocks;
 use reqwest::Client as HttpClient;
 use sqlx::postgres::PgPool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Node columns for `Node` rows. NULLs left by old migrations are coalesced, since
+/// `#[sqlx(default)]` only covers columns that are missing entirely.
+const NODE_COLUMNS: &str = "id::text, name, ip, port, COALESCE(sftp_port, 2022) AS sftp_port, \
+    COALESCE(ram_limit, 0) AS ram_limit, COALESCE(disk_limit, 0) AS disk_limit, \
+    COALESCE(cpu_limit, 0) AS cpu_limit, COALESCE(version, '') AS version, \
+    COALESCE(auto_allocation_range, '') AS auto_allocation_range, location_id::text";
+
+/// Columns every nodes table has had since the first migration; the rest default.
+const MINIMAL_NODE_COLUMNS_QUERY: &str = "SELECT id::text, name, ip, port FROM nodes";
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
-    pub redis: Option<ConnectionManager>,
+    /// Optional cache layer; `connection()` is `None` while Redis is unset or degraded
+    pub redis: Arc<RedisCache>,
     pub http_client: HttpClient,
+    pub node_retry: NodeRetryConfig,
+    pub node_ops: Arc<NodeOpLimiter>,
+    pub health_probes: Arc<HealthProbes>,
     pub panel_name: Arc<RwLock<String>>,
     pub panel_font: Arc<RwLock<String>>,
     pub panel_font_url: Arc<RwLock<String>>,
     pub nodes_cache: Arc<RwLock<Option<Vec<Node>>>>,
     pub heartbeats_cache: Arc<RwLock<HashMap<String, HeartbeatPayload>>>,
+    /// HMAC key for `/download/{token}` URLs (see services::signed_urls)
+    pub download_secret: Arc<RwLock<Vec<u8>>>,
+    /// Fires whenever a heartbeat is stored; overview sockets re-render on it
+    pub heartbeat_events: tokio::sync::broadcast::Sender<String>,
+    /// `APP_KEY`: encrypts secret server variables and node tokens at rest (see
+    /// services::secrets and services::node_tokens)
+    pub secrets_key: Arc<Vec<u8>>,
+    /// Which routes `auth_middleware` guards (`AUTH_MODE`)
+    pub auth_mode: AuthMode,
+    /// `COOKIE_SECURE`/`COOKIE_SAMESITE` for the session cookie
+    pub session_cookie: SessionCookieConfig,
+    /// Nudges the job worker after an enqueue instead of waiting for its next poll
+    pub jobs_wake: Arc<tokio::sync::Notify>,
+    /// Per-IP budget for the unauthenticated `/public/servers/{id}/status`
+    pub public_status_limiter: Arc<RateLimiter>,
+    /// Output of running server installs, for the manage page
+    pub install_logs: Arc<InstallLogs>,
+    /// Allocations held while a create-server form has them selected
+    pub allocation_reservations: Arc<AllocationReservations>,
+    /// One token rotation per node at a time
+    pub token_locks: Arc<TokenLocks>,
+    /// Latest applied migration at startup, reported by `/health`
+    pub schema_version: Option<i64>,
+    /// `https` when the panel terminates TLS itself (see http::listen)
+    pub public_scheme: &'static str,
+    pub listen_port: u16,
+    /// Per-route caps for multipart uploads
+    pub upload_limits: UploadLimits,
 }
 
 impl AppState {
+    /// State for a panel on `db` (already migrated), configured from the environment.
+    /// `secrets_key` is `APP_KEY` and `download_secret` the download signing secret; the
+    /// panel loads both from `.env`, the integration tests bring their own.
+    pub async fn from_env(
+        db: PgPool,
+        secrets_key: Vec<u8>,
+        download_secret: Vec<u8>,
+        schema_version: Option<i64>,
+        public_scheme: &'static str,
+        listen_port: u16,
+    ) -> Self {
+        let redis = Arc::new(RedisCache::from_env().await);
+
+        // Written by hand into .env, so it may not be what the settings form would accept
+        let panel_name = std::env::var("PANEL_NAME").unwrap_or_default();
+        let panel_name =
+            overview::clean_panel_text(&panel_name, overview::DEFAULT_PANEL_NAME, false);
+        let panel_font = std::env::var("PANEL_FONT").unwrap_or_default();
+        let panel_font =
+            overview::clean_panel_text(&panel_font, overview::DEFAULT_PANEL_FONT, true);
+        let panel_font_url = std::env::var("PANEL_FONT_URL")
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let panel_font_url = match overview::check_font_url(&panel_font_url) {
+            Ok(()) => panel_font_url,
+            Err(e) => {
+                tracing::warn!("Ignoring PANEL_FONT_URL: {}", e);
+                String::new()
+            }
+        };
+
+        AppState {
+            db,
+            redis: redis.clone(),
+            http_client: HttpClient::new(),
+            node_retry: NodeRetryConfig::from_env(),
+            node_ops: Arc::new(NodeOpLimiter::from_env()),
+            health_probes: Arc::new(HealthProbes::from_env()),
+            panel_name: Arc::new(RwLock::new(panel_name)),
+            panel_font: Arc::new(RwLock::new(panel_font)),
+            panel_font_url: Arc::new(RwLock::new(panel_font_url)),
+            nodes_cache: Arc::new(RwLock::new(None)),
+            heartbeats_cache: Arc::new(RwLock::new(HashMap::new())),
+            download_secret: Arc::new(RwLock::new(download_secret)),
+            heartbeat_events: tokio::sync::broadcast::channel(64).0,
+            secrets_key: Arc::new(secrets_key),
+            auth_mode: AuthMode::from_env(),
+            session_cookie: SessionCookieConfig::from_env(),
+            jobs_wake: Arc::new(tokio::sync::Notify::new()),
+            public_status_limiter: Arc::new(RateLimiter::from_env("PUBLIC_STATUS_RATE_LIMIT", 60)),
+            install_logs: Default::default(),
+            allocation_reservations: Arc::new(AllocationReservations::from_env(redis.clone())),
+            token_locks: Arc::new(TokenLocks::new(redis)),
+            schema_version,
+            public_scheme,
+            listen_port,
+            upload_limits: UploadLimits::from_env(),
+        }
+    }
+
+    /// `scheme://host` the request reached the panel at, for URLs handed to nodes and
+    /// users (install scripts, status links). Behind a TLS-terminating proxy the scheme
+    /// comes from `X-Forwarded-Proto`.
+    pub fn base_url(&self, headers: &axum::http::HeaderMap) -> String {
+        let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+        let scheme = self.request_scheme(headers);
+        match header("host") {
+            Some(host) => format!("{}://{}", scheme, host),
+            None => format!("{}://127.0.0.1:{}", scheme, self.listen_port),
+        }
+    }
+
+    /// `https` when the panel serves TLS itself or a proxy says it terminated TLS.
+    pub fn request_scheme(&self, headers: &axum::http::HeaderMap) -> &'static str {
+        match headers
+            .get("x-forwarded-proto")
+            .and_then(|h| h.to_str().ok())
+        {
+            Some("https") => "https",
+            _ => self.public_scheme,
+        }
+    }
+
     pub async fn get_nodes(&self) -> Vec<Node> {
         // 1. Check RAM Cache
         {
@@ -29,41 +164,116 @@ impl AppState {
         }
 
         // 2. Check Redis Cache
-        if let Some(manager) = &self.redis {
-            let mut con = manager.clone();
+        if let Some(mut con) = self.redis.connection() {
             let cached: Result<String, _> =
                 redis::AsyncCommands::get(&mut con, "cache:nodes").await;
-            if let Ok(json) = cached {
-                if let Ok(nodes) = serde_json::from_str::<Vec<Node>>(&json) {
-                    // Populate RAM
-                    let mut lock = self.nodes_cache.write().await;
-                    *lock = Some(nodes.clone());
-                    return nodes;
-                }
+            self.redis.observe(&cached);
+            if let Ok(json) = cached
+                && let Ok(nodes) = serde_json::from_str::<Vec<Node>>(&json)
+            {
+                // Populate RAM
+                let mut lock = self.nodes_cache.write().await;
+                *lock = Some(nodes.clone());
+                return nodes;
             }
         }
 
         // 3. Fetch DB
-        let nodes_result = sqlx::query_as::<_, Node>("SELECT id::text, name, ip, port, token, sftp_port, ram_limit, disk_limit, cpu_limit, version FROM nodes")
-            .fetch_all(&self.db)
-            .await;
-
-        let nodes = nodes_result.unwrap_or_default();
+        let nodes = match self.fetch_nodes().await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                // Schema drift must not blank the whole dashboard; serve what the base table has
+                tracing::error!(
+                    "Failed to load nodes ({}); falling back to id/name/ip/port. Restart the panel so its startup migrations add the missing node columns.",
+                    e
+                );
+                return sqlx::query_as::<_, Node>(MINIMAL_NODE_COLUMNS_QUERY)
+                    .fetch_all(&self.db)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Minimal node query failed too: {}", e);
+                        Vec::new()
+                    });
+            }
+        };
 
         // 4. Update Caches
+        self.store_nodes(&nodes).await;
+        nodes
+    }
+
+    async fn fetch_nodes(&self) -> Result<Vec<Node>, sqlx::Error> {
+        sqlx::query_as::<_, Node>(&format!("SELECT {} FROM nodes", NODE_COLUMNS))
+            .fetch_all(&self.db)
+            .await
+    }
+
+    async fn store_nodes(&self, nodes: &[Node]) {
         // Update Redis
-        if let Some(manager) = &self.redis {
-            let mut con = manager.clone();
-            let json = serde_json::to_string(&nodes).unwrap_or_default();
-            let _: Result<(), _> =
+        if let Some(mut con) = self.redis.connection() {
+            let json = serde_json::to_string(nodes).unwrap_or_default();
+            let res: Result<(), _> =
                 redis::AsyncCommands::set_ex(&mut con, "cache:nodes", json, 300).await; // 5 min TTL
+            self.redis.observe(&res);
         }
 
         // Update RAM
         let mut lock = self.nodes_cache.write().await;
-        *lock = Some(nodes.clone());
+        *lock = Some(nodes.to_vec());
+    }
 
-        nodes
+    /// Reloads the nodes cache from the DB, picking up edits made outside this panel.
+    /// A failed query keeps the cache as it is.
+    pub async fn refresh_nodes_cache(&self) {
+        match self.fetch_nodes().await {
+            Ok(nodes) => self.store_nodes(&nodes).await,
+            Err(e) => tracing::warn!("Failed to refresh the nodes cache: {}", e),
+        }
+    }
+
+    /// Drops this instance's RAM copy only; the sender already cleared Redis.
+    pub async fn clear_local_nodes_cache(&self) {
+        *self.nodes_cache.write().await = None;
+    }
+
+    /// Node row including its decrypted API token, straight from the DB. `get_nodes` never
+    /// carries tokens, so anything that calls the node agent goes through here.
+    pub async fn get_node_with_token(&self, node_id: &str) -> Option<Node> {
+        let mut node = sqlx::query_as::<_, Node>(&format!(
+            "SELECT {}, COALESCE(token, '') AS token FROM nodes WHERE id = $1::uuid",
+            NODE_COLUMNS
+        ))
+        .bind(node_id)
+        .fetch_optional(&self.db)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("Failed to load node {}: {}", node_id, e);
+            None
+        })?;
+        node.token = node_tokens::open_or_empty(&self.secrets_key, node_id, &node.token);
+        Some(node)
+    }
+
+    /// Latest heartbeat for a node if it is still fresh (Redis first, then memory).
+    pub async fn node_stats(&self, node_id: &str) -> Option<HeartbeatPayload> {
+        if let Some(mut con) = self.redis.connection() {
+            let key = format!("node:{}:stats", node_id);
+            let cached: Result<String, _> = redis::AsyncCommands::get(&mut con, &key).await;
+            self.redis.observe(&cached);
+            if let Ok(json) = cached
+                && let Ok(payload) = serde_json::from_str::<HeartbeatPayload>(&json)
+            {
+                return Some(payload);
+            }
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        self.heartbeats_cache
+            .read()
+            .await
+            .get(node_id)
+            .filter(|p| p.is_fresh(now))
+            .cloned()
     }
 
     pub async fn invalidate_nodes_cache(&self) {
@@ -72,9 +282,14 @@ impl AppState {
         *lock = None;
 
         // Clear Redis
-        if let Some(manager) = &self.redis {
-            let mut con = manager.clone();
-            let _: Result<(), _> = redis::AsyncCommands::del(&mut con, "cache:nodes").await;
+        if let Some(mut con) = self.redis.connection() {
+            let res: Result<(), _> = redis::AsyncCommands::del(&mut con, "cache:nodes").await;
+            self.redis.observe(&res);
         }
+
+        // Other panel instances keep their own RAM copy
+        self.redis
+            .publish(crate::services::nodes_cache::INVALIDATE_CHANNEL, "nodes")
+            .await;
     }
 }
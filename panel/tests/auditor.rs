@@ -0,0 +1,73 @@
+//! The auditor role through the router: every page, no changes.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use panel::http::handlers::auth::AuthMode;
+use reqwest::StatusCode;
+
+/// The `kind` of each `/api/search` result for `q`, in order.
+async fn search_kinds(panel: &TestPanel, cookie: &str, q: &str) -> Vec<String> {
+    let res = panel.get_as(cookie, &format!("/api/search?q={}", q)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let results: Vec<serde_json::Value> = res.json().await.unwrap();
+    results
+        .iter()
+        .map(|r| r["kind"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn auditor_can_read_but_not_delete_servers() {
+    let Some(panel) = TestPanel::start_with(|state| state.auth_mode = AuthMode::All).await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let server_id = panel.insert_server(node_id, image_id, "Survival").await;
+    let auditor = panel
+        .insert_user("auditor", "auditor", "hunter2hunter2")
+        .await;
+    let cookie = panel.session_cookie(auditor).await;
+
+    let res = panel.get_as(&cookie, "/servers").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.text().await.unwrap().contains("Survival"));
+
+    let path = format!("/servers/{}/delete", server_id);
+    let res = panel.post_form_as(&cookie, &path, &[]).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let status: String = sqlx::query_scalar("SELECT status FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(status, "running");
+    assert!(node.requests().is_empty());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn auditor_searches_all_servers_and_nodes_but_not_users() {
+    let Some(panel) = TestPanel::start_with(|state| state.auth_mode = AuthMode::All).await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    // Owned by someone else
+    panel.insert_server(node_id, image_id, "Survival").await;
+    let auditor = panel
+        .insert_user("auditor", "auditor", "hunter2hunter2")
+        .await;
+    let cookie = panel.session_cookie(auditor).await;
+
+    assert_eq!(search_kinds(&panel, &cookie, "surv").await, ["server"]);
+    let node_name = format!("node-{}", &node_id.simple().to_string()[..8]);
+    assert_eq!(search_kinds(&panel, &cookie, &node_name).await, ["node"]);
+    assert!(search_kinds(&panel, &cookie, "auditor").await.is_empty());
+
+    panel.finish().await;
+}
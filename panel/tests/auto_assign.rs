@@ -0,0 +1,315 @@
+//! Auto-assigned allocations and nodes on the create form: around ports other open forms
+//! hold, and inside the chosen location.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use panel::services::reservations::AllocationReservations;
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+struct Setup {
+    node_id: Uuid,
+    runtime_id: Uuid,
+    image_id: Uuid,
+    _node: MockNode,
+}
+
+async fn setup(panel: &TestPanel) -> Setup {
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    Setup {
+        node_id,
+        runtime_id,
+        image_id,
+        _node: node,
+    }
+}
+
+/// Submits the create form with "Auto" allocation; returns where it redirected to.
+async fn create_auto(
+    panel: &TestPanel,
+    setup: &Setup,
+    name: &str,
+    reservation_token: &str,
+) -> String {
+    let res = panel
+        .post_form(
+            "/servers",
+            &[
+                ("name", name),
+                ("runtime_id", &setup.runtime_id.to_string()),
+                ("image_id", &setup.image_id.to_string()),
+                ("node_id", &setup.node_id.to_string()),
+                ("default_allocation", ""),
+                ("reservation_token", reservation_token),
+            ],
+        )
+        .await;
+    common::location(&res)
+}
+
+async fn assigned_port(panel: &TestPanel, name: &str) -> i32 {
+    sqlx::query_scalar(
+        "SELECT a.port FROM allocations a JOIN servers s ON s.id = a.server_id WHERE s.name = $1",
+    )
+    .bind(name)
+    .fetch_one(panel.db())
+    .await
+    .unwrap()
+}
+
+async fn reserve(panel: &TestPanel, holder: &str, allocation_id: Uuid) {
+    let allocation_id = allocation_id.to_string();
+    let res = panel
+        .post_form(
+            "/servers/new/reserve-allocation",
+            &[
+                ("reservation_token", holder),
+                ("allocation_id", &allocation_id),
+            ],
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn auto_assign_skips_a_reserved_allocation_until_it_expires() {
+    let Some(panel) = TestPanel::start_with(|state| {
+        state.allocation_reservations = Arc::new(AllocationReservations::new(
+            state.redis.clone(),
+            Duration::from_secs(1),
+        ));
+    })
+    .await
+    else {
+        return;
+    };
+    let setup = setup(&panel).await;
+    let first = panel.insert_allocation(setup.node_id, 25565).await;
+    panel.insert_allocation(setup.node_id, 25566).await;
+    reserve(&panel, "other-form", first).await;
+
+    assert!(
+        !create_auto(&panel, &setup, "First", "my-form")
+            .await
+            .contains("error=")
+    );
+    assert_eq!(assigned_port(&panel, "First").await, 25566);
+
+    // Only the held port is left
+    assert_eq!(
+        create_auto(&panel, &setup, "Second", "my-form").await,
+        "/servers/new?error=no_allocations"
+    );
+
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    assert!(
+        !create_auto(&panel, &setup, "Second", "my-form")
+            .await
+            .contains("error=")
+    );
+    assert_eq!(assigned_port(&panel, "Second").await, 25565);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn auto_assign_finds_a_free_allocation_behind_many_held_ones() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let setup = setup(&panel).await;
+    for port in 30000..30030 {
+        let id = panel.insert_allocation(setup.node_id, port).await;
+        if port < 30029 {
+            reserve(&panel, &format!("form-{}", port), id).await;
+        }
+    }
+
+    assert!(
+        !create_auto(&panel, &setup, "Behind", "my-form")
+            .await
+            .contains("error=")
+    );
+    assert_eq!(assigned_port(&panel, "Behind").await, 30029);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn auto_assign_may_take_the_forms_own_reservation() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let setup = setup(&panel).await;
+    let only = panel.insert_allocation(setup.node_id, 25565).await;
+    reserve(&panel, "my-form", only).await;
+
+    assert!(
+        !create_auto(&panel, &setup, "Mine", "my-form")
+            .await
+            .contains("error=")
+    );
+    assert_eq!(assigned_port(&panel, "Mine").await, 25565);
+
+    panel.finish().await;
+}
+
+/// Gives `node_id` an auto-allocation range and RAM limit (0 = unlimited, so placed first).
+async fn set_range(panel: &TestPanel, node_id: Uuid, range: &str, ram_limit: i32) {
+    sqlx::query("UPDATE nodes SET auto_allocation_range = $2, ram_limit = $3 WHERE id = $1")
+        .bind(node_id)
+        .bind(range)
+        .bind(ram_limit)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    panel.state.invalidate_nodes_cache().await;
+}
+
+#[tokio::test]
+async fn auto_allocation_skips_nodes_that_cant_take_the_server() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let mut nodes = Vec::new();
+    for _ in 0..4 {
+        let node = MockNode::start().await;
+        let id = panel.insert_node(&node).await;
+        nodes.push((id, node));
+    }
+    let [full, decommissioning, offline, chosen] = [0, 1, 2, 3].map(|i| nodes[i].0);
+    for (id, node) in [&nodes[0], &nodes[1], &nodes[3]] {
+        assert_eq!(panel.heartbeat(*id, &node.token()).await, StatusCode::OK);
+    }
+    // Everything but `chosen` ranks ahead of it on free RAM
+    set_range(&panel, full, "40000-40000", 0).await;
+    set_range(&panel, decommissioning, "41000-41010", 0).await;
+    set_range(&panel, offline, "42000-42010", 0).await;
+    set_range(&panel, chosen, "43000-43010", 4096).await;
+    // The only port in `full`'s range is in use
+    let used = panel.insert_allocation(full, 40000).await;
+    let server = panel.insert_server(full, image_id, "Existing").await;
+    sqlx::query("UPDATE allocations SET server_id = $2 WHERE id = $1")
+        .bind(used)
+        .bind(server)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    sqlx::query("UPDATE nodes SET decommission_started_at = NOW() WHERE id = $1")
+        .bind(decommissioning)
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    let res = panel
+        .post_form(
+            "/servers",
+            &[
+                ("name", "Anywhere"),
+                ("runtime_id", &runtime_id.to_string()),
+                ("image_id", &image_id.to_string()),
+                ("default_allocation", ""),
+            ],
+        )
+        .await;
+    assert!(!common::location(&res).contains("error="));
+    assert_eq!(assigned_port(&panel, "Anywhere").await, 43000);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn creates_stay_inside_the_chosen_location() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let setup = setup(&panel).await;
+    let elsewhere = MockNode::start().await;
+    let elsewhere_id = panel.insert_node(&elsewhere).await;
+    assert_eq!(
+        panel.heartbeat(elsewhere_id, &elsewhere.token()).await,
+        StatusCode::OK
+    );
+    let eu = panel::services::locations::create(panel.db(), "EU", "")
+        .await
+        .unwrap();
+    // Placement would rank the unlimited node elsewhere first
+    sqlx::query("UPDATE nodes SET location_id = $2, ram_limit = 4096 WHERE id = $1")
+        .bind(setup.node_id)
+        .bind(eu)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    panel.state.invalidate_nodes_cache().await;
+    // Lower port outside the location, so only the scope keeps auto-assign off it
+    let outside = panel.insert_allocation(elsewhere_id, 25565).await;
+    panel.insert_allocation(setup.node_id, 25600).await;
+    let (portless_runtime, portless_image) =
+        panel.insert_image("ghcr.io/example/bot:1", false).await;
+
+    let create = |name: &'static str, fields: Vec<(&'static str, String)>| {
+        let mut fields = fields;
+        fields.push(("name", name.to_string()));
+        fields.push(("location_id", eu.to_string()));
+        let panel = &panel;
+        async move {
+            let fields: Vec<(&str, &str)> = fields.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            common::location(&panel.post_form("/servers", &fields).await)
+        }
+    };
+    let image = |runtime: Uuid, image: Uuid| {
+        vec![
+            ("runtime_id", runtime.to_string()),
+            ("image_id", image.to_string()),
+        ]
+    };
+
+    let mut auto = image(setup.runtime_id, setup.image_id);
+    auto.push(("default_allocation", String::new()));
+    assert!(!create("Auto", auto).await.contains("error="));
+    assert_eq!(assigned_port(&panel, "Auto").await, 25600);
+
+    let mut picked = image(setup.runtime_id, setup.image_id);
+    picked.push(("default_allocation", outside.to_string()));
+    assert_eq!(
+        create("Picked", picked).await,
+        "/servers/new?error=node_outside_location"
+    );
+
+    let mut on_node = image(portless_runtime, portless_image);
+    on_node.push(("node_id", elsewhere_id.to_string()));
+    assert_eq!(
+        create("OnNode", on_node).await,
+        "/servers/new?error=node_outside_location"
+    );
+
+    // Left to placement, a portless server lands inside the location
+    assert!(
+        !create("Placed", image(portless_runtime, portless_image))
+            .await
+            .contains("error=")
+    );
+    let placed_on: Uuid = sqlx::query_scalar("SELECT node_id FROM servers WHERE name = 'Placed'")
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(placed_on, setup.node_id);
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM servers")
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+
+    panel.finish().await;
+}
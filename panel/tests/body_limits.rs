@@ -0,0 +1,61 @@
+//! Request body caps: `MAX_BODY_SIZE` (2 MiB by default) everywhere, and the higher
+//! `EGG_UPLOAD_SIZE` (4 MiB) on egg imports.
+
+mod common;
+
+use common::TestPanel;
+use reqwest::StatusCode;
+
+const MIB: usize = 1024 * 1024;
+const FORM: &str = "application/x-www-form-urlencoded";
+const JSON: &str = "application/json";
+
+async fn post_bytes(panel: &TestPanel, path: &str, content_type: &str, len: usize) -> StatusCode {
+    panel
+        .client
+        .post(format!("{}{}", panel.url, path))
+        .header("content-type", content_type)
+        .body(vec![b' '; len])
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_are_refused() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    assert_eq!(
+        post_bytes(&panel, "/servers", FORM, 3 * MIB).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert_eq!(
+        post_bytes(&panel, "/api/v1/runtimes", JSON, 3 * MIB).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn egg_imports_get_a_higher_limit() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, _) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let path = format!("/api/v1/runtimes/{}/import-egg", runtime_id);
+
+    // Read in full, then rejected as an egg
+    assert_eq!(
+        post_bytes(&panel, &path, JSON, 3 * MIB).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        post_bytes(&panel, &path, JSON, 5 * MIB).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    panel.finish().await;
+}
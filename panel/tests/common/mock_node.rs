@@ -0,0 +1,409 @@
+//! An in-process stand-in for the node agent, speaking the parts of `node/API.md` the panel's
+//! create, power and token flows use. Containers only exist as a state string.
+
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// One authenticated request the mock answered.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub body: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    token: Mutex<String>,
+    /// Panel URL and this node's id there, for the heartbeat `/update-token` sends
+    panel: Mutex<Option<(String, Uuid)>>,
+    requests: Mutex<Vec<Recorded>>,
+    /// Container uuid -> Docker state
+    containers: Mutex<HashMap<String, String>>,
+    /// Answers queued by `fail_next`, taken by the first matching request
+    failures: Mutex<Vec<Failure>>,
+    /// Power actions are acknowledged but leave the state alone, see `stall_power`
+    power_stalled: Mutex<bool>,
+    /// What `GET /port-bindings` answers; without any the mock is an agent too old to have it
+    port_bindings: Mutex<Option<serde_json::Value>>,
+}
+
+struct Failure {
+    method: String,
+    path: String,
+    status: StatusCode,
+    code: String,
+}
+
+#[derive(Clone)]
+pub struct MockNode {
+    pub port: u16,
+    inner: Arc<Inner>,
+}
+
+impl MockNode {
+    pub async fn start() -> Self {
+        let inner = Arc::new(Inner::default());
+        *inner.token.lock().unwrap() = format!("mock-{}", Uuid::new_v4().simple());
+
+        let app = Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .route("/containers", post(create_container))
+            .route("/containers/{uuid}", delete(delete_container))
+            .route("/containers/{uuid}/state", get(container_state))
+            .route("/containers/{uuid}/inspect", get(inspect_container))
+            .route("/containers/{uuid}/power", post(power_container))
+            .route("/port-bindings", get(port_bindings))
+            .route("/free-ports", get(free_ports))
+            .route("/update-token", post(update_token))
+            .with_state(inner.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { port, inner }
+    }
+
+    /// The token the mock currently accepts.
+    pub fn token(&self) -> String {
+        self.inner.token.lock().unwrap().clone()
+    }
+
+    /// Points the mock at the panel it was registered with as `node_id`.
+    pub fn attach(&self, panel_url: &str, node_id: Uuid) {
+        *self.inner.panel.lock().unwrap() = Some((panel_url.to_string(), node_id));
+    }
+
+    /// Every authenticated request so far, oldest first.
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.inner.requests.lock().unwrap().clone()
+    }
+
+    /// The authenticated requests matching `method` and `path`.
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<Recorded> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path == path)
+            .collect()
+    }
+
+    /// Makes the next `times` authenticated `method path` requests fail with `status` and
+    /// the error `code`, as a node in trouble would.
+    pub fn fail_next(
+        &self,
+        method: &str,
+        path: &str,
+        status: StatusCode,
+        code: &str,
+        times: usize,
+    ) {
+        let mut failures = self.inner.failures.lock().unwrap();
+        for _ in 0..times {
+            failures.push(Failure {
+                method: method.to_string(),
+                path: path.to_string(),
+                status,
+                code: code.to_string(),
+            });
+        }
+    }
+
+    pub fn container_state(&self, uuid: &str) -> Option<String> {
+        self.inner.containers.lock().unwrap().get(uuid).cloned()
+    }
+
+    /// Moves the container to `state`, as Docker would once a slow action finished.
+    pub fn set_container_state(&self, uuid: &str, state: &str) {
+        self.inner
+            .containers
+            .lock()
+            .unwrap()
+            .insert(uuid.to_string(), state.to_string());
+    }
+
+    /// While `stalled`, power actions answer with the unchanged state, like a container that
+    /// takes its time to start or stop.
+    pub fn stall_power(&self, stalled: bool) {
+        *self.inner.power_stalled.lock().unwrap() = stalled;
+    }
+
+    /// Makes `GET /port-bindings` report `bindings`, as `docker inspect` would list them.
+    pub fn set_port_bindings(&self, bindings: serde_json::Value) {
+        *self.inner.port_bindings.lock().unwrap() = Some(bindings);
+    }
+}
+
+fn error(status: StatusCode, code: &str, message: &str) -> Response {
+    (
+        status,
+        Json(serde_json::json!({ "error": message, "code": code })),
+    )
+        .into_response()
+}
+
+/// Records the request, or returns what to send back instead: a 401 when the bearer token is
+/// wrong, or a failure queued by `fail_next`.
+fn reject_unauthorized(
+    inner: &Inner,
+    headers: &HeaderMap,
+    method: &str,
+    path: String,
+    body: serde_json::Value,
+) -> Option<Response> {
+    let expected = format!("Bearer {}", inner.token.lock().unwrap());
+    let presented = headers.get("authorization").and_then(|v| v.to_str().ok());
+    if presented != Some(expected.as_str()) {
+        return Some(error(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Invalid token",
+        ));
+    }
+    let mut failures = inner.failures.lock().unwrap();
+    let failure = failures
+        .iter()
+        .position(|f| f.method == method && f.path == path)
+        .map(|i| failures.remove(i));
+    inner.requests.lock().unwrap().push(Recorded {
+        method: method.to_string(),
+        path,
+        body,
+    });
+    failure.map(|f| error(f.status, &f.code, "Injected failure"))
+}
+
+fn state_body(uuid: &str, state: &str) -> Json<serde_json::Value> {
+    let started_at = if state == "running" {
+        chrono::Utc::now().timestamp()
+    } else {
+        0
+    };
+    Json(serde_json::json!({ "server_id": uuid, "state": state, "started_at": started_at }))
+}
+
+async fn create_container(
+    State(inner): State<Arc<Inner>>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    if let Some(res) = reject_unauthorized(
+        &inner,
+        &headers,
+        "POST",
+        "/containers".to_string(),
+        body.clone(),
+    ) {
+        return res;
+    }
+    let uuid = body["uuid"].as_str().unwrap_or_default().to_string();
+    let start = body.get("start").and_then(|s| s.as_bool()).unwrap_or(true);
+    let state = if start { "running" } else { "created" };
+    inner
+        .containers
+        .lock()
+        .unwrap()
+        .insert(uuid.clone(), state.to_string());
+    Json(Uuid::new_v4().simple().to_string()).into_response()
+}
+
+async fn delete_container(
+    State(inner): State<Arc<Inner>>,
+    Path(uuid): Path<String>,
+    request: Request,
+) -> Response {
+    let path = format!("/containers/{}", uuid);
+    let purge = request
+        .uri()
+        .query()
+        .is_some_and(|q| q.contains("purge=true"));
+    // The query is recorded as the body, so tests can tell a purge from a plain delete
+    if let Some(res) = reject_unauthorized(
+        &inner,
+        request.headers(),
+        "DELETE",
+        path,
+        serde_json::json!({ "purge": purge }),
+    ) {
+        return res;
+    }
+    if inner.containers.lock().unwrap().remove(&uuid).is_none() {
+        return error(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            "No such container",
+        );
+    }
+    Json(serde_json::json!({ "status": "deleted", "killed": false, "purged": purge }))
+        .into_response()
+}
+
+async fn container_state(
+    State(inner): State<Arc<Inner>>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let path = format!("/containers/{}/state", uuid);
+    if let Some(res) = reject_unauthorized(&inner, &headers, "GET", path, serde_json::Value::Null) {
+        return res;
+    }
+    match inner.containers.lock().unwrap().get(&uuid) {
+        Some(state) => state_body(&uuid, state).into_response(),
+        None => error(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            "No such container",
+        ),
+    }
+}
+
+/// A sliver of `docker inspect`: the state, and the env the container was created with.
+async fn inspect_container(
+    State(inner): State<Arc<Inner>>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let path = format!("/containers/{}/inspect", uuid);
+    if let Some(res) = reject_unauthorized(&inner, &headers, "GET", path, serde_json::Value::Null) {
+        return res;
+    }
+    let Some(state) = inner.containers.lock().unwrap().get(&uuid).cloned() else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            "No such container",
+        );
+    };
+    let env: Vec<String> = inner
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|r| r.path == "/containers" && r.body["uuid"] == uuid.as_str())
+        .and_then(|r| r.body["environment"].as_object().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value.as_str().unwrap_or_default()))
+        .collect();
+    Json(serde_json::json!({
+        "Id": Uuid::new_v4().simple().to_string(),
+        "State": { "Status": state },
+        "Config": { "Env": env },
+    }))
+    .into_response()
+}
+
+async fn power_container(
+    State(inner): State<Arc<Inner>>,
+    Path(uuid): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    let path = format!("/containers/{}/power", uuid);
+    if let Some(res) = reject_unauthorized(&inner, &headers, "POST", path, body.clone()) {
+        return res;
+    }
+    let mut containers = inner.containers.lock().unwrap();
+    let Some(state) = containers.get_mut(&uuid) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "container_not_found",
+            "No such container",
+        );
+    };
+    let target = match body["action"].as_str() {
+        Some("start") | Some("restart") => "running",
+        Some("stop") | Some("kill") => "exited",
+        _ => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "Unknown power action",
+            );
+        }
+    };
+    if !*inner.power_stalled.lock().unwrap() {
+        *state = target.to_string();
+    }
+    state_body(&uuid, state).into_response()
+}
+
+async fn port_bindings(State(inner): State<Arc<Inner>>, headers: HeaderMap) -> Response {
+    let path = "/port-bindings".to_string();
+    if let Some(res) = reject_unauthorized(&inner, &headers, "GET", path, serde_json::Value::Null) {
+        return res;
+    }
+    match inner.port_bindings.lock().unwrap().clone() {
+        Some(bindings) => Json(bindings).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Nothing listens on the mock's host, so every port in the range is free.
+async fn free_ports(State(inner): State<Arc<Inner>>, request: Request) -> Response {
+    let range: HashMap<String, u16> = request
+        .uri()
+        .query()
+        .and_then(|q| serde_urlencoded::from_str(q).ok())
+        .unwrap_or_default();
+    let path = "/free-ports".to_string();
+    let query = serde_json::json!(range);
+    if let Some(res) = reject_unauthorized(&inner, request.headers(), "GET", path, query) {
+        return res;
+    }
+    let (start, end) = (range["start"], range["end"]);
+    Json(serde_json::json!({ "start": start, "end": end, "free": (start..=end).collect::<Vec<_>>() }))
+        .into_response()
+}
+
+/// Keeps the new token only once the panel accepted a heartbeat carrying it, like the agent.
+async fn update_token(
+    State(inner): State<Arc<Inner>>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    if let Some(res) = reject_unauthorized(
+        &inner,
+        &headers,
+        "POST",
+        "/update-token".to_string(),
+        serde_json::Value::Null,
+    ) {
+        return res;
+    }
+    let Some(new_token) = body["token"].as_str().map(str::to_string) else {
+        return error(StatusCode::BAD_REQUEST, "invalid_request", "Missing token");
+    };
+    let Some((panel_url, node_id)) = inner.panel.lock().unwrap().clone() else {
+        return error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "Mock node is not attached to a panel",
+        );
+    };
+
+    let verified = reqwest::Client::new()
+        .post(format!("{}/nodes/{}/heartbeat", panel_url, node_id))
+        .bearer_auth(&new_token)
+        .json(&super::heartbeat_payload(node_id))
+        .send()
+        .await
+        .is_ok_and(|r| r.status().is_success());
+    if !verified {
+        return error(
+            StatusCode::BAD_GATEWAY,
+            "token_verification_failed",
+            "Panel rejected the new token",
+        );
+    }
+    *inner.token.lock().unwrap() = new_token;
+    StatusCode::OK.into_response()
+}
@@ -0,0 +1,314 @@
+//! Shared harness for the panel's integration tests: a scratch database per test, the panel's
+//! router served on a local port with its job worker, and an in-process mock of the node agent
+//! API (`node/API.md`) that records what the panel sent it.
+//!
+//! The tests need `TEST_DATABASE_URL`, a Postgres role allowed to create databases (e.g.
+//! `postgres://postgres@localhost/postgres`). Without it they print a note and pass.
+
+// Every test binary uses a different part of the harness
+#![allow(dead_code)]
+
+pub mod mock_node;
+
+use panel::http::handlers::auth::AuthMode;
+use panel::http::routes;
+use panel::services::{jobs, migrations, node_tokens};
+use panel::state::AppState;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub use mock_node::MockNode;
+
+/// How long `wait_for` polls before failing the test.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct TestPanel {
+    pub state: AppState,
+    /// `http://127.0.0.1:<port>` of the running panel
+    pub url: String,
+    /// Doesn't follow redirects, so form posts can check where they lead
+    pub client: reqwest::Client,
+    admin: PgPool,
+    db_name: String,
+}
+
+impl TestPanel {
+    /// A panel on a freshly migrated database, with `AUTH_MODE=off`. `None` (after a note
+    /// on stderr) when `TEST_DATABASE_URL` is unset.
+    pub async fn start() -> Option<Self> {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Like `start`, with `configure` adjusting the state before the panel serves it.
+    pub async fn start_with(configure: impl FnOnce(&mut AppState)) -> Option<Self> {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping this test");
+            return None;
+        };
+        let options =
+            PgConnectOptions::from_str(&url).expect("TEST_DATABASE_URL is not a Postgres URL");
+        let admin = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(options.clone())
+            .await
+            .expect("Failed to connect to TEST_DATABASE_URL");
+        let db_name = format!("panel_test_{}", Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE DATABASE {}", db_name))
+            .execute(&admin)
+            .await
+            .expect("Failed to create the test database");
+
+        let db = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options.database(&db_name))
+            .await
+            .expect("Failed to connect to the test database");
+        let schema_version = migrations::run(&db).await;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut state =
+            AppState::from_env(db, vec![7; 32], vec![9; 32], schema_version, "http", port).await;
+        state.auth_mode = AuthMode::Off;
+        configure(&mut state);
+
+        let app = routes::router(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let mut config = jobs::JobConfig::from_env();
+        config.poll_interval = Duration::from_millis(100);
+        tokio::spawn(jobs::run(state.clone(), config));
+
+        Some(Self {
+            state,
+            url: format!("http://127.0.0.1:{}", port),
+            client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+            admin,
+            db_name,
+        })
+    }
+
+    /// Drops the test's database. Tests that panic leave theirs behind (`panel_test_*`).
+    pub async fn finish(self) {
+        self.state.db.close().await;
+        let _ = sqlx::query(&format!(
+            "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+            self.db_name
+        ))
+        .execute(&self.admin)
+        .await;
+    }
+
+    pub fn db(&self) -> &PgPool {
+        &self.state.db
+    }
+
+    /// Registers `node` with the panel, its token stored sealed like the panel does.
+    pub async fn insert_node(&self, node: &MockNode) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO nodes (id, name, ip, port, token) VALUES ($1, $2, '127.0.0.1', $3, $4)",
+        )
+        .bind(id)
+        .bind(format!("node-{}", &id.simple().to_string()[..8]))
+        .bind(node.port as i32)
+        .bind(node_tokens::seal(&self.state.secrets_key, &node.token()))
+        .execute(self.db())
+        .await
+        .expect("Failed to insert node");
+        self.state.invalidate_nodes_cache().await;
+        node.attach(&self.url, id);
+        id
+    }
+
+    /// A runtime with one image running `docker_image`, without an install script.
+    pub async fn insert_image(&self, docker_image: &str, requires_port: bool) -> (Uuid, Uuid) {
+        let runtime_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO runtimes (id, name) VALUES ($1, $2)")
+            .bind(runtime_id)
+            .bind(format!("runtime-{}", &runtime_id.simple().to_string()[..8]))
+            .execute(self.db())
+            .await
+            .expect("Failed to insert runtime");
+        let image_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO images (id, runtime_id, name, docker_images, startup_command, requires_port) \
+             VALUES ($1, $2, 'Test image', $3, 'java -jar server.jar', $4)",
+        )
+        .bind(image_id)
+        .bind(runtime_id)
+        .bind(docker_image)
+        .bind(requires_port)
+        .execute(self.db())
+        .await
+        .expect("Failed to insert image");
+        (runtime_id, image_id)
+    }
+
+    /// A server row on `node_id` built from `image_id`, without a container anywhere.
+    pub async fn insert_server(&self, node_id: Uuid, image_id: Uuid, name: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO servers (id, name, owner_id, node_id, image_id, docker_image, startup_command, status) \
+             VALUES ($1, $2, '1', $3, $4, 'ghcr.io/example/java:21', 'java -jar server.jar', 'running')",
+        )
+        .bind(id)
+        .bind(name)
+        .bind(node_id)
+        .bind(image_id)
+        .execute(self.db())
+        .await
+        .expect("Failed to insert server");
+        id
+    }
+
+    pub async fn insert_allocation(&self, node_id: Uuid, port: i32) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO allocations (id, node_id, ip, port) VALUES ($1, $2, '0.0.0.0', $3)",
+        )
+        .bind(id)
+        .bind(node_id)
+        .bind(port)
+        .execute(self.db())
+        .await
+        .expect("Failed to insert allocation");
+        id
+    }
+
+    /// A user with `role` who logs in with `password`.
+    pub async fn insert_user(&self, username: &str, role: &str, password: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO users (id, username, email, password_hash, role) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(id)
+        .bind(username)
+        .bind(format!("{}@example.com", username))
+        .bind(bcrypt::hash(password, 4).unwrap())
+        .bind(role)
+        .execute(self.db())
+        .await
+        .expect("Failed to insert user");
+        id
+    }
+
+    /// A `Cookie` header value for a fresh session of `user_id`.
+    pub async fn session_cookie(&self, user_id: Uuid) -> String {
+        let session_id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, NOW() + INTERVAL '1 hour')",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .execute(self.db())
+        .await
+        .expect("Failed to insert session");
+        format!("session_id={}", session_id)
+    }
+
+    /// Posts an urlencoded form, as the browser would.
+    pub async fn post_form(&self, path: &str, fields: &[(&str, &str)]) -> reqwest::Response {
+        self.post_form_as("", path, fields).await
+    }
+
+    /// Like `post_form`, sending `cookie` (see `session_cookie`).
+    pub async fn post_form_as(
+        &self,
+        cookie: &str,
+        path: &str,
+        fields: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.client
+            .post(format!("{}{}", self.url, path))
+            .header("content-type", "application/x-www-form-urlencoded")
+            .header("cookie", cookie)
+            .body(serde_urlencoded::to_string(fields).unwrap())
+            .send()
+            .await
+            .expect("Panel request failed")
+    }
+
+    /// GETs `path` sending `cookie`.
+    pub async fn get_as(&self, cookie: &str, path: &str) -> reqwest::Response {
+        self.client
+            .get(format!("{}{}", self.url, path))
+            .header("cookie", cookie)
+            .send()
+            .await
+            .expect("Panel request failed")
+    }
+
+    pub async fn post_json(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.client
+            .post(format!("{}{}", self.url, path))
+            .json(body)
+            .send()
+            .await
+            .expect("Panel request failed")
+    }
+
+    /// Sends a heartbeat for `node_id` with `token`, as the agent does.
+    pub async fn heartbeat(&self, node_id: Uuid, token: &str) -> reqwest::StatusCode {
+        self.client
+            .post(format!("{}/nodes/{}/heartbeat", self.url, node_id))
+            .bearer_auth(token)
+            .json(&heartbeat_payload(node_id))
+            .send()
+            .await
+            .expect("Heartbeat request failed")
+            .status()
+    }
+}
+
+/// A minimal heartbeat body from an idle four-core node.
+pub fn heartbeat_payload(node_id: Uuid) -> serde_json::Value {
+    serde_json::json!({
+        "node_id": node_id.to_string(),
+        "cpu_usage": 3.5,
+        "ram_usage": 1024,
+        "ram_total": 8192,
+        "uptime": 60,
+        "version": env!("CARGO_PKG_VERSION"),
+        "timestamp": chrono::Utc::now().timestamp_millis(),
+        "heartbeat_interval": 5,
+        "cpu_cores": 4,
+        "containers": [],
+    })
+}
+
+/// The `Location` of a redirect.
+pub fn location(res: &reqwest::Response) -> String {
+    assert!(
+        res.status().is_redirection(),
+        "expected a redirect, got {}",
+        res.status()
+    );
+    res.headers()["location"].to_str().unwrap().to_string()
+}
+
+/// Polls `check` until it returns `Some`, failing the test after `WAIT_TIMEOUT`.
+pub async fn wait_for<T, F, Fut>(what: &str, mut check: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+    loop {
+        if let Some(value) = check().await {
+            return value;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "timed out waiting for {}",
+            what
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
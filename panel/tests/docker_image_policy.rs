@@ -0,0 +1,102 @@
+//! `ENFORCE_IMAGE_DOCKER_IMAGES=true`: servers only get docker images their image lists,
+//! unless an admin types a custom one. Its own binary, since the switch is process-wide.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+async fn docker_image(panel: &TestPanel, server_id: Uuid) -> String {
+    sqlx::query_scalar("SELECT docker_image FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_the_images_docker_images_are_accepted() {
+    // SAFETY: the only test in this binary, set before anything reads the environment
+    unsafe { std::env::set_var("ENFORCE_IMAGE_DOCKER_IMAGES", "true") };
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", false).await;
+    let (runtime_id, image_id, node_id_text) = (
+        runtime_id.to_string(),
+        image_id.to_string(),
+        node_id.to_string(),
+    );
+    let create = |name: &'static str, picked: &'static str, custom: &'static str| {
+        let fields = [
+            ("name", name),
+            ("runtime_id", runtime_id.as_str()),
+            ("image_id", image_id.as_str()),
+            ("node_id", node_id_text.as_str()),
+            ("docker_image", picked),
+            ("custom_docker_image", custom),
+        ];
+        let panel = &panel;
+        async move { common::location(&panel.post_form("/servers", &fields).await) }
+    };
+
+    assert_eq!(
+        create("Picked", "ghcr.io/example/miner:1", "").await,
+        "/servers/new?error=docker_image_not_allowed"
+    );
+    assert!(
+        !create("Listed", "ghcr.io/example/java:21", "")
+            .await
+            .contains("error=")
+    );
+    assert!(
+        !create("Custom", "", "ghcr.io/example/custom:1")
+            .await
+            .contains("error=")
+    );
+
+    // Editing may keep the current (custom) image, but not switch to an unlisted one
+    let custom: Uuid = sqlx::query_scalar("SELECT id FROM servers WHERE name = 'Custom'")
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    let update = format!("/servers/{}/update", custom);
+    let res = panel
+        .post_form(&update, &[("docker_image", "ghcr.io/example/custom:1")])
+        .await;
+    assert_eq!(
+        common::location(&res),
+        format!("/servers/{}/manage", custom)
+    );
+    let res = panel
+        .post_form(&update, &[("docker_image", "ghcr.io/example/miner:1")])
+        .await;
+    assert_eq!(
+        common::location(&res),
+        format!("/servers/{}/edit?error=docker_image_not_allowed", custom)
+    );
+    assert_eq!(
+        docker_image(&panel, custom).await,
+        "ghcr.io/example/custom:1"
+    );
+    let res = panel
+        .post_form(&update, &[("docker_image", "ghcr.io/example/java:21")])
+        .await;
+    assert_eq!(
+        common::location(&res),
+        format!("/servers/{}/manage", custom)
+    );
+    assert_eq!(
+        docker_image(&panel, custom).await,
+        "ghcr.io/example/java:21"
+    );
+
+    panel.finish().await;
+}
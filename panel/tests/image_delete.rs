@@ -0,0 +1,212 @@
+//! Deleting images that servers still use: refused, unless forced onto a replacement image.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+async fn image_exists(panel: &TestPanel, image_id: Uuid) -> bool {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM images WHERE id = $1)")
+        .bind(image_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap()
+}
+
+/// The `HX-Redirect` of an htmx delete from the image edit page.
+fn hx_redirect(res: &reqwest::Response) -> String {
+    res.headers()["hx-redirect"].to_str().unwrap().to_string()
+}
+
+async fn delete_from_page(
+    panel: &TestPanel,
+    runtime_id: Uuid,
+    image_id: Uuid,
+) -> reqwest::Response {
+    panel
+        .client
+        .delete(format!(
+            "{}/runtimes/{}/images/{}",
+            panel.url, runtime_id, image_id
+        ))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// (runtime id, image id) of an image used by one server on a mock node.
+async fn image_in_use(panel: &TestPanel) -> (Uuid, Uuid) {
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    panel.insert_server(node_id, image_id, "Survival").await;
+    (runtime_id, image_id)
+}
+
+#[tokio::test]
+async fn unused_image_is_deleted() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+
+    let res = delete_from_page(&panel, runtime_id, image_id).await;
+    assert_eq!(hx_redirect(&res), "/runtimes");
+    assert!(!image_exists(&panel, image_id).await);
+
+    let (_, api_image) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let res = panel
+        .client
+        .delete(format!("{}/api/v1/images/{}", panel.url, api_image))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert!(!image_exists(&panel, api_image).await);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn image_in_use_is_not_deleted() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, image_id) = image_in_use(&panel).await;
+
+    let res = delete_from_page(&panel, runtime_id, image_id).await;
+    let to = hx_redirect(&res);
+    assert_eq!(
+        to,
+        format!(
+            "/runtimes/{}/images/{}/edit?error=in_use&detail=1",
+            runtime_id, image_id
+        )
+    );
+    assert!(image_exists(&panel, image_id).await);
+
+    let res = panel
+        .client
+        .delete(format!("{}/api/v1/images/{}", panel.url, image_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["error"], "in_use");
+    assert!(image_exists(&panel, image_id).await);
+
+    // The edit page then offers the forced delete, once there is an image to move to
+    panel.insert_image("ghcr.io/example/java:17", true).await;
+    let page = panel
+        .client
+        .get(format!("{}{}", panel.url, to))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(page.contains("used by 1 server(s)"));
+    assert!(page.contains("Move Servers and Delete"));
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn forced_delete_moves_servers_to_the_replacement() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, image_id) = image_in_use(&panel).await;
+    let (_, replacement) = panel.insert_image("ghcr.io/example/java:17", true).await;
+
+    let path = format!(
+        "/runtimes/{}/images/{}/reassign-delete",
+        runtime_id, image_id
+    );
+    let res = panel
+        .post_form(&path, &[("replacement_id", &replacement.to_string())])
+        .await;
+    assert_eq!(hx_redirect(&res), "/runtimes");
+    assert!(!image_exists(&panel, image_id).await);
+
+    let (moved_to, needs_recreate, docker_image): (Uuid, bool, String) = sqlx::query_as(
+        "SELECT image_id, needs_recreate, docker_image FROM servers WHERE name = 'Survival'",
+    )
+    .fetch_one(panel.db())
+    .await
+    .unwrap();
+    assert_eq!(moved_to, replacement);
+    assert!(needs_recreate);
+    assert_eq!(docker_image, "ghcr.io/example/java:21");
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn forced_delete_needs_another_existing_image() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (runtime_id, image_id) = image_in_use(&panel).await;
+
+    let path = format!(
+        "/runtimes/{}/images/{}/reassign-delete",
+        runtime_id, image_id
+    );
+    for replacement in [
+        image_id.to_string(),
+        Uuid::new_v4().to_string(),
+        String::new(),
+    ] {
+        let res = panel
+            .post_form(&path, &[("replacement_id", &replacement)])
+            .await;
+        assert!(hx_redirect(&res).ends_with("/edit?error=invalid_replacement&detail="));
+        assert!(image_exists(&panel, image_id).await);
+    }
+
+    let res = panel
+        .client
+        .delete(format!(
+            "{}/api/v1/images/{}?reassign_to={}",
+            panel.url, image_id, image_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(image_exists(&panel, image_id).await);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn api_forced_delete_moves_servers() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (_, image_id) = image_in_use(&panel).await;
+    let (_, replacement) = panel.insert_image("ghcr.io/example/java:17", true).await;
+
+    let res = panel
+        .client
+        .delete(format!(
+            "{}/api/v1/images/{}?reassign_to={}",
+            panel.url, image_id, replacement
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert!(!image_exists(&panel, image_id).await);
+    let moved_to: Uuid = sqlx::query_scalar("SELECT image_id FROM servers WHERE name = 'Survival'")
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(moved_to, replacement);
+
+    panel.finish().await;
+}
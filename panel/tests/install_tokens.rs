@@ -0,0 +1,61 @@
+//! Node install commands carry a single-use install token, never the node token itself.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+
+/// The `t=` install token in the first install command on `page`.
+fn install_token(page: &str, node_id: &str) -> String {
+    let marker = format!("/install/{}?t=", node_id);
+    let start = page.find(&marker).expect("no install command on the page") + marker.len();
+    page[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
+
+#[tokio::test]
+async fn pages_show_an_install_token_that_works_once() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await.to_string();
+
+    for path in [
+        format!("/nodes/{}/setup", node_id),
+        format!("/nodes/{}/edit", node_id),
+    ] {
+        let page = panel.get_as("", &path).await.text().await.unwrap();
+        assert!(
+            !page.contains(&node.token()),
+            "{} shows the node token",
+            path
+        );
+        assert_eq!(install_token(&page, &node_id).len(), 32, "{}", path);
+    }
+
+    let page = panel
+        .get_as("", &format!("/nodes/{}/setup", node_id))
+        .await
+        .text()
+        .await
+        .unwrap();
+    let script_path = format!("/install/{}?t={}", node_id, install_token(&page, &node_id));
+    let script = panel.get_as("", &script_path).await.text().await.unwrap();
+    assert!(script.contains(&format!("NODE_TOKEN='{}'", node.token())));
+
+    let again = panel.get_as("", &script_path).await.text().await.unwrap();
+    assert!(!again.contains(&node.token()));
+    assert!(again.contains("Install token is invalid, expired or already used"));
+
+    let without = panel
+        .get_as("", &format!("/install/{}", node_id))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!without.contains(&node.token()));
+
+    panel.finish().await;
+}
@@ -0,0 +1,145 @@
+//! The janitor's sweep over servers stuck installing: flagged, or deleted when configured to.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use panel::services::janitor::{self, JanitorConfig};
+use reqwest::StatusCode;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn config(auto_delete: bool) -> JanitorConfig {
+    JanitorConfig {
+        interval: Duration::from_secs(300),
+        stuck_after: Duration::from_secs(3600),
+        auto_delete,
+    }
+}
+
+/// A server on `node_id` in `status`, created `age_minutes` ago.
+async fn server_in(
+    panel: &TestPanel,
+    node_id: Uuid,
+    image_id: Uuid,
+    name: &str,
+    status: &str,
+    age_minutes: i32,
+) -> Uuid {
+    let id = panel.insert_server(node_id, image_id, name).await;
+    sqlx::query(
+        "UPDATE servers SET status = $2, created_at = NOW() - make_interval(mins => $3) WHERE id = $1",
+    )
+    .bind(id)
+    .bind(status)
+    .bind(age_minutes)
+    .execute(panel.db())
+    .await
+    .unwrap();
+    id
+}
+
+async fn flag_reason(panel: &TestPanel, id: Uuid) -> Option<Option<String>> {
+    sqlx::query_scalar("SELECT flag_reason FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(panel.db())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sweep_flags_only_old_unfinished_installs() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let stuck = server_in(&panel, node_id, image_id, "Stuck", "installing", 120).await;
+    let failed = server_in(&panel, node_id, image_id, "Failed", "install_failed", 120).await;
+    let recent = server_in(&panel, node_id, image_id, "Recent", "installing", 5).await;
+    let running = server_in(&panel, node_id, image_id, "Running", "running", 120).await;
+
+    janitor::sweep(&panel.state, &config(false)).await.unwrap();
+
+    assert_eq!(
+        flag_reason(&panel, stuck).await,
+        Some(Some(
+            "Stuck in 'installing' for over 60 minutes".to_string()
+        ))
+    );
+    assert_eq!(
+        flag_reason(&panel, failed).await,
+        Some(Some(
+            "Stuck in 'install_failed' for over 60 minutes".to_string()
+        ))
+    );
+    assert_eq!(flag_reason(&panel, recent).await, Some(None));
+    assert_eq!(flag_reason(&panel, running).await, Some(None));
+    assert!(node.requests().is_empty());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn sweep_deletes_stuck_servers_when_configured_to() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let stuck = server_in(&panel, node_id, image_id, "Stuck", "installing", 120).await;
+    let allocation = panel.insert_allocation(node_id, 25565).await;
+    sqlx::query("UPDATE allocations SET server_id = $2 WHERE id = $1")
+        .bind(allocation)
+        .bind(stuck)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    let running = server_in(&panel, node_id, image_id, "Running", "running", 120).await;
+
+    janitor::sweep(&panel.state, &config(true)).await.unwrap();
+
+    assert_eq!(flag_reason(&panel, stuck).await, None);
+    assert_eq!(flag_reason(&panel, running).await, Some(None));
+    let held: Option<Uuid> = sqlx::query_scalar("SELECT server_id FROM allocations WHERE id = $1")
+        .bind(allocation)
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(held, None);
+    assert_eq!(
+        node.requests_to("DELETE", &format!("/containers/{}", stuck))
+            .len(),
+        1
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn sweep_keeps_the_server_when_the_container_cleanup_fails() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let stuck = server_in(&panel, node_id, image_id, "Stuck", "installing", 120).await;
+    node.fail_next(
+        "DELETE",
+        &format!("/containers/{}", stuck),
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "docker_error",
+        1,
+    );
+
+    janitor::sweep(&panel.state, &config(true)).await.unwrap();
+    assert_eq!(flag_reason(&panel, stuck).await, Some(None));
+
+    // The next sweep retries and succeeds
+    janitor::sweep(&panel.state, &config(true)).await.unwrap();
+    assert_eq!(flag_reason(&panel, stuck).await, None);
+
+    panel.finish().await;
+}
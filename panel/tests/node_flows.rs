@@ -0,0 +1,977 @@
+//! The panel's side of the node protocol, end to end against the mock agent: creating a
+//! server, power actions, deleting, port checks, heartbeats and token rotation.
+
+mod common;
+
+use common::{MockNode, TestPanel, wait_for};
+use panel::http::handlers::auth::AuthMode;
+use panel::services::node_tokens;
+use reqwest::StatusCode;
+use std::time::Duration;
+use uuid::Uuid;
+
+async fn pending_token(panel: &TestPanel, node_id: Uuid) -> Option<String> {
+    sqlx::query_scalar("SELECT pending_token FROM nodes WHERE id = $1")
+        .bind(node_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap()
+}
+
+async fn server_status(panel: &TestPanel, server_id: Uuid) -> String {
+    sqlx::query_scalar("SELECT status FROM servers WHERE id = $1")
+        .bind(server_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap()
+}
+
+/// Creates a server on `node_id` through the create form and waits for its install job.
+async fn create_server(
+    panel: &TestPanel,
+    node_id: Uuid,
+    allocation_id: Uuid,
+    start_on_install: bool,
+) -> Uuid {
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let (runtime_id, image_id, node_id, allocation_id) = (
+        runtime_id.to_string(),
+        image_id.to_string(),
+        node_id.to_string(),
+        allocation_id.to_string(),
+    );
+    let mut fields = vec![
+        ("name", "Survival"),
+        ("runtime_id", runtime_id.as_str()),
+        ("image_id", image_id.as_str()),
+        ("node_id", node_id.as_str()),
+        ("default_allocation", allocation_id.as_str()),
+        ("docker_image", "ghcr.io/example/java:21"),
+        ("ram_limit", "1024"),
+    ];
+    if start_on_install {
+        fields.push(("start_on_install", "on"));
+    }
+    let res = panel.post_form("/servers", &fields).await;
+    let to = common::location(&res);
+    assert!(!to.contains("error="), "create failed: {}", to);
+
+    let server_id: Uuid = sqlx::query_scalar("SELECT id FROM servers WHERE name = 'Survival'")
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    wait_for("the create job", || async {
+        Some(server_status(panel, server_id).await).filter(|s| s != "installing" && s != "queued")
+    })
+    .await;
+    server_id
+}
+
+#[tokio::test]
+async fn create_sends_the_container_to_the_node() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+
+    let server_id = create_server(&panel, node_id, allocation_id, true).await;
+    assert_eq!(server_status(&panel, server_id).await, "running");
+
+    let creates = node.requests_to("POST", "/containers");
+    assert_eq!(creates.len(), 1);
+    let body = &creates[0].body;
+    assert_eq!(body["uuid"], server_id.to_string());
+    assert_eq!(body["image"], "ghcr.io/example/java:21");
+    assert_eq!(body["memory_limit"], 1024);
+    assert_eq!(body["ports"]["25565/tcp"], "25565");
+    assert_eq!(body["environment"]["SERVER_PORT"], "25565");
+    assert_eq!(body["environment"]["SERVER_IP"], "0.0.0.0");
+    assert_eq!(body["environment"]["SERVER_MEMORY"], "1024");
+    assert_eq!(body["environment"]["P_SERVER_UUID"], server_id.to_string());
+    assert_eq!(body["start"], true);
+    assert_eq!(
+        node.container_state(&server_id.to_string()).as_deref(),
+        Some("running")
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn reserved_env_wins_over_image_variables() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", false).await;
+    let variable = |env: &str, value: &str| {
+        serde_json::json!({
+            "name": env,
+            "description": "",
+            "env_variable": env,
+            "default_value": value,
+            "user_viewable": true,
+            "user_editable": false,
+        })
+    };
+    sqlx::query("UPDATE images SET variables = $2 WHERE id = $1")
+        .bind(image_id)
+        .bind(
+            serde_json::json!([variable("SERVER_MEMORY", "999"), variable("MOTD", "Hi")])
+                .to_string(),
+        )
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    let res = panel
+        .post_form(
+            "/servers",
+            &[
+                ("name", "Portless"),
+                ("runtime_id", &runtime_id.to_string()),
+                ("image_id", &image_id.to_string()),
+                ("node_id", &node_id.to_string()),
+                ("docker_image", "ghcr.io/example/java:21"),
+                ("ram_limit", "2048"),
+            ],
+        )
+        .await;
+    let to = common::location(&res);
+    assert!(!to.contains("error="), "create failed: {}", to);
+
+    let creates = wait_for("the create request", || async {
+        Some(node.requests_to("POST", "/containers")).filter(|r| !r.is_empty())
+    })
+    .await;
+    let env = &creates[0].body["environment"];
+    assert_eq!(env["SERVER_MEMORY"], "2048");
+    assert_eq!(env["MOTD"], "Hi");
+    // No allocation, so no address either
+    assert_eq!(env["SERVER_PORT"], "");
+    assert_eq!(env["SERVER_IP"], "");
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn create_without_start_on_install_leaves_the_container_stopped() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+
+    let server_id = create_server(&panel, node_id, allocation_id, false).await;
+
+    let creates = node.requests_to("POST", "/containers");
+    assert_eq!(creates[0].body["start"], false);
+    assert_eq!(
+        node.container_state(&server_id.to_string()).as_deref(),
+        Some("created")
+    );
+
+    panel.finish().await;
+}
+
+/// A panel whose node calls retry without the production backoff.
+async fn quick_retry_panel() -> Option<TestPanel> {
+    TestPanel::start_with(|state| state.node_retry.base_delay = Duration::from_millis(10)).await
+}
+
+#[tokio::test]
+async fn create_retries_transient_node_failures() {
+    let Some(panel) = quick_retry_panel().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    node.fail_next(
+        "POST",
+        "/containers",
+        StatusCode::SERVICE_UNAVAILABLE,
+        "docker_error",
+        2,
+    );
+
+    let server_id = create_server(&panel, node_id, allocation_id, true).await;
+    assert_eq!(server_status(&panel, server_id).await, "running");
+    assert_eq!(node.requests_to("POST", "/containers").len(), 3);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn create_gives_up_on_a_final_node_error() {
+    let Some(panel) = quick_retry_panel().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    node.fail_next(
+        "POST",
+        "/containers",
+        StatusCode::NOT_FOUND,
+        "image_not_found",
+        1,
+    );
+
+    let server_id = create_server(&panel, node_id, allocation_id, true).await;
+    assert_eq!(server_status(&panel, server_id).await, "install_failed");
+    assert_eq!(node.requests_to("POST", "/containers").len(), 1);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn create_on_an_offline_node_is_refused() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    let (runtime_id, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+
+    let res = panel
+        .post_form(
+            "/servers",
+            &[
+                ("name", "Survival"),
+                ("runtime_id", &runtime_id.to_string()),
+                ("image_id", &image_id.to_string()),
+                ("node_id", &node_id.to_string()),
+                ("default_allocation", &allocation_id.to_string()),
+            ],
+        )
+        .await;
+
+    assert_eq!(common::location(&res), "/servers/new?error=node_offline");
+    assert!(node.requests().is_empty());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn power_stop_gives_the_server_its_stop_timeout() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let server_id = create_server(&panel, node_id, allocation_id, true).await;
+    sqlx::query("UPDATE servers SET stop_timeout_seconds = 90 WHERE id = $1")
+        .bind(server_id)
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    let path = format!("/api/servers/{}/power?wait=true", server_id);
+    let res = panel
+        .post_json(&path, &serde_json::json!({ "action": "stop" }))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["state"], "exited");
+
+    let powers = node.requests_to("POST", &format!("/containers/{}/power", server_id));
+    assert_eq!(powers.len(), 1);
+    assert_eq!(
+        powers[0].body,
+        serde_json::json!({ "action": "stop", "grace": 90 })
+    );
+
+    let res = panel
+        .post_json(&path, &serde_json::json!({ "action": "start" }))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        node.container_state(&server_id.to_string()).as_deref(),
+        Some("running")
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn power_wait_polls_until_the_target_state_or_times_out() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let allocation_id = panel.insert_allocation(node_id, 25565).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let server_id = create_server(&panel, node_id, allocation_id, false).await;
+    let uuid = server_id.to_string();
+    let start = serde_json::json!({ "action": "start" });
+    node.stall_power(true);
+
+    // Without wait the action is only acknowledged
+    let res = panel
+        .post_json(&format!("/api/servers/{}/power", server_id), &start)
+        .await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["state"], "created");
+
+    let res = panel
+        .post_json(
+            &format!("/api/servers/{}/power?wait=true&timeout=1", server_id),
+            &start,
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["error"], "timeout");
+    assert_eq!(body["state"], "created");
+
+    let path = format!("/api/servers/{}/power?wait=true&timeout=10", server_id);
+    let waiting = tokio::spawn({
+        let client = panel.client.clone();
+        let url = format!("{}{}", panel.url, path);
+        async move { client.post(url).json(&start).send().await.unwrap() }
+    });
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    node.set_container_state(&uuid, "running");
+    let res = waiting.await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["state"], "running");
+    assert!(
+        !node
+            .requests_to("GET", &format!("/containers/{}/state", uuid))
+            .is_empty()
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn power_refuses_suspended_and_unknown_servers() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let server_id = panel.insert_server(node_id, image_id, "Survival").await;
+    sqlx::query("UPDATE servers SET suspend_reason = 'unpaid' WHERE id = $1")
+        .bind(server_id)
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    let res = panel
+        .post_json(
+            &format!("/api/servers/{}/power", server_id),
+            &serde_json::json!({ "action": "restart" }),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["error"], "server_suspended");
+
+    let res = panel
+        .post_json(
+            &format!("/api/servers/{}/power", Uuid::new_v4()),
+            &serde_json::json!({ "action": "start" }),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert!(node.requests().is_empty());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn delete_wipes_the_files_only_once_the_name_is_typed() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let wiped = panel.insert_server(node_id, image_id, "Survival").await;
+    let kept = panel.insert_server(node_id, image_id, "Creative").await;
+    for id in [wiped, kept] {
+        node.set_container_state(&id.to_string(), "running");
+    }
+    let exists = |id: Uuid| {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM servers WHERE id = $1)")
+            .bind(id)
+            .fetch_one(panel.db())
+    };
+
+    let path = format!("/servers/{}/delete", wiped);
+    for typed in ["", "survival", "Creative"] {
+        let res = panel
+            .post_form(&path, &[("purge", "on"), ("purge_confirm", typed)])
+            .await;
+        assert_eq!(
+            common::location(&res),
+            format!("/servers/{}/edit?error=purge_unconfirmed", wiped)
+        );
+    }
+    assert!(node.requests().is_empty());
+    assert!(exists(wiped).await.unwrap());
+
+    let res = panel
+        .post_form(&path, &[("purge", "on"), ("purge_confirm", " Survival ")])
+        .await;
+    assert_eq!(common::location(&res), "/servers");
+    let deletes = node.requests_to("DELETE", &format!("/containers/{}", wiped));
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].body["purge"], true);
+    assert!(!exists(wiped).await.unwrap());
+
+    // A plain delete leaves the files on the node
+    let res = panel
+        .post_form(&format!("/servers/{}/delete", kept), &[])
+        .await;
+    assert_eq!(common::location(&res), "/servers");
+    let deletes = node.requests_to("DELETE", &format!("/containers/{}", kept));
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].body["purge"], false);
+    assert!(!exists(kept).await.unwrap());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn port_audit_lists_where_allocations_and_containers_disagree() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let survival = panel.insert_server(node_id, image_id, "Survival").await;
+    let creative = panel.insert_server(node_id, image_id, "Creative").await;
+    for (port, server) in [(25565, survival), (25566, creative)] {
+        let allocation = panel.insert_allocation(node_id, port).await;
+        sqlx::query("UPDATE allocations SET server_id = $2 WHERE id = $1")
+            .bind(allocation)
+            .bind(server)
+            .execute(panel.db())
+            .await
+            .unwrap();
+    }
+    let path = format!("/nodes/{}/allocations/audit", node_id);
+    let audit = || async {
+        panel
+            .client
+            .get(format!("{}{}", panel.url, path))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    };
+
+    assert!(audit().await.contains("too old to report port bindings"));
+
+    let deleted = Uuid::new_v4();
+    let binding = |port: u16, server: Uuid| {
+        serde_json::json!({
+            "host_port": port,
+            "host_ip": "0.0.0.0",
+            "container_port": format!("{}/tcp", port),
+            "container": format!("yunexal-{}", server),
+            "server_id": server.to_string(),
+            "state": "exited",
+        })
+    };
+    node.set_port_bindings(serde_json::json!([
+        binding(25565, survival),
+        binding(25566, survival),
+        binding(30000, deleted),
+    ]));
+    let page = audit().await;
+    assert!(!page.contains("Could not read"));
+    for expected in [
+        "Allocated, not bound",
+        "Bound by another server",
+        "Bound, not allocated",
+        &format!("{} (deleted)", deleted),
+        "(exited)",
+    ] {
+        assert!(page.contains(expected), "missing {:?}", expected);
+    }
+    assert_eq!(page.matches("Allocated, not bound").count(), 1);
+
+    node.set_port_bindings(serde_json::json!([
+        binding(25565, survival),
+        binding(25566, creative),
+    ]));
+    assert!(
+        audit()
+            .await
+            .contains("All 2 binding(s) match their allocations.")
+    );
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn free_ports_leave_out_what_the_panel_already_claims() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    panel.insert_allocation(node_id, 25566).await;
+    let free_ports = |query: String| {
+        panel
+            .client
+            .get(format!(
+                "{}/nodes/{}/allocations/free-ports?{}",
+                panel.url, node_id, query
+            ))
+            .send()
+    };
+
+    let res = free_ports("start=25565&end=25568".to_string())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["free"], serde_json::json!([25565, 25567, 25568]));
+    let probes = node.requests_to("GET", "/free-ports");
+    assert_eq!(probes[0].body["start"], 25565);
+    assert_eq!(probes[0].body["end"], 25568);
+
+    // The agent's own port is claimed as well
+    let agent = format!("start={}&end={}", node.port, node.port);
+    let body: serde_json::Value = free_ports(agent).await.unwrap().json().await.unwrap();
+    assert_eq!(body["free"], serde_json::json!([]));
+
+    node.fail_next(
+        "GET",
+        "/free-ports",
+        StatusCode::BAD_REQUEST,
+        "range_too_large",
+        1,
+    );
+    let res = free_ports("start=1&end=5000".to_string()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["error"], "node_error");
+
+    let res = panel
+        .client
+        .get(format!(
+            "{}/nodes/{}/allocations/free-ports?start=1&end=2",
+            panel.url,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn heartbeat_needs_the_node_token() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+
+    assert_eq!(
+        panel.heartbeat(node_id, "not-the-token").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(panel.state.node_stats(&node_id.to_string()).await.is_none());
+
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let stats = panel
+        .state
+        .node_stats(&node_id.to_string())
+        .await
+        .expect("heartbeat was not stored");
+    assert_eq!(stats.cpu_cores, 4);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn rotate_token_switches_panel_and_node_over() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let old_token = node.token();
+
+    let res = panel
+        .client
+        .post(format!("{}/nodes/{}/rotate-token", panel.url, node_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let new_token = node.token();
+    assert_ne!(new_token, old_token);
+    let sealed: String = sqlx::query_scalar("SELECT token FROM nodes WHERE id = $1")
+        .bind(node_id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap();
+    assert_eq!(
+        node_tokens::open(&panel.state.secrets_key, &sealed).unwrap(),
+        new_token
+    );
+    assert!(pending_token(&panel, node_id).await.is_none());
+
+    // The old token is done for on both sides
+    assert_eq!(
+        panel.heartbeat(node_id, &old_token).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(panel.heartbeat(node_id, &new_token).await, StatusCode::OK);
+    let node_row = panel
+        .state
+        .get_node_with_token(&node_id.to_string())
+        .await
+        .unwrap();
+    assert_eq!(node_row.token, new_token);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn rotate_token_keeps_the_old_token_when_the_node_refuses() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let old_token = node.token();
+    // The node's callback goes nowhere, so it can't verify the new token
+    node.attach("http://127.0.0.1:9", node_id);
+
+    let res = panel
+        .client
+        .post(format!("{}/nodes/{}/rotate-token", panel.url, node_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    assert!(res.text().await.unwrap().contains("could not verify"));
+
+    assert_eq!(node.token(), old_token);
+    let node_row = panel
+        .state
+        .get_node_with_token(&node_id.to_string())
+        .await
+        .unwrap();
+    assert_eq!(node_row.token, old_token);
+    assert_eq!(panel.heartbeat(node_id, &old_token).await, StatusCode::OK);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn token_changes_for_a_node_take_turns() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let old_token = node.token();
+    let locks = &panel.state.token_locks;
+
+    let lock = locks.acquire(node_id).await.unwrap();
+    assert!(locks.acquire(node_id).await.is_none());
+    // Other nodes aren't held up
+    let other = locks.acquire(Uuid::new_v4()).await.unwrap();
+    locks.release(other).await;
+
+    let rotate = || {
+        panel
+            .client
+            .post(format!("{}/nodes/{}/rotate-token", panel.url, node_id))
+            .send()
+    };
+    let res = rotate().await.unwrap();
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert!(node.requests_to("POST", "/update-token").is_empty());
+    assert_eq!(node.token(), old_token);
+    assert!(pending_token(&panel, node_id).await.is_none());
+
+    locks.release(lock).await;
+    let res = rotate().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(node.token(), old_token);
+    // The rotation gave its lock back
+    let lock = locks.acquire(node_id).await.unwrap();
+    locks.release(lock).await;
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn clear_pending_token_needs_an_admin_and_their_password() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    sqlx::query("UPDATE nodes SET pending_token = 'left-over', pending_token_expires = NOW() + INTERVAL '1 hour' WHERE id = $1")
+        .bind(node_id)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    let admin = panel.insert_user("admin", "admin", "correct-horse").await;
+    let admin = panel.session_cookie(admin).await;
+    let user = panel.insert_user("someone", "user", "correct-horse").await;
+    let user = panel.session_cookie(user).await;
+    let path = format!("/nodes/{}/clear-pending-token", node_id);
+
+    // AUTH_MODE=off lets anyone reach the route, but not past the handler's own checks
+    for (cookie, password, refusal) in [
+        ("", "correct-horse", "Session expired"),
+        (user.as_str(), "correct-horse", "Only admins"),
+        (admin.as_str(), "wrong", "Incorrect password"),
+    ] {
+        let res = panel
+            .post_form_as(cookie, &path, &[("password", password)])
+            .await;
+        assert!(res.text().await.unwrap().contains(refusal), "{}", refusal);
+        assert!(pending_token(&panel, node_id).await.is_some());
+    }
+
+    let res = panel
+        .post_form_as(&admin, &path, &[("password", "correct-horse")])
+        .await;
+    assert!(res.text().await.unwrap().contains("Pending token cleared"));
+    assert!(pending_token(&panel, node_id).await.is_none());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn rotate_all_rotates_online_nodes_and_reports_the_rest() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let online = MockNode::start().await;
+    let online_id = panel.insert_node(&online).await;
+    let offline = MockNode::start().await;
+    let offline_id = panel.insert_node(&offline).await;
+    let busy = MockNode::start().await;
+    let busy_id = panel.insert_node(&busy).await;
+    for (id, node) in [(online_id, &online), (busy_id, &busy)] {
+        assert_eq!(panel.heartbeat(id, &node.token()).await, StatusCode::OK);
+    }
+    let (online_token, offline_token, busy_token) = (online.token(), offline.token(), busy.token());
+    let lock = panel.state.token_locks.acquire(busy_id).await.unwrap();
+
+    // AUTH_MODE=off: no session needed
+    let res = panel.post_form("/nodes/rotate-all", &[]).await;
+    panel.state.token_locks.release(lock).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["rotated"], 1);
+    assert_eq!(body["failed"], 0);
+    assert_eq!(body["skipped"], 2);
+    let status_of = |id: Uuid| {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["node_id"] == id.to_string())
+            .map(|r| r["status"].as_str().unwrap().to_string())
+            .unwrap()
+    };
+    assert_eq!(status_of(online_id), "rotated");
+    assert_eq!(status_of(offline_id), "skipped");
+    assert_eq!(status_of(busy_id), "busy");
+
+    assert_ne!(online.token(), online_token);
+    assert_eq!(offline.token(), offline_token);
+    assert_eq!(busy.token(), busy_token);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn rotate_all_is_for_admins_when_auth_is_on() {
+    let Some(panel) = TestPanel::start_with(|state| state.auth_mode = AuthMode::All).await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let user = panel.insert_user("someone", "user", "correct-horse").await;
+    let user = panel.session_cookie(user).await;
+    let admin = panel.insert_user("admin", "admin", "correct-horse").await;
+    let admin = panel.session_cookie(admin).await;
+    let token = node.token();
+
+    let res = panel.post_form_as(&user, "/nodes/rotate-all", &[]).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(node.token(), token);
+
+    let res = panel.post_form_as(&admin, "/nodes/rotate-all", &[]).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(node.token(), token);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn malformed_node_ids_are_a_bad_request() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    for path in [
+        "/nodes/not-a-uuid/edit",
+        "/nodes/not-a-uuid/setup",
+        "/nodes/not-a-uuid/allocations",
+        "/install/not-a-uuid",
+    ] {
+        let res = panel.get_as("", path).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "GET {}", path);
+    }
+    for path in [
+        "/nodes/not-a-uuid/heartbeat",
+        "/nodes/not-a-uuid/rotate-token",
+        "/nodes/1%27%20OR%201%3D1/update",
+    ] {
+        let res = panel.post_json(path, &serde_json::json!({})).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "POST {}", path);
+    }
+    let res = panel
+        .client
+        .delete(format!("{}/nodes/not-a-uuid", panel.url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn inspect_is_for_admins_and_404s_without_a_container() {
+    let Some(panel) = TestPanel::start_with(|state| state.auth_mode = AuthMode::All).await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let server_id = panel.insert_server(node_id, image_id, "Survival").await;
+    let admin = panel.insert_user("root", "admin", "secret").await;
+    let admin = panel.session_cookie(admin).await;
+    let user = panel.insert_user("player", "user", "secret").await;
+    let user = panel.session_cookie(user).await;
+    let path = format!("/servers/{}/inspect", server_id);
+
+    let res = panel.get_as(&user, &path).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = panel.get_as(&admin, &path).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["error"], "container_not_found");
+
+    node.set_container_state(&server_id.to_string(), "running");
+    let res = panel.get_as(&admin, &path).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["State"]["Status"], "running");
+
+    let res = panel
+        .get_as(&admin, &format!("/servers/{}/inspect", Uuid::new_v4()))
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn lifecycle_events_clear_the_nodes_stats() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let lifecycle = |token: String, event: &'static str| {
+        let request = panel
+            .client
+            .post(format!("{}/nodes/{}/lifecycle", panel.url, node_id))
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "event": event }));
+        async move { request.send().await.unwrap().status() }
+    };
+
+    for event in ["starting", "stopping"] {
+        assert_eq!(
+            panel.heartbeat(node_id, &node.token()).await,
+            StatusCode::OK
+        );
+        assert!(panel.state.node_stats(&node_id.to_string()).await.is_some());
+
+        assert_eq!(
+            lifecycle("wrong".to_string(), event).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            lifecycle(node.token(), "rebooting").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert!(panel.state.node_stats(&node_id.to_string()).await.is_some());
+
+        assert_eq!(lifecycle(node.token(), event).await, StatusCode::OK);
+        assert!(panel.state.node_stats(&node_id.to_string()).await.is_none());
+    }
+
+    panel.finish().await;
+}
@@ -0,0 +1,106 @@
+//! Loading node rows into the nodes cache, including from tables older migrations left
+//! behind, and keeping the cache current.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+
+#[tokio::test]
+async fn null_node_columns_load_as_defaults() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    sqlx::query(
+        "UPDATE nodes SET sftp_port = NULL, ram_limit = NULL, disk_limit = NULL, cpu_limit = NULL, \
+         version = NULL, auto_allocation_range = NULL WHERE id = $1",
+    )
+    .bind(node_id)
+    .execute(panel.db())
+    .await
+    .unwrap();
+    panel.state.invalidate_nodes_cache().await;
+
+    let nodes = panel.state.get_nodes().await;
+    assert_eq!(nodes.len(), 1);
+    let loaded = &nodes[0];
+    assert_eq!(loaded.id, node_id.to_string());
+    assert_eq!(loaded.sftp_port, 2022);
+    assert_eq!(
+        (loaded.ram_limit, loaded.disk_limit, loaded.cpu_limit),
+        (0, 0, 0)
+    );
+    assert_eq!(loaded.version, "");
+    assert_eq!(loaded.auto_allocation_range, "");
+    assert!(!loaded.location_id.is_empty());
+
+    let with_token = panel
+        .state
+        .get_node_with_token(&node_id.to_string())
+        .await
+        .unwrap();
+    assert_eq!(with_token.token, node.token());
+    assert_eq!(with_token.sftp_port, 2022);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn missing_node_columns_fall_back_to_the_base_columns() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    sqlx::query("ALTER TABLE nodes DROP COLUMN auto_allocation_range")
+        .execute(panel.db())
+        .await
+        .unwrap();
+    panel.state.invalidate_nodes_cache().await;
+
+    let nodes = panel.state.get_nodes().await;
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].id, node_id.to_string());
+    assert_eq!(nodes[0].port, node.port as i32);
+    assert_eq!(nodes[0].ram_limit, 0);
+
+    // Nothing got cached, so the next call retries the full query
+    assert!(panel.state.nodes_cache.read().await.is_none());
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn refresh_picks_up_edits_made_straight_in_the_db() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    assert_eq!(panel.state.get_nodes().await[0].ram_limit, 0);
+
+    sqlx::query("UPDATE nodes SET ram_limit = 8192 WHERE id = $1")
+        .bind(node_id)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    // Cached until something reloads it
+    assert_eq!(panel.state.get_nodes().await[0].ram_limit, 0);
+    panel.state.refresh_nodes_cache().await;
+    assert_eq!(panel.state.get_nodes().await[0].ram_limit, 8192);
+
+    // A failing reload keeps what the cache had
+    sqlx::query("ALTER TABLE nodes DROP COLUMN auto_allocation_range")
+        .execute(panel.db())
+        .await
+        .unwrap();
+    panel.state.refresh_nodes_cache().await;
+    let cached = panel.state.nodes_cache.read().await.clone().unwrap();
+    assert_eq!(cached[0].ram_limit, 8192);
+
+    panel.state.invalidate_nodes_cache().await;
+    assert!(panel.state.nodes_cache.read().await.is_none());
+
+    panel.finish().await;
+}
@@ -0,0 +1,94 @@
+//! The overview page's stats: the fleet totals, and the socket re-rendering them as
+//! heartbeats arrive.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use futures_util::StreamExt;
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The next stats fragment pushed over `socket`.
+async fn next_fragment(socket: &mut Socket) -> String {
+    let msg = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("no stats pushed within 5s")
+        .expect("socket closed")
+        .expect("socket error");
+    match msg {
+        Message::Text(html) => html.to_string(),
+        other => panic!("expected a text frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn heartbeats_push_fresh_stats() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+
+    let ws_url = format!("{}/overview/ws", panel.url.replacen("http", "ws", 1));
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+
+    // The first render comes right away, before any heartbeat
+    let html = next_fragment(&mut socket).await;
+    assert!(html.contains(r#"<span style="color: #28a745">0</span> / 1"#));
+
+    assert_eq!(
+        panel.heartbeat(node_id, &node.token()).await,
+        StatusCode::OK
+    );
+    let html = next_fragment(&mut socket).await;
+    assert!(html.contains(r#"<span style="color: #28a745">1</span> / 1"#));
+
+    panel.finish().await;
+}
+
+/// The "free / total" allocations card of a stats fragment or the overview page.
+fn allocations_card(html: &str) -> &str {
+    let start = html
+        .find("<!-- 7. Allocations -->")
+        .expect("no allocations card");
+    let end = start + html[start..].find("Allocations Free").unwrap();
+    &html[start..end]
+}
+
+#[tokio::test]
+async fn allocation_totals_count_free_ports_across_nodes() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let mut nodes = Vec::new();
+    for _ in 0..2 {
+        let node = MockNode::start().await;
+        nodes.push((panel.insert_node(&node).await, node));
+    }
+    let taken = panel.insert_allocation(nodes[0].0, 25565).await;
+    panel.insert_allocation(nodes[0].0, 25566).await;
+    panel.insert_allocation(nodes[1].0, 25565).await;
+    let server = panel.insert_server(nodes[0].0, image_id, "Survival").await;
+    sqlx::query("UPDATE allocations SET server_id = $2 WHERE id = $1")
+        .bind(taken)
+        .bind(server)
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    for path in ["/", "/overview/stats"] {
+        let html = panel.get_as("", path).await.text().await.unwrap();
+        assert!(
+            allocations_card(&html).contains(r#"<span style="color: #28a745">2</span> / 3"#),
+            "{}",
+            path
+        );
+    }
+
+    panel.finish().await;
+}
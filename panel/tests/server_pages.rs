@@ -0,0 +1,226 @@
+//! The server list and edit pages against a real database.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+/// A node, an image and the servers `names` on them.
+async fn servers(panel: &TestPanel, names: &[&str]) -> Vec<Uuid> {
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let mut ids = Vec::new();
+    for name in names {
+        ids.push(panel.insert_server(node_id, image_id, name).await);
+    }
+    ids
+}
+
+async fn page(panel: &TestPanel, path: &str) -> String {
+    let res = panel.get_as("", path).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    res.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn tag_filter_lists_only_matching_servers() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let ids = servers(&panel, &["Survival", "Creative"]).await;
+    sqlx::query("UPDATE servers SET tags = ARRAY['prod', 'eu'] WHERE id = $1")
+        .bind(ids[0])
+        .execute(panel.db())
+        .await
+        .unwrap();
+
+    let all = page(&panel, "/servers").await;
+    assert!(all.contains("Survival") && all.contains("Creative"));
+    assert!(all.contains("/servers?tag=prod"));
+
+    // Matched case-insensitively, like tags are stored
+    let prod = page(&panel, "/servers?tag=%20Prod").await;
+    assert!(prod.contains("Survival"));
+    assert!(!prod.contains("Creative"));
+
+    let none = page(&panel, "/servers?tag=staging").await;
+    assert!(none.contains("No servers with this tag."));
+
+    panel.finish().await;
+}
+
+/// The JSON the create page hands its script in `<script id="{id}">`.
+fn page_json(page: &str, id: &str) -> serde_json::Value {
+    let start = page
+        .find(&format!(r#"<script id="{}" type="application/json">"#, id))
+        .unwrap_or_else(|| panic!("no {} on the page", id));
+    let rest = &page[start..];
+    let body = &rest[rest.find('>').unwrap() + 1..rest.find("</script>").unwrap()];
+    serde_json::from_str(body.trim()).unwrap()
+}
+
+#[tokio::test]
+async fn create_page_counts_free_ports_per_node() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let (with_ports, without_ports) = (MockNode::start().await, MockNode::start().await);
+    let with_ports = panel.insert_node(&with_ports).await;
+    let without_ports = panel.insert_node(&without_ports).await;
+    panel.insert_allocation(with_ports, 25565).await;
+    let held = panel.insert_allocation(with_ports, 25566).await;
+    let res = panel
+        .post_form(
+            "/servers/new/reserve-allocation",
+            &[
+                ("reservation_token", "other-form"),
+                ("allocation_id", &held.to_string()),
+            ],
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    let counts = page_json(&page(&panel, "/servers/new").await, "free-counts-data");
+    // The port another form holds isn't offered, and a node without ports shows 0
+    assert_eq!(counts[with_ports.to_string()], 1);
+    assert_eq!(counts[without_ports.to_string()], 0);
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn partial_edit_form_keeps_what_it_leaves_out() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let id = servers(&panel, &["Survival"]).await[0];
+    sqlx::query(
+        "UPDATE servers SET description = 'Main world', ram_limit = 2048, oom_killer = TRUE, \
+         tags = ARRAY['prod'], variables = '{\"MOTD\": \"Hi\"}' WHERE id = $1",
+    )
+    .bind(id)
+    .execute(panel.db())
+    .await
+    .unwrap();
+    let update = format!("/servers/{}/update", id);
+    let manage = format!("/servers/{}/manage", id);
+
+    // Only the name; a non-admin owner change is ignored
+    let res = panel
+        .post_form(&update, &[("name", "Renamed"), ("owner_id", "2")])
+        .await;
+    assert_eq!(common::location(&res), manage);
+
+    type Row = (
+        String,
+        String,
+        Option<String>,
+        i32,
+        bool,
+        Vec<String>,
+        serde_json::Value,
+        String,
+        bool,
+    );
+    let row = || async {
+        sqlx::query_as::<_, Row>(
+            "SELECT name, owner_id, description, ram_limit, oom_killer, tags, variables, status, needs_recreate \
+             FROM servers WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(panel.db())
+        .await
+        .unwrap()
+    };
+    let (name, owner, description, ram, oom_killer, tags, variables, status, needs_recreate) =
+        row().await;
+    assert_eq!(name, "Renamed");
+    assert_eq!(owner, "1");
+    assert_eq!(description.as_deref(), Some("Main world"));
+    assert_eq!(ram, 2048);
+    assert!(oom_killer);
+    assert_eq!(tags, ["prod"]);
+    assert_eq!(variables, serde_json::json!({ "MOTD": "Hi" }));
+    assert_eq!(status, "running");
+    assert!(!needs_recreate);
+
+    // Empty numbers keep theirs; the checkbox marker without the box turns the OOM killer off
+    let res = panel
+        .post_form(
+            &update,
+            &[
+                ("ram_limit", ""),
+                ("oom_killer_present", "1"),
+                ("description", ""),
+            ],
+        )
+        .await;
+    assert_eq!(common::location(&res), manage);
+    let (name, _, description, ram, oom_killer, tags, _, status, _) = row().await;
+    assert_eq!(name, "Renamed");
+    assert_eq!(description.as_deref(), Some(""));
+    assert_eq!(ram, 2048);
+    assert!(!oom_killer);
+    assert_eq!(tags, ["prod"]);
+    assert_eq!(status, "running");
+
+    panel.finish().await;
+}
+
+#[tokio::test]
+async fn startup_badge_follows_the_heartbeat() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let id = panel.insert_server(node_id, image_id, "Survival").await;
+    let badge = format!("/servers/{}/startup-badge", id);
+
+    assert!(page(&panel, &badge).await.contains("Node offline"));
+
+    let now = chrono::Utc::now().timestamp();
+    for (container, label) in [
+        (serde_json::json!(null), "No container"),
+        (
+            serde_json::json!({ "state": "running", "started_at": now, "ready": false }),
+            "Starting…",
+        ),
+        (
+            serde_json::json!({ "state": "running", "started_at": now, "ready": true }),
+            "Ready",
+        ),
+        (
+            serde_json::json!({ "state": "running", "started_at": now }),
+            "Running",
+        ),
+        (serde_json::json!({ "state": "exited" }), "Stopped"),
+    ] {
+        let mut heartbeat = common::heartbeat_payload(node_id);
+        if let serde_json::Value::Object(mut container) = container {
+            container.insert("server_id".to_string(), id.to_string().into());
+            heartbeat["containers"] = serde_json::json!([container]);
+        }
+        let res = panel
+            .client
+            .post(format!("{}/nodes/{}/heartbeat", panel.url, node_id))
+            .bearer_auth(node.token())
+            .json(&heartbeat)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(
+            page(&panel, &badge)
+                .await
+                .contains(&format!(">{}</span>", label)),
+            "{}",
+            label
+        );
+    }
+
+    panel.finish().await;
+}
@@ -0,0 +1,123 @@
+//! Uptime segments built from heartbeat container states: extended while the state holds,
+//! split on flips, restarts and heartbeat gaps.
+
+mod common;
+
+use common::{MockNode, TestPanel};
+use panel::models::ContainerState;
+use panel::services::uptime;
+use uuid::Uuid;
+
+/// Stale window passed to `record`, in seconds
+const STALE_AFTER: u64 = 60;
+
+fn running(server_id: Uuid, started_at: i64) -> ContainerState {
+    ContainerState {
+        server_id: server_id.to_string(),
+        state: "running".to_string(),
+        started_at,
+        rx_bytes: None,
+        tx_bytes: None,
+        ready: None,
+    }
+}
+
+async fn record(panel: &TestPanel, node_id: Uuid, containers: Vec<ContainerState>) {
+    uptime::record(
+        panel.state.clone(),
+        node_id.to_string(),
+        containers,
+        STALE_AFTER,
+    )
+    .await;
+}
+
+/// The server's segments as (up, seconds long), oldest first.
+async fn segments(panel: &TestPanel, server_id: Uuid) -> Vec<(bool, f64)> {
+    sqlx::query_as(
+        "SELECT up, EXTRACT(EPOCH FROM last_seen_at - started_at)::float8 FROM server_uptime_segments
+         WHERE server_id = $1 ORDER BY started_at, id",
+    )
+    .bind(server_id)
+    .fetch_all(panel.db())
+    .await
+    .unwrap()
+}
+
+/// Container start times are whole seconds, so lengths are only compared to the second.
+fn assert_segments(actual: &[(bool, f64)], expected: &[(bool, i64)]) {
+    assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+    for ((up, secs), (want_up, want_secs)) in actual.iter().zip(expected) {
+        assert!(
+            *up == *want_up && (secs - *want_secs as f64).abs() <= 1.0,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+}
+
+/// Moves every recorded segment `secs` into the past, as if that much time went by.
+async fn age(panel: &TestPanel, secs: i64) {
+    sqlx::query(
+        "UPDATE server_uptime_segments SET started_at = started_at - make_interval(secs => $1),
+         last_seen_at = last_seen_at - make_interval(secs => $1)",
+    )
+    .bind(secs as f64)
+    .execute(panel.db())
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn segments_follow_the_container_state() {
+    let Some(panel) = TestPanel::start().await else {
+        return;
+    };
+    let node = MockNode::start().await;
+    let node_id = panel.insert_node(&node).await;
+    let (_, image_id) = panel.insert_image("ghcr.io/example/java:21", true).await;
+    let server_id = panel.insert_server(node_id, image_id, "Survival").await;
+    let installing = panel.insert_server(node_id, image_id, "Fresh").await;
+    sqlx::query("UPDATE servers SET status = 'installing' WHERE id = $1")
+        .bind(installing)
+        .execute(panel.db())
+        .await
+        .unwrap();
+    let now = || chrono::Utc::now().timestamp();
+    let boot = now() - 3600;
+
+    record(&panel, node_id, vec![running(server_id, boot)]).await;
+    age(&panel, 30).await;
+    record(&panel, node_id, vec![running(server_id, boot)]).await;
+    assert_segments(&segments(&panel, server_id).await, &[(true, 30)]);
+    assert!(segments(&panel, installing).await.is_empty());
+
+    // Restarted 10s ago: down from the last heartbeat until then
+    age(&panel, 30).await;
+    record(&panel, node_id, vec![running(server_id, now() - 10)]).await;
+    assert_segments(
+        &segments(&panel, server_id).await,
+        &[(true, 30), (false, 20), (true, 10)],
+    );
+
+    // Missing from the heartbeat: no container, so down since the last one
+    age(&panel, 20).await;
+    record(&panel, node_id, Vec::new()).await;
+    assert_segments(&segments(&panel, server_id).await[3..], &[(false, 20)]);
+
+    // Heartbeats stopped for longer than the stale window: the gap stays unknown
+    age(&panel, STALE_AFTER as i64 * 5).await;
+    record(&panel, node_id, vec![running(server_id, now())]).await;
+    let segs = segments(&panel, server_id).await;
+    assert_eq!(segs.len(), 5);
+    assert_segments(&segs[4..], &[(true, 0)]);
+
+    let created_at = chrono::Utc::now() - chrono::Duration::days(2);
+    let uptime = uptime::server(panel.db(), server_id, created_at).await;
+    // 40s up out of 80s known
+    assert!(uptime.day.is_some_and(|p| (p - 50.0).abs() < 2.0));
+    assert_eq!(uptime.day, uptime.month);
+
+    panel.finish().await;
+}